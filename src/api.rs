@@ -0,0 +1,430 @@
+//! 本地 REST 控制接口：监听过程中，其他程序可以通过 HTTP 发送数据、查询最近收到的数据、
+//! 修改串口参数和操作控制线
+//!
+//! 所有应答都是 JSON；参数可以放在查询字符串中，也可以以表单（application/x-www-form-urlencoded）放在请求体中。
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serialport::SerialPort;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::events::{self, Event};
+use crate::signal::{self, LineAction, ModemStatus};
+use crate::{format_hex, lock, parse_baud, parse_duration, parse_hex, FlowControlArg, ParityArg};
+
+/// 读取请求的超时
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// 请求头的最大长度
+const MAX_HEADER: usize = 16 * 1024;
+/// 请求体的最大长度
+const MAX_BODY: usize = 1024 * 1024;
+
+const INDEX: &str = r#"{"endpoints":[
+"POST /send  请求体原样写入串口，或用参数 text=（按 URL 编码）/ hex=",
+"GET  /rx?since=<偏移>  最近收到的数据，从上次应答的 next 开始查询即可只取新数据",
+"GET  /settings  当前串口参数",
+"POST /settings?baud=&data_bits=&parity=&stop_bits=&flow_control=  修改串口参数",
+"GET  /control  输入状态线 CTS/DSR/DCD/RI",
+"POST /control?dtr=on|off|pulse:100ms&rts=...&break=250ms  操作控制线"
+]}"#;
+
+/// 收到的一块数据
+struct Chunk {
+    /// 这块数据第一个字节在整个会话中的偏移
+    offset: u64,
+    ts: String,
+    data: Vec<u8>,
+}
+
+/// 最近收到的数据，超过容量时丢弃最早的部分
+struct History {
+    chunks: VecDeque<Chunk>,
+    bytes: usize,
+    capacity: usize,
+    /// 下一个字节的偏移（即会话中收到的总字节数）
+    next: u64,
+}
+
+struct Shared {
+    /// 当前串口的副本，重连后更新
+    port: Mutex<Option<Box<dyn SerialPort>>>,
+    history: Mutex<History>,
+}
+
+/// 在后台线程运行的 HTTP 服务
+pub struct Api {
+    shared: Arc<Shared>,
+}
+
+/// 一个请求
+struct Request {
+    method: String,
+    path: String,
+    params: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn param(&self, name: &str) -> Option<String> {
+        self.params.iter().find(|(key, _)| key == name).map(|(_, value)| String::from_utf8_lossy(value).into_owned())
+    }
+}
+
+/// 应答：状态码和 JSON 内容
+type Response = (u16, String);
+
+fn error(status: u16, message: &str) -> Response {
+    (status, format!("{{\"error\":{}}}", events::quote(message)))
+}
+
+impl Api {
+    /// 监听 `listen`（只写端口如 :8081 时监听所有网卡），保留最近 `history` 字节收到的数据
+    pub fn start(listen: &str, history: usize) -> Result<Api> {
+        let address = match listen.starts_with(':') {
+            true => format!("0.0.0.0{}", listen),
+            false => listen.to_string(),
+        };
+        let listener = TcpListener::bind(&address).with_context(|| format!("REST 接口监听 {} 失败", listen))?;
+        let local = listener.local_addr().context("读取监听地址失败")?;
+        let shared = Arc::new(Shared {
+            port: Mutex::new(None),
+            history: Mutex::new(History { chunks: VecDeque::new(), bytes: 0, capacity: history, next: 0 }),
+        });
+        let server = Arc::clone(&shared);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                let shared = Arc::clone(&server);
+                thread::spawn(move || serve(stream, &shared));
+            }
+        });
+        events::status(&format!("[REST 接口在 http://{}/ 上监听]", local));
+        Ok(Api { shared })
+    }
+
+    /// 使用（重新）打开的串口
+    pub fn attach(&self, port: &dyn SerialPort) {
+        match port.try_clone() {
+            Ok(port) => *lock(&self.shared.port) = Some(port),
+            Err(e) => {
+                *lock(&self.shared.port) = None;
+                events::status(&format!("[REST 接口无法复制串口句柄: {}]", e));
+            }
+        }
+    }
+
+    /// 记录收到的数据
+    pub fn record(&self, data: &[u8]) {
+        let mut history = lock(&self.shared.history);
+        let chunk = Chunk { offset: history.next, ts: jiff::Zoned::now().strftime("%Y-%m-%dT%H:%M:%S%.3f%:z").to_string(), data: data.to_vec() };
+        history.next += data.len() as u64;
+        history.bytes += data.len();
+        history.chunks.push_back(chunk);
+        while history.bytes > history.capacity {
+            let Some(oldest) = history.chunks.pop_front() else { break };
+            history.bytes -= oldest.data.len();
+        }
+    }
+}
+
+/// 处理一个连接上的一个请求（应答后关闭连接）
+fn serve(mut stream: TcpStream, shared: &Shared) {
+    let _ = stream.set_read_timeout(Some(TIMEOUT));
+    let _ = stream.set_write_timeout(Some(TIMEOUT));
+    let (status, body) = match read_request(&mut stream) {
+        Ok(request) => route(&request, shared),
+        Err(e) => error(400, &format!("{:#}", e)),
+    };
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len() + 1,
+        body
+    );
+    let _ = stream.write_all(response.as_bytes());
+    let _ = stream.write_all(b"\n");
+}
+
+fn read_request(stream: &mut TcpStream) -> Result<Request> {
+    let mut data = Vec::new();
+    let mut buffer = [0u8; 4096];
+    let end = loop {
+        if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if data.len() > MAX_HEADER {
+            bail!("请求头过长");
+        }
+        match stream.read(&mut buffer).context("读取请求失败")? {
+            0 => bail!("请求不完整"),
+            n => data.extend_from_slice(&buffer[..n]),
+        }
+    };
+    let head = String::from_utf8_lossy(&data[..end]).into_owned();
+    let mut lines = head.lines();
+    let mut first = lines.next().unwrap_or_default().split_whitespace();
+    let (Some(method), Some(target)) = (first.next(), first.next()) else {
+        bail!("无效的请求行");
+    };
+    let length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("Content-Length"))
+        .map(|(_, value)| value.trim().parse::<usize>().context("无效的 Content-Length"))
+        .transpose()?
+        .unwrap_or(0);
+    if length > MAX_BODY {
+        bail!("请求体超过 {} 字节", MAX_BODY);
+    }
+    let mut body = data.split_off(end);
+    while body.len() < length {
+        match stream.read(&mut buffer).context("读取请求体失败")? {
+            0 => bail!("请求体不完整"),
+            n => body.extend_from_slice(&buffer[..n]),
+        }
+    }
+    body.truncate(length);
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut params = parse_form(query);
+    // curl -d 等默认把请求体标成表单，所以同时保留原始内容：/send 没有 text=、hex= 参数时照样发送请求体
+    if head.to_ascii_lowercase().contains("application/x-www-form-urlencoded") {
+        params.extend(parse_form(&String::from_utf8_lossy(&body)));
+    }
+    Ok(Request { method: method.to_string(), path: path.to_string(), params, body })
+}
+
+/// 解析 a=1&b=%0D%0A 形式的参数（值按字节解码）
+fn parse_form(text: &str) -> Vec<(String, Vec<u8>)> {
+    text.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (String::from_utf8_lossy(&percent_decode(key)).into_owned(), percent_decode(value))
+        })
+        .collect()
+}
+
+fn percent_decode(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if bytes.get(i + 1..i + 3).is_some_and(|digits| digits.iter().all(u8::is_ascii_hexdigit)) => {
+                out.push(u8::from_str_radix(&text[i + 1..i + 3], 16).unwrap_or_default());
+                i += 2;
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    out
+}
+
+fn route(request: &Request, shared: &Shared) -> Response {
+    let mut port = lock(&shared.port);
+    let result = match (request.method.as_str(), request.path.trim_end_matches('/')) {
+        ("GET", "") => return (200, INDEX.to_string()),
+        ("GET", "/rx") => return rx(request, &lock(&shared.history)),
+        (_, "/rx") => return error(405, "/rx 只支持 GET"),
+        (method, "/send" | "/settings" | "/control") => match port.as_mut() {
+            None => return error(503, "串口未打开"),
+            Some(port) => match (method, request.path.trim_end_matches('/')) {
+                ("POST", "/send") => send(request, port),
+                ("GET", "/settings") => settings(port),
+                ("POST", "/settings") => change_settings(request, port),
+                ("GET", "/control") => Ok(control(port)),
+                ("POST", "/control") => change_control(request, port),
+                (_, path) => return error(405, &format!("{} 不支持 {}", path, method)),
+            },
+        },
+        (_, path) => return error(404, &format!("没有 {}，GET / 查看可用的接口", path)),
+    };
+    match result {
+        Ok(body) => (200, body),
+        Err(e) if e.is::<BadRequest>() => error(400, &format!("{:#}", e)),
+        Err(e) => error(500, &format!("{:#}", e)),
+    }
+}
+
+/// 参数有误（应答 400）
+#[derive(Debug)]
+struct BadRequest(String);
+
+impl std::fmt::Display for BadRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for BadRequest {}
+
+fn bad(message: String) -> anyhow::Error {
+    BadRequest(message).into()
+}
+
+fn send(request: &Request, port: &mut Box<dyn SerialPort>) -> Result<String> {
+    let data = match (request.params.iter().find(|(key, _)| key == "text"), request.param("hex")) {
+        (Some((_, text)), _) => text.clone(),
+        (None, Some(hex)) => parse_hex(&hex).map_err(|e| bad(format!("无效的十六进制数据 '{}'：{}", hex, e)))?,
+        (None, None) => request.body.clone(),
+    };
+    if data.is_empty() {
+        return Err(bad("没有要发送的数据（请求体为空，也没有 text= 或 hex= 参数）".to_string()));
+    }
+    port.write_all(&data).context("写入串口失败")?;
+    port.flush().context("写入串口失败")?;
+    events::emit(Event::Tx(&data));
+    Ok(format!("{{\"sent\":{}}}", data.len()))
+}
+
+fn rx(request: &Request, history: &History) -> Response {
+    let since = match request.param("since").map(|since| since.parse::<u64>()) {
+        Some(Ok(since)) => since,
+        Some(Err(_)) => return error(400, "since 应为字节偏移（上次应答中的 next）"),
+        None => 0,
+    };
+    let oldest = history.chunks.front().map_or(history.next, |chunk| chunk.offset);
+    let mut out = format!("{{\"next\":{},\"missed\":{},\"chunks\":[", history.next, oldest.saturating_sub(since));
+    let mut first = true;
+    for chunk in history.chunks.iter().filter(|chunk| chunk.offset + chunk.data.len() as u64 > since) {
+        // 从块中间开始时只取 since 之后的部分
+        let data = &chunk.data[since.saturating_sub(chunk.offset) as usize..];
+        let offset = chunk.offset.max(since);
+        if !first {
+            out.push(',');
+        }
+        first = false;
+        write!(
+            out,
+            "{{\"offset\":{},\"ts\":\"{}\",\"len\":{},\"hex\":{},\"text\":{}}}",
+            offset,
+            chunk.ts,
+            data.len(),
+            events::quote(&format_hex(data)),
+            events::quote(&String::from_utf8_lossy(data))
+        )
+        .unwrap();
+    }
+    out.push_str("]}");
+    (200, out)
+}
+
+fn settings(port: &mut Box<dyn SerialPort>) -> Result<String> {
+    let parity = match port.parity().context("读取校验位失败")? {
+        serialport::Parity::None => "none",
+        serialport::Parity::Odd => "odd",
+        serialport::Parity::Even => "even",
+    };
+    let flow_control = match port.flow_control().context("读取流控方式失败")? {
+        serialport::FlowControl::None => "none",
+        serialport::FlowControl::Hardware => "rtscts",
+        serialport::FlowControl::Software => "xonxoff",
+    };
+    let stop_bits = match port.stop_bits().context("读取停止位失败")? {
+        serialport::StopBits::One => 1,
+        serialport::StopBits::Two => 2,
+    };
+    Ok(format!(
+        "{{\"baud\":{},\"data_bits\":{},\"parity\":\"{}\",\"stop_bits\":{},\"flow_control\":\"{}\"}}",
+        port.baud_rate().context("读取波特率失败")?,
+        u8::from(port.data_bits().context("读取数据位失败")?),
+        parity,
+        stop_bits,
+        flow_control
+    ))
+}
+
+fn change_settings(request: &Request, port: &mut Box<dyn SerialPort>) -> Result<String> {
+    let mut changed = Vec::new();
+    for (key, _) in &request.params {
+        let value = request.param(key).unwrap_or_default();
+        let invalid = |what: &str| bad(format!("无效的{} '{}'", what, value));
+        match key.as_str() {
+            "baud" => port.set_baud_rate(parse_baud(&value).map_err(bad)?).context("设置波特率失败")?,
+            "data_bits" => {
+                let bits = value.parse::<u8>().ok().and_then(|bits| serialport::DataBits::try_from(bits).ok()).ok_or_else(|| invalid("数据位"))?;
+                port.set_data_bits(bits).context("设置数据位失败")?
+            }
+            "parity" => {
+                let parity = ParityArg::from_str(&value, true).map_err(|_| invalid("校验位"))?;
+                port.set_parity(parity.into()).context("设置校验位失败")?
+            }
+            "stop_bits" => {
+                let bits = value.parse::<u8>().ok().and_then(|bits| serialport::StopBits::try_from(bits).ok()).ok_or_else(|| invalid("停止位"))?;
+                port.set_stop_bits(bits).context("设置停止位失败")?
+            }
+            "flow_control" => {
+                let flow = FlowControlArg::from_str(&value, true).map_err(|_| invalid("流控方式"))?;
+                port.set_flow_control(flow.into()).context("设置流控方式失败")?
+            }
+            key => return Err(bad(format!("未知的参数 '{}'，可用 baud、data_bits、parity、stop_bits、flow_control", key))),
+        }
+        changed.push(format!("{}={}", key, value));
+    }
+    if changed.is_empty() {
+        return Err(bad("没有要修改的参数".to_string()));
+    }
+    events::status(&format!("[REST 接口修改串口参数：{}]", changed.join(" ")));
+    settings(port)
+}
+
+fn control(port: &mut Box<dyn SerialPort>) -> String {
+    let status = ModemStatus::read(port);
+    let level = |level: Option<bool>| level.map_or("null".to_string(), |level| level.to_string());
+    format!("{{\"cts\":{},\"dsr\":{},\"dcd\":{},\"ri\":{}}}", level(status.cts), level(status.dsr), level(status.cd), level(status.ri))
+}
+
+fn change_control(request: &Request, port: &mut Box<dyn SerialPort>) -> Result<String> {
+    let action = |name: &str| request.param(name).map(|value| signal::parse_action(&value).map_err(bad)).transpose();
+    let dtr = action("dtr")?;
+    let rts = action("rts")?;
+    let pulse = request.param("break").map(|value| parse_duration(&value).map_err(bad)).transpose()?;
+    if dtr.is_none() && rts.is_none() && pulse.is_none() {
+        return Err(bad("没有要操作的控制线（可用 dtr、rts、break）".to_string()));
+    }
+    if let Some(unknown) = request.params.iter().map(|(key, _)| key).find(|key| !["dtr", "rts", "break"].contains(&key.as_str())) {
+        return Err(bad(format!("未知的参数 '{}'，可用 dtr、rts、break", unknown)));
+    }
+    let set = |port: &mut Box<dyn SerialPort>, name: &str, level: bool| match name {
+        "DTR" => port.write_data_terminal_ready(level).context("设置 DTR 失败"),
+        _ => port.write_request_to_send(level).context("设置 RTS 失败"),
+    };
+    let mut pulses = Vec::new();
+    for (name, action) in [("DTR", dtr), ("RTS", rts)] {
+        let Some(action) = action else { continue };
+        set(port, name, !matches!(action, LineAction::Off))?;
+        if let LineAction::Pulse(duration) = action {
+            pulses.push((duration, name));
+        }
+    }
+    // 脉冲按时长从短到长依次复位
+    let start = Instant::now();
+    pulses.sort_by_key(|&(duration, _)| duration);
+    for (duration, name) in pulses {
+        thread::sleep(duration.saturating_sub(start.elapsed()));
+        set(port, name, false)?;
+    }
+    if let Some(duration) = pulse {
+        port.set_break().context("发送 break 失败")?;
+        thread::sleep(duration);
+        port.clear_break().context("结束 break 失败")?;
+    }
+    Ok(control(port))
+}
@@ -0,0 +1,277 @@
+//! AT 命令模式：自动追加 CR，等待 OK/ERROR/+CME ERROR 等最终结果码，收集多行应答，
+//! 并把应答之外的非请求结果码（URC）单独显示
+//!
+//! 任一命令返回错误或超时时以非 0 退出码结束，便于在脚本中使用。
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::events::{self, Event};
+use crate::parse_duration;
+use crate::signal::Rs485;
+use crate::terminal;
+
+/// at 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct AtArgs {
+    /// 依次执行的 AT 命令（不需要 CR），如 AT+CSQ ATI "AT+COPS?"
+    #[arg(required = true)]
+    pub commands: Vec<String>,
+
+    /// 等待每条命令最终结果码的超时时间
+    #[arg(long, default_value = "5s", value_parser = parse_duration)]
+    pub timeout: Duration,
+
+    /// 执行完所有命令后继续显示 URC 的时长（如 30s）
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub urc: Option<Duration>,
+
+    /// 某条命令失败后继续执行后面的命令（退出码仍为非 0）
+    #[arg(long)]
+    pub keep_going: bool,
+}
+
+/// 执行 at 子命令
+pub fn run_at(port: &mut Box<dyn SerialPort>, opts: &AtArgs, rs485: Option<Rs485>) -> Result<()> {
+    port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+    let mut modem = Modem::new(rs485, opts.timeout);
+    let mut failed = 0;
+    for command in &opts.commands {
+        events::status(&format!("> {}", command));
+        match modem.command(port, command) {
+            Ok(response) => {
+                for line in &response {
+                    events::status(&format!("  {}", line));
+                }
+                events::status("  OK");
+            }
+            Err(e) if opts.keep_going => {
+                events::status(&format!("  {:#}", e));
+                failed += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if let Some(duration) = opts.urc {
+        modem.listen(port, duration)?;
+    }
+    if failed > 0 {
+        bail!("{} 条命令执行失败", failed);
+    }
+    Ok(())
+}
+
+/// 与 AT 命令设备的会话：按行切分收到的数据，区分命令应答和 URC
+pub struct Modem {
+    rs485: Option<Rs485>,
+    timeout: Duration,
+    pending: Vec<u8>,
+}
+
+/// 一条命令的应答：最终结果码之前的应答行，以及失败时的结果码说明
+pub struct Reply {
+    pub lines: Vec<String>,
+    pub error: Option<String>,
+}
+
+impl Reply {
+    fn into_result(self, command: &str) -> Result<Vec<String>> {
+        match self.error {
+            Some(error) => bail!("{} 失败：{}", command, error),
+            None => Ok(self.lines),
+        }
+    }
+}
+
+/// 一行收到的内容
+enum Line {
+    Text(String),
+    /// 等待输入数据的 "> " 提示符（如 AT+CMGS、AT+CIPSEND）
+    Prompt,
+}
+
+impl Modem {
+    pub fn new(rs485: Option<Rs485>, timeout: Duration) -> Self {
+        Modem { rs485, timeout, pending: Vec::new() }
+    }
+
+    /// 执行一条命令，返回最终结果码 OK 之前的应答行（不含回显和 URC）；
+    /// 结果为 ERROR 等失败码或超时时返回错误
+    pub fn command(&mut self, port: &mut Box<dyn SerialPort>, command: &str) -> Result<Vec<String>> {
+        self.execute(port, command)?.into_result(command)
+    }
+
+    /// 执行一条命令，失败码也作为应答返回（调用方可据应答行给出更具体的说明）；只有超时和读写错误返回错误
+    pub fn execute(&mut self, port: &mut Box<dyn SerialPort>, command: &str) -> Result<Reply> {
+        terminal::transmit(port, self.rs485, format!("{}\r", command).as_bytes())?;
+        self.response(port, command)
+    }
+
+    /// 发送一条需要输入数据的命令（如 AT+CMGS），等待 "> " 提示符
+    pub fn prompt(&mut self, port: &mut Box<dyn SerialPort>, command: &str) -> Result<()> {
+        terminal::transmit(port, self.rs485, format!("{}\r", command).as_bytes())?;
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match self.read_line(port, deadline)? {
+                Some(Line::Prompt) => return Ok(()),
+                Some(Line::Text(line)) if line == command || line == "OK" => {}
+                Some(Line::Text(line)) => {
+                    if let Some(error) = final_result(&line).and_then(|r| r.err()) {
+                        bail!("{} 失败：{}", command, error);
+                    }
+                    show_urc(&line);
+                }
+                None => bail!("{} 在 {:?} 内没有出现输入提示符", command, self.timeout),
+            }
+        }
+    }
+
+    /// 在提示符后发送数据（以 Ctrl+Z 结束），等待最终结果码
+    pub fn send_data(&mut self, port: &mut Box<dyn SerialPort>, command: &str, data: &[u8]) -> Result<Vec<String>> {
+        terminal::transmit(port, self.rs485, &[data, &[0x1A]].concat())?;
+        self.response(port, command)?.into_result(command)
+    }
+
+    /// 在指定时长内显示收到的 URC
+    pub fn listen(&mut self, port: &mut Box<dyn SerialPort>, duration: Duration) -> Result<()> {
+        let deadline = Instant::now() + duration;
+        while let Some(line) = self.read_line(port, deadline)? {
+            if let Line::Text(line) = line {
+                show_urc(&line);
+            }
+        }
+        Ok(())
+    }
+
+    /// 收集应答行直到最终结果码
+    fn response(&mut self, port: &mut Box<dyn SerialPort>, command: &str) -> Result<Reply> {
+        let deadline = Instant::now() + self.timeout;
+        let prefix = response_prefix(command);
+        let mut lines = Vec::new();
+        loop {
+            let line = match self.read_line(port, deadline)? {
+                Some(Line::Text(line)) => line,
+                Some(Line::Prompt) => continue,
+                None => bail!("{} 在 {:?} 内没有返回结果码", command, self.timeout),
+            };
+            match final_result(&line) {
+                // 只有拨号（ATD/ATO）的 CONNECT 是最终结果码，ESP-AT 等建立连接时的 CONNECT 之后还有 OK
+                Some(Ok(())) if line.starts_with("CONNECT") && !is_dial(command) => lines.push(line),
+                Some(Ok(())) => return Ok(Reply { lines, error: None }),
+                Some(Err(error)) => return Ok(Reply { lines, error: Some(error) }),
+                None if line == command => {}
+                None if is_urc(&line, prefix) => show_urc(&line),
+                None => lines.push(line),
+            }
+        }
+    }
+
+    /// 读取下一行（去掉行尾 CR/LF 的非空行），到截止时间返回 `None`
+    fn read_line(&mut self, port: &mut Box<dyn SerialPort>, deadline: Instant) -> Result<Option<Line>> {
+        let mut buffer = [0u8; 256];
+        loop {
+            while let Some(end) = self.pending.iter().position(|&b| b == b'\r' || b == b'\n') {
+                let line: Vec<u8> = self.pending.drain(..=end).collect();
+                let text = String::from_utf8_lossy(&line[..end]).trim().to_string();
+                if !text.is_empty() {
+                    return Ok(Some(Line::Text(text)));
+                }
+            }
+            if self.pending.starts_with(b">") {
+                self.pending.drain(..1);
+                if self.pending.first() == Some(&b' ') {
+                    self.pending.drain(..1);
+                }
+                return Ok(Some(Line::Prompt));
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            match port.read(&mut buffer) {
+                Ok(n) => {
+                    events::emit(Event::Rx(&buffer[..n]));
+                    self.pending.extend_from_slice(&buffer[..n]);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e).context("读取串口失败"),
+            }
+        }
+    }
+}
+
+/// 判断最终结果码：成功为 `Ok`，失败为带说明的 `Err`，不是结果码为 `None`
+fn final_result(line: &str) -> Option<std::result::Result<(), String>> {
+    match line {
+        "OK" | "SEND OK" => Some(Ok(())),
+        _ if line.starts_with("CONNECT") => Some(Ok(())),
+        "ERROR" | "FAIL" | "NO CARRIER" | "BUSY" | "NO ANSWER" | "NO DIALTONE" | "SEND FAIL" => Some(Err(line.to_string())),
+        _ => {
+            let code = line.strip_prefix("+CME ERROR:").or_else(|| line.strip_prefix("+CMS ERROR:"))?.trim();
+            Some(Err(match error_name(line.starts_with("+CMS"), code) {
+                Some(name) => format!("{}（{}）", line, name),
+                None => line.to_string(),
+            }))
+        }
+    }
+}
+
+/// 常见 CME/CMS 错误码的说明（设备开启 AT+CMEE=2 时直接返回文字，不需要查表）
+fn error_name(sms: bool, code: &str) -> Option<&'static str> {
+    let code: u16 = code.parse().ok()?;
+    let name = match (sms, code) {
+        (false, 3) => "不允许的操作",
+        (false, 4) => "不支持的操作",
+        (false, 10) => "未插入 SIM 卡",
+        (false, 11) => "需要 SIM PIN",
+        (false, 12) => "需要 SIM PUK",
+        (false, 13) => "SIM 卡故障",
+        (false, 14) => "SIM 卡忙",
+        (false, 16) => "密码错误",
+        (false, 30) => "无网络服务",
+        (false, 50) => "参数错误",
+        (false, 100) => "未知错误",
+        (true, 300) => "ME 故障",
+        (true, 302) => "不允许的操作",
+        (true, 304) => "PDU 参数无效",
+        (true, 305) => "文本参数无效",
+        (true, 310) => "未插入 SIM 卡",
+        (true, 321) => "无效的存储索引",
+        (true, 322) => "存储已满",
+        (true, 330) => "短信中心地址未知",
+        (true, 331) => "无网络服务",
+        (true, 500) => "未知错误",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn is_dial(command: &str) -> bool {
+    command.get(..3).is_some_and(|p| p.eq_ignore_ascii_case("ATD") || p.eq_ignore_ascii_case("ATO"))
+}
+
+/// 命令应答行的前缀，如 AT+CSQ 和 AT+CSQ? 的应答以 "+CSQ" 开头
+fn response_prefix(command: &str) -> Option<&str> {
+    let name = command.get(2..)?;
+    if !command[..2].eq_ignore_ascii_case("AT") || !name.starts_with(['+', '^', '$', '#']) {
+        return None;
+    }
+    let end = name.find(['=', '?']).unwrap_or(name.len());
+    Some(&name[..end])
+}
+
+/// 应答中不属于当前命令的行：其他命令前缀的 +XXX: 行、RING 等
+fn is_urc(line: &str, prefix: Option<&str>) -> bool {
+    if matches!(line, "RING" | "RDY" | "SMS Ready" | "Call Ready") {
+        return true;
+    }
+    match (line.split_once(':'), prefix) {
+        (Some((name, _)), Some(prefix)) if name.starts_with(['+', '^', '$', '#']) => !name.eq_ignore_ascii_case(prefix),
+        _ => false,
+    }
+}
+
+fn show_urc(line: &str) {
+    events::status(&format!("[URC] {}", line));
+}
@@ -0,0 +1,244 @@
+//! AVR 串口 bootloader：STK500v1（Arduino Uno/Nano 等的 Optiboot 及旧版 ATmegaBOOT）和
+//! AVR109（Arduino Leonardo/Micro 的 Caterina 等 butterfly 兼容 bootloader）
+//!
+//! 两种协议的 flash 地址都按字（2 字节）计。STK500v1 每条命令以 0x20（CRC_EOP）结束，
+//! 应答为 0x14（INSYNC）+ 数据 + 0x10（OK），写页时 bootloader 自动擦除该页；
+//! AVR109 为单字符命令，成功时回复 '\r'，写入前需先整片擦除。
+
+use anyhow::{bail, Result};
+use std::time::Duration;
+
+use crate::format_hex;
+use crate::xmodem::Link;
+
+const CRC_EOP: u8 = 0x20;
+const INSYNC: u8 = 0x14;
+const OK: u8 = 0x10;
+
+const GET_SYNC: u8 = 0x30;
+const GET_PARAMETER: u8 = 0x41;
+const ENTER_PROGMODE: u8 = 0x50;
+const LEAVE_PROGMODE: u8 = 0x51;
+const LOAD_ADDRESS: u8 = 0x55;
+const PROG_PAGE: u8 = 0x64;
+const READ_PAGE: u8 = 0x74;
+const READ_SIGN: u8 = 0x75;
+
+/// 参数：bootloader 软件主版本号和次版本号
+const SW_MAJOR: u8 = 0x81;
+const SW_MINOR: u8 = 0x82;
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+/// 同步的尝试次数（复位后 bootloader 需要一点时间启动）
+const SYNC_ATTEMPTS: usize = 10;
+/// 16 位字地址最大能寻址的 flash（更大的芯片需要扩展地址）
+pub const MAX_FLASH: u32 = 0x20000;
+
+/// bootloader 的公共操作
+pub trait Programmer {
+    /// bootloader 的名称和版本
+    fn describe(&self) -> String;
+    /// 芯片签名（3 字节，如 1E 95 0F 为 ATmega328P）
+    fn signature(&mut self) -> Result<[u8; 3]>;
+    /// 整片擦除（写页时会自动擦除的 bootloader 不需要）
+    fn erase(&mut self) -> Result<()>;
+    /// 写入一页，`address` 为字节地址
+    fn write_page(&mut self, address: u32, data: &[u8]) -> Result<()>;
+    /// 读出一页
+    fn read_page(&mut self, address: u32, len: usize) -> Result<Vec<u8>>;
+    /// 结束编程，运行新程序
+    fn finish(&mut self) -> Result<()>;
+    /// 写入的块大小（bootloader 自己报告时）
+    fn block_size(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// STK500v1 bootloader
+pub struct Stk500<'l, 'p> {
+    link: &'l mut Link<'p>,
+    version: (u8, u8),
+}
+
+impl<'l, 'p> Stk500<'l, 'p> {
+    /// 反复发送 GET_SYNC 直到 bootloader 应答，再读取版本并进入编程模式
+    pub fn connect(link: &'l mut Link<'p>) -> Result<Self> {
+        let mut synced = false;
+        for _ in 0..SYNC_ATTEMPTS {
+            link.send(&[GET_SYNC, CRC_EOP])?;
+            if link.read_exact(2, Duration::from_millis(300))?.as_deref() == Some(&[INSYNC, OK]) {
+                synced = true;
+                break;
+            }
+        }
+        if !synced {
+            bail!("bootloader 没有应答 STK500 同步命令，请确认复位电路（DTR 经电容接 RESET）正常、波特率正确（Uno 的 Optiboot 为 115200，旧版 Nano 为 57600）");
+        }
+        // 丢弃多次同步产生的多余应答
+        link.purge()?;
+        let mut stk = Stk500 { link, version: (0, 0) };
+        stk.version = (stk.parameter(SW_MAJOR)?, stk.parameter(SW_MINOR)?);
+        stk.command(&[ENTER_PROGMODE], 0)?;
+        Ok(stk)
+    }
+
+    /// 发送一条命令并读取应答中的 `len` 字节数据
+    fn command(&mut self, command: &[u8], len: usize) -> Result<Vec<u8>> {
+        let mut packet = command.to_vec();
+        packet.push(CRC_EOP);
+        self.link.send(&packet)?;
+        let Some(reply) = self.link.read_exact(len + 2, TIMEOUT)? else {
+            bail!("等待 bootloader 应答命令 {:02X} 超时", command[0]);
+        };
+        if reply[0] != INSYNC || reply[len + 1] != OK {
+            bail!("bootloader 应答命令 {:02X} 有误：{}", command[0], format_hex(&reply));
+        }
+        Ok(reply[1..=len].to_vec())
+    }
+
+    fn parameter(&mut self, parameter: u8) -> Result<u8> {
+        Ok(self.command(&[GET_PARAMETER, parameter], 1)?[0])
+    }
+
+    fn load_address(&mut self, address: u32) -> Result<()> {
+        let word = (address / 2) as u16;
+        self.command(&[LOAD_ADDRESS, word as u8, (word >> 8) as u8], 0).map(|_| ())
+    }
+}
+
+impl Programmer for Stk500<'_, '_> {
+    fn describe(&self) -> String {
+        format!("STK500v1 bootloader {}.{}", self.version.0, self.version.1)
+    }
+
+    fn signature(&mut self) -> Result<[u8; 3]> {
+        let signature = self.command(&[READ_SIGN], 3)?;
+        Ok([signature[0], signature[1], signature[2]])
+    }
+
+    fn erase(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_page(&mut self, address: u32, data: &[u8]) -> Result<()> {
+        self.load_address(address)?;
+        let mut command = vec![PROG_PAGE, (data.len() >> 8) as u8, data.len() as u8, b'F'];
+        command.extend_from_slice(data);
+        self.command(&command, 0).map(|_| ())
+    }
+
+    fn read_page(&mut self, address: u32, len: usize) -> Result<Vec<u8>> {
+        self.load_address(address)?;
+        self.command(&[READ_PAGE, (len >> 8) as u8, len as u8, b'F'], len)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.command(&[LEAVE_PROGMODE], 0).map(|_| ())
+    }
+}
+
+/// AVR109 bootloader
+pub struct Avr109<'l, 'p> {
+    link: &'l mut Link<'p>,
+    id: String,
+    block: usize,
+}
+
+impl<'l, 'p> Avr109<'l, 'p> {
+    /// 读取软件标识和块大小，进入编程模式
+    pub fn connect(link: &'l mut Link<'p>) -> Result<Self> {
+        link.send(b"S")?;
+        let Some(id) = link.read_exact(7, TIMEOUT)? else {
+            bail!("bootloader 没有应答 AVR109 标识命令，Caterina 需先以 1200 波特率打开再关闭串口进入 bootloader（端口号可能改变）");
+        };
+        let mut avr = Avr109 { link, id: String::from_utf8_lossy(&id).trim().to_string(), block: 0 };
+        avr.link.send(b"b")?;
+        avr.block = match avr.link.read_exact(3, TIMEOUT)? {
+            Some(reply) if reply[0] == b'Y' => u16::from_be_bytes([reply[1], reply[2]]) as usize,
+            _ => bail!("bootloader 不支持块写入（AVR109 'b' 命令）"),
+        };
+        avr.command(b"P")?;
+        Ok(avr)
+    }
+
+    /// 发送命令并等待 '\r'
+    fn command(&mut self, command: &[u8]) -> Result<()> {
+        self.command_timeout(command, TIMEOUT)
+    }
+
+    fn command_timeout(&mut self, command: &[u8], timeout: Duration) -> Result<()> {
+        self.link.send(command)?;
+        match self.link.read_byte(timeout)? {
+            Some(b'\r') => Ok(()),
+            Some(byte) => bail!("bootloader 应答命令 '{}' 有误：{:02X}", command[0] as char, byte),
+            None => bail!("等待 bootloader 应答命令 '{}' 超时", command[0] as char),
+        }
+    }
+
+    fn set_address(&mut self, address: u32) -> Result<()> {
+        let word = (address / 2) as u16;
+        self.command(&[b'A', (word >> 8) as u8, word as u8])
+    }
+}
+
+impl Programmer for Avr109<'_, '_> {
+    fn describe(&self) -> String {
+        format!("AVR109 bootloader {}", self.id)
+    }
+
+    fn signature(&mut self) -> Result<[u8; 3]> {
+        self.link.send(b"s")?;
+        match self.link.read_exact(3, TIMEOUT)? {
+            // 高位在后
+            Some(signature) => Ok([signature[2], signature[1], signature[0]]),
+            None => bail!("读取芯片签名超时"),
+        }
+    }
+
+    fn erase(&mut self) -> Result<()> {
+        self.command_timeout(b"e", Duration::from_secs(10))
+    }
+
+    fn write_page(&mut self, address: u32, data: &[u8]) -> Result<()> {
+        self.set_address(address)?;
+        let mut command = vec![b'B', (data.len() >> 8) as u8, data.len() as u8, b'F'];
+        command.extend_from_slice(data);
+        self.command(&command)
+    }
+
+    fn read_page(&mut self, address: u32, len: usize) -> Result<Vec<u8>> {
+        self.set_address(address)?;
+        self.link.send(&[b'g', (len >> 8) as u8, len as u8, b'F'])?;
+        match self.link.read_exact(len, TIMEOUT)? {
+            Some(data) => Ok(data),
+            None => bail!("读取 0x{:05X} 超时", address),
+        }
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.command(b"L")?;
+        self.command(b"E")
+    }
+
+    fn block_size(&self) -> Option<usize> {
+        Some(self.block)
+    }
+}
+
+/// 常见芯片的名称和 flash 页大小
+pub fn chip(signature: [u8; 3]) -> Option<(&'static str, usize)> {
+    Some(match signature {
+        [0x1E, 0x95, 0x0F] => ("ATmega328P", 128),
+        [0x1E, 0x95, 0x14] => ("ATmega328", 128),
+        [0x1E, 0x95, 0x16] => ("ATmega328PB", 128),
+        [0x1E, 0x94, 0x0B] => ("ATmega168P", 128),
+        [0x1E, 0x94, 0x06] => ("ATmega168", 128),
+        [0x1E, 0x93, 0x0F] => ("ATmega88P", 64),
+        [0x1E, 0x93, 0x07] => ("ATmega8", 64),
+        [0x1E, 0x95, 0x87] => ("ATmega32U4", 128),
+        [0x1E, 0x97, 0x05] => ("ATmega1284P", 256),
+        [0x1E, 0x96, 0x09] => ("ATmega644", 256),
+        [0x1E, 0x96, 0x0A] => ("ATmega644P", 256),
+        _ => return None,
+    })
+}
@@ -0,0 +1,198 @@
+//! 吞吐量测试：在指定时长内以最快速度收发数据，统计实际速率与波特率理论速率的比值，
+//! 并根据递增序列的连续性检查丢字节
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::events;
+use crate::parse_duration;
+
+/// bench 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    /// 测试方向：tx 只发送，rx 只接收（对端运行 tx），both 同时收发（需短接 TX/RX 或对端回显）
+    #[arg(long, value_enum, default_value_t = Mode::Both)]
+    pub mode: Mode,
+
+    /// 测试时长
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
+    pub duration: Duration,
+}
+
+/// 测试方向
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Mode {
+    Tx,
+    Rx,
+    Both,
+}
+
+/// 接收统计
+#[derive(Default)]
+struct RxStats {
+    bytes: u64,
+    /// 递增序列中断的次数
+    gaps: u64,
+    /// 按序列跳变估算的丢失字节数（每次中断最多计 255）
+    missing: u64,
+    last: Option<u8>,
+    /// 收到第一个和最后一个字节的时刻，用于计算速率
+    first_at: Option<Instant>,
+    last_at: Option<Instant>,
+}
+
+impl RxStats {
+    fn feed(&mut self, data: &[u8]) {
+        let now = Instant::now();
+        self.first_at.get_or_insert(now);
+        self.last_at = Some(now);
+        for &byte in data {
+            if let Some(last) = self.last {
+                let expected = last.wrapping_add(1);
+                if byte != expected {
+                    self.gaps += 1;
+                    self.missing += byte.wrapping_sub(expected) as u64;
+                }
+            }
+            self.last = Some(byte);
+        }
+        self.bytes += data.len() as u64;
+    }
+
+    fn elapsed(&self) -> Duration {
+        match (self.first_at, self.last_at) {
+            (Some(first), Some(last)) => last - first,
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// 每个字符在线路上占用的位数（起始位 + 数据位 + 校验位 + 停止位）
+fn bits_per_char(port: &dyn SerialPort) -> Result<f64> {
+    let data = match port.data_bits().context("读取数据位失败")? {
+        serialport::DataBits::Five => 5.0,
+        serialport::DataBits::Six => 6.0,
+        serialport::DataBits::Seven => 7.0,
+        serialport::DataBits::Eight => 8.0,
+    };
+    let parity = match port.parity().context("读取校验位失败")? {
+        serialport::Parity::None => 0.0,
+        _ => 1.0,
+    };
+    let stop = match port.stop_bits().context("读取停止位失败")? {
+        serialport::StopBits::One => 1.0,
+        serialport::StopBits::Two => 2.0,
+    };
+    Ok(1.0 + data + parity + stop)
+}
+
+/// 执行吞吐量测试
+pub fn run_bench(port: &mut Box<dyn SerialPort>, opts: &BenchArgs) -> Result<()> {
+    let baud = port.baud_rate().context("读取波特率失败")?;
+    let theoretical = baud as f64 / bits_per_char(port.as_ref())?;
+    events::status(&format!(
+        "开始吞吐量测试（{:?}，理论速率 {:.0} 字节/秒）...",
+        opts.duration, theoretical
+    ));
+
+    let (sent, rx) = match opts.mode {
+        Mode::Tx => (Some(transmit(port, opts.duration)?), None),
+        Mode::Rx => (None, Some(receive(port, opts.duration)?)),
+        Mode::Both => {
+            let mut writer = port.try_clone().context("无法复制串口句柄")?;
+            thread::scope(|scope| {
+                let tx = scope.spawn(|| transmit(&mut writer, opts.duration));
+                // 多接收一会儿，收完发送端最后写出的数据
+                let rx = receive(port, opts.duration + Duration::from_millis(500));
+                let tx = tx.join().map_err(|_| anyhow::anyhow!("发送线程异常退出"))?;
+                Ok::<_, anyhow::Error>((Some(tx?), Some(rx?)))
+            })?
+        }
+    };
+
+    if let Some((bytes, elapsed)) = sent {
+        report("发送", bytes, elapsed, theoretical);
+    }
+    let Some(rx) = rx else { return Ok(()) };
+    report("接收", rx.bytes, rx.elapsed(), theoretical);
+    if rx.bytes == 0 {
+        bail!("未收到任何数据");
+    }
+
+    let mut problems = Vec::new();
+    if rx.gaps > 0 {
+        problems.push(format!("序列中断 {} 次，估计丢失 {} 字节", rx.gaps, rx.missing));
+    }
+    if let Some((bytes, _)) = sent {
+        if rx.bytes < bytes {
+            problems.push(format!("发送 {} 字节，只收到 {} 字节", bytes, rx.bytes));
+        }
+    }
+    if problems.is_empty() {
+        events::status("未发现丢字节");
+    } else {
+        events::status(&format!("警告：{}", problems.join("；")));
+    }
+    Ok(())
+}
+
+fn report(direction: &str, bytes: u64, elapsed: Duration, theoretical: f64) {
+    let secs = elapsed.as_secs_f64();
+    if secs == 0.0 {
+        events::status(&format!("{}：{} 字节", direction, bytes));
+        return;
+    }
+    let rate = bytes as f64 / secs;
+    events::status(&format!(
+        "{}：{} 字节 / {:.2} 秒 = {:.0} 字节/秒（理论值的 {:.1}%）",
+        direction,
+        bytes,
+        secs,
+        rate,
+        rate * 100.0 / theoretical
+    ));
+}
+
+/// 持续发送递增字节序列，返回发送的字节数和耗时（含等待数据发完）
+fn transmit(port: &mut Box<dyn SerialPort>, duration: Duration) -> Result<(u64, Duration)> {
+    let start = Instant::now();
+    let mut chunk = [0u8; 1024];
+    let mut next = 0u8;
+    let mut sent = 0;
+    while start.elapsed() < duration {
+        chunk.iter_mut().for_each(|b| {
+            *b = next;
+            next = next.wrapping_add(1);
+        });
+        let mut pending = &chunk[..];
+        while !pending.is_empty() {
+            match port.write(pending) {
+                Ok(n) => pending = &pending[n..],
+                // 发送缓冲区满，稍后重试
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e).context("写入串口失败"),
+            }
+        }
+        sent += chunk.len() as u64;
+    }
+    port.flush().context("刷新缓冲区失败")?;
+    Ok((sent, start.elapsed()))
+}
+
+/// 接收数据并检查递增序列的连续性
+fn receive(port: &mut Box<dyn SerialPort>, duration: Duration) -> Result<RxStats> {
+    let start = Instant::now();
+    let mut stats = RxStats::default();
+    let mut buffer = [0u8; 4096];
+    while start.elapsed() < duration {
+        match port.read(&mut buffer) {
+            Ok(n) => stats.feed(&buffer[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e).context("读取串口失败"),
+        }
+    }
+    Ok(stats)
+}
@@ -0,0 +1,288 @@
+//! 误码率测试：持续发送伪随机序列（PRBS），接收端自同步后逐位比较，统计误码率
+//!
+//! 发送端和接收端可以是同一实例（短接 TX/RX，`--role both`），
+//! 也可以在线路两端各运行一个实例（`--role tx` / `--role rx`）。
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::events;
+use crate::parse_duration;
+use crate::signal::Rs485;
+
+/// 失步判定窗口（字节）
+const SYNC_WINDOW: u64 = 64;
+
+/// ber 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct BerArgs {
+    /// 本实例的角色：tx 只发送，rx 只接收，both 同时收发（回环）
+    #[arg(long, value_enum, default_value_t = Role::Both)]
+    pub role: Role,
+
+    /// PRBS 多项式阶数
+    #[arg(long, value_enum, default_value_t = PrbsOrder::Prbs15)]
+    pub prbs: PrbsOrder,
+
+    /// 测试时长
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
+    pub duration: Duration,
+
+    /// 接收端输出中间结果的间隔
+    #[arg(long, default_value = "1s", value_parser = parse_duration)]
+    pub report: Duration,
+}
+
+/// 测试角色
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Tx,
+    Rx,
+    Both,
+}
+
+/// PRBS 序列（ITU-T O.150 多项式）
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum PrbsOrder {
+    /// x^7 + x^6 + 1
+    #[value(name = "7")]
+    Prbs7,
+    /// x^9 + x^5 + 1
+    #[value(name = "9")]
+    Prbs9,
+    /// x^11 + x^9 + 1
+    #[value(name = "11")]
+    Prbs11,
+    /// x^15 + x^14 + 1
+    #[value(name = "15")]
+    Prbs15,
+    /// x^23 + x^18 + 1
+    #[value(name = "23")]
+    Prbs23,
+    /// x^31 + x^28 + 1
+    #[value(name = "31")]
+    Prbs31,
+}
+
+impl PrbsOrder {
+    /// (阶数, 抽头)
+    fn taps(self) -> (u32, u32) {
+        match self {
+            PrbsOrder::Prbs7 => (7, 6),
+            PrbsOrder::Prbs9 => (9, 5),
+            PrbsOrder::Prbs11 => (11, 9),
+            PrbsOrder::Prbs15 => (15, 14),
+            PrbsOrder::Prbs23 => (23, 18),
+            PrbsOrder::Prbs31 => (31, 28),
+        }
+    }
+}
+
+/// 斐波那契 LFSR：s[k] = s[k-n] xor s[k-m]，状态即最近输出的 n 位（bit0 为最新）
+struct Prbs {
+    order: u32,
+    tap: u32,
+    state: u32,
+}
+
+impl Prbs {
+    fn new(order: PrbsOrder) -> Self {
+        let (order, tap) = order.taps();
+        Prbs { order, tap, state: Self::mask_for(order) }
+    }
+
+    fn mask_for(order: u32) -> u32 {
+        ((1u64 << order) - 1) as u32
+    }
+
+    fn next_bit(&mut self) -> u8 {
+        let bit = ((self.state >> (self.order - 1)) ^ (self.state >> (self.tap - 1))) & 1;
+        self.state = ((self.state << 1) | bit) & Self::mask_for(self.order);
+        bit as u8
+    }
+
+    /// 生成下一个字节（高位先出）
+    fn next_byte(&mut self) -> u8 {
+        (0..8).fold(0, |byte, _| (byte << 1) | self.next_bit())
+    }
+
+    /// 用收到的位更新状态（同步时使用）
+    fn push_byte(&mut self, byte: u8) {
+        self.state = ((self.state << 8) | byte as u32) & Self::mask_for(self.order);
+    }
+}
+
+/// 接收端校验器：收满 n 位后以收到的数据作为生成器状态（自同步），之后逐字节比较；
+/// 窗口内误码过多视为失步（丢字节或多字节），丢弃该窗口的统计并重新同步
+struct Checker {
+    prbs: Prbs,
+    synced: bool,
+    sync_bits: u32,
+    bits: u64,
+    bit_errors: u64,
+    byte_errors: u64,
+    resyncs: u64,
+    window_bytes: u64,
+    window_bit_errors: u64,
+    window_byte_errors: u64,
+}
+
+impl Checker {
+    fn new(order: PrbsOrder) -> Self {
+        Checker {
+            prbs: Prbs::new(order),
+            synced: false,
+            sync_bits: 0,
+            bits: 0,
+            bit_errors: 0,
+            byte_errors: 0,
+            resyncs: 0,
+            window_bytes: 0,
+            window_bit_errors: 0,
+            window_byte_errors: 0,
+        }
+    }
+
+    fn feed(&mut self, data: &[u8]) {
+        for &byte in data {
+            if !self.synced {
+                self.prbs.push_byte(byte);
+                self.sync_bits += 8;
+                // 全零状态会一直预测出 0（如线路空闲为低电平），不能作为同步点
+                if self.sync_bits >= self.prbs.order && self.prbs.state != 0 {
+                    self.synced = true;
+                }
+                continue;
+            }
+
+            let diff = byte ^ self.prbs.next_byte();
+            self.window_bytes += 1;
+            self.window_bit_errors += diff.count_ones() as u64;
+            self.window_byte_errors += (diff != 0) as u64;
+            if self.window_bytes == SYNC_WINDOW {
+                self.end_window();
+            }
+        }
+    }
+
+    fn end_window(&mut self) {
+        // 误码超过 1/4 时几乎可以肯定是位置错开了，而不是线路误码
+        if self.window_bit_errors > self.window_bytes * 8 / 4 {
+            self.synced = false;
+            self.sync_bits = 0;
+            self.resyncs += 1;
+        } else {
+            self.bits += self.window_bytes * 8;
+            self.bit_errors += self.window_bit_errors;
+            self.byte_errors += self.window_byte_errors;
+        }
+        self.window_bytes = 0;
+        self.window_bit_errors = 0;
+        self.window_byte_errors = 0;
+    }
+
+    fn summary(&self) -> String {
+        let rate = if self.bits == 0 { 0.0 } else { self.bit_errors as f64 / self.bits as f64 };
+        format!(
+            "已校验 {} 位，误码 {} 位，误字节 {}，误码率 {:.3e}，失步 {} 次{}",
+            self.bits,
+            self.bit_errors,
+            self.byte_errors,
+            rate,
+            self.resyncs,
+            if self.synced { "" } else { "（当前未同步）" }
+        )
+    }
+}
+
+/// 执行误码率测试
+pub fn run_ber(port: &mut Box<dyn SerialPort>, opts: &BerArgs, rs485: Option<Rs485>) -> Result<()> {
+    events::status(&format!("开始误码率测试（PRBS{}，{:?}）...", opts.prbs.taps().0, opts.duration));
+    match opts.role {
+        Role::Tx => {
+            let sent = transmit(port, opts, rs485)?;
+            events::status(&format!("发送完成，共 {} 字节", sent));
+            Ok(())
+        }
+        Role::Rx => receive(port, opts, opts.duration),
+        Role::Both => {
+            let mut writer = port.try_clone().context("无法复制串口句柄")?;
+            let tx = thread::scope(|scope| {
+                let tx = scope.spawn(|| transmit(&mut writer, opts, rs485));
+                // 多接收一会儿，收完发送端最后写出的数据
+                let rx = receive(port, opts, opts.duration + Duration::from_millis(500));
+                let tx = tx.join().map_err(|_| anyhow::anyhow!("发送线程异常退出"))?;
+                rx.and(tx)
+            })?;
+            events::status(&format!("共发送 {} 字节", tx));
+            Ok(())
+        }
+    }
+}
+
+/// 持续发送 PRBS 序列，返回发送的字节数
+fn transmit(port: &mut Box<dyn SerialPort>, opts: &BerArgs, rs485: Option<Rs485>) -> Result<u64> {
+    let mut prbs = Prbs::new(opts.prbs);
+    let mut sent = 0;
+    let mut write = |port: &mut Box<dyn SerialPort>| {
+        let deadline = Instant::now() + opts.duration;
+        let mut chunk = [0u8; 256];
+        while Instant::now() < deadline {
+            chunk.iter_mut().for_each(|b| *b = prbs.next_byte());
+            let mut pending = &chunk[..];
+            while !pending.is_empty() {
+                match port.write(pending) {
+                    Ok(n) => pending = &pending[n..],
+                    // 发送缓冲区满，稍后重试
+                    Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+                    Err(e) => return Err(e).context("写入串口失败"),
+                }
+            }
+            sent += chunk.len() as u64;
+        }
+        Ok(())
+    };
+    match rs485 {
+        Some(rs485) => rs485.transmit(port, write)?,
+        None => {
+            write(port)?;
+            port.flush().context("刷新缓冲区失败")?;
+        }
+    }
+    Ok(sent)
+}
+
+/// 接收并校验 PRBS 序列，定期输出中间结果
+fn receive(port: &mut Box<dyn SerialPort>, opts: &BerArgs, duration: Duration) -> Result<()> {
+    let mut checker = Checker::new(opts.prbs);
+    let start = Instant::now();
+    let mut next_report = start + opts.report;
+    let mut received = 0;
+    let mut buffer = [0u8; 1024];
+    while start.elapsed() < duration {
+        match port.read(&mut buffer) {
+            Ok(n) => {
+                received += n;
+                checker.feed(&buffer[..n]);
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e).context("读取串口失败"),
+        }
+        if Instant::now() >= next_report {
+            next_report += opts.report;
+            events::status(&format!("[{:.0}s] {}", start.elapsed().as_secs_f64(), checker.summary()));
+        }
+    }
+
+    events::status(&format!("接收 {} 字节，{}", received, checker.summary()));
+    if received == 0 {
+        bail!("未收到任何数据");
+    }
+    if checker.bits == 0 {
+        bail!("未能与 PRBS{} 序列同步，请确认发送端使用相同的 --prbs", opts.prbs.taps().0);
+    }
+    Ok(())
+}
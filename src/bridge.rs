@@ -0,0 +1,1594 @@
+//! 网络桥接：把串口开放给局域网中的其他机器（类似 ser2net），网络上收到的字节原样写入串口，
+//! 串口收到的字节原样发给网络对端；UDP 输出只把串口收到的数据发到网络上，MQTT 按主题发布和订阅，
+//! gRPC 服务为各种语言的客户端提供收发数据和控制线的接口
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::events::{self, Event};
+use crate::framing::{Deframer, FrameSpec};
+use crate::grpc::{self, Frame, Value};
+use crate::mqtt::{self, Broker, Packet, Refused};
+use crate::rfc2217::{self, Item, PortControl, Telnet};
+use crate::signal::{ModemStatus, Rs485};
+use crate::websocket::{self, Decoder, Message};
+use crate::{lock, parse_duration, parse_hex};
+
+/// bridge 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct BridgeArgs {
+    #[command(subcommand)]
+    pub command: BridgeCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum BridgeCommand {
+    /// 监听 TCP 端口，把连接上的客户端与串口双向转发（可用 nc、PuTTY 的 Raw 模式或 socat 连接）
+    TcpServer {
+        /// 监听的地址和端口（如 :5000 或 0.0.0.0:5000，只允许本机连接时用 127.0.0.1:5000）
+        #[arg(long, value_name = "ADDR")]
+        listen: String,
+
+        /// 允许多个客户端同时连接：串口数据发给所有客户端，任一客户端的数据都写入串口
+        /// （默认只允许一个客户端，其余连接被拒绝）
+        #[arg(long)]
+        multi: bool,
+
+        /// 客户端空闲（双向都没有数据）超过该时长时断开（如 10m）
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        idle_timeout: Option<Duration>,
+
+        /// 使用 RFC 2217（Telnet COM-Port-Control）：客户端（如 pyserial 的 rfc2217://）可以远程设置
+        /// 波特率、数据位、校验位、停止位、流控和 DTR/RTS/break，并收到 CTS/DSR 等输入线的变化
+        #[arg(long)]
+        rfc2217: bool,
+    },
+    /// 接受 WebSocket 连接（如浏览器中 new WebSocket("ws://主机:8080/")），网页可以直接收发串口数据
+    Ws {
+        /// 监听的地址和端口（如 :8080 或 0.0.0.0:8080，只允许本机连接时用 127.0.0.1:8080）
+        #[arg(long, value_name = "ADDR")]
+        listen: String,
+
+        /// 消息格式：json 时串口数据以文本消息发出（内容与 --output jsonl 的 data 事件相同），
+        /// 客户端发送 {"text":"..."} 或 {"hex":"..."}；binary 时串口数据原样以二进制消息发出，
+        /// 客户端的文本消息也原样写入串口。两种格式下客户端的二进制消息都原样写入串口
+        #[arg(long, value_enum, default_value = "json")]
+        format: WsFormat,
+
+        /// 允许多个客户端同时连接（如同时打开多个网页），串口数据发给所有客户端
+        /// （默认只允许一个客户端，其余连接被拒绝）
+        #[arg(long)]
+        multi: bool,
+
+        /// 客户端空闲（双向都没有数据）超过该时长时断开（如 10m）
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        idle_timeout: Option<Duration>,
+    },
+    /// 连接远端的 TCP 服务，把连接与串口双向转发，断开后自动重连（断开期间串口收到的数据被丢弃）
+    TcpClient {
+        /// 远端地址和端口（如 192.168.1.10:4000、server.local:4000）
+        #[arg(value_name = "HOST:PORT")]
+        address: String,
+
+        /// 断开或连接失败后重连的间隔
+        #[arg(long, default_value = "3s", value_name = "DURATION", value_parser = parse_duration)]
+        retry_interval: Duration,
+
+        /// 断开后不重连，直接退出
+        #[arg(long)]
+        no_reconnect: bool,
+    },
+    /// 连接 MQTT 服务器：把串口收到的每块数据（指定了 --frame 时为每个完整的帧，按行发布可用 --frame 'delim:\n'）
+    /// 发布到 --topic，订阅 --command-topic 并把收到的消息写入串口；断开后自动重连（断开期间串口收到的数据被丢弃）。
+    /// 只支持明文连接，服务器要求 TLS 时需用 stunnel 等在本机建立隧道
+    Mqtt(MqttArgs),
+    /// 把串口收到的每块数据（指定了 --frame 时为每个完整的帧）作为一个 UDP 数据报发出，
+    /// 网络上任意多个接收方可以同时收听（只发送，不接收）
+    Udp {
+        /// 目标地址和端口（如 192.168.1.20:6000；广播如 192.168.1.255:6000，组播如 239.1.2.3:6000）
+        #[arg(value_name = "HOST:PORT")]
+        address: String,
+
+        /// 允许发往广播地址
+        #[arg(long)]
+        broadcast: bool,
+
+        /// 组播数据报的 TTL（可经过的路由器数，1 为只在本网段，仅 IPv4）
+        #[arg(long, default_value = "1", value_name = "N")]
+        ttl: u32,
+
+        /// 发送使用的本地地址和端口（如 192.168.1.5:0，默认由系统选择）
+        #[arg(long, value_name = "ADDR")]
+        bind: Option<String>,
+    },
+    /// gRPC 服务（HTTP/2 明文）：SendBytes 写入串口，StreamReceived 订阅串口收到的数据（指定了 --frame 时
+    /// 每条消息为一个完整的帧），SetControlLines 设置 DTR/RTS/break 并返回输入状态线；服务定义见
+    /// proto/serial_tool.proto，可用 protoc 生成 Go、Python 等语言的客户端。允许多个客户端同时连接
+    Grpc {
+        /// 监听的地址和端口（如 :50051 或 0.0.0.0:50051，只允许本机连接时用 127.0.0.1:50051）
+        #[arg(long, value_name = "ADDR")]
+        listen: String,
+    },
+}
+
+/// bridge mqtt 的参数
+#[derive(clap::Args, Debug)]
+pub struct MqttArgs {
+    /// 服务器地址：mqtt://[用户名:密码@]主机[:端口]（默认端口 1883），也可以只写 主机[:端口]；
+    /// 不支持 TLS（mqtts://），用户名和密码以明文发送
+    #[arg(value_name = "URL", value_parser = mqtt::parse_broker)]
+    pub broker: Broker,
+
+    /// 发布串口数据的主题（如 bench/sensor1/rx）
+    #[arg(long, value_name = "TOPIC")]
+    pub topic: String,
+
+    /// 订阅的命令主题，收到的消息原样写入串口（可用 + 和 # 通配符）
+    #[arg(long, value_name = "TOPIC")]
+    pub command_topic: Option<String>,
+
+    /// 用户名（优先于地址中的用户名）
+    #[arg(long)]
+    pub username: Option<String>,
+
+    /// 密码（优先于地址中的密码）
+    #[arg(long)]
+    pub password: Option<String>,
+
+    /// 客户端标识符（默认为 serial-tool-<进程号>）
+    #[arg(long, value_name = "ID")]
+    pub client_id: Option<String>,
+
+    /// 发布和订阅的 QoS：0 为最多一次，1 为至少一次（服务器确认收到，断开时未确认的消息不会重发）
+    #[arg(long, default_value = "0", value_parser = clap::value_parser!(u8).range(0..=1))]
+    pub qos: u8,
+
+    /// 发布为保留消息（新的订阅者会先收到最后一条）
+    #[arg(long)]
+    pub retain: bool,
+
+    /// 心跳间隔，0s 为不发心跳
+    #[arg(long, default_value = "60s", value_name = "DURATION", value_parser = parse_duration)]
+    pub keep_alive: Duration,
+
+    /// 断开或连接失败后重连的间隔
+    #[arg(long, default_value = "3s", value_name = "DURATION", value_parser = parse_duration)]
+    pub retry_interval: Duration,
+
+    /// 断开后不重连，直接退出
+    #[arg(long)]
+    pub no_reconnect: bool,
+}
+
+/// WebSocket 消息格式
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WsFormat {
+    /// JSON 文本消息
+    Json,
+    /// 二进制消息，内容为原始字节
+    Binary,
+}
+
+/// 检查停止标志和空闲时长的间隔
+const POLL: Duration = Duration::from_millis(200);
+/// 对端接收太慢时放弃写入的时长
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+/// 连接远端的超时
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// gRPC 每个订阅最多缓存的消息数，客户端接收太慢时丢弃新的数据
+const GRPC_QUEUE: usize = 1024;
+
+/// 执行 bridge 子命令，`frame` 为 --frame 指定的帧格式（UDP、MQTT 和 gRPC 按帧发送）
+pub fn run_bridge(port: &mut Box<dyn SerialPort>, opts: &BridgeArgs, rs485: Option<Rs485>, frame: Option<&FrameSpec>) -> Result<()> {
+    match &opts.command {
+        BridgeCommand::TcpServer { listen, multi, idle_timeout, rfc2217 } => {
+            let wire = if *rfc2217 { Wire::Telnet } else { Wire::Raw };
+            tcp_server(port, listen, *multi, *idle_timeout, wire, rs485)
+        }
+        BridgeCommand::Ws { listen, format, multi, idle_timeout } => {
+            tcp_server(port, listen, *multi, *idle_timeout, Wire::WebSocket(*format), rs485)
+        }
+        BridgeCommand::TcpClient { address, retry_interval, no_reconnect } => {
+            tcp_client(port, address, (!no_reconnect).then_some(*retry_interval), rs485)
+        }
+        BridgeCommand::Mqtt(mqtt) => mqtt_bridge(port, mqtt, rs485, frame),
+        BridgeCommand::Udp { address, broadcast, ttl, bind } => udp(port, address, *broadcast, *ttl, bind.as_deref(), frame),
+        BridgeCommand::Grpc { listen } => grpc_server(port, listen, rs485, frame),
+    }
+}
+
+/// 与对端之间的传输方式
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Wire {
+    /// 原样收发字节
+    Raw,
+    /// Telnet（RFC 2217），数据中的 0xFF 需要转义
+    Telnet,
+    /// WebSocket，串口数据按指定格式封装为消息
+    WebSocket(WsFormat),
+}
+
+impl Wire {
+    /// 按传输方式封装要发给对端的串口数据
+    fn encode(self, data: &[u8]) -> Cow<'_, [u8]> {
+        match self {
+            Wire::Raw => Cow::Borrowed(data),
+            Wire::Telnet => Cow::Owned(rfc2217::escape(data)),
+            Wire::WebSocket(WsFormat::Json) => Cow::Owned(websocket::frame(websocket::TEXT, events::json(Event::Rx(data)).as_bytes())),
+            Wire::WebSocket(WsFormat::Binary) => Cow::Owned(websocket::frame(websocket::BINARY, data)),
+        }
+    }
+}
+
+/// 一个连接上的协议状态
+enum Session {
+    Raw,
+    Telnet(Telnet, PortControl),
+    WebSocket(Decoder, WsFormat),
+}
+
+/// 网络对端（服务端模式下的客户端，或客户端模式下的远端服务）
+struct Client {
+    id: usize,
+    peer: SocketAddr,
+    stream: TcpStream,
+    /// 最近一次收发数据的时间
+    active: Instant,
+    wire: Wire,
+    /// 对端要求暂停发送（RFC 2217 的 FLOWCONTROL-SUSPEND），期间串口数据不发给它
+    suspended: bool,
+}
+
+/// 串口和网络对端之间的转发
+struct Hub<'p> {
+    /// 写入串口的句柄（各客户端的线程共用）
+    serial: Mutex<Box<dyn SerialPort>>,
+    rs485: Option<Rs485>,
+    clients: Mutex<Vec<Client>>,
+    next_id: AtomicUsize,
+    /// 串口出错时通知所有线程退出
+    stop: &'p AtomicBool,
+}
+
+/// 写入串口（RS-485 时切换收发方向）
+fn write_port(serial: &mut Box<dyn SerialPort>, rs485: Option<Rs485>, data: &[u8]) -> Result<()> {
+    let result = match rs485 {
+        Some(rs485) => rs485.transmit(serial, |port| port.write_all(data).context("写入串口失败")),
+        None => serial.write_all(data).context("写入串口失败"),
+    };
+    events::emit(Event::Tx(data));
+    result
+}
+
+/// 持续读取串口，把每块数据（指定了帧格式时为每个完整的帧）交给 `send`，直到设置了停止标志或读取出错
+fn pump_frames(port: &mut Box<dyn SerialPort>, frame: Option<&FrameSpec>, stop: &AtomicBool, mut send: impl FnMut(&[u8])) -> Result<()> {
+    let mut deframer = frame.cloned().map(Deframer::new);
+    if let (Some(deframer), Ok(baud)) = (deframer.as_mut(), port.baud_rate()) {
+        deframer.set_baud(baud);
+    }
+    let mut buffer = [0u8; 4096];
+    while !stop.load(Ordering::Relaxed) {
+        let n = match port.read(&mut buffer) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
+                // 按空闲间隔分帧的格式在这里输出最后一帧
+                for frame in deframer.as_mut().map(|deframer| deframer.poll_idle(Instant::now())).unwrap_or_default() {
+                    send(&frame.data);
+                }
+                continue;
+            }
+            Err(e) => return Err(e).context("读取串口失败"),
+        };
+        events::emit(Event::Rx(&buffer[..n]));
+        match deframer.as_mut() {
+            Some(deframer) => {
+                for frame in deframer.push(&buffer[..n], Instant::now()) {
+                    send(&frame.data);
+                }
+            }
+            None => send(&buffer[..n]),
+        }
+    }
+    Ok(())
+}
+
+impl Hub<'_> {
+    fn clients(&self) -> MutexGuard<'_, Vec<Client>> {
+        lock(&self.clients)
+    }
+
+    fn serial(&self) -> MutexGuard<'_, Box<dyn SerialPort>> {
+        lock(&self.serial)
+    }
+
+    /// 加入一个对端，返回它的编号
+    fn join(&self, stream: &TcpStream, peer: SocketAddr, wire: Wire) -> Result<usize> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let stream = stream.try_clone().context("无法复制网络连接句柄")?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT)).context("设置网络连接超时失败")?;
+        self.clients().push(Client { id, peer, stream, active: Instant::now(), wire, suspended: false });
+        Ok(id)
+    }
+
+    /// 只发给一个对端（Telnet 协商和 RFC 2217 应答），写入失败时由读取线程发现断开
+    fn send(&self, id: usize, data: &[u8]) {
+        if let Some(client) = self.clients().iter_mut().find(|client| client.id == id) {
+            let _ = client.stream.write_all(data);
+        }
+    }
+
+    /// 移除对端并关闭连接，返回是否还在列表中（没有被别处先移除）
+    fn leave(&self, id: usize) -> bool {
+        let mut clients = self.clients();
+        match clients.iter().position(|client| client.id == id) {
+            Some(index) => {
+                let _ = clients.remove(index).stream.shutdown(Shutdown::Both);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 把串口收到的数据发给所有对端，写入失败的对端断开
+    fn broadcast(&self, data: &[u8]) {
+        self.clients().retain_mut(|client| {
+            if client.suspended {
+                return true;
+            }
+            match client.stream.write_all(&client.wire.encode(data)) {
+                Ok(()) => {
+                    client.active = Instant::now();
+                    true
+                }
+                Err(e) => {
+                    events::status(&format!("发往 {} 失败（{}），已断开", client.peer, e));
+                    let _ = client.stream.shutdown(Shutdown::Both);
+                    false
+                }
+            }
+        });
+    }
+
+    /// 把对端发来的数据写入串口
+    fn write_serial(&self, id: usize, data: &[u8]) -> Result<()> {
+        if let Some(client) = self.clients().iter_mut().find(|client| client.id == id) {
+            client.active = Instant::now();
+        }
+        write_port(&mut self.serial(), self.rs485, data)
+    }
+
+    /// 对端最近一次收发数据以来的时长，已断开时返回 None
+    fn idle(&self, id: usize) -> Option<Duration> {
+        self.clients().iter().find(|client| client.id == id).map(|client| client.active.elapsed())
+    }
+
+    /// 持续读取串口并发给对端，直到读取出错或写入串口出错（已设置停止标志）
+    fn pump_serial(&self, port: &mut Box<dyn SerialPort>) -> Result<()> {
+        let mut buffer = [0u8; 4096];
+        while !self.stop.load(Ordering::Relaxed) {
+            let n = match port.read(&mut buffer) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => {
+                    self.stop.store(true, Ordering::Relaxed);
+                    return Err(e).context("读取串口失败");
+                }
+            };
+            events::emit(Event::Rx(&buffer[..n]));
+            self.broadcast(&buffer[..n]);
+        }
+        Ok(())
+    }
+
+    /// 服务端接受的连接：WebSocket 先完成握手，然后加入并转发直到断开
+    fn serve(&self, mut stream: TcpStream, peer: SocketAddr, idle_timeout: Option<Duration>, wire: Wire) {
+        if let Wire::WebSocket(_) = wire {
+            if let Err(e) = websocket::handshake(&mut stream) {
+                events::status(&format!("客户端 {} 握手失败: {:#}", peer, e));
+                let _ = stream.shutdown(Shutdown::Both);
+                return;
+            }
+        }
+        match self.join(&stream, peer, wire) {
+            Ok(id) => {
+                events::status(&format!("客户端 {} 已连接", peer));
+                self.pump_client(id, stream, peer, idle_timeout, wire);
+            }
+            Err(e) => events::status(&format!("客户端 {} 初始化失败: {:#}", peer, e)),
+        }
+    }
+
+    /// 持续读取一个对端并写入串口，直到对方断开、空闲超时或串口出错；
+    /// RFC 2217 时执行对方的串口控制命令，WebSocket 时按消息格式解码
+    fn pump_client(&self, id: usize, mut stream: TcpStream, peer: SocketAddr, idle_timeout: Option<Duration>, wire: Wire) {
+        let mut session = match wire {
+            Wire::Raw => Session::Raw,
+            Wire::Telnet => Session::Telnet(Telnet::new(), PortControl::new()),
+            Wire::WebSocket(format) => Session::WebSocket(Decoder::new(), format),
+        };
+        if let Session::Telnet(telnet, _) = &mut session {
+            self.send(id, &telnet.greeting());
+        }
+        let mut polled: Option<Instant> = None;
+        let mut buffer = [0u8; 4096];
+        let reason = loop {
+            if self.stop.load(Ordering::Relaxed) {
+                break "串口已关闭".to_string();
+            }
+            match (self.idle(id), idle_timeout) {
+                (None, _) => return,
+                (Some(idle), Some(timeout)) if idle >= timeout => break format!("空闲超过 {:?}", timeout),
+                _ => {}
+            }
+            if let Session::Telnet(_, control) = &mut session {
+                if polled.is_none_or(|at| at.elapsed() >= POLL) {
+                    polled = Some(Instant::now());
+                    if let Some(notify) = control.poll_modem(&mut self.serial()) {
+                        self.send(id, &notify);
+                    }
+                }
+            }
+            let result = match stream.read(&mut buffer) {
+                Ok(0) => break "对方关闭了连接".to_string(),
+                Ok(n) => match &mut session {
+                    Session::Raw => self.write_serial(id, &buffer[..n]).map(|()| None),
+                    Session::Telnet(telnet, control) => self.receive_telnet(id, peer, telnet, control, &buffer[..n]).map(|()| None),
+                    Session::WebSocket(decoder, format) => self.receive_websocket(id, decoder, *format, &buffer[..n]),
+                },
+                Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+                Err(e) => break e.to_string(),
+            };
+            match result {
+                Ok(None) => {}
+                Ok(Some(reason)) => break reason,
+                Err(e) => {
+                    self.stop.store(true, Ordering::Relaxed);
+                    break format!("{:#}", e);
+                }
+            }
+        };
+        if self.leave(id) {
+            events::status(&format!("{} 已断开（{}）", peer, reason));
+        }
+    }
+
+    /// 处理 Telnet 连接收到的数据：数据写入串口，串口控制命令作用于串口，应答发回对端
+    fn receive_telnet(&self, id: usize, peer: SocketAddr, telnet: &mut Telnet, control: &mut PortControl, data: &[u8]) -> Result<()> {
+        let mut reply = Vec::new();
+        for item in telnet.decode(data, &mut reply) {
+            match item {
+                Item::Data(data) => self.write_serial(id, &data)?,
+                Item::Command(command, payload) => {
+                    let (answer, note) = control.apply(&mut self.serial(), command, &payload);
+                    reply.extend(answer);
+                    if let Some(note) = note {
+                        events::status(&format!("{}：{}", peer, note));
+                    }
+                    if let Some(client) = self.clients().iter_mut().find(|client| client.id == id) {
+                        client.suspended = control.suspended;
+                    }
+                }
+            }
+        }
+        if !reply.is_empty() {
+            self.send(id, &reply);
+        }
+        Ok(())
+    }
+
+    /// 处理 WebSocket 连接收到的数据：消息内容写入串口，ping 和 close 的应答发回对端；
+    /// 返回需要断开时的原因（对方关闭或协议错误）
+    fn receive_websocket(&self, id: usize, decoder: &mut Decoder, format: WsFormat, data: &[u8]) -> Result<Option<String>> {
+        let mut reply = Vec::new();
+        let messages = match decoder.decode(data, &mut reply) {
+            Ok(messages) => messages,
+            Err(e) => {
+                // 1002：协议错误
+                self.send(id, &websocket::frame(websocket::CLOSE, &1002u16.to_be_bytes()));
+                return Ok(Some(format!("{:#}", e)));
+            }
+        };
+        for message in messages {
+            let data = match (message, format) {
+                (Message::Binary(data), _) => data,
+                (Message::Text(text), WsFormat::Binary) => text.into_bytes(),
+                (Message::Text(text), WsFormat::Json) => match parse_message(&text) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        // 格式有误的消息只告诉发送它的客户端
+                        reply.extend(websocket::frame(websocket::TEXT, events::json(Event::Error(&e)).as_bytes()));
+                        continue;
+                    }
+                },
+            };
+            if !data.is_empty() {
+                self.write_serial(id, &data)?;
+            }
+        }
+        if !reply.is_empty() {
+            self.send(id, &reply);
+        }
+        Ok(decoder.closed.then(|| "对方关闭了连接".to_string()))
+    }
+}
+
+/// 解析 JSON 格式下客户端发来的文本消息：{"text":"..."} 按 UTF-8 写入，{"hex":"..."} 按十六进制写入
+fn parse_message(message: &str) -> std::result::Result<Vec<u8>, String> {
+    let usage = || format!(r#"无法识别的消息 {}，应为 {{"text":"..."}} 或 {{"hex":"..."}}"#, message);
+    let mut reader = JsonReader { rest: message, depth: 0 };
+    let fields = reader.object().filter(|_| reader.rest.trim_matches(JSON_WHITESPACE).is_empty());
+    let Some(fields) = fields else {
+        return Err(format!("消息 {} 不是有效的 JSON 对象", message));
+    };
+    // 只看顶层的键，两个都有时不知道该用哪个
+    let mut found = fields.into_iter().filter(|(key, _)| key == "text" || key == "hex");
+    let (key, value) = match (found.next(), found.next()) {
+        (Some(field), None) => field,
+        _ => return Err(usage()),
+    };
+    let Some(value) = value else {
+        return Err(format!(r#"消息 {} 中 "{}" 的值应为字符串"#, message, key));
+    };
+    if key == "text" {
+        return Ok(value.into_bytes());
+    }
+    parse_hex(&value).map_err(|e| format!("无效的十六进制数据 '{}'：{}", value, e))
+}
+
+const JSON_WHITESPACE: [char; 4] = [' ', '\t', '\n', '\r'];
+/// JSON 对象和数组的最大嵌套层数
+const JSON_MAX_DEPTH: usize = 32;
+
+/// 按 JSON 语法读取客户端消息；只保留字符串的内容，其他类型的值只检查语法
+struct JsonReader<'a> {
+    rest: &'a str,
+    depth: usize,
+}
+
+impl JsonReader<'_> {
+    /// 跳过空白后，下一个字符是 `c` 时读掉它
+    fn eat(&mut self, c: char) -> bool {
+        self.rest = self.rest.trim_start_matches(JSON_WHITESPACE);
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 读取一个对象，返回其中的键和值（值是字符串时为 Some）
+    fn object(&mut self) -> Option<Vec<(String, Option<String>)>> {
+        if !self.eat('{') || self.depth == JSON_MAX_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        let mut fields = Vec::new();
+        if !self.eat('}') {
+            loop {
+                let key = self.string()?;
+                if !self.eat(':') {
+                    return None;
+                }
+                fields.push((key, self.value()?));
+                if self.eat('}') {
+                    break;
+                }
+                if !self.eat(',') {
+                    return None;
+                }
+            }
+        }
+        self.depth -= 1;
+        Some(fields)
+    }
+
+    fn array(&mut self) -> Option<()> {
+        if !self.eat('[') || self.depth == JSON_MAX_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        if !self.eat(']') {
+            loop {
+                self.value()?;
+                if self.eat(']') {
+                    break;
+                }
+                if !self.eat(',') {
+                    return None;
+                }
+            }
+        }
+        self.depth -= 1;
+        Some(())
+    }
+
+    /// 读取一个值，是字符串时返回其内容
+    fn value(&mut self) -> Option<Option<String>> {
+        self.rest = self.rest.trim_start_matches(JSON_WHITESPACE);
+        match self.rest.chars().next()? {
+            '"' => return self.string().map(Some),
+            '{' => drop(self.object()?),
+            '[' => self.array()?,
+            '-' | '0'..='9' => self.number()?,
+            _ => {
+                let literal = ["true", "false", "null"].into_iter().find(|literal| self.rest.starts_with(literal))?;
+                self.rest = &self.rest[literal.len()..];
+            }
+        }
+        Some(None)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if !self.eat('"') {
+            return None;
+        }
+        let mut escaped = false;
+        let end = self.rest.char_indices().find_map(|(at, c)| match c {
+            _ if escaped => {
+                escaped = false;
+                None
+            }
+            '\\' => {
+                escaped = true;
+                None
+            }
+            '"' => Some(at),
+            _ => None,
+        })?;
+        let raw = &self.rest[..end];
+        // 字符串中不能直接出现控制字符
+        if raw.chars().any(|c| c < ' ') {
+            return None;
+        }
+        self.rest = &self.rest[end + 1..];
+        unescape_json(raw)
+    }
+
+    /// 数字：-?(0|[1-9][0-9]*)(.[0-9]+)?([eE][+-]?[0-9]+)?
+    fn number(&mut self) -> Option<()> {
+        let bytes = self.rest.as_bytes();
+        let mut at = 0;
+        let digits = |at: &mut usize| {
+            let start = *at;
+            while bytes.get(*at).is_some_and(u8::is_ascii_digit) {
+                *at += 1;
+            }
+            *at > start
+        };
+        if bytes.first() == Some(&b'-') {
+            at += 1;
+        }
+        if bytes.get(at) == Some(&b'0') {
+            at += 1;
+        } else if !digits(&mut at) {
+            return None;
+        }
+        if bytes.get(at) == Some(&b'.') {
+            at += 1;
+            if !digits(&mut at) {
+                return None;
+            }
+        }
+        if matches!(bytes.get(at), Some(b'e' | b'E')) {
+            at += 1;
+            if matches!(bytes.get(at), Some(b'+' | b'-')) {
+                at += 1;
+            }
+            if !digits(&mut at) {
+                return None;
+            }
+        }
+        self.rest = &self.rest[at..];
+        Some(())
+    }
+}
+
+/// 还原 JSON 字符串中的转义（\n、\"、\u00e9 等），有无效的转义时返回 None
+fn unescape_json(text: &str) -> Option<String> {
+    let hex4 = |chars: &mut std::str::Chars| u32::from_str_radix(&chars.take(4).collect::<String>(), 16).ok();
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next()? {
+            '"' => '"',
+            '\\' => '\\',
+            '/' => '/',
+            'b' => '\x08',
+            'f' => '\x0C',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'u' => {
+                // UTF-16 代理对由两个 \u 组成
+                let code = match hex4(&mut chars)? {
+                    high @ 0xD800..0xDC00 => match (chars.next()?, chars.next()?, hex4(&mut chars)?) {
+                        ('\\', 'u', low @ 0xDC00..0xE000) => 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00),
+                        _ => return None,
+                    },
+                    code => code,
+                };
+                char::from_u32(code)?
+            }
+            _ => return None,
+        });
+    }
+    Some(out)
+}
+
+/// 监听 `listen`（只写了端口如 :8080 时监听所有网卡），返回非阻塞的监听端口（以便串口出错时退出）和实际地址
+fn bind(listen: &str) -> Result<(TcpListener, SocketAddr)> {
+    let address = match listen.starts_with(':') {
+        true => format!("0.0.0.0{}", listen),
+        false => listen.to_string(),
+    };
+    let listener = TcpListener::bind(&address).with_context(|| format!("监听 {} 失败", listen))?;
+    listener.set_nonblocking(true).context("设置监听端口失败")?;
+    let local = listener.local_addr().context("读取监听地址失败")?;
+    Ok((listener, local))
+}
+
+/// TCP 服务端：接受客户端连接，每个客户端一个线程把收到的数据写入串口，
+/// 当前线程读取串口并发给所有客户端
+fn tcp_server(
+    port: &mut Box<dyn SerialPort>,
+    listen: &str,
+    multi: bool,
+    idle_timeout: Option<Duration>,
+    wire: Wire,
+    rs485: Option<Rs485>,
+) -> Result<()> {
+    let (listener, local) = bind(listen)?;
+    let stop = AtomicBool::new(false);
+    let hub = Hub {
+        serial: Mutex::new(port.try_clone().context("无法复制串口句柄")?),
+        rs485,
+        clients: Mutex::new(Vec::new()),
+        next_id: AtomicUsize::new(0),
+        stop: &stop,
+    };
+    let mode = if multi { "允许多个客户端" } else { "只允许一个客户端" };
+    let protocol = match wire {
+        Wire::Raw => "TCP ",
+        Wire::Telnet => "RFC 2217 ",
+        Wire::WebSocket(_) => "WebSocket ",
+    };
+    events::status(&format!("在 {} 上等待 {}连接（{}，按 Ctrl+C 退出）...", local, protocol, mode));
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            while !stop.load(Ordering::Relaxed) {
+                let (stream, peer) = match listener.accept() {
+                    Ok(accepted) => accepted,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(POLL);
+                        continue;
+                    }
+                    Err(e) => {
+                        events::status(&format!("接受连接失败: {}", e));
+                        thread::sleep(POLL);
+                        continue;
+                    }
+                };
+                if !multi && !hub.clients().is_empty() {
+                    events::status(&format!("拒绝 {} 的连接：已有客户端连接（可用 --multi 允许多个客户端）", peer));
+                    let _ = stream.shutdown(Shutdown::Both);
+                    continue;
+                }
+                let prepared = stream
+                    .set_nonblocking(false)
+                    .and_then(|()| stream.set_read_timeout(Some(POLL)))
+                    .and_then(|()| stream.set_nodelay(true));
+                match prepared {
+                    Ok(()) => {
+                        let hub = &hub;
+                        scope.spawn(move || hub.serve(stream, peer, idle_timeout, wire));
+                    }
+                    Err(e) => events::status(&format!("客户端 {} 初始化失败: {}", peer, e)),
+                }
+            }
+        });
+        let result = hub.pump_serial(port);
+        stop.store(true, Ordering::Relaxed);
+        result
+    })?;
+    bail!("写入串口失败，桥接结束")
+}
+
+/// TCP 客户端：连接远端并在另一个线程把收到的数据写入串口，当前线程读取串口并发给远端；
+/// `retry` 为 None 时断开后不重连
+fn tcp_client(port: &mut Box<dyn SerialPort>, address: &str, retry: Option<Duration>, rs485: Option<Rs485>) -> Result<()> {
+    let stop = AtomicBool::new(false);
+    let hub = Hub {
+        serial: Mutex::new(port.try_clone().context("无法复制串口句柄")?),
+        rs485,
+        clients: Mutex::new(Vec::new()),
+        next_id: AtomicUsize::new(0),
+        stop: &stop,
+    };
+    // 不重连时第一次就连不上直接报错
+    let mut first = match retry {
+        None => Some(connect(address)?),
+        Some(_) => None,
+    };
+
+    let result = thread::scope(|scope| {
+        scope.spawn(|| {
+            while !stop.load(Ordering::Relaxed) {
+                let connected = match first.take() {
+                    Some(connected) => Ok(connected),
+                    None => {
+                        events::status(&format!("连接 {}...", address));
+                        connect(address)
+                    }
+                };
+                match connected.and_then(|(stream, peer)| hub.join(&stream, peer, Wire::Raw).map(|id| (id, stream, peer))) {
+                    Ok((id, stream, peer)) => {
+                        events::status(&format!("已连接 {}，开始转发（按 Ctrl+C 退出）", peer));
+                        hub.pump_client(id, stream, peer, None, Wire::Raw);
+                    }
+                    Err(e) => events::status(&format!("{:#}", e)),
+                }
+                let Some(interval) = retry else {
+                    stop.store(true, Ordering::Relaxed);
+                    break;
+                };
+                if !stop.load(Ordering::Relaxed) {
+                    events::status(&format!("{:?} 后重连...", interval));
+                    let deadline = Instant::now() + interval;
+                    while !stop.load(Ordering::Relaxed) && Instant::now() < deadline {
+                        thread::sleep(POLL.min(deadline.saturating_duration_since(Instant::now())));
+                    }
+                }
+            }
+        });
+        let result = hub.pump_serial(port);
+        stop.store(true, Ordering::Relaxed);
+        result
+    });
+    match (result, retry) {
+        (Err(e), _) => Err(e),
+        (Ok(()), None) => Ok(()),
+        (Ok(()), Some(_)) => bail!("写入串口失败，桥接结束"),
+    }
+}
+
+/// 依次尝试解析出的各个地址，连上第一个即返回
+fn connect(address: &str) -> Result<(TcpStream, SocketAddr)> {
+    let addrs = address.to_socket_addrs().with_context(|| format!("无法解析地址 {}", address))?;
+    let mut last = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(POLL)).context("设置网络连接超时失败")?;
+                stream.set_nodelay(true).context("设置网络连接失败")?;
+                return Ok((stream, addr));
+            }
+            Err(e) => last = Some(e),
+        }
+    }
+    match last {
+        Some(e) => Err(e).with_context(|| format!("连接 {} 失败", address)),
+        None => bail!("地址 {} 没有解析出任何 IP", address),
+    }
+}
+
+/// UDP 输出：每块数据或每帧发一个数据报，发送失败（如网络暂时不通）只提示，不停止
+fn udp(port: &mut Box<dyn SerialPort>, address: &str, broadcast: bool, ttl: u32, bind: Option<&str>, frame: Option<&FrameSpec>) -> Result<()> {
+    let target = address
+        .to_socket_addrs()
+        .with_context(|| format!("无法解析地址 {}", address))?
+        .next()
+        .with_context(|| format!("地址 {} 没有解析出任何 IP", address))?;
+    let local = match (bind, target) {
+        (Some(bind), _) => bind,
+        (None, SocketAddr::V4(_)) => "0.0.0.0:0",
+        (None, SocketAddr::V6(_)) => "[::]:0",
+    };
+    let socket = UdpSocket::bind(local).with_context(|| format!("绑定本地地址 {} 失败", local))?;
+    if broadcast {
+        socket.set_broadcast(true).context("设置广播失败")?;
+    }
+    if target.ip().is_multicast() && target.is_ipv4() {
+        socket.set_multicast_ttl_v4(ttl).context("设置组播 TTL 失败")?;
+    }
+
+    let kind = match (target.ip().is_multicast(), broadcast) {
+        (true, _) => "组播",
+        (false, true) => "广播",
+        (false, false) => "",
+    };
+    let unit = if frame.is_some() { "每帧" } else { "每块数据" };
+    events::status(&format!("把串口数据以 UDP {}发往 {}，{}一个数据报（按 Ctrl+C 退出）...", kind, target, unit));
+
+    // 同样的错误只提示一次，恢复后再出错时重新提示
+    let mut last_error: Option<String> = None;
+    pump_frames(port, frame, &AtomicBool::new(false), |datagram| {
+        match socket.send_to(datagram, target) {
+            Ok(_) => last_error = None,
+            Err(e) => {
+                let text = match e.kind() {
+                    io::ErrorKind::PermissionDenied if !broadcast => format!("{}（发往广播地址需要 --broadcast）", e),
+                    _ => e.to_string(),
+                };
+                if last_error.as_ref() != Some(&text) {
+                    events::status(&format!("发往 {} 失败: {}", target, text));
+                    last_error = Some(text);
+                }
+            }
+        }
+    })
+}
+
+/// MQTT 桥接当前的连接，串口线程通过它发布
+struct Publisher {
+    stream: TcpStream,
+    last_sent: Instant,
+    next_id: u16,
+}
+
+impl Publisher {
+    /// 写入失败时关闭连接，由读取线程发现断开后重连
+    fn send(&mut self, packet: &[u8]) -> bool {
+        match self.stream.write_all(packet) {
+            Ok(()) => {
+                self.last_sent = Instant::now();
+                true
+            }
+            Err(_) => {
+                let _ = self.stream.shutdown(Shutdown::Both);
+                false
+            }
+        }
+    }
+}
+
+/// MQTT 桥接：另一个线程负责连接、心跳和把命令主题的消息写入串口，当前线程读取串口并发布
+fn mqtt_bridge(port: &mut Box<dyn SerialPort>, opts: &MqttArgs, rs485: Option<Rs485>, frame: Option<&FrameSpec>) -> Result<()> {
+    // MQTT 3.1.1 不能不收自己发布的消息，会把串口数据写回串口
+    if let Some(command_topic) = opts.command_topic.as_deref().filter(|filter| mqtt::topic_matches(filter, &opts.topic)) {
+        bail!("命令主题 {} 包含了发布主题 {}，串口数据会被写回串口，请换用不重叠的主题", command_topic, opts.topic);
+    }
+    let client_id = opts.client_id.clone().unwrap_or_else(|| format!("serial-tool-{}", std::process::id()));
+    let connect = mqtt::Connect {
+        client_id: &client_id,
+        username: opts.username.as_deref().or(opts.broker.username.as_deref()),
+        password: opts.password.as_deref().or(opts.broker.password.as_deref()),
+        keep_alive: opts.keep_alive,
+    };
+    let retry = (!opts.no_reconnect).then_some(opts.retry_interval);
+    let mut serial = port.try_clone().context("无法复制串口句柄")?;
+    let stop = AtomicBool::new(false);
+    let publisher: Mutex<Option<Publisher>> = Mutex::new(None);
+    // 使桥接结束的错误（服务器拒绝连接、写入串口失败等）
+    let failure: Mutex<Option<anyhow::Error>> = Mutex::new(None);
+
+    let result = thread::scope(|scope| {
+        scope.spawn(|| {
+            while !stop.load(Ordering::Relaxed) {
+                events::status(&format!("连接 MQTT 服务器 {}...", opts.broker.address));
+                let session = connect_broker(&opts.broker.address, &connect, opts).and_then(|(stream, peer, decoder, early)| {
+                    let writer = stream.try_clone().map_err(|e| Refused::Retry(anyhow::Error::from(e).context("无法复制网络连接句柄")))?;
+                    Ok((stream, peer, decoder, early, writer))
+                });
+                match session {
+                    Ok((mut stream, peer, decoder, early, writer)) => {
+                        *lock(&publisher) = Some(Publisher { stream: writer, last_sent: Instant::now(), next_id: 1 });
+                        let subscribed = match &opts.command_topic {
+                            Some(topic) => format!("，订阅 {}", topic),
+                            None => String::new(),
+                        };
+                        events::status(&format!("已连接 {}，串口数据发布到 {}{}（按 Ctrl+C 退出）", peer, opts.topic, subscribed));
+                        let write = |data: &[u8]| write_port(&mut serial, rs485, data);
+                        let reason = mqtt_session(&mut stream, decoder, early, &publisher, opts.keep_alive, &stop, write);
+                        if let Some(mut publisher) = lock(&publisher).take() {
+                            publisher.send(&mqtt::disconnect());
+                        }
+                        let _ = stream.shutdown(Shutdown::Both);
+                        match reason {
+                            Ok(reason) => events::status(&format!("与 {} 的连接断开（{}）", peer, reason)),
+                            Err(e) => {
+                                *lock(&failure) = Some(e);
+                                stop.store(true, Ordering::Relaxed);
+                                break;
+                            }
+                        }
+                    }
+                    Err(Refused::Fatal(e)) => {
+                        *lock(&failure) = Some(e);
+                        stop.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                    Err(Refused::Retry(e)) if retry.is_none() => {
+                        *lock(&failure) = Some(e);
+                        stop.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                    Err(Refused::Retry(e)) => events::status(&format!("{:#}", e)),
+                }
+                let Some(interval) = retry else {
+                    stop.store(true, Ordering::Relaxed);
+                    break;
+                };
+                if !stop.load(Ordering::Relaxed) {
+                    events::status(&format!("{:?} 后重连...", interval));
+                    let deadline = Instant::now() + interval;
+                    while !stop.load(Ordering::Relaxed) && Instant::now() < deadline {
+                        thread::sleep(POLL.min(deadline.saturating_duration_since(Instant::now())));
+                    }
+                }
+            }
+        });
+        let qos = opts.qos;
+        let result = pump_frames(port, frame, &stop, |payload| {
+            let mut guard = lock(&publisher);
+            let Some(link) = guard.as_mut() else {
+                return;
+            };
+            // 报文标识符 1 留给订阅
+            let id = (qos == 1).then(|| {
+                link.next_id = link.next_id.checked_add(1).unwrap_or(2);
+                link.next_id
+            });
+            if !link.send(&mqtt::publish(&opts.topic, payload, id, opts.retain)) {
+                *guard = None;
+            }
+        });
+        stop.store(true, Ordering::Relaxed);
+        result
+    });
+    let failure = lock(&failure).take();
+    match failure {
+        Some(e) => Err(e),
+        None => result,
+    }
+}
+
+/// 连接服务器并完成 CONNECT（和订阅）握手
+fn connect_broker(
+    address: &str,
+    options: &mqtt::Connect,
+    opts: &MqttArgs,
+) -> std::result::Result<(TcpStream, SocketAddr, mqtt::Decoder, Vec<Packet>), Refused> {
+    let (mut stream, peer) = connect(address).map_err(Refused::Retry)?;
+    let mut decoder = mqtt::Decoder::new();
+    let subscribe = opts.command_topic.as_deref().map(|topic| (topic, opts.qos));
+    let early = mqtt::handshake(&mut stream, &mut decoder, options, subscribe)?;
+    Ok((stream, peer, decoder, early))
+}
+
+/// 在一个连接上收取命令主题的消息交给 `write` 写入串口、按时发送心跳，返回断开的原因；写入串口失败时返回错误
+fn mqtt_session(
+    stream: &mut TcpStream,
+    mut decoder: mqtt::Decoder,
+    mut packets: Vec<Packet>,
+    publisher: &Mutex<Option<Publisher>>,
+    keep_alive: Duration,
+    stop: &AtomicBool,
+    mut write: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<String> {
+    let mut last_received = Instant::now();
+    let mut buffer = [0u8; 4096];
+    loop {
+        for packet in packets.drain(..) {
+            if let Packet::Publish { payload, id, .. } = packet {
+                if let Some(id) = id {
+                    if let Some(link) = lock(publisher).as_mut() {
+                        link.send(&mqtt::puback(id));
+                    }
+                }
+                write(&payload)?;
+            }
+        }
+        if stop.load(Ordering::Relaxed) {
+            return Ok("串口已关闭".to_string());
+        }
+        if !keep_alive.is_zero() {
+            // 服务器在 1.5 倍心跳间隔内没有任何报文时认为连接已断
+            if last_received.elapsed() > keep_alive * 3 / 2 {
+                return Ok("服务器没有响应心跳".to_string());
+            }
+            let mut guard = lock(publisher);
+            if let Some(link) = guard.as_mut() {
+                if link.last_sent.elapsed() >= keep_alive / 2 && !link.send(&mqtt::pingreq()) {
+                    *guard = None;
+                }
+            }
+        }
+        if lock(publisher).is_none() {
+            return Ok("发送失败".to_string());
+        }
+        match stream.read(&mut buffer) {
+            Ok(0) => return Ok("服务器关闭了连接".to_string()),
+            Ok(n) => {
+                last_received = Instant::now();
+                match decoder.decode(&buffer[..n]) {
+                    Ok(decoded) => packets = decoded,
+                    Err(e) => return Ok(format!("{:#}", e)),
+                }
+            }
+            Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+            Err(e) => return Ok(e.to_string()),
+        }
+    }
+}
+
+/// gRPC 服务的方法
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Rpc {
+    SendBytes,
+    StreamReceived,
+    SetControlLines,
+}
+
+/// 一个进行中的调用
+struct Call {
+    rpc: Rpc,
+    messages: grpc::MessageDecoder,
+    /// SendBytes 累计写入的字节数
+    total: u64,
+}
+
+/// StreamReceived 的一个订阅
+struct Subscriber {
+    peer: SocketAddr,
+    queue: SyncSender<Vec<u8>>,
+    /// 正在丢弃数据（只在开始丢弃时提示一次）
+    dropping: bool,
+}
+
+/// gRPC 服务各个连接共享的状态
+struct GrpcHub<'a> {
+    serial: Mutex<Box<dyn SerialPort>>,
+    rs485: Option<Rs485>,
+    subscribers: Mutex<Vec<Subscriber>>,
+    stop: &'a AtomicBool,
+    /// 使桥接结束的错误（写入串口失败）
+    failure: Mutex<Option<anyhow::Error>>,
+}
+
+impl GrpcHub<'_> {
+    /// 把串口收到的一块数据编码为 ReceivedChunk 交给所有订阅，订阅的缓存满时丢弃
+    fn publish(&self, data: &[u8], sequence: u64) {
+        let mut message = Vec::with_capacity(data.len() + 24);
+        grpc::put_bytes(&mut message, 1, data);
+        grpc::put_varint(&mut message, 2, sequence);
+        grpc::put_varint(&mut message, 3, jiff::Timestamp::now().as_microsecond() as u64);
+        lock(&self.subscribers).retain_mut(|subscriber| match subscriber.queue.try_send(message.clone()) {
+            Ok(()) => {
+                subscriber.dropping = false;
+                true
+            }
+            Err(TrySendError::Full(_)) => {
+                if !subscriber.dropping {
+                    events::status(&format!("{} 接收太慢，丢弃部分串口数据", subscriber.peer));
+                    subscriber.dropping = true;
+                }
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+
+    /// 执行调用收到的一条消息，返回应答；出错时返回结束调用的状态码和原因
+    fn execute(&self, call: &mut Call, message: &[u8]) -> std::result::Result<Option<Vec<u8>>, (u32, anyhow::Error)> {
+        let fields = grpc::fields(message).map_err(|e| (grpc::INVALID_ARGUMENT, e))?;
+        match call.rpc {
+            // 请求只用于开始订阅
+            Rpc::StreamReceived => Ok(None),
+            Rpc::SendBytes => {
+                // 同一字段出现多次时以最后一次为准
+                let data = fields
+                    .iter()
+                    .rev()
+                    .find_map(|(field, value)| match (field, value) {
+                        (1, Value::Bytes(data)) => Some(*data),
+                        _ => None,
+                    })
+                    .unwrap_or_default();
+                if !data.is_empty() {
+                    self.write_serial(data).map_err(|e| (grpc::UNAVAILABLE, e))?;
+                }
+                call.total += data.len() as u64;
+                let mut reply = Vec::new();
+                grpc::put_varint(&mut reply, 1, data.len() as u64);
+                grpc::put_varint(&mut reply, 2, call.total);
+                Ok(Some(reply))
+            }
+            Rpc::SetControlLines => self.set_control_lines(&fields).map(Some).map_err(|e| (grpc::UNAVAILABLE, e)),
+        }
+    }
+
+    /// 写入串口，失败时结束桥接
+    fn write_serial(&self, data: &[u8]) -> Result<()> {
+        let result = write_port(&mut lock(&self.serial), self.rs485, data);
+        if let Err(e) = &result {
+            *lock(&self.failure) = Some(anyhow::anyhow!("{:#}", e));
+            self.stop.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// 按 ControlRequest 设置控制线，返回读取到输入状态线的 ControlReply
+    fn set_control_lines(&self, fields: &[(u32, Value)]) -> Result<Vec<u8>> {
+        let (mut dtr, mut rts, mut break_ms) = (None, None, 0);
+        for (field, value) in fields {
+            match (field, value) {
+                (1, Value::Varint(level)) => dtr = Some(*level != 0),
+                (2, Value::Varint(level)) => rts = Some(*level != 0),
+                (3, Value::Varint(ms)) => break_ms = *ms,
+                _ => {}
+            }
+        }
+        let mut serial = lock(&self.serial);
+        if let Some(level) = dtr {
+            serial.write_data_terminal_ready(level).context("设置 DTR 失败")?;
+        }
+        if let Some(level) = rts {
+            serial.write_request_to_send(level).context("设置 RTS 失败")?;
+        }
+        if break_ms > 0 {
+            serial.set_break().context("发送 break 失败")?;
+            thread::sleep(Duration::from_millis(break_ms));
+            serial.clear_break().context("结束 break 失败")?;
+        }
+        let status = ModemStatus::read(&mut serial);
+        let mut reply = Vec::new();
+        for (field, level) in [(1, status.cts), (2, status.dsr), (3, status.cd), (4, status.ri)] {
+            if let Some(level) = level {
+                grpc::put_varint(&mut reply, field, level as u64);
+            }
+        }
+        Ok(reply)
+    }
+
+    /// 一个连接：读取 HTTP/2 前言后处理调用，直到对方断开或串口关闭
+    fn serve(&self, mut stream: TcpStream, peer: SocketAddr) {
+        let data = match grpc::read_preface(&mut stream) {
+            Ok(data) => data,
+            Err(e) => {
+                events::status(&format!("客户端 {} 握手失败: {:#}", peer, e));
+                let _ = stream.shutdown(Shutdown::Both);
+                return;
+            }
+        };
+        let conn = match stream.try_clone().context("无法复制网络连接句柄").and_then(grpc::Connection::new) {
+            Ok(conn) => conn,
+            Err(e) => {
+                events::status(&format!("客户端 {} 初始化失败: {:#}", peer, e));
+                let _ = stream.shutdown(Shutdown::Both);
+                return;
+            }
+        };
+        events::status(&format!("客户端 {} 已连接", peer));
+        let mut session = GrpcSession {
+            hub: self,
+            conn: &conn,
+            peer,
+            headers: grpc::HeaderDecoder::new(),
+            calls: HashMap::new(),
+            block: None,
+        };
+        // 结束时等待这个连接上所有订阅的发送线程
+        let reason = thread::scope(|scope| {
+            let reason = session.run(&mut stream, data, scope);
+            conn.close();
+            reason
+        });
+        let _ = stream.shutdown(Shutdown::Both);
+        events::status(&format!("客户端 {} 已断开（{}）", peer, reason));
+    }
+}
+
+/// 一个连接上进行中的调用和头部解码状态
+struct GrpcSession<'c, 'h> {
+    hub: &'c GrpcHub<'h>,
+    conn: &'c grpc::Connection,
+    peer: SocketAddr,
+    headers: grpc::HeaderDecoder,
+    calls: HashMap<u32, Call>,
+    /// 还没收完的头部块：流编号、HEADERS 的标志和已收到的内容
+    block: Option<(u32, u8, Vec<u8>)>,
+}
+
+impl<'c> GrpcSession<'c, '_> {
+    /// 处理收到的帧，返回断开的原因；`data` 为读取前言时多读到的数据
+    fn run<'s>(&mut self, stream: &mut TcpStream, mut data: Vec<u8>, scope: &'s thread::Scope<'s, '_>) -> String
+    where
+        'c: 's,
+    {
+        let mut frames = grpc::FrameDecoder::default();
+        let mut buffer = [0u8; 16384];
+        loop {
+            let handled = frames.decode(&data).and_then(|decoded| {
+                for frame in decoded {
+                    if let Some(reason) = self.handle(frame, scope)? {
+                        return Ok(Some(reason));
+                    }
+                }
+                Ok(None)
+            });
+            match handled {
+                Ok(Some(reason)) => return reason,
+                Ok(None) => {}
+                Err(e) => {
+                    self.conn.go_away(grpc::PROTOCOL_ERROR, &format!("{:#}", e));
+                    return format!("{:#}", e);
+                }
+            }
+            if self.hub.stop.load(Ordering::Relaxed) {
+                for &id in self.calls.keys() {
+                    let _ = self.conn.finish(id, grpc::UNAVAILABLE, "串口已关闭");
+                }
+                self.conn.go_away(grpc::NO_ERROR, "");
+                return "串口已关闭".to_string();
+            }
+            if self.conn.is_closed() {
+                return "发送失败".to_string();
+            }
+            data.clear();
+            match stream.read(&mut buffer) {
+                Ok(0) => return "客户端关闭了连接".to_string(),
+                Ok(n) => data.extend_from_slice(&buffer[..n]),
+                Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+                Err(e) => return e.to_string(),
+            }
+        }
+    }
+
+    /// 处理一帧，客户端结束连接时返回原因；返回错误时按协议错误断开连接
+    fn handle<'s>(&mut self, frame: Frame, scope: &'s thread::Scope<'s, '_>) -> Result<Option<String>>
+    where
+        'c: 's,
+    {
+        if self.block.is_some() && frame.kind != grpc::CONTINUATION {
+            bail!("头部块还没有结束就收到了其他帧");
+        }
+        match frame.kind {
+            grpc::SETTINGS if frame.flags & grpc::ACK == 0 => self.conn.apply_settings(&frame.payload)?,
+            grpc::PING if frame.flags & grpc::ACK == 0 => self.conn.send_frame(grpc::PING, grpc::ACK, 0, &frame.payload)?,
+            grpc::WINDOW_UPDATE => self.conn.window_update(frame.stream, &frame.payload)?,
+            grpc::RST_STREAM => {
+                self.calls.remove(&frame.stream);
+                self.conn.reset(frame.stream);
+            }
+            grpc::GOAWAY => return Ok(Some("客户端结束了连接".to_string())),
+            grpc::HEADERS => {
+                let block = frame.content()?.to_vec();
+                match frame.flags & grpc::END_HEADERS {
+                    0 => self.block = Some((frame.stream, frame.flags, block)),
+                    _ => self.start_call(frame.stream, frame.flags, &block, scope)?,
+                }
+            }
+            grpc::CONTINUATION => {
+                let Some((id, flags, mut block)) = self.block.take().filter(|(id, _, _)| *id == frame.stream) else {
+                    bail!("收到了不属于任何头部块的 CONTINUATION");
+                };
+                block.extend_from_slice(&frame.payload);
+                if block.len() > grpc::MAX_HEADER_BLOCK {
+                    bail!("头部块超过 {} 字节", grpc::MAX_HEADER_BLOCK);
+                }
+                match frame.flags & grpc::END_HEADERS {
+                    0 => self.block = Some((id, flags, block)),
+                    _ => self.start_call(id, flags, &block, scope)?,
+                }
+            }
+            grpc::DATA => {
+                // 流量控制按包括填充在内的整个帧计算
+                self.conn.consumed(frame.stream, frame.payload.len())?;
+                self.receive(frame.stream, frame.content()?)?;
+                if frame.flags & grpc::END_STREAM != 0 {
+                    self.half_close(frame.stream)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// 收到完整的头部块：开始一个新的调用，或是客户端在调用结束时发送的头部
+    fn start_call<'s>(&mut self, id: u32, flags: u8, block: &[u8], scope: &'s thread::Scope<'s, '_>) -> Result<()>
+    where
+        'c: 's,
+    {
+        // 不处理的头部块也要解码，以保持动态表同步
+        let headers = self.headers.decode(block)?;
+        if self.calls.contains_key(&id) {
+            if flags & grpc::END_STREAM != 0 {
+                self.half_close(id)?;
+            }
+            return Ok(());
+        }
+        if !self.conn.open(id) {
+            return self.conn.send_frame(grpc::RST_STREAM, 0, id, &grpc::REFUSED_STREAM.to_be_bytes());
+        }
+        let header = |name: &str| headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+        let path = header(":path").unwrap_or_default();
+        let rpc = match path.strip_prefix('/').and_then(|path| path.split_once('/')) {
+            Some((grpc::SERVICE, "SendBytes")) => Rpc::SendBytes,
+            Some((grpc::SERVICE, "StreamReceived")) => Rpc::StreamReceived,
+            Some((grpc::SERVICE, "SetControlLines")) => Rpc::SetControlLines,
+            _ => return self.conn.finish(id, grpc::UNIMPLEMENTED, &format!("没有方法 {}，服务定义见 proto/serial_tool.proto", path)),
+        };
+        if !header("content-type").is_some_and(|kind| kind.starts_with("application/grpc")) {
+            return self.conn.finish(id, grpc::INVALID_ARGUMENT, "content-type 应为 application/grpc");
+        }
+        self.calls.insert(id, Call { rpc, messages: grpc::MessageDecoder::default(), total: 0 });
+        if rpc == Rpc::StreamReceived {
+            let (queue, messages) = mpsc::sync_channel(GRPC_QUEUE);
+            lock(&self.hub.subscribers).push(Subscriber { peer: self.peer, queue, dropping: false });
+            // 立即发送应答头部，客户端可以知道订阅已经开始
+            self.conn.send_headers(id)?;
+            let conn = self.conn;
+            scope.spawn(move || grpc_subscriber(conn, id, messages));
+        }
+        if flags & grpc::END_STREAM != 0 {
+            self.half_close(id)?;
+        }
+        Ok(())
+    }
+
+    /// 处理一个调用收到的 DATA：逐条执行解出的消息并应答
+    fn receive(&mut self, id: u32, data: &[u8]) -> Result<()> {
+        let Some(call) = self.calls.get_mut(&id) else {
+            return Ok(());
+        };
+        let result = call.messages.decode(data).map_err(|e| (grpc::INVALID_ARGUMENT, e)).and_then(|messages| {
+            let mut replies = Vec::new();
+            for message in messages {
+                replies.extend(self.hub.execute(call, &message)?);
+            }
+            Ok(replies)
+        });
+        match result {
+            Ok(replies) => {
+                for reply in replies {
+                    self.conn.send_message(id, &reply)?;
+                }
+                Ok(())
+            }
+            Err((status, e)) => {
+                self.calls.remove(&id);
+                self.conn.finish(id, status, &format!("{:#}", e))
+            }
+        }
+    }
+
+    /// 客户端结束发送：StreamReceived 继续推送，其他调用正常结束
+    fn half_close(&mut self, id: u32) -> Result<()> {
+        match self.calls.get(&id).map(|call| call.rpc) {
+            Some(Rpc::StreamReceived) | None => Ok(()),
+            Some(_) => {
+                self.calls.remove(&id);
+                self.conn.finish(id, grpc::OK, "")
+            }
+        }
+    }
+}
+
+/// 把订阅到的串口数据发给一个 StreamReceived 调用，直到调用被取消、连接断开或串口关闭
+fn grpc_subscriber(conn: &grpc::Connection, id: u32, messages: Receiver<Vec<u8>>) {
+    while conn.wait_writable(id) {
+        match messages.recv_timeout(POLL) {
+            Ok(message) => {
+                if conn.send_message(id, &message).is_err() {
+                    return;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                let _ = conn.finish(id, grpc::UNAVAILABLE, "串口已关闭");
+                return;
+            }
+        }
+    }
+}
+
+/// gRPC 服务端：每个连接一个线程处理调用，每个 StreamReceived 订阅一个线程发送，
+/// 当前线程读取串口并交给所有订阅
+fn grpc_server(port: &mut Box<dyn SerialPort>, listen: &str, rs485: Option<Rs485>, frame: Option<&FrameSpec>) -> Result<()> {
+    let (listener, local) = bind(listen)?;
+    let stop = AtomicBool::new(false);
+    let hub = GrpcHub {
+        serial: Mutex::new(port.try_clone().context("无法复制串口句柄")?),
+        rs485,
+        subscribers: Mutex::new(Vec::new()),
+        stop: &stop,
+        failure: Mutex::new(None),
+    };
+    events::status(&format!("在 {} 上等待 gRPC 连接（服务 {}，按 Ctrl+C 退出）...", local, grpc::SERVICE));
+
+    let result = thread::scope(|scope| {
+        scope.spawn(|| {
+            while !stop.load(Ordering::Relaxed) {
+                let (stream, peer) = match listener.accept() {
+                    Ok(accepted) => accepted,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(POLL);
+                        continue;
+                    }
+                    Err(e) => {
+                        events::status(&format!("接受连接失败: {}", e));
+                        thread::sleep(POLL);
+                        continue;
+                    }
+                };
+                let prepared = stream
+                    .set_nonblocking(false)
+                    .and_then(|()| stream.set_read_timeout(Some(POLL)))
+                    .and_then(|()| stream.set_write_timeout(Some(WRITE_TIMEOUT)))
+                    .and_then(|()| stream.set_nodelay(true));
+                match prepared {
+                    Ok(()) => {
+                        let hub = &hub;
+                        scope.spawn(move || hub.serve(stream, peer));
+                    }
+                    Err(e) => events::status(&format!("客户端 {} 初始化失败: {}", peer, e)),
+                }
+            }
+        });
+        let mut sequence = 0;
+        let result = pump_frames(port, frame, &stop, |data| {
+            sequence += 1;
+            hub.publish(data, sequence);
+        });
+        stop.store(true, Ordering::Relaxed);
+        // 让订阅的发送线程以 UNAVAILABLE 结束调用
+        lock(&hub.subscribers).clear();
+        result
+    });
+    let failure = lock(&hub.failure).take();
+    match failure {
+        Some(e) => Err(e),
+        None => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_messages() {
+        assert_eq!(parse_message(r#"{"text":"AT\r\n"}"#).unwrap(), b"AT\r\n");
+        assert_eq!(parse_message(r#" { "hex" : "01 03 0a" } "#).unwrap(), [0x01, 0x03, 0x0A]);
+        assert_eq!(parse_message(r#"{"text":"é😀\"\\\/"}"#).unwrap(), "é😀\"\\/".as_bytes());
+        // 其他键的值可以是任意 JSON，其中嵌套的 "text" 不算
+        let message = r#"{"id":-1.5e3,"meta":{"text":"x","list":[1,true,null,"hex",{}]},"text":"ok"}"#;
+        assert_eq!(parse_message(message).unwrap(), b"ok");
+
+        for message in [
+            r#"{"meta":{"text":"x"}}"#,
+            r#"{"text":"a","hex":"01"}"#,
+            r#"{"text":1}"#,
+            r#"{"hex":"0"}"#,
+            r#"{"text":"a"} x"#,
+            r#"{"text":"a",}"#,
+            r#"{"text":"\x"}"#,
+            "{\"text\":\"a\nb\"}",
+            r#"{"text":"a""#,
+            r#"{"n":01,"text":"a"}"#,
+            r#"["text","a"]"#,
+            r#"text:a"#,
+        ] {
+            assert!(parse_message(message).is_err(), "{} 应解析失败", message);
+        }
+        // 嵌套过深时报错而不是栈溢出
+        let deep = format!(r#"{{"text":"a","x":{}{}}}"#, "[".repeat(10000), "]".repeat(10000));
+        assert!(parse_message(&deep).is_err());
+    }
+}
@@ -0,0 +1,446 @@
+//! 会话抓包：--record 把串口收发的每一段数据连同方向和微秒时间戳写入紧凑的二进制文件，
+//! 离线的 capture 子命令查看概要或导出为文本（--output jsonl 时导出为 JSON Lines）
+//!
+//! 文件格式：头部为魔数 "SERCAP"、版本号（1 字节）和开始时间（Unix 时间，微秒，i64 小端）；
+//! 之后每条记录为 类型（1 字节）+ 距上一条记录的微秒数（varint）+ 数据长度（varint）+ 数据。
+//! 读取时跳过不认识的记录类型。
+
+use anyhow::{bail, Context, Result};
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::{events, format_hex, lock};
+
+const MAGIC: &[u8; 6] = b"SERCAP";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 1 + 8;
+
+/// 收到的数据
+const KIND_RX: u8 = 1;
+/// 发出的数据
+const KIND_TX: u8 = 2;
+/// 打开端口：波特率（u32 小端）+ 端口名
+const KIND_OPEN: u8 = 3;
+/// 修改波特率：新的波特率（u32 小端）
+const KIND_BAUD: u8 = 4;
+
+/// capture 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct CaptureArgs {
+    #[command(subcommand)]
+    pub command: CaptureCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum CaptureCommand {
+    /// 导出为文本，每条记录一行：时间、相对开始的秒数、方向、长度、十六进制和文本
+    Export {
+        /// --record 写入的抓包文件
+        file: PathBuf,
+
+        /// 写入文件而不是标准输出
+        #[arg(long, short = 'o', value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// 显示开始时间、时长、打开过的端口和收发的次数与字节数
+    Info {
+        /// --record 写入的抓包文件
+        file: PathBuf,
+    },
+}
+
+pub fn run_capture(opts: &CaptureArgs) -> Result<()> {
+    match &opts.command {
+        CaptureCommand::Export { file, output } => export(file, output.as_deref()),
+        CaptureCommand::Info { file } => info(file),
+    }
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// 抓包文件的写入端，同一会话中（包括重连和 try_clone 出的端口）共用一个
+pub struct Recorder {
+    sink: Mutex<Sink>,
+}
+
+struct Sink {
+    path: PathBuf,
+    /// 写入失败后为 None，不再记录
+    file: Option<File>,
+    start: Instant,
+    /// 上一条记录相对开始的微秒数
+    last: u64,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Arc<Recorder>> {
+        let start = Instant::now();
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        header.extend_from_slice(&jiff::Timestamp::now().as_microsecond().to_le_bytes());
+        let mut file = File::create(path).with_context(|| format!("无法创建抓包文件 {}", path.display()))?;
+        file.write_all(&header).with_context(|| format!("写入抓包文件 {} 失败", path.display()))?;
+        Ok(Arc::new(Recorder { sink: Mutex::new(Sink { path: path.to_path_buf(), file: Some(file), start, last: 0 }) }))
+    }
+
+    /// 每条记录直接写入文件，进程被 Ctrl+C 结束时不会丢失缓冲中的数据
+    fn record(&self, kind: u8, data: &[u8]) {
+        let mut sink = lock(&self.sink);
+        if sink.file.is_none() {
+            return;
+        }
+        let offset = sink.start.elapsed().as_micros() as u64;
+        let mut record = Vec::with_capacity(data.len() + 12);
+        record.push(kind);
+        put_varint(&mut record, offset.saturating_sub(sink.last));
+        put_varint(&mut record, data.len() as u64);
+        record.extend_from_slice(data);
+        sink.last = offset;
+        if let Err(e) = sink.file.as_mut().unwrap().write_all(&record) {
+            events::status(&format!("[写入抓包文件 {} 失败，停止记录: {}]", sink.path.display(), e));
+            sink.file = None;
+        }
+    }
+}
+
+/// 开启了 --record 时把端口包装为记录收发数据的端口，并写入一条打开端口的记录
+pub fn record(port: Box<dyn SerialPort>, recorder: Option<&Arc<Recorder>>, name: &str, baud: u32) -> Box<dyn SerialPort> {
+    let Some(recorder) = recorder else {
+        return port;
+    };
+    let mut data = baud.to_le_bytes().to_vec();
+    data.extend_from_slice(name.as_bytes());
+    recorder.record(KIND_OPEN, &data);
+    Box::new(RecordingPort { inner: port, recorder: recorder.clone() })
+}
+
+/// 记录经过的每一次读写的端口包装
+struct RecordingPort {
+    inner: Box<dyn SerialPort>,
+    recorder: Arc<Recorder>,
+}
+
+impl Read for RecordingPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.recorder.record(KIND_RX, &buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl Write for RecordingPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if n > 0 {
+            self.recorder.record(KIND_TX, &buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl SerialPort for RecordingPort {
+    fn name(&self) -> Option<String> {
+        self.inner.name()
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        self.inner.baud_rate()
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        self.inner.data_bits()
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        self.inner.flow_control()
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        self.inner.parity()
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        self.inner.stop_bits()
+    }
+
+    fn timeout(&self) -> Duration {
+        self.inner.timeout()
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.inner.set_baud_rate(baud_rate)?;
+        self.recorder.record(KIND_BAUD, &baud_rate.to_le_bytes());
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+        self.inner.set_data_bits(data_bits)
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+        self.inner.set_flow_control(flow_control)
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+        self.inner.set_parity(parity)
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+        self.inner.set_stop_bits(stop_bits)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.inner.set_timeout(timeout)
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> serialport::Result<()> {
+        self.inner.write_request_to_send(level)
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> serialport::Result<()> {
+        self.inner.write_data_terminal_ready(level)
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        self.inner.read_clear_to_send()
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        self.inner.read_data_set_ready()
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        self.inner.read_ring_indicator()
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        self.inner.read_carrier_detect()
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        self.inner.bytes_to_read()
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        self.inner.bytes_to_write()
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        self.inner.clear(buffer_to_clear)
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(Box::new(RecordingPort { inner: self.inner.try_clone()?, recorder: self.recorder.clone() }))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        self.inner.set_break()
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        self.inner.clear_break()
+    }
+}
+
+/// 读出的一条记录
+struct Record<'a> {
+    kind: u8,
+    /// 相对开始的微秒数
+    offset: u64,
+    data: &'a [u8],
+}
+
+/// 读取整个抓包文件，返回开始时间（Unix 微秒）和所有记录；末尾不完整的记录（写入时进程被结束）只给出警告
+fn parse<'a>(content: &'a [u8], path: &Path) -> Result<(i64, Vec<Record<'a>>)> {
+    if content.len() < HEADER_LEN || &content[..MAGIC.len()] != MAGIC {
+        bail!("{} 不是 serial-tool 抓包文件", path.display());
+    }
+    if content[MAGIC.len()] != VERSION {
+        bail!("{} 的格式版本 {} 不受支持（此版本只支持 {}）", path.display(), content[MAGIC.len()], VERSION);
+    }
+    let start = i64::from_le_bytes(content[MAGIC.len() + 1..HEADER_LEN].try_into().unwrap());
+
+    let mut records = Vec::new();
+    let mut rest = &content[HEADER_LEN..];
+    let mut offset = 0u64;
+    while !rest.is_empty() {
+        let parsed = (|| {
+            let (&kind, after) = rest.split_first()?;
+            let (delta, after) = take_varint(after)?;
+            let (len, after) = take_varint(after)?;
+            let len = usize::try_from(len).ok().filter(|&len| len <= after.len())?;
+            Some((kind, delta, &after[..len], &after[len..]))
+        })();
+        let Some((kind, delta, data, after)) = parsed else {
+            events::status(&format!("警告：{} 末尾有 {} 字节不完整的记录，已忽略", path.display(), rest.len()));
+            break;
+        };
+        offset = offset.saturating_add(delta);
+        records.push(Record { kind, offset, data });
+        rest = after;
+    }
+    Ok((start, records))
+}
+
+fn take_varint(data: &[u8]) -> Option<(u64, &[u8])> {
+    let mut value = 0u64;
+    for (i, &byte) in data.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7F) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, &data[i + 1..]));
+        }
+    }
+    None
+}
+
+/// 打开端口记录中的波特率和端口名
+fn open_info(data: &[u8]) -> Option<(u32, String)> {
+    let (baud, name) = data.split_first_chunk::<4>()?;
+    Some((u32::from_le_bytes(*baud), String::from_utf8_lossy(name).into_owned()))
+}
+
+fn baud_info(data: &[u8]) -> Option<u32> {
+    data.try_into().ok().map(u32::from_le_bytes)
+}
+
+/// Unix 微秒时间按本地时区格式化
+fn format_time(us: i64, format: &str) -> String {
+    match jiff::Timestamp::from_microsecond(us) {
+        Ok(ts) => ts.to_zoned(jiff::tz::TimeZone::system()).strftime(format).to_string(),
+        Err(_) => format!("@{}us", us),
+    }
+}
+
+fn export(path: &Path, output: Option<&Path>) -> Result<()> {
+    let content = fs::read(path).with_context(|| format!("读取抓包文件 {} 失败", path.display()))?;
+    let (start, records) = parse(&content, path)?;
+
+    let mut out: Box<dyn Write> = match output {
+        Some(output) => Box::new(BufWriter::new(File::create(output).with_context(|| format!("无法创建文件 {}", output.display()))?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    let jsonl = events::jsonl();
+    if !jsonl {
+        writeln!(out, "# {}：开始于 {}", path.display(), format_time(start, "%Y-%m-%d %H:%M:%S%.6f %:z"))?;
+    }
+    for record in &records {
+        let time = start.saturating_add(record.offset as i64);
+        let line = match jsonl {
+            true => {
+                let body = match record.kind {
+                    KIND_RX | KIND_TX => format!(
+                        "\"event\":\"data\",\"dir\":\"{}\",\"len\":{},\"hex\":{},\"text\":{}",
+                        if record.kind == KIND_RX { "rx" } else { "tx" },
+                        record.data.len(),
+                        events::quote(&format_hex(record.data)),
+                        events::quote(&String::from_utf8_lossy(record.data))
+                    ),
+                    KIND_OPEN => match open_info(record.data) {
+                        Some((baud, name)) => format!("\"event\":\"open\",\"port\":{},\"baud\":{}", events::quote(&name), baud),
+                        None => continue,
+                    },
+                    KIND_BAUD => match baud_info(record.data) {
+                        Some(baud) => format!("\"event\":\"baud\",\"baud\":{}", baud),
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+                format!(
+                    "{{\"ts\":\"{}\",\"offset_us\":{},{}}}",
+                    format_time(time, "%Y-%m-%dT%H:%M:%S%.6f%:z"),
+                    record.offset,
+                    body
+                )
+            }
+            false => {
+                let body = match record.kind {
+                    KIND_RX | KIND_TX => format!(
+                        "{}  {:>5}  {}  {}",
+                        if record.kind == KIND_RX { "RX  " } else { "TX  " },
+                        record.data.len(),
+                        format_hex(record.data),
+                        events::quote(&String::from_utf8_lossy(record.data))
+                    ),
+                    KIND_OPEN => match open_info(record.data) {
+                        Some((baud, name)) => format!("OPEN  {} {}", name, baud),
+                        None => continue,
+                    },
+                    KIND_BAUD => match baud_info(record.data) {
+                        Some(baud) => format!("BAUD  {}", baud),
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+                format!(
+                    "{}  +{}.{:06}  {}",
+                    format_time(time, "%Y-%m-%d %H:%M:%S%.6f"),
+                    record.offset / 1_000_000,
+                    record.offset % 1_000_000,
+                    body
+                )
+            }
+        };
+        writeln!(out, "{}", line).context("写入导出内容失败")?;
+    }
+    out.flush().context("写入导出内容失败")?;
+
+    if let Some(output) = output {
+        events::status(&format!("已导出 {} 条记录到 {}", records.len(), output.display()));
+    }
+    Ok(())
+}
+
+fn info(path: &Path) -> Result<()> {
+    let content = fs::read(path).with_context(|| format!("读取抓包文件 {} 失败", path.display()))?;
+    let (start, records) = parse(&content, path)?;
+
+    let (mut rx, mut tx) = ((0u64, 0u64), (0u64, 0u64));
+    let mut ports: Vec<(String, u32, u64)> = Vec::new();
+    for record in &records {
+        match record.kind {
+            KIND_RX => rx = (rx.0 + 1, rx.1 + record.data.len() as u64),
+            KIND_TX => tx = (tx.0 + 1, tx.1 + record.data.len() as u64),
+            KIND_OPEN => {
+                if let Some((baud, name)) = open_info(record.data) {
+                    match ports.iter_mut().find(|(n, b, _)| *n == name && *b == baud) {
+                        Some((_, _, count)) => *count += 1,
+                        None => ports.push((name, baud, 1)),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let duration = records.last().map_or(0, |record| record.offset);
+
+    events::status(&format!(
+        "{}：开始于 {}，时长 {}.{:06} 秒，{} 条记录",
+        path.display(),
+        format_time(start, "%Y-%m-%d %H:%M:%S%.6f %:z"),
+        duration / 1_000_000,
+        duration % 1_000_000,
+        records.len()
+    ));
+    for (name, baud, count) in &ports {
+        events::status(&format!("  端口：{} {}（打开 {} 次）", name, baud, count));
+    }
+    events::status(&format!("  发送：{} 次，{} 字节", tx.0, tx.1));
+    events::status(&format!("  接收：{} 次，{} 字节", rx.0, rx.1));
+    Ok(())
+}
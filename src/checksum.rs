@@ -0,0 +1,394 @@
+//! 校验和算法：发送时自动计算并插入校验和，监听时校验收到的帧，以及离线计算校验和的 crc 子命令
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::events;
+use crate::gzip::crc32;
+use crate::{format_hex, parse_escapes, parse_hex};
+
+/// crc 子命令参数
+#[derive(clap::Args, Debug)]
+#[command(group(clap::ArgGroup::new("input").required(true).args(["data", "file"])))]
+pub struct CrcArgs {
+    /// 要计算的数据（十六进制，如 "01 03 00 00 00 01"）
+    pub data: Option<String>,
+
+    /// 把数据按文本解析（支持 \r \n \xNN 等转义）
+    #[arg(long, requires = "data")]
+    pub text: bool,
+
+    /// 计算文件内容的校验和
+    #[arg(long, value_name = "PATH")]
+    pub file: Option<PathBuf>,
+}
+
+/// 校验和算法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// CRC-8（多项式 0x07，初值 0）
+    Crc8,
+    /// CRC-16/MODBUS（低字节在前）
+    Crc16Modbus,
+    /// CRC-16/CCITT-FALSE（高字节在前）
+    Crc16Ccitt,
+    /// CRC-16/X-25（HDLC/PPP 的 FCS，低字节在前）
+    Crc16X25,
+    /// CRC-16/XMODEM（高字节在前）
+    Crc16Xmodem,
+    /// CRC-32（IEEE 802.3，低字节在前）
+    Crc32,
+    /// 纵向冗余校验（字节和的补码，Modbus ASCII 使用）
+    Lrc,
+    /// 各字节异或
+    Xor,
+    /// 各字节之和的低 8 位
+    Sum8,
+    /// 各字节之和的低 16 位（高字节在前）
+    Sum16,
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 10] = [
+        Algorithm::Crc8,
+        Algorithm::Crc16Modbus,
+        Algorithm::Crc16Ccitt,
+        Algorithm::Crc16X25,
+        Algorithm::Crc16Xmodem,
+        Algorithm::Crc32,
+        Algorithm::Lrc,
+        Algorithm::Xor,
+        Algorithm::Sum8,
+        Algorithm::Sum16,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Crc8 => "crc8",
+            Algorithm::Crc16Modbus => "crc16-modbus",
+            Algorithm::Crc16Ccitt => "crc16-ccitt",
+            Algorithm::Crc16X25 => "crc16-x25",
+            Algorithm::Crc16Xmodem => "crc16-xmodem",
+            Algorithm::Crc32 => "crc32",
+            Algorithm::Lrc => "lrc",
+            Algorithm::Xor => "xor",
+            Algorithm::Sum8 => "sum8",
+            Algorithm::Sum16 => "sum16",
+        }
+    }
+
+    /// 校验和的字节数
+    pub fn size(self) -> usize {
+        match self {
+            Algorithm::Crc8 | Algorithm::Lrc | Algorithm::Xor | Algorithm::Sum8 => 1,
+            Algorithm::Crc16Modbus
+            | Algorithm::Crc16Ccitt
+            | Algorithm::Crc16X25
+            | Algorithm::Crc16Xmodem
+            | Algorithm::Sum16 => 2,
+            Algorithm::Crc32 => 4,
+        }
+    }
+
+    /// 是否低字节在前发送
+    fn little_endian(self) -> bool {
+        matches!(self, Algorithm::Crc16Modbus | Algorithm::Crc16X25 | Algorithm::Crc32)
+    }
+
+    /// 计算校验值
+    pub fn value(self, data: &[u8]) -> u32 {
+        match self {
+            Algorithm::Crc8 => crc8(data) as u32,
+            Algorithm::Crc16Modbus => crc16_modbus(data) as u32,
+            Algorithm::Crc16Ccitt => crc16_ccitt(data, 0xFFFF) as u32,
+            Algorithm::Crc16X25 => crc16_x25(data) as u32,
+            Algorithm::Crc16Xmodem => crc16_xmodem(data) as u32,
+            Algorithm::Crc32 => crc32(data),
+            Algorithm::Lrc => sum8(data).wrapping_neg() as u32,
+            Algorithm::Xor => data.iter().fold(0, |acc, b| acc ^ b) as u32,
+            Algorithm::Sum8 => sum8(data) as u32,
+            Algorithm::Sum16 => data.iter().fold(0u16, |acc, &b| acc.wrapping_add(b as u16)) as u32,
+        }
+    }
+
+    /// 计算校验和，按线路上的字节顺序返回
+    pub fn compute(self, data: &[u8]) -> Vec<u8> {
+        let size = self.size();
+        let bytes = self.value(data).to_be_bytes();
+        let mut bytes = bytes[4 - size..].to_vec();
+        if self.little_endian() {
+            bytes.reverse();
+        }
+        bytes
+    }
+}
+
+/// 校验和在帧中的位置
+#[derive(Debug, Clone, Copy)]
+enum Position {
+    /// 数据末尾
+    End,
+    /// 距开头的字节数（负数表示距末尾，如 -1 表示最后一个字节之前）
+    Offset(i64),
+}
+
+/// 校验和设置（--checksum）：校验和覆盖插入位置之前的全部数据
+#[derive(Debug, Clone, Copy)]
+pub struct Checksum {
+    pub algorithm: Algorithm,
+    position: Position,
+}
+
+/// 解析 `算法[:位置]`，如 `crc16-modbus`、`sum8:-1`
+pub fn parse_checksum(s: &str) -> std::result::Result<Checksum, String> {
+    let (name, position) = s.split_once(':').unwrap_or((s, "end"));
+    let algorithm = Algorithm::ALL.into_iter().find(|a| a.name() == name.to_ascii_lowercase()).ok_or_else(|| {
+        let names: Vec<&str> = Algorithm::ALL.iter().map(|a| a.name()).collect();
+        format!("未知的校验算法 '{}'，可用：{}", name, names.join("、"))
+    })?;
+    let position = match position {
+        "end" => Position::End,
+        n => Position::Offset(n.parse().map_err(|_| format!("无效的校验和位置 '{}'，应为 end 或整数偏移", n))?),
+    };
+    Ok(Checksum { algorithm, position })
+}
+
+impl Checksum {
+    /// 把校验和插入指定位置的字节偏移，超出数据范围时返回错误
+    fn insert_at(&self, len: usize) -> Result<usize> {
+        let at = match self.position {
+            Position::End => return Ok(len),
+            Position::Offset(n) if n < 0 => len as i64 + n,
+            Position::Offset(n) => n,
+        };
+        if at < 0 || at > len as i64 {
+            anyhow::bail!("校验和位置 {} 超出数据范围（{} 字节）", at, len);
+        }
+        Ok(at as usize)
+    }
+
+    /// 计算插入位置之前数据的校验和并插入
+    pub fn apply(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let at = self.insert_at(data.len())?;
+        let sum = self.algorithm.compute(&data[..at]);
+        Ok([&data[..at], &sum, &data[at..]].concat())
+    }
+
+    /// 校验收到的一帧（与 `apply` 的位置约定相同），不通过时返回原因
+    pub fn verify(&self, frame: &[u8]) -> std::result::Result<(), String> {
+        let size = self.algorithm.size();
+        let Some(payload_len) = frame.len().checked_sub(size) else {
+            return Err(format!("帧长度不足以包含 {} 校验和", self.algorithm.name()));
+        };
+        let at = self.insert_at(payload_len).map_err(|e| e.to_string())?;
+        let received = &frame[at..at + size];
+        let expected = self.algorithm.compute(&frame[..at]);
+        if received == expected {
+            Ok(())
+        } else {
+            Err(format!("{} 错误：收到 {}，应为 {}", self.algorithm.name(), format_hex(received), format_hex(&expected)))
+        }
+    }
+}
+
+/// 执行 crc 子命令：列出各算法的校验值（不需要打开串口）
+pub fn run_crc(opts: &CrcArgs) -> Result<()> {
+    let data = match (&opts.data, &opts.file) {
+        (Some(text), _) if opts.text => parse_escapes(text)?,
+        (Some(hex), _) => parse_hex(hex)?,
+        (None, Some(path)) => fs::read(path).with_context(|| format!("无法读取文件 {}", path.display()))?,
+        (None, None) => unreachable!("clap 保证提供了数据或文件"),
+    };
+
+    events::status(&format!("数据长度 {} 字节", data.len()));
+    events::status(&format!("{:<14}{:<14}{}", "算法", "校验值", "发送顺序"));
+    for algorithm in Algorithm::ALL {
+        let value = format!("0x{:0width$X}", algorithm.value(&data), width = algorithm.size() * 2);
+        events::status(&format!("{:<16}{:<16}{}", algorithm.name(), value, format_hex(&algorithm.compute(&data))));
+    }
+    Ok(())
+}
+
+fn sum8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |acc, &b| acc.wrapping_add(b))
+}
+
+/// CRC-8/SMBUS
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// CRC-16/MODBUS
+pub fn crc16_modbus(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xA001 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+/// CRC-16/XMODEM（XMODEM/YMODEM 数据块的 CRC）
+pub fn crc16_xmodem(data: &[u8]) -> u16 {
+    crc16_ccitt(data, 0)
+}
+
+/// 多项式 0x1021 的非反射 CRC-16（初值 0xFFFF 为 CCITT-FALSE，初值 0 为 XMODEM）
+fn crc16_ccitt(data: &[u8], init: u16) -> u16 {
+    let mut crc = init;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// CRC-16/X-25（HDLC/PPP 的 FCS-16）
+pub fn crc16_x25(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x8408 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// MD5 摘要（ESP ROM 烧录后与 flash 中的内容比较）
+pub fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+    let constants: Vec<u32> = (1..=64).map(|i| ((i as f64).sin().abs() * 4294967296.0) as u32).collect();
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend(((data.len() as u64) * 8).to_le_bytes());
+
+    let mut state = [0x67452301u32, 0xEFCDAB89, 0x98BADCFE, 0x10325476];
+    for chunk in message.chunks(64) {
+        let words: Vec<u32> = chunk.chunks(4).map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]])).collect();
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(constants[i]).wrapping_add(words[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(SHIFTS[i / 16 * 4 + i % 4]));
+        }
+        for (value, add) in state.iter_mut().zip([a, b, c, d]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+    let mut digest = [0u8; 16];
+    for (out, value) in digest.chunks_mut(4).zip(state) {
+        out.copy_from_slice(&value.to_le_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// CRC 目录（reveng）中各算法对 "123456789" 的校验值
+    #[test]
+    fn check_values() {
+        let expected = [
+            (Algorithm::Crc8, 0xF4),
+            (Algorithm::Crc16Modbus, 0x4B37),
+            (Algorithm::Crc16Ccitt, 0x29B1),
+            (Algorithm::Crc16X25, 0x906E),
+            (Algorithm::Crc16Xmodem, 0x31C3),
+            (Algorithm::Crc32, 0xCBF4_3926),
+            (Algorithm::Lrc, 0x23),
+            (Algorithm::Xor, 0x31),
+            (Algorithm::Sum8, 0xDD),
+            (Algorithm::Sum16, 0x01DD),
+        ];
+        for (algorithm, value) in expected {
+            assert_eq!(algorithm.value(b"123456789"), value, "{}", algorithm.name());
+        }
+    }
+
+    #[test]
+    fn wire_byte_order() {
+        assert_eq!(Algorithm::Crc16Modbus.compute(b"123456789"), [0x37, 0x4B]);
+        assert_eq!(Algorithm::Crc16Ccitt.compute(b"123456789"), [0x29, 0xB1]);
+        assert_eq!(Algorithm::Crc32.compute(b"123456789"), [0x26, 0x39, 0xF4, 0xCB]);
+        assert_eq!(Algorithm::Sum16.compute(b"123456789"), [0x01, 0xDD]);
+        // 读保持寄存器请求：01 03 00 00 00 0A C5 CD
+        assert_eq!(Algorithm::Crc16Modbus.compute(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A]), [0xC5, 0xCD]);
+    }
+
+    #[test]
+    fn apply_and_verify_positions() {
+        let end = parse_checksum("crc16-modbus").unwrap();
+        let frame = end.apply(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A]).unwrap();
+        assert_eq!(frame, [0x01, 0x03, 0x00, 0x00, 0x00, 0x0A, 0xC5, 0xCD]);
+        assert_eq!(end.verify(&frame), Ok(()));
+        assert!(end.verify(&frame[..7]).is_err());
+        assert!(end.verify(&[0x01]).is_err());
+
+        // 插在最后一个字节（如 CR）之前
+        let before_cr = parse_checksum("SUM8:-1").unwrap();
+        let frame = before_cr.apply(&[0x01, 0x02, 0x03, 0x0D]).unwrap();
+        assert_eq!(frame, [0x01, 0x02, 0x03, 0x06, 0x0D]);
+        assert_eq!(before_cr.verify(&frame), Ok(()));
+
+        let offset = parse_checksum("xor:1").unwrap();
+        assert_eq!(offset.apply(&[0xAA, 0xBB]).unwrap(), [0xAA, 0xAA, 0xBB]);
+        assert!(parse_checksum("xor:5").unwrap().apply(&[0xAA]).is_err());
+        assert!(parse_checksum("crc99").is_err());
+        assert!(parse_checksum("crc8:abc").is_err());
+    }
+
+    /// RFC 1321 附录 A.5 的测试向量
+    #[test]
+    fn md5_rfc1321() {
+        let cases: [(&[u8], &str); 7] = [
+            (b"", "d41d8cd98f00b204e9800998ecf8427e"),
+            (b"a", "0cc175b9c0f1b6a831c399e269772661"),
+            (b"abc", "900150983cd24fb0d6963f7d28e17f72"),
+            (b"message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+            (b"abcdefghijklmnopqrstuvwxyz", "c3fcd3d76192e4007dfb496cca67e13b"),
+            (b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789", "d174ab98d277d9f5a5611c2c9f419d9f"),
+            (b"12345678901234567890123456789012345678901234567890123456789012345678901234567890", "57edf4a22be3c955ac49da2e2107b67a"),
+        ];
+        for (data, digest) in cases {
+            let hex: String = md5(data).iter().map(|b| format!("{:02x}", b)).collect();
+            assert_eq!(hex, digest, "{:?}", String::from_utf8_lossy(data));
+        }
+        // 填充恰好放得下、需要多一个分组等边界长度
+        let boundaries = [
+            (55, "04364420e25c512fd958a70738aa8f72"),
+            (56, "668a72d5ba17f08e62dabcafad6db14b"),
+            (63, "7dc2ca208106a2f703567bdff99d8981"),
+            (64, "c1bb4f81d892b2d57947682aeb252456"),
+            (65, "1bc932052302d074bdec39795fe00cf6"),
+        ];
+        for (len, digest) in boundaries {
+            let hex: String = md5(&vec![b'x'; len]).iter().map(|b| format!("{:02x}", b)).collect();
+            assert_eq!(hex, digest, "{} 个 'x'", len);
+        }
+    }
+}
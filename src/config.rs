@@ -0,0 +1,511 @@
+//! 配置文件：TOML 的常用子集（表、字符串、整数、浮点数、布尔值和数组）
+//!
+//! 默认依次查找当前目录下的 `serial-tool.toml` 和用户配置目录下的
+//! `serial-tool/config.toml`，也可用 `--config` 指定。
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 配置值
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Str(_) => "字符串",
+            Value::Int(_) => "整数",
+            Value::Float(_) => "浮点数",
+            Value::Bool(_) => "布尔值",
+            Value::Array(_) => "数组",
+        }
+    }
+}
+
+/// 一个表（`[name]` 及其下的键值）
+#[derive(Debug, Default)]
+pub struct Table {
+    pub name: String,
+    entries: Vec<(String, Value)>,
+}
+
+impl Table {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    /// 按文件中的顺序列出全部键值
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// 读取字符串值；键不存在时返回 `None`，类型不符时报错
+    pub fn str(&self, key: &str) -> Result<Option<&str>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::Str(s)) => Ok(Some(s)),
+            Some(v) => Err(self.type_error(key, "字符串", v)),
+        }
+    }
+
+    /// 读取整数
+    pub fn int(&self, key: &str) -> Result<Option<i64>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::Int(n)) => Ok(Some(*n)),
+            Some(v) => Err(self.type_error(key, "整数", v)),
+        }
+    }
+
+    /// 读取布尔值
+    pub fn bool(&self, key: &str) -> Result<Option<bool>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::Bool(b)) => Ok(Some(*b)),
+            Some(v) => Err(self.type_error(key, "布尔值", v)),
+        }
+    }
+
+    /// 读取数值（整数也可以）
+    pub fn float(&self, key: &str) -> Result<Option<f64>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::Float(n)) => Ok(Some(*n)),
+            Some(Value::Int(n)) => Ok(Some(*n as f64)),
+            Some(v) => Err(self.type_error(key, "数值", v)),
+        }
+    }
+
+    /// 读取字符串数组
+    pub fn str_array(&self, key: &str) -> Result<Option<Vec<&str>>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::Array(items)) => items
+                .iter()
+                .map(|item| match item {
+                    Value::Str(s) => Ok(s.as_str()),
+                    v => Err(self.type_error(key, "字符串数组", v)),
+                })
+                .collect::<Result<Vec<_>>>()
+                .map(Some),
+            Some(v) => Err(self.type_error(key, "字符串数组", v)),
+        }
+    }
+
+    fn type_error(&self, key: &str, expected: &str, found: &Value) -> anyhow::Error {
+        anyhow!("配置 [{}] 中的 {} 应为{}，实际为{}", self.name, key, expected, found.type_name())
+    }
+}
+
+/// 已解析的配置文件
+#[derive(Debug, Default)]
+pub struct Config {
+    pub path: PathBuf,
+    tables: Vec<Table>,
+}
+
+impl Config {
+    /// 读取指定的配置文件；未指定时查找默认位置，都不存在则返回空配置
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_paths().into_iter().find(|p| p.is_file()) {
+                Some(path) => path,
+                None => return Ok(Config::default()),
+            },
+        };
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("读取配置文件 {} 失败", path.display()))?;
+        let tables = parse(&content).with_context(|| format!("配置文件 {} 有误", path.display()))?;
+        Ok(Config { path, tables })
+    }
+
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        self.tables.iter().find(|t| t.name == name)
+    }
+
+    /// 列出 `[prefix.xxx]` 形式的子表，返回 (xxx, 表)
+    pub fn subtables<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a str, &'a Table)> {
+        self.tables.iter().filter_map(move |t| {
+            let rest = t.name.strip_prefix(prefix)?.strip_prefix('.')?;
+            Some((rest, t))
+        })
+    }
+}
+
+fn default_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from("serial-tool.toml")];
+    let config_dir = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    if let Some(dir) = config_dir {
+        paths.push(dir.join("serial-tool").join("config.toml"));
+    }
+    paths
+}
+
+/// 解析配置文本；顶层（第一个表头之前）的键归入名称为空的表
+fn parse(content: &str) -> Result<Vec<Table>> {
+    let mut tables = vec![Table::default()];
+    let mut lines = content.lines().enumerate();
+
+    while let Some((i, line)) = lines.next() {
+        let mut text = strip_comment(line).trim().to_string();
+        if text.is_empty() {
+            continue;
+        }
+        let lineno = i + 1;
+
+        if text.starts_with('[') {
+            let name = text
+                .strip_prefix('[')
+                .and_then(|s| s.strip_suffix(']'))
+                .filter(|s| !s.starts_with('['))
+                .ok_or_else(|| anyhow!("第 {} 行：无效的表头（不支持 [[数组表]]）", lineno))?
+                .trim();
+            let name = split_dotted(name).with_context(|| format!("第 {} 行：无效的表名", lineno))?.join(".");
+            if tables.iter().any(|t| t.name == name) {
+                bail!("第 {} 行：表 [{}] 重复定义", lineno, name);
+            }
+            tables.push(Table { name, entries: Vec::new() });
+            continue;
+        }
+
+        // 数组可以跨多行：括号未闭合时继续拼接后续行
+        while open_brackets(&text) > 0 {
+            let Some((_, next)) = lines.next() else {
+                bail!("第 {} 行：数组缺少 ']'", lineno);
+            };
+            text.push(' ');
+            text.push_str(strip_comment(next).trim());
+        }
+
+        let (key, value) = split_key_value(&text).with_context(|| format!("第 {} 行", lineno))?;
+        let table = tables.last_mut().expect("至少有顶层表");
+        if table.get(&key).is_some() {
+            bail!("第 {} 行：键 {} 重复定义", lineno, key);
+        }
+        table.entries.push((key, value));
+    }
+    Ok(tables)
+}
+
+fn split_key_value(text: &str) -> Result<(String, Value)> {
+    let eq = find_unquoted(text, '=').ok_or_else(|| anyhow!("应为 键 = 值"))?;
+    let key = unquote_key(text[..eq].trim())?;
+    let mut parser = ValueParser { rest: text[eq + 1..].trim() };
+    let value = parser.value()?;
+    if !parser.rest.trim().is_empty() {
+        bail!("值后有多余内容: {}", parser.rest.trim());
+    }
+    Ok((key, value))
+}
+
+/// 去掉不在字符串内的 `#` 注释
+fn strip_comment(line: &str) -> &str {
+    match find_unquoted(line, '#') {
+        Some(i) => &line[..i],
+        None => line,
+    }
+}
+
+/// 查找不在引号内的字符
+fn find_unquoted(text: &str, target: char) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == target => return Some(i),
+            None => {}
+        }
+    }
+    None
+}
+
+/// 未闭合的 '[' 数量（忽略字符串内的括号）
+fn open_brackets(text: &str) -> i32 {
+    let Some(eq) = find_unquoted(text, '=') else { return 0 };
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for c in text[eq..].chars() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                _ => {}
+            },
+        }
+    }
+    depth
+}
+
+/// 按不在引号内的 '.' 拆分表名，如 `reset."a.b"` -> ["reset", "a.b"]
+fn split_dotted(name: &str) -> Result<Vec<String>> {
+    let mut parts = Vec::new();
+    let mut rest = name;
+    while let Some(i) = find_unquoted(rest, '.') {
+        parts.push(unquote_key(rest[..i].trim())?);
+        rest = &rest[i + 1..];
+    }
+    parts.push(unquote_key(rest.trim())?);
+    Ok(parts)
+}
+
+fn unquote_key(key: &str) -> Result<String> {
+    if key.is_empty() {
+        bail!("键名为空");
+    }
+    if key.starts_with('"') || key.starts_with('\'') {
+        let mut parser = ValueParser { rest: key };
+        return match parser.value()? {
+            Value::Str(s) if parser.rest.is_empty() => Ok(s),
+            _ => bail!("无效的键名 {}", key),
+        };
+    }
+    if !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        bail!("无效的键名 {}（含特殊字符时请加引号）", key);
+    }
+    Ok(key.to_string())
+}
+
+/// 从文本开头逐个解析值
+struct ValueParser<'a> {
+    rest: &'a str,
+}
+
+impl ValueParser<'_> {
+    fn value(&mut self) -> Result<Value> {
+        self.rest = self.rest.trim_start();
+        match self.rest.chars().next() {
+            None => bail!("缺少值"),
+            Some('"') => self.basic_string().map(Value::Str),
+            Some('\'') => self.literal_string().map(Value::Str),
+            Some('[') => self.array(),
+            Some(_) => self.bare(),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String> {
+        let mut out = String::new();
+        let mut chars = self.rest[1..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.rest = &self.rest[i + 2..];
+                    return Ok(out);
+                }
+                '\\' => {
+                    let (_, e) = chars.next().ok_or_else(|| anyhow!("字符串未结束"))?;
+                    match e {
+                        'n' => out.push('\n'),
+                        'r' => out.push('\r'),
+                        't' => out.push('\t'),
+                        '"' => out.push('"'),
+                        '\\' => out.push('\\'),
+                        'u' => {
+                            let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                            let code = u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| anyhow!("无效的转义 \\u{}", hex))?;
+                            out.push(code);
+                        }
+                        other => bail!("不支持的转义序列 \\{}", other),
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+        bail!("字符串缺少结束的 '\"'")
+    }
+
+    fn literal_string(&mut self) -> Result<String> {
+        let end = self.rest[1..].find('\'').ok_or_else(|| anyhow!("字符串缺少结束的 '''"))?;
+        let s = self.rest[1..end + 1].to_string();
+        self.rest = &self.rest[end + 2..];
+        Ok(s)
+    }
+
+    fn array(&mut self) -> Result<Value> {
+        self.rest = &self.rest[1..];
+        let mut items = Vec::new();
+        loop {
+            self.rest = self.rest.trim_start();
+            if let Some(rest) = self.rest.strip_prefix(']') {
+                self.rest = rest;
+                return Ok(Value::Array(items));
+            }
+            items.push(self.value()?);
+            self.rest = self.rest.trim_start();
+            if let Some(rest) = self.rest.strip_prefix(',') {
+                self.rest = rest;
+            } else if !self.rest.starts_with(']') {
+                bail!("数组元素之间应以 ',' 分隔");
+            }
+        }
+    }
+
+    /// 布尔值、浮点数或整数（整数支持 0x/0o/0b 前缀，数字间可用 `_` 分隔）
+    fn bare(&mut self) -> Result<Value> {
+        let end = self.rest.find([',', ']', ' ', '\t']).unwrap_or(self.rest.len());
+        let token = &self.rest[..end];
+        self.rest = &self.rest[end..];
+
+        match token {
+            "true" => return Ok(Value::Bool(true)),
+            "false" => return Ok(Value::Bool(false)),
+            _ => {}
+        }
+        let digits = token.replace('_', "");
+        if !digits.trim_start_matches(['+', '-']).starts_with("0x") && digits.contains(['.', 'e', 'E']) {
+            return digits
+                .parse()
+                .map(Value::Float)
+                .map_err(|_| anyhow!("无效的值 {}（字符串请加引号）", token));
+        }
+        let (negative, digits) = match digits.strip_prefix('-') {
+            Some(d) => (true, d.to_string()),
+            None => (false, digits.strip_prefix('+').unwrap_or(&digits).to_string()),
+        };
+        let parsed = if let Some(hex) = digits.strip_prefix("0x") {
+            i64::from_str_radix(hex, 16)
+        } else if let Some(oct) = digits.strip_prefix("0o") {
+            i64::from_str_radix(oct, 8)
+        } else if let Some(bin) = digits.strip_prefix("0b") {
+            i64::from_str_radix(bin, 2)
+        } else {
+            digits.parse()
+        };
+        let n = parsed.map_err(|_| anyhow!("无效的值 {}（字符串请加引号）", token))?;
+        Ok(Value::Int(if negative { -n } else { n }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(content: &str) -> Config {
+        Config { path: PathBuf::new(), tables: parse(content).unwrap() }
+    }
+
+    fn error(content: &str) -> String {
+        format!("{:#}", parse(content).unwrap_err())
+    }
+
+    #[test]
+    fn values() {
+        let config = config(
+            r#"
+top = 1
+
+[values]
+dec = 115_200
+neg = -42
+hex = 0xFF
+neg_hex = -0x10
+oct = 0o17
+bin = 0b1010
+float = 2.5
+exp = 1e3
+yes = true
+no = false
+basic = "a\tb\n\"c\" \\ \u00e9"
+literal = 'C:\path\no-escape'
+hash = "not # a comment"  # 这里才是注释
+"quoted key" = 'x'
+"#,
+        );
+        assert_eq!(config.table("").unwrap().int("top").unwrap(), Some(1));
+        let t = config.table("values").unwrap();
+        assert_eq!(t.int("dec").unwrap(), Some(115200));
+        assert_eq!(t.int("neg").unwrap(), Some(-42));
+        assert_eq!(t.int("hex").unwrap(), Some(255));
+        assert_eq!(t.int("neg_hex").unwrap(), Some(-16));
+        assert_eq!(t.int("oct").unwrap(), Some(15));
+        assert_eq!(t.int("bin").unwrap(), Some(10));
+        assert_eq!(t.float("float").unwrap(), Some(2.5));
+        assert_eq!(t.float("exp").unwrap(), Some(1000.0));
+        assert_eq!(t.bool("yes").unwrap(), Some(true));
+        assert_eq!(t.bool("no").unwrap(), Some(false));
+        assert_eq!(t.str("basic").unwrap(), Some("a\tb\n\"c\" \\ é"));
+        assert_eq!(t.str("literal").unwrap(), Some(r"C:\path\no-escape"));
+        assert_eq!(t.str("hash").unwrap(), Some("not # a comment"));
+        assert_eq!(t.str("quoted key").unwrap(), Some("x"));
+        assert_eq!(t.str("missing").unwrap(), None);
+        assert!(t.str("dec").is_err());
+        assert!(t.int("basic").is_err());
+    }
+
+    #[test]
+    fn arrays_and_tables() {
+        let config = config(
+            r#"
+[reset.arduino]
+steps = [
+    "dtr=0",  # 注释中的 ] 不影响
+    "wait=100ms",
+    "dtr=1",
+]
+nested = [[1, 2], [], ["]"]]
+
+[reset."esp.32"]
+steps = ['a', "b"]
+
+[other]
+"#,
+        );
+        let arduino = config.table("reset.arduino").unwrap();
+        assert_eq!(arduino.str_array("steps").unwrap(), Some(vec!["dtr=0", "wait=100ms", "dtr=1"]));
+        assert_eq!(
+            arduino.get("nested"),
+            Some(&Value::Array(vec![
+                Value::Array(vec![Value::Int(1), Value::Int(2)]),
+                Value::Array(vec![]),
+                Value::Array(vec![Value::Str("]".to_string())]),
+            ]))
+        );
+        let names: Vec<&str> = config.subtables("reset").map(|(name, _)| name).collect();
+        assert_eq!(names, ["arduino", "esp.32"]);
+        let keys: Vec<&str> = arduino.entries().map(|(key, _)| key).collect();
+        assert_eq!(keys, ["steps", "nested"]);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(error("a = 1\na = 2").contains("第 2 行：键 a 重复定义"));
+        assert!(error("[t]\n[t]").contains("表 [t] 重复定义"));
+        assert!(error("[[t]]").contains("不支持 [[数组表]]"));
+        assert!(error("a = \"abc").contains("缺少结束"));
+        assert!(error("a =").contains("缺少值"));
+        assert!(error("a = 1 2").contains("多余内容"));
+        assert!(error("a = hello").contains("字符串请加引号"));
+        assert!(error("a = [1, 2").contains("缺少 ']'"));
+        assert!(error("a = [1 2]").contains("','"));
+        assert!(error("a b = 1").contains("无效的键名"));
+        assert!(error("a = \"\\q\"").contains("不支持的转义序列"));
+        assert!(error("just text").contains("应为 键 = 值"));
+    }
+}
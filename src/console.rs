@@ -0,0 +1,271 @@
+//! 控制台原始模式与按键解析
+//!
+//! 原始模式下按键不经过系统行缓冲和回显，由程序自行处理；
+//! Windows 下同时开启 VT 输入/输出，使方向键等以 ANSI 转义序列到达，与 Unix 统一解析。
+
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::io;
+
+/// 解析后的按键
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    /// Ctrl+字母（小写），如 Ctrl+R 为 `Ctrl('r')`
+    Ctrl(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Esc,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    /// 功能键 F1-F12
+    F(u8),
+    /// Ctrl+]（telnet 风格的退出键）
+    CtrlBracket,
+}
+
+impl Key {
+    /// 按键对应的终端字节序列（xterm 风格），字符模式下原样转发给设备
+    pub fn to_bytes(self) -> Vec<u8> {
+        let seq: &[u8] = match self {
+            Key::Char(c) => return c.to_string().into_bytes(),
+            Key::Ctrl(c) => return vec![(c as u8) & 0x1f],
+            Key::Enter => b"\r",
+            Key::Tab => b"\t",
+            Key::Backspace => b"\x7f",
+            Key::Delete => b"\x1b[3~",
+            Key::Esc => b"\x1b",
+            Key::Up => b"\x1b[A",
+            Key::Down => b"\x1b[B",
+            Key::Right => b"\x1b[C",
+            Key::Left => b"\x1b[D",
+            Key::Home => b"\x1b[H",
+            Key::End => b"\x1b[F",
+            Key::PageUp => b"\x1b[5~",
+            Key::PageDown => b"\x1b[6~",
+            Key::F(n @ 1..=4) => return vec![0x1b, b'O', b'P' + n - 1],
+            Key::F(n) => {
+                let code = match n {
+                    5 => 15,
+                    6..=10 => n + 11,
+                    _ => n + 12,
+                };
+                return format!("\x1b[{}~", code).into_bytes();
+            }
+            Key::CtrlBracket => b"\x1d",
+        };
+        seq.to_vec()
+    }
+}
+
+/// 原始模式守卫，离开作用域时恢复控制台设置
+pub struct RawMode {
+    #[cfg(unix)]
+    saved: nix::sys::termios::Termios,
+    #[cfg(windows)]
+    saved: (u32, u32),
+}
+
+impl RawMode {
+    #[cfg(unix)]
+    pub fn enable() -> Result<Self> {
+        use nix::sys::termios::{self, InputFlags, LocalFlags, SetArg, SpecialCharacterIndices};
+
+        let saved = termios::tcgetattr(0).context("标准输入不是终端")?;
+        let mut raw = saved.clone();
+        raw.input_flags &= !(InputFlags::ICRNL | InputFlags::IXON | InputFlags::INLCR | InputFlags::ISTRIP);
+        raw.local_flags &= !(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG | LocalFlags::IEXTEN);
+        raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+        raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+        // 保留输出处理（OPOST），让 "\n" 仍然换到行首
+        termios::tcsetattr(0, SetArg::TCSANOW, &raw).context("无法切换到原始模式")?;
+        Ok(RawMode { saved })
+    }
+
+    #[cfg(windows)]
+    pub fn enable() -> Result<Self> {
+        use windows_sys::Win32::System::Console::*;
+
+        unsafe {
+            let input = GetStdHandle(STD_INPUT_HANDLE);
+            let output = GetStdHandle(STD_OUTPUT_HANDLE);
+            let (mut in_mode, mut out_mode) = (0, 0);
+            if GetConsoleMode(input, &mut in_mode) == 0 || GetConsoleMode(output, &mut out_mode) == 0 {
+                anyhow::bail!("标准输入不是控制台");
+            }
+            let raw_in = (in_mode & !(ENABLE_LINE_INPUT | ENABLE_ECHO_INPUT | ENABLE_PROCESSED_INPUT))
+                | ENABLE_VIRTUAL_TERMINAL_INPUT;
+            let raw_out = out_mode | ENABLE_PROCESSED_OUTPUT | ENABLE_VIRTUAL_TERMINAL_PROCESSING;
+            if SetConsoleMode(input, raw_in) == 0 || SetConsoleMode(output, raw_out) == 0 {
+                anyhow::bail!("无法切换到原始模式");
+            }
+            Ok(RawMode { saved: (in_mode, out_mode) })
+        }
+    }
+}
+
+impl Drop for RawMode {
+    #[cfg(unix)]
+    fn drop(&mut self) {
+        use nix::sys::termios::{self, SetArg};
+        let _ = termios::tcsetattr(0, SetArg::TCSANOW, &self.saved);
+    }
+
+    #[cfg(windows)]
+    fn drop(&mut self) {
+        use windows_sys::Win32::System::Console::*;
+        unsafe {
+            SetConsoleMode(GetStdHandle(STD_INPUT_HANDLE), self.saved.0);
+            SetConsoleMode(GetStdHandle(STD_OUTPUT_HANDLE), self.saved.1);
+        }
+    }
+}
+
+/// 获取终端窗口大小（列数, 行数），无法获取时返回 `None`
+#[cfg(unix)]
+pub fn size() -> Option<(u16, u16)> {
+    use nix::libc;
+
+    let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+    let ok = unsafe { libc::ioctl(1, libc::TIOCGWINSZ, &mut ws) } == 0;
+    (ok && ws.ws_col > 0 && ws.ws_row > 0).then_some((ws.ws_col, ws.ws_row))
+}
+
+/// 获取终端窗口大小（列数, 行数），无法获取时返回 `None`
+#[cfg(windows)]
+pub fn size() -> Option<(u16, u16)> {
+    use windows_sys::Win32::System::Console::*;
+
+    unsafe {
+        let mut info: CONSOLE_SCREEN_BUFFER_INFO = std::mem::zeroed();
+        if GetConsoleScreenBufferInfo(GetStdHandle(STD_OUTPUT_HANDLE), &mut info) == 0 {
+            return None;
+        }
+        let w = info.srWindow;
+        Some(((w.Right - w.Left + 1) as u16, (w.Bottom - w.Top + 1) as u16))
+    }
+}
+
+/// 从标准输入读取按键
+///
+/// 每次读取系统当前可用的全部字节再整体解析，
+/// 单独到达的 ESC 视为 Esc 键，随后紧跟的字节视为转义序列。
+#[derive(Default)]
+pub struct KeyReader {
+    pending: VecDeque<Key>,
+}
+
+impl KeyReader {
+    /// 阻塞读取下一个按键；标准输入结束时返回 `None`
+    pub fn next_key(&mut self) -> Result<Option<Key>> {
+        while self.pending.is_empty() {
+            let mut buf = [0u8; 512];
+            let n = read_stdin(&mut buf).context("读取键盘输入失败")?;
+            if n == 0 {
+                return Ok(None);
+            }
+            parse_keys(&buf[..n], &mut self.pending);
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+#[cfg(unix)]
+fn read_stdin(buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match nix::unistd::read(0, buf) {
+            Ok(n) => return Ok(n),
+            Err(nix::errno::Errno::EINTR) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+#[cfg(windows)]
+fn read_stdin(buf: &mut [u8]) -> io::Result<usize> {
+    use std::io::Read;
+    io::stdin().read(buf)
+}
+
+/// 把一段输入字节解析为按键序列
+fn parse_keys(bytes: &[u8], out: &mut VecDeque<Key>) {
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        i += 1;
+        let key = match b {
+            0x1b => match parse_escape(&bytes[i..]) {
+                Some((key, used)) => {
+                    i += used;
+                    key
+                }
+                None => Key::Esc,
+            },
+            b'\r' | b'\n' => Key::Enter,
+            b'\t' => Key::Tab,
+            0x7f | 0x08 => Key::Backspace,
+            0x1d => Key::CtrlBracket,
+            0x01..=0x1a => Key::Ctrl((b'a' + b - 1) as char),
+            0x00..=0x1f => continue,
+            _ => {
+                // UTF-8 多字节字符
+                let len = match b {
+                    0xc0..=0xdf => 2,
+                    0xe0..=0xef => 3,
+                    0xf0..=0xf7 => 4,
+                    _ => 1,
+                };
+                let end = (i - 1 + len).min(bytes.len());
+                let ch = std::str::from_utf8(&bytes[i - 1..end])
+                    .ok()
+                    .and_then(|s| s.chars().next())
+                    .unwrap_or(char::REPLACEMENT_CHARACTER);
+                i = end;
+                Key::Char(ch)
+            }
+        };
+        out.push_back(key);
+    }
+}
+
+/// 解析 ESC 之后的转义序列，返回按键和消耗的字节数
+fn parse_escape(rest: &[u8]) -> Option<(Key, usize)> {
+    match rest {
+        [b'[', b'A', ..] | [b'O', b'A', ..] => Some((Key::Up, 2)),
+        [b'[', b'B', ..] | [b'O', b'B', ..] => Some((Key::Down, 2)),
+        [b'[', b'C', ..] | [b'O', b'C', ..] => Some((Key::Right, 2)),
+        [b'[', b'D', ..] | [b'O', b'D', ..] => Some((Key::Left, 2)),
+        [b'[', b'H', ..] | [b'O', b'H', ..] => Some((Key::Home, 2)),
+        [b'[', b'F', ..] | [b'O', b'F', ..] => Some((Key::End, 2)),
+        [b'O', c @ b'P'..=b'S', ..] => Some((Key::F(c - b'P' + 1), 2)),
+        [b'[', tail @ ..] => {
+            // CSI 数字 ~ 形式，如 "[3~" 为 Delete、"[15~" 为 F5
+            let digits = tail.iter().take_while(|b| b.is_ascii_digit()).count();
+            if digits == 0 || tail.get(digits) != Some(&b'~') {
+                return None;
+            }
+            let code: u32 = std::str::from_utf8(&tail[..digits]).ok()?.parse().ok()?;
+            let key = match code {
+                1 | 7 => Key::Home,
+                3 => Key::Delete,
+                4 | 8 => Key::End,
+                5 => Key::PageUp,
+                6 => Key::PageDown,
+                11..=15 => Key::F((code - 10) as u8),
+                17..=21 => Key::F((code - 11) as u8),
+                23 | 24 => Key::F((code - 12) as u8),
+                _ => return None,
+            };
+            Some((key, digits + 2))
+        }
+        _ => None,
+    }
+}
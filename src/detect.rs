@@ -0,0 +1,125 @@
+//! 波特率自动检测：依次尝试常用波特率，按收到数据的可读程度打分
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serialport::SerialPort;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::events;
+use crate::signal::Rs485;
+use crate::terminal;
+use crate::{parse_duration, parse_escapes};
+
+/// 默认尝试的波特率（常用值优先）
+const COMMON_RATES: &[u32] = &[
+    115200, 9600, 57600, 38400, 19200, 230400, 460800, 921600, 74880, 250000, 4800, 2400, 1200,
+];
+
+/// 收到的字节少于该数量时不足以判断
+const MIN_BYTES: usize = 8;
+
+/// detect-baud 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct DetectBaudArgs {
+    /// 要尝试的波特率（逗号分隔，默认为常用波特率）
+    #[arg(long, value_delimiter = ',', value_name = "RATES")]
+    pub rates: Vec<u32>,
+
+    /// 每个波特率的监听时长
+    #[arg(long, default_value = "1s", value_parser = parse_duration)]
+    pub listen: Duration,
+
+    /// 切换波特率后发送的探测内容（支持 \r \n \xNN 等转义），用于触发设备输出
+    #[arg(long, value_name = "TEXT")]
+    pub probe: Option<String>,
+
+    /// 期望收到的内容（正则）；匹配的波特率直接判定为正确
+    #[arg(long, value_name = "REGEX")]
+    pub expect: Option<Regex>,
+}
+
+/// 某个波特率的检测结果
+struct Trial {
+    baud: u32,
+    bytes: usize,
+    score: f64,
+    matched: bool,
+}
+
+/// 可读字节占比：可打印 ASCII、常见空白和合法 UTF-8 多字节字符计为可读
+fn printable_score(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let text = String::from_utf8_lossy(data);
+    let good: usize = text
+        .chars()
+        .filter(|&c| c != char::REPLACEMENT_CHARACTER)
+        .filter(|&c| !c.is_control() || matches!(c, '\r' | '\n' | '\t'))
+        .map(char::len_utf8)
+        .sum();
+    good as f64 / data.len() as f64
+}
+
+/// 执行波特率检测并输出各波特率得分和结论
+pub fn run_detect(port: &mut Box<dyn SerialPort>, opts: &DetectBaudArgs, rs485: Option<Rs485>) -> Result<()> {
+    let rates = if opts.rates.is_empty() { COMMON_RATES } else { &opts.rates };
+    let probe = opts.probe.as_deref().map(parse_escapes).transpose().context("探测内容解析失败")?;
+    let original = port.baud_rate().ok();
+
+    let mut trials = Vec::new();
+    for &baud in rates {
+        port.set_baud_rate(baud).with_context(|| format!("设置波特率 {} 失败", baud))?;
+        port.clear(serialport::ClearBuffer::All).context("清空缓冲区失败")?;
+        if let Some(probe) = &probe {
+            terminal::transmit(port, rs485, probe).context("发送探测内容失败")?;
+        }
+
+        let data = listen(port, opts.listen)?;
+        let matched = opts.expect.as_ref().is_some_and(|re| re.is_match(&String::from_utf8_lossy(&data)));
+        let trial = Trial { baud, bytes: data.len(), score: printable_score(&data), matched };
+        events::status(&format!(
+            "{:>8}  收到 {:>5} 字节  可读 {:>5.1}%{}",
+            baud,
+            trial.bytes,
+            trial.score * 100.0,
+            if matched { "  匹配" } else { "" }
+        ));
+        trials.push(trial);
+        if matched {
+            break; // 已找到期望内容，无需继续
+        }
+    }
+
+    if let Some(baud) = original {
+        port.set_baud_rate(baud).ok();
+    }
+
+    let best = trials
+        .iter()
+        .filter(|t| t.matched || t.bytes >= MIN_BYTES)
+        .max_by(|a, b| (a.matched, a.score).partial_cmp(&(b.matched, b.score)).unwrap_or(std::cmp::Ordering::Equal));
+    match best {
+        Some(t) if t.matched => events::status(&format!("检测结果：{}（收到期望内容）", t.baud)),
+        Some(t) if t.score >= 0.9 => events::status(&format!("检测结果：{}（可读 {:.1}%）", t.baud, t.score * 100.0)),
+        Some(t) => events::status(&format!("可能的波特率：{}（可读仅 {:.1}%，结果不可靠）", t.baud, t.score * 100.0)),
+        None => anyhow::bail!("所有波特率下收到的数据都不足 {} 字节，请确认设备正在输出或使用 --probe 触发输出", MIN_BYTES),
+    }
+    Ok(())
+}
+
+/// 在当前波特率下读取指定时长的数据
+fn listen(port: &mut Box<dyn SerialPort>, duration: Duration) -> Result<Vec<u8>> {
+    let deadline = Instant::now() + duration;
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 256];
+    while Instant::now() < deadline {
+        match port.read(&mut buffer) {
+            Ok(n) => received.extend_from_slice(&buffer[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e).context("读取串口失败"),
+        }
+    }
+    Ok(received)
+}
@@ -0,0 +1,328 @@
+//! DL/T 645-2007 多功能电能表通信协议：帧编解码（数据域加 0x33、累加校验和）、
+//! 常用数据标识的数值解析，以及读取电表数据的 dlt645 子命令
+//!
+//! 帧结构：`[FE..] 68 A0..A5 68 C L DATA CS 16`，地址为 6 字节 BCD（低字节在前），
+//! 每字节可用 AA 作为通配符；CS 为第一个 68 到数据域末尾的字节和。
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::events::{self, Event};
+use crate::format_hex;
+use crate::framing::{Deframer, FrameSpec};
+use crate::parse_duration;
+use crate::signal::Rs485;
+use crate::terminal;
+
+/// dlt645 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct Dlt645Args {
+    #[command(subcommand)]
+    pub command: Dlt645Command,
+
+    /// 电表地址（12 位，不足时左侧补 0；A 为通配符，如 AAAAAAAAAAAA 表示任意地址）
+    #[arg(long, default_value = "AAAAAAAAAAAA", value_parser = parse_address, global = true)]
+    pub addr: [u8; 6],
+
+    /// 等待电表应答的超时时间
+    #[arg(long, default_value = "2s", value_parser = parse_duration, global = true)]
+    pub timeout: Duration,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Dlt645Command {
+    /// 读数据（控制码 0x11）
+    Read {
+        /// 数据标识 DI3DI2DI1DI0（十六进制），如 00010000 正向有功总电能、02010100 A 相电压、
+        /// 02020100 A 相电流、02030000 总有功功率、02060000 总功率因数、02800002 电网频率
+        #[arg(long, default_value = "00010000", value_parser = parse_di)]
+        di: u32,
+    },
+    /// 读通信地址（控制码 0x13，总线上只能有一块电表）
+    Address,
+}
+
+/// 唤醒电表的前导字节
+const PREAMBLE: [u8; 4] = [0xFE; 4];
+/// 帧起始符和结束符
+const START: u8 = 0x68;
+const END: u8 = 0x16;
+/// 数据域最大长度
+const MAX_DATA: usize = 200;
+
+/// 解析电表地址，返回线路上的字节顺序（低字节在前）
+fn parse_address(s: &str) -> std::result::Result<[u8; 6], String> {
+    if s.len() > 12 || !s.chars().all(|c| c.is_ascii_digit() || c.eq_ignore_ascii_case(&'a')) {
+        return Err(format!("无效的电表地址 '{}'，应为最多 12 位数字，A 为通配符", s));
+    }
+    let digits = format!("{:0>12}", s.to_ascii_uppercase());
+    let mut address = [0u8; 6];
+    for (i, byte) in address.iter_mut().enumerate() {
+        let pair = &digits[10 - i * 2..12 - i * 2];
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| format!("无效的电表地址 '{}'", s))?;
+    }
+    Ok(address)
+}
+
+fn parse_di(s: &str) -> std::result::Result<u32, String> {
+    match u32::from_str_radix(s, 16) {
+        Ok(di) if s.len() == 8 => Ok(di),
+        _ => Err(format!("无效的数据标识 '{}'，应为 8 位十六进制（DI3DI2DI1DI0）", s)),
+    }
+}
+
+/// 地址按表号显示（高字节在前）
+fn format_address(address: &[u8]) -> String {
+    address.iter().rev().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// 一个数据标识的名称和格式
+struct DataItem {
+    di: u32,
+    name: &'static str,
+    /// 数据字节数
+    size: usize,
+    /// 小数位数
+    decimals: usize,
+    unit: &'static str,
+    /// 最高字节的最高位为符号位
+    signed: bool,
+}
+
+const fn item(di: u32, name: &'static str, size: usize, decimals: usize, unit: &'static str, signed: bool) -> DataItem {
+    DataItem { di, name, size, decimals, unit, signed }
+}
+
+/// 常用数据标识
+const ITEMS: &[DataItem] = &[
+    item(0x0000_0000, "组合有功总电能", 4, 2, "kWh", true),
+    item(0x0001_0000, "正向有功总电能", 4, 2, "kWh", false),
+    item(0x0001_0100, "正向有功费率 1 电能", 4, 2, "kWh", false),
+    item(0x0001_0200, "正向有功费率 2 电能", 4, 2, "kWh", false),
+    item(0x0001_0300, "正向有功费率 3 电能", 4, 2, "kWh", false),
+    item(0x0001_0400, "正向有功费率 4 电能", 4, 2, "kWh", false),
+    item(0x0002_0000, "反向有功总电能", 4, 2, "kWh", false),
+    item(0x0003_0000, "组合无功 1 总电能", 4, 2, "kvarh", true),
+    item(0x0004_0000, "组合无功 2 总电能", 4, 2, "kvarh", true),
+    item(0x0201_0100, "A 相电压", 2, 1, "V", false),
+    item(0x0201_0200, "B 相电压", 2, 1, "V", false),
+    item(0x0201_0300, "C 相电压", 2, 1, "V", false),
+    item(0x0202_0100, "A 相电流", 3, 3, "A", true),
+    item(0x0202_0200, "B 相电流", 3, 3, "A", true),
+    item(0x0202_0300, "C 相电流", 3, 3, "A", true),
+    item(0x0203_0000, "总有功功率", 3, 4, "kW", true),
+    item(0x0203_0100, "A 相有功功率", 3, 4, "kW", true),
+    item(0x0203_0200, "B 相有功功率", 3, 4, "kW", true),
+    item(0x0203_0300, "C 相有功功率", 3, 4, "kW", true),
+    item(0x0204_0000, "总无功功率", 3, 4, "kvar", true),
+    item(0x0206_0000, "总功率因数", 2, 3, "", true),
+    item(0x0280_0001, "零线电流", 3, 3, "A", true),
+    item(0x0280_0002, "电网频率", 2, 2, "Hz", false),
+    item(0x0280_0007, "表内温度", 2, 1, "°C", true),
+];
+
+/// 解码 BCD 数值（低字节在前），非 BCD 时返回 `None`
+fn decode_bcd(bytes: &[u8], decimals: usize, signed: bool) -> Option<String> {
+    let mut bytes = bytes.to_vec();
+    let negative = signed && bytes.last().is_some_and(|b| b & 0x80 != 0);
+    if negative {
+        *bytes.last_mut()? &= 0x7F;
+    }
+    let digits: String = bytes.iter().rev().map(|b| format!("{:02X}", b)).collect();
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (int, frac) = digits.split_at(digits.len() - decimals);
+    let int = int.trim_start_matches('0');
+    let int = if int.is_empty() { "0" } else { int };
+    let sign = if negative { "-" } else { "" };
+    Some(match decimals {
+        0 => format!("{}{}", sign, int),
+        _ => format!("{}{}.{}", sign, int, frac),
+    })
+}
+
+/// 解码数据域中的数据标识和数值，如 "正向有功总电能 = 1234.56 kWh"
+fn describe_data(data: &[u8]) -> String {
+    let Some(di) = data.get(..4) else {
+        return format!("数据 {}", format_hex(data));
+    };
+    let di = u32::from_le_bytes([di[0], di[1], di[2], di[3]]);
+    let value = &data[4..];
+    match ITEMS.iter().find(|item| item.di == di) {
+        Some(item) if value.len() == item.size => match decode_bcd(value, item.decimals, item.signed) {
+            Some(number) => format!("{} = {} {}", item.name, number, item.unit).trim_end().to_string(),
+            None => format!("{} = {}（非 BCD）", item.name, format_hex(value)),
+        },
+        Some(item) if value.is_empty() => item.name.to_string(),
+        Some(item) => format!("{} = {}", item.name, format_hex(value)),
+        None if value.is_empty() => format!("DI {:08X}", di),
+        None => format!("DI {:08X} = {}", di, format_hex(value)),
+    }
+}
+
+/// 控制码的功能名称
+fn function_name(code: u8) -> &'static str {
+    match code & 0x1F {
+        0x08 => "广播校时",
+        0x11 => "读数据",
+        0x12 => "读后续数据",
+        0x13 => "读通信地址",
+        0x14 => "写数据",
+        0x15 => "写通信地址",
+        0x16 => "冻结命令",
+        0x17 => "更改通信速率",
+        0x18 => "修改密码",
+        0x19 => "最大需量清零",
+        0x1A => "电表清零",
+        0x1B => "事件清零",
+        0x1C => "跳合闸、报警、保电",
+        _ => "未知功能",
+    }
+}
+
+/// 异常应答的错误信息字
+fn error_text(code: u8) -> String {
+    const BITS: [&str; 7] = ["其他错误", "无请求数据", "密码错或未授权", "通信速率不能更改", "年时区数超", "日时段数超", "费率数超"];
+    let errors: Vec<&str> = BITS.iter().enumerate().filter(|(i, _)| code & (1 << i) != 0).map(|(_, s)| *s).collect();
+    match errors.is_empty() {
+        true => format!("错误字 0x{:02X}", code),
+        false => errors.join("、"),
+    }
+}
+
+/// 解析出的一帧
+pub struct Telegram {
+    pub address: [u8; 6],
+    pub control: u8,
+    /// 已减去 0x33 的数据域
+    pub data: Vec<u8>,
+}
+
+impl Telegram {
+    /// 是否为电表的应答
+    fn is_response(&self) -> bool {
+        self.control & 0x80 != 0
+    }
+
+    /// 是否为异常应答
+    fn is_error(&self) -> bool {
+        self.control & 0x40 != 0
+    }
+
+    /// 帧内容说明，如 "地址 000012345678 读数据应答 正向有功总电能 = 1234.56 kWh"
+    pub fn describe(&self) -> String {
+        let direction = if self.is_response() { "应答" } else { "请求" };
+        let mut text = format!("地址 {} {}{}", format_address(&self.address), function_name(self.control), direction);
+        if self.is_error() {
+            let code = self.data.first().copied().unwrap_or(0);
+            text.push_str(&format!(" 异常：{}", error_text(code)));
+            return text;
+        }
+        let detail = match self.control & 0x1F {
+            0x11 | 0x12 => describe_data(&self.data),
+            0x13 if self.data.len() == 6 => format!("表号 {}", format_address(&self.data)),
+            _ if self.data.is_empty() => String::new(),
+            _ => format!("数据 {}", format_hex(&self.data)),
+        };
+        if !detail.is_empty() {
+            text.push(' ');
+            text.push_str(&detail);
+        }
+        if self.control & 0x20 != 0 {
+            text.push_str("（有后续数据）");
+        }
+        text
+    }
+}
+
+/// 编码一帧（含前导 FE），数据域逐字节加 0x33
+pub fn encode(address: &[u8; 6], control: u8, data: &[u8]) -> Vec<u8> {
+    let mut frame = vec![START];
+    frame.extend_from_slice(address);
+    frame.push(START);
+    frame.push(control);
+    frame.push(data.len() as u8);
+    frame.extend(data.iter().map(|b| b.wrapping_add(0x33)));
+    frame.push(checksum(&frame));
+    frame.push(END);
+    [&PREAMBLE[..], &frame].concat()
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |acc, &b| acc.wrapping_add(b))
+}
+
+/// 帧头（以 0x68 开始的前 10 字节）是否有效
+pub fn header_ok(data: &[u8]) -> bool {
+    matches!(data, [START, _, _, _, _, _, _, START, _, len, ..] if *len as usize <= MAX_DATA)
+}
+
+/// 根据帧头中的数据域长度计算帧总长，不足 10 字节时返回 `None`
+pub fn frame_len(data: &[u8]) -> Option<usize> {
+    data.get(9).map(|&len| 12 + len as usize)
+}
+
+/// 解析一个完整的帧（以 0x68 开始、以 0x16 结束），校验失败时返回原因
+pub fn decode(frame: &[u8]) -> std::result::Result<Telegram, String> {
+    if !header_ok(frame) || frame_len(frame) != Some(frame.len()) {
+        return Err("帧结构错误".to_string());
+    }
+    let (body, tail) = frame.split_at(frame.len() - 2);
+    if tail[1] != END {
+        return Err(format!("结束符应为 16，实际为 {:02X}", tail[1]));
+    }
+    let expected = checksum(body);
+    if tail[0] != expected {
+        return Err(format!("校验和错误：收到 {:02X}，应为 {:02X}", tail[0], expected));
+    }
+    let mut address = [0u8; 6];
+    address.copy_from_slice(&body[1..7]);
+    Ok(Telegram { address, control: body[8], data: body[10..].iter().map(|b| b.wrapping_sub(0x33)).collect() })
+}
+
+/// 执行 dlt645 子命令
+pub fn run_dlt645(port: &mut Box<dyn SerialPort>, opts: &Dlt645Args, rs485: Option<Rs485>) -> Result<()> {
+    let (control, data) = match &opts.command {
+        Dlt645Command::Read { di } => (0x11, di.to_le_bytes().to_vec()),
+        Dlt645Command::Address => (0x13, Vec::new()),
+    };
+    let request = encode(&opts.addr, control, &data);
+    port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+    terminal::transmit(port, rs485, &request)?;
+
+    let response = receive(port, Instant::now() + opts.timeout)?
+        .with_context(|| format!("电表 {} 无应答（超时 {:?}）", format_address(&opts.addr), opts.timeout))?;
+    if response.control & 0x1F != control {
+        bail!("应答控制码 0x{:02X} 与请求 0x{:02X} 不符", response.control, control);
+    }
+    if response.is_error() {
+        bail!("{}", response.describe());
+    }
+    events::status(&response.describe());
+    Ok(())
+}
+
+/// 接收电表的应答帧（跳过总线上其他设备的请求帧），超时返回 `None`
+fn receive(port: &mut Box<dyn SerialPort>, deadline: Instant) -> Result<Option<Telegram>> {
+    let mut deframer = Deframer::new(FrameSpec::Dlt645);
+    let mut buffer = [0u8; 256];
+    while Instant::now() < deadline {
+        let n = match port.read(&mut buffer) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e).context("读取串口失败"),
+        };
+        for frame in deframer.push(&buffer[..n], Instant::now()) {
+            events::emit(Event::Rx(&frame.data));
+            match decode(&frame.data) {
+                Ok(telegram) if telegram.is_response() => return Ok(Some(telegram)),
+                Ok(_) => {}
+                Err(error) => events::status(&format!("收到无效的帧 {}：{}", format_hex(&frame.data), error)),
+            }
+        }
+    }
+    Ok(None)
+}
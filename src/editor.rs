@@ -0,0 +1,189 @@
+//! 交互模式的行编辑器：光标移动、历史记录和 Ctrl+R 反向搜索
+
+use crate::console::Key;
+
+/// 历史记录最多保留的条数
+const HISTORY_LIMIT: usize = 500;
+
+/// 单行编辑器
+#[derive(Default)]
+pub struct LineEditor {
+    line: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+    /// 正在浏览的历史位置（`None` 表示在编辑新行）
+    browse: Option<usize>,
+    /// 开始浏览历史前正在编辑的内容
+    draft: Vec<char>,
+    /// Ctrl+R 搜索状态
+    search: Option<Search>,
+}
+
+/// 反向搜索状态
+struct Search {
+    query: String,
+    /// 当前匹配的历史下标
+    matched: Option<usize>,
+}
+
+impl LineEditor {
+    /// 处理一个按键，按下回车时返回完成的一行
+    pub fn handle(&mut self, key: Key) -> Option<String> {
+        if self.search.is_some() {
+            return self.handle_search(key);
+        }
+
+        match key {
+            Key::Char(c) => {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+            }
+            Key::Delete if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+            Key::Home => self.cursor = 0,
+            Key::End | Key::Ctrl('e') => self.cursor = self.line.len(),
+            Key::Ctrl('u') => {
+                self.line.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            Key::Ctrl('k') => self.line.truncate(self.cursor),
+            Key::Ctrl('w') => {
+                // 删除光标前的一个单词
+                let mut start = self.cursor;
+                while start > 0 && self.line[start - 1] == ' ' {
+                    start -= 1;
+                }
+                while start > 0 && self.line[start - 1] != ' ' {
+                    start -= 1;
+                }
+                self.line.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            Key::Ctrl('c') => self.set_line(Vec::new()),
+            Key::Up => self.history_prev(),
+            Key::Down => self.history_next(),
+            Key::Ctrl('r') => self.search = Some(Search { query: String::new(), matched: None }),
+            Key::Enter => return Some(self.submit()),
+            _ => {}
+        }
+        None
+    }
+
+    fn handle_search(&mut self, key: Key) -> Option<String> {
+        let search = self.search.as_mut()?;
+        match key {
+            Key::Char(c) => {
+                search.query.push(c);
+                search.matched = find_back(&self.history, &search.query, self.history.len());
+            }
+            Key::Backspace => {
+                search.query.pop();
+                search.matched = find_back(&self.history, &search.query, self.history.len());
+            }
+            Key::Ctrl('r') => {
+                // 继续向更早的记录搜索
+                let from = search.matched.unwrap_or(self.history.len());
+                if let Some(i) = find_back(&self.history, &search.query, from) {
+                    search.matched = Some(i);
+                }
+            }
+            Key::Ctrl('g') | Key::Ctrl('c') | Key::Esc => self.search = None,
+            Key::Enter => {
+                self.accept_search();
+                return Some(self.submit());
+            }
+            _ => self.accept_search(),
+        }
+        None
+    }
+
+    /// 退出搜索，把匹配到的记录放入编辑行
+    fn accept_search(&mut self) {
+        if let Some(Search { matched: Some(i), .. }) = self.search.take() {
+            self.set_line(self.history[i].chars().collect());
+        }
+    }
+
+    fn submit(&mut self) -> String {
+        let line: String = self.line.iter().collect();
+        if !line.is_empty() && self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+            if self.history.len() > HISTORY_LIMIT {
+                self.history.remove(0);
+            }
+        }
+        self.browse = None;
+        self.set_line(Vec::new());
+        line
+    }
+
+    fn history_prev(&mut self) {
+        let next = match self.browse {
+            None if self.history.is_empty() => return,
+            None => {
+                self.draft = std::mem::take(&mut self.line);
+                self.history.len() - 1
+            }
+            Some(0) => return,
+            Some(i) => i - 1,
+        };
+        self.browse = Some(next);
+        self.set_line(self.history[next].chars().collect());
+    }
+
+    fn history_next(&mut self) {
+        match self.browse {
+            None => {}
+            Some(i) if i + 1 < self.history.len() => {
+                self.browse = Some(i + 1);
+                self.set_line(self.history[i + 1].chars().collect());
+            }
+            Some(_) => {
+                self.browse = None;
+                let draft = std::mem::take(&mut self.draft);
+                self.set_line(draft);
+            }
+        }
+    }
+
+    fn set_line(&mut self, line: Vec<char>) {
+        self.cursor = line.len();
+        self.line = line;
+    }
+
+    /// 当前应显示的内容，以及光标距行尾的列数
+    pub fn render(&self) -> (String, usize) {
+        if let Some(search) = &self.search {
+            let found = search.matched.map_or("", |i| self.history[i].as_str());
+            return (format!("(reverse-i-search)`{}': {}", search.query, found), 0);
+        }
+        let text: String = self.line.iter().collect();
+        let back = self.line[self.cursor..].iter().map(|&c| char_width(c)).sum();
+        (text, back)
+    }
+}
+
+/// 从 `before` 之前向前查找包含 `query` 的历史记录
+fn find_back(history: &[String], query: &str, before: usize) -> Option<usize> {
+    if query.is_empty() {
+        return None;
+    }
+    history[..before.min(history.len())].iter().rposition(|h| h.contains(query))
+}
+
+/// 字符在终端中占用的列数（中日韩全角字符占两列）
+pub fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115F | 0x2E80..=0x303E | 0x3041..=0x33FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF | 0xAC00..=0xD7A3 | 0xF900..=0xFAFF | 0xFE30..=0xFE4F | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6 | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
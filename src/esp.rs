@@ -0,0 +1,255 @@
+//! ESP8266/ESP32 AT 固件配网：扫描 Wi-Fi（AT+CWLAP 解析为表格）、连接热点、建立 TCP/UDP 连接并进入透传模式，
+//! 也可用 wizard 按提示一步步完成
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+
+use crate::at::Modem;
+use crate::events;
+use crate::parse_duration;
+use crate::signal::Rs485;
+
+/// esp 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct EspArgs {
+    #[command(subcommand)]
+    pub command: EspCommand,
+
+    /// 等待每条 AT 命令结果的超时时间（扫描和连接热点通常需要数秒）
+    #[arg(long, default_value = "20s", value_parser = parse_duration, global = true)]
+    pub timeout: Duration,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum EspCommand {
+    /// 扫描附近的 Wi-Fi 热点
+    Scan,
+    /// 以 Station 模式连接热点，成功后显示获得的 IP
+    Join {
+        #[arg(long)]
+        ssid: String,
+        /// 密码（开放网络不填）
+        #[arg(long, default_value = "")]
+        password: String,
+    },
+    /// 建立 TCP/UDP 连接（需先连接热点）
+    Connect {
+        /// 服务器地址和端口，如 192.168.1.10:8080 或 example.com:80
+        #[arg(long, value_name = "HOST:PORT", value_parser = parse_server)]
+        server: (String, u16),
+        /// 使用 UDP
+        #[arg(long)]
+        udp: bool,
+        /// 连接后进入透传模式，之后串口收发的数据直接转发到服务器
+        #[arg(long)]
+        transparent: bool,
+    },
+    /// 交互式向导：扫描、选择热点、输入密码、连接热点，再可选地建立连接并进入透传模式
+    Wizard,
+}
+
+fn parse_server(s: &str) -> std::result::Result<(String, u16), String> {
+    match s.rsplit_once(':').map(|(host, port)| (host, port.parse::<u16>())) {
+        Some((host, Ok(port))) if !host.is_empty() => Ok((host.to_string(), port)),
+        _ => Err(format!("无效的服务器 '{}'，应为 地址:端口", s)),
+    }
+}
+
+/// 一个扫描到的热点
+struct AccessPoint {
+    ssid: String,
+    rssi: i32,
+    security: &'static str,
+    mac: String,
+    channel: String,
+}
+
+/// 执行 esp 子命令
+pub fn run_esp(port: &mut Box<dyn SerialPort>, opts: &EspArgs, rs485: Option<Rs485>) -> Result<()> {
+    port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+    let mut modem = Modem::new(rs485, opts.timeout);
+    modem.command(port, "AT").context("模块无应答，请确认是 ESP-AT 固件且串口波特率正确（常见 115200）")?;
+    modem.command(port, "ATE0")?;
+    match &opts.command {
+        EspCommand::Scan => {
+            print_access_points(&scan(port, &mut modem)?);
+        }
+        EspCommand::Join { ssid, password } => join(port, &mut modem, ssid, password)?,
+        EspCommand::Connect { server: (host, remote), udp, transparent } => {
+            connect(port, &mut modem, host, *remote, *udp, *transparent)?;
+        }
+        EspCommand::Wizard => wizard(port, &mut modem)?,
+    }
+    Ok(())
+}
+
+fn scan(port: &mut Box<dyn SerialPort>, modem: &mut Modem) -> Result<Vec<AccessPoint>> {
+    // CWLAP 只能在 Station 或 Station+AP 模式下执行
+    modem.command(port, "AT+CWMODE=1")?;
+    events::status("正在扫描 Wi-Fi 热点...");
+    let mut access_points: Vec<AccessPoint> = modem.command(port, "AT+CWLAP")?.iter().filter_map(|line| parse_cwlap(line)).collect();
+    access_points.sort_by_key(|ap| -ap.rssi);
+    Ok(access_points)
+}
+
+/// 解析 `+CWLAP:(<加密方式>,"<SSID>",<RSSI>,"<MAC>",<信道>,...)`
+fn parse_cwlap(line: &str) -> Option<AccessPoint> {
+    let fields = split_fields(line.strip_prefix("+CWLAP:")?.trim().strip_prefix('(')?.strip_suffix(')')?);
+    let security = match fields.first()?.as_str() {
+        "0" => "开放",
+        "1" => "WEP",
+        "2" => "WPA-PSK",
+        "3" => "WPA2-PSK",
+        "4" => "WPA/WPA2-PSK",
+        "5" => "WPA2-企业",
+        "6" => "WPA3-PSK",
+        "7" => "WPA2/WPA3-PSK",
+        "8" => "WAPI-PSK",
+        _ => "未知",
+    };
+    Some(AccessPoint {
+        ssid: fields.get(1)?.clone(),
+        rssi: fields.get(2)?.parse().ok()?,
+        security,
+        mac: fields.get(3).cloned().unwrap_or_default(),
+        channel: fields.get(4).cloned().unwrap_or_default(),
+    })
+}
+
+/// 按逗号拆分参数，引号内的逗号不拆分，去掉引号和转义符
+fn split_fields(text: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let (mut quoted, mut escaped) = (false, false);
+    for c in text.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => {
+                escaped = true;
+                continue;
+            }
+            '"' => {
+                quoted = !quoted;
+                continue;
+            }
+            ',' if !quoted => {
+                fields.push(String::new());
+                continue;
+            }
+            _ => {}
+        }
+        if let Some(field) = fields.last_mut() {
+            field.push(c);
+        }
+    }
+    fields
+}
+
+fn print_access_points(access_points: &[AccessPoint]) {
+    if access_points.is_empty() {
+        events::status("没有扫描到热点");
+        return;
+    }
+    events::status(&format!("{:>3}  {:>5}  {:>4}  {:<14}  {:<17}  SSID", "#", "RSSI", "信道", "加密", "MAC"));
+    for (i, ap) in access_points.iter().enumerate() {
+        events::status(&format!(
+            "{:>3}  {:>5}  {:>6}  {:<16}  {:<17}  {}",
+            i + 1,
+            ap.rssi,
+            ap.channel,
+            ap.security,
+            ap.mac,
+            if ap.ssid.is_empty() { "（隐藏）" } else { &ap.ssid }
+        ));
+    }
+}
+
+/// AT 命令字符串参数的转义：引号、逗号和反斜杠前加反斜杠
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        if matches!(c, '"' | ',' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn join(port: &mut Box<dyn SerialPort>, modem: &mut Modem, ssid: &str, password: &str) -> Result<()> {
+    modem.command(port, "AT+CWMODE=1")?;
+    events::status(&format!("正在连接热点 {}...", ssid));
+    let reply = modem.execute(port, &format!("AT+CWJAP={},{}", quote(ssid), quote(password)))?;
+    if let Some(error) = reply.error {
+        // 失败时先返回 +CWJAP:<错误码>，再返回 FAIL 或 ERROR
+        let code = reply.lines.iter().find_map(|line| line.strip_prefix("+CWJAP:")).map(str::trim);
+        let reason = match code {
+            Some("1") => "连接超时",
+            Some("2") => "密码错误",
+            Some("3") => "找不到该热点",
+            Some("4") => "连接失败",
+            _ => &error,
+        };
+        bail!("连接热点 {} 失败：{}", ssid, reason);
+    }
+    let ip = modem
+        .command(port, "AT+CIFSR")?
+        .iter()
+        .find_map(|line| line.strip_prefix("+CIFSR:STAIP,").map(|ip| ip.trim_matches('"').to_string()));
+    events::status(&format!("已连接热点 {}，IP {}", ssid, ip.as_deref().unwrap_or("未知")));
+    Ok(())
+}
+
+fn connect(port: &mut Box<dyn SerialPort>, modem: &mut Modem, host: &str, remote: u16, udp: bool, transparent: bool) -> Result<()> {
+    let kind = if udp { "UDP" } else { "TCP" };
+    // 透传只支持单连接模式
+    modem.command(port, "AT+CIPMUX=0")?;
+    let reply = modem.execute(port, &format!("AT+CIPSTART=\"{}\",{},{}", kind, quote(host), remote))?;
+    if let Some(error) = reply.error {
+        match reply.lines.iter().any(|line| line == "ALREADY CONNECTED") {
+            true => bail!("已经存在连接，请先执行 AT+CIPCLOSE"),
+            false => bail!("连接 {} {}:{} 失败：{}（请确认已连接热点、服务器可达）", kind, host, remote, error),
+        }
+    }
+    events::status(&format!("已建立 {} 连接 {}:{}", kind, host, remote));
+    if transparent {
+        modem.command(port, "AT+CIPMODE=1")?;
+        modem.prompt(port, "AT+CIPSEND")?;
+        events::status("已进入透传模式，可用 terminal 收发数据；退出透传请单独发送 +++（前后各停顿 1 秒以上，不带换行）");
+    }
+    Ok(())
+}
+
+/// 从标准输入读取一行回答
+fn ask(question: &str) -> Result<String> {
+    print!("{}", question);
+    io::stdout().flush().context("刷新标准输出失败")?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer).context("读取标准输入失败")?;
+    Ok(answer.trim().to_string())
+}
+
+fn wizard(port: &mut Box<dyn SerialPort>, modem: &mut Modem) -> Result<()> {
+    let access_points = scan(port, modem)?;
+    print_access_points(&access_points);
+    let ssid = match ask("选择热点序号（或直接输入 SSID）：")? {
+        answer if answer.is_empty() => bail!("未选择热点"),
+        answer => match answer.parse::<usize>().ok().and_then(|i| access_points.get(i.checked_sub(1)?)) {
+            Some(ap) => ap.ssid.clone(),
+            None => answer,
+        },
+    };
+    let password = ask("密码（开放网络直接回车）：")?;
+    join(port, modem, &ssid, &password)?;
+
+    let server = ask("服务器地址和端口（如 192.168.1.10:8080，直接回车跳过）：")?;
+    if server.is_empty() {
+        return Ok(());
+    }
+    let (host, remote) = parse_server(&server).map_err(|e| anyhow::anyhow!(e))?;
+    let udp = ask("协议 TCP/UDP（默认 TCP）：")?.eq_ignore_ascii_case("udp");
+    let transparent = ask("进入透传模式？[y/N]：")?.eq_ignore_ascii_case("y");
+    connect(port, modem, &host, remote, udp, transparent)
+}
@@ -0,0 +1,349 @@
+//! Espressif 芯片 ROM 下载模式（esptool 使用的串口协议）：ESP8266、ESP32 及 S2/S3/C 系列
+//!
+//! 每个请求和应答都是一个 SLIP 帧。请求为 `00 命令 长度(2) 校验(4) 数据`，
+//! 应答为 `01 命令 长度(2) 值(4) 数据`，数据末尾是状态字节（0 为成功，其后为错误码）。
+//! 多字节字段均为小端。可以先把 esptool 的 stub 程序加载到 RAM 运行，再由 stub 处理后续命令。
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::framing::{slip_decode, slip_encode, SLIP_END};
+use crate::xmodem::Link;
+
+const FLASH_BEGIN: u8 = 0x02;
+const FLASH_DATA: u8 = 0x03;
+const FLASH_END: u8 = 0x04;
+const MEM_BEGIN: u8 = 0x05;
+const MEM_END: u8 = 0x06;
+const MEM_DATA: u8 = 0x07;
+const SYNC: u8 = 0x08;
+const READ_REG: u8 = 0x0A;
+const SPI_SET_PARAMS: u8 = 0x0B;
+const SPI_ATTACH: u8 = 0x0D;
+const SPI_FLASH_MD5: u8 = 0x13;
+
+/// 用于识别芯片型号的寄存器（ROM 中的固定值）
+const CHIP_DETECT_MAGIC: u32 = 0x4000_1000;
+/// 数据块校验的初值
+const CHECKSUM_SEED: u8 = 0xEF;
+/// ROM 写 flash 的块大小
+const ROM_BLOCK: usize = 0x400;
+/// stub 写 flash 的块大小
+const STUB_BLOCK: usize = 0x4000;
+/// 写 RAM（加载 stub）的块大小
+const RAM_BLOCK: usize = 0x1800;
+const SECTOR: usize = 0x1000;
+/// 设置给 ROM 的 flash 容量（只用于 ROM 检查地址范围）
+const FLASH_SIZE: u32 = 16 * 1024 * 1024;
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+/// 擦除 flash 每 MB 的最长时长
+const ERASE_PER_MB: Duration = Duration::from_secs(30);
+/// 计算 MD5 每 MB 的最长时长
+const MD5_PER_MB: Duration = Duration::from_secs(8);
+
+/// 芯片型号
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Chip {
+    Esp8266,
+    Esp32,
+    Esp32S2,
+    Esp32S3,
+    Esp32C2,
+    Esp32C3,
+    Esp32C6,
+    Esp32H2,
+    /// 未知的识别值，按 ESP32 之后的系列处理
+    Unknown(u32),
+}
+
+impl Chip {
+    fn from_magic(magic: u32) -> Chip {
+        match magic {
+            0xFFF0_C101 => Chip::Esp8266,
+            0x00F0_1D83 => Chip::Esp32,
+            0x0000_07C6 => Chip::Esp32S2,
+            0x0000_0009 => Chip::Esp32S3,
+            0x6F51_306F | 0x7C41_A06F => Chip::Esp32C2,
+            0x6921_506F | 0x1B31_506F | 0x4881_606F | 0x4361_606F => Chip::Esp32C3,
+            0x2CE0_806F => Chip::Esp32C6,
+            0xD7B7_3E80 => Chip::Esp32H2,
+            magic => Chip::Unknown(magic),
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Chip::Esp8266 => "ESP8266".to_string(),
+            Chip::Esp32 => "ESP32".to_string(),
+            Chip::Esp32S2 => "ESP32-S2".to_string(),
+            Chip::Esp32S3 => "ESP32-S3".to_string(),
+            Chip::Esp32C2 => "ESP32-C2".to_string(),
+            Chip::Esp32C3 => "ESP32-C3".to_string(),
+            Chip::Esp32C6 => "ESP32-C6".to_string(),
+            Chip::Esp32H2 => "ESP32-H2".to_string(),
+            Chip::Unknown(magic) => format!("未知芯片（识别值 0x{:08X}）", magic),
+        }
+    }
+}
+
+/// esptool 的 stub 程序（stub_flasher_*.json：代码段、数据段和入口地址）
+pub struct Stub {
+    entry: u32,
+    segments: Vec<(u32, Vec<u8>)>,
+}
+
+impl Stub {
+    pub fn load(path: &Path) -> Result<Stub> {
+        let text = fs::read_to_string(path).with_context(|| format!("读取 stub 文件 {} 失败", path.display()))?;
+        let context = || format!("stub 文件 {} 格式有误，应为 esptool 的 stub_flasher_*.json", path.display());
+        let number = |key: &str| -> Option<u32> {
+            let pattern = Regex::new(&format!(r#""{}"\s*:\s*(\d+)"#, key)).ok()?;
+            pattern.captures(&text)?[1].parse().ok()
+        };
+        let string = |key: &str| -> Option<String> {
+            let pattern = Regex::new(&format!(r#""{}"\s*:\s*"([^"]*)""#, key)).ok()?;
+            Some(pattern.captures(&text)?[1].to_string())
+        };
+        let entry = number("entry").with_context(context)?;
+        let mut segments = Vec::new();
+        for name in ["text", "data"] {
+            if let (Some(start), Some(content)) = (number(&format!("{}_start", name)), string(name)) {
+                segments.push((start, base64_decode(&content).with_context(context)?));
+            }
+        }
+        if segments.is_empty() {
+            bail!(context());
+        }
+        Ok(Stub { entry, segments })
+    }
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace() && *c != b'=') {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = buffer << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(CHECKSUM_SEED, |x, &b| x ^ b) as u32
+}
+
+fn words(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+/// 与 ROM 下载模式（或 stub）的连接
+pub struct Loader<'l, 'p> {
+    link: &'l mut Link<'p>,
+    pub chip: Chip,
+    stub: bool,
+}
+
+impl<'l, 'p> Loader<'l, 'p> {
+    /// 发送 SYNC 直到 ROM 应答，再读取识别寄存器确定芯片型号
+    pub fn connect(link: &'l mut Link<'p>) -> Result<Self> {
+        let mut loader = Loader { link, chip: Chip::Unknown(0), stub: false };
+        let mut sync = vec![0x07, 0x07, 0x12, 0x20];
+        sync.extend([0x55; 32]);
+        let mut synced = false;
+        for _ in 0..10 {
+            if loader.request(SYNC, &sync, 0, Duration::from_millis(100)).is_ok() {
+                synced = true;
+                break;
+            }
+        }
+        if !synced {
+            bail!("芯片没有应答同步命令，请确认已进入下载模式（复位时 GPIO0 为低电平，可用 --reset-seq esp32 对应的自动下载电路）");
+        }
+        // ROM 对一次 SYNC 会回复多个应答，丢弃多余的
+        loader.link.purge()?;
+        loader.chip = Chip::from_magic(loader.read_reg(CHIP_DETECT_MAGIC)?);
+        Ok(loader)
+    }
+
+    /// 读取一个 SLIP 帧，超时返回 None
+    fn read_frame(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>> {
+        let deadline = Instant::now() + timeout;
+        let mut frame = Vec::new();
+        let mut started = false;
+        loop {
+            let Some(byte) = self.link.read_byte(deadline.saturating_duration_since(Instant::now()))? else {
+                return Ok(None);
+            };
+            match (byte, started) {
+                (SLIP_END, false) => started = true,
+                (SLIP_END, true) if frame.is_empty() => {}
+                (SLIP_END, true) => match slip_decode(&frame) {
+                    Some(decoded) => return Ok(Some(decoded)),
+                    // 转义无效：开头的 END 是启动信息中的噪声，把这个 END 当作帧开始
+                    None => frame.clear(),
+                },
+                (_, true) => frame.push(byte),
+                // 帧外的数据（如 ROM 的启动信息）
+                (_, false) => {}
+            }
+        }
+    }
+
+    /// 发送请求并等待对应的应答，返回应答中的值和数据
+    fn request(&mut self, command: u8, data: &[u8], checksum: u32, timeout: Duration) -> Result<(u32, Vec<u8>)> {
+        let mut packet = vec![0x00, command];
+        packet.extend((data.len() as u16).to_le_bytes());
+        packet.extend(checksum.to_le_bytes());
+        packet.extend_from_slice(data);
+        self.link.send(&slip_encode(&packet))?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            let Some(frame) = self.read_frame(deadline.saturating_duration_since(Instant::now()))? else {
+                bail!("等待命令 {:02X} 的应答超时", command);
+            };
+            if frame.len() >= 8 && frame[0] == 0x01 && frame[1] == command {
+                let value = u32::from_le_bytes([frame[4], frame[5], frame[6], frame[7]]);
+                return Ok((value, frame[8..].to_vec()));
+            }
+        }
+    }
+
+    /// 发送命令并检查状态：应答数据中前 `payload` 字节为结果，其后为状态和错误码
+    fn command(&mut self, command: u8, data: &[u8], checksum: u32, payload: usize, timeout: Duration) -> Result<(u32, Vec<u8>)> {
+        let (value, mut body) = self.request(command, data, checksum, timeout)?;
+        match body.get(payload..payload + 2) {
+            Some([0, _]) => {
+                body.truncate(payload);
+                Ok((value, body))
+            }
+            Some([_, error]) => bail!("命令 {:02X} 失败，错误码 0x{:02X}", command, error),
+            _ => bail!("命令 {:02X} 的应答不完整", command),
+        }
+    }
+
+    pub fn read_reg(&mut self, address: u32) -> Result<u32> {
+        Ok(self.command(READ_REG, &words(&[address]), 0, 0, TIMEOUT)?.0)
+    }
+
+    /// 把 stub 写入 RAM 并运行，之后的命令由 stub 处理
+    pub fn run_stub(&mut self, stub: &Stub) -> Result<()> {
+        for (address, data) in &stub.segments {
+            let blocks = data.len().div_ceil(RAM_BLOCK);
+            self.command(MEM_BEGIN, &words(&[data.len() as u32, blocks as u32, RAM_BLOCK as u32, *address]), 0, 0, TIMEOUT)?;
+            for (seq, block) in data.chunks(RAM_BLOCK).enumerate() {
+                let mut packet = words(&[block.len() as u32, seq as u32, 0, 0]);
+                packet.extend_from_slice(block);
+                self.command(MEM_DATA, &packet, checksum(block), 0, TIMEOUT)?;
+            }
+        }
+        self.command(MEM_END, &words(&[0, stub.entry]), 0, 0, TIMEOUT)?;
+        // stub 启动后发送 "OHAI"
+        match self.read_frame(TIMEOUT)? {
+            Some(frame) if frame == b"OHAI" => {
+                self.stub = true;
+                Ok(())
+            }
+            _ => bail!("stub 没有启动（没有收到 OHAI），请确认 stub 文件与芯片型号匹配"),
+        }
+    }
+
+    /// 准备写 flash：连接 SPI flash 并设置容量（ESP8266 ROM 不需要）
+    pub fn attach(&mut self) -> Result<()> {
+        if self.chip == Chip::Esp8266 && !self.stub {
+            return Ok(());
+        }
+        let attach = if self.stub { words(&[0]) } else { words(&[0, 0]) };
+        self.command(SPI_ATTACH, &attach, 0, 0, TIMEOUT)?;
+        if self.chip != Chip::Esp8266 {
+            self.command(SPI_SET_PARAMS, &words(&[0, FLASH_SIZE, 0x10000, SECTOR as u32, 0x100, 0xFFFF]), 0, 0, TIMEOUT)?;
+        }
+        Ok(())
+    }
+
+    fn flash_begin(&mut self, erase: usize, blocks: usize, offset: u32, timeout: Duration) -> Result<()> {
+        let block = if self.stub { STUB_BLOCK } else { ROM_BLOCK };
+        let mut begin = vec![erase as u32, blocks as u32, block as u32, offset];
+        // ESP32-S2 及之后的 ROM 多一个“加密写入”参数
+        if !self.stub && !matches!(self.chip, Chip::Esp8266 | Chip::Esp32) {
+            begin.push(0);
+        }
+        self.command(FLASH_BEGIN, &words(&begin), 0, 0, timeout).map(|_| ())
+    }
+
+    /// 写入一段 flash（先擦除，再逐块写入，最后一块用 0xFF 补齐），以已写入的字节数回调
+    pub fn write(&mut self, offset: u32, data: &[u8], progress: &mut dyn FnMut(usize)) -> Result<()> {
+        let block = if self.stub { STUB_BLOCK } else { ROM_BLOCK };
+        let blocks = data.len().div_ceil(block);
+        let erase = match self.chip == Chip::Esp8266 && !self.stub {
+            true => esp8266_erase_size(offset as usize, data.len()),
+            false => data.len(),
+        };
+        let timeout = TIMEOUT + ERASE_PER_MB * (data.len().div_ceil(1 << 20) as u32);
+        self.flash_begin(erase, blocks, offset, timeout).context("擦除 flash 失败")?;
+        for (seq, chunk) in data.chunks(block).enumerate() {
+            let mut padded = chunk.to_vec();
+            padded.resize(block, 0xFF);
+            let mut packet = words(&[block as u32, seq as u32, 0, 0]);
+            packet.extend_from_slice(&padded);
+            self.command(FLASH_DATA, &packet, checksum(&padded), 0, TIMEOUT)
+                .with_context(|| format!("写入 0x{:X} 失败", offset as usize + seq * block))?;
+            progress(((seq + 1) * block).min(data.len()));
+        }
+        Ok(())
+    }
+
+    /// flash 中一段数据的 MD5；ESP8266 ROM 不支持，返回 None
+    pub fn md5(&mut self, offset: u32, len: usize) -> Result<Option<[u8; 16]>> {
+        if self.chip == Chip::Esp8266 && !self.stub {
+            return Ok(None);
+        }
+        let timeout = TIMEOUT + MD5_PER_MB * (len.div_ceil(1 << 20) as u32);
+        // ROM 返回 32 个十六进制字符，stub 返回 16 字节
+        let payload = if self.stub { 16 } else { 32 };
+        let (_, body) = self.command(SPI_FLASH_MD5, &words(&[offset, len as u32, 0, 0]), 0, payload, timeout)?;
+        let digest = match self.stub {
+            true => body,
+            false => (0..16)
+                .map(|i| std::str::from_utf8(&body[i * 2..i * 2 + 2]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()))
+                .collect::<Option<Vec<u8>>>()
+                .context("MD5 应答格式有误")?,
+        };
+        Ok(digest.try_into().ok())
+    }
+
+    /// 结束写 flash；`run` 时让 ROM 直接运行新程序，否则留在下载模式（之后用复位线重启）
+    pub fn finish(&mut self, run: bool) -> Result<()> {
+        // 与 esptool 相同：先发送长度为 0 的 FLASH_BEGIN，再发送 FLASH_END
+        self.flash_begin(0, 0, 0, TIMEOUT)?;
+        self.command(FLASH_END, &words(&[u32::from(!run)]), 0, 0, TIMEOUT).map(|_| ())
+    }
+}
+
+/// ESP8266 ROM 按擦除长度计算时有错误（把第一个 64KB 块内的扇区算了两次），按 esptool 的方法修正
+fn esp8266_erase_size(offset: usize, len: usize) -> usize {
+    const SECTORS_PER_BLOCK: usize = 16;
+    let sectors = len.div_ceil(SECTOR);
+    let first = offset / SECTOR;
+    let head = (SECTORS_PER_BLOCK - first % SECTORS_PER_BLOCK).min(sectors);
+    match sectors < 2 * head {
+        true => sectors.div_ceil(2) * SECTOR,
+        false => (sectors - head) * SECTOR,
+    }
+}
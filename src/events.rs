@@ -0,0 +1,124 @@
+//! 输出模式：普通文本或 JSON Lines 结构化事件
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::format_hex;
+
+/// 是否以 JSON Lines 输出（整个进程内只设置一次）
+static JSONL: AtomicBool = AtomicBool::new(false);
+
+/// 输出格式
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// 面向人阅读的文本
+    Text,
+    /// 每行一个 JSON 对象
+    Jsonl,
+}
+
+pub fn init(format: OutputFormat) {
+    JSONL.store(format == OutputFormat::Jsonl, Ordering::Relaxed);
+}
+
+pub fn jsonl() -> bool {
+    JSONL.load(Ordering::Relaxed)
+}
+
+/// 会被记录的事件
+pub enum Event<'a> {
+    /// 端口已打开
+    Open { port: &'a str, baud: u32 },
+    /// 端口已关闭
+    Close { port: &'a str },
+    /// 收到的数据
+    Rx(&'a [u8]),
+    /// 发出的数据
+    Tx(&'a [u8]),
+    /// 输入状态线电平变化
+    Signal { line: &'a str, level: bool },
+    /// 传输进度：当前位置、总长度和每秒字节数
+    Progress { done: u64, total: Option<u64>, rate: u64 },
+    /// 提示信息
+    Status(&'a str),
+    /// 错误
+    Error(&'a str),
+}
+
+/// JSON 模式下输出一个事件；文本模式下不输出
+pub fn emit(event: Event) {
+    if jsonl() {
+        println!("{}", json(event));
+    }
+}
+
+/// 把事件编码为一行 JSON（不含换行）
+pub fn json(event: Event) -> String {
+    let mut line = format!(
+        "{{\"ts\":\"{}\"",
+        jiff::Zoned::now().strftime("%Y-%m-%dT%H:%M:%S%.3f%:z")
+    );
+    match event {
+        Event::Open { port, baud } => {
+            write!(line, ",\"event\":\"open\",\"port\":{},\"baud\":{}", quote(port), baud).unwrap();
+        }
+        Event::Close { port } => {
+            write!(line, ",\"event\":\"close\",\"port\":{}", quote(port)).unwrap();
+        }
+        Event::Rx(data) | Event::Tx(data) => {
+            let dir = if matches!(event, Event::Rx(_)) { "rx" } else { "tx" };
+            write!(
+                line,
+                ",\"event\":\"data\",\"dir\":\"{}\",\"len\":{},\"hex\":{},\"text\":{}",
+                dir,
+                data.len(),
+                quote(&format_hex(data)),
+                quote(&String::from_utf8_lossy(data))
+            )
+            .unwrap();
+        }
+        Event::Signal { line: name, level } => {
+            write!(line, ",\"event\":\"signal\",\"line\":{},\"level\":{}", quote(name), level).unwrap();
+        }
+        Event::Progress { done, total, rate } => {
+            let total = total.map_or("null".to_string(), |total| total.to_string());
+            write!(line, ",\"event\":\"progress\",\"done\":{},\"total\":{},\"rate\":{}", done, total, rate).unwrap();
+        }
+        Event::Status(message) => {
+            write!(line, ",\"event\":\"status\",\"message\":{}", quote(message)).unwrap();
+        }
+        Event::Error(message) => {
+            write!(line, ",\"event\":\"error\",\"message\":{}", quote(message)).unwrap();
+        }
+    }
+    line.push('}');
+    line
+}
+
+/// 输出提示信息：文本模式直接打印，JSON 模式输出 status 事件
+pub fn status(message: &str) {
+    if jsonl() {
+        emit(Event::Status(message));
+    } else {
+        println!("{}", message);
+    }
+}
+
+/// 转为带引号的 JSON 字符串
+pub fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,519 @@
+//! Firmata 客户端：连接烧录了 StandardFirmata 的 Arduino，查询固件和引脚能力，设置引脚模式，
+//! 读取模拟量和数字输入，写数字输出和 PWM，持续显示上报的数值
+//!
+//! Firmata 按 MIDI 风格编码：命令字节最高位为 1，数据字节为 7 位；14 位数值拆为低 7 位和高 7 位。
+//! 扩展命令为 `F0 命令 数据... F7`（SysEx）。StandardFirmata 默认波特率为 57600。
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::events::{self, Event};
+use crate::parse_duration;
+use crate::signal::Rs485;
+use crate::terminal;
+
+/// firmata 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct FirmataArgs {
+    #[command(subcommand)]
+    pub command: FirmataCommand,
+
+    /// 等待板子应答的超时时间（打开串口会使 Arduino 复位，启动需要 1~2 秒）
+    #[arg(long, default_value = "3s", value_parser = parse_duration, global = true)]
+    pub timeout: Duration,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum FirmataCommand {
+    /// 显示固件版本和各引脚支持的模式
+    Info,
+    /// 设置引脚模式，如 `firmata mode 13 output`、`firmata mode A0 analog`
+    Mode {
+        #[arg(value_parser = parse_pin)]
+        pin: Pin,
+        #[arg(value_enum)]
+        mode: PinMode,
+    },
+    /// 写数字输出（自动设为输出模式），如 `firmata write 13 high`
+    Write {
+        #[arg(value_parser = parse_pin)]
+        pin: Pin,
+        #[arg(value_parser = parse_level, action = clap::ArgAction::Set)]
+        level: bool,
+    },
+    /// 写 PWM 占空比（自动设为 PWM 模式，Arduino Uno 为 0~255），如 `firmata pwm 9 128`
+    Pwm {
+        #[arg(value_parser = parse_pin)]
+        pin: Pin,
+        value: u16,
+    },
+    /// 读取一次引脚的值：A0 等为模拟量（自动设为模拟输入），数字引脚读取当前电平
+    Read {
+        #[arg(value_parser = parse_pin)]
+        pin: Pin,
+    },
+    /// 持续显示模拟量和数字输入的上报值（数字输入只在变化时显示）
+    Watch {
+        /// 上报的模拟通道，如 A0，可重复
+        #[arg(long, value_name = "PIN", value_parser = parse_pin)]
+        analog: Vec<Pin>,
+
+        /// 上报的数字输入引脚（自动设为输入模式），可重复
+        #[arg(long, value_name = "PIN", value_parser = parse_pin)]
+        digital: Vec<Pin>,
+
+        /// 启用内部上拉（数字输入未接时为高电平）
+        #[arg(long)]
+        pullup: bool,
+
+        /// 模拟量采样间隔
+        #[arg(long, default_value = "100ms", value_parser = parse_duration)]
+        interval: Duration,
+
+        /// 运行指定时长后退出（如 30s、5m）
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        exit_after: Option<Duration>,
+    },
+}
+
+/// 引脚：数字引脚号，或模拟通道 A0、A1...
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pin {
+    Digital(u8),
+    Analog(u8),
+}
+
+fn parse_pin(s: &str) -> std::result::Result<Pin, String> {
+    let pin = match s.strip_prefix(['A', 'a']) {
+        Some(channel) => channel.parse().ok().filter(|&c| c < 16).map(Pin::Analog),
+        None => s.parse().ok().filter(|&p| p < 128).map(Pin::Digital),
+    };
+    pin.ok_or_else(|| format!("无效的引脚 '{}'，应为数字引脚号（如 13）或模拟通道（如 A0）", s))
+}
+
+fn parse_level(s: &str) -> std::result::Result<bool, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "1" | "high" | "on" => Ok(true),
+        "0" | "low" | "off" => Ok(false),
+        _ => Err(format!("无效的电平 '{}'，应为 high/low 或 1/0", s)),
+    }
+}
+
+/// 引脚模式
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum PinMode {
+    Input,
+    Output,
+    Analog,
+    Pwm,
+    Servo,
+    Pullup,
+}
+
+impl PinMode {
+    fn code(self) -> u8 {
+        match self {
+            PinMode::Input => 0x00,
+            PinMode::Output => 0x01,
+            PinMode::Analog => 0x02,
+            PinMode::Pwm => 0x03,
+            PinMode::Servo => 0x04,
+            PinMode::Pullup => 0x0B,
+        }
+    }
+}
+
+/// 能力报告中的模式名
+fn mode_name(code: u8) -> String {
+    let name = match code {
+        0x00 => "输入",
+        0x01 => "输出",
+        0x02 => "模拟",
+        0x03 => "PWM",
+        0x04 => "舵机",
+        0x05 => "移位",
+        0x06 => "I2C",
+        0x07 => "OneWire",
+        0x08 => "步进电机",
+        0x09 => "编码器",
+        0x0A => "串口",
+        0x0B => "上拉输入",
+        _ => return format!("模式 {}", code),
+    };
+    name.to_string()
+}
+
+const ANALOG_MESSAGE: u8 = 0xE0;
+const DIGITAL_MESSAGE: u8 = 0x90;
+const REPORT_ANALOG: u8 = 0xC0;
+const REPORT_DIGITAL: u8 = 0xD0;
+const SET_PIN_MODE: u8 = 0xF4;
+const SET_DIGITAL_PIN_VALUE: u8 = 0xF5;
+const REPORT_VERSION: u8 = 0xF9;
+const START_SYSEX: u8 = 0xF0;
+const END_SYSEX: u8 = 0xF7;
+
+const ANALOG_MAPPING_QUERY: u8 = 0x69;
+const ANALOG_MAPPING_RESPONSE: u8 = 0x6A;
+const CAPABILITY_QUERY: u8 = 0x6B;
+const CAPABILITY_RESPONSE: u8 = 0x6C;
+const EXTENDED_ANALOG: u8 = 0x6F;
+const STRING_DATA: u8 = 0x71;
+const REPORT_FIRMWARE: u8 = 0x79;
+const SAMPLING_INTERVAL: u8 = 0x7A;
+
+/// 收到的消息
+enum Message {
+    Version(u8, u8),
+    Firmware { major: u8, minor: u8, name: String },
+    Analog { channel: u8, value: u16 },
+    /// 一个端口（8 个引脚）的电平
+    Digital { port: u8, value: u8 },
+    /// 其他 SysEx 消息：命令和数据字节
+    Sysex(u8, Vec<u8>),
+}
+
+/// 把 7 位数据字节两两合成（低 7 位在前）
+fn join_7bit(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2).map(|pair| (pair[0] & 0x7F) as u16 | ((pair[1] & 0x7F) as u16) << 7).collect()
+}
+
+/// 14 位数值拆为两个 7 位数据字节
+fn split_7bit(value: u16) -> [u8; 2] {
+    [(value & 0x7F) as u8, (value >> 7 & 0x7F) as u8]
+}
+
+/// 按字节解析收到的数据
+#[derive(Default)]
+struct Parser {
+    command: Option<u8>,
+    data: Vec<u8>,
+    sysex: bool,
+}
+
+impl Parser {
+    fn push(&mut self, byte: u8) -> Option<Message> {
+        if self.sysex {
+            if byte != END_SYSEX {
+                self.data.push(byte);
+                return None;
+            }
+            self.sysex = false;
+            let data = std::mem::take(&mut self.data);
+            let (&command, data) = data.split_first()?;
+            return Some(match command {
+                REPORT_FIRMWARE if data.len() >= 2 => {
+                    let name: String = join_7bit(&data[2..]).into_iter().filter_map(|c| char::from_u32(c as u32)).collect();
+                    Message::Firmware { major: data[0], minor: data[1], name }
+                }
+                _ => Message::Sysex(command, data.to_vec()),
+            });
+        }
+        if byte == START_SYSEX {
+            self.sysex = true;
+            self.data.clear();
+            return None;
+        }
+        if byte & 0x80 != 0 {
+            self.command = Some(byte);
+            self.data.clear();
+            return None;
+        }
+        let command = self.command?;
+        self.data.push(byte);
+        let needed = match command & 0xF0 {
+            ANALOG_MESSAGE | DIGITAL_MESSAGE => 2,
+            _ if command == REPORT_VERSION => 2,
+            _ => {
+                self.command = None;
+                return None;
+            }
+        };
+        if self.data.len() < needed {
+            return None;
+        }
+        self.command = None;
+        let data = std::mem::take(&mut self.data);
+        let value = join_7bit(&data)[0];
+        Some(match command & 0xF0 {
+            ANALOG_MESSAGE => Message::Analog { channel: command & 0x0F, value },
+            DIGITAL_MESSAGE => Message::Digital { port: command & 0x0F, value: value as u8 },
+            _ => Message::Version(data[0], data[1]),
+        })
+    }
+}
+
+/// 与板子的会话
+struct Board {
+    rs485: Option<Rs485>,
+    timeout: Duration,
+    parser: Parser,
+    received: VecDeque<Message>,
+    /// 模拟通道对应的数字引脚号（ANALOG_MAPPING_RESPONSE），首次用到时查询
+    analog_pins: Option<Vec<(u8, u8)>>,
+}
+
+impl Board {
+    fn send(&mut self, port: &mut Box<dyn SerialPort>, bytes: &[u8]) -> Result<()> {
+        terminal::transmit(port, self.rs485, bytes)
+    }
+
+    fn sysex(&mut self, port: &mut Box<dyn SerialPort>, command: u8, data: &[u8]) -> Result<()> {
+        self.send(port, &[&[START_SYSEX, command][..], data, &[END_SYSEX]].concat())
+    }
+
+    /// 读取串口并解析出完整的消息，超时返回空列表
+    fn poll(&mut self, port: &mut Box<dyn SerialPort>) -> Result<Vec<Message>> {
+        let mut buffer = [0u8; 256];
+        let mut messages: Vec<Message> = self.received.drain(..).collect();
+        match port.read(&mut buffer) {
+            Ok(n) => {
+                events::emit(Event::Rx(&buffer[..n]));
+                messages.extend(buffer[..n].iter().filter_map(|&b| self.parser.push(b)));
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e).context("读取串口失败"),
+        }
+        // 固件的调试输出（STRING_DATA）随时可能出现
+        messages.retain(|message| match message {
+            Message::Sysex(STRING_DATA, data) => {
+                let text: String = join_7bit(data).into_iter().filter_map(|c| char::from_u32(c as u32)).collect();
+                events::status(&format!("[板子] {}", text));
+                false
+            }
+            _ => true,
+        });
+        Ok(messages)
+    }
+
+    /// 等待第一条满足条件的消息，其间收到的其他消息留给后续读取
+    fn wait<T>(&mut self, port: &mut Box<dyn SerialPort>, what: &str, mut matches: impl FnMut(&Message) -> Option<T>) -> Result<T> {
+        let deadline = Instant::now() + self.timeout;
+        let mut skipped = VecDeque::new();
+        while Instant::now() < deadline {
+            let mut messages = self.poll(port)?.into_iter();
+            while let Some(message) = messages.next() {
+                if let Some(value) = matches(&message) {
+                    skipped.extend(messages);
+                    self.received = skipped;
+                    return Ok(value);
+                }
+                skipped.push_back(message);
+            }
+        }
+        self.received = skipped;
+        bail!("{:?} 内没有收到{}", self.timeout, what)
+    }
+
+    /// 各模拟通道及其数字引脚号
+    fn analog_pins(&mut self, port: &mut Box<dyn SerialPort>) -> Result<&[(u8, u8)]> {
+        if self.analog_pins.is_none() {
+            self.sysex(port, ANALOG_MAPPING_QUERY, &[])?;
+            let mapping = self.wait(port, "模拟通道映射", |message| match message {
+                Message::Sysex(ANALOG_MAPPING_RESPONSE, data) => Some(data.clone()),
+                _ => None,
+            })?;
+            // 每个数字引脚一个字节，127 表示不是模拟引脚
+            self.analog_pins = Some(
+                mapping.iter().enumerate().filter(|(_, &c)| c != 0x7F).map(|(pin, &c)| (c, pin as u8)).collect(),
+            );
+        }
+        Ok(self.analog_pins.as_deref().unwrap_or_default())
+    }
+
+    /// 引脚的数字引脚号（模拟通道按映射换算）
+    fn resolve(&mut self, port: &mut Box<dyn SerialPort>, pin: Pin) -> Result<u8> {
+        let channel = match pin {
+            Pin::Digital(pin) => return Ok(pin),
+            Pin::Analog(channel) => channel,
+        };
+        match self.analog_pins(port)?.iter().find(|(c, _)| *c == channel) {
+            Some(&(_, pin)) => Ok(pin),
+            None => bail!("板子没有模拟通道 A{}", channel),
+        }
+    }
+
+    fn set_mode(&mut self, port: &mut Box<dyn SerialPort>, pin: u8, mode: PinMode) -> Result<()> {
+        self.send(port, &[SET_PIN_MODE, pin, mode.code()])
+    }
+}
+
+/// 连接板子：等待（或查询）版本报告和固件名
+fn connect(port: &mut Box<dyn SerialPort>, rs485: Option<Rs485>, timeout: Duration) -> Result<Board> {
+    let mut board = Board { rs485, timeout, parser: Parser::default(), received: VecDeque::new(), analog_pins: None };
+    // 打开串口使板子复位时，启动后会主动发送版本和固件名；没有复位时需要查询
+    board.send(port, &[REPORT_VERSION])?;
+    let version = board
+        .wait(port, " Firmata 版本报告", |message| match message {
+            Message::Version(major, minor) => Some((*major, *minor)),
+            _ => None,
+        })
+        .context("请确认板子已烧录 StandardFirmata 且波特率一致（默认 57600）")?;
+    board.sysex(port, REPORT_FIRMWARE, &[])?;
+    let firmware = board.wait(port, "固件名", |message| match message {
+        Message::Firmware { major, minor, name } => Some(format!("{} {}.{}", name, major, minor)),
+        _ => None,
+    })?;
+    events::status(&format!("已连接 {}（Firmata 协议 {}.{}）", firmware, version.0, version.1));
+    Ok(board)
+}
+
+/// 执行 firmata 子命令
+pub fn run_firmata(port: &mut Box<dyn SerialPort>, opts: &FirmataArgs, rs485: Option<Rs485>) -> Result<()> {
+    port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+    let mut board = connect(port, rs485, opts.timeout)?;
+    match &opts.command {
+        FirmataCommand::Info => info(port, &mut board)?,
+        FirmataCommand::Mode { pin, mode } => {
+            let number = board.resolve(port, *pin)?;
+            board.set_mode(port, number, *mode)?;
+            events::status(&format!("引脚 {} 已设为{}模式", number, mode_name(mode.code())));
+        }
+        FirmataCommand::Write { pin, level } => {
+            let number = board.resolve(port, *pin)?;
+            board.set_mode(port, number, PinMode::Output)?;
+            board.send(port, &[SET_DIGITAL_PIN_VALUE, number, *level as u8])?;
+            events::status(&format!("引脚 {} 输出{}电平", number, if *level { "高" } else { "低" }));
+        }
+        FirmataCommand::Pwm { pin, value } => {
+            let number = board.resolve(port, *pin)?;
+            if *value > 0x3FFF {
+                bail!("PWM 值 {} 超出范围（0~16383）", value);
+            }
+            board.set_mode(port, number, PinMode::Pwm)?;
+            match number < 16 {
+                true => board.send(port, &[&[ANALOG_MESSAGE | number][..], &split_7bit(*value)].concat())?,
+                false => board.sysex(port, EXTENDED_ANALOG, &[&[number][..], &split_7bit(*value)].concat())?,
+            }
+            events::status(&format!("引脚 {} PWM 值 {}", number, value));
+        }
+        FirmataCommand::Read { pin } => match *pin {
+            Pin::Analog(channel) => {
+                let number = board.resolve(port, *pin)?;
+                board.set_mode(port, number, PinMode::Analog)?;
+                board.send(port, &[REPORT_ANALOG | channel, 1])?;
+                let value = board.wait(port, "模拟量上报", |message| match message {
+                    Message::Analog { channel: c, value } if *c == channel => Some(*value),
+                    _ => None,
+                });
+                board.send(port, &[REPORT_ANALOG | channel, 0])?;
+                events::status(&format!("A{} = {}", channel, value?));
+            }
+            Pin::Digital(number) => {
+                // 开启端口上报后板子会立即发送一次端口电平
+                let port_number = number / 8;
+                board.send(port, &[REPORT_DIGITAL | port_number, 1])?;
+                let value = board.wait(port, "数字端口上报", |message| match message {
+                    Message::Digital { port: p, value } if *p == port_number => Some(*value),
+                    _ => None,
+                });
+                board.send(port, &[REPORT_DIGITAL | port_number, 0])?;
+                let level = value? >> (number % 8) & 1;
+                events::status(&format!("引脚 {} = {}", number, if level == 1 { "高" } else { "低" }));
+            }
+        },
+        FirmataCommand::Watch { analog, digital, pullup, interval, exit_after } => {
+            watch(port, &mut board, analog, digital, *pullup, *interval, *exit_after)?;
+        }
+    }
+    Ok(())
+}
+
+fn info(port: &mut Box<dyn SerialPort>, board: &mut Board) -> Result<()> {
+    board.sysex(port, CAPABILITY_QUERY, &[])?;
+    let capabilities = board.wait(port, "引脚能力报告", |message| match message {
+        Message::Sysex(CAPABILITY_RESPONSE, data) => Some(data.clone()),
+        _ => None,
+    })?;
+    let analog_pins = board.analog_pins(port)?.to_vec();
+    // 每个引脚为若干 (模式, 分辨率) 对，以 7F 结束
+    for (pin, entry) in capabilities.split_inclusive(|&b| b == 0x7F).enumerate() {
+        let modes: Vec<String> = entry
+            .strip_suffix(&[0x7F])
+            .unwrap_or(entry)
+            .chunks_exact(2)
+            .map(|pair| match pair[0] {
+                0x02 | 0x03 => format!("{}({} 位)", mode_name(pair[0]), pair[1]),
+                mode => mode_name(mode),
+            })
+            .collect();
+        let name = match analog_pins.iter().find(|(_, p)| *p as usize == pin) {
+            Some((channel, _)) => format!("{:>3} (A{})", pin, channel),
+            None => format!("{:>3}     ", pin),
+        };
+        events::status(&format!("{}  {}", name, if modes.is_empty() { "不可用".to_string() } else { modes.join("、") }));
+    }
+    Ok(())
+}
+
+fn watch(
+    port: &mut Box<dyn SerialPort>,
+    board: &mut Board,
+    analog: &[Pin],
+    digital: &[Pin],
+    pullup: bool,
+    interval: Duration,
+    exit_after: Option<Duration>,
+) -> Result<()> {
+    if analog.is_empty() && digital.is_empty() {
+        bail!("请用 --analog 或 --digital 指定要上报的引脚");
+    }
+    let interval_ms = interval.as_millis().clamp(1, 0x3FFF) as u16;
+    board.sysex(port, SAMPLING_INTERVAL, &split_7bit(interval_ms))?;
+
+    let mut channels = Vec::new();
+    for &pin in analog {
+        let Pin::Analog(channel) = pin else {
+            bail!("--analog 应为模拟通道，如 A0");
+        };
+        let number = board.resolve(port, pin)?;
+        board.set_mode(port, number, PinMode::Analog)?;
+        board.send(port, &[REPORT_ANALOG | channel, 1])?;
+        channels.push(channel);
+    }
+    let mut pins = Vec::new();
+    for &pin in digital {
+        let number = board.resolve(port, pin)?;
+        board.set_mode(port, number, if pullup { PinMode::Pullup } else { PinMode::Input })?;
+        pins.push(number);
+    }
+    let mut ports: Vec<u8> = pins.iter().map(|pin| pin / 8).collect();
+    ports.sort();
+    ports.dedup();
+    for &p in &ports {
+        board.send(port, &[REPORT_DIGITAL | p, 1])?;
+    }
+    events::status("正在显示上报值（按 Ctrl+C 退出）...");
+
+    let mut levels: Vec<Option<u8>> = vec![None; pins.len()];
+    let deadline = exit_after.map(|d| Instant::now() + d);
+    while deadline.is_none_or(|d| Instant::now() < d) {
+        for message in board.poll(port)? {
+            match message {
+                Message::Analog { channel, value } if channels.contains(&channel) => {
+                    events::status(&format!("A{} = {}", channel, value));
+                }
+                Message::Digital { port: p, value } => {
+                    for (i, &pin) in pins.iter().enumerate() {
+                        let level = value >> (pin % 8) & 1;
+                        if pin / 8 == p && levels[i] != Some(level) {
+                            levels[i] = Some(level);
+                            events::status(&format!("引脚 {} = {}", pin, if level == 1 { "高" } else { "低" }));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    for channel in channels {
+        board.send(port, &[REPORT_ANALOG | channel, 0])?;
+    }
+    for p in ports {
+        board.send(port, &[REPORT_DIGITAL | p, 0])?;
+    }
+    Ok(())
+}
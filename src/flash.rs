@@ -0,0 +1,325 @@
+//! 固件烧录：通过芯片内置的串口 bootloader（STM32、AVR）或 ROM 下载模式（ESP32/ESP8266）写入 Intel HEX、S-record 或二进制固件
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::avr::{self, Avr109, Programmer, Stk500};
+use crate::checksum;
+use crate::config::Config;
+use crate::esploader::{Loader, Stub};
+use crate::events;
+use crate::image::{self, Segment};
+use crate::format_hex;
+use crate::progress::Progress;
+use crate::reset;
+use crate::signal::Rs485;
+use crate::stm32::{self, Bootloader};
+use crate::xmodem::Link;
+
+/// flash 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct FlashArgs {
+    #[command(subcommand)]
+    pub command: FlashCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum FlashCommand {
+    /// STM32 系统 bootloader（USART1 等，BOOT0 为高电平时复位进入；可用 --reset-seq stm32 控制 BOOT0 和复位线）
+    Stm32 {
+        /// 固件文件（Intel HEX、S-record 按扩展名或内容识别，其他按二进制处理）
+        file: PathBuf,
+
+        /// 二进制固件的写入地址（Intel HEX 使用文件中的地址）
+        #[arg(long, default_value = "0x08000000", value_parser = image::parse_address)]
+        address: u32,
+
+        /// 写入前不擦除（默认全片擦除）
+        #[arg(long)]
+        no_erase: bool,
+
+        /// 写入后不读出校验
+        #[arg(long)]
+        no_verify: bool,
+
+        /// 完成后用 Go 命令从固件起始地址运行
+        #[arg(long)]
+        go: bool,
+
+        /// 完成后执行的复位序列（如 stm32-run：拉低 BOOT0 后复位，运行新固件）
+        #[arg(long, value_name = "NAME", conflicts_with = "go")]
+        run_seq: Option<String>,
+    },
+    /// ESP32/ESP8266 ROM 下载模式：按 偏移 文件 写入多个二进制镜像，写入后用 MD5 校验；默认先用 RTS/DTR 进入下载模式
+    Esp {
+        /// 偏移和文件，可重复，如 0x1000 bootloader.bin 0x8000 partitions.bin 0x10000 app.bin
+        #[arg(required = true, value_name = "OFFSET FILE")]
+        images: Vec<String>,
+
+        /// 先加载 esptool 的 stub 程序（stub_flasher_*.json），写入更快，ESP8266 也能用 MD5 校验
+        #[arg(long, value_name = "FILE")]
+        stub: Option<PathBuf>,
+
+        /// 写入后不校验 MD5
+        #[arg(long)]
+        no_verify: bool,
+
+        /// 不用 RTS/DTR 进入下载模式和复位（已手动按住 BOOT 键复位）；完成后由 ROM 直接运行新程序
+        #[arg(long)]
+        no_reset: bool,
+    },
+    /// AVR（Arduino）串口 bootloader：STK500v1（Optiboot 等）或 AVR109（Caterina 等），STK500v1 先用 DTR 脉冲复位进入 bootloader
+    Avr {
+        /// 固件文件（Intel HEX、S-record 按扩展名或内容识别，其他按二进制处理）
+        file: PathBuf,
+
+        /// bootloader 协议
+        #[arg(long, value_enum, default_value = "stk500v1")]
+        protocol: AvrProtocol,
+
+        /// 二进制固件的写入地址（Intel HEX 使用文件中的地址）
+        #[arg(long, default_value = "0", value_parser = image::parse_address)]
+        address: u32,
+
+        /// flash 页大小（字节，默认使用 bootloader 报告的块大小或按芯片签名确定，未知芯片为 128）
+        #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u16).range(2..=1024))]
+        page_size: Option<u16>,
+
+        /// 写入后不读出校验
+        #[arg(long)]
+        no_verify: bool,
+
+        /// 不发送复位脉冲（已手动复位进入 bootloader）
+        #[arg(long)]
+        no_reset: bool,
+    },
+}
+
+/// AVR bootloader 协议
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum AvrProtocol {
+    /// Arduino Uno/Nano/Pro Mini 等（Optiboot、ATmegaBOOT）
+    Stk500v1,
+    /// Arduino Leonardo/Micro 等（Caterina）及其它 butterfly 兼容 bootloader
+    Avr109,
+}
+
+/// 执行 flash 子命令
+pub fn run_flash(port: &mut Box<dyn SerialPort>, opts: &FlashArgs, rs485: Option<Rs485>, config: &Config) -> Result<()> {
+    match &opts.command {
+        FlashCommand::Stm32 { file, address, no_erase, no_verify, go, run_seq } => {
+            // 查找复位序列放在烧录前，名称有误时不做任何操作
+            let run_seq = run_seq.as_deref().map(|name| reset::lookup(name, config)).transpose()?;
+            flash_stm32(port, rs485, &image::load(file, *address)?.segments, file, *no_erase, *no_verify, *go)?;
+            if let Some(sequence) = run_seq {
+                reset::reset(port, &sequence, Some(Duration::ZERO), None)?;
+            }
+        }
+        FlashCommand::Esp { images, stub, no_verify, no_reset } => {
+            let images = images
+                .chunks(2)
+                .map(|pair| match pair {
+                    [offset, file] => {
+                        let offset = image::parse_address(offset).map_err(anyhow::Error::msg)?;
+                        let data = fs::read(file).with_context(|| format!("读取镜像 {} 失败", file))?;
+                        Ok((offset, file.as_str(), data))
+                    }
+                    _ => bail!("镜像参数应为成对的 偏移 文件，'{}' 缺少文件", pair[0]),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let stub = stub.as_deref().map(Stub::load).transpose()?;
+            if !no_reset {
+                reset::reset(port, &reset::lookup("esp32", config)?, Some(Duration::from_millis(50)), None)?;
+            } else {
+                port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+            }
+            flash_esp(port, rs485, &images, stub.as_ref(), *no_verify, *no_reset)?;
+            if !no_reset {
+                reset::reset(port, &reset::lookup("esp32-run", config)?, Some(Duration::ZERO), None)?;
+                events::status("已复位，运行新程序");
+            }
+        }
+        FlashCommand::Avr { file, protocol, address, page_size, no_verify, no_reset } => {
+            let segments = image::load(file, *address)?.segments;
+            if segments.last().is_some_and(|last| last.end() > avr::MAX_FLASH as u64) {
+                bail!("固件超出 128KB，STK500v1/AVR109 不支持更大的 flash（ATmega2560 请使用 STK500v2 bootloader 的工具）");
+            }
+            if matches!(protocol, AvrProtocol::Stk500v1) && !no_reset {
+                // Arduino 自动复位：DTR 脉冲经电容复位芯片，bootloader 随即等待同步
+                reset::reset(port, &reset::lookup("arduino", config)?, Some(Duration::from_millis(50)), None)?;
+            } else {
+                port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+            }
+            let mut link = Link::new(port, rs485);
+            match protocol {
+                AvrProtocol::Stk500v1 => flash_avr(&mut Stk500::connect(&mut link)?, &segments, file, page_size.map(usize::from), *no_verify)?,
+                AvrProtocol::Avr109 => flash_avr(&mut Avr109::connect(&mut link)?, &segments, file, page_size.map(usize::from), *no_verify)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// STM32：全片擦除后按 256 字节块写入，再逐块读出比较
+fn flash_stm32(
+    port: &mut Box<dyn SerialPort>,
+    rs485: Option<Rs485>,
+    segments: &[Segment],
+    file: &Path,
+    no_erase: bool,
+    no_verify: bool,
+    go: bool,
+) -> Result<()> {
+    let total: usize = segments.iter().map(|segment| segment.data.len()).sum();
+    // bootloader 固定使用偶校验
+    port.set_parity(serialport::Parity::Even).context("设置偶校验失败")?;
+    port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+    let mut link = Link::new(port, rs485);
+    let mut bootloader = Bootloader::connect(&mut link)?;
+    let id = bootloader.chip_id()?;
+    events::status(&format!("已连接 bootloader {}.{}，芯片 ID 0x{:04X}", bootloader.version >> 4, bootloader.version & 0x0F, id));
+
+    if !no_erase {
+        events::status("全片擦除...");
+        bootloader.mass_erase()?;
+    }
+    events::status(&format!("写入 {}（{} 字节，{} 段）...", file.display(), total, segments.len()));
+    let mut progress = Progress::new(Some(total));
+    let mut done = 0;
+    for segment in segments {
+        for (index, chunk) in segment.data.chunks(stm32::BLOCK).enumerate() {
+            // 写入长度须为 4 的倍数，不足时用 0xFF（擦除后的值）补齐
+            let mut block = chunk.to_vec();
+            block.resize(chunk.len().next_multiple_of(4), 0xFF);
+            bootloader.write(segment.address + (index * stm32::BLOCK) as u32, &block)?;
+            done += chunk.len();
+            progress.update(done);
+        }
+    }
+    progress.finish();
+
+    if !no_verify {
+        events::status("校验...");
+        let mut progress = Progress::new(Some(total));
+        let mut done = 0;
+        for segment in segments {
+            for (index, chunk) in segment.data.chunks(stm32::BLOCK).enumerate() {
+                let at = segment.address + (index * stm32::BLOCK) as u32;
+                let read = bootloader.read_memory(at, chunk.len())?;
+                if let Some(offset) = read.iter().zip(chunk).position(|(a, b)| a != b) {
+                    progress.finish();
+                    bail!("校验失败：0x{:08X} 处读出 {:02X}，应为 {:02X}", at + offset as u32, read[offset], chunk[offset]);
+                }
+                done += chunk.len();
+                progress.update(done);
+            }
+        }
+        progress.finish();
+    }
+    events::status(&format!("烧录完成：{} 字节{}", total, if no_verify { "" } else { "，校验通过" }));
+
+    if go {
+        let start = segments[0].address;
+        bootloader.go(start)?;
+        events::status(&format!("已从 0x{:08X} 开始运行", start));
+    }
+    Ok(())
+}
+
+/// ESP：同步后（可选加载 stub）逐个擦除并写入镜像，用 flash 中数据的 MD5 校验
+fn flash_esp(
+    port: &mut Box<dyn SerialPort>,
+    rs485: Option<Rs485>,
+    images: &[(u32, &str, Vec<u8>)],
+    stub: Option<&Stub>,
+    no_verify: bool,
+    run: bool,
+) -> Result<()> {
+    let mut link = Link::new(port, rs485);
+    let mut loader = Loader::connect(&mut link)?;
+    events::status(&format!("已连接 {} ROM 下载模式", loader.chip.name()));
+    if let Some(stub) = stub {
+        loader.run_stub(stub)?;
+        events::status("stub 已运行");
+    }
+    loader.attach()?;
+    for (offset, file, data) in images {
+        events::status(&format!("写入 {} 到 0x{:X}（{} 字节，擦除中）...", file, offset, data.len()));
+        let mut progress = Progress::new(Some(data.len()));
+        loader.write(*offset, data, &mut |done| progress.update(done))?;
+        progress.finish();
+        if no_verify {
+            continue;
+        }
+        match loader.md5(*offset, data.len())? {
+            Some(digest) if digest == checksum::md5(data) => events::status(&format!("校验通过：{}，MD5 {}", file, md5_hex(&digest))),
+            Some(digest) => bail!("校验失败：{} 在 flash 中的 MD5 为 {}，应为 {}", file, md5_hex(&digest), md5_hex(&checksum::md5(data))),
+            None => events::status("ESP8266 ROM 不支持 MD5 校验（使用 --stub 时可以校验），已跳过"),
+        }
+    }
+    loader.finish(run)?;
+    events::status(&format!("烧录完成：{} 个镜像", images.len()));
+    Ok(())
+}
+
+fn md5_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// AVR：按页写入（页中固件没有覆盖的部分填 0xFF），再逐页读出比较，最后退出 bootloader 运行新程序
+fn flash_avr(programmer: &mut dyn Programmer, segments: &[Segment], file: &Path, page_size: Option<usize>, no_verify: bool) -> Result<()> {
+    let signature = programmer.signature()?;
+    let chip = avr::chip(signature);
+    events::status(&format!(
+        "已连接 {}，芯片签名 {}{}",
+        programmer.describe(),
+        format_hex(&signature),
+        chip.map_or(String::new(), |(name, _)| format!("（{}）", name))
+    ));
+    let page_size = page_size.or(programmer.block_size()).or(chip.map(|(_, size)| size)).unwrap_or(128);
+    let pages = pages(segments, page_size);
+    let total = pages.len() * page_size;
+
+    programmer.erase()?;
+    events::status(&format!("写入 {}（{} 页，每页 {} 字节）...", file.display(), pages.len(), page_size));
+    let mut progress = Progress::new(Some(total));
+    for (index, (address, page)) in pages.iter().enumerate() {
+        programmer.write_page(*address, page)?;
+        progress.update((index + 1) * page_size);
+    }
+    progress.finish();
+
+    if !no_verify {
+        events::status("校验...");
+        let mut progress = Progress::new(Some(total));
+        for (index, (address, page)) in pages.iter().enumerate() {
+            let read = programmer.read_page(*address, page.len())?;
+            if let Some(offset) = read.iter().zip(page).position(|(a, b)| a != b) {
+                progress.finish();
+                bail!("校验失败：0x{:05X} 处读出 {:02X}，应为 {:02X}", *address as usize + offset, read[offset], page[offset]);
+            }
+            progress.update((index + 1) * page_size);
+        }
+        progress.finish();
+    }
+    programmer.finish()?;
+    events::status(&format!("烧录完成：{} 页{}，已运行新程序", pages.len(), if no_verify { "" } else { "，校验通过" }));
+    Ok(())
+}
+
+/// 把各段数据按页对齐切分，返回 (页起始地址, 页内容)
+fn pages(segments: &[Segment], size: usize) -> Vec<(u32, Vec<u8>)> {
+    let mut pages: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+    for segment in segments {
+        for (offset, &byte) in segment.data.iter().enumerate() {
+            let address = segment.address as usize + offset;
+            let page = pages.entry((address - address % size) as u32).or_insert_with(|| vec![0xFF; size]);
+            page[address % size] = byte;
+        }
+    }
+    pages.into_iter().collect()
+}
@@ -0,0 +1,1016 @@
+//! 帧格式：监听时把收到的字节流切分为完整的帧，发送时按帧格式封装数据
+
+use anyhow::{bail, Result};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::checksum::crc16_x25;
+use crate::gzip::crc32;
+use crate::dlt645;
+use crate::lin;
+use crate::modbus;
+use crate::mavlink;
+use crate::mstp;
+use crate::nmea;
+use crate::protocol::Protocol;
+use crate::ubx;
+use crate::xbee;
+use crate::{parse_duration, parse_escapes, parse_hex};
+
+/// 帧格式（--frame）
+#[derive(Debug, Clone)]
+pub enum FrameSpec {
+    /// 以指定字节序列结尾的帧，如 `delim:\r\n` 或 `delim:0x7E`
+    Delimiter(Vec<u8>),
+    /// 带长度字段的帧，如 `len:offset=2,size=1,endian=big,extra=3`
+    Length(LengthField),
+    /// COBS 编码、以 0x00 结尾的帧
+    Cobs,
+    /// SLIP（RFC 1055）转义、以 0xC0 分隔的帧
+    Slip,
+    /// 类 HDLC 帧（RFC 1662）：0x7E 标志、0x7D 字节填充、帧尾为 FCS，如 `hdlc` 或 `hdlc:fcs=32`
+    ///
+    /// 异步串口上只有字节填充，同步 HDLC 的位填充由硬件完成，不会出现在收到的数据中。
+    Hdlc(Fcs),
+    /// Modbus RTU：按 3.5 字符的空闲间隔和 CRC 切分，标注从站地址、功能码和 CRC 状态，
+    /// 如 `modbus-rtu` 或 `modbus-rtu:gap=20ms`（USB 转换器的延迟计时器会拉长间隔时可调大）
+    ModbusRtu { gap: Option<Duration> },
+    /// Modbus ASCII：以 ':' 开始、CRLF 结束的十六进制文本帧，解码后标注从站地址、功能码和 LRC 状态；
+    /// 发送时把数据（地址 + PDU）加上 LRC 编码为 ASCII 帧
+    ModbusAscii,
+    /// DL/T 645-2007 电能表帧：跳过前导 FE，按 68 帧头和长度切分，校验后解码数据标识和数值
+    Dlt645,
+    /// BACnet MS/TP：按 55 FF 前导码和帧头长度切分，校验帧头和数据 CRC，标注帧类型、源/目的地址和 APDU 类型
+    Mstp,
+    /// XBee API 帧：按 7E 起始符和长度切分，校验和正确时标注帧类型、地址、AT 命令和数据；
+    /// `xbee:escaped` 为 API 2 模式（带转义），发送时把数据（帧类型及之后的字段）封装为 API 帧
+    Xbee { escaped: bool },
+    /// MAVLink v1/v2：按 FE/FD 起始符和长度切分，校验 CRC（含 CRC_EXTRA），标注消息名、系统/组件 ID 和主要字段
+    Mavlink,
+    /// NMEA 0183：按 '$'/'!' 开始、换行结束的文本语句切分，校验和错误时标为错误帧，解析 GGA、RMC、GSV、VTG 等语句的字段
+    Nmea,
+    /// u-blox UBX：按 B5 62 同步字符和长度切分，校验 CK_A/CK_B，标注消息名和常用消息的字段；
+    /// 混在其中的 NMEA 语句同样切分并解码。发送时把数据（类别、ID 和载荷）封装为 UBX 帧
+    Ubx,
+    /// LIN：经收发器收到的 break（0x00）、55 同步字节、PID、数据和校验和，按下一个帧头与校验和
+    /// 或空闲间隔切分，校验 PID 奇偶位和经典/增强校验和，如 `lin` 或 `lin:gap=20ms`
+    Lin { gap: Option<Duration> },
+    /// 按用户编写的协议描述文件切分并解码各字段，如 `format:sensor.toml`
+    Custom(Protocol),
+}
+
+/// HDLC 帧校验序列
+#[derive(Debug, Clone, Copy)]
+pub enum Fcs {
+    None,
+    /// CRC-16/X-25
+    Fcs16,
+    /// CRC-32
+    Fcs32,
+}
+
+impl Fcs {
+    fn len(self) -> usize {
+        match self {
+            Fcs::None => 0,
+            Fcs::Fcs16 => 2,
+            Fcs::Fcs32 => 4,
+        }
+    }
+
+    /// 计算 FCS（低字节在前发送）
+    fn compute(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Fcs::None => Vec::new(),
+            Fcs::Fcs16 => crc16_x25(data).to_le_bytes().to_vec(),
+            Fcs::Fcs32 => crc32(data).to_le_bytes().to_vec(),
+        }
+    }
+}
+
+/// 切分出的一帧
+pub struct Frame {
+    /// 解码后的内容（解码失败时为原始数据）
+    pub data: Vec<u8>,
+    /// 解码或校验失败的原因
+    pub error: Option<String>,
+    /// 帧内容说明（如协议字段）
+    pub info: Option<String>,
+}
+
+impl Frame {
+    fn ok(data: Vec<u8>) -> Self {
+        Frame { data, error: None, info: None }
+    }
+
+    fn bad(data: Vec<u8>, error: impl Into<String>) -> Self {
+        Frame { data, error: Some(error.into()), info: None }
+    }
+}
+
+/// 长度字段的位置和含义：帧总长 = offset + size + 长度值 + extra
+///
+/// `extra` 为长度值之外还要计入的字节数（如长度不含的校验和），可以为负数（如长度值包含了帧头）。
+#[derive(Debug, Clone, Copy)]
+pub struct LengthField {
+    /// 长度字段在帧中的偏移
+    offset: usize,
+    /// 长度字段的字节数（1、2 或 4）
+    size: usize,
+    big_endian: bool,
+    extra: i64,
+}
+
+/// len 帧的最大长度，超过时认为长度字段无效
+const MAX_FRAME: i64 = 65536;
+
+impl LengthField {
+    pub fn parse(s: &str) -> std::result::Result<Self, String> {
+        let mut field = LengthField { offset: 0, size: 1, big_endian: true, extra: 0 };
+        for option in s.split(',').filter(|o| !o.is_empty()) {
+            let (key, value) = option.split_once('=').ok_or_else(|| format!("无效的选项 '{}'，应为 键=值", option))?;
+            let number = || value.parse::<i64>().map_err(|_| format!("{} 的值 '{}' 不是有效的整数", key, value));
+            match key {
+                "offset" => field.offset = usize::try_from(number()?).map_err(|_| "offset 不能为负数".to_string())?,
+                "size" => {
+                    field.size = match number()? {
+                        n @ (1 | 2 | 4) => n as usize,
+                        _ => return Err("size 应为 1、2 或 4".to_string()),
+                    }
+                }
+                "endian" => {
+                    field.big_endian = match value {
+                        "big" | "be" => true,
+                        "little" | "le" => false,
+                        _ => return Err(format!("无效的字节序 '{}'，应为 big 或 little", value)),
+                    }
+                }
+                "extra" => field.extra = number()?,
+                _ => return Err(format!("未知的选项 '{}'，可用选项：offset、size、endian、extra", key)),
+            }
+        }
+        Ok(field)
+    }
+
+    /// 根据已缓存的数据计算帧总长，数据不足以读出长度字段时返回 `None`
+    pub fn frame_len(&self, data: &[u8]) -> Option<i64> {
+        let field = data.get(self.offset..self.offset + self.size)?;
+        let value = if self.big_endian {
+            field.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64)
+        } else {
+            field.iter().rev().fold(0u64, |acc, &b| (acc << 8) | b as u64)
+        };
+        Some((self.offset + self.size) as i64 + value as i64 + self.extra)
+    }
+}
+
+/// 解析帧格式参数
+pub fn parse_frame(s: &str) -> std::result::Result<FrameSpec, String> {
+    let (kind, value) = s.split_once(':').unwrap_or((s, ""));
+    match kind {
+        "delim" => {
+            // 0x 开头按十六进制字节解析，否则按转义文本解析
+            let delimiter = match value.strip_prefix("0x").or(value.strip_prefix("0X")) {
+                Some(hex) => parse_hex(hex),
+                None => parse_escapes(value),
+            }
+            .map_err(|e| format!("帧分隔符解析失败: {}", e))?;
+            if delimiter.is_empty() {
+                return Err("帧分隔符不能为空".to_string());
+            }
+            Ok(FrameSpec::Delimiter(delimiter))
+        }
+        "len" => LengthField::parse(value).map(FrameSpec::Length),
+        "cobs" => Ok(FrameSpec::Cobs),
+        "slip" => Ok(FrameSpec::Slip),
+        "hdlc" => match value {
+            "" | "fcs=16" => Ok(FrameSpec::Hdlc(Fcs::Fcs16)),
+            "fcs=32" => Ok(FrameSpec::Hdlc(Fcs::Fcs32)),
+            "fcs=none" => Ok(FrameSpec::Hdlc(Fcs::None)),
+            _ => Err(format!("无效的 hdlc 选项 '{}'，应为 fcs=16、fcs=32 或 fcs=none", value)),
+        },
+        "modbus-rtu" => match value.strip_prefix("gap=") {
+            Some(gap) => Ok(FrameSpec::ModbusRtu { gap: Some(parse_duration(gap)?) }),
+            None if value.is_empty() => Ok(FrameSpec::ModbusRtu { gap: None }),
+            None => Err(format!("无效的 modbus-rtu 选项 '{}'，应为 gap=<时长>", value)),
+        },
+        "modbus-ascii" => Ok(FrameSpec::ModbusAscii),
+        "dlt645" => Ok(FrameSpec::Dlt645),
+        "mstp" => Ok(FrameSpec::Mstp),
+        "mavlink" => Ok(FrameSpec::Mavlink),
+        "nmea" => Ok(FrameSpec::Nmea),
+        "ubx" => Ok(FrameSpec::Ubx),
+        "lin" => match value.strip_prefix("gap=") {
+            Some(gap) => Ok(FrameSpec::Lin { gap: Some(parse_duration(gap)?) }),
+            None if value.is_empty() => Ok(FrameSpec::Lin { gap: None }),
+            None => Err(format!("无效的 lin 选项 '{}'，应为 gap=<时长>", value)),
+        },
+        "xbee" => match value {
+            "" => Ok(FrameSpec::Xbee { escaped: false }),
+            "escaped" => Ok(FrameSpec::Xbee { escaped: true }),
+            _ => Err(format!("无效的 xbee 选项 '{}'，应为 escaped", value)),
+        },
+        "format" => Protocol::load(Path::new(value)).map(FrameSpec::Custom).map_err(|e| format!("{:#}", e)),
+        _ => Err(format!(
+            "未知的帧格式 '{}'，应为 delim:<分隔符>、len:<选项>、cobs、slip、hdlc、modbus-rtu、modbus-ascii、dlt645、mstp、xbee、mavlink、nmea、ubx、lin 或 format:<协议描述文件>",
+            s
+        )),
+    }
+}
+
+impl FrameSpec {
+    /// 按帧格式封装一段要发送的数据
+    pub fn encode(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            FrameSpec::Delimiter(delimiter) => Ok([payload, delimiter].concat()),
+            FrameSpec::Length(_) => bail!("len 帧格式不支持发送时封装，请直接发送完整的帧"),
+            FrameSpec::Cobs => {
+                let mut frame = cobs_encode(payload);
+                frame.push(0);
+                Ok(frame)
+            }
+            FrameSpec::Slip => Ok(slip_encode(payload)),
+            FrameSpec::Hdlc(fcs) => Ok(hdlc_encode(payload, *fcs)),
+            FrameSpec::ModbusRtu { .. } => bail!("modbus-rtu 帧格式不支持发送时封装，请使用 --checksum crc16-modbus"),
+            FrameSpec::ModbusAscii => Ok(modbus::ascii_encode(payload)),
+            FrameSpec::Dlt645 => bail!("dlt645 帧格式不支持发送时封装，请使用 dlt645 子命令"),
+            FrameSpec::Xbee { escaped } => Ok(xbee::encode(payload, *escaped)),
+            FrameSpec::Mstp => bail!("mstp 帧格式只用于监听时解码，请直接发送完整的帧"),
+            FrameSpec::Mavlink => bail!("mavlink 帧格式只用于监听时解码，请直接发送完整的帧"),
+            FrameSpec::Nmea => bail!("nmea 帧格式只用于监听时解码，请直接发送完整的语句"),
+            FrameSpec::Ubx => match payload {
+                [class, id, rest @ ..] => Ok(ubx::encode(*class, *id, rest)),
+                _ => bail!("UBX 消息至少需要类别和 ID 两个字节"),
+            },
+            FrameSpec::Lin { .. } => bail!("lin 帧格式只用于监听时解码，请使用 lin 子命令发送"),
+            FrameSpec::Custom(_) => bail!("format 帧格式只用于监听时解码，请直接发送完整的帧"),
+        }
+    }
+
+    /// 是否为二进制协议（默认以十六进制显示）
+    pub fn is_binary(&self) -> bool {
+        matches!(self, FrameSpec::ModbusRtu { .. } | FrameSpec::ModbusAscii | FrameSpec::Dlt645 | FrameSpec::Mstp | FrameSpec::Xbee { .. } | FrameSpec::Mavlink | FrameSpec::Ubx | FrameSpec::Lin { .. } | FrameSpec::Custom(_))
+    }
+}
+
+/// 从字节流中切分帧，缓存未完整的部分
+pub struct Deframer {
+    spec: FrameSpec,
+    pending: Vec<u8>,
+    /// 按空闲间隔分帧时的间隔（modbus-rtu、lin）
+    gap: Duration,
+    last_rx: Option<Instant>,
+}
+
+impl Deframer {
+    pub fn new(spec: FrameSpec) -> Self {
+        Deframer { spec, pending: Vec::new(), gap: modbus::frame_gap(9600), last_rx: None }
+    }
+
+    /// 按当前波特率确定帧间隔（未在帧格式中指定时）
+    pub fn set_baud(&mut self, baud: u32) {
+        match &self.spec {
+            FrameSpec::ModbusRtu { gap } => self.gap = gap.unwrap_or_else(|| modbus::frame_gap(baud)),
+            FrameSpec::Lin { gap } => self.gap = gap.unwrap_or_else(|| lin::frame_gap(baud)),
+            _ => {}
+        }
+    }
+
+    /// 空闲超过帧间隔时输出缓存的不完整帧
+    pub fn poll_idle(&mut self, now: Instant) -> Vec<Frame> {
+        match (&self.spec, self.last_rx) {
+            (FrameSpec::ModbusRtu { .. }, Some(last)) if now - last > self.gap && !self.pending.is_empty() => {
+                vec![modbus_frame(std::mem::take(&mut self.pending))]
+            }
+            (FrameSpec::Lin { .. }, Some(last)) if now - last > self.gap && !self.pending.is_empty() => {
+                vec![lin_frame(std::mem::take(&mut self.pending))]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// 追加收到的数据，返回其中所有已完整的帧（不含分隔符，跳过空帧）
+    pub fn push(&mut self, data: &[u8], now: Instant) -> Vec<Frame> {
+        let mut frames = self.poll_idle(now);
+        self.last_rx = Some(now);
+        self.pending.extend_from_slice(data);
+        match &self.spec {
+            FrameSpec::Delimiter(delimiter) => {
+                while let Some(pos) = self.pending.windows(delimiter.len()).position(|w| w == delimiter.as_slice()) {
+                    let frame: Vec<u8> = self.pending.drain(..pos + delimiter.len()).take(pos).collect();
+                    if !frame.is_empty() {
+                        frames.push(Frame::ok(frame));
+                    }
+                }
+            }
+            FrameSpec::Length(field) => {
+                while let Some(len) = field.frame_len(&self.pending) {
+                    // 长度值不合理时丢弃一个字节重新对齐
+                    if len < (field.offset + field.size) as i64 || len > MAX_FRAME {
+                        log::debug!("无效的帧长度 {}，丢弃 1 字节", len);
+                        self.pending.remove(0);
+                        continue;
+                    }
+                    let len = len as usize;
+                    if self.pending.len() < len {
+                        break;
+                    }
+                    frames.push(Frame::ok(self.pending.drain(..len).collect()));
+                }
+            }
+            FrameSpec::Cobs => {
+                while let Some(pos) = self.pending.iter().position(|&b| b == 0) {
+                    let encoded: Vec<u8> = self.pending.drain(..=pos).take(pos).collect();
+                    if encoded.is_empty() {
+                        continue;
+                    }
+                    frames.push(match cobs_decode(&encoded) {
+                        Some(data) => Frame::ok(data),
+                        None => Frame::bad(encoded, "COBS 解码失败"),
+                    });
+                }
+            }
+            FrameSpec::Slip => {
+                while let Some(pos) = self.pending.iter().position(|&b| b == SLIP_END) {
+                    let encoded: Vec<u8> = self.pending.drain(..=pos).take(pos).collect();
+                    if encoded.is_empty() {
+                        continue;
+                    }
+                    frames.push(match slip_decode(&encoded) {
+                        Some(data) => Frame::ok(data),
+                        None => Frame::bad(encoded, "SLIP 转义无效"),
+                    });
+                }
+            }
+            FrameSpec::Hdlc(fcs) => {
+                while let Some(pos) = self.pending.iter().position(|&b| b == HDLC_FLAG) {
+                    let stuffed: Vec<u8> = self.pending.drain(..=pos).take(pos).collect();
+                    // 相邻帧可以共用标志字节，两个标志之间为空
+                    if !stuffed.is_empty() {
+                        frames.push(hdlc_decode(&stuffed, *fcs));
+                    }
+                }
+            }
+            FrameSpec::ModbusRtu { .. } => {
+                // 同一次读取中可能有连续的多帧（如请求和响应），按 CRC 找出完整的帧
+                while let Some(len) = (4..=self.pending.len().min(modbus::MAX_ADU)).find(|&n| modbus::crc_ok(&self.pending[..n])) {
+                    frames.push(modbus_frame(self.pending.drain(..len).collect()));
+                }
+                if self.pending.len() > modbus::MAX_ADU {
+                    frames.push(modbus_frame(std::mem::take(&mut self.pending)));
+                }
+            }
+            FrameSpec::ModbusAscii => loop {
+                // 丢弃起始符之前的数据；帧内出现新的起始符时从新的起始符重新开始
+                match self.pending.iter().position(|&b| b == b':') {
+                    Some(start) => {
+                        self.pending.drain(..start);
+                    }
+                    None => {
+                        self.pending.clear();
+                        break;
+                    }
+                }
+                let Some(end) = self.pending.windows(2).position(|w| w == b"\r\n") else {
+                    if self.pending.len() > modbus::MAX_ASCII_FRAME {
+                        frames.push(Frame::bad(std::mem::take(&mut self.pending), "Modbus ASCII 帧过长，缺少 CRLF"));
+                    }
+                    break;
+                };
+                if let Some(restart) = self.pending[1..end].iter().rposition(|&b| b == b':') {
+                    frames.push(Frame::bad(self.pending.drain(..restart + 1).collect(), "Modbus ASCII 帧不完整"));
+                    continue;
+                }
+                let text: Vec<u8> = self.pending.drain(..end + 2).collect();
+                frames.push(match modbus::ascii_decode(&text[1..end]) {
+                    Some(data) => modbus_ascii_frame(data),
+                    None => Frame::bad(text, "Modbus ASCII 帧含非十六进制字符"),
+                });
+            },
+            FrameSpec::Dlt645 => loop {
+                match self.pending.iter().position(|&b| b == 0x68) {
+                    Some(start) => {
+                        self.pending.drain(..start);
+                    }
+                    None => {
+                        self.pending.clear();
+                        break;
+                    }
+                }
+                let Some(len) = dlt645::frame_len(&self.pending) else { break };
+                // 帧头或结束符不对时可能是数据中的 0x68，丢弃一个字节重新对齐
+                if !dlt645::header_ok(&self.pending) {
+                    self.pending.remove(0);
+                    continue;
+                }
+                if self.pending.len() < len {
+                    break;
+                }
+                if self.pending[len - 1] != 0x16 {
+                    log::debug!("DL/T 645 帧缺少结束符，丢弃 1 字节");
+                    self.pending.remove(0);
+                    continue;
+                }
+                let data: Vec<u8> = self.pending.drain(..len).collect();
+                frames.push(match dlt645::decode(&data) {
+                    Ok(telegram) => Frame { data, error: None, info: Some(telegram.describe()) },
+                    Err(error) => Frame::bad(data, error),
+                });
+            },
+            FrameSpec::Mstp => loop {
+                match self.pending.windows(2).position(|w| w == mstp::PREAMBLE) {
+                    Some(start) => {
+                        self.pending.drain(..start);
+                    }
+                    None => {
+                        // 末尾的 0x55 可能是下一帧前导码的一半，保留
+                        let keep = usize::from(self.pending.last() == Some(&0x55));
+                        self.pending.drain(..self.pending.len() - keep);
+                        break;
+                    }
+                }
+                if self.pending.len() < mstp::HEADER_LEN {
+                    break;
+                }
+                if !mstp::header_ok(&self.pending) {
+                    frames.push(Frame::bad(self.pending.drain(..mstp::HEADER_LEN).collect(), "MS/TP 帧头 CRC 错误"));
+                    continue;
+                }
+                if mstp::data_len(&self.pending) > mstp::MAX_DATA {
+                    frames.push(Frame::bad(self.pending.drain(..mstp::HEADER_LEN).collect(), "MS/TP 数据长度超出上限"));
+                    continue;
+                }
+                let len = mstp::frame_len(&self.pending);
+                if self.pending.len() < len {
+                    break;
+                }
+                let data: Vec<u8> = self.pending.drain(..len).collect();
+                let description = mstp::describe(&data);
+                frames.push(match mstp::check(&data) {
+                    Ok(()) => Frame { data, error: None, info: Some(description) },
+                    Err(error) => Frame::bad(data, format!("{}，{}", description, error)),
+                });
+            },
+            FrameSpec::Xbee { escaped } => loop {
+                match self.pending.iter().position(|&b| b == xbee::START) {
+                    Some(start) => {
+                        self.pending.drain(..start);
+                    }
+                    None => {
+                        self.pending.clear();
+                        break;
+                    }
+                }
+                match xbee::take_frame(&self.pending, *escaped) {
+                    xbee::Take::Incomplete => break,
+                    xbee::Take::Invalid => {
+                        log::debug!("XBee 帧长度无效，丢弃 1 字节");
+                        self.pending.remove(0);
+                    }
+                    xbee::Take::Frame(consumed, data) => {
+                        self.pending.drain(..consumed);
+                        frames.push(match xbee::check(&data) {
+                            Ok(()) => {
+                                let info = xbee::describe(xbee::frame_data(&data));
+                                Frame { data, error: None, info: Some(info) }
+                            }
+                            Err(error) => Frame::bad(data, error),
+                        });
+                    }
+                }
+            },
+            FrameSpec::Mavlink => loop {
+                match self.pending.iter().position(|&b| mavlink::is_magic(b)) {
+                    Some(start) => {
+                        self.pending.drain(..start);
+                    }
+                    None => {
+                        self.pending.clear();
+                        break;
+                    }
+                }
+                let Some(len) = mavlink::frame_len(&self.pending) else {
+                    break;
+                };
+                if self.pending.len() < len {
+                    break;
+                }
+                let data: Vec<u8> = self.pending.drain(..len).collect();
+                let description = mavlink::describe(&data);
+                frames.push(match mavlink::check(&data) {
+                    Ok(()) => Frame { data, error: None, info: Some(description) },
+                    Err(error) => Frame::bad(data, format!("{}，{}", description, error)),
+                });
+            },
+            FrameSpec::Lin { .. } => loop {
+                // 帧从 break（0x00）或同步字节开始，之前的数据不属于任何帧
+                match self.pending.windows(2).position(|w| w == [0x00, lin::SYNC]).or_else(|| self.pending.iter().position(|&b| b == lin::SYNC)) {
+                    Some(start) => {
+                        self.pending.drain(..start);
+                    }
+                    None if self.pending.last() == Some(&0x00) => {
+                        self.pending.drain(..self.pending.len() - 1);
+                        break;
+                    }
+                    None => {
+                        self.pending.clear();
+                        break;
+                    }
+                }
+                let header = if self.pending[0] == 0x00 { 3 } else { 2 };
+                if self.pending.len() < header + 2 {
+                    break;
+                }
+                // 下一个帧头之前的数据为应答；数据中也可能出现 00 55，优先取校验和通过的位置，
+                // 其次为只有帧头、没有应答的帧，都不是时应答有误，在下一个帧头处切分
+                let longest = header + 9;
+                let next_header = |n: usize| {
+                    self.pending[n..].starts_with(&[0x00, lin::SYNC]) && self.pending.get(n + 2).is_some_and(|&pid| lin::id_of(pid).is_some())
+                };
+                let last = longest.min(self.pending.len().saturating_sub(3));
+                let end = (header + 2..=last)
+                    .find(|&n| next_header(n) && lin::complete(&self.pending[..n]))
+                    .or_else(|| (header..=last).find(|&n| next_header(n)));
+                match end {
+                    Some(end) => frames.push(lin_frame(self.pending.drain(..end).collect())),
+                    None if self.pending.len() >= longest + 3 => frames.push(lin_frame(self.pending.drain(..longest).collect())),
+                    None => break,
+                }
+            },
+            FrameSpec::Nmea => loop {
+                match self.pending.iter().position(|&b| nmea::is_start(b)) {
+                    Some(start) => {
+                        self.pending.drain(..start);
+                    }
+                    None => {
+                        self.pending.clear();
+                        break;
+                    }
+                }
+                let Some(end) = self.pending.iter().position(|&b| b == b'\n') else {
+                    if self.pending.len() > nmea::MAX_LINE {
+                        frames.push(Frame::bad(std::mem::take(&mut self.pending), "NMEA 语句过长，缺少换行"));
+                    }
+                    break;
+                };
+                frames.push(nmea_frame(self.pending.drain(..=end).take(end).collect()));
+            },
+            FrameSpec::Ubx => loop {
+                // UBX 同步字符或 NMEA 起始符，末尾的 B5 可能是下一帧同步字符的一半
+                let start = self.pending.iter().enumerate().position(|(i, &b)| {
+                    nmea::is_start(b) || (b == ubx::SYNC[0] && self.pending.get(i + 1).is_none_or(|&next| next == ubx::SYNC[1]))
+                });
+                match start {
+                    Some(start) => {
+                        self.pending.drain(..start);
+                    }
+                    None => {
+                        self.pending.clear();
+                        break;
+                    }
+                }
+                if nmea::is_start(self.pending[0]) {
+                    let sync = self.pending.windows(2).position(|w| w == ubx::SYNC);
+                    match (self.pending.iter().position(|&b| b == b'\n'), sync) {
+                        (Some(end), sync) if sync.is_none_or(|sync| end < sync) => {
+                            frames.push(nmea_frame(self.pending.drain(..=end).take(end).collect()));
+                        }
+                        // 语句还没结束就出现了 UBX 帧
+                        (_, Some(sync)) => {
+                            frames.push(Frame::bad(self.pending.drain(..sync).collect(), "NMEA 语句不完整"));
+                        }
+                        _ => {
+                            if self.pending.len() > nmea::MAX_LINE {
+                                frames.push(Frame::bad(std::mem::take(&mut self.pending), "NMEA 语句过长，缺少换行"));
+                            }
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                if self.pending.len() < ubx::HEADER_LEN {
+                    break;
+                }
+                let len = ubx::payload_len(&self.pending);
+                if len > ubx::MAX_PAYLOAD {
+                    log::debug!("UBX 载荷长度 {} 超出上限，丢弃 1 字节", len);
+                    self.pending.remove(0);
+                    continue;
+                }
+                if self.pending.len() < ubx::HEADER_LEN + len + 2 {
+                    break;
+                }
+                let data: Vec<u8> = self.pending.drain(..ubx::HEADER_LEN + len + 2).collect();
+                let description = ubx::describe(&data);
+                frames.push(match ubx::check(&data) {
+                    Ok(()) => Frame { data, error: None, info: Some(description) },
+                    Err(error) => Frame::bad(data, format!("{}，{}", description, error)),
+                });
+            },
+            FrameSpec::Custom(protocol) => loop {
+                // 丢弃帧头之前的数据，末尾可能是不完整的帧头，保留
+                let header = protocol.header();
+                let position = match header.is_empty() {
+                    true => Some(0),
+                    false => self.pending.windows(header.len()).position(|w| w == header),
+                };
+                match position {
+                    Some(0) => {}
+                    Some(pos) => {
+                        log::debug!("丢弃帧头前的 {} 字节", pos);
+                        self.pending.drain(..pos);
+                    }
+                    None => {
+                        let keep = header.len().saturating_sub(1).min(self.pending.len());
+                        self.pending.drain(..self.pending.len() - keep);
+                        break;
+                    }
+                }
+                let Some(len) = protocol.frame_len(&self.pending) else { break };
+                if len < header.len().max(1) as i64 || len > MAX_FRAME {
+                    log::debug!("无效的帧长度 {}，丢弃 1 字节", len);
+                    self.pending.remove(0);
+                    continue;
+                }
+                if self.pending.len() < len as usize {
+                    break;
+                }
+                let data: Vec<u8> = self.pending.drain(..len as usize).collect();
+                frames.push(match protocol.decode(&data) {
+                    Ok(info) => Frame { data, error: None, info: Some(info) },
+                    Err(error) => Frame::bad(data, error),
+                });
+            },
+        }
+        frames
+    }
+
+    /// 取出未完整的剩余数据（监听结束时输出）
+    pub fn take_pending(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// COBS 编码（不含结尾的 0x00）
+fn cobs_encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 254 + 2);
+    let mut code_pos = 0;
+    out.push(0);
+    for (i, &byte) in data.iter().enumerate() {
+        if byte != 0 {
+            out.push(byte);
+        }
+        // 遇到 0 或数据块满 254 字节时回填长度码，开始新块；满块之后没有数据时不再补一个空块
+        if byte == 0 || (out.len() - code_pos == 255 && i + 1 < data.len()) {
+            out[code_pos] = (out.len() - code_pos) as u8;
+            code_pos = out.len();
+            out.push(0);
+        }
+    }
+    out[code_pos] = (out.len() - code_pos) as u8;
+    out
+}
+
+/// COBS 解码（输入不含结尾的 0x00），数据无效时返回 `None`
+fn cobs_decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let code = data[i] as usize;
+        if code == 0 || i + code > data.len() {
+            return None;
+        }
+        out.extend_from_slice(&data[i + 1..i + code]);
+        i += code;
+        // 长度码 0xFF 表示数据块后没有 0
+        if code != 0xFF && i < data.len() {
+            out.push(0);
+        }
+    }
+    Some(out)
+}
+
+pub const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+/// SLIP 编码：帧前后各加一个 END（帧前的 END 用于清掉线路上的噪声）
+pub fn slip_encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 2);
+    out.push(SLIP_END);
+    for &byte in data {
+        match byte {
+            SLIP_END => out.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC => out.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+            _ => out.push(byte),
+        }
+    }
+    out.push(SLIP_END);
+    out
+}
+
+/// SLIP 解码（输入不含 END），转义序列无效时返回 `None`
+pub fn slip_decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        out.push(match byte {
+            SLIP_ESC => match bytes.next() {
+                Some(&SLIP_ESC_END) => SLIP_END,
+                Some(&SLIP_ESC_ESC) => SLIP_ESC,
+                _ => return None,
+            },
+            _ => byte,
+        });
+    }
+    Some(out)
+}
+
+const HDLC_FLAG: u8 = 0x7E;
+const HDLC_ESC: u8 = 0x7D;
+
+/// HDLC 编码：追加 FCS，对标志和转义字节做填充，前后加标志字节
+fn hdlc_encode(data: &[u8], fcs: Fcs) -> Vec<u8> {
+    let mut out = vec![HDLC_FLAG];
+    for byte in [data, &fcs.compute(data)].concat() {
+        if byte == HDLC_FLAG || byte == HDLC_ESC {
+            out.extend_from_slice(&[HDLC_ESC, byte ^ 0x20]);
+        } else {
+            out.push(byte);
+        }
+    }
+    out.push(HDLC_FLAG);
+    out
+}
+
+/// HDLC 解码（输入不含标志字节）：去除字节填充并校验 FCS，返回的内容不含 FCS
+fn hdlc_decode(stuffed: &[u8], fcs: Fcs) -> Frame {
+    let mut data = Vec::with_capacity(stuffed.len());
+    let mut bytes = stuffed.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte != HDLC_ESC {
+            data.push(byte);
+            continue;
+        }
+        match bytes.next() {
+            Some(&next) => data.push(next ^ 0x20),
+            // 0x7D 0x7E 为中止序列
+            None => return Frame::bad(stuffed.to_vec(), "帧被中止"),
+        }
+    }
+
+    if data.len() < fcs.len() {
+        return Frame::bad(data, "帧长度不足以包含 FCS");
+    }
+    let received = data.split_off(data.len() - fcs.len());
+    let expected = fcs.compute(&data);
+    let error = (received != expected).then(|| {
+        let hex = |b: &[u8]| b.iter().rev().map(|b| format!("{:02X}", b)).collect::<String>();
+        format!("FCS 错误：收到 0x{}，应为 0x{}", hex(&received), hex(&expected))
+    });
+    Frame { data, error, info: None }
+}
+
+/// 一行 NMEA 语句（不含换行）：校验校验和并解析字段
+fn nmea_frame(mut data: Vec<u8>) -> Frame {
+    if data.last() == Some(&b'\r') {
+        data.pop();
+    }
+    let line = String::from_utf8_lossy(&data).into_owned();
+    let description = nmea::describe(&line);
+    match nmea::check(&line) {
+        Ok(()) => Frame { data, error: None, info: Some(description) },
+        Err(error) => Frame::bad(data, format!("{}，{}", description, error)),
+    }
+}
+
+/// 标注 Modbus ASCII 帧（已解码为字节）的从站地址、功能码和 LRC 状态
+fn modbus_ascii_frame(data: Vec<u8>) -> Frame {
+    let description = modbus::describe(&data);
+    if modbus::lrc_ok(&data) {
+        Frame { data, error: None, info: Some(format!("{}，LRC 正确", description)) }
+    } else {
+        Frame::bad(data, format!("{}，LRC 错误", description))
+    }
+}
+
+/// 标注 LIN 帧的 ID、数据和校验方式
+fn lin_frame(data: Vec<u8>) -> Frame {
+    match lin::decode(&data) {
+        Ok(info) => Frame { data, error: None, info: Some(info) },
+        Err(error) => Frame::bad(data, error),
+    }
+}
+
+/// 标注 Modbus RTU 帧的从站地址、功能码和 CRC 状态
+fn modbus_frame(data: Vec<u8>) -> Frame {
+    let description = modbus::describe(&data);
+    if modbus::crc_ok(&data) {
+        Frame { data, error: None, info: Some(format!("{}，CRC 正确", description)) }
+    } else {
+        Frame::bad(data, format!("{}，CRC 错误", description))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// from..=to 的连续字节
+    fn run(from: u8, to: u8) -> Vec<u8> {
+        (from..=to).collect()
+    }
+
+    /// 按帧格式依次推入各段数据，返回切分出的帧（内容和是否有错）
+    fn deframe(spec: &str, chunks: &[&[u8]]) -> Vec<(Vec<u8>, bool)> {
+        let mut deframer = Deframer::new(parse_frame(spec).unwrap());
+        let now = Instant::now();
+        chunks.iter().flat_map(|chunk| deframer.push(chunk, now)).map(|frame| (frame.data, frame.error.is_some())).collect()
+    }
+
+    #[test]
+    fn delimiter_frames() {
+        assert!(matches!(parse_frame(r"delim:\r\n"), Ok(FrameSpec::Delimiter(d)) if d == b"\r\n"));
+        assert!(matches!(parse_frame("delim:0x7E"), Ok(FrameSpec::Delimiter(d)) if d == [0x7E]));
+        assert!(parse_frame("delim:").is_err());
+        assert!(parse_frame("delim:0x7").is_err());
+
+        // 分隔符跨两次读取，空帧跳过
+        let frames = deframe(r"delim:\r\n", &[b"ab\r", b"\ncd\r\n\r\nef"]);
+        assert_eq!(frames, [(b"ab".to_vec(), false), (b"cd".to_vec(), false)]);
+
+        let mut deframer = Deframer::new(parse_frame("delim:0x0D0A").unwrap());
+        deframer.push(b"x\r\nrest", Instant::now());
+        assert_eq!(deframer.take_pending(), b"rest");
+        assert_eq!(parse_frame("delim:;").unwrap().encode(b"AT").unwrap(), b"AT;");
+    }
+
+    #[test]
+    fn cobs_known_vectors() {
+        // 参考 https://en.wikipedia.org/wiki/Consistent_Overhead_Byte_Stuffing 的示例
+        let cases: &[(&[u8], &[u8])] = &[
+            (&[], &[0x01]),
+            (&[0x00], &[0x01, 0x01]),
+            (&[0x00, 0x00], &[0x01, 0x01, 0x01]),
+            (&[0x00, 0x11, 0x00], &[0x01, 0x02, 0x11, 0x01]),
+            (&[0x11, 0x22, 0x00, 0x33], &[0x03, 0x11, 0x22, 0x02, 0x33]),
+            (&[0x11, 0x22, 0x33, 0x44], &[0x05, 0x11, 0x22, 0x33, 0x44]),
+            (&[0x11, 0x00, 0x00, 0x00], &[0x02, 0x11, 0x01, 0x01, 0x01]),
+        ];
+        for (data, encoded) in cases {
+            assert_eq!(cobs_encode(data), *encoded, "编码 {:02X?}", data);
+            assert_eq!(cobs_decode(encoded).as_deref(), Some(*data), "解码 {:02X?}", encoded);
+        }
+    }
+
+    #[test]
+    fn cobs_block_boundaries() {
+        // 恰好 254 个非零字节：一个满块，不补空块
+        let data = run(0x01, 0xFE);
+        let encoded = cobs_encode(&data);
+        assert_eq!(encoded.len(), 255);
+        assert_eq!(encoded[0], 0xFF);
+        assert_eq!(&encoded[1..], &data[..]);
+
+        // 00 01..FE：先是空块，再是满块
+        let mut data = vec![0x00];
+        data.extend(run(0x01, 0xFE));
+        let mut expected = vec![0x01, 0xFF];
+        expected.extend(run(0x01, 0xFE));
+        assert_eq!(cobs_encode(&data), expected);
+
+        // 255 个非零字节：满块之后还有一个字节
+        let data = run(0x01, 0xFF);
+        let mut expected = vec![0xFF];
+        expected.extend(run(0x01, 0xFE));
+        expected.extend([0x02, 0xFF]);
+        assert_eq!(cobs_encode(&data), expected);
+
+        // 02..FF 00：满块之后是 0
+        let mut data = run(0x02, 0xFF);
+        data.push(0x00);
+        let mut expected = vec![0xFF];
+        expected.extend(run(0x02, 0xFF));
+        expected.extend([0x01, 0x01]);
+        assert_eq!(cobs_encode(&data), expected);
+
+        // 03..FF 00 01
+        let mut data = run(0x03, 0xFF);
+        data.extend([0x00, 0x01]);
+        let mut expected = vec![0xFE];
+        expected.extend(run(0x03, 0xFF));
+        expected.extend([0x02, 0x01]);
+        assert_eq!(cobs_encode(&data), expected);
+    }
+
+    #[test]
+    fn cobs_round_trip() {
+        for len in [0, 1, 253, 254, 255, 256, 508, 509, 600] {
+            for zero_every in [0, 1, 7, 254, 255] {
+                let data: Vec<u8> = (0..len)
+                    .map(|i| match zero_every {
+                        0 => (i % 255 + 1) as u8,
+                        n if i % n == 0 => 0,
+                        _ => (i % 251 + 1) as u8,
+                    })
+                    .collect();
+                let encoded = cobs_encode(&data);
+                assert!(!encoded.contains(&0), "长度 {} 的编码中出现 0", len);
+                assert!(encoded.len() <= len + len / 254 + 1, "长度 {} 的编码开销过大", len);
+                assert_eq!(cobs_decode(&encoded), Some(data), "长度 {} 往返不一致", len);
+            }
+        }
+    }
+
+    #[test]
+    fn cobs_rejects_invalid() {
+        assert_eq!(cobs_decode(&[0x00]), None);
+        assert_eq!(cobs_decode(&[0x05, 0x11, 0x22]), None);
+    }
+
+    #[test]
+    fn length_frames() {
+        // 帧头 AA 55、1 字节长度、数据、长度不含的 3 字节尾部
+        let frames = deframe("len:offset=2,size=1,extra=3", &[&[0xAA, 0x55, 0x02, 0x10], &[0x20, 1, 2, 3, 0xAA, 0x55, 0x00, 9, 9, 9, 0xAA]]);
+        assert_eq!(frames, [(vec![0xAA, 0x55, 0x02, 0x10, 0x20, 1, 2, 3], false), (vec![0xAA, 0x55, 0x00, 9, 9, 9], false)]);
+
+        // 2 字节小端长度
+        let frames = deframe("len:size=2,endian=little", &[&[0x03, 0x00, b'a', b'b', b'c', 0x01]]);
+        assert_eq!(frames, [(vec![0x03, 0x00, b'a', b'b', b'c'], false)]);
+
+        // 长度值包含帧头时 extra 为负数；不合理的长度丢弃 1 字节后重新对齐
+        let frames = deframe("len:offset=1,size=1,extra=-2", &[&[0x7F, 0x00, 0x03, 0x42]]);
+        assert_eq!(frames, [(vec![0x00, 0x03, 0x42], false)]);
+
+        assert!(parse_frame("len:size=3").is_err());
+        assert!(parse_frame("len:offset=-1").is_err());
+        assert!(parse_frame("len:endian=middle").is_err());
+        assert!(parse_frame("len:width=1").is_err());
+        assert!(parse_frame("len:").unwrap().encode(b"x").is_err());
+    }
+
+    #[test]
+    fn slip_frames() {
+        // RFC 1055：END 转义为 ESC ESC_END，ESC 转义为 ESC ESC_ESC
+        let encoded = slip_encode(&[0x01, SLIP_END, 0x02, SLIP_ESC, 0x03]);
+        assert_eq!(encoded, [0xC0, 0x01, 0xDB, 0xDC, 0x02, 0xDB, 0xDD, 0x03, 0xC0]);
+        assert_eq!(slip_decode(&encoded[1..encoded.len() - 1]), Some(vec![0x01, 0xC0, 0x02, 0xDB, 0x03]));
+        assert_eq!(slip_decode(&[0xDB, 0x01]), None);
+        assert_eq!(slip_decode(&[0x01, 0xDB]), None);
+
+        // 连续的 END 之间为空，跳过；无效转义标为错误帧
+        let frames = deframe("slip", &[&[0xC0, 0x41, 0xDB], &[0xDC, 0xC0, 0xC0, 0xDB, 0x00, 0xC0, 0x42]]);
+        assert_eq!(frames, [(vec![0x41, 0xC0], false), (vec![0xDB, 0x00], true)]);
+    }
+
+    #[test]
+    fn hdlc_frames() {
+        assert!(matches!(parse_frame("hdlc"), Ok(FrameSpec::Hdlc(Fcs::Fcs16))));
+        assert!(matches!(parse_frame("hdlc:fcs=32"), Ok(FrameSpec::Hdlc(Fcs::Fcs32))));
+        assert!(matches!(parse_frame("hdlc:fcs=none"), Ok(FrameSpec::Hdlc(Fcs::None))));
+        assert!(parse_frame("hdlc:fcs=8").is_err());
+
+        // 标志和转义字节填充为 7D 5E、7D 5D
+        assert_eq!(hdlc_encode(&[0x7E, 0x7D, 0x01], Fcs::None), [0x7E, 0x7D, 0x5E, 0x7D, 0x5D, 0x01, 0x7E]);
+        // FCS 低字节在前：CRC-16/X-25 与 CRC-32 的 "123456789" 校验值为 0x906E、0xCBF43926
+        assert_eq!(hdlc_encode(b"123456789", Fcs::Fcs16), [b"~123456789".as_slice(), &[0x6E, 0x90, 0x7E]].concat());
+        assert_eq!(hdlc_encode(b"123456789", Fcs::Fcs32), [b"~123456789".as_slice(), &[0x26, 0x39, 0xF4, 0xCB, 0x7E]].concat());
+
+        // 相邻帧共用标志字节；编码后再切分得到原内容
+        let a = hdlc_encode(&[0x7E, 0x01], Fcs::Fcs32);
+        let b = hdlc_encode(&[0x02, 0x7D], Fcs::Fcs32);
+        let frames = deframe("hdlc:fcs=32", &[&a[..3], &[&a[3..], &b[1..]].concat()]);
+        assert_eq!(frames, [(vec![0x7E, 0x01], false), (vec![0x02, 0x7D], false)]);
+
+        // FCS 错误、不足以包含 FCS 和中止序列都标为错误帧
+        let mut corrupt = hdlc_encode(b"abc", Fcs::Fcs16);
+        corrupt[1] = b'x';
+        let frames = deframe("hdlc", &[&corrupt, &[0x7E, 0x01, 0x7E, 0x41, 0x42, 0x7D, 0x7E]]);
+        assert_eq!(frames, [(b"xbc".to_vec(), true), (vec![0x01], true), (vec![0x41, 0x42, 0x7D], true)]);
+    }
+
+    #[test]
+    fn modbus_rtu_frames() {
+        assert!(matches!(parse_frame("modbus-rtu:gap=5ms"), Ok(FrameSpec::ModbusRtu { gap: Some(g) }) if g == Duration::from_millis(5)));
+        assert!(parse_frame("modbus-rtu:5ms").is_err());
+
+        // 读保持寄存器的请求和响应在同一次读取中到达，按 CRC 切分
+        let request = [0x01, 0x03, 0x00, 0x00, 0x00, 0x0A, 0xC5, 0xCD];
+        let mut response = vec![0x01, 0x03, 0x02, 0x00, 0x01];
+        response.extend_from_slice(&crate::checksum::crc16_modbus(&response).to_le_bytes());
+        let mut deframer = Deframer::new(parse_frame("modbus-rtu:gap=5ms").unwrap());
+        let start = Instant::now();
+        let frames = deframer.push(&[&request[..], &response, &[0x01, 0x03]].concat(), start);
+        assert_eq!(frames.iter().map(|f| (f.data.as_slice(), f.error.is_some())).collect::<Vec<_>>(), [(&request[..], false), (&response[..], false)]);
+        assert!(frames[0].info.as_deref().unwrap().contains("CRC 正确"));
+
+        // 剩下的不完整帧在空闲超过帧间隔后作为 CRC 错误的帧输出
+        assert!(deframer.poll_idle(start + Duration::from_millis(2)).is_empty());
+        let frames = deframer.poll_idle(start + Duration::from_millis(10));
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].data, [0x01, 0x03]);
+        assert!(frames[0].error.as_deref().unwrap().contains("CRC 错误"));
+        assert!(deframer.poll_idle(start + Duration::from_millis(20)).is_empty());
+    }
+}
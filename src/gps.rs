@@ -0,0 +1,363 @@
+//! GPS 实时定位显示：解析 NMEA（GGA、RMC、VTG、GSA、GSV）和 u-blox UBX（NAV-PVT、NAV-DOP），
+//! 原地刷新显示定位状态、经纬度、海拔、HDOP、卫星数和速度，可把每个定位点记录为 GPX 轨迹
+//!
+//! 输出不是终端或使用 JSON Lines 时，改为每个定位周期输出一行摘要。
+
+use anyhow::{Context, Result};
+use serialport::SerialPort;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, IsTerminal, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::events::{self, Event};
+use crate::framing::{Deframer, FrameSpec};
+use crate::nmea;
+use crate::parse_duration;
+use crate::ubx;
+
+/// gps 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct GpsArgs {
+    /// 把每个有效定位点记录到 GPX 轨迹文件（覆盖已有文件）
+    #[arg(long, value_name = "FILE")]
+    pub gpx: Option<PathBuf>,
+
+    /// 运行指定时长后退出（如 30s、5m）
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub exit_after: Option<Duration>,
+}
+
+/// 原地刷新的间隔
+const REDRAW: Duration = Duration::from_millis(250);
+
+/// 当前定位状态，各字段取自最近收到的语句或消息
+#[derive(Default)]
+struct Fix {
+    source: &'static str,
+    quality: String,
+    /// GSA 中的 2D/3D
+    dimension: Option<&'static str>,
+    valid: bool,
+    date: Option<String>,
+    time: Option<String>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    altitude: Option<f64>,
+    hdop: Option<f64>,
+    used: Option<u32>,
+    /// 各卫星系统（NMEA 发送方）的可见卫星数
+    in_view: HashMap<String, u32>,
+    speed: Option<f64>,
+    course: Option<f64>,
+    updated: Option<Instant>,
+    errors: u64,
+    /// 上次 GGA 之后收到的 RMC 数；连续两条 RMC 之间没有 GGA 时（接收机只输出 RMC），以 RMC 作为定位周期的结束
+    rmc_without_gga: u32,
+}
+
+impl Fix {
+    /// 处理一行 NMEA 语句，GGA（或没有 GGA 时的 RMC）结束一个定位周期时返回 `true`
+    fn nmea(&mut self, line: &str) -> bool {
+        let (address, fields) = nmea::split(line);
+        let field = |i: usize| fields.get(i).copied().unwrap_or("");
+        let number = |i: usize| field(i).parse::<f64>().ok();
+        let (talker, kind) = address.split_at(address.len().min(2));
+        self.source = "NMEA";
+        self.updated = Some(Instant::now());
+        match kind {
+            "GGA" => {
+                self.time = nmea::time(field(0)).or(self.time.take());
+                self.valid = !matches!(field(5), "" | "0");
+                self.quality = nmea::gga_quality(field(5)).to_string();
+                self.set_position(nmea::coordinate(field(1), field(2)), nmea::coordinate(field(3), field(4)));
+                self.used = field(6).parse().ok();
+                self.hdop = number(7);
+                self.altitude = number(8);
+                self.rmc_without_gga = 0;
+                return true;
+            }
+            "RMC" => {
+                self.time = nmea::time(field(0)).or(self.time.take());
+                self.date = nmea::date(field(8)).or(self.date.take());
+                match field(1) {
+                    "A" if !self.valid => {
+                        self.valid = true;
+                        self.quality = "已定位".to_string();
+                    }
+                    "A" => {}
+                    _ => {
+                        self.valid = false;
+                        self.quality = "未定位".to_string();
+                    }
+                }
+                self.set_position(nmea::coordinate(field(2), field(3)), nmea::coordinate(field(4), field(5)));
+                self.speed = number(6).map(|knots| knots * 1.852);
+                self.course = number(7);
+                self.rmc_without_gga += 1;
+                return self.rmc_without_gga > 1;
+            }
+            "VTG" => {
+                self.course = number(0);
+                self.speed = number(6);
+            }
+            "GSA" => {
+                self.dimension = match field(1) {
+                    "2" => Some("2D"),
+                    "3" => Some("3D"),
+                    _ => None,
+                };
+            }
+            "GSV" => {
+                if let Ok(count) = field(2).parse() {
+                    self.in_view.insert(talker.to_string(), count);
+                }
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// 处理一帧 UBX 消息，NAV-PVT 结束一个定位周期时返回 `true`
+    fn ubx(&mut self, frame: &[u8]) -> bool {
+        let payload = &frame[ubx::HEADER_LEN..frame.len() - 2];
+        match (frame[2], frame[3]) {
+            (0x01, 0x07) => {
+                let Some(pvt) = ubx::nav_pvt(payload) else {
+                    return false;
+                };
+                self.source = "UBX";
+                self.updated = Some(Instant::now());
+                self.quality = pvt.fix;
+                self.dimension = None;
+                self.valid = pvt.valid;
+                self.date = Some(pvt.date);
+                self.time = Some(pvt.time);
+                self.set_position(Some(pvt.lat), Some(pvt.lon));
+                self.altitude = Some(pvt.altitude);
+                self.used = Some(pvt.satellites as u32);
+                self.speed = Some(pvt.speed);
+                self.course = Some(pvt.heading);
+                true
+            }
+            (0x01, 0x04) => {
+                self.hdop = ubx::nav_dop_hdop(payload).or(self.hdop);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn set_position(&mut self, lat: Option<f64>, lon: Option<f64>) {
+        if self.valid && lat.is_some() && lon.is_some() {
+            self.lat = lat;
+            self.lon = lon;
+        }
+    }
+
+    fn fix_text(&self) -> String {
+        match (self.quality.is_empty(), self.dimension) {
+            (true, _) => "等待数据".to_string(),
+            (false, Some(dimension)) if self.valid => format!("{}（{}）", dimension, self.quality),
+            _ => self.quality.clone(),
+        }
+    }
+
+    fn position_text(&self) -> (String, String) {
+        match (self.lat, self.lon) {
+            (Some(lat), Some(lon)) => (
+                format!("{:.7}°{}", lat.abs(), if lat < 0.0 { 'S' } else { 'N' }),
+                format!("{:.7}°{}", lon.abs(), if lon < 0.0 { 'W' } else { 'E' }),
+            ),
+            _ => ("-".to_string(), "-".to_string()),
+        }
+    }
+
+    fn satellites_text(&self) -> String {
+        let used = self.used.map_or("-".to_string(), |n| n.to_string());
+        match self.in_view.is_empty() {
+            true => format!("使用 {}", used),
+            false => format!("使用 {}，可见 {}", used, self.in_view.values().sum::<u32>()),
+        }
+    }
+
+    /// 原地刷新显示的各行
+    fn lines(&self, gpx: Option<&Gpx>) -> Vec<String> {
+        let (lat, lon) = self.position_text();
+        let time = match (&self.date, &self.time) {
+            (Some(date), Some(time)) => format!("{} {} UTC", date, time),
+            (None, Some(time)) => format!("{} UTC", time),
+            _ => "-".to_string(),
+        };
+        let mut lines = vec![
+            format!("定位    {}", self.fix_text()),
+            format!("时间    {}", time),
+            format!("纬度    {}", lat),
+            format!("经度    {}", lon),
+            format!("海拔    {}", optional(self.altitude, 1, " m")),
+            format!("HDOP    {}", optional(self.hdop, 2, "")),
+            format!("卫星    {}", self.satellites_text()),
+            format!("速度    {}  航向 {}", optional(self.speed, 1, " km/h"), optional(self.course, 1, "°")),
+        ];
+        if let Some(gpx) = gpx {
+            lines.push(format!("GPX     {}（{} 个点）", gpx.path.display(), gpx.points));
+        }
+        let age = match self.updated {
+            Some(at) => format!("{} 数据，{:.1} s 前更新", self.source, at.elapsed().as_secs_f64()),
+            None => "等待数据...".to_string(),
+        };
+        let errors = if self.errors > 0 { format!("，校验错误 {}", self.errors) } else { String::new() };
+        lines.push(format!("{}{}（按 Ctrl+C 退出）", age, errors));
+        lines
+    }
+
+    /// 一个定位周期的单行摘要
+    fn summary(&self) -> String {
+        let (lat, lon) = self.position_text();
+        format!(
+            "{} {} {} {} 海拔 {} HDOP {} 卫星 {} 速度 {}",
+            self.time.as_deref().unwrap_or("-"),
+            self.fix_text(),
+            lat,
+            lon,
+            optional(self.altitude, 1, " m"),
+            optional(self.hdop, 2, ""),
+            self.satellites_text(),
+            optional(self.speed, 1, " km/h")
+        )
+    }
+}
+
+fn optional(value: Option<f64>, precision: usize, unit: &str) -> String {
+    match value {
+        Some(value) => format!("{:.*}{}", precision, value, unit),
+        None => "-".to_string(),
+    }
+}
+
+/// GPX 轨迹文件：每写入一个点后都补上结尾的闭合标签，程序被中断时文件仍然完整
+struct Gpx {
+    path: PathBuf,
+    file: File,
+    points: u64,
+    /// 上一个点的时间，同一定位周期只记录一次
+    last: Option<String>,
+}
+
+const GPX_HEAD: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+<gpx version=\"1.1\" creator=\"serial-tool\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n  <trk>\n    <trkseg>\n";
+const GPX_TAIL: &str = "    </trkseg>\n  </trk>\n</gpx>\n";
+
+impl Gpx {
+    fn create(path: &Path) -> Result<Self> {
+        let mut file = File::create(path).with_context(|| format!("无法创建 GPX 文件 {}", path.display()))?;
+        file.write_all(format!("{}{}", GPX_HEAD, GPX_TAIL).as_bytes())?;
+        Ok(Gpx { path: path.to_path_buf(), file, points: 0, last: None })
+    }
+
+    fn add(&mut self, fix: &Fix) -> Result<()> {
+        let (Some(lat), Some(lon), true) = (fix.lat, fix.lon, fix.valid) else {
+            return Ok(());
+        };
+        if fix.time.is_some() && fix.time == self.last {
+            return Ok(());
+        }
+        let mut point = format!("      <trkpt lat=\"{:.7}\" lon=\"{:.7}\">", lat, lon);
+        if let Some(altitude) = fix.altitude {
+            point.push_str(&format!("<ele>{:.2}</ele>", altitude));
+        }
+        if let (Some(date), Some(time)) = (&fix.date, &fix.time) {
+            point.push_str(&format!("<time>{}T{}Z</time>", date, time));
+        }
+        if let Some(used) = fix.used {
+            point.push_str(&format!("<sat>{}</sat>", used));
+        }
+        if let Some(hdop) = fix.hdop {
+            point.push_str(&format!("<hdop>{:.2}</hdop>", hdop));
+        }
+        point.push_str("</trkpt>\n");
+        self.file.seek(SeekFrom::End(-(GPX_TAIL.len() as i64)))?;
+        self.file.write_all(format!("{}{}", point, GPX_TAIL).as_bytes())?;
+        self.file.flush()?;
+        self.points += 1;
+        self.last = fix.time.clone();
+        Ok(())
+    }
+}
+
+/// 原地刷新的显示区域
+struct Display {
+    /// 上次输出的行数，刷新时先把光标移回区域开头
+    drawn: usize,
+}
+
+impl Display {
+    fn draw(&mut self, lines: &[String]) -> Result<()> {
+        let mut out = io::stdout().lock();
+        if self.drawn > 0 {
+            write!(out, "\x1b[{}A", self.drawn)?;
+        }
+        for line in lines {
+            write!(out, "\r\x1b[K{}\n", line)?;
+        }
+        out.flush()?;
+        self.drawn = lines.len();
+        Ok(())
+    }
+}
+
+/// 执行 gps 子命令
+pub fn run_gps(port: &mut Box<dyn SerialPort>, opts: &GpsArgs) -> Result<()> {
+    let mut gpx = opts.gpx.as_deref().map(Gpx::create).transpose()?;
+    let mut display = match !events::jsonl() && io::stdout().is_terminal() {
+        true => Some(Display { drawn: 0 }),
+        false => None,
+    };
+    let mut fix = Fix::default();
+    let mut deframer = Deframer::new(FrameSpec::Ubx);
+    let deadline = opts.exit_after.map(|d| Instant::now() + d);
+    let mut last_draw: Option<Instant> = None;
+    let mut buffer = [0u8; 1024];
+    if display.is_none() {
+        events::status("正在接收 GPS 数据（按 Ctrl+C 退出）...");
+    }
+    while deadline.is_none_or(|d| Instant::now() < d) {
+        let n = match port.read(&mut buffer) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => 0,
+            Err(e) => return Err(e).context("读取串口失败"),
+        };
+        if n > 0 {
+            events::emit(Event::Rx(&buffer[..n]));
+        }
+        for frame in deframer.push(&buffer[..n], Instant::now()) {
+            if frame.error.is_some() {
+                fix.errors += 1;
+                continue;
+            }
+            let epoch = match frame.data.starts_with(&ubx::SYNC) {
+                true => fix.ubx(&frame.data),
+                false => fix.nmea(&String::from_utf8_lossy(&frame.data)),
+            };
+            if epoch {
+                if let Some(gpx) = gpx.as_mut() {
+                    gpx.add(&fix)?;
+                }
+                if display.is_none() {
+                    events::status(&fix.summary());
+                }
+            }
+        }
+        if let Some(display) = display.as_mut() {
+            if last_draw.is_none_or(|at| at.elapsed() >= REDRAW) {
+                display.draw(&fix.lines(gpx.as_ref()))?;
+                last_draw = Some(Instant::now());
+            }
+        }
+    }
+    if let Some(gpx) = &gpx {
+        events::status(&format!("已记录 {} 个轨迹点到 {}", gpx.points, gpx.path.display()));
+    }
+    Ok(())
+}
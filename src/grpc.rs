@@ -0,0 +1,1047 @@
+//! gRPC 服务端（HTTP/2 明文，即 h2c）：HTTP/2 帧和流量控制、HPACK 头部解码、gRPC 消息分帧和
+//! protobuf 编解码，供网络桥接让 Go、Python 等语言生成的 gRPC 客户端访问串口；服务定义见 proto/serial_tool.proto
+
+use anyhow::{bail, Context, Result};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::lock;
+
+/// 服务名，方法的路径为 /serialtool.v1.Serial/<方法名>
+pub const SERVICE: &str = "serialtool.v1.Serial";
+
+/// 帧类型
+pub const DATA: u8 = 0x0;
+pub const HEADERS: u8 = 0x1;
+pub const RST_STREAM: u8 = 0x3;
+pub const SETTINGS: u8 = 0x4;
+pub const PING: u8 = 0x6;
+pub const GOAWAY: u8 = 0x7;
+pub const WINDOW_UPDATE: u8 = 0x8;
+pub const CONTINUATION: u8 = 0x9;
+
+/// 帧标志
+pub const END_STREAM: u8 = 0x1;
+pub const ACK: u8 = 0x1;
+pub const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+/// gRPC 状态码
+pub const OK: u32 = 0;
+pub const INVALID_ARGUMENT: u32 = 3;
+pub const UNIMPLEMENTED: u32 = 12;
+pub const UNAVAILABLE: u32 = 14;
+
+/// HTTP/2 错误码
+pub const NO_ERROR: u32 = 0;
+pub const PROTOCOL_ERROR: u32 = 1;
+pub const REFUSED_STREAM: u32 = 7;
+
+/// 客户端连接后先发送的固定前言
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+/// 等待前言的时长
+const PREFACE_TIMEOUT: Duration = Duration::from_secs(5);
+/// 接受的最大帧长度（HTTP/2 的默认值，没有在 SETTINGS 中放大）
+const MAX_FRAME: usize = 16384;
+/// 头部块（HEADERS 和 CONTINUATION 合起来）的最大长度
+pub const MAX_HEADER_BLOCK: usize = 64 * 1024;
+/// 流量控制窗口的初始值
+const INITIAL_WINDOW: i64 = 65535;
+/// HPACK 动态表的大小上限（HTTP/2 的默认值）
+const TABLE_SIZE: usize = 4096;
+/// 单条 gRPC 消息的最大长度
+const MAX_MESSAGE: usize = 4 * 1024 * 1024;
+/// 同时进行的调用数上限
+const MAX_STREAMS: u32 = 100;
+/// 一个流等待发送的数据超过该长度时，生产者等待对方接收
+const MAX_QUEUED: usize = 256 * 1024;
+/// 等待发送窗口时检查连接状态的间隔
+const POLL: Duration = Duration::from_millis(200);
+
+/// 读取客户端的连接前言，返回之后已经读到的数据；不是 HTTP/2 明文连接时报错
+pub fn read_preface(stream: &mut TcpStream) -> Result<Vec<u8>> {
+    let deadline = Instant::now() + PREFACE_TIMEOUT;
+    let mut data = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while data.len() < PREFACE.len() {
+        if !PREFACE.starts_with(&data) {
+            break;
+        }
+        if Instant::now() >= deadline {
+            bail!("等待 HTTP/2 连接前言超时");
+        }
+        match stream.read(&mut buffer) {
+            Ok(0) => bail!("对方在握手前关闭了连接"),
+            Ok(n) => data.extend_from_slice(&buffer[..n]),
+            Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e).context("读取连接前言失败"),
+        }
+    }
+    if !data.starts_with(PREFACE) {
+        // 浏览器、curl 等发来的 HTTP/1.1 请求
+        if data.starts_with(b"GET ") || data.starts_with(b"POST ") || data.starts_with(b"HEAD ") {
+            let body = "这里是串口的 gRPC 服务（HTTP/2 明文），请用 gRPC 客户端以不加密的方式连接，服务定义见 proto/serial_tool.proto\n";
+            let response = format!(
+                "HTTP/1.1 505 HTTP Version Not Supported\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes());
+        }
+        bail!("不是 HTTP/2 明文连接（客户端需使用不加密的连接，如 Go 的 insecure.NewCredentials()、Python 的 grpc.insecure_channel）");
+    }
+    Ok(data.split_off(PREFACE.len()))
+}
+
+/// 收到的一帧
+pub struct Frame {
+    pub kind: u8,
+    pub flags: u8,
+    pub stream: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// 去掉填充（HEADERS 还要去掉优先级字段）后的内容
+    pub fn content(&self) -> Result<&[u8]> {
+        let mut data = &self.payload[..];
+        let mut padding = 0;
+        if self.flags & PADDED != 0 && matches!(self.kind, DATA | HEADERS) {
+            padding = *data.first().context("帧的填充长度缺失")? as usize;
+            data = &data[1..];
+        }
+        if self.flags & PRIORITY != 0 && self.kind == HEADERS {
+            data = data.get(5..).context("HEADERS 帧的优先级字段不完整")?;
+        }
+        data.len().checked_sub(padding).map(|len| &data[..len]).context("帧的填充长度超过了内容")
+    }
+}
+
+fn encode_frame(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 9);
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    out.push(kind);
+    out.push(flags);
+    out.extend_from_slice(&(stream & 0x7FFF_FFFF).to_be_bytes());
+    out.extend_from_slice(payload);
+    out
+}
+
+/// 从字节流中解出完整的帧
+#[derive(Default)]
+pub struct FrameDecoder {
+    pending: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn decode(&mut self, data: &[u8]) -> Result<Vec<Frame>> {
+        self.pending.extend_from_slice(data);
+        let mut frames = Vec::new();
+        while self.pending.len() >= 9 {
+            let len = u32::from_be_bytes([0, self.pending[0], self.pending[1], self.pending[2]]) as usize;
+            if len > MAX_FRAME {
+                bail!("帧长度 {} 超过 {}", len, MAX_FRAME);
+            }
+            if self.pending.len() < 9 + len {
+                break;
+            }
+            let header: Vec<u8> = self.pending.drain(..9).collect();
+            frames.push(Frame {
+                kind: header[3],
+                flags: header[4],
+                stream: u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7FFF_FFFF,
+                payload: self.pending.drain(..len).collect(),
+            });
+        }
+        Ok(frames)
+    }
+}
+
+/// HPACK 静态表（RFC 7541 附录 A），索引从 1 开始
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// 每种码长的符号（按符号排序）。RFC 7541 附录 B 的 Huffman 码是规范 Huffman 码，
+/// 由码长即可还原每个符号的编码；256 为 EOS
+const HUFFMAN: &[(u32, &[u16])] = &[
+    (5, &[48, 49, 50, 97, 99, 101, 105, 111, 115, 116]),
+    (6, &[32, 37, 45, 46, 47, 51, 52, 53, 54, 55, 56, 57, 61, 65, 95, 98, 100, 102, 103, 104, 108, 109, 110, 112, 114, 117]),
+    (7, &[58, 66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79, 80, 81, 82, 83, 84, 85, 86, 87, 89, 106, 107, 113, 118, 119, 120, 121, 122]),
+    (8, &[38, 42, 44, 59, 88, 90]),
+    (10, &[33, 34, 40, 41, 63]),
+    (11, &[39, 43, 124]),
+    (12, &[35, 62]),
+    (13, &[0, 36, 64, 91, 93, 126]),
+    (14, &[94, 125]),
+    (15, &[60, 96, 123]),
+    (19, &[92, 195, 208]),
+    (20, &[128, 130, 131, 162, 184, 194, 224, 226]),
+    (21, &[153, 161, 167, 172, 176, 177, 179, 209, 216, 217, 227, 229, 230]),
+    (22, &[129, 132, 133, 134, 136, 146, 154, 156, 160, 163, 164, 169, 170, 173, 178, 181, 185, 186, 187, 189, 190, 196, 198, 228, 232, 233]),
+    (23, &[1, 135, 137, 138, 139, 140, 141, 143, 147, 149, 150, 151, 152, 155, 157, 158, 165, 166, 168, 174, 175, 180, 182, 183, 188, 191, 197, 231, 239]),
+    (24, &[9, 142, 144, 145, 148, 159, 171, 206, 215, 225, 236, 237]),
+    (25, &[199, 207, 234, 235]),
+    (26, &[192, 193, 200, 201, 202, 205, 210, 213, 218, 219, 238, 240, 242, 243, 255]),
+    (27, &[203, 204, 211, 212, 214, 221, 222, 223, 241, 244, 245, 246, 247, 248, 250, 251, 252, 253, 254]),
+    (28, &[2, 3, 4, 5, 6, 7, 8, 11, 12, 14, 15, 16, 17, 18, 19, 20, 21, 23, 24, 25, 26, 27, 28, 29, 30, 31, 127, 220, 249]),
+    (30, &[10, 13, 22, 256]),
+];
+
+/// 长度为 `len` 位的编码 `code` 对应的符号
+fn huffman_symbol(code: u32, len: u32) -> Option<u16> {
+    // 规范 Huffman 码中，同一码长的编码按符号顺序连续分配，下一码长从上一码长的末尾加一左移开始
+    let mut first = 0u32;
+    let mut bits = HUFFMAN[0].0;
+    for &(length, symbols) in HUFFMAN {
+        first <<= length - bits;
+        bits = length;
+        if length == len {
+            return code.checked_sub(first).and_then(|i| symbols.get(i as usize)).copied();
+        }
+        if length > len {
+            return None;
+        }
+        first += symbols.len() as u32;
+    }
+    None
+}
+
+fn huffman_decode(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() * 8 / 5);
+    let (mut code, mut len) = (0u32, 0u32);
+    for &byte in data {
+        for bit in (0..8).rev() {
+            code = code << 1 | (byte >> bit & 1) as u32;
+            len += 1;
+            match huffman_symbol(code, len) {
+                Some(256) => bail!("头部中出现了 EOS 符号"),
+                Some(symbol) => {
+                    out.push(symbol as u8);
+                    code = 0;
+                    len = 0;
+                }
+                None if len >= 30 => bail!("无效的 Huffman 编码"),
+                None => {}
+            }
+        }
+    }
+    // 结尾只能是不超过 7 位的 EOS 前缀（全为 1）
+    if len > 7 || code != (1 << len) - 1 {
+        bail!("Huffman 编码的结尾无效");
+    }
+    Ok(out)
+}
+
+/// 读取 `prefix` 位前缀的整数（RFC 7541 5.1）
+fn integer(data: &[u8], at: &mut usize, prefix: u32) -> Result<usize> {
+    let mask = (1usize << prefix) - 1;
+    let first = *data.get(*at).context("头部块不完整")? as usize & mask;
+    *at += 1;
+    if first < mask {
+        return Ok(first);
+    }
+    let mut value = mask;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*at).context("头部块不完整")?;
+        *at += 1;
+        value += ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+        if shift > 28 {
+            bail!("头部块中的整数过大");
+        }
+    }
+}
+
+fn string(data: &[u8], at: &mut usize) -> Result<String> {
+    let huffman = *data.get(*at).context("头部块不完整")? & 0x80 != 0;
+    let len = integer(data, at, 7)?;
+    let raw = data.get(*at..*at + len).context("头部块不完整")?;
+    *at += len;
+    let bytes = match huffman {
+        true => huffman_decode(raw)?,
+        false => raw.to_vec(),
+    };
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// HPACK 解码器，连接上所有头部块共用同一个动态表
+pub struct HeaderDecoder {
+    table: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl HeaderDecoder {
+    pub fn new() -> Self {
+        HeaderDecoder { table: VecDeque::new(), size: 0, max_size: TABLE_SIZE }
+    }
+
+    /// 解码一个完整的头部块
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<(String, String)>> {
+        let mut headers = Vec::new();
+        let mut at = 0;
+        while at < block.len() {
+            let byte = block[at];
+            if byte & 0x80 != 0 {
+                let index = integer(block, &mut at, 7)?;
+                headers.push(self.entry(index)?);
+            } else if byte & 0x40 != 0 {
+                // 加入动态表的字面值
+                let header = self.literal(block, &mut at, 6)?;
+                self.insert(header.clone());
+                headers.push(header);
+            } else if byte & 0x20 != 0 {
+                let size = integer(block, &mut at, 5)?;
+                if size > TABLE_SIZE {
+                    bail!("动态表大小 {} 超过 {}", size, TABLE_SIZE);
+                }
+                self.max_size = size;
+                self.evict();
+            } else {
+                // 不加入动态表的字面值
+                headers.push(self.literal(block, &mut at, 4)?);
+            }
+        }
+        Ok(headers)
+    }
+
+    fn literal(&self, block: &[u8], at: &mut usize, prefix: u32) -> Result<(String, String)> {
+        let name = match integer(block, at, prefix)? {
+            0 => string(block, at)?,
+            index => self.entry(index)?.0,
+        };
+        Ok((name, string(block, at)?))
+    }
+
+    fn entry(&self, index: usize) -> Result<(String, String)> {
+        match index {
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.to_string(), value.to_string()))
+            }
+            _ => index
+                .checked_sub(62)
+                .and_then(|index| self.table.get(index))
+                .cloned()
+                .with_context(|| format!("头部块引用了不存在的表项 {}", index)),
+        }
+    }
+
+    fn insert(&mut self, header: (String, String)) {
+        self.size += header.0.len() + header.1.len() + 32;
+        self.table.push_front(header);
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            let Some((name, value)) = self.table.pop_back() else {
+                break;
+            };
+            self.size -= name.len() + value.len() + 32;
+        }
+    }
+}
+
+/// 写入 `prefix` 位前缀的整数，`flags` 为第一个字节前缀之外的高位
+fn put_integer(out: &mut Vec<u8>, flags: u8, prefix: u32, value: usize) {
+    let mask = (1usize << prefix) - 1;
+    if value < mask {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | mask as u8);
+    let mut rest = value - mask;
+    while rest >= 0x80 {
+        out.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    out.push(rest as u8);
+}
+
+/// 不加入动态表的字面值头部（不使用 Huffman 编码），`name_index` 为静态表中名称的索引
+fn put_header(out: &mut Vec<u8>, name_index: Option<usize>, name: &str, value: &str) {
+    match name_index {
+        Some(index) => put_integer(out, 0, 4, index),
+        None => {
+            out.push(0);
+            put_integer(out, 0, 7, name.len());
+            out.extend_from_slice(name.as_bytes());
+        }
+    }
+    put_integer(out, 0, 7, value.len());
+    out.extend_from_slice(value.as_bytes());
+}
+
+/// 应答头部：:status 200 和 content-type
+fn response_headers(out: &mut Vec<u8>) {
+    // 静态表第 8 项 :status 200
+    out.push(0x88);
+    put_header(out, Some(31), "content-type", "application/grpc");
+}
+
+/// 结束调用的 grpc-status 和 grpc-message（按 gRPC 的规定百分号编码）
+fn trailers(out: &mut Vec<u8>, status: u32, message: &str) {
+    put_header(out, None, "grpc-status", &status.to_string());
+    if !message.is_empty() {
+        let mut encoded = String::with_capacity(message.len());
+        for &byte in message.as_bytes() {
+            match byte {
+                b'%' | 0..=0x1F | 0x7F..=0xFF => encoded.push_str(&format!("%{:02X}", byte)),
+                byte => encoded.push(byte as char),
+            }
+        }
+        put_header(out, None, "grpc-message", &encoded);
+    }
+}
+
+/// 一个流的发送状态
+struct Outgoing {
+    window: i64,
+    headers_sent: bool,
+    /// 因发送窗口不足还没有发出的 DATA 内容
+    queued: Vec<u8>,
+    /// 发完 `queued` 后发送的结束头部
+    trailers: Option<Vec<u8>>,
+}
+
+struct SendState {
+    stream: TcpStream,
+    window: i64,
+    initial_window: i64,
+    max_frame: usize,
+    streams: HashMap<u32, Outgoing>,
+    /// 最近开始的流，GOAWAY 时告知对方
+    last_stream: u32,
+    closed: bool,
+}
+
+impl SendState {
+    fn write(&mut self, kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Result<()> {
+        if self.closed {
+            bail!("连接已关闭");
+        }
+        let result = self.stream.write_all(&encode_frame(kind, flags, stream, payload));
+        if result.is_err() {
+            self.closed = true;
+        }
+        result.context("发送失败")
+    }
+
+    /// 在窗口允许的范围内发出排队的数据，发完时发送结束头部
+    fn flush(&mut self, id: u32) -> Result<()> {
+        loop {
+            let max_frame = self.max_frame;
+            let connection_window = self.window;
+            let Some(outgoing) = self.streams.get_mut(&id) else {
+                return Ok(());
+            };
+            if outgoing.queued.is_empty() {
+                if let Some(trailers) = outgoing.trailers.take() {
+                    self.streams.remove(&id);
+                    return self.write(HEADERS, END_HEADERS | END_STREAM, id, &trailers);
+                }
+                return Ok(());
+            }
+            let n = (outgoing.queued.len() as i64).min(outgoing.window).min(connection_window).min(max_frame as i64);
+            if n <= 0 {
+                return Ok(());
+            }
+            let data: Vec<u8> = outgoing.queued.drain(..n as usize).collect();
+            outgoing.window -= n;
+            self.window -= n;
+            self.write(DATA, 0, id, &data)?;
+        }
+    }
+
+    fn flush_all(&mut self) -> Result<()> {
+        let ids: Vec<u32> = self.streams.keys().copied().collect();
+        for id in ids {
+            self.flush(id)?;
+        }
+        Ok(())
+    }
+}
+
+/// 一个连接的发送端：各个流的应答都经过这里，按对方的流量控制窗口发送
+pub struct Connection {
+    state: Mutex<SendState>,
+    /// 窗口增大、数据发出或连接关闭时通知等待的发送者
+    changed: Condvar,
+}
+
+impl Connection {
+    /// 发送服务端的 SETTINGS
+    pub fn new(stream: TcpStream) -> Result<Self> {
+        let mut state = SendState {
+            stream,
+            window: INITIAL_WINDOW,
+            initial_window: INITIAL_WINDOW,
+            max_frame: MAX_FRAME,
+            streams: HashMap::new(),
+            last_stream: 0,
+            closed: false,
+        };
+        // SETTINGS_MAX_CONCURRENT_STREAMS
+        let mut settings = 3u16.to_be_bytes().to_vec();
+        settings.extend_from_slice(&MAX_STREAMS.to_be_bytes());
+        state.write(SETTINGS, 0, 0, &settings)?;
+        Ok(Connection { state: Mutex::new(state), changed: Condvar::new() })
+    }
+
+    /// 发送不受流量控制的帧（PING 应答等）
+    pub fn send_frame(&self, kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Result<()> {
+        lock(&self.state).write(kind, flags, stream, payload)
+    }
+
+    /// 开始一个流；进行中的流过多时返回 false
+    pub fn open(&self, id: u32) -> bool {
+        let mut state = lock(&self.state);
+        state.last_stream = state.last_stream.max(id);
+        if state.streams.len() >= MAX_STREAMS as usize {
+            return false;
+        }
+        let window = state.initial_window;
+        state.streams.insert(id, Outgoing { window, headers_sent: false, queued: Vec::new(), trailers: None });
+        true
+    }
+
+    /// 发送应答头部（之后才发送消息时不必调用，发送消息时会先发送头部）
+    pub fn send_headers(&self, id: u32) -> Result<()> {
+        let mut state = lock(&self.state);
+        let Some(outgoing) = state.streams.get_mut(&id).filter(|outgoing| !outgoing.headers_sent) else {
+            return Ok(());
+        };
+        outgoing.headers_sent = true;
+        let mut block = Vec::new();
+        response_headers(&mut block);
+        state.write(HEADERS, END_HEADERS, id, &block)
+    }
+
+    /// 发送一条 gRPC 消息；窗口不足的部分排队，等对方增大窗口后发出，不会阻塞
+    pub fn send_message(&self, id: u32, message: &[u8]) -> Result<()> {
+        self.send_headers(id)?;
+        let mut state = lock(&self.state);
+        let Some(outgoing) = state.streams.get_mut(&id).filter(|outgoing| outgoing.trailers.is_none()) else {
+            return Ok(());
+        };
+        outgoing.queued.push(0);
+        outgoing.queued.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        outgoing.queued.extend_from_slice(message);
+        state.flush(id)
+    }
+
+    /// 等待流中排队的数据减少到可以继续发送，流已结束或连接已关闭时返回 false
+    pub fn wait_writable(&self, id: u32) -> bool {
+        let mut state = lock(&self.state);
+        loop {
+            match state.streams.get(&id) {
+                _ if state.closed => return false,
+                Some(outgoing) if outgoing.trailers.is_none() => {
+                    if outgoing.queued.len() < MAX_QUEUED {
+                        return true;
+                    }
+                }
+                _ => return false,
+            }
+            state = self.changed.wait_timeout(state, POLL).unwrap_or_else(|e| e.into_inner()).0;
+        }
+    }
+
+    /// 以 `status` 结束调用（排队的数据发完后发送）；还没有发送应答头部时只发送一个头部块
+    pub fn finish(&self, id: u32, status: u32, message: &str) -> Result<()> {
+        let mut state = lock(&self.state);
+        let Some(outgoing) = state.streams.get_mut(&id).filter(|outgoing| outgoing.trailers.is_none()) else {
+            return Ok(());
+        };
+        let mut block = Vec::new();
+        if !outgoing.headers_sent {
+            response_headers(&mut block);
+        }
+        trailers(&mut block, status, message);
+        outgoing.trailers = Some(block);
+        let result = state.flush(id);
+        self.changed.notify_all();
+        result
+    }
+
+    /// 对方取消了流
+    pub fn reset(&self, id: u32) {
+        lock(&self.state).streams.remove(&id);
+        self.changed.notify_all();
+    }
+
+    /// 应用对方的 SETTINGS 并确认
+    pub fn apply_settings(&self, payload: &[u8]) -> Result<()> {
+        let mut state = lock(&self.state);
+        for setting in payload.chunks_exact(6) {
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match u16::from_be_bytes([setting[0], setting[1]]) {
+                // SETTINGS_INITIAL_WINDOW_SIZE：按差值调整所有流的窗口
+                4 => {
+                    let delta = value as i64 - state.initial_window;
+                    state.initial_window = value as i64;
+                    for outgoing in state.streams.values_mut() {
+                        outgoing.window += delta;
+                    }
+                }
+                // SETTINGS_MAX_FRAME_SIZE
+                5 => state.max_frame = (value as usize).clamp(MAX_FRAME, 0xFF_FFFF),
+                _ => {}
+            }
+        }
+        state.write(SETTINGS, ACK, 0, &[])?;
+        let result = state.flush_all();
+        self.changed.notify_all();
+        result
+    }
+
+    /// 对方增大了连接（`stream` 为 0）或一个流的窗口
+    pub fn window_update(&self, stream: u32, payload: &[u8]) -> Result<()> {
+        let Some(bytes) = payload.get(..4) else {
+            bail!("WINDOW_UPDATE 帧长度无效");
+        };
+        let increment = (u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) & 0x7FFF_FFFF) as i64;
+        let mut state = lock(&self.state);
+        let result = match stream {
+            0 => {
+                state.window += increment;
+                state.flush_all()
+            }
+            id => match state.streams.get_mut(&id) {
+                Some(outgoing) => {
+                    outgoing.window += increment;
+                    state.flush(id)
+                }
+                None => Ok(()),
+            },
+        };
+        self.changed.notify_all();
+        result
+    }
+
+    /// 收到了 `len` 字节的 DATA，让对方可以继续发送
+    pub fn consumed(&self, stream: u32, len: usize) -> Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        let increment = (len as u32).to_be_bytes();
+        let mut state = lock(&self.state);
+        state.write(WINDOW_UPDATE, 0, 0, &increment)?;
+        if state.streams.contains_key(&stream) {
+            state.write(WINDOW_UPDATE, 0, stream, &increment)?;
+        }
+        Ok(())
+    }
+
+    /// 发送 GOAWAY 告知对方连接即将关闭
+    pub fn go_away(&self, code: u32, reason: &str) {
+        let mut state = lock(&self.state);
+        let mut payload = state.last_stream.to_be_bytes().to_vec();
+        payload.extend_from_slice(&code.to_be_bytes());
+        payload.extend_from_slice(reason.as_bytes());
+        let _ = state.write(GOAWAY, 0, 0, &payload);
+    }
+
+    pub fn close(&self) {
+        lock(&self.state).closed = true;
+        self.changed.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        lock(&self.state).closed
+    }
+}
+
+/// 从 DATA 中解出完整的 gRPC 消息（5 字节前缀：是否压缩和长度）
+#[derive(Default)]
+pub struct MessageDecoder {
+    pending: Vec<u8>,
+}
+
+impl MessageDecoder {
+    pub fn decode(&mut self, data: &[u8]) -> Result<Vec<Vec<u8>>> {
+        self.pending.extend_from_slice(data);
+        let mut messages = Vec::new();
+        while self.pending.len() >= 5 {
+            if self.pending[0] != 0 {
+                bail!("不支持压缩的消息（客户端不要设置 grpc-encoding）");
+            }
+            let len = u32::from_be_bytes([self.pending[1], self.pending[2], self.pending[3], self.pending[4]]) as usize;
+            if len > MAX_MESSAGE {
+                bail!("消息长度 {} 超过 {}", len, MAX_MESSAGE);
+            }
+            if self.pending.len() < 5 + len {
+                break;
+            }
+            messages.push(self.pending.drain(..5 + len).skip(5).collect());
+        }
+        Ok(messages)
+    }
+}
+
+/// protobuf 字段的值
+pub enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    /// 用不到的 fixed32、fixed64 字段
+    Fixed,
+}
+
+fn varint(data: &[u8], at: &mut usize) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*at).context("消息不完整")?;
+        *at += 1;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("消息中的整数过长")
+}
+
+/// 解出消息的所有字段：字段编号和值
+pub fn fields(data: &[u8]) -> Result<Vec<(u32, Value<'_>)>> {
+    let mut fields = Vec::new();
+    let mut at = 0;
+    while at < data.len() {
+        let key = varint(data, &mut at)?;
+        let value = match key & 0x07 {
+            0 => Value::Varint(varint(data, &mut at)?),
+            1 | 5 => {
+                at += if key & 0x07 == 1 { 8 } else { 4 };
+                Value::Fixed
+            }
+            2 => {
+                let len = varint(data, &mut at)? as usize;
+                let bytes = data.get(at..at.saturating_add(len)).context("消息不完整")?;
+                at += len;
+                Value::Bytes(bytes)
+            }
+            kind => bail!("不支持的字段类型 {}", kind),
+        };
+        fields.push(((key >> 3) as u32, value));
+    }
+    if at > data.len() {
+        bail!("消息不完整");
+    }
+    Ok(fields)
+}
+
+fn put_raw_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// 写入整数（uint64、int64、bool 等）字段
+pub fn put_varint(out: &mut Vec<u8>, field: u32, value: u64) {
+    put_raw_varint(out, (field as u64) << 3);
+    put_raw_varint(out, value);
+}
+
+/// 写入 bytes 字段
+pub fn put_bytes(out: &mut Vec<u8>, field: u32, data: &[u8]) {
+    put_raw_varint(out, (field as u64) << 3 | 2);
+    put_raw_varint(out, data.len() as u64);
+    out.extend_from_slice(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_hex;
+    use std::net::TcpListener;
+
+    fn headers(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter().map(|&(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    type Headers<'a> = &'a [(&'a str, &'a str)];
+
+    /// 依次解码 RFC 7541 附录 C 中的几个头部块（十六进制），检查解出的头部以及之后动态表的内容和大小
+    fn check_blocks(decoder: &mut HeaderDecoder, blocks: &[(&str, Headers, Headers, usize)]) {
+        for (i, &(block, expected, table, size)) in blocks.iter().enumerate() {
+            assert_eq!(decoder.decode(&parse_hex(block).unwrap()).unwrap(), headers(expected), "第 {} 个头部块", i + 1);
+            assert_eq!(decoder.table.iter().cloned().collect::<Vec<_>>(), headers(table), "第 {} 个头部块后的动态表", i + 1);
+            assert_eq!(decoder.size, size, "第 {} 个头部块后的动态表大小", i + 1);
+        }
+    }
+
+    const REQUEST_1: &[(&str, &str)] = &[(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com")];
+    const REQUEST_2: &[(&str, &str)] =
+        &[(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com"), ("cache-control", "no-cache")];
+    const REQUEST_3: &[(&str, &str)] =
+        &[(":method", "GET"), (":scheme", "https"), (":path", "/index.html"), (":authority", "www.example.com"), ("custom-key", "custom-value")];
+    const TABLE_1: &[(&str, &str)] = &[(":authority", "www.example.com")];
+    const TABLE_2: &[(&str, &str)] = &[("cache-control", "no-cache"), (":authority", "www.example.com")];
+    const TABLE_3: &[(&str, &str)] = &[("custom-key", "custom-value"), ("cache-control", "no-cache"), (":authority", "www.example.com")];
+
+    #[test]
+    fn hpack_requests() {
+        // C.3：不使用 Huffman 编码
+        check_blocks(
+            &mut HeaderDecoder::new(),
+            &[
+                ("828684410f7777772e6578616d706c652e636f6d", REQUEST_1, TABLE_1, 57),
+                ("828684be58086e6f2d6361636865", REQUEST_2, TABLE_2, 110),
+                ("828785bf400a637573746f6d2d6b65790c637573746f6d2d76616c7565", REQUEST_3, TABLE_3, 164),
+            ],
+        );
+        // C.4：同样的请求，使用 Huffman 编码
+        check_blocks(
+            &mut HeaderDecoder::new(),
+            &[
+                ("828684418cf1e3c2e5f23a6ba0ab90f4ff", REQUEST_1, TABLE_1, 57),
+                ("828684be5886a8eb10649cbf", REQUEST_2, TABLE_2, 110),
+                ("828785bf408825a849e95ba97d7f8925a849e95bb8e8b4bf", REQUEST_3, TABLE_3, 164),
+            ],
+        );
+    }
+
+    #[test]
+    fn hpack_responses_with_eviction() {
+        // C.6：动态表大小为 256，后面的应答使旧的表项被逐出
+        let mut decoder = HeaderDecoder::new();
+        decoder.max_size = 256;
+        let date_21 = ("date", "Mon, 21 Oct 2013 20:13:21 GMT");
+        let date_22 = ("date", "Mon, 21 Oct 2013 20:13:22 GMT");
+        let location = ("location", "https://www.example.com");
+        let cookie = ("set-cookie", "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1");
+        check_blocks(
+            &mut decoder,
+            &[
+                (
+                    "488264025885aec3771a4b6196d07abe941054d444a8200595040b8166e082a62d1bff6e919d29ad171863c78f0b97c8e9ae82ae43d3",
+                    &[(":status", "302"), ("cache-control", "private"), date_21, location],
+                    &[location, date_21, ("cache-control", "private"), (":status", "302")],
+                    222,
+                ),
+                (
+                    "4883640effc1c0bf",
+                    &[(":status", "307"), ("cache-control", "private"), date_21, location],
+                    &[(":status", "307"), location, date_21, ("cache-control", "private")],
+                    222,
+                ),
+                (
+                    "88c16196d07abe941054d444a8200595040b8166e084a62d1bffc05a839bd9ab77ad94e7821dd7f2e6c7b335dfdfcd5b3960d5af27087f3672c1ab270fb5291f9587316065c003ed4ee5b1063d5007",
+                    &[(":status", "200"), ("cache-control", "private"), date_22, location, ("content-encoding", "gzip"), cookie],
+                    &[cookie, ("content-encoding", "gzip"), date_22],
+                    215,
+                ),
+            ],
+        );
+
+        // 动态表大小更新为 0 时清空动态表，超过上限时报错
+        assert!(decoder.decode(&[0x20]).unwrap().is_empty());
+        assert!(decoder.table.is_empty() && decoder.size == 0);
+        assert!(decoder.decode(&[0x3F, 0xE2, 0x1F]).is_err());
+        assert!(decoder.decode(&[0xBE]).is_err(), "引用不存在的表项");
+    }
+
+    #[test]
+    fn hpack_primitives() {
+        // C.1：5 位前缀的 10、1337 和 8 位前缀的 42
+        for (prefix, value, encoded) in [(5, 10, &[0x0A][..]), (5, 1337, &[0x1F, 0x9A, 0x0A]), (8, 42, &[0x2A])] {
+            let mut out = Vec::new();
+            put_integer(&mut out, 0, prefix, value);
+            assert_eq!(out, encoded);
+            assert_eq!(integer(encoded, &mut 0, prefix).unwrap(), value);
+        }
+        assert!(integer(&[0x1F, 0x9A], &mut 0, 5).is_err());
+
+        assert_eq!(huffman_decode(&parse_hex("f1e3c2e5f23a6ba0ab90f4ff").unwrap()).unwrap(), b"www.example.com");
+        assert_eq!(huffman_decode(&[]).unwrap(), b"");
+        // 填充超过 7 位、填充不全为 1、包含 EOS 都是错误
+        assert!(huffman_decode(&[0xF8, 0xFF]).is_err(), "'&' 之后是 8 位填充");
+        assert!(huffman_decode(&[0x00]).is_err(), "'0' 之后的填充为 0");
+        assert!(huffman_decode(&[0xFF, 0xFF, 0xFF, 0xFF]).is_err());
+
+        // 编码的头部能被解码器还原
+        let mut block = Vec::new();
+        response_headers(&mut block);
+        trailers(&mut block, UNAVAILABLE, "串口 100%");
+        let decoded = HeaderDecoder::new().decode(&block).unwrap();
+        assert_eq!(
+            decoded,
+            headers(&[
+                (":status", "200"),
+                ("content-type", "application/grpc"),
+                ("grpc-status", "14"),
+                ("grpc-message", "%E4%B8%B2%E5%8F%A3 100%25"),
+            ])
+        );
+    }
+
+    #[test]
+    fn http2_frames() {
+        let mut frame = encode_frame(HEADERS, END_HEADERS | PADDED | PRIORITY, 0x8000_0003, &[2, 0, 0, 0, 0, 16, 0x82, 0x86, 0, 0]);
+        assert_eq!(frame[..9], [0, 0, 10, HEADERS, 0x2C, 0, 0, 0, 3]);
+        frame.extend(encode_frame(DATA, END_STREAM, 1, b"abc"));
+
+        let mut decoder = FrameDecoder::default();
+        assert!(decoder.decode(&frame[..12]).unwrap().is_empty());
+        let frames = decoder.decode(&frame[12..]).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].kind, frames[0].stream, frames[0].content().unwrap()), (HEADERS, 3, &[0x82, 0x86][..]));
+        assert_eq!((frames[1].kind, frames[1].flags, frames[1].stream, frames[1].content().unwrap()), (DATA, END_STREAM, 1, &b"abc"[..]));
+
+        let bad = Frame { kind: DATA, flags: PADDED, stream: 1, payload: vec![5, 1, 2] };
+        assert!(bad.content().is_err(), "填充超过了内容");
+        assert!(FrameDecoder::default().decode(&[0x00, 0x40, 0x01, 0, 0, 0, 0, 0, 1]).is_err(), "超过最大帧长度");
+    }
+
+    #[test]
+    fn grpc_messages() {
+        // 5 字节前缀：不压缩、长度（大端）；消息可以跨 DATA 帧，一帧中也可以有多条
+        let mut decoder = MessageDecoder::default();
+        assert!(decoder.decode(&[0, 0, 0, 0, 2, b'h']).unwrap().is_empty());
+        assert_eq!(decoder.decode(&[b'i', 0, 0, 0, 0, 0, 0, 0]).unwrap(), [b"hi".to_vec(), Vec::new()]);
+        assert!(decoder.decode(&[0]).unwrap().is_empty());
+
+        assert!(MessageDecoder::default().decode(&[1, 0, 0, 0, 1, 0]).is_err(), "压缩的消息");
+        assert!(MessageDecoder::default().decode(&[0, 0x7F, 0, 0, 0]).is_err(), "超过最大长度");
+    }
+
+    #[test]
+    fn grpc_stream() {
+        // 本机连接上检查应答的帧：SETTINGS、应答头部、带 5 字节前缀的 DATA、结束头部
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut client, _) = listener.accept().unwrap();
+        let connection = Connection::new(server).unwrap();
+        // 流 1 的发送窗口只有 4 字节，超出的部分等对方增大窗口后发出
+        connection.apply_settings(&[0, 4, 0, 0, 0, 4]).unwrap();
+        assert!(connection.open(1));
+        connection.send_message(1, b"\x0A\x02hi").unwrap();
+        connection.finish(1, OK, "").unwrap();
+        connection.window_update(1, &[0, 0, 0, 100]).unwrap();
+        connection.close();
+
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut decoder = FrameDecoder::default();
+        let mut frames = Vec::new();
+        let mut buffer = [0u8; 1024];
+        while frames.len() < 6 {
+            let n = client.read(&mut buffer).unwrap();
+            assert!(n > 0, "连接提前关闭");
+            frames.extend(decoder.decode(&buffer[..n]).unwrap());
+        }
+        let kinds: Vec<(u8, u8, u32)> = frames.iter().map(|frame| (frame.kind, frame.flags, frame.stream)).collect();
+        assert_eq!(
+            kinds,
+            [(SETTINGS, 0, 0), (SETTINGS, ACK, 0), (HEADERS, END_HEADERS, 1), (DATA, 0, 1), (DATA, 0, 1), (HEADERS, END_HEADERS | END_STREAM, 1)]
+        );
+        assert_eq!(frames[0].payload, [0, 3, 0, 0, 0, 100]);
+        assert_eq!([&frames[3].payload[..], &frames[4].payload[..]].concat(), [0, 0, 0, 0, 4, 0x0A, 0x02, b'h', b'i']);
+        assert_eq!(frames[3].payload.len(), 4);
+        let mut headers = HeaderDecoder::new();
+        assert_eq!(headers.decode(&frames[2].payload).unwrap()[0], (":status".to_string(), "200".to_string()));
+        assert_eq!(headers.decode(&frames[5].payload).unwrap(), [("grpc-status".to_string(), "0".to_string())]);
+    }
+
+    #[test]
+    fn protobuf_fields() {
+        // SendReply { written: 2, total: 300 }
+        let mut reply = Vec::new();
+        put_varint(&mut reply, 1, 2);
+        put_varint(&mut reply, 2, 300);
+        assert_eq!(reply, [0x08, 0x02, 0x10, 0xAC, 0x02]);
+        // ReceivedChunk { data: "hi", sequence: 1, timestamp_us: 1700000000000000 }
+        let mut chunk = Vec::new();
+        put_bytes(&mut chunk, 1, b"hi");
+        put_varint(&mut chunk, 2, 1);
+        put_varint(&mut chunk, 3, 1_700_000_000_000_000);
+        assert_eq!(chunk, parse_hex("0A026869 1001 188080F9C0C1C48203").unwrap());
+
+        // ControlRequest { dtr: false, break_ms: 250 }，中间夹着未知的 fixed32、fixed64 字段
+        let data = parse_hex("0800 2501020304 290102030405060708 18FA01 120568656C6C6F").unwrap();
+        let fields = fields(&data).unwrap();
+        let summary: Vec<String> = fields
+            .iter()
+            .map(|(number, value)| match value {
+                Value::Varint(v) => format!("{}={}", number, v),
+                Value::Bytes(b) => format!("{}={}", number, String::from_utf8_lossy(b)),
+                Value::Fixed => format!("{}=fixed", number),
+            })
+            .collect();
+        assert_eq!(summary, ["1=0", "4=fixed", "5=fixed", "3=250", "2=hello"]);
+        assert!(super::fields(&[]).unwrap().is_empty());
+
+        for data in ["0A05 6869", "08", "0B00", "25 0102", "08 FFFFFFFFFFFFFFFFFFFF01"] {
+            assert!(super::fields(&parse_hex(data).unwrap()).is_err(), "{} 应解析失败", data);
+        }
+    }
+}
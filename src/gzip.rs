@@ -0,0 +1,272 @@
+//! 最小 gzip 编码器（LZ77 + 固定哈夫曼编码），用于压缩轮转后的日志文件
+
+/// LZ77 滑动窗口大小（DEFLATE 允许的最大距离）
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// 每个位置最多回溯的候选数，越大压缩率越高、速度越慢
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+/// 按 gzip 格式（RFC 1952）压缩数据
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // 头部：魔数、DEFLATE、无标志、无时间戳、未知系统
+    let mut out = vec![0x1f, 0x8b, 0x08, 0x00, 0, 0, 0, 0, 0x00, 0xff];
+    out.extend_from_slice(&deflate(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// CRC-32（IEEE 802.3，gzip/zip 使用的多项式）
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// 按位写入（DEFLATE 的比特流从低位开始）
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    nbits: u32,
+}
+
+impl BitWriter {
+    fn write_bits(&mut self, value: u32, n: u32) {
+        self.acc |= (value as u64) << self.nbits;
+        self.nbits += n;
+        while self.nbits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.nbits -= 8;
+        }
+    }
+
+    /// 哈夫曼码按高位在前写入
+    fn write_code(&mut self, code: u32, len: u32) {
+        let reversed = code.reverse_bits() >> (32 - len);
+        self.write_bits(reversed, len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.nbits > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+/// 写入字面量/长度符号（固定哈夫曼表）
+fn write_symbol(w: &mut BitWriter, sym: u32) {
+    match sym {
+        0..=143 => w.write_code(0x30 + sym, 8),
+        144..=255 => w.write_code(0x190 + sym - 144, 9),
+        256..=279 => w.write_code(sym - 256, 7),
+        _ => w.write_code(0xC0 + sym - 280, 8),
+    }
+}
+
+fn write_match(w: &mut BitWriter, len: usize, dist: usize) {
+    let li = LENGTH_BASE.iter().rposition(|&b| b as usize <= len).unwrap_or(0);
+    write_symbol(w, 257 + li as u32);
+    w.write_bits((len - LENGTH_BASE[li] as usize) as u32, LENGTH_EXTRA[li] as u32);
+
+    let di = DIST_BASE.iter().rposition(|&b| b as usize <= dist).unwrap_or(0);
+    w.write_code(di as u32, 5);
+    w.write_bits((dist - DIST_BASE[di] as usize) as u32, DIST_EXTRA[di] as u32);
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let v = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+    (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+/// 把位置 `pos` 加入哈希链
+fn insert(data: &[u8], pos: usize, head: &mut [usize], prev: &mut [usize]) {
+    if pos + MIN_MATCH <= data.len() {
+        let h = hash(&data[pos..]);
+        prev[pos % WINDOW] = head[h];
+        head[h] = pos;
+    }
+}
+
+/// DEFLATE 压缩（RFC 1951），整个输入作为一个固定哈夫曼块输出
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter { out: Vec::with_capacity(data.len() / 2 + 16), acc: 0, nbits: 0 };
+    w.write_bits(1, 1); // BFINAL
+    w.write_bits(1, 2); // BTYPE = 01（固定哈夫曼）
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW];
+
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_len, mut best_dist) = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let max = (data.len() - i).min(MAX_MATCH);
+            let mut cand = head[hash(&data[i..])];
+            let mut chain = 0;
+            while cand != usize::MAX && cand < i && i - cand <= WINDOW && chain < MAX_CHAIN {
+                let len = data[cand..].iter().zip(&data[i..i + max]).take_while(|(a, b)| a == b).count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = i - cand;
+                    if len == max {
+                        break;
+                    }
+                }
+                let next = prev[cand % WINDOW];
+                if next >= cand {
+                    break; // 窗口中的旧记录已被覆盖
+                }
+                cand = next;
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            write_match(&mut w, best_len, best_dist);
+            for pos in i..i + best_len {
+                insert(data, pos, &mut head, &mut prev);
+            }
+            i += best_len;
+        } else {
+            write_symbol(&mut w, data[i] as u32);
+            insert(data, i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+
+    write_symbol(&mut w, 256); // 块结束
+    w.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 只支持固定哈夫曼块的 DEFLATE 解码，用来检验压缩结果
+    fn inflate_fixed(data: &[u8]) -> Vec<u8> {
+        let mut pos = 0usize;
+        let mut bit = |n: u32| -> u32 {
+            let mut value = 0;
+            for i in 0..n {
+                value |= ((data[pos / 8] >> (pos % 8)) & 1) as u32 * (1 << i);
+                pos += 1;
+            }
+            value
+        };
+        // 哈夫曼码高位在前，逐位读入
+        let code = |bit: &mut dyn FnMut(u32) -> u32, len: u32| (0..len).fold(0, |code, _| code << 1 | bit(1));
+
+        let mut out = Vec::new();
+        loop {
+            let last = bit(1);
+            assert_eq!(bit(2), 1, "只应出现固定哈夫曼块");
+            loop {
+                let mut sym = code(&mut bit, 7);
+                sym = match sym {
+                    0..=0x17 => sym + 256,
+                    _ => {
+                        sym = sym << 1 | bit(1);
+                        match sym {
+                            0x30..=0xBF => sym - 0x30,
+                            0xC0..=0xC7 => sym - 0xC0 + 280,
+                            _ => (sym << 1 | bit(1)) - 0x190 + 144,
+                        }
+                    }
+                };
+                match sym {
+                    0..=255 => out.push(sym as u8),
+                    256 => break,
+                    _ => {
+                        let li = (sym - 257) as usize;
+                        let len = LENGTH_BASE[li] as usize + bit(LENGTH_EXTRA[li] as u32) as usize;
+                        let di = code(&mut bit, 5) as usize;
+                        let dist = DIST_BASE[di] as usize + bit(DIST_EXTRA[di] as u32) as usize;
+                        assert!(dist <= out.len(), "距离超出已解码的数据");
+                        for _ in 0..len {
+                            out.push(out[out.len() - dist]);
+                        }
+                    }
+                }
+            }
+            if last == 1 {
+                return out;
+            }
+        }
+    }
+
+    #[test]
+    fn crc32_check_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414F_A339);
+    }
+
+    #[test]
+    fn deflate_known_output() {
+        // 与 zlib 固定哈夫曼（Z_FIXED）的输出一致
+        assert_eq!(deflate(b""), [0x03, 0x00]);
+        assert_eq!(deflate(b"a"), [0x4B, 0x04, 0x00]);
+        // "abc" 加一个长度 9、距离 3 的重复（zlib 拆成两段，结果同样能被 zlib 解压）
+        assert_eq!(deflate(b"abcabcabcabc"), [0x4B, 0x4C, 0x4A, 0x86, 0x23, 0x00]);
+    }
+
+    #[test]
+    fn gzip_header_and_trailer() {
+        let data = b"hello hello hello\n";
+        let gz = compress(data);
+        assert_eq!(gz[..10], [0x1f, 0x8b, 0x08, 0x00, 0, 0, 0, 0, 0x00, 0xff]);
+        let trailer = &gz[gz.len() - 8..];
+        assert_eq!(trailer[..4], crc32(data).to_le_bytes());
+        assert_eq!(trailer[4..], (data.len() as u32).to_le_bytes());
+        assert_eq!(inflate_fixed(&gz[10..gz.len() - 8]), data);
+    }
+
+    #[test]
+    fn deflate_round_trip() {
+        let mut seed = 0x1234_5678u32;
+        let mut noise = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as u8
+        };
+        let log: Vec<u8> = (0..2000).flat_map(|i| format!("[12:00:{:02}] temp={} ok\r\n", i % 60, i % 7).into_bytes()).collect();
+        let mut distant: Vec<u8> = (0..40_000).map(|_| noise()).collect();
+        distant.extend_from_within(100..400);
+        let cases: Vec<Vec<u8>> = vec![
+            vec![0; 1000],
+            vec![0xFF; 259],
+            (0..=255).collect(),
+            (0..5000).map(|_| noise()).collect(),
+            log,
+            distant,
+        ];
+        for data in cases {
+            assert_eq!(inflate_fixed(&deflate(&data)), data, "长度 {} 往返不一致", data.len());
+        }
+    }
+}
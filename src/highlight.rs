@@ -0,0 +1,96 @@
+//! 按正则规则为监听输出着色
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+
+/// 一条高亮规则：匹配的文本使用指定颜色
+#[derive(Debug, Clone)]
+pub struct HighlightRule {
+    pattern: Regex,
+    /// ANSI SGR 参数（如 "31" 表示红色）
+    style: &'static str,
+}
+
+/// 可用的颜色名
+const COLORS: &[(&str, &str)] = &[
+    ("red", "31"),
+    ("green", "32"),
+    ("yellow", "33"),
+    ("blue", "34"),
+    ("magenta", "35"),
+    ("cyan", "36"),
+    ("white", "37"),
+    ("bold", "1"),
+    ("bright-red", "1;31"),
+    ("bright-green", "1;32"),
+    ("bright-yellow", "1;33"),
+    ("inverse", "7"),
+];
+
+/// 解析 "PATTERN=COLOR" 形式的规则（以最后一个 '=' 分隔，正则中可包含 '='）
+pub fn parse_rule(s: &str) -> std::result::Result<HighlightRule, String> {
+    let (pattern, color) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("无效的高亮规则 '{}'，应为 正则=颜色", s))?;
+    let style = COLORS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(color.trim()))
+        .map(|(_, code)| *code)
+        .ok_or_else(|| {
+            format!(
+                "未知的颜色 '{}'，可用颜色：{}",
+                color,
+                COLORS.iter().map(|(n, _)| *n).collect::<Vec<_>>().join(", ")
+            )
+        })?;
+    let pattern = Regex::new(pattern).map_err(|e| format!("无效的正则 '{}': {}", pattern, e))?;
+    Ok(HighlightRule { pattern, style })
+}
+
+/// 从规则文件读取：每行一条 "PATTERN=COLOR"，忽略空行和 # 注释
+pub fn load_rules(path: &Path) -> Result<Vec<HighlightRule>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("读取高亮规则文件 {} 失败", path.display()))?;
+
+    let mut rules = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_rule(line) {
+            Ok(rule) => rules.push(rule),
+            Err(e) => bail!("{} 第 {} 行：{}", path.display(), i + 1, e),
+        }
+    }
+    Ok(rules)
+}
+
+/// 为文本中匹配的部分加上颜色，先出现的规则优先，重叠部分不重复着色
+pub fn apply(rules: &[HighlightRule], text: &str) -> String {
+    let mut spans: Vec<(usize, usize, &str)> = Vec::new();
+    for rule in rules {
+        for m in rule.pattern.find_iter(text) {
+            if m.is_empty() || spans.iter().any(|&(s, e, _)| m.start() < e && s < m.end()) {
+                continue;
+            }
+            spans.push((m.start(), m.end(), rule.style));
+        }
+    }
+    if spans.is_empty() {
+        return text.to_string();
+    }
+    spans.sort_by_key(|&(start, _, _)| start);
+
+    let mut out = String::with_capacity(text.len() + spans.len() * 10);
+    let mut pos = 0;
+    for (start, end, style) in spans {
+        out.push_str(&text[pos..start]);
+        out.push_str(&format!("\x1b[{}m{}\x1b[0m", style, &text[start..end]));
+        pos = end;
+    }
+    out.push_str(&text[pos..]);
+    out
+}
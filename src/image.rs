@@ -0,0 +1,491 @@
+//! 固件镜像：读取 Intel HEX、Motorola S-record 和二进制文件，检查校验和与地址重叠，
+//! 合并多个文件，转换为二进制或 Intel HEX；烧录子命令和离线的 hex 子命令都使用这里
+//!
+//! 原始记录按地址排序后合并为连续的段，段与段之间的空隙在转换为二进制时用填充字节补齐。
+
+use anyhow::{bail, Context, Result};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::events;
+use crate::gzip::crc32;
+
+/// hex 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct HexArgs {
+    /// 固件文件（Intel HEX、S-record 或二进制；二进制用 文件@地址 指定加载地址，默认为 0）；多个文件合并，地址不能重叠
+    #[arg(required = true, value_name = "FILE[@ADDR]")]
+    pub files: Vec<String>,
+
+    /// 把合并后的镜像写入文件（.hex/.ihex 为 Intel HEX，其他为二进制）；不指定时只显示各段信息
+    #[arg(long, short = 'o', value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// 转换为二进制时填充段间空隙的字节
+    #[arg(long, default_value = "0xFF", value_parser = parse_byte)]
+    pub fill: u8,
+}
+
+fn parse_byte(s: &str) -> std::result::Result<u8, String> {
+    parse_address(s).ok().and_then(|value| u8::try_from(value).ok()).ok_or_else(|| format!("无效的字节 '{}'", s))
+}
+
+/// 解析十进制或 0x 开头的十六进制地址
+pub fn parse_address(s: &str) -> std::result::Result<u32, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("无效的地址 '{}'，应为十进制或 0x 开头的十六进制", s))
+}
+
+/// 固件中一段连续的数据
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    /// 段后第一个字节的地址
+    pub fn end(&self) -> u64 {
+        self.address as u64 + self.data.len() as u64
+    }
+}
+
+/// 文件格式
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    IntelHex,
+    Srec,
+    Binary,
+}
+
+impl Format {
+    fn name(self) -> &'static str {
+        match self {
+            Format::IntelHex => "Intel HEX",
+            Format::Srec => "S-record",
+            Format::Binary => "二进制",
+        }
+    }
+}
+
+/// 按地址排好序、互不重叠的固件镜像
+pub struct Image {
+    pub segments: Vec<Segment>,
+    /// 文件中的起始（入口）地址
+    pub start: Option<u32>,
+}
+
+/// 一条数据记录及其来源，用于合并时报告重叠的位置
+struct Record {
+    address: u32,
+    data: Vec<u8>,
+    source: String,
+}
+
+impl Image {
+    /// 按地址排序合并记录：相邻的合并为一段，重叠时报错
+    fn from_records(mut records: Vec<Record>, start: Option<u32>) -> Result<Image> {
+        records.sort_by_key(|record| record.address);
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut last_source = String::new();
+        for record in records.into_iter().filter(|record| !record.data.is_empty()) {
+            match segments.last_mut() {
+                Some(last) if (record.address as u64) < last.end() => bail!(
+                    "地址重叠：{} 的 0x{:08X} 与 {} 的 0x{:08X}-0x{:08X} 重叠",
+                    record.source,
+                    record.address,
+                    last_source,
+                    last.address,
+                    last.end() - 1
+                ),
+                Some(last) if record.address as u64 == last.end() => last.data.extend_from_slice(&record.data),
+                _ => segments.push(Segment { address: record.address, data: record.data }),
+            }
+            last_source = record.source;
+        }
+        if segments.last().is_some_and(|last| last.end() > 1 << 32) {
+            bail!("数据超出 32 位地址范围");
+        }
+        Ok(Image { segments, start })
+    }
+
+    /// 数据总字节数（不含空隙）
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|segment| segment.data.len()).sum()
+    }
+
+    /// 转换为从第一段地址开始的连续二进制，空隙用 `fill` 填充
+    pub fn to_binary(&self, fill: u8) -> Result<(u32, Vec<u8>)> {
+        let (Some(first), Some(last)) = (self.segments.first(), self.segments.last()) else {
+            return Ok((0, Vec::new()));
+        };
+        let span = last.end() - first.address as u64;
+        if span > MAX_BINARY {
+            bail!(
+                "地址跨度 0x{:08X}-0x{:08X} 过大（{} MB），转换为二进制前请去掉不需要的段",
+                first.address,
+                last.end() - 1,
+                span >> 20
+            );
+        }
+        let mut binary = vec![fill; span as usize];
+        for segment in &self.segments {
+            let offset = (segment.address - first.address) as usize;
+            binary[offset..offset + segment.data.len()].copy_from_slice(&segment.data);
+        }
+        Ok((first.address, binary))
+    }
+
+    /// 转换为 Intel HEX 文本（每条记录 16 字节，地址超过 64KB 时使用扩展线性地址记录）
+    pub fn to_intel_hex(&self) -> String {
+        let mut out = String::new();
+        let mut upper = 0u16;
+        for segment in &self.segments {
+            for (index, chunk) in segment.data.chunks(16).enumerate() {
+                let address = segment.address + (index * 16) as u32;
+                if (address >> 16) as u16 != upper {
+                    upper = (address >> 16) as u16;
+                    hex_record(&mut out, 0x04, 0, &upper.to_be_bytes());
+                }
+                // 跨越 64KB 边界的记录拆成两条
+                let room = (0x10000 - (address & 0xFFFF)) as usize;
+                if chunk.len() > room {
+                    hex_record(&mut out, 0x00, address as u16, &chunk[..room]);
+                    upper = upper.wrapping_add(1);
+                    hex_record(&mut out, 0x04, 0, &upper.to_be_bytes());
+                    hex_record(&mut out, 0x00, 0, &chunk[room..]);
+                } else {
+                    hex_record(&mut out, 0x00, address as u16, chunk);
+                }
+            }
+        }
+        if let Some(start) = self.start {
+            hex_record(&mut out, 0x05, 0, &start.to_be_bytes());
+        }
+        hex_record(&mut out, 0x01, 0, &[]);
+        out
+    }
+}
+
+/// 转换为二进制的最大跨度
+const MAX_BINARY: u64 = 256 << 20;
+
+fn hex_record(out: &mut String, kind: u8, address: u16, data: &[u8]) {
+    let mut bytes = vec![data.len() as u8, (address >> 8) as u8, address as u8, kind];
+    bytes.extend_from_slice(data);
+    let checksum = bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)).wrapping_neg();
+    out.push(':');
+    for byte in bytes.iter().chain([&checksum]) {
+        let _ = write!(out, "{:02X}", byte);
+    }
+    out.push('\n');
+}
+
+/// 按扩展名或内容判断文件格式（':' 开头为 Intel HEX，'S0'-'S9' 开头为 S-record）
+fn detect(file: &Path, content: &[u8]) -> Format {
+    let ext = file.extension().map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("hex" | "ihex" | "ihx") => return Format::IntelHex,
+        Some("srec" | "s19" | "s28" | "s37" | "mot") => return Format::Srec,
+        Some("bin") => return Format::Binary,
+        _ => {}
+    }
+    match content.trim_ascii_start() {
+        [b':', ..] => Format::IntelHex,
+        [b'S', b'0'..=b'9', ..] => Format::Srec,
+        _ => Format::Binary,
+    }
+}
+
+/// 读取固件文件；二进制文件加载到 `address`
+pub fn load(file: &Path, address: u32) -> Result<Image> {
+    let (image, _) = load_file(file, address)?;
+    if image.segments.is_empty() {
+        bail!("固件 {} 没有数据", file.display());
+    }
+    Ok(image)
+}
+
+fn load_file(file: &Path, address: u32) -> Result<(Image, Format)> {
+    let content = fs::read(file).with_context(|| format!("读取固件 {} 失败", file.display()))?;
+    let format = detect(file, &content);
+    let name = file.display().to_string();
+    let (records, start) = match format {
+        Format::IntelHex => parse_intel_hex(&String::from_utf8_lossy(&content), &name),
+        Format::Srec => parse_srec(&String::from_utf8_lossy(&content), &name),
+        Format::Binary => Ok((vec![Record { address, data: content, source: name }], None)),
+    }
+    .with_context(|| format!("解析 {} 文件 {} 失败", format.name(), file.display()))?;
+    let image = Image::from_records(records, start).with_context(|| format!("固件 {} 有误", file.display()))?;
+    Ok((image, format))
+}
+
+/// 把一条记录的十六进制字符拆成字节
+fn record_bytes(line: &str, number: usize) -> Result<Vec<u8>> {
+    (0..line.len())
+        .step_by(2)
+        .map(|i| line.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect::<Option<Vec<u8>>>()
+        .with_context(|| format!("第 {} 行含有无效的十六进制字符", number))
+}
+
+/// Intel HEX：数据记录（00）、结束（01）、扩展段地址（02）、起始段地址（03）、扩展线性地址（04）和起始线性地址（05）
+fn parse_intel_hex(text: &str, name: &str) -> Result<(Vec<Record>, Option<u32>)> {
+    let mut records = Vec::new();
+    let mut base = 0u32;
+    let mut start = None;
+    for (number, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+        if line.is_empty() {
+            continue;
+        }
+        let Some(record) = line.strip_prefix(':') else {
+            bail!("第 {} 行不是以 ':' 开头的记录", number);
+        };
+        let bytes = record_bytes(record, number)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            bail!("第 {} 行记录长度不符", number);
+        }
+        if bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            bail!("第 {} 行校验和错误", number);
+        }
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        match (bytes[3], data.len()) {
+            (0x00, _) => records.push(Record { address: base.wrapping_add(offset), data: data.to_vec(), source: format!("{} 第 {} 行", name, number) }),
+            (0x01, _) => break,
+            (0x02, 2) => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            (0x03, 4) => start = Some(((u16::from_be_bytes([data[0], data[1]]) as u32) << 4) + u16::from_be_bytes([data[2], data[3]]) as u32),
+            (0x04, 2) => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+            (0x05, 4) => start = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]])),
+            (kind @ 0x02..=0x05, _) => bail!("第 {} 行 {:02X} 记录的长度不符", number, kind),
+            (kind, _) => bail!("第 {} 行含有无效的记录类型 {:02X}", number, kind),
+        }
+    }
+    Ok((records, start))
+}
+
+/// Motorola S-record：S0 头、S1/S2/S3 数据（16/24/32 位地址）、S5/S6 记录数、S7/S8/S9 起始地址
+fn parse_srec(text: &str, name: &str) -> Result<(Vec<Record>, Option<u32>)> {
+    let mut records = Vec::new();
+    let mut start = None;
+    let mut count = 0usize;
+    for (number, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+        if line.is_empty() {
+            continue;
+        }
+        let (Some(b'S'), Some(&kind)) = (line.bytes().next(), line.as_bytes().get(1)) else {
+            bail!("第 {} 行不是以 'S' 开头的记录", number);
+        };
+        let bytes = record_bytes(line.get(2..).unwrap_or_default(), number)?;
+        if bytes.len() < 2 || bytes.len() != bytes[0] as usize + 1 {
+            bail!("第 {} 行记录长度不符", number);
+        }
+        if bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0xFF {
+            bail!("第 {} 行校验和错误", number);
+        }
+        let width = match kind {
+            b'0' | b'1' | b'5' | b'9' => 2,
+            b'2' | b'6' | b'8' => 3,
+            b'3' | b'7' => 4,
+            _ => bail!("第 {} 行含有无效的记录类型 S{}", number, kind as char),
+        };
+        if bytes.len() < width + 2 {
+            bail!("第 {} 行记录长度不符", number);
+        }
+        let address = bytes[1..=width].iter().fold(0u32, |address, &b| address << 8 | b as u32);
+        let data = &bytes[width + 1..bytes.len() - 1];
+        match kind {
+            b'1'..=b'3' => {
+                records.push(Record { address, data: data.to_vec(), source: format!("{} 第 {} 行", name, number) });
+                count += 1;
+            }
+            b'5' | b'6' if address as usize != count % (1 << (width * 8)) => {
+                bail!("第 {} 行记录数 {} 与实际的数据记录数 {} 不符", number, address, count)
+            }
+            b'7'..=b'9' => start = Some(address),
+            _ => {}
+        }
+    }
+    Ok((records, start))
+}
+
+/// 执行 hex 子命令
+pub fn run_hex(opts: &HexArgs) -> Result<()> {
+    let mut records = Vec::new();
+    let mut start = None;
+    for spec in &opts.files {
+        let (path, address) = match spec.rsplit_once('@') {
+            Some((path, address)) => (path, parse_address(address).map_err(anyhow::Error::msg)?),
+            None => (spec.as_str(), 0),
+        };
+        let (image, format) = load_file(Path::new(path), address)?;
+        events::status(&format!(
+            "{}：{}，{} 段，{} 字节{}",
+            path,
+            format.name(),
+            image.segments.len(),
+            image.len(),
+            image.start.map_or(String::new(), |start| format!("，起始地址 0x{:08X}", start))
+        ));
+        start = start.or(image.start);
+        records.extend(image.segments.into_iter().map(|segment| Record { address: segment.address, data: segment.data, source: path.to_string() }));
+    }
+    let image = Image::from_records(records, start)?;
+    if image.segments.is_empty() {
+        bail!("没有数据");
+    }
+
+    events::status(&format!("{:<14}{:<14}{:<12}{}", "起始", "结束", "长度", "与上一段的空隙"));
+    let mut previous: Option<u64> = None;
+    for segment in &image.segments {
+        events::status(&format!(
+            "{:<16}{:<16}{:<14}{}",
+            format!("0x{:08X}", segment.address),
+            format!("0x{:08X}", segment.end() - 1),
+            segment.data.len(),
+            previous.map_or(String::new(), |end| format!("{} 字节", segment.address as u64 - end))
+        ));
+        previous = Some(segment.end());
+    }
+
+    let Some(output) = &opts.output else {
+        let (base, binary) = image.to_binary(opts.fill)?;
+        events::status(&format!("共 {} 字节，转换为二进制从 0x{:08X} 开始、{} 字节，CRC-32 0x{:08X}", image.len(), base, binary.len(), crc32(&binary)));
+        return Ok(());
+    };
+    let is_hex = output.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("hex") || ext.eq_ignore_ascii_case("ihex"));
+    if is_hex {
+        fs::write(output, image.to_intel_hex()).with_context(|| format!("写入 {} 失败", output.display()))?;
+        events::status(&format!("已写入 Intel HEX：{}", output.display()));
+    } else {
+        let (base, binary) = image.to_binary(opts.fill)?;
+        fs::write(output, &binary).with_context(|| format!("写入 {} 失败", output.display()))?;
+        events::status(&format!("已写入二进制：{}（从 0x{:08X} 开始，{} 字节，CRC-32 0x{:08X}）", output.display(), base, binary.len(), crc32(&binary)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTEL_HEX: &str = "\
+:10010000214601360121470136007EFE09D2190140
+:100110002146017E17C20001FF5F16002148011928
+:10012000194E79234623965778239EDA3F01B2CAA7
+:100130003F0156702B5E712B722B732146013421C7
+:00000001FF
+";
+
+    /// Wikipedia SREC 词条中的示例（"Hello world." 程序）
+    const SREC: &str = "\
+S00F000068656C6C6F202020202000003C
+S11F00007C0802A6900100049421FFF07C6C1B787C8C23783C6000003863000026
+S11F001C4BFFFFE5398000007D83637880010014382100107C0803A64E800020E9
+S111003848656C6C6F20776F726C642E0A0042
+S5030003F9
+S9030000FC
+";
+
+    fn intel_hex(text: &str) -> Result<Image> {
+        let (records, start) = parse_intel_hex(text, "t.hex")?;
+        Image::from_records(records, start)
+    }
+
+    fn srec(text: &str) -> Result<Image> {
+        let (records, start) = parse_srec(text, "t.srec")?;
+        Image::from_records(records, start)
+    }
+
+    fn error(result: Result<Image>) -> String {
+        format!("{:#}", result.err().expect("应当解析失败"))
+    }
+
+    #[test]
+    fn intel_hex_round_trip() {
+        let image = intel_hex(INTEL_HEX).unwrap();
+        assert_eq!(image.segments.len(), 1);
+        assert_eq!(image.segments[0].address, 0x0100);
+        assert_eq!(image.len(), 64);
+        assert_eq!(image.segments[0].data[..4], [0x21, 0x46, 0x01, 0x36]);
+        assert_eq!(image.start, None);
+        assert_eq!(image.to_intel_hex(), INTEL_HEX);
+    }
+
+    #[test]
+    fn intel_hex_extended_addresses() {
+        let image = intel_hex(":020000040800F2\n:0400100001020304E2\n:0400000508000131BD\n:00000001FF\n").unwrap();
+        assert_eq!(image.segments[0].address, 0x0800_0010);
+        assert_eq!(image.segments[0].data, [1, 2, 3, 4]);
+        assert_eq!(image.start, Some(0x0800_0131));
+        assert_eq!(image.to_intel_hex(), ":020000040800F2\n:0400100001020304E2\n:0400000508000131BD\n:00000001FF\n");
+
+        // 扩展段地址：基址为段值乘 16
+        let image = intel_hex(":020000021000EC\n:01000000AA55\n:00000001FF\n").unwrap();
+        assert_eq!(image.segments[0].address, 0x10000);
+    }
+
+    #[test]
+    fn intel_hex_splits_at_64k_boundary() {
+        let image = Image { segments: vec![Segment { address: 0xFFF8, data: (0..16).collect() }], start: None };
+        assert_eq!(
+            image.to_intel_hex(),
+            ":08FFF8000001020304050607E5\n:020000040001F9\n:0800000008090A0B0C0D0E0F9C\n:00000001FF\n"
+        );
+        let round_trip = intel_hex(&image.to_intel_hex()).unwrap();
+        assert_eq!(round_trip.segments.len(), 1);
+        assert_eq!(round_trip.segments[0].address, 0xFFF8);
+        assert_eq!(round_trip.segments[0].data, image.segments[0].data);
+    }
+
+    #[test]
+    fn intel_hex_errors() {
+        assert!(error(intel_hex(":0400100001020304E3\n")).contains("第 1 行校验和错误"));
+        assert!(error(intel_hex(":0500100001020304E2\n")).contains("长度不符"));
+        assert!(error(intel_hex("0400100001020304E2\n")).contains("不是以 ':' 开头"));
+        assert!(error(intel_hex(":0400100001020304EZ\n")).contains("无效的十六进制字符"));
+        assert!(error(intel_hex(":0400100001020304中\n")).contains("无效的十六进制字符"));
+        assert!(error(intel_hex(":0100000601F8\n")).contains("无效的记录类型 06"));
+        assert!(error(intel_hex(":01000000AA55\n:01000000AA55\n")).contains("地址重叠"));
+    }
+
+    #[test]
+    fn srec_example() {
+        let image = srec(SREC).unwrap();
+        assert_eq!(image.segments.len(), 1);
+        assert_eq!(image.segments[0].address, 0);
+        assert_eq!(image.len(), 70);
+        assert!(image.segments[0].data.ends_with(b"Hello world.\n\0"));
+        assert_eq!(image.start, Some(0));
+
+        // S5 记录数与数据记录数不符
+        let miscounted = SREC.replace("S5030003F9", "S5030002FA");
+        assert!(error(srec(&miscounted)).contains("记录数 2 与实际的数据记录数 3 不符"));
+        let corrupted = SREC.replace("0A0042", "0A0043");
+        assert!(error(srec(&corrupted)).contains("第 4 行校验和错误"));
+    }
+
+    #[test]
+    fn binary_conversion_fills_gaps() {
+        let image = intel_hex(":01000000AA55\n:01000300BB41\n:00000001FF\n").unwrap();
+        assert_eq!(image.segments.len(), 2);
+        assert_eq!(image.to_binary(0xFF).unwrap(), (0, vec![0xAA, 0xFF, 0xFF, 0xBB]));
+        assert_eq!(Image { segments: Vec::new(), start: None }.to_binary(0).unwrap(), (0, Vec::new()));
+    }
+
+    #[test]
+    fn format_detection() {
+        assert_eq!(detect(Path::new("fw.HEX"), b"\x00"), Format::IntelHex);
+        assert_eq!(detect(Path::new("fw.s19"), b""), Format::Srec);
+        assert_eq!(detect(Path::new("fw.bin"), b":00000001FF"), Format::Binary);
+        assert_eq!(detect(Path::new("fw"), b"\r\n:00000001FF"), Format::IntelHex);
+        assert_eq!(detect(Path::new("fw"), b"S00F0000"), Format::Srec);
+        assert_eq!(detect(Path::new("fw"), b"\x7FELF"), Format::Binary);
+        assert_eq!(parse_address("0x08000000"), Ok(0x0800_0000));
+        assert_eq!(parse_address("4096"), Ok(4096));
+        assert!(parse_address("0xZZ").is_err());
+    }
+}
@@ -0,0 +1,474 @@
+//! Kermit 文件传输：基本的短包协议（单字符校验和、停等确认、超时和 NAK 时重发），
+//! 控制字符前缀转义，对方要求时使用 8 位前缀（只能传 7 位数据的线路）。
+//!
+//! 包格式为 `SOH LEN SEQ TYPE DATA CHECK CR`，LEN、SEQ 和 CHECK 都是加 32 后的可打印字符。
+//! 发送方依次发送 S（协商参数）、F（文件名）、D（数据）、Z（文件结束），全部文件发完后发送 B；
+//! 接收方对每个包回复 Y（ACK）或 N（NAK），出错时任一方发送 E 包说明原因。
+
+use anyhow::{bail, Context, Result};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::xmodem::Link;
+
+const MARK: u8 = 0x01;
+/// 本方使用的控制字符前缀
+const QCTL: u8 = b'#';
+/// 标准短包的最大长度
+const MAX_LEN: u8 = 94;
+const RETRIES: usize = 10;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn tochar(x: u8) -> u8 {
+    x + 32
+}
+
+fn unchar(c: u8) -> u8 {
+    c.wrapping_sub(32)
+}
+
+fn ctl(c: u8) -> u8 {
+    c ^ 64
+}
+
+/// 单字符校验和（类型 1）：LEN 到 DATA 各字符之和，高两位折叠进低 6 位
+fn check(bytes: &[u8]) -> u8 {
+    let sum: u32 = bytes.iter().map(|&b| b as u32).sum();
+    tochar(((sum + ((sum & 192) >> 6)) & 63) as u8)
+}
+
+/// 收到的包
+enum Received {
+    Packet { seq: u8, kind: u8, data: Vec<u8> },
+    Bad,
+    Timeout,
+}
+
+/// 对方在 S 包或其 ACK 中声明的参数
+struct Params {
+    /// 对方能接收的最大包长
+    max_len: u8,
+    /// 对方的包结束符
+    eol: u8,
+    /// 对方发送时使用的控制字符前缀
+    qctl: u8,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params { max_len: 80, eol: b'\r', qctl: QCTL }
+    }
+}
+
+/// 一次 Kermit 会话
+pub struct Kermit<'l, 'p> {
+    link: &'l mut Link<'p>,
+    seq: u8,
+    peer: Params,
+    /// 8 位前缀（对方要求时启用）
+    qbin: Option<u8>,
+    started: bool,
+}
+
+impl<'l, 'p> Kermit<'l, 'p> {
+    pub fn new(link: &'l mut Link<'p>) -> Self {
+        Kermit { link, seq: 0, peer: Params::default(), qbin: None, started: false }
+    }
+
+    /// 本方参数：最大包长 94、超时 5 秒、无填充、CR 结束、'#' 控制前缀、对方要求时使用 8 位前缀、单字符校验
+    fn params(&self) -> Vec<u8> {
+        vec![tochar(MAX_LEN), tochar(TIMEOUT.as_secs() as u8), tochar(0), ctl(0), tochar(b'\r'), QCTL, self.qbin.unwrap_or(b'Y'), b'1']
+    }
+
+    fn accept_params(&mut self, data: &[u8]) {
+        let field = |i: usize| data.get(i).copied().filter(|&c| c != b' ');
+        self.peer = Params {
+            max_len: field(0).map(unchar).filter(|&len| len >= 10).unwrap_or(80).min(MAX_LEN),
+            eol: field(4).map(unchar).unwrap_or(b'\r'),
+            qctl: field(5).unwrap_or(QCTL),
+        };
+        // 对方给出具体的前缀字符时启用 8 位前缀
+        self.qbin = field(6).filter(|&c| (33..=62).contains(&c) || (96..=126).contains(&c));
+    }
+
+    fn send_packet(&mut self, seq: u8, kind: u8, data: &[u8]) -> Result<()> {
+        let mut packet = vec![MARK, tochar(data.len() as u8 + 3), tochar(seq), kind];
+        packet.extend_from_slice(data);
+        packet.push(check(&packet[1..]));
+        packet.push(self.peer.eol);
+        self.link.send(&packet)
+    }
+
+    fn read_packet(&mut self, timeout: Duration) -> Result<Received> {
+        let deadline = Instant::now() + timeout;
+        // 已经读到了下一个包的 SOH
+        let mut marked = false;
+        'mark: loop {
+            if !std::mem::take(&mut marked) {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                match self.link.read_byte(remaining)? {
+                    None => return Ok(Received::Timeout),
+                    Some(MARK) => {}
+                    Some(_) => continue,
+                }
+            }
+            let Some(len) = self.link.read_byte(Duration::from_secs(1))? else {
+                return Ok(Received::Bad);
+            };
+            if len == MARK {
+                marked = true;
+                continue;
+            }
+            let len = unchar(len) as usize;
+            if !(3..=MAX_LEN as usize).contains(&len) {
+                return Ok(Received::Bad);
+            }
+            let mut body = vec![tochar(len as u8)];
+            for _ in 0..len {
+                match self.link.read_byte(Duration::from_secs(1))? {
+                    // 包中出现 SOH：前一个包不完整，从这里重新开始
+                    Some(MARK) => {
+                        marked = true;
+                        continue 'mark;
+                    }
+                    Some(byte) => body.push(byte),
+                    None => return Ok(Received::Bad),
+                }
+            }
+            let received = body.pop().unwrap_or_default();
+            if check(&body) != received {
+                return Ok(Received::Bad);
+            }
+            return Ok(Received::Packet { seq: unchar(body[1]) & 63, kind: body[2], data: body[3..].to_vec() });
+        }
+    }
+
+    /// 数据字段编码：控制字符加前缀，启用 8 位前缀时高位置位的字节加 8 位前缀
+    fn encode_byte(&self, byte: u8, out: &mut Vec<u8>) {
+        let mut byte = byte;
+        if let Some(qbin) = self.qbin.filter(|_| byte & 0x80 != 0) {
+            out.push(qbin);
+            byte &= 0x7F;
+        }
+        let low = byte & 0x7F;
+        if low < 32 || low == 127 {
+            out.extend([QCTL, ctl(byte)]);
+        } else if low == QCTL || Some(low) == self.qbin {
+            out.extend([QCTL, byte]);
+        } else {
+            out.push(byte);
+        }
+    }
+
+    fn decode(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len());
+        let mut bytes = data.iter().copied();
+        while let Some(mut byte) = bytes.next() {
+            let mut high = 0;
+            if Some(byte) == self.qbin {
+                high = 0x80;
+                let Some(next) = bytes.next() else { break };
+                byte = next;
+            }
+            if byte == self.peer.qctl {
+                let Some(next) = bytes.next() else { break };
+                let low = next & 0x7F;
+                byte = if (0x3F..=0x5F).contains(&low) { ctl(next) } else { next };
+            }
+            out.push(byte | high);
+        }
+        out
+    }
+
+    /// 对方 E 包中的错误说明
+    fn remote_error(data: &[u8]) -> String {
+        format!("对方报告错误：{}", String::from_utf8_lossy(data))
+    }
+
+    // ---- 发送 ----
+
+    /// 发送一个包并等待确认，返回 ACK 中的数据
+    fn exchange(&mut self, kind: u8, data: &[u8]) -> Result<Vec<u8>> {
+        for _ in 0..RETRIES {
+            self.send_packet(self.seq, kind, data)?;
+            match self.read_packet(TIMEOUT)? {
+                Received::Packet { seq, kind: b'Y', data } if seq == self.seq => {
+                    self.seq = (self.seq + 1) & 63;
+                    return Ok(data);
+                }
+                // 对下一个包的 NAK 等同于对这个包的 ACK
+                Received::Packet { seq, kind: b'N', .. } if seq == (self.seq + 1) & 63 => {
+                    self.seq = seq;
+                    return Ok(Vec::new());
+                }
+                Received::Packet { kind: b'E', data, .. } => bail!("{}", Self::remote_error(&data)),
+                _ => {}
+            }
+        }
+        self.send_packet(self.seq, b'E', b"Too many retries")?;
+        bail!("包 {}（{}）重发 {} 次仍未被确认", self.seq, kind as char, RETRIES)
+    }
+
+    /// 发送方开始：发送 S 包协商参数（接收方还没启动时在等待时长内反复发送）
+    pub fn start_send(&mut self, wait: Duration) -> Result<()> {
+        let deadline = Instant::now() + wait;
+        while Instant::now() < deadline {
+            self.send_packet(0, b'S', &self.params())?;
+            match self.read_packet(TIMEOUT.min(deadline.saturating_duration_since(Instant::now())))? {
+                Received::Packet { seq: 0, kind: b'Y', data } => {
+                    self.accept_params(&data);
+                    self.seq = 1;
+                    return Ok(());
+                }
+                Received::Packet { kind: b'E', data, .. } => bail!("{}", Self::remote_error(&data)),
+                _ => {}
+            }
+        }
+        bail!("{:?} 内接收方没有响应，请先在设备上启动 Kermit 接收（如 receive 命令）", wait)
+    }
+
+    /// 发送一个文件，每确认一个数据包以已发送的字节数回调
+    pub fn send_file(&mut self, name: &str, data: &[u8], progress: &mut dyn FnMut(usize)) -> Result<()> {
+        let mut encoded = Vec::new();
+        for &byte in name.as_bytes() {
+            self.encode_byte(byte, &mut encoded);
+        }
+        self.exchange(b'F', &encoded)?;
+        // 数据字段的长度：包长减去 SEQ、TYPE 和校验和
+        let room = self.peer.max_len as usize - 3;
+        let mut offset = 0;
+        while offset < data.len() {
+            let mut chunk = Vec::with_capacity(room);
+            let mut end = offset;
+            let mut byte = Vec::with_capacity(3);
+            while end < data.len() {
+                byte.clear();
+                self.encode_byte(data[end], &mut byte);
+                if chunk.len() + byte.len() > room {
+                    break;
+                }
+                chunk.extend_from_slice(&byte);
+                end += 1;
+            }
+            let reply = self.exchange(b'D', &chunk)?;
+            // 接收方在 ACK 中要求中止：X 为本文件，Z 为整批
+            if matches!(reply.first(), Some(b'X' | b'Z')) {
+                self.exchange(b'Z', b"D")?;
+                bail!("接收方取消了传输");
+            }
+            offset = end;
+            progress(offset);
+        }
+        self.exchange(b'Z', &[])?;
+        Ok(())
+    }
+
+    /// 全部发送完成：B 包
+    pub fn finish_send(&mut self) -> Result<()> {
+        self.exchange(b'B', &[]).map(|_| ())
+    }
+
+    // ---- 接收 ----
+
+    /// 接收下一个包：重复的包再次确认，出错或超时时发送 NAK
+    fn next_packet(&mut self, wait: Duration) -> Result<(u8, Vec<u8>)> {
+        let deadline = Instant::now() + wait;
+        let mut errors = 0;
+        loop {
+            let timeout = TIMEOUT.min(deadline.saturating_duration_since(Instant::now()));
+            match self.read_packet(timeout)? {
+                Received::Packet { kind: b'E', data, .. } => bail!("{}", Self::remote_error(&data)),
+                Received::Packet { seq, kind, data } if seq == self.seq => return Ok((kind, data)),
+                // 对方没有收到上一个 ACK
+                Received::Packet { seq, kind, .. } if seq == (self.seq + 63) & 63 => {
+                    let ack = if kind == b'S' { self.params() } else { Vec::new() };
+                    self.send_packet(seq, b'Y', &ack)?;
+                }
+                Received::Packet { .. } | Received::Bad | Received::Timeout => {
+                    errors += 1;
+                    if errors > RETRIES || Instant::now() >= deadline {
+                        self.send_packet(self.seq, b'E', b"Too many retries")?;
+                        bail!("连续 {} 次没有收到有效的包，已放弃接收", errors);
+                    }
+                    self.send_packet(self.seq, b'N', &[])?;
+                }
+            }
+        }
+    }
+
+    fn ack(&mut self, data: &[u8]) -> Result<()> {
+        self.send_packet(self.seq, b'Y', data)?;
+        self.seq = (self.seq + 1) & 63;
+        Ok(())
+    }
+
+    /// 等待发送方的下一个文件，返回文件名；收到 B（全部完成）时返回 None
+    pub fn next_file(&mut self, wait: Duration) -> Result<Option<String>> {
+        if !self.started {
+            let deadline = Instant::now() + wait;
+            loop {
+                if Instant::now() >= deadline {
+                    bail!("{:?} 内发送方没有开始发送，请先在设备上启动 Kermit 发送（如 send 命令）", wait);
+                }
+                // 发送 NAK 提示发送方开始
+                self.send_packet(0, b'N', &[])?;
+                if let Received::Packet { seq: 0, kind: b'S', data } = self.read_packet(TIMEOUT.min(deadline.saturating_duration_since(Instant::now())))? {
+                    self.accept_params(&data);
+                    let params = self.params();
+                    self.ack(&params)?;
+                    self.started = true;
+                    break;
+                }
+            }
+        }
+        let (kind, data) = self.next_packet(TIMEOUT * RETRIES as u32)?;
+        match kind {
+            b'F' => {
+                self.ack(&[])?;
+                Ok(Some(String::from_utf8_lossy(&self.decode(&data)).into_owned()))
+            }
+            b'B' => {
+                self.ack(&[])?;
+                Ok(None)
+            }
+            _ => bail!("应为文件头（F 包），收到 {} 包", kind as char),
+        }
+    }
+
+    /// 接收文件数据写入 `out`，直到 Z 包；每收到一个数据包以已接收的字节数回调，返回文件长度
+    pub fn receive_file(&mut self, out: &mut dyn Write, progress: &mut dyn FnMut(usize)) -> Result<u64> {
+        let mut received = 0;
+        loop {
+            let (kind, data) = self.next_packet(TIMEOUT * RETRIES as u32)?;
+            match kind {
+                b'D' => {
+                    let bytes = self.decode(&data);
+                    out.write_all(&bytes).context("写入文件失败")?;
+                    received += bytes.len();
+                    self.ack(&[])?;
+                    progress(received);
+                }
+                // 文件属性（本方没有声明支持，只确认）
+                b'A' => self.ack(&[])?,
+                b'Z' => {
+                    self.ack(&[])?;
+                    if data.first() == Some(&b'D') {
+                        bail!("发送方放弃了这个文件");
+                    }
+                    return Ok(received as u64);
+                }
+                _ => bail!("应为数据（D 包），收到 {} 包", kind as char),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xmodem::tests::{pipe, sample};
+    use serialport::SerialPort;
+    use std::thread;
+
+    /// 读出线路另一端收到的全部数据
+    fn output(port: &mut Box<dyn SerialPort>) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut buf = [0; 4096];
+        while let Ok(n) = port.read(&mut buf) {
+            out.extend_from_slice(&buf[..n]);
+        }
+        out
+    }
+
+    #[test]
+    fn packets() {
+        // 单字符校验和：LEN、SEQ、TYPE 和数据之和，高两位折叠进低 6 位
+        assert_eq!(check(b"# N"), b'3');
+        // 219 = 0b11011011：高两位 3 加到低 6 位（27 + 3 = 30）
+        assert_eq!(check(b"#_Y"), tochar(30));
+
+        let (mut a, mut b) = pipe();
+        let mut link = Link::new(&mut a, None);
+        let mut kermit = Kermit::new(&mut link);
+        kermit.send_packet(0, b'N', &[]).unwrap();
+        assert_eq!(output(&mut b), b"\x01# N3\r");
+        // 本方的 S 包参数：最大包长 94、超时 5 秒、无填充、CR 结束、'#' 前缀、可以使用 8 位前缀、校验类型 1
+        kermit.send_packet(0, b'S', &kermit.params()).unwrap();
+        assert_eq!(output(&mut b), b"\x01+ S~% @-#Y1\\\r");
+
+        // 包前的杂散数据被跳过；校验错误返回 Bad；包中出现 SOH 时从新的包开始
+        b.write_all(b"junk\x01# N3\r\x01# N4\r\x01+ S~%\x01# N3\r").unwrap();
+        for expected in ["N", "bad", "N"] {
+            let got = match kermit.read_packet(Duration::from_secs(1)).unwrap() {
+                Received::Packet { seq: 0, kind, data } if data.is_empty() => (kind as char).to_string(),
+                Received::Packet { .. } => "other".to_string(),
+                Received::Bad => "bad".to_string(),
+                Received::Timeout => "timeout".to_string(),
+            };
+            assert_eq!(got, expected);
+        }
+        assert!(matches!(kermit.read_packet(Duration::from_millis(50)).unwrap(), Received::Timeout));
+        b.write_all(b"\x01\x7f").unwrap();
+        assert!(matches!(kermit.read_packet(Duration::from_secs(1)).unwrap(), Received::Bad), "LEN 超出范围");
+    }
+
+    #[test]
+    fn encoding() {
+        let (mut a, _b) = pipe();
+        let mut link = Link::new(&mut a, None);
+        let mut kermit = Kermit::new(&mut link);
+        let encode = |kermit: &Kermit, data: &[u8]| {
+            let mut out = Vec::new();
+            for &byte in data {
+                kermit.encode_byte(byte, &mut out);
+            }
+            out
+        };
+        // 控制字符和 DEL 加 '#' 前缀并异或 64，前缀字符本身也加前缀
+        assert_eq!(encode(&kermit, b"\r\n\x7f#A\x81"), b"#M#J#?##A#\xc1");
+        let all: Vec<u8> = (0..=255).collect();
+        let encoded = encode(&kermit, &all);
+        assert!(encoded[..].iter().all(|&b| !(b & 0x7F < 32 || b & 0x7F == 127)), "编码后没有控制字符");
+        assert_eq!(kermit.decode(&encoded), all);
+
+        // 8 位前缀：高位置位的字节加 '&' 前缀并去掉高位，编码后只有 7 位字符
+        kermit.qbin = Some(b'&');
+        assert_eq!(encode(&kermit, b"&\xc1\x8d\xa3"), b"#&&A&#M&##");
+        let encoded = encode(&kermit, &all);
+        assert!(encoded.iter().all(|&b| (32..127).contains(&b)));
+        assert_eq!(kermit.decode(&encoded), all);
+    }
+
+    /// 在两个线程中发送和接收一个文件，发送方可以要求 8 位前缀
+    fn session(data: &[u8], qbin: Option<u8>) -> (String, Vec<u8>) {
+        let (mut a, mut b) = pipe();
+        thread::scope(|scope| {
+            let sender = scope.spawn(|| -> Result<()> {
+                let mut link = Link::new(&mut a, None);
+                let mut kermit = Kermit::new(&mut link);
+                kermit.qbin = qbin;
+                kermit.start_send(Duration::from_secs(5))?;
+                kermit.send_file("fw.bin", data, &mut |_| {})?;
+                kermit.finish_send()
+            });
+            let mut link = Link::new(&mut b, None);
+            let mut kermit = Kermit::new(&mut link);
+            let name = kermit.next_file(Duration::from_secs(5)).unwrap().unwrap();
+            assert_eq!(kermit.qbin, qbin);
+            let mut received = Vec::new();
+            assert_eq!(kermit.receive_file(&mut received, &mut |_| {}).unwrap(), data.len() as u64);
+            assert!(kermit.next_file(Duration::from_secs(5)).unwrap().is_none());
+            sender.join().unwrap().unwrap();
+            (name, received)
+        })
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = sample(3000);
+        for qbin in [None, Some(b'&')] {
+            assert_eq!(session(&data, qbin), ("fw.bin".to_string(), data.clone()), "8 位前缀：{:?}", qbin);
+        }
+        assert_eq!(session(&[], None).1, b"");
+    }
+}
@@ -0,0 +1,340 @@
+//! LIN 2.x：经 LIN 收发器（如 TJA1020/TJA1021）接到串口时，作为主节点发送帧头（break + 同步 + PID）
+//! 并发送或接收应答，作为从节点按 PID 回应数据，以及监听时切分并校验帧（`--frame lin`）
+//!
+//! 帧结构：`break 55 PID 数据(1~8) 校验和`。PID 为 6 位 ID 加两位奇偶校验，
+//! 校验和为带进位累加和取反：经典校验（LIN 1.x）只含数据，增强校验（LIN 2.x）还含 PID；
+//! 诊断帧（ID 0x3C~0x3F）总是使用经典校验。break 在串口上通常收到为一个 0x00 字节。
+//! 单线总线上收发器会把发出的数据回送到接收端。
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::events::{self, Event};
+use crate::format_hex;
+use crate::framing::{Deframer, FrameSpec};
+use crate::{parse_duration, parse_hex};
+use crate::terminal;
+
+/// lin 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct LinArgs {
+    #[command(subcommand)]
+    pub command: LinCommand,
+
+    /// 发送应答时使用经典校验（LIN 1.x 节点），默认为增强校验（诊断帧 ID 0x3C~0x3F 总是经典校验）
+    #[arg(long, global = true)]
+    pub classic: bool,
+
+    /// break 的产生方式：signal 为串口 break 信号（时长取决于系统调度），
+    /// baud 为临时降低波特率发送 0x00（适合不支持 break 的 USB 转串口）
+    #[arg(long, value_enum, default_value = "signal", global = true)]
+    pub break_method: BreakMethod,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum LinCommand {
+    /// 作为主节点发送帧头：带 --data 时由主节点发送应答，否则等待从节点应答
+    Master {
+        /// 帧 ID（0~63，可写作 0x10）
+        #[arg(long, value_parser = parse_id)]
+        id: u8,
+
+        /// 主节点发送的应答数据（十六进制，1~8 字节）
+        #[arg(long)]
+        data: Option<String>,
+
+        /// 等待从节点应答的时长
+        #[arg(long, default_value = "50ms", value_parser = parse_duration)]
+        timeout: Duration,
+
+        /// 按指定间隔重复发送（如 100ms），按 Ctrl+C 退出
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        interval: Option<Duration>,
+    },
+    /// 作为从节点：收到匹配 ID 的帧头时发送应答，并显示总线上的所有帧
+    Slave {
+        /// 应答：ID=数据（十六进制），如 0x21=0102A0FF，可重复
+        #[arg(long, value_name = "ID=DATA", value_parser = parse_response, required = true)]
+        respond: Vec<(u8, Vec<u8>)>,
+
+        /// 运行指定时长后退出（如 30s、5m）
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        exit_after: Option<Duration>,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum BreakMethod {
+    Signal,
+    Baud,
+}
+
+fn parse_id(s: &str) -> std::result::Result<u8, String> {
+    let id = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    };
+    id.filter(|&id| id < 64).ok_or_else(|| format!("无效的 LIN ID '{}'，应为 0~63", s))
+}
+
+fn parse_data(s: &str) -> std::result::Result<Vec<u8>, String> {
+    let data = parse_hex(s).map_err(|e| format!("无效的数据 '{}'，应为十六进制：{}", s, e))?;
+    match (1..=8).contains(&data.len()) {
+        true => Ok(data),
+        false => Err(format!("LIN 应答数据应为 1~8 字节，'{}' 为 {} 字节", s, data.len())),
+    }
+}
+
+fn parse_response(s: &str) -> std::result::Result<(u8, Vec<u8>), String> {
+    let (id, data) = s.split_once('=').ok_or_else(|| format!("无效的应答 '{}'，应为 ID=数据，如 0x21=0102", s))?;
+    Ok((parse_id(id)?, parse_data(data)?))
+}
+
+/// 同步字节
+pub const SYNC: u8 = 0x55;
+
+/// 6 位 ID 加上奇偶校验位：P0 = ID0^ID1^ID2^ID4，P1 = !(ID1^ID3^ID4^ID5)
+pub fn protected_id(id: u8) -> u8 {
+    let bit = |n: u8| id >> n & 1;
+    let p0 = bit(0) ^ bit(1) ^ bit(2) ^ bit(4);
+    let p1 = (bit(1) ^ bit(3) ^ bit(4) ^ bit(5)) ^ 1;
+    id & 0x3F | p0 << 6 | p1 << 7
+}
+
+/// PID 奇偶校验正确时返回 ID
+pub fn id_of(pid: u8) -> Option<u8> {
+    let id = pid & 0x3F;
+    (protected_id(id) == pid).then_some(id)
+}
+
+/// 诊断帧（主节点请求 0x3C、从节点应答 0x3D 及保留的 0x3E/0x3F）
+fn is_diagnostic(id: u8) -> bool {
+    id >= 0x3C
+}
+
+/// 带进位累加后取反；增强校验从 PID 开始累加
+pub fn checksum(pid: Option<u8>, data: &[u8]) -> u8 {
+    let sum = pid.iter().chain(data).fold(0u16, |sum, &b| {
+        let sum = sum + b as u16;
+        if sum > 0xFF { sum - 0xFF } else { sum }
+    });
+    !(sum as u8)
+}
+
+/// 应答（数据和校验和）
+fn response(id: u8, data: &[u8], classic: bool) -> Vec<u8> {
+    let pid = protected_id(id);
+    let enhanced = !classic && !is_diagnostic(id);
+    [data, &[checksum(enhanced.then_some(pid), data)]].concat()
+}
+
+/// 帧起始位置（去掉 break 产生的 0x00）
+fn sync_offset(frame: &[u8]) -> Option<usize> {
+    match frame {
+        [0x00, SYNC, ..] => Some(1),
+        [SYNC, ..] => Some(0),
+        _ => None,
+    }
+}
+
+/// 校验帧：PID 奇偶校验，应答的经典或增强校验。成功时返回帧内容说明
+pub fn decode(frame: &[u8]) -> std::result::Result<String, String> {
+    let Some(offset) = sync_offset(frame) else {
+        return Err("不是 LIN 帧（缺少同步字节 55）".to_string());
+    };
+    let Some(&pid) = frame.get(offset + 1) else {
+        return Err("帧头不完整".to_string());
+    };
+    let Some(id) = id_of(pid) else {
+        return Err(format!("PID {:02X} 奇偶校验错误", pid));
+    };
+    let head = format!("ID 0x{:02X}（PID {:02X}）", id, pid);
+    let response = &frame[offset + 2..];
+    let Some((&received, data)) = response.split_last() else {
+        return Ok(format!("{} 仅帧头，无应答", head));
+    };
+    if data.is_empty() || data.len() > 8 {
+        return Err(format!("{} 应答长度 {} 字节无效", head, response.len()));
+    }
+    let classic = checksum(None, data);
+    let enhanced = checksum(Some(pid), data);
+    let kind = match received {
+        _ if received == enhanced && !is_diagnostic(id) => "增强校验",
+        _ if received == classic => "经典校验",
+        _ => {
+            return Err(format!(
+                "{} 数据 {}，校验和错误：收到 {:02X}，增强校验应为 {:02X}，经典校验应为 {:02X}",
+                head,
+                format_hex(data),
+                received,
+                enhanced,
+                classic
+            ))
+        }
+    };
+    Ok(format!("{} 数据 {}，{}正确", head, format_hex(data), kind))
+}
+
+/// 是否为带应答且校验通过的完整帧，用于在连续数据中找帧的边界
+pub fn complete(frame: &[u8]) -> bool {
+    decode(frame).is_ok() && sync_offset(frame).is_some_and(|offset| frame.len() > offset + 2)
+}
+
+/// 帧间的空闲时长：30 位时间，至少 5ms（USB 转串口的延迟会把数据成批送达）
+pub fn frame_gap(baud: u32) -> Duration {
+    Duration::from_secs_f64(30.0 / baud.max(1) as f64).max(Duration::from_millis(5))
+}
+
+/// 发送 break（至少 13 位的显性电平）
+fn send_break(port: &mut Box<dyn SerialPort>, method: BreakMethod) -> Result<()> {
+    let baud = port.baud_rate().context("读取波特率失败")?;
+    match method {
+        BreakMethod::Signal => {
+            let length = Duration::from_secs_f64(13.0 / baud.max(1) as f64).max(Duration::from_millis(1));
+            port.set_break().context("设置 break 失败")?;
+            thread::sleep(length);
+            port.clear_break().context("清除 break 失败")?;
+        }
+        BreakMethod::Baud => {
+            // 0x00 的起始位和 8 个数据位共 9 位显性电平，按 9/13 的波特率发送即为 13 位时间
+            port.set_baud_rate(baud * 9 / 13).context("设置波特率失败")?;
+            terminal::transmit(port, None, &[0x00])?;
+            port.flush().context("等待发送完成失败")?;
+            port.set_baud_rate(baud).context("恢复波特率失败")?;
+        }
+    }
+    Ok(())
+}
+
+/// 收集一段时间内收到的数据（收到数据后再等待一个帧间隔，确认应答已结束）
+fn collect(port: &mut Box<dyn SerialPort>, timeout: Duration, gap: Duration) -> Result<Vec<u8>> {
+    let deadline = Instant::now() + timeout;
+    let mut received = Vec::new();
+    let mut last: Option<Instant> = None;
+    let mut buffer = [0u8; 64];
+    while Instant::now() < deadline && last.is_none_or(|at| at.elapsed() < gap) {
+        match port.read(&mut buffer) {
+            Ok(n) => {
+                events::emit(Event::Rx(&buffer[..n]));
+                received.extend_from_slice(&buffer[..n]);
+                last = Some(Instant::now());
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e).context("读取串口失败"),
+        }
+    }
+    Ok(received)
+}
+
+/// 执行 lin 子命令
+pub fn run_lin(port: &mut Box<dyn SerialPort>, opts: &LinArgs) -> Result<()> {
+    port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+    match &opts.command {
+        LinCommand::Master { id, data, timeout, interval } => {
+            let data = data.as_deref().map(parse_data).transpose().map_err(anyhow::Error::msg)?;
+            loop {
+                master(port, opts, *id, data.as_deref(), *timeout)?;
+                match interval {
+                    Some(interval) => thread::sleep(*interval),
+                    None => return Ok(()),
+                }
+            }
+        }
+        LinCommand::Slave { respond, exit_after } => slave(port, opts, respond, *exit_after),
+    }
+}
+
+fn master(port: &mut Box<dyn SerialPort>, opts: &LinArgs, id: u8, data: Option<&[u8]>, timeout: Duration) -> Result<()> {
+    let pid = protected_id(id);
+    let mut frame = vec![SYNC, pid];
+    if let Some(data) = data {
+        frame.extend(response(id, data, opts.classic));
+    }
+    send_break(port, opts.break_method)?;
+    terminal::transmit(port, None, &frame)?;
+
+    let gap = frame_gap(port.baud_rate().unwrap_or(19200));
+    let received = collect(port, timeout, gap)?;
+    // 收发器回送的帧头之后为应答（主节点自己发送的应答也会回送）
+    let Some(start) = received.windows(2).position(|w| w == [SYNC, pid]) else {
+        bail!("没有收到帧头回送 {}，请确认已接收发器且总线已上电", format_hex(&received));
+    };
+    let echoed = &received[start..];
+    match data {
+        Some(_) if echoed != frame.as_slice() => {
+            bail!("回送的数据 {} 与发送的 {} 不一致（总线冲突或从节点同时应答）", format_hex(echoed), format_hex(&frame))
+        }
+        Some(_) => events::status(&format!("发送 {}", decode(&frame).unwrap_or_default())),
+        None if echoed.len() == 2 => bail!("ID 0x{:02X}（PID {:02X}）在 {:?} 内没有从节点应答", id, pid, timeout),
+        None => match decode(echoed) {
+            Ok(info) => events::status(&format!("应答 {}", info)),
+            Err(error) => bail!("应答 {}：{}", format_hex(&echoed[2..]), error),
+        },
+    }
+    Ok(())
+}
+
+fn slave(port: &mut Box<dyn SerialPort>, opts: &LinArgs, responses: &[(u8, Vec<u8>)], exit_after: Option<Duration>) -> Result<()> {
+    let mut deframer = Deframer::new(FrameSpec::Lin { gap: None });
+    if let Ok(baud) = port.baud_rate() {
+        deframer.set_baud(baud);
+    }
+    let ids: Vec<String> = responses.iter().map(|(id, _)| format!("0x{:02X}", id)).collect();
+    events::status(&format!("作为从节点应答 ID {}（按 Ctrl+C 退出）", ids.join("、")));
+
+    // 最近收到的三个字节，以及还未收到的自己应答的回送（没有回送时遇到不同的字节即放弃）
+    let mut recent: Vec<u8> = Vec::new();
+    let mut echo: Vec<u8> = Vec::new();
+    let deadline = exit_after.map(|d| Instant::now() + d);
+    let mut buffer = [0u8; 64];
+    while deadline.is_none_or(|d| Instant::now() < d) {
+        let n = match port.read(&mut buffer) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
+                for frame in deframer.poll_idle(Instant::now()) {
+                    show(&frame);
+                }
+                continue;
+            }
+            Err(e) => return Err(e).context("读取串口失败"),
+        };
+        events::emit(Event::Rx(&buffer[..n]));
+        for &byte in &buffer[..n] {
+            if echo.first() == Some(&byte) {
+                echo.remove(0);
+                continue;
+            }
+            echo.clear();
+            recent.push(byte);
+            if recent.len() > 3 {
+                recent.remove(0);
+            }
+            // 帧头：break（0x00）、同步字节和 PID
+            let [.., 0x00, SYNC, pid] = recent[..] else {
+                continue;
+            };
+            let Some((id, data)) = id_of(pid).and_then(|id| responses.iter().find(|(r, _)| *r == id)) else {
+                continue;
+            };
+            let reply = response(*id, data, opts.classic);
+            terminal::transmit(port, None, &reply)?;
+            echo = reply;
+            recent.clear();
+        }
+        for frame in deframer.push(&buffer[..n], Instant::now()) {
+            show(&frame);
+        }
+    }
+    Ok(())
+}
+
+fn show(frame: &crate::framing::Frame) {
+    match (&frame.info, &frame.error) {
+        (_, Some(error)) => events::status(&format!("{}  [{}]", format_hex(&frame.data), error)),
+        (Some(info), None) => events::status(&format!("{}  [{}]", format_hex(&frame.data), info)),
+        (None, None) => events::status(&format_hex(&frame.data)),
+    }
+}
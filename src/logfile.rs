@@ -0,0 +1,169 @@
+//! 接收数据日志：在打印的同时写入文件，可按大小或日期轮转
+
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::{format_hex, gzip};
+
+/// 日志文件格式
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// 带时间戳的文本行
+    Text,
+    /// 带时间戳的十六进制行
+    Hex,
+    /// 原始二进制（与收到的字节完全一致）
+    Raw,
+}
+
+/// 轮转策略
+#[derive(Clone, Copy, Debug)]
+pub enum RotatePolicy {
+    /// 文件超过指定字节数时轮转
+    Size(u64),
+    /// 每天轮转一次
+    Daily,
+}
+
+/// 解析轮转策略："size=50M" 或 "daily"
+pub fn parse_rotate(s: &str) -> std::result::Result<RotatePolicy, String> {
+    if s.eq_ignore_ascii_case("daily") {
+        return Ok(RotatePolicy::Daily);
+    }
+    let size = s
+        .strip_prefix("size=")
+        .ok_or_else(|| format!("无效的轮转策略 '{}'，应为 size=<大小> 或 daily", s))?;
+    let (num, unit) = size.split_at(size.find(|c: char| !c.is_ascii_digit()).unwrap_or(size.len()));
+    let num: u64 = num.parse().map_err(|_| format!("无效的文件大小 '{}'", size))?;
+    let scale = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return Err(format!("无效的大小单位 '{}'（可用 K、M、G）", unit)),
+    };
+    match num.checked_mul(scale) {
+        Some(0) | None => Err(format!("无效的文件大小 '{}'", size)),
+        Some(bytes) => Ok(RotatePolicy::Size(bytes)),
+    }
+}
+
+/// 轮转设置
+#[derive(Clone, Copy, Debug)]
+pub struct Rotation {
+    pub policy: RotatePolicy,
+    /// 保留的归档数量
+    pub keep: usize,
+    /// 是否 gzip 压缩归档
+    pub gzip: bool,
+}
+
+/// 接收数据日志文件
+pub struct RxLog {
+    path: PathBuf,
+    file: File,
+    format: LogFormat,
+    rotation: Option<Rotation>,
+    /// 当前文件已写入的字节数
+    written: u64,
+    /// 当前文件对应的日期（按天轮转时使用）
+    day: jiff::civil::Date,
+}
+
+impl RxLog {
+    /// 打开日志文件；`append` 为 false 时清空已有内容
+    pub fn open(path: &Path, format: LogFormat, append: bool, rotation: Option<Rotation>) -> Result<Self> {
+        let file = open_file(path, append)?;
+        let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(RxLog {
+            path: path.to_path_buf(),
+            file,
+            format,
+            rotation,
+            written,
+            day: jiff::Zoned::now().date(),
+        })
+    }
+
+    /// 记录原始字节（仅 raw 格式生效）
+    pub fn write_raw(&mut self, data: &[u8]) -> Result<()> {
+        if self.format == LogFormat::Raw {
+            self.write(data)?;
+        }
+        Ok(())
+    }
+
+    /// 记录一条带时间戳的数据（仅 text/hex 格式生效）
+    pub fn write_record(&mut self, data: &[u8]) -> Result<()> {
+        let body = match self.format {
+            LogFormat::Text => String::from_utf8_lossy(data).into_owned(),
+            LogFormat::Hex => format_hex(data),
+            LogFormat::Raw => return Ok(()),
+        };
+        let stamp = jiff::Zoned::now().strftime("%Y-%m-%d %H:%M:%S%.3f");
+        self.write(format!("[{}] {}\n", stamp, body).as_bytes())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        if let Some(rotation) = self.rotation {
+            let due = match rotation.policy {
+                RotatePolicy::Size(limit) => self.written > 0 && self.written + bytes.len() as u64 > limit,
+                RotatePolicy::Daily => jiff::Zoned::now().date() != self.day,
+            };
+            if due {
+                self.rotate(rotation).context("日志轮转失败")?;
+            }
+        }
+
+        self.file.write_all(bytes).context("写入日志失败")?;
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// 归档当前文件：log -> log.1 -> log.2 ...，超出保留数量的最旧归档被删除
+    fn rotate(&mut self, rotation: Rotation) -> Result<()> {
+        self.file.flush()?;
+        let archive = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            if rotation.gzip {
+                name.push(".gz");
+            }
+            PathBuf::from(name)
+        };
+
+        if rotation.keep > 0 {
+            let _ = fs::remove_file(archive(rotation.keep));
+            for n in (1..rotation.keep).rev() {
+                let from = archive(n);
+                if from.exists() {
+                    fs::rename(&from, archive(n + 1))?;
+                }
+            }
+            if rotation.gzip {
+                let data = fs::read(&self.path)?;
+                fs::write(archive(1), gzip::compress(&data))?;
+            } else {
+                fs::rename(&self.path, archive(1))?;
+            }
+        }
+
+        // 重新创建（清空）当前日志文件
+        self.file = open_file(&self.path, false)?;
+        self.written = 0;
+        self.day = jiff::Zoned::now().date();
+        Ok(())
+    }
+}
+
+fn open_file(path: &Path, append: bool) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .with_context(|| format!("无法打开日志文件 {}", path.display()))
+}
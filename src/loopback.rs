@@ -0,0 +1,149 @@
+//! 回环自检：发送已知数据并检查是否原样收回（需短接 TX/RX 或使用回环头），
+//! 统计错误字节、丢失字节和延迟
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::events;
+use crate::signal::Rs485;
+use crate::terminal;
+use crate::{parse_duration, parse_escapes};
+
+/// loopback 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct LoopbackArgs {
+    /// 测试数据类型
+    #[arg(long, value_enum, default_value_t = Pattern::Counter)]
+    pub pattern: Pattern,
+
+    /// 自定义测试数据（支持 \r \n \xNN 等转义），指定后忽略 --pattern 和 --size
+    #[arg(long, value_name = "TEXT")]
+    pub data: Option<String>,
+
+    /// 每轮发送的字节数
+    #[arg(long, default_value_t = 256, value_parser = clap::value_parser!(u16).range(1..=4096))]
+    pub size: u16,
+
+    /// 测试轮数
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    pub count: u32,
+
+    /// 每轮等待数据收回的超时时间
+    #[arg(long, default_value = "1s", value_parser = parse_duration)]
+    pub timeout: Duration,
+}
+
+/// 测试数据类型
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Pattern {
+    /// 递增字节（每轮起始值不同，可发现错位和残留数据）
+    Counter,
+    /// 可打印字符
+    Text,
+    /// 0x55/0xAA 交替（相邻位翻转最多）
+    Alternating,
+}
+
+impl Pattern {
+    fn generate(self, round: u32, size: usize) -> Vec<u8> {
+        let offset = round.wrapping_mul(31);
+        (0..size as u32)
+            .map(|i| match self {
+                Pattern::Counter => offset.wrapping_add(i) as u8,
+                Pattern::Text => b' ' + (offset.wrapping_add(i) % 95) as u8,
+                Pattern::Alternating => if i % 2 == 0 { 0x55 } else { 0xAA },
+            })
+            .collect()
+    }
+}
+
+/// 一轮测试的结果
+struct Round {
+    received: usize,
+    errors: usize,
+    /// 从开始发送到收到第一个字节的时间
+    latency: Option<Duration>,
+}
+
+/// 执行回环自检，有错误或丢失时返回错误（退出码非零）
+pub fn run_loopback(port: &mut Box<dyn SerialPort>, opts: &LoopbackArgs, rs485: Option<Rs485>) -> Result<()> {
+    let custom = opts.data.as_deref().map(parse_escapes).transpose().context("测试数据解析失败")?;
+    if custom.as_ref().is_some_and(|data| data.is_empty()) {
+        bail!("测试数据不能为空");
+    }
+
+    let mut sent = 0;
+    let mut received = 0;
+    let mut errors = 0;
+    let mut latencies = Vec::new();
+    for round in 1..=opts.count {
+        let expected = match &custom {
+            Some(data) => data.clone(),
+            None => opts.pattern.generate(round, opts.size as usize),
+        };
+        port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+
+        let result = run_round(port, &expected, opts.timeout, rs485)?;
+        sent += expected.len();
+        received += result.received;
+        errors += result.errors;
+        latencies.extend(result.latency);
+        events::status(&format!(
+            "第 {} 轮：收回 {}/{} 字节，错误 {}{}",
+            round,
+            result.received,
+            expected.len(),
+            result.errors,
+            result.latency.map(|l| format!("，延迟 {:.1}ms", millis(l))).unwrap_or_default()
+        ));
+    }
+
+    let lost = sent - received;
+    let mut summary = format!("共发送 {} 字节，收回 {} 字节，错误 {} 字节，丢失 {} 字节", sent, received, errors, lost);
+    if let (Some(min), Some(max)) = (latencies.iter().min(), latencies.iter().max()) {
+        let avg = latencies.iter().sum::<Duration>() / latencies.len() as u32;
+        summary += &format!("；延迟 最小 {:.1}ms / 平均 {:.1}ms / 最大 {:.1}ms", millis(*min), millis(avg), millis(*max));
+    }
+    events::status(&summary);
+
+    if received == 0 {
+        bail!("未收回任何数据，请检查 TX/RX 是否短接");
+    }
+    if errors > 0 || lost > 0 {
+        bail!("回环测试未通过");
+    }
+    events::status("回环测试通过");
+    Ok(())
+}
+
+/// 发送一轮数据并读取收回的数据，逐字节比较
+fn run_round(port: &mut Box<dyn SerialPort>, expected: &[u8], timeout: Duration, rs485: Option<Rs485>) -> Result<Round> {
+    let start = Instant::now();
+    terminal::transmit(port, rs485, expected)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut data = Vec::with_capacity(expected.len());
+    let mut latency = None;
+    let mut buffer = [0u8; 512];
+    while data.len() < expected.len() && Instant::now() < deadline {
+        match port.read(&mut buffer) {
+            Ok(n) => {
+                latency.get_or_insert_with(|| start.elapsed());
+                data.extend_from_slice(&buffer[..n]);
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e).context("读取串口失败"),
+        }
+    }
+
+    // 多收的字节计为错误
+    let extra = data.len().saturating_sub(expected.len());
+    let errors = data.iter().zip(expected).filter(|(a, b)| a != b).count() + extra;
+    Ok(Round { received: data.len().min(expected.len()), errors, latency })
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
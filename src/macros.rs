@@ -0,0 +1,74 @@
+//! 交互模式下的宏按键：F1-F12 一键发送预设内容
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::Path;
+
+use crate::{parse_escapes, parse_hex};
+
+/// 一个宏按键绑定
+#[derive(Debug, Clone)]
+pub struct Macro {
+    /// 宏定义中的原始文本，用于回显
+    pub label: String,
+    pub payload: Vec<u8>,
+}
+
+/// F1-F12 的宏绑定表
+#[derive(Debug, Default)]
+pub struct Macros {
+    keys: [Option<Macro>; 12],
+}
+
+impl Macros {
+    /// 从宏文件读取：每行一条 "F1 = 内容"，忽略空行和 # 注释
+    ///
+    /// 内容默认按文本解析并支持转义序列（如 `AT\r\n`），
+    /// 也可加 `hex:` 前缀按十六进制解析，或加 `text:` 前缀保留首尾空白。
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("读取宏文件 {} 失败", path.display()))?;
+
+        let mut macros = Macros::default();
+        for (i, line) in content.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let (n, m) = parse_line(line).with_context(|| format!("{} 第 {} 行有误", path.display(), i + 1))?;
+            macros.keys[n as usize - 1] = Some(m);
+        }
+        Ok(macros)
+    }
+
+    /// 查找功能键 Fn 绑定的宏
+    pub fn get(&self, n: u8) -> Option<&Macro> {
+        self.keys.get((n as usize).checked_sub(1)?)?.as_ref()
+    }
+}
+
+/// 解析一行宏定义，返回功能键编号和宏内容
+fn parse_line(line: &str) -> Result<(u8, Macro)> {
+    let Some((key, value)) = line.split_once('=') else {
+        bail!("应为 F1 = 内容");
+    };
+    let key = key.trim();
+    let n = key
+        .strip_prefix(['F', 'f'])
+        .and_then(|n| n.parse::<u8>().ok())
+        .filter(|n| (1..=12).contains(n))
+        .with_context(|| format!("无效的按键 '{}'，可用 F1-F12", key))?;
+
+    let value = value.trim_start();
+    let payload = if let Some(rest) = value.strip_prefix("hex:") {
+        parse_hex(rest.trim()).with_context(|| format!("F{} 的十六进制内容解析失败", n))?
+    } else if let Some(rest) = value.strip_prefix("text:") {
+        parse_escapes(rest.strip_prefix(' ').unwrap_or(rest)).context("转义序列解析失败")?
+    } else {
+        parse_escapes(value.trim_end()).context("转义序列解析失败")?
+    };
+    if payload.is_empty() {
+        bail!("F{} 的内容为空", n);
+    }
+    Ok((n, Macro { label: value.trim().to_string(), payload }))
+}
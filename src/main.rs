@@ -1,52 +1,539 @@
+mod api;
+mod at;
+mod avr;
+mod bench;
+mod ber;
+mod bridge;
+mod capture;
+mod checksum;
+mod config;
+mod console;
+mod detect;
+mod dlt645;
+mod editor;
+mod esp;
+mod esploader;
+mod events;
+mod firmata;
+mod flash;
+mod framing;
+mod gps;
+mod grpc;
+mod gzip;
+mod highlight;
+mod image;
+mod kermit;
+mod lin;
+mod logfile;
+mod loopback;
+mod macros;
+mod mavlink;
+mod mbus;
+mod menu;
+mod modbus;
+mod monitor;
+mod obd;
+mod mqtt;
+mod mstp;
+mod netport;
+mod nmea;
+mod protocol;
+mod ping;
+mod progress;
+mod receive;
+mod reset;
+mod resolver;
+mod rfc2217;
+mod signal;
+mod slave;
+mod slcan;
+mod sms;
+mod stm32;
+mod template;
+mod terminal;
+mod tui;
+mod ubx;
+mod webhook;
+mod websocket;
+mod xbee;
+mod xfer;
+mod xmodem;
+mod ymodem;
+mod zmodem;
+
+use at::AtArgs;
+use bench::BenchArgs;
+use ber::BerArgs;
+use bridge::BridgeArgs;
+use capture::CaptureArgs;
+use checksum::{Checksum, CrcArgs};
 use clap::Parser;
+use config::Config;
+use detect::DetectBaudArgs;
+use dlt645::Dlt645Args;
+use esp::EspArgs;
+use events::{Event, OutputFormat};
+use firmata::FirmataArgs;
+use flash::FlashArgs;
+use framing::FrameSpec;
+use gps::GpsArgs;
+use image::HexArgs;
+use lin::LinArgs;
+use loopback::LoopbackArgs;
+use mbus::MbusArgs;
+use modbus::ModbusArgs;
+use monitor::{Monitor, MonitorArgs};
+use obd::ObdArgs;
+use ping::PingArgs;
+use progress::Progress;
+use receive::ReceiveArgs;
+use regex::Regex;
+use reset::AutoReset;
+use signal::{Rs485, SignalArgs};
+use slcan::SlcanArgs;
+use sms::SmsArgs;
+use terminal::TerminalArgs;
+use ubx::UbxArgs;
+use xbee::XbeeArgs;
+use xfer::XferArgs;
 use anyhow::{Context, Result};
 use serialport::SerialPort;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use std::fs;
 use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
 use std::fmt::Write;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// 绑定的COM口
+    /// 绑定的COM口（也可用 usb:VID:PID、serial:序列号、auto 或设备友好名称指定；
+    /// rfc2217://主机:端口 或 tcp://主机:端口 使用网络上的串口服务器）
     #[arg(short, long, default_value = "COM3")]
     port: String,
 
-    /// 波特率
-    #[arg(short, long, default_value = "115200")]
+    /// 波特率（任意数值，或预设名如 esp8266-boot、dmx、midi）
+    #[arg(short, long, default_value = "115200", value_parser = parse_baud)]
     baud: u32,
 
+    /// 数据位（5/6/7/8）
+    #[arg(long, default_value = "8", value_parser = clap::value_parser!(u8).range(5..=8))]
+    data_bits: u8,
+
+    /// 停止位（1/2）
+    #[arg(long, default_value = "1", value_parser = clap::value_parser!(u8).range(1..=2))]
+    stop_bits: u8,
+
+    /// 校验位
+    #[arg(long, value_enum, default_value = "none")]
+    parity: ParityArg,
+
+    /// 流控方式
+    #[arg(long, value_enum, default_value = "none")]
+    flow_control: FlowControlArg,
+
+    /// 读超时（毫秒）
+    #[arg(long, default_value = "100", value_parser = clap::value_parser!(u64).range(1..))]
+    timeout_ms: u64,
+
+    /// 接收缓冲区大小（字节）
+    #[arg(long, default_value = "256", value_parser = clap::value_parser!(u32).range(1..))]
+    rx_buffer: u32,
+
     /// 十六进制模式
     #[arg(long)]
     hex: bool,
 
+    /// 端口不存在时等待其出现（可选超时秒数，省略则一直等待）
+    #[arg(long, value_name = "SECONDS", num_args = 0..=1, require_equals = true, default_missing_value = "0")]
+    wait: Option<u64>,
+
+    /// RS-485 方向控制：发送前置位 RTS，发送完成后复位（平时保持接收方向）
+    #[arg(long)]
+    rs485: bool,
+
+    /// RS-485 置位 RTS 后到开始发送的延时
+    #[arg(long, default_value = "0ms", value_name = "DURATION", value_parser = parse_duration, requires = "rs485")]
+    rs485_pre_delay: Duration,
+
+    /// RS-485 发送完成后到复位 RTS 的延时
+    #[arg(long, default_value = "0ms", value_name = "DURATION", value_parser = parse_duration, requires = "rs485")]
+    rs485_post_delay: Duration,
+
+    /// 配置文件路径（默认查找 ./serial-tool.toml 和用户配置目录下的 serial-tool/config.toml）
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// 发送/监听/交互前先复位设备（如 arduino：拉低 DTR 复位）
+    #[arg(long, value_enum, value_name = "KIND", group = "reset")]
+    auto_reset: Option<AutoReset>,
+
+    /// 发送/监听/交互/烧录前执行指定的复位序列（内置 arduino、esp32、esp32-run、stm32、stm32-run，或配置文件中的 [reset.<名称>]）
+    #[arg(long, value_name = "NAME", group = "reset")]
+    reset_seq: Option<String>,
+
+    /// 复位后等待设备就绪的时间（默认 2s）；配合 --reset-banner 时为等待启动信息的超时
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, requires = "reset")]
+    reset_wait: Option<Duration>,
+
+    /// 复位后等待匹配该正则的启动信息再继续
+    #[arg(long, value_name = "REGEX", requires = "reset")]
+    reset_banner: Option<Regex>,
+
+    /// 帧格式：监听时按帧切分并逐帧显示，发送时按帧封装（如 delim:\r\n、delim:0x7E、len:offset=2,size=1,extra=3、cobs、slip、hdlc、modbus-rtu、modbus-ascii、dlt645、mstp、xbee、mavlink、nmea、ubx、lin、format:<协议描述文件>；--decode 为同义写法，如 --decode nmea）
+    #[arg(long, visible_aliases = ["framing", "decode"], value_name = "SPEC", value_parser = framing::parse_frame)]
+    frame: Option<FrameSpec>,
+
+    /// 发送时自动计算并插入校验和，监听时（配合 --frame）校验每帧：算法[:位置]（算法：crc8、crc16-modbus、crc16-ccitt、crc16-x25、crc32、lrc、xor、sum8；
+    /// 位置默认 end，整数为插入处的字节偏移，负数从末尾算起；校验和覆盖插入位置之前的数据）
+    #[arg(long, value_name = "ALGO[:POS]", value_parser = checksum::parse_checksum)]
+    checksum: Option<Checksum>,
+
+    /// 输出格式（jsonl：每个事件输出一行 JSON）
+    #[arg(long, value_enum, default_value = "text")]
+    output: OutputFormat,
+
+    /// 把串口收发的所有数据连同方向和微秒时间戳记录到抓包文件（二进制格式，可用 capture export 导出为文本）
+    #[arg(long, value_name = "PATH")]
+    record: Option<PathBuf>,
+
     /// 要执行的操作类型
     #[command(subcommand)]
     action: Action,
 }
 
+/// 校验方式（命令行取值）
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ParityArg {
+    None,
+    Odd,
+    Even,
+}
+
+impl From<ParityArg> for serialport::Parity {
+    fn from(p: ParityArg) -> Self {
+        match p {
+            ParityArg::None => serialport::Parity::None,
+            ParityArg::Odd => serialport::Parity::Odd,
+            ParityArg::Even => serialport::Parity::Even,
+        }
+    }
+}
+
+/// 流控方式（命令行取值）
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum FlowControlArg {
+    /// 无流控
+    None,
+    /// RTS/CTS 硬件流控
+    Rtscts,
+    /// XON/XOFF 软件流控
+    Xonxoff,
+}
+
+impl From<FlowControlArg> for serialport::FlowControl {
+    fn from(f: FlowControlArg) -> Self {
+        match f {
+            FlowControlArg::None => serialport::FlowControl::None,
+            FlowControlArg::Rtscts => serialport::FlowControl::Hardware,
+            FlowControlArg::Xonxoff => serialport::FlowControl::Software,
+        }
+    }
+}
+
+/// 常用设备的波特率预设
+const BAUD_PRESETS: &[(&str, u32)] = &[
+    ("esp8266-boot", 74880),
+    ("esp32-boot", 115200),
+    ("dmx", 250000),
+    ("midi", 31250),
+    ("klipper", 250000),
+];
+
+/// 解析波特率：数值或预设名
+fn parse_baud(s: &str) -> std::result::Result<u32, String> {
+    if let Some(&(_, rate)) = BAUD_PRESETS.iter().find(|(name, _)| name.eq_ignore_ascii_case(s)) {
+        return Ok(rate);
+    }
+    match s.parse::<u32>() {
+        Ok(0) => Err("波特率不能为 0".to_string()),
+        Ok(rate) => Ok(rate),
+        Err(_) => Err(format!(
+            "无效的波特率 '{}'，可用预设：{}",
+            s,
+            BAUD_PRESETS.iter().map(|(n, _)| *n).collect::<Vec<_>>().join(", ")
+        )),
+    }
+}
+
+/// send 子命令参数
+#[derive(clap::Args, Debug)]
+#[command(group(clap::ArgGroup::new("payload").required(true).args(["message", "file", "script"])))]
+struct SendArgs {
+    /// 要发送的消息内容（"-" 表示从标准输入流式读取）
+    message: Option<String>,
+
+    /// 发送文件内容（原样发送；十六进制模式下按十六进制文本解析），发送时显示进度
+    #[arg(long, value_name = "PATH")]
+    file: Option<PathBuf>,
+
+    /// 从文件的该偏移处开始发送，用于续传中断的发送（可用 K/M 后缀）
+    #[arg(long, value_name = "BYTES", value_parser = parse_size, requires = "file")]
+    offset: Option<u64>,
+
+    /// 按行依次发送脚本文件中的消息（支持 hex:/text: 前缀和 delay: 延时行）
+    #[arg(long, value_name = "PATH")]
+    script: Option<PathBuf>,
+
+    /// 解析消息中的转义序列（\r \n \t \0 \\ \xNN）
+    #[arg(long)]
+    escapes: bool,
+
+    /// 追加在消息末尾的换行符
+    #[arg(long, value_enum, default_value = "none")]
+    line_ending: LineEnding,
+
+    /// 重复发送次数（0 表示无限次）
+    #[arg(long, default_value = "1", value_name = "N")]
+    repeat: u64,
+
+    /// 重复发送的间隔（如 500ms、2s）
+    #[arg(long, default_value = "1s", value_parser = parse_duration)]
+    interval: Duration,
+
+    /// 将消息中的 {n} 替换为递增计数（从 1 开始；十六进制模式下替换为计数低 8 位的两位十六进制）
+    #[arg(long)]
+    counter: bool,
+
+    /// 展开消息中的模板变量（{seq}、{timestamp}、{rand:N}、{env:NAME}）
+    #[arg(long)]
+    template: bool,
+
+    /// 每个字节之间的发送间隔（如 2ms）
+    #[arg(long, value_parser = parse_duration)]
+    byte_delay: Option<Duration>,
+
+    /// 分块发送时每块的字节数
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u32).range(1..))]
+    chunk_size: Option<u32>,
+
+    /// 分块之间的发送间隔（需配合 --chunk-size）
+    #[arg(long, value_parser = parse_duration, requires = "chunk_size")]
+    chunk_delay: Option<Duration>,
+
+    /// 发送后等待匹配该正则的响应，超时则以非零状态退出
+    #[arg(long, value_name = "REGEX")]
+    expect: Option<Regex>,
+
+    /// 等待响应的超时时间
+    #[arg(long, default_value = "2s", value_parser = parse_duration, requires = "expect")]
+    expect_timeout: Duration,
+
+    /// 本地回显发送的内容（设备不回显时便于查看）
+    #[arg(long)]
+    local_echo: bool,
+}
+
+/// 发送节奏控制：逐字节/分块延时，以及 RS-485 方向切换
+#[derive(Debug, Clone, Copy)]
+struct TxPacing {
+    byte_delay: Option<Duration>,
+    chunk_size: Option<usize>,
+    chunk_delay: Option<Duration>,
+    /// 当前块已发送的字节数（跨多次写入累计）
+    sent_in_chunk: usize,
+    rs485: Option<Rs485>,
+}
+
+impl TxPacing {
+    fn from_args(send: &SendArgs, rs485: Option<Rs485>) -> Self {
+        TxPacing {
+            byte_delay: send.byte_delay,
+            chunk_size: send.chunk_size.map(|n| n as usize),
+            chunk_delay: send.chunk_delay,
+            sent_in_chunk: 0,
+            rs485,
+        }
+    }
+
+    /// 按设定的节奏写入数据（RS-485 模式下整段数据发送期间保持发送方向）
+    fn write(&mut self, port: &mut Box<dyn SerialPort>, bytes: &[u8]) -> Result<()> {
+        match self.rs485 {
+            Some(rs485) => rs485.transmit(port, |port| self.write_paced(port, bytes)),
+            None => self.write_paced(port, bytes),
+        }
+    }
+
+    fn write_paced(&mut self, port: &mut Box<dyn SerialPort>, bytes: &[u8]) -> Result<()> {
+        if self.byte_delay.is_none() && self.chunk_size.is_none() {
+            return port.write_all(bytes).context("写入串口失败");
+        }
+
+        let step = if self.byte_delay.is_some() { 1 } else { usize::MAX };
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            // 本次写入长度：不超过单字节步长，也不越过当前块边界
+            let mut len = rest.len().min(step);
+            if let Some(size) = self.chunk_size {
+                len = len.min(size - self.sent_in_chunk);
+            }
+            let (head, tail) = rest.split_at(len);
+            port.write_all(head).context("写入串口失败")?;
+            port.flush().context("刷新缓冲区失败")?;
+            rest = tail;
+
+            self.sent_in_chunk += len;
+            if self.chunk_size.is_some_and(|size| self.sent_in_chunk >= size) {
+                self.sent_in_chunk = 0;
+                if let Some(delay) = self.chunk_delay {
+                    thread::sleep(delay);
+                    continue;
+                }
+            }
+            if let Some(delay) = self.byte_delay {
+                thread::sleep(delay);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 解析时长：支持 ms/s/m 后缀，无后缀按毫秒
+fn parse_duration(s: &str) -> std::result::Result<Duration, String> {
+    let s = s.trim();
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(i) => s.split_at(i),
+        None => (s, "ms"),
+    };
+    let value: f64 = num.parse().map_err(|_| format!("无效的时长 '{}'", s))?;
+    let secs = match unit {
+        "ms" => value / 1000.0,
+        "s" => value,
+        "m" => value * 60.0,
+        _ => return Err(format!("无效的时长单位 '{}'（可用 ms、s、m）", unit)),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("时长超出范围 '{}'", s))
+}
+
+/// 解析字节数：支持 K/M 后缀（1024 进制）
+fn parse_size(s: &str) -> std::result::Result<u64, String> {
+    let upper = s.trim().to_ascii_uppercase();
+    let (number, scale) = match upper.strip_suffix('K') {
+        Some(number) => (number, 1024),
+        None => match upper.strip_suffix('M') {
+            Some(number) => (number, 1024 * 1024),
+            None => (upper.as_str(), 1),
+        },
+    };
+    number.parse::<u64>().ok().and_then(|n| n.checked_mul(scale)).ok_or_else(|| format!("无效的字节数 '{}'", s))
+}
+
+/// 换行符类型
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum LineEnding {
+    None,
+    Cr,
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    fn as_bytes(self) -> &'static [u8] {
+        match self {
+            LineEnding::None => b"",
+            LineEnding::Cr => b"\r",
+            LineEnding::Lf => b"\n",
+            LineEnding::Crlf => b"\r\n",
+        }
+    }
+}
+
 #[derive(clap::Subcommand, Debug)]
 enum Action {
     /// 发送消息到串口
-    Send {
-        /// 要发送的消息内容
-        message: String,
-    },
+    Send(SendArgs),
     /// 监听串口数据
-    Monitor,
+    Monitor(MonitorArgs),
+    /// 把收到的原始数据原样写入文件（如导出 flash），空闲、收满字节数或收到指定序列时停止
+    Receive(ReceiveArgs),
+    /// 列出可用串口（含USB信息）
+    List,
+    /// 交互式终端：同时发送键盘输入和显示收到的数据
+    Terminal(TerminalArgs),
+    /// 发送串口 break 信号
+    Break {
+        /// break 持续时间（如 250ms、1s）
+        #[arg(long, default_value = "250ms", value_parser = parse_duration)]
+        duration: Duration,
+    },
+    /// 设置、清除或脉冲 DTR/RTS 控制线（如 --dtr on --rts pulse:100ms）
+    Signal(SignalArgs),
+    /// 自动检测波特率：依次尝试常用波特率，按收到数据的可读程度判断
+    DetectBaud(DetectBaudArgs),
+    /// 回环自检：发送测试数据并检查是否原样收回（需短接 TX/RX）
+    Loopback(LoopbackArgs),
+    /// 误码率测试：发送 PRBS 伪随机序列并统计接收端的误码
+    Ber(BerArgs),
+    /// 测量往返延迟：发送探测包并等待回显设备原样返回
+    Ping(PingArgs),
+    /// 吞吐量测试：满速收发数据，对比实际速率与理论速率
+    Bench(BenchArgs),
+    /// Modbus RTU/ASCII：作为主站读写从站，或模拟从站
+    Modbus(ModbusArgs),
+    /// DL/T 645-2007 电能表：读取电能、电压、电流等数据或通信地址
+    Dlt645(Dlt645Args),
+    /// M-Bus 主站：向热量表、水表发送 REQ_UD2 并解码应答中的数据记录
+    Mbus(MbusArgs),
+    /// SLCAN USB-CAN 适配器：设置波特率，发送 CAN 帧并显示收到的帧
+    Slcan(SlcanArgs),
+    /// ELM327 OBD-II 适配器：自动初始化后查询 PID（解码转速、车速、温度等）或读取故障码
+    Obd(ObdArgs),
+    /// AT 命令：等待 OK/ERROR 等结果码，显示多行应答和 URC，失败时退出码非 0
+    At(AtArgs),
+    /// 经 AT 命令发送短信（PDU 模式，中文自动 UCS-2 编码，超长时拆分为级联短信）
+    Sms(SmsArgs),
+    /// ESP8266/ESP32 AT 固件配网：扫描 Wi-Fi、连接热点、建立 TCP/UDP 连接并进入透传模式
+    Esp(EspArgs),
+    /// XBee API 模式：发送本地/远程 AT 命令或数据包并解码应答帧
+    Xbee(XbeeArgs),
+    /// u-blox GNSS 接收机：构造带校验和的 UBX 配置消息（CFG-RATE、CFG-PRT、CFG-MSG 等）发送并等待 ACK
+    Ubx(UbxArgs),
+    /// GPS 实时定位：解析 NMEA/UBX，原地刷新定位状态、经纬度、海拔、HDOP、卫星数和速度，可记录 GPX 轨迹
+    Gps(GpsArgs),
+    /// Firmata 客户端：设置 Arduino 引脚模式、读模拟量、写数字输出和 PWM、持续显示上报值
+    Firmata(FirmataArgs),
+    /// LIN 2.x（经 LIN 收发器）：作为主节点发送帧头并收发应答，或作为从节点按 ID 应答
+    Lin(LinArgs),
+    /// 文件传输：用 XMODEM/YMODEM/ZMODEM/Kermit 发送或接收文件（bootloader 升级固件、路由器恢复等）
+    Xfer(XferArgs),
+    /// 网络桥接：监听 TCP 端口让局域网中的其他机器访问串口设备（类似 ser2net，可用 RFC 2217 远程设置串口），连接远端 TCP 服务并与串口双向转发，把收到的数据以 UDP 数据报发给网络上的接收方，通过 WebSocket 供网页收发，接入 MQTT 服务器，或提供 gRPC 服务
+    Bridge(BridgeArgs),
+    /// 固件烧录：通过芯片的串口 bootloader 写入 Intel HEX、S-record 或二进制固件（STM32 系统 bootloader，AVR/Arduino 的 STK500v1 和 AVR109 bootloader，ESP32/ESP8266 ROM 下载模式）
+    Flash(FlashArgs),
+    /// 离线计算数据的各种校验和（CRC-8/16/32、LRC、累加和等），不打开串口
+    Crc(CrcArgs),
+    /// 离线查看、检查和合并固件文件（Intel HEX、S-record、二进制），转换为二进制或 Intel HEX，不打开串口
+    Hex(HexArgs),
+    /// 离线查看 --record 写入的抓包文件，或导出为文本、JSON Lines，不打开串口
+    Capture(CaptureArgs),
 }
 
 /// 字符串转十六进制字节（如 "A1B2" -> [0xA1, 0xB2]）
 fn parse_hex(hex_str: &str) -> Result<Vec<u8>> {
-    // 预处理：移除可能的分隔符（如空格、冒号）
+    // 预处理：移除可能的分隔符（如空格、冒号）和每组前面的 0x 前缀
     let filtered: String = hex_str
-        .chars()
-        .filter(|c| !c.is_whitespace() && *c != ':')
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .map(|group| group.strip_prefix("0x").or_else(|| group.strip_prefix("0X")).unwrap_or(group))
         .collect();
 
-    // 检查有效长度（必须是偶数）
-    if filtered.len() % 2 != 0 {
-        println!("十六进制字符串长度必须是偶数");
+    // 只接受十六进制数字，且长度必须是偶数；否则按两个字符切分时会越界或落在多字节字符中间
+    if let Some(c) = filtered.chars().find(|c| !c.is_ascii_hexdigit()) {
+        anyhow::bail!("无效的十六进制字符 '{}'", c);
+    }
+    if !filtered.len().is_multiple_of(2) {
+        anyhow::bail!("十六进制字符串长度必须是偶数（共 {} 个数字）", filtered.len());
     }
 
     // 每两个字符解析为一个字节
@@ -60,6 +547,39 @@ fn parse_hex(hex_str: &str) -> Result<Vec<u8>> {
         .collect()
 }
 
+/// 解析文本中的转义序列（如 "AT\r\n" -> [0x41, 0x54, 0x0D, 0x0A]）
+fn parse_escapes(text: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('r') => out.push(b'\r'),
+            Some('n') => out.push(b'\n'),
+            Some('t') => out.push(b'\t'),
+            Some('0') => out.push(0),
+            Some('\\') => out.push(b'\\'),
+            Some('x') => {
+                let digits: String = chars.by_ref().take(2).collect();
+                let byte = Some(&digits)
+                    .filter(|d| d.len() == 2 && d.chars().all(|c| c.is_ascii_hexdigit()))
+                    .and_then(|d| u8::from_str_radix(d, 16).ok())
+                    .with_context(|| format!("无效的转义序列: '\\x{}'", digits))?;
+                out.push(byte);
+            }
+            Some(other) => anyhow::bail!("不支持的转义序列: '\\{}'", other),
+            None => anyhow::bail!("消息以未完成的转义符 '\\' 结尾"),
+        }
+    }
+
+    Ok(out)
+}
+
 /// 字节数组转十六进制字符串（如 [0x41] -> "41"）
 fn format_hex(bytes: &[u8]) -> String {
     let mut hex_str = String::with_capacity(bytes.len() * 3);
@@ -72,62 +592,339 @@ fn format_hex(bytes: &[u8]) -> String {
     hex_str
 }
 
+/// 加锁；持锁的线程 panic 后照常使用其中的数据，不让一个线程出错连带其他线程也 panic
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// 本地回显发送的数据：青色显示并加 "TX> " 前缀，与设备返回的数据区分
+fn echo_tx(bytes: &[u8], hex_mode: bool) {
+    if events::jsonl() {
+        return; // JSONL 模式下已有 tx 事件
+    }
+    let text = if hex_mode {
+        format_hex(bytes)
+    } else {
+        String::from_utf8_lossy(bytes).trim_end_matches(['\r', '\n']).to_string()
+    };
+    anstream::println!("\x1b[36mTX> {}\x1b[0m", text);
+}
+
+/// 按 --checksum 插入校验和，再按 --frame 封装
+fn encode_payload(mut bytes: Vec<u8>, args: &Args) -> Result<Vec<u8>> {
+    if let Some(checksum) = &args.checksum {
+        bytes = checksum.apply(&bytes)?;
+    }
+    match &args.frame {
+        Some(frame) => frame.encode(&bytes),
+        None => Ok(bytes),
+    }
+}
+
 // 打开对应串口函数
-fn open_serial(port_name: &str, baud_rate: u32) -> Result<Box<dyn SerialPort>> {
-    let port = serialport::new(port_name, baud_rate)
-        .data_bits(serialport::DataBits::Eight)
-        .stop_bits(serialport::StopBits::One)
-        .parity(serialport::Parity::None)
-        .timeout(Duration::from_millis(100))
-        .open()
-        .with_context(|| format!("无法打开端口 {}", port_name))?;
+fn open_serial(port_name: &str, args: &Args) -> Result<Box<dyn SerialPort>> {
+    let data_bits = serialport::DataBits::try_from(args.data_bits)
+        .map_err(|_| anyhow::anyhow!("不支持的数据位: {}", args.data_bits))?;
+    let stop_bits = serialport::StopBits::try_from(args.stop_bits)
+        .map_err(|_| anyhow::anyhow!("不支持的停止位: {}", args.stop_bits))?;
+
+    let mut port = match netport::is_url(port_name) {
+        true => {
+            let settings = netport::Settings {
+                baud: args.baud,
+                data_bits,
+                parity: args.parity.into(),
+                stop_bits,
+                flow_control: args.flow_control.into(),
+                timeout: Duration::from_millis(args.timeout_ms),
+            };
+            netport::open(port_name, &settings).with_context(|| format!("无法打开网络串口 {}", port_name))?
+        }
+        false => serialport::new(port_name, args.baud)
+            .data_bits(data_bits)
+            .stop_bits(stop_bits)
+            .parity(args.parity.into())
+            .flow_control(args.flow_control.into())
+            .timeout(Duration::from_millis(args.timeout_ms))
+            .open()
+            .with_context(|| format!("无法打开端口 {}", port_name))?,
+    };
+
+    // 非标准波特率可能被驱动静默取近似值，这里核对实际生效的值
+    match port.baud_rate() {
+        Ok(actual) if actual != args.baud => events::status(&format!(
+            "警告：请求波特率 {}，驱动实际使用 {}（误差 {:.2}%）",
+            args.baud,
+            actual,
+            (actual as f64 - args.baud as f64).abs() * 100.0 / args.baud as f64
+        )),
+        Ok(_) => {}
+        Err(e) => log::debug!("无法读取实际波特率: {}", e),
+    }
+
+    // RS-485 平时保持接收方向
+    if let Some(rs485) = rs485_config(args) {
+        rs485.receive(&mut port)?;
+    }
 
     Ok(port)
 }
 
-// 检查串口是否存在
-fn port_exists(port_name: &str) -> bool {
-    serialport::available_ports()
-        .map(|ports| ports.iter().any(|p| p.port_name == port_name))
-        .unwrap_or(false)
+fn rs485_config(args: &Args) -> Option<Rs485> {
+    args.rs485.then_some(Rs485 { pre_delay: args.rs485_pre_delay, post_delay: args.rs485_post_delay })
+}
+
+/// 列出所有可用串口及其USB信息
+fn list_ports() -> Result<()> {
+    let ports = serialport::available_ports().context("枚举串口失败")?;
+    if ports.is_empty() {
+        println!("未发现可用串口");
+        return Ok(());
+    }
+
+    let print_row = |name: &str, id: &str, manufacturer: &str, product: &str, serial: &str| {
+        println!("{:<16} {:<10} {:<24} {:<32} {}", name, id, manufacturer, product, serial);
+    };
+
+    print_row("端口", "VID:PID", "厂商", "产品", "序列号");
+    for p in &ports {
+        match &p.port_type {
+            serialport::SerialPortType::UsbPort(usb) => print_row(
+                &p.port_name,
+                &format!("{:04X}:{:04X}", usb.vid, usb.pid),
+                usb.manufacturer.as_deref().unwrap_or("-"),
+                usb.product.as_deref().unwrap_or("-"),
+                usb.serial_number.as_deref().unwrap_or("-"),
+            ),
+            serialport::SerialPortType::PciPort => print_row(&p.port_name, "PCI", "-", "-", "-"),
+            serialport::SerialPortType::BluetoothPort => print_row(&p.port_name, "蓝牙", "-", "-", "-"),
+            serialport::SerialPortType::Unknown => print_row(&p.port_name, "未知", "-", "-", "-"),
+        }
+    }
+
+    Ok(())
+}
+
+/// 一次发送流程中的步骤
+enum SendStep {
+    /// 发送一段数据
+    Data(Vec<u8>),
+    /// 等待一段时间
+    Delay(Duration),
+}
+
+/// 根据参数生成发送步骤，`seq` 为当前发送序号
+fn load_steps(send: &SendArgs, hex_mode: bool, seq: u64) -> Result<Vec<SendStep>> {
+    if let Some(path) = &send.script {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("读取脚本 {} 失败", path.display()))?;
+        return parse_script(&content, send, hex_mode, seq)
+            .with_context(|| format!("解析脚本 {} 失败", path.display()));
+    }
+
+    if let Some(path) = &send.file {
+        let data = fs::read(path).with_context(|| format!("读取文件 {} 失败", path.display()))?;
+        let mut bytes = if hex_mode {
+            parse_hex(&String::from_utf8_lossy(&data)).context("十六进制解析失败")?
+        } else {
+            data
+        };
+        if let Some(offset) = send.offset {
+            if offset > bytes.len() as u64 {
+                anyhow::bail!("偏移 {} 超出了文件长度（{} 字节）", offset, bytes.len());
+            }
+            bytes.drain(..offset as usize);
+        }
+        bytes.extend_from_slice(send.line_ending.as_bytes());
+        return Ok(vec![SendStep::Data(bytes)]);
+    }
+
+    let message = send.message.as_deref().unwrap_or_default();
+    Ok(vec![SendStep::Data(encode_message(message, send, hex_mode, seq)?)])
 }
 
-fn send_message(port: &mut Box<dyn SerialPort>, message: &str, hex_mode: bool) -> Result<()> {
-    // 转化为字节流
-    let bytes = if hex_mode {
+/// 解析发送脚本
+///
+/// 每行一条消息，按顺序发送：
+/// - `hex: 01 02 03` / `text: hello`：指定本行的解析方式，无前缀时沿用全局 `--hex`
+/// - `delay: 500ms`：等待指定时长
+/// - 空行和 `#` 开头的注释行会被忽略
+fn parse_script(content: &str, send: &SendArgs, hex_mode: bool, seq: u64) -> Result<Vec<SendStep>> {
+    let mut steps = Vec::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let step = if let Some(rest) = trimmed.strip_prefix("delay:") {
+            parse_duration(rest).map(SendStep::Delay).map_err(anyhow::Error::msg)
+        } else if let Some(rest) = trimmed.strip_prefix("hex:") {
+            encode_message(rest.trim(), send, true, seq).map(SendStep::Data)
+        } else if let Some(rest) = line.trim_start().strip_prefix("text:") {
+            let text = rest.strip_prefix(' ').unwrap_or(rest);
+            encode_message(text, send, false, seq).map(SendStep::Data)
+        } else {
+            encode_message(line, send, hex_mode, seq).map(SendStep::Data)
+        };
+        steps.push(step.with_context(|| format!("第 {} 行有误", i + 1))?);
+    }
+
+    Ok(steps)
+}
+
+/// 将一条文本消息编码为字节流（含模板展开、计数替换、转义和末尾换行符）
+fn encode_message(message: &str, send: &SendArgs, hex_mode: bool, seq: u64) -> Result<Vec<u8>> {
+    let mut message = if send.template {
+        template::expand(message, seq).context("模板展开失败")?
+    } else {
+        message.to_string()
+    };
+    if send.counter {
+        let value = if hex_mode { format!("{:02X}", seq & 0xFF) } else { seq.to_string() };
+        message = message.replace("{n}", &value);
+    }
+
+    let mut bytes = if hex_mode {
         parse_hex(&message).context("十六进制解析失败")?
+    } else if send.escapes {
+        parse_escapes(&message).context("转义序列解析失败")?
     } else {
-        message.as_bytes().to_vec()
+        message.into_bytes()
     };
+    bytes.extend_from_slice(send.line_ending.as_bytes());
+    Ok(bytes)
+}
 
+fn send_message(port: &mut Box<dyn SerialPort>, bytes: &[u8], pacing: &mut TxPacing) -> Result<()> {
     // 写入串口
-    port.write_all(&bytes)
-        .context("写入串口失败")?;
+    pacing.write(port, bytes)?;
 
     // 确保数据完全发送
     port.flush()
         .context("刷新缓冲区失败")?;
+    events::emit(Event::Tx(bytes));
 
     Ok(())
 }
 
-/// 持续监听串口数据
-fn monitor_port(port: &mut Box<dyn SerialPort>, hex_mode: bool) -> Result<()> {
-    let mut buffer = [0u8; 256]; // 固定大小缓冲区
+/// 分段发送文件内容并显示进度，`offset` 为之前已经发送过的字节数
+fn send_file_data(port: &mut Box<dyn SerialPort>, bytes: &[u8], pacing: &mut TxPacing, offset: usize) -> Result<()> {
+    let mut progress = Progress::resumed(Some(offset + bytes.len()), offset);
+    let mut sent = 0;
+    for piece in bytes.chunks(1024) {
+        pacing.write(port, piece)?;
+        port.flush().context("刷新缓冲区失败")?;
+        sent += piece.len();
+        progress.update(offset + sent);
+    }
+    progress.finish();
+    events::emit(Event::Tx(bytes));
+    Ok(())
+}
 
-    loop {
+/// 读取串口直到数据匹配正则或超时，匹配成功时打印收到的响应
+fn expect_response(port: &mut Box<dyn SerialPort>, pattern: &Regex, timeout: Duration, hex_mode: bool) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 256];
+
+    while Instant::now() < deadline {
         match port.read(&mut buffer) {
-            Ok(n) => {
-                // 将字节转为字符串（宽松UTF-8处理）
-                let output = if hex_mode {
-                    format_hex(&buffer[..n])
-                } else {
-                    String::from_utf8_lossy(&buffer[..n]).into_owned()
-                };
-                println!("{}", output); // 实时输出
-            },
-            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
-            Err(e) => return Err(e.into()),
+            Ok(n) => received.extend_from_slice(&buffer[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e).context("读取响应失败"),
+        }
+
+        let text = String::from_utf8_lossy(&received);
+        if pattern.is_match(&text) {
+            if events::jsonl() {
+                events::emit(Event::Rx(&received));
+            } else if hex_mode {
+                println!("{}", format_hex(&received));
+            } else {
+                println!("{}", text);
+            }
+            return Ok(());
+        }
+    }
+
+    let partial = if hex_mode {
+        format_hex(&received)
+    } else {
+        String::from_utf8_lossy(&received).into_owned()
+    };
+    anyhow::bail!("等待响应超时（{:?}），未匹配 /{}/，已收到：{:?}", timeout, pattern, partial)
+}
+
+/// 从输入流分块读取并写入串口，返回发送的字节数
+fn send_stream(
+    port: &mut Box<dyn SerialPort>,
+    mut input: impl Read,
+    hex_mode: bool,
+    line_ending: LineEnding,
+    pacing: &mut TxPacing,
+    local_echo: bool,
+) -> Result<usize> {
+    let mut chunk = [0u8; 4096];
+    let mut pending_nibble: Option<u8> = None; // 十六进制模式下跨块的半个字节
+    let mut total = 0;
+
+    loop {
+        let n = match input.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e).context("读取标准输入失败"),
+        };
+
+        let bytes = if hex_mode {
+            let mut out = Vec::with_capacity(n / 2 + 1);
+            for &c in &chunk[..n] {
+                if c.is_ascii_whitespace() || c == b':' {
+                    continue;
+                }
+                let digit = (c as char)
+                    .to_digit(16)
+                    .with_context(|| format!("无效的十六进制字符: '{}'", c as char))? as u8;
+                match pending_nibble.take() {
+                    Some(high) => out.push(high << 4 | digit),
+                    None => pending_nibble = Some(digit),
+                }
+            }
+            out
+        } else {
+            chunk[..n].to_vec()
+        };
+
+        pacing.write(port, &bytes)?;
+        events::emit(Event::Tx(&bytes));
+        if local_echo && !bytes.is_empty() {
+            echo_tx(&bytes, hex_mode);
+        }
+        total += bytes.len();
+    }
+
+    if pending_nibble.is_some() {
+        anyhow::bail!("十六进制字符串长度必须是偶数");
+    }
+    pacing.write(port, line_ending.as_bytes())?;
+    total += line_ending.as_bytes().len();
+    port.flush().context("刷新缓冲区失败")?;
+    Ok(total)
+}
+
+/// 设备断开后反复尝试重新打开端口
+fn reopen_serial(args: &Args) -> Result<Box<dyn SerialPort>> {
+    loop {
+        let port_name = resolver::wait_for_port(&args.port, None)?;
+        match open_serial(&port_name, args) {
+            Ok(port) => return Ok(port),
+            Err(e) => {
+                log::debug!("重新打开端口失败: {:#}", e);
+                thread::sleep(Duration::from_millis(500));
+            }
         }
     }
 }
@@ -135,30 +932,236 @@ fn monitor_port(port: &mut Box<dyn SerialPort>, hex_mode: bool) -> Result<()> {
 fn main() -> Result<()> {
     env_logger::init(); // 初始化日志
     let args = Args::parse();
+    events::init(args.output);
+
+    let result = run(&args);
+    if let Err(e) = &result {
+        if events::jsonl() {
+            events::emit(Event::Error(&format!("{:#}", e)));
+            std::process::exit(1);
+        }
+    }
+    result
+}
 
-    if !port_exists(&args.port) {
-        anyhow::bail!("端口 {} 不存在！可用端口：{:?}",
-            args.port,
-            serialport::available_ports()?
-        );
+fn run(args: &Args) -> Result<()> {
+    // 列出端口不需要打开串口
+    if let Action::List = args.action {
+        return list_ports();
+    }
+    if let Action::Crc(opts) = &args.action {
+        return checksum::run_crc(opts);
     }
+    if let Action::Hex(opts) = &args.action {
+        return image::run_hex(opts);
+    }
+    if let Action::Capture(opts) = &args.action {
+        return capture::run_capture(opts);
+    }
+
+    let config = Config::load(args.config.as_deref())?;
+    let recorder = args.record.as_deref().map(capture::Recorder::create).transpose()?;
+
+    let port_name = match args.wait {
+        Some(0) => resolver::wait_for_port(&args.port, None)?,
+        Some(secs) => resolver::wait_for_port(&args.port, Some(Duration::from_secs(secs)))?,
+        None => resolver::resolve_port(&args.port)?,
+    };
 
     // 打开串口（带错误上下文）
-    let mut port = open_serial(&args.port, args.baud)
+    let port = open_serial(&port_name, args)
         .context("串口初始化失败，请检查端口是否存在或权限")?;
+    let mut port = capture::record(port, recorder.as_ref(), &port_name, args.baud);
+    events::emit(Event::Open { port: &port_name, baud: args.baud });
 
-    match args.action {
-        Action::Send { message } => {  // 直接解构 message
-            send_message(&mut port, &message, args.hex)
+    let reset_name = args.reset_seq.as_deref().or(args.auto_reset.map(AutoReset::name));
+    if let Some(name) = reset_name {
+        if matches!(args.action, Action::Send(_) | Action::Monitor(_) | Action::Terminal(_) | Action::Flash(_)) {
+            let sequence = reset::lookup(name, &config)?;
+            reset::reset(&mut port, &sequence, args.reset_wait, args.reset_banner.as_ref())?;
+        }
+    }
+
+    match &args.action {
+        Action::Send(send) if send.message.as_deref() == Some("-") => {
+            if send.repeat != 1 {
+                anyhow::bail!("从标准输入发送时不支持 --repeat");
+            }
+            if send.expect.is_some() {
+                anyhow::bail!("从标准输入发送时不支持 --expect");
+            }
+            if args.frame.is_some() || args.checksum.is_some() {
+                anyhow::bail!("从标准输入发送时不支持 --frame 和 --checksum");
+            }
+            let total = send_stream(
+                &mut port,
+                io::stdin().lock(),
+                args.hex,
+                send.line_ending,
+                &mut TxPacing::from_args(send, rs485_config(args)),
+                send.local_echo,
+            )
                 .context("发送消息失败")?;
-            println!("消息已发送");
+            events::status(&format!("消息已发送（{} 字节）", total));
+        }
+        Action::Send(send) => {
+            let mut pacing = TxPacing::from_args(send, rs485_config(args));
+            let mut seq = 1;
+            loop {
+                for step in load_steps(send, args.hex, seq)? {
+                    let bytes = match step {
+                        SendStep::Data(bytes) => encode_payload(bytes, args)?,
+                        SendStep::Delay(delay) => {
+                            thread::sleep(delay);
+                            continue;
+                        }
+                    };
+                    match send.file {
+                        Some(_) => send_file_data(&mut port, &bytes, &mut pacing, send.offset.unwrap_or(0) as usize),
+                        None => send_message(&mut port, &bytes, &mut pacing),
+                    }
+                    .context("发送消息失败")?;
+                    if send.local_echo {
+                        echo_tx(&bytes, args.hex);
+                    }
+                    if send.repeat == 1 {
+                        events::status(&format!("消息已发送（{} 字节）", bytes.len()));
+                    } else {
+                        events::status(&format!("第 {} 次消息已发送（{} 字节）", seq, bytes.len()));
+                    }
+
+                    if let Some(pattern) = &send.expect {
+                        expect_response(&mut port, pattern, send.expect_timeout, args.hex)?;
+                    }
+                }
+
+                if send.repeat != 0 && seq >= send.repeat {
+                    break;
+                }
+                seq += 1;
+                thread::sleep(send.interval);
+            }
+        }
+        Action::Monitor(opts) => {
+            let mut monitor = Monitor::new(opts, &port_name, args.hex, args.frame.as_ref(), args.checksum.as_ref(), args.rx_buffer as usize)?;
+            events::status("开始监听串口数据（按 Ctrl+C 退出）...");
+            loop {
+                match monitor.run(&mut port) {
+                    Err(e) if opts.reconnect => {
+                        events::status(&format!("[连接断开: {}，正在重连...]", e));
+                        events::emit(Event::Close { port: &port_name });
+                        let lost_at = Instant::now();
+                        port = capture::record(reopen_serial(args)?, recorder.as_ref(), &port_name, args.baud);
+                        events::emit(Event::Open { port: &port_name, baud: args.baud });
+                        events::status(&format!("[已重连，中断 {:.1} 秒]", lost_at.elapsed().as_secs_f64()));
+                    }
+                    result => {
+                        result.context("监听过程中发生错误")?;
+                        break;
+                    }
+                }
+            }
+            monitor.finish()?;
+        }
+        Action::Receive(opts) => {
+            receive::run_receive(&mut port, opts)?;
+        }
+        Action::Break { duration } => {
+            port.set_break().context("设置 break 失败")?;
+            thread::sleep(*duration);
+            port.clear_break().context("清除 break 失败")?;
+            events::status(&format!("已发送 break 信号（{:?}）", duration));
+        }
+        Action::Signal(opts) => {
+            signal::run_signal(&mut port, opts)?;
+        }
+        Action::DetectBaud(opts) => {
+            detect::run_detect(&mut port, opts, rs485_config(args))?;
         }
-        Action::Monitor => {
-            println!("开始监听串口数据（按 Ctrl+C 退出）...");
-            monitor_port(&mut port, args.hex)
-                .context("监听过程中发生错误")?;
+        Action::Loopback(opts) => {
+            loopback::run_loopback(&mut port, opts, rs485_config(args))?;
         }
+        Action::Ber(opts) => {
+            ber::run_ber(&mut port, opts, rs485_config(args))?;
+        }
+        Action::Ping(opts) => {
+            ping::run_ping(&mut port, opts, rs485_config(args))?;
+        }
+        Action::Bench(opts) => {
+            bench::run_bench(&mut port, opts)?;
+        }
+        Action::Modbus(opts) => {
+            modbus::run_modbus(&mut port, opts, rs485_config(args))?;
+        }
+        Action::Dlt645(opts) => {
+            dlt645::run_dlt645(&mut port, opts, rs485_config(args))?;
+        }
+        Action::Mbus(opts) => {
+            mbus::run_mbus(&mut port, opts, rs485_config(args))?;
+        }
+        Action::Slcan(opts) => {
+            slcan::run_slcan(&mut port, opts)?;
+        }
+        Action::Obd(opts) => {
+            obd::run_obd(&mut port, opts)?;
+        }
+        Action::At(opts) => {
+            at::run_at(&mut port, opts, rs485_config(args))?;
+        }
+        Action::Sms(opts) => {
+            sms::run_sms(&mut port, opts, rs485_config(args))?;
+        }
+        Action::Esp(opts) => {
+            esp::run_esp(&mut port, opts, rs485_config(args))?;
+        }
+        Action::Xbee(opts) => {
+            xbee::run_xbee(&mut port, opts, rs485_config(args))?;
+        }
+        Action::Ubx(opts) => {
+            ubx::run_ubx(&mut port, opts, rs485_config(args))?;
+        }
+        Action::Gps(opts) => {
+            gps::run_gps(&mut port, opts)?;
+        }
+        Action::Firmata(opts) => {
+            firmata::run_firmata(&mut port, opts, rs485_config(args))?;
+        }
+        Action::Lin(opts) => {
+            lin::run_lin(&mut port, opts)?;
+        }
+        Action::Xfer(opts) => {
+            xfer::run_xfer(&mut port, opts, rs485_config(args))?;
+        }
+        Action::Bridge(opts) => {
+            bridge::run_bridge(&mut port, opts, rs485_config(args), args.frame.as_ref())?;
+        }
+        Action::Flash(opts) => {
+            flash::run_flash(&mut port, opts, rs485_config(args), &config)?;
+        }
+        Action::Terminal(opts) => {
+            terminal::run_terminal(&mut port, opts, args.hex, rs485_config(args), args.rx_buffer as usize)?;
+        }
+        Action::List | Action::Crc(_) | Action::Hex(_) | Action::Capture(_) => unreachable!(),
     }
 
+    events::emit(Event::Close { port: &port_name });
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_input() {
+        assert_eq!(parse_hex("A1b2").unwrap(), [0xA1, 0xB2]);
+        assert_eq!(parse_hex(" 01 02\t03\r\n0a:0B ").unwrap(), [0x01, 0x02, 0x03, 0x0A, 0x0B]);
+        assert_eq!(parse_hex("0x01 0X02 0xA1B2").unwrap(), [0x01, 0x02, 0xA1, 0xB2]);
+        assert_eq!(parse_hex("").unwrap(), []);
+
+        // 奇数个数字、非十六进制字符和多字节字符都返回错误而不是 panic
+        for input in ["abc", "zz", "0x1", "01 0y02", "é1", "１２"] {
+            assert!(parse_hex(input).is_err(), "{:?} 应解析失败", input);
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! MAVLink v1/v2 帧解码：识别起始符（v1 为 FE，v2 为 FD）和长度，按消息的 CRC_EXTRA 校验 CRC，
+//! 显示消息名、系统/组件 ID 和常用消息（心跳、姿态、GPS 等）的主要字段
+//!
+//! v1：`FE 长度 序号 系统 组件 消息ID 载荷 CRC(2)`；
+//! v2：`FD 长度 不兼容标志 兼容标志 序号 系统 组件 消息ID(3, 低字节在前) 载荷 CRC(2) [签名(13)]`，
+//! v2 的载荷会截掉末尾的 0，解码字段时按 0 补齐。
+
+use crate::checksum::crc16_x25;
+
+const MAGIC_V1: u8 = 0xFE;
+const MAGIC_V2: u8 = 0xFD;
+/// v2 不兼容标志中的签名位
+const SIGNED: u8 = 0x01;
+
+/// 是否为帧起始符
+pub fn is_magic(byte: u8) -> bool {
+    byte == MAGIC_V1 || byte == MAGIC_V2
+}
+
+/// 由帧头算出整帧长度，帧头不完整时返回 `None`
+pub fn frame_len(frame: &[u8]) -> Option<usize> {
+    match *frame.first()? {
+        MAGIC_V1 => Some(*frame.get(1)? as usize + 8),
+        _ => {
+            let len = *frame.get(1)? as usize + 12;
+            Some(if frame.get(2)? & SIGNED != 0 { len + 13 } else { len })
+        }
+    }
+}
+
+/// 帧头字段
+struct Header {
+    version: u8,
+    seq: u8,
+    system: u8,
+    component: u8,
+    id: u32,
+    payload: usize,
+}
+
+fn header(frame: &[u8]) -> Header {
+    match frame[0] {
+        MAGIC_V1 => Header { version: 1, seq: frame[2], system: frame[3], component: frame[4], id: frame[5] as u32, payload: 6 },
+        _ => Header {
+            version: 2,
+            seq: frame[4],
+            system: frame[5],
+            component: frame[6],
+            id: u32::from_le_bytes([frame[7], frame[8], frame[9], 0]),
+            payload: 10,
+        },
+    }
+}
+
+/// 常用消息的名称和 CRC_EXTRA（由消息定义算出，校验 CRC 时追加在载荷后面）
+fn message(id: u32) -> Option<(&'static str, u8)> {
+    let message = match id {
+        0 => ("HEARTBEAT", 50),
+        1 => ("SYS_STATUS", 124),
+        2 => ("SYSTEM_TIME", 137),
+        4 => ("PING", 237),
+        11 => ("SET_MODE", 89),
+        20 => ("PARAM_REQUEST_READ", 214),
+        21 => ("PARAM_REQUEST_LIST", 159),
+        22 => ("PARAM_VALUE", 220),
+        23 => ("PARAM_SET", 168),
+        24 => ("GPS_RAW_INT", 24),
+        25 => ("GPS_STATUS", 23),
+        26 => ("SCALED_IMU", 170),
+        27 => ("RAW_IMU", 144),
+        29 => ("SCALED_PRESSURE", 115),
+        30 => ("ATTITUDE", 39),
+        31 => ("ATTITUDE_QUATERNION", 246),
+        32 => ("LOCAL_POSITION_NED", 185),
+        33 => ("GLOBAL_POSITION_INT", 104),
+        35 => ("RC_CHANNELS_RAW", 244),
+        36 => ("SERVO_OUTPUT_RAW", 222),
+        42 => ("MISSION_CURRENT", 28),
+        44 => ("MISSION_COUNT", 221),
+        47 => ("MISSION_ACK", 153),
+        51 => ("MISSION_REQUEST_INT", 196),
+        62 => ("NAV_CONTROLLER_OUTPUT", 183),
+        65 => ("RC_CHANNELS", 118),
+        66 => ("REQUEST_DATA_STREAM", 148),
+        69 => ("MANUAL_CONTROL", 243),
+        73 => ("MISSION_ITEM_INT", 38),
+        74 => ("VFR_HUD", 20),
+        76 => ("COMMAND_LONG", 152),
+        77 => ("COMMAND_ACK", 143),
+        105 => ("HIGHRES_IMU", 93),
+        109 => ("RADIO_STATUS", 185),
+        111 => ("TIMESYNC", 34),
+        116 => ("SCALED_IMU2", 76),
+        125 => ("POWER_STATUS", 203),
+        147 => ("BATTERY_STATUS", 154),
+        148 => ("AUTOPILOT_VERSION", 178),
+        230 => ("ESTIMATOR_STATUS", 163),
+        241 => ("VIBRATION", 90),
+        242 => ("HOME_POSITION", 104),
+        245 => ("EXTENDED_SYS_STATE", 130),
+        253 => ("STATUSTEXT", 83),
+        _ => return None,
+    };
+    Some(message)
+}
+
+/// 校验 CRC；不认识的消息没有 CRC_EXTRA，无法校验
+pub fn check(frame: &[u8]) -> std::result::Result<(), String> {
+    let header = header(frame);
+    let Some((_, extra)) = message(header.id) else {
+        return Ok(());
+    };
+    let end = header.payload + frame[1] as usize;
+    let crc = !crc16_x25(&[&frame[1..end], &[extra]].concat());
+    let received = u16::from_le_bytes([frame[end], frame[end + 1]]);
+    match crc == received {
+        true => Ok(()),
+        false => Err(format!("CRC 错误：收到 {:04X}，应为 {:04X}", received, crc)),
+    }
+}
+
+/// 帧内容说明，如 "v2 #12 系统 1 组件 1 HEARTBEAT 四旋翼 ArduPilot 已解锁 ..."
+pub fn describe(frame: &[u8]) -> String {
+    let header = header(frame);
+    let payload = &frame[header.payload..header.payload + frame[1] as usize];
+    let name = match message(header.id) {
+        Some((name, _)) => name.to_string(),
+        None => format!("消息 {}（未校验 CRC）", header.id),
+    };
+    let mut text = format!("v{} #{} 系统 {} 组件 {} {}", header.version, header.seq, header.system, header.component, name);
+    if let Some(fields) = fields(header.id, &Payload(payload)) {
+        text.push(' ');
+        text.push_str(&fields);
+    }
+    if header.version == 2 && frame[2] & SIGNED != 0 {
+        text.push_str("（已签名）");
+    }
+    text
+}
+
+/// 载荷，超出实际长度的字节按 0 读取（v2 截掉了末尾的 0）
+struct Payload<'a>(&'a [u8]);
+
+impl Payload<'_> {
+    fn bytes<const N: usize>(&self, offset: usize) -> [u8; N] {
+        let mut bytes = [0u8; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.0.get(offset + i).copied().unwrap_or(0);
+        }
+        bytes
+    }
+
+    fn u8(&self, offset: usize) -> u8 {
+        self.bytes::<1>(offset)[0]
+    }
+
+    fn u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes(self.bytes(offset))
+    }
+
+    fn i16(&self, offset: usize) -> i16 {
+        i16::from_le_bytes(self.bytes(offset))
+    }
+
+    fn u32(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.bytes(offset))
+    }
+
+    fn i32(&self, offset: usize) -> i32 {
+        i32::from_le_bytes(self.bytes(offset))
+    }
+
+    fn f32(&self, offset: usize) -> f32 {
+        f32::from_le_bytes(self.bytes(offset))
+    }
+}
+
+/// 常用消息的主要字段（按线路上的字段顺序，即按类型大小排序后的偏移）
+fn fields(id: u32, p: &Payload) -> Option<String> {
+    let text = match id {
+        0 => format!(
+            "{} {} {} {} 自定义模式 {}",
+            vehicle_type(p.u8(4)),
+            autopilot(p.u8(5)),
+            if p.u8(6) & 0x80 != 0 { "已解锁" } else { "未解锁" },
+            system_status(p.u8(7)),
+            p.u32(0)
+        ),
+        1 => {
+            let remaining = p.u8(30) as i8;
+            format!(
+                "电池 {:.2} V {:.2} A 剩余 {}，CPU 负载 {:.1}%",
+                p.u16(14) as f64 / 1000.0,
+                p.i16(16) as f64 / 100.0,
+                if remaining < 0 { "未知".to_string() } else { format!("{}%", remaining) },
+                p.u16(12) as f64 / 10.0
+            )
+        }
+        24 => format!(
+            "{} 卫星 {} 纬度 {:.7} 经度 {:.7} 高度 {:.2} m HDOP {:.2} 速度 {:.2} m/s",
+            fix_type(p.u8(28)),
+            p.u8(29),
+            p.i32(8) as f64 / 1e7,
+            p.i32(12) as f64 / 1e7,
+            p.i32(16) as f64 / 1000.0,
+            p.u16(20) as f64 / 100.0,
+            p.u16(24) as f64 / 100.0
+        ),
+        30 => format!(
+            "横滚 {:.1}° 俯仰 {:.1}° 偏航 {:.1}°",
+            p.f32(4).to_degrees(),
+            p.f32(8).to_degrees(),
+            p.f32(12).to_degrees()
+        ),
+        33 => format!(
+            "纬度 {:.7} 经度 {:.7} 高度 {:.2} m 相对高度 {:.2} m 航向 {:.1}°",
+            p.i32(4) as f64 / 1e7,
+            p.i32(8) as f64 / 1e7,
+            p.i32(12) as f64 / 1000.0,
+            p.i32(16) as f64 / 1000.0,
+            p.u16(26) as f64 / 100.0
+        ),
+        74 => format!(
+            "空速 {:.1} m/s 地速 {:.1} m/s 航向 {}° 油门 {}% 高度 {:.1} m 爬升率 {:.1} m/s",
+            p.f32(0),
+            p.f32(4),
+            p.i16(16),
+            p.u16(18),
+            p.f32(8),
+            p.f32(12)
+        ),
+        77 => format!("命令 {} {}", p.u16(0), command_result(p.u8(2))),
+        253 => {
+            let text: Vec<u8> = p.bytes::<50>(1).into_iter().take_while(|&b| b != 0).collect();
+            format!("[{}] {}", severity(p.u8(0)), String::from_utf8_lossy(&text))
+        }
+        _ => return None,
+    };
+    Some(text)
+}
+
+fn vehicle_type(kind: u8) -> String {
+    let name = match kind {
+        0 => "通用",
+        1 => "固定翼",
+        2 => "四旋翼",
+        3 => "共轴直升机",
+        4 => "直升机",
+        6 => "地面站",
+        10 => "地面车辆",
+        11 => "水面船",
+        12 => "潜航器",
+        13 => "六旋翼",
+        14 => "八旋翼",
+        15 => "三旋翼",
+        19..=22 => "垂直起降",
+        26 => "云台",
+        _ => return format!("类型 {}", kind),
+    };
+    name.to_string()
+}
+
+fn autopilot(kind: u8) -> String {
+    match kind {
+        0 => "通用飞控".to_string(),
+        3 => "ArduPilot".to_string(),
+        8 => "非飞控".to_string(),
+        12 => "PX4".to_string(),
+        _ => format!("飞控 {}", kind),
+    }
+}
+
+fn system_status(status: u8) -> String {
+    let name = match status {
+        0 => "未初始化",
+        1 => "启动中",
+        2 => "校准中",
+        3 => "待机",
+        4 => "运行中",
+        5 => "严重故障",
+        6 => "紧急状态",
+        7 => "关机中",
+        8 => "飞行终止",
+        _ => return format!("状态 {}", status),
+    };
+    name.to_string()
+}
+
+fn fix_type(fix: u8) -> String {
+    let name = match fix {
+        0 => "无 GPS",
+        1 => "未定位",
+        2 => "2D 定位",
+        3 => "3D 定位",
+        4 => "DGPS",
+        5 => "RTK 浮点解",
+        6 => "RTK 固定解",
+        7 => "静态",
+        8 => "PPP",
+        _ => return format!("定位类型 {}", fix),
+    };
+    name.to_string()
+}
+
+fn command_result(result: u8) -> String {
+    let name = match result {
+        0 => "已接受",
+        1 => "暂时拒绝",
+        2 => "拒绝",
+        3 => "不支持",
+        4 => "失败",
+        5 => "执行中",
+        6 => "已取消",
+        _ => return format!("结果 {}", result),
+    };
+    name.to_string()
+}
+
+fn severity(level: u8) -> &'static str {
+    match level {
+        0 => "EMERGENCY",
+        1 => "ALERT",
+        2 => "CRITICAL",
+        3 => "ERROR",
+        4 => "WARNING",
+        5 => "NOTICE",
+        6 => "INFO",
+        _ => "DEBUG",
+    }
+}
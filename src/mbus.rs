@@ -0,0 +1,398 @@
+//! M-Bus（EN 13757-2/-3）主站：经电平转换器向热量表、水表等发送 REQ_UD2，解码 RSP_UD 中的数据记录
+//!
+//! 帧格式：单字符 `E5`（确认）、短帧 `10 C A CS 16`、长帧 `68 L L 68 C A CI 数据 CS 16`，
+//! CS 为 C 到数据末尾的字节和。
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::events::{self, Event};
+use crate::format_hex;
+use crate::parse_duration;
+use crate::signal::Rs485;
+use crate::terminal;
+
+/// mbus 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct MbusArgs {
+    /// 一次地址（0～250；253 为二次地址寻址，254 为点对点广播，总线上只能有一块表）
+    #[arg(long, default_value_t = 254, value_parser = clap::value_parser!(u8).range(0..=254))]
+    pub addr: u8,
+
+    /// 请求前不发送 SND_NKE 初始化
+    #[arg(long)]
+    pub no_init: bool,
+
+    /// 等待应答的超时时间
+    #[arg(long, default_value = "2s", value_parser = parse_duration)]
+    pub timeout: Duration,
+}
+
+/// 确认字符
+const ACK: u8 = 0xE5;
+/// SND_NKE 和 REQ_UD2 的控制码（REQ_UD2 的 FCB 位为 0）
+const SND_NKE: u8 = 0x40;
+const REQ_UD2: u8 = 0x5B;
+
+/// 执行 mbus 子命令：初始化后发送 REQ_UD2 并解码应答
+pub fn run_mbus(port: &mut Box<dyn SerialPort>, opts: &MbusArgs, rs485: Option<Rs485>) -> Result<()> {
+    port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+    if !opts.no_init {
+        terminal::transmit(port, rs485, &short_frame(SND_NKE, opts.addr))?;
+        match receive(port, Instant::now() + opts.timeout)? {
+            Some(frame) if frame == [ACK] => {}
+            Some(frame) => bail!("SND_NKE 的应答不是 E5：{}", format_hex(&frame)),
+            None => bail!("地址 {} 无应答（SND_NKE 超时 {:?}）", opts.addr, opts.timeout),
+        }
+    }
+
+    terminal::transmit(port, rs485, &short_frame(REQ_UD2, opts.addr))?;
+    let frame = receive(port, Instant::now() + opts.timeout)?
+        .with_context(|| format!("地址 {} 无应答（REQ_UD2 超时 {:?}）", opts.addr, opts.timeout))?;
+    let (control, address, ci, data) = decode_long(&frame)?;
+    if control & 0x0F != 0x08 {
+        bail!("应答控制码 0x{:02X} 不是 RSP_UD", control);
+    }
+
+    events::status(&format!("RSP_UD 地址 {}，CI 0x{:02X}", address, ci));
+    let records = match ci {
+        0x72 => {
+            let header = data.get(..12).context("应答数据短于固定数据头")?;
+            print_header(header);
+            &data[12..]
+        }
+        0x7A => data.get(4..).context("应答数据短于短数据头")?,
+        0x78 => data,
+        _ => bail!("不支持的 CI 0x{:02X}（仅支持可变数据结构 72/78/7A）", ci),
+    };
+    for line in decode_records(records) {
+        events::status(&format!("  {}", line));
+    }
+    Ok(())
+}
+
+fn short_frame(control: u8, address: u8) -> Vec<u8> {
+    vec![0x10, control, address, control.wrapping_add(address), 0x16]
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |acc, &b| acc.wrapping_add(b))
+}
+
+/// 根据已收到的数据（以起始字节开始）计算帧长，数据不足时返回 `None`
+fn frame_len(data: &[u8]) -> Option<usize> {
+    match data.first()? {
+        &ACK => Some(1),
+        0x10 => Some(5),
+        _ => data.get(1).map(|&len| len as usize + 6),
+    }
+}
+
+/// 接收一帧（单字符、短帧或长帧），超时返回 `None`
+fn receive(port: &mut Box<dyn SerialPort>, deadline: Instant) -> Result<Option<Vec<u8>>> {
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 512];
+    while Instant::now() < deadline {
+        match port.read(&mut buffer) {
+            Ok(n) => received.extend_from_slice(&buffer[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e).context("读取串口失败"),
+        }
+        // 丢弃起始字节之前的噪声（总线上电平转换器常在开头产生 00 或 FF）
+        match received.iter().position(|b| matches!(b, &ACK | 0x10 | 0x68)) {
+            Some(start) => {
+                received.drain(..start);
+            }
+            None => received.clear(),
+        }
+        if let Some(len) = frame_len(&received) {
+            if received.len() >= len {
+                received.truncate(len);
+                events::emit(Event::Rx(&received));
+                return Ok(Some(received));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// 校验长帧，返回 (控制码, 地址, CI, 数据)
+fn decode_long(frame: &[u8]) -> Result<(u8, u8, u8, &[u8])> {
+    let [0x68, len, len2, 0x68, body @ ..] = frame else {
+        bail!("应答不是长帧：{}", format_hex(frame));
+    };
+    let len = *len as usize;
+    if len != *len2 as usize || len < 3 || body.len() != len + 2 {
+        bail!("长帧的长度字段无效：{}", format_hex(frame));
+    }
+    let (body, tail) = body.split_at(len);
+    if tail[1] != 0x16 {
+        bail!("长帧缺少结束符 16：{}", format_hex(frame));
+    }
+    if tail[0] != checksum(body) {
+        bail!("校验和错误：收到 {:02X}，应为 {:02X}", tail[0], checksum(body));
+    }
+    Ok((body[0], body[1], body[2], &body[3..]))
+}
+
+/// 显示固定数据头：表号、厂商、版本、介质、访问号和状态
+fn print_header(header: &[u8]) {
+    let id: String = header[..4].iter().rev().map(|b| format!("{:02X}", b)).collect();
+    let manufacturer = u16::from_le_bytes([header[4], header[5]]);
+    let letters: String = [10, 5, 0].iter().map(|shift| (((manufacturer >> shift) & 0x1F) as u8 + 64) as char).collect();
+    events::status(&format!(
+        "表号 {}，厂商 {}，版本 {}，介质 {}，访问号 {}，状态 0x{:02X}",
+        id,
+        letters,
+        header[6],
+        medium_name(header[7]),
+        header[8],
+        header[9]
+    ));
+}
+
+fn medium_name(code: u8) -> &'static str {
+    match code {
+        0x00 => "其他",
+        0x01 => "油",
+        0x02 => "电",
+        0x03 => "燃气",
+        0x04 => "热量（回水）",
+        0x05 => "蒸汽",
+        0x06 => "温水",
+        0x07 => "水",
+        0x08 => "热分配表",
+        0x09 => "压缩空气",
+        0x0A => "冷量（回水）",
+        0x0B => "冷量（供水）",
+        0x0C => "热量（供水）",
+        0x0D => "冷热量",
+        0x0E => "总线/系统",
+        0x15 => "热水",
+        0x16 => "冷水",
+        0x17 => "冷热水",
+        0x18 => "压力",
+        0x19 => "A/D 转换器",
+        _ => "未知",
+    }
+}
+
+/// 数据记录的数值
+enum Number {
+    Int(i64),
+    Real(f64),
+    Text(String),
+}
+
+/// 解码全部数据记录，每条记录一行
+fn decode_records(mut data: &[u8]) -> Vec<String> {
+    let mut lines = Vec::new();
+    while let Some((&dif, rest)) = data.split_first() {
+        data = rest;
+        match dif {
+            // 填充字节
+            0x2F => continue,
+            0x0F | 0x1F => {
+                let more = if dif == 0x1F { "，后续还有数据" } else { "" };
+                lines.push(format!("厂商数据 {}{}", format_hex(data), more));
+                break;
+            }
+            _ => {}
+        }
+        match decode_record(dif, data) {
+            Ok((line, used)) => {
+                lines.push(line);
+                data = &data[used..];
+            }
+            Err(error) => {
+                lines.push(format!("{}（剩余 {}）", error, format_hex(data)));
+                break;
+            }
+        }
+    }
+    lines
+}
+
+/// 解码一条记录（`data` 从 DIF 之后开始），返回说明和使用的字节数
+fn decode_record(dif: u8, data: &[u8]) -> std::result::Result<(String, usize), String> {
+    let mut pos = 0;
+    let mut storage = ((dif >> 6) & 1) as u64;
+    let mut tariff = 0u32;
+    let mut extension = dif & 0x80 != 0;
+    let mut dife_count = 0;
+    while extension {
+        // 规范规定最多 10 个 DIFE
+        if dife_count == 10 {
+            return Err("DIFE 超过 10 个".to_string());
+        }
+        let dife = *data.get(pos).ok_or("DIFE 不完整")?;
+        storage |= ((dife & 0x0F) as u64) << (1 + 4 * dife_count);
+        tariff |= (((dife >> 4) & 0x03) as u32) << (2 * dife_count);
+        extension = dife & 0x80 != 0;
+        dife_count += 1;
+        pos += 1;
+    }
+
+    let vif = *data.get(pos).ok_or("缺少 VIF")?;
+    pos += 1;
+    let mut vifes = Vec::new();
+    let mut extension = vif & 0x80 != 0;
+    while extension {
+        let vife = *data.get(pos).ok_or("VIFE 不完整")?;
+        vifes.push(vife);
+        extension = vife & 0x80 != 0;
+        pos += 1;
+    }
+    // 纯文本单位：VIFE 之后是长度和倒序的 ASCII 单位
+    let mut text_unit = None;
+    if vif & 0x7F == 0x7C {
+        let len = *data.get(pos).ok_or("文本单位不完整")? as usize;
+        let unit = data.get(pos + 1..pos + 1 + len).ok_or("文本单位不完整")?;
+        text_unit = Some(unit.iter().rev().map(|&b| b as char).collect::<String>());
+        pos += 1 + len;
+    }
+
+    let coding = dif & 0x0F;
+    let size = match coding {
+        0x0 | 0x8 => 0,
+        0x1 | 0x9 => 1,
+        0x2 | 0xA => 2,
+        0x3 | 0xB => 3,
+        0x4 | 0x5 | 0xC => 4,
+        0x6 | 0xE => 6,
+        0x7 => 8,
+        0xD => 1 + *data.get(pos).ok_or("变长数据缺少长度")? as usize,
+        _ => return Err(format!("不支持的 DIF 0x{:02X}", dif)),
+    };
+    let raw = data.get(pos..pos + size).ok_or("数据不完整")?;
+    pos += size;
+
+    let number = match coding {
+        0x1..=0x4 | 0x6 | 0x7 => {
+            let mut bytes = [0u8; 8];
+            bytes[..size].copy_from_slice(raw);
+            // 按最高字节的符号位扩展
+            if raw[size - 1] & 0x80 != 0 {
+                bytes[size..].fill(0xFF);
+            }
+            Some(Number::Int(i64::from_le_bytes(bytes)))
+        }
+        0x5 => Some(Number::Real(f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64)),
+        0x9..=0xC | 0xE => Some(decode_bcd(raw)),
+        0xD => Some(Number::Text(raw[1..].iter().rev().map(|&b| b as char).collect())),
+        _ => None,
+    };
+
+    let function = match (dif >> 4) & 0x03 {
+        1 => "最大值 ",
+        2 => "最小值 ",
+        3 => "错误时的值 ",
+        _ => "",
+    };
+    let (name, exponent, unit) = match text_unit {
+        Some(unit) => ("数值".to_string(), 0, unit),
+        None => describe_vif(vif, &vifes),
+    };
+    let value = match (&number, vif & 0x7F) {
+        (None, _) => "（无数据）".to_string(),
+        // 时间点：类型 G 为日期，类型 F 为日期和时间
+        (Some(Number::Int(_)), 0x6C) if size == 2 => format_date(raw),
+        (Some(Number::Int(_)), 0x6D) if size == 4 => format_datetime(raw),
+        (Some(number), _) => format_number(number, exponent),
+    };
+    let mut line = format!("{}{} = {}{}", function, name, value, if unit.is_empty() { String::new() } else { format!(" {}", unit) });
+    if storage != 0 {
+        line.push_str(&format!("（存储号 {}）", storage));
+    }
+    if tariff != 0 {
+        line.push_str(&format!("（费率 {}）", tariff));
+    }
+    Ok((line, pos))
+}
+
+/// BCD 数值（低字节在前），最高半字节为 F 表示负数
+fn decode_bcd(raw: &[u8]) -> Number {
+    let digits: String = raw.iter().rev().map(|b| format!("{:02X}", b)).collect();
+    let (negative, digits) = match digits.strip_prefix('F') {
+        Some(rest) => (true, rest),
+        None => (false, digits.as_str()),
+    };
+    match digits.parse::<i64>() {
+        Ok(n) if digits.chars().all(|c| c.is_ascii_digit()) => Number::Int(if negative { -n } else { n }),
+        _ => Number::Text(format!("0x{}", digits)),
+    }
+}
+
+/// 按 10 的幂缩放后显示
+fn format_number(number: &Number, exponent: i32) -> String {
+    match number {
+        Number::Int(n) if exponent >= 0 => (*n as i128 * 10i128.pow(exponent as u32)).to_string(),
+        Number::Int(n) => {
+            let decimals = (-exponent) as usize;
+            let digits = format!("{:0>width$}", n.unsigned_abs(), width = decimals + 1);
+            let (int, frac) = digits.split_at(digits.len() - decimals);
+            format!("{}{}.{}", if *n < 0 { "-" } else { "" }, int, frac)
+        }
+        Number::Real(x) => (x * 10f64.powi(exponent)).to_string(),
+        Number::Text(text) => text.clone(),
+    }
+}
+
+/// 类型 G 日期
+fn format_date(raw: &[u8]) -> String {
+    let day = raw[0] & 0x1F;
+    let month = raw[1] & 0x0F;
+    let year = ((raw[0] & 0xE0) >> 5) | ((raw[1] & 0xF0) >> 1);
+    format!("20{:02}-{:02}-{:02}", year, month, day)
+}
+
+/// 类型 F 日期和时间
+fn format_datetime(raw: &[u8]) -> String {
+    let minute = raw[0] & 0x3F;
+    let hour = raw[1] & 0x1F;
+    format!("{} {:02}:{:02}", format_date(&raw[2..4]), hour, minute)
+}
+
+/// 按 VIF 主表确定名称、10 的幂和单位；扩展表只标注 VIF 值
+fn describe_vif(vif: u8, vifes: &[u8]) -> (String, i32, String) {
+    let n = (vif & 0x07) as i32;
+    let nn = (vif & 0x03) as i32;
+    let duration = |nn: i32| ["s", "min", "h", "天"][nn as usize].to_string();
+    let (name, exponent, unit) = match vif & 0x7F {
+        0x00..=0x07 => ("能量", n - 3, "Wh".to_string()),
+        0x08..=0x0F => ("能量", n, "J".to_string()),
+        0x10..=0x17 => ("体积", n - 6, "m³".to_string()),
+        0x18..=0x1F => ("质量", n - 3, "kg".to_string()),
+        0x20..=0x23 => ("通电时间", 0, duration(nn)),
+        0x24..=0x27 => ("运行时间", 0, duration(nn)),
+        0x28..=0x2F => ("功率", n - 3, "W".to_string()),
+        0x30..=0x37 => ("功率", n, "J/h".to_string()),
+        0x38..=0x3F => ("体积流量", n - 6, "m³/h".to_string()),
+        0x40..=0x47 => ("体积流量", n - 7, "m³/min".to_string()),
+        0x48..=0x4F => ("体积流量", n - 9, "m³/s".to_string()),
+        0x50..=0x57 => ("质量流量", n - 3, "kg/h".to_string()),
+        0x58..=0x5B => ("供水温度", nn - 3, "°C".to_string()),
+        0x5C..=0x5F => ("回水温度", nn - 3, "°C".to_string()),
+        0x60..=0x63 => ("温差", nn - 3, "K".to_string()),
+        0x64..=0x67 => ("外部温度", nn - 3, "°C".to_string()),
+        0x68..=0x6B => ("压力", nn - 3, "bar".to_string()),
+        0x6C => ("日期", 0, String::new()),
+        0x6D => ("日期时间", 0, String::new()),
+        0x6E => ("热分配单位", 0, String::new()),
+        0x70..=0x73 => ("平均时长", 0, duration(nn)),
+        0x74..=0x77 => ("实际时长", 0, duration(nn)),
+        0x78 => ("生产编号", 0, String::new()),
+        0x79 => ("标识", 0, String::new()),
+        0x7A => ("总线地址", 0, String::new()),
+        0x7E => ("任意 VIF", 0, String::new()),
+        0x7F => ("厂商自定义", 0, String::new()),
+        _ => ("VIF", 0, format!("0x{:02X}", vif)),
+    };
+    // FB/FD 扩展表的含义由第一个 VIFE 决定，这里只显示编码
+    match (vif, vifes.first()) {
+        (0xFB | 0xFD, Some(vife)) => (format!("扩展 VIF {:02X} {:02X}", vif, vife), 0, String::new()),
+        _ => (name.to_string(), exponent, unit),
+    }
+}
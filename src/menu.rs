@@ -0,0 +1,157 @@
+//! 交互模式的 Ctrl+A 设置菜单：运行中修改波特率、校验位、十六进制显示和换行符，
+//! 以及切换 DTR/RTS 输出线，无需重新打开串口
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serialport::SerialPort;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::console::Key;
+use crate::editor::LineEditor;
+use crate::signal::Rs485;
+use crate::{parse_baud, LineEnding, ParityArg};
+
+/// 菜单说明
+const HELP: &str = "[设置] b 波特率  p 校验位  h 十六进制  e 换行符  d DTR  r RTS  Ctrl+A 发送 Ctrl+A  Esc 取消";
+
+/// 运行中可修改的设置
+pub struct Runtime {
+    /// 十六进制模式，接收线程据此切换显示方式
+    pub hex: Arc<AtomicBool>,
+    pub line_ending: LineEnding,
+    /// DTR/RTS 输出线状态（打开串口时系统通常会置位两者）
+    pub dtr: bool,
+    pub rts: bool,
+    /// RS-485 方向控制（启用时 RTS 由发送过程控制）
+    pub rs485: Option<Rs485>,
+}
+
+impl Runtime {
+    pub fn new(hex_mode: bool, line_ending: LineEnding, rs485: Option<Rs485>) -> Self {
+        Runtime {
+            hex: Arc::new(AtomicBool::new(hex_mode)),
+            line_ending,
+            dtr: true,
+            rts: rs485.is_none(),
+            rs485,
+        }
+    }
+
+    pub fn hex(&self) -> bool {
+        self.hex.load(Ordering::Relaxed)
+    }
+}
+
+/// 菜单处理按键的结果
+pub enum Outcome {
+    /// 按键不属于菜单，按普通输入处理
+    Pass(Key),
+    /// 菜单打开中，在输入行位置显示该内容（光标距末尾的列数）
+    Prompt(String, usize),
+    /// 菜单已关闭，附带修改结果的提示
+    Done(Option<String>),
+}
+
+#[derive(Default)]
+enum State {
+    #[default]
+    Closed,
+    Open,
+    /// 正在输入新的波特率
+    Baud(LineEditor),
+}
+
+#[derive(Default)]
+pub struct Menu {
+    state: State,
+}
+
+impl Menu {
+    /// 处理一个按键：Ctrl+A 打开菜单，菜单打开时由菜单处理按键
+    pub fn handle(&mut self, key: Key, port: &mut Box<dyn SerialPort>, runtime: &mut Runtime) -> Result<Outcome> {
+        match std::mem::take(&mut self.state) {
+            State::Closed if key == Key::Ctrl('a') => {
+                self.state = State::Open;
+                Ok(Outcome::Prompt(HELP.to_string(), 0))
+            }
+            State::Closed => Ok(Outcome::Pass(key)),
+            State::Open => self.choose(key, port, runtime),
+            State::Baud(mut editor) => match key {
+                Key::Esc | Key::Ctrl('c') => Ok(Outcome::Done(None)),
+                Key::Enter => {
+                    let input = editor.handle(key).unwrap_or_default();
+                    let baud = match parse_baud(input.trim()) {
+                        Ok(baud) => baud,
+                        Err(e) => return Ok(Outcome::Done(Some(format!("[{}]", e)))),
+                    };
+                    port.set_baud_rate(baud).context("设置波特率失败")?;
+                    Ok(Outcome::Done(Some(format!("[波特率已改为 {}]", baud))))
+                }
+                key => {
+                    editor.handle(key);
+                    let prompt = baud_prompt(&editor);
+                    self.state = State::Baud(editor);
+                    Ok(prompt)
+                }
+            },
+        }
+    }
+
+    fn choose(&mut self, key: Key, port: &mut Box<dyn SerialPort>, runtime: &mut Runtime) -> Result<Outcome> {
+        let message = match key {
+            Key::Ctrl('a') => return Ok(Outcome::Pass(key)),
+            Key::Char('b' | 'B') => {
+                let editor = LineEditor::default();
+                let prompt = baud_prompt(&editor);
+                self.state = State::Baud(editor);
+                return Ok(prompt);
+            }
+            Key::Char('p' | 'P') => {
+                let current = port.parity().context("读取校验位失败")?;
+                let next = cycle(ParityArg::value_variants(), |p| serialport::Parity::from(*p) == current);
+                port.set_parity(next.into()).context("设置校验位失败")?;
+                format!("[校验位已改为 {}]", value_name(&next))
+            }
+            Key::Char('h' | 'H') => {
+                let hex = !runtime.hex();
+                runtime.hex.store(hex, Ordering::Relaxed);
+                if hex { "[已切换为十六进制模式]" } else { "[已切换为文本模式]" }.to_string()
+            }
+            Key::Char('e' | 'E') => {
+                let current = runtime.line_ending;
+                runtime.line_ending = cycle(LineEnding::value_variants(), |e| value_name(e) == value_name(&current));
+                format!("[换行符已改为 {}]", value_name(&runtime.line_ending))
+            }
+            Key::Char('d' | 'D') => {
+                port.write_data_terminal_ready(!runtime.dtr).context("设置 DTR 失败")?;
+                runtime.dtr = !runtime.dtr;
+                format!("[DTR 已{}]", if runtime.dtr { "置位" } else { "复位" })
+            }
+            Key::Char('r' | 'R') if runtime.rs485.is_some() => "[RS-485 模式下 RTS 由发送过程控制]".to_string(),
+            Key::Char('r' | 'R') => {
+                port.write_request_to_send(!runtime.rts).context("设置 RTS 失败")?;
+                runtime.rts = !runtime.rts;
+                format!("[RTS 已{}]", if runtime.rts { "置位" } else { "复位" })
+            }
+            _ => return Ok(Outcome::Done(None)),
+        };
+        Ok(Outcome::Done(Some(message)))
+    }
+}
+
+fn baud_prompt(editor: &LineEditor) -> Outcome {
+    let (text, back) = editor.render();
+    Outcome::Prompt(format!("[设置] 新波特率（回车确认，Esc 取消）: {}", text), back)
+}
+
+/// 返回取值列表中当前值的下一个（循环）
+fn cycle<T: Copy>(values: &[T], is_current: impl Fn(&T) -> bool) -> T {
+    let i = values.iter().position(is_current).map_or(0, |i| i + 1);
+    values[i % values.len()]
+}
+
+/// 命令行取值名，如 `crlf`
+fn value_name(value: &impl ValueEnum) -> String {
+    value.to_possible_value().map(|v| v.get_name().to_string()).unwrap_or_default()
+}
@@ -0,0 +1,557 @@
+//! Modbus 协议（RTU 和 ASCII）：帧间隔、CRC/LRC 校验、帧内容说明，以及读写从站或模拟从站的 modbus 子命令
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::fmt::Write;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::checksum::{crc16_modbus, Algorithm};
+use crate::events::{self, Event};
+use crate::format_hex;
+use crate::parse_duration;
+use crate::signal::Rs485;
+use crate::slave::{self, SlaveArgs};
+use crate::terminal;
+
+/// modbus 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct ModbusArgs {
+    #[command(subcommand)]
+    pub command: ModbusCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ModbusCommand {
+    /// 读取线圈、离散输入或寄存器（功能码 01/02/03/04）
+    Read(ReadArgs),
+    /// 写线圈或寄存器（功能码 05/06/15/16）
+    Write(WriteArgs),
+    /// 模拟从站：按映射文件响应主站的读写请求
+    Slave(SlaveArgs),
+}
+
+/// 传输模式
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// 二进制帧，CRC-16 校验
+    Rtu,
+    /// 以 ':' 开始、CRLF 结束的十六进制文本帧，LRC 校验
+    Ascii,
+}
+
+impl Mode {
+    /// 帧末尾校验值的字节数（ASCII 为解码后的字节数）
+    pub fn check_size(self) -> usize {
+        match self {
+            Mode::Rtu => 2,
+            Mode::Ascii => 1,
+        }
+    }
+
+    /// 计算校验值（CRC 低字节在前，或 LRC）
+    pub fn check(self, adu: &[u8]) -> Vec<u8> {
+        match self {
+            Mode::Rtu => crc16_modbus(adu).to_le_bytes().to_vec(),
+            Mode::Ascii => Algorithm::Lrc.compute(adu),
+        }
+    }
+
+    /// 加上校验值，编码为线路上的帧
+    pub fn encode(self, adu: &[u8]) -> Vec<u8> {
+        match self {
+            Mode::Rtu => [adu, &self.check(adu)].concat(),
+            Mode::Ascii => ascii_encode(adu),
+        }
+    }
+}
+
+/// 主站请求的公共参数
+#[derive(clap::Args, Debug)]
+pub struct MasterArgs {
+    /// 传输模式
+    #[arg(long, value_enum, default_value_t = Mode::Rtu)]
+    pub mode: Mode,
+
+    /// 从站地址
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=247))]
+    pub slave: u8,
+
+    /// 等待从站响应的超时时间
+    #[arg(long, default_value = "1s", value_parser = parse_duration)]
+    pub timeout: Duration,
+
+    /// 超时或 CRC 错误时的重试次数（异常响应不重试）
+    #[arg(long, default_value_t = 2)]
+    pub retries: u32,
+}
+
+/// modbus read 参数
+#[derive(clap::Args, Debug)]
+pub struct ReadArgs {
+    #[command(flatten)]
+    pub master: MasterArgs,
+
+    /// 功能码：1 读线圈，2 读离散输入，3 读保持寄存器，4 读输入寄存器
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u8).range(1..=4))]
+    pub fc: u8,
+
+    /// 起始地址（从 0 开始的协议地址）
+    #[arg(long, default_value_t = 0)]
+    pub addr: u16,
+
+    /// 读取数量（寄存器最多 125 个，线圈最多 2000 个）
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..=2000))]
+    pub count: u16,
+
+    /// 寄存器值的解释方式
+    #[arg(long, value_enum, default_value_t = ValueFormat::Uint16)]
+    pub format: ValueFormat,
+
+    /// float32 低位寄存器在前（默认高位寄存器在前）
+    #[arg(long)]
+    pub word_swap: bool,
+}
+
+/// modbus write 参数
+#[derive(clap::Args, Debug)]
+pub struct WriteArgs {
+    #[command(flatten)]
+    pub master: MasterArgs,
+
+    /// 写线圈（默认写保持寄存器）
+    #[arg(long)]
+    pub coil: bool,
+
+    /// 功能码（5/6/15/16），默认单个值用 05/06、多个值用 15/16
+    #[arg(long, value_parser = ["5", "6", "15", "16"])]
+    pub fc: Option<String>,
+
+    /// 起始地址（从 0 开始的协议地址）
+    #[arg(long, default_value_t = 0)]
+    pub addr: u16,
+
+    /// 要写入的值：寄存器为整数（支持负数和 0x 十六进制）或 float32，线圈为 0/1/on/off
+    #[arg(required = true, num_args = 1.., allow_negative_numbers = true)]
+    pub values: Vec<String>,
+
+    /// 寄存器值的解释方式（uint16/int16/hex 都按整数解析）
+    #[arg(long, value_enum, default_value_t = ValueFormat::Uint16)]
+    pub format: ValueFormat,
+
+    /// float32 低位寄存器在前（默认高位寄存器在前）
+    #[arg(long)]
+    pub word_swap: bool,
+}
+
+/// 寄存器值的解释方式
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueFormat {
+    /// 无符号 16 位整数
+    Uint16,
+    /// 有符号 16 位整数
+    Int16,
+    /// 两个寄存器组成的 32 位浮点数
+    Float32,
+    /// 十六进制
+    Hex,
+}
+
+/// 最大 RTU 帧长度（地址 + PDU 253 字节 + CRC）
+pub const MAX_ADU: usize = 256;
+
+/// 3.5 个字符时间的帧间隔；波特率高于 19200 时规范规定固定为 1.75ms
+pub fn frame_gap(baud: u32) -> Duration {
+    if baud > 19200 {
+        return Duration::from_micros(1750);
+    }
+    // 每个字符按 11 位计（起始位 + 8 数据位 + 校验位/第二停止位 + 停止位）
+    Duration::from_secs_f64(3.5 * 11.0 / baud.max(1) as f64)
+}
+
+/// Modbus ASCII 帧的最大长度（':'、地址 + PDU + LRC 的十六进制文本和 CRLF）
+pub const MAX_ASCII_FRAME: usize = 1 + 2 * (MAX_ADU - 1) + 2;
+
+/// 检查帧末尾的 CRC（低字节在前）
+pub fn crc_ok(frame: &[u8]) -> bool {
+    frame.len() >= 4 && {
+        let (body, crc) = frame.split_at(frame.len() - 2);
+        crc16_modbus(body).to_le_bytes() == crc
+    }
+}
+
+/// 检查帧末尾的 LRC（Modbus ASCII 解码后的数据）
+pub fn lrc_ok(frame: &[u8]) -> bool {
+    frame.len() >= 3 && {
+        let (body, lrc) = frame.split_at(frame.len() - 1);
+        Mode::Ascii.check(body) == lrc
+    }
+}
+
+/// 编码为 Modbus ASCII 帧：加上 LRC 后转为大写十六进制文本，前加 ':'，后加 CRLF
+pub fn ascii_encode(adu: &[u8]) -> Vec<u8> {
+    let mut text = String::from(":");
+    for byte in adu.iter().chain(&Mode::Ascii.check(adu)) {
+        write!(text, "{:02X}", byte).unwrap();
+    }
+    text.push_str("\r\n");
+    text.into_bytes()
+}
+
+/// 解码 ':' 与 CRLF 之间的十六进制文本（结果含 LRC），有非十六进制字符时返回 `None`
+pub fn ascii_decode(text: &[u8]) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    text.chunks(2).map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()).collect()
+}
+
+/// 功能码名称
+pub fn function_name(code: u8) -> &'static str {
+    match code {
+        0x01 => "读线圈",
+        0x02 => "读离散输入",
+        0x03 => "读保持寄存器",
+        0x04 => "读输入寄存器",
+        0x05 => "写单个线圈",
+        0x06 => "写单个寄存器",
+        0x07 => "读异常状态",
+        0x08 => "诊断",
+        0x0B => "读通信事件计数",
+        0x0F => "写多个线圈",
+        0x10 => "写多个寄存器",
+        0x11 => "报告从站 ID",
+        0x16 => "屏蔽写寄存器",
+        0x17 => "读写多个寄存器",
+        0x2B => "读设备标识",
+        _ => "未知功能",
+    }
+}
+
+/// 异常码名称
+pub fn exception_name(code: u8) -> &'static str {
+    match code {
+        0x01 => "非法功能",
+        0x02 => "非法数据地址",
+        0x03 => "非法数据值",
+        0x04 => "从站设备故障",
+        0x05 => "确认",
+        0x06 => "从站设备忙",
+        0x08 => "存储奇偶性差错",
+        0x0A => "网关路径不可用",
+        0x0B => "网关目标设备响应失败",
+        _ => "未知异常",
+    }
+}
+
+/// 说明帧的从站地址和功能码，如 "从站 1 0x03 读保持寄存器"
+pub fn describe(frame: &[u8]) -> String {
+    match frame {
+        [] => "空帧".to_string(),
+        [slave] => format!("从站 {}", slave),
+        [slave, function, rest @ ..] if function & 0x80 != 0 => {
+            let code = function & 0x7F;
+            match rest.first() {
+                Some(&exception) => format!(
+                    "从站 {} 0x{:02X} {} 异常：{}（0x{:02X}）",
+                    slave,
+                    code,
+                    function_name(code),
+                    exception_name(exception),
+                    exception
+                ),
+                None => format!("从站 {} 0x{:02X} {} 异常", slave, code, function_name(code)),
+            }
+        }
+        [slave, function, ..] => format!("从站 {} 0x{:02X} {}", slave, function, function_name(*function)),
+    }
+}
+
+/// 执行 modbus 子命令
+pub fn run_modbus(port: &mut Box<dyn SerialPort>, opts: &ModbusArgs, rs485: Option<Rs485>) -> Result<()> {
+    match &opts.command {
+        ModbusCommand::Read(read) => run_read(port, read, rs485),
+        ModbusCommand::Write(write) => run_write(port, write, rs485),
+        ModbusCommand::Slave(slave) => slave::run_slave(port, slave, rs485),
+    }
+}
+
+fn run_read(port: &mut Box<dyn SerialPort>, opts: &ReadArgs, rs485: Option<Rs485>) -> Result<()> {
+    let registers = opts.fc >= 3;
+    if registers && opts.count > 125 {
+        bail!("一次最多读取 125 个寄存器");
+    }
+    if registers && opts.format == ValueFormat::Float32 && !opts.count.is_multiple_of(2) {
+        bail!("float32 需要偶数个寄存器");
+    }
+
+    let mut pdu = vec![opts.fc];
+    pdu.extend_from_slice(&opts.addr.to_be_bytes());
+    pdu.extend_from_slice(&opts.count.to_be_bytes());
+    let response = transact(port, rs485, &opts.master, &pdu)?;
+
+    let data = match response.get(2..) {
+        Some([byte_count, data @ ..]) if data.len() == *byte_count as usize => data,
+        _ => bail!("响应格式错误：{}", format_hex(&response)),
+    };
+    let expected = if registers { opts.count as usize * 2 } else { (opts.count as usize).div_ceil(8) };
+    if data.len() != expected {
+        bail!("响应数据长度为 {} 字节，应为 {} 字节", data.len(), expected);
+    }
+
+    events::status(&format!("{}，起始地址 {}，数量 {}", describe(&[opts.master.slave, opts.fc]), opts.addr, opts.count));
+    if !registers {
+        for i in 0..opts.count as usize {
+            let bit = data[i / 8] >> (i % 8) & 1;
+            events::status(&format!("{:>6}: {}", opts.addr as usize + i, bit));
+        }
+        return Ok(());
+    }
+
+    let words: Vec<u16> = data.chunks(2).map(|w| u16::from_be_bytes([w[0], w[1]])).collect();
+    match opts.format {
+        ValueFormat::Float32 => {
+            for (i, pair) in words.chunks(2).enumerate() {
+                let (high, low) = if opts.word_swap { (pair[1], pair[0]) } else { (pair[0], pair[1]) };
+                let value = f32::from_bits((high as u32) << 16 | low as u32);
+                events::status(&format!("{:>6}: {}", opts.addr as usize + i * 2, value));
+            }
+        }
+        format => {
+            for (i, &word) in words.iter().enumerate() {
+                let value = match format {
+                    ValueFormat::Int16 => (word as i16).to_string(),
+                    ValueFormat::Hex => format!("0x{:04X}", word),
+                    _ => word.to_string(),
+                };
+                events::status(&format!("{:>6}: {}", opts.addr as usize + i, value));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_write(port: &mut Box<dyn SerialPort>, opts: &WriteArgs, rs485: Option<Rs485>) -> Result<()> {
+    let (function, data) = if opts.coil {
+        let bits = opts.values.iter().map(|v| parse_coil(v)).collect::<Result<Vec<bool>>>()?;
+        let function = match opts.fc.as_deref() {
+            None if bits.len() == 1 => 0x05,
+            None | Some("15") => 0x0F,
+            Some("5") => 0x05,
+            Some(other) => bail!("写线圈应使用功能码 5 或 15，而不是 {}", other),
+        };
+        if function == 0x05 && bits.len() != 1 {
+            bail!("功能码 5 只能写一个线圈");
+        }
+        if bits.len() > 1968 {
+            bail!("一次最多写 1968 个线圈");
+        }
+        (function, WriteData::Coils(bits))
+    } else {
+        let mut words = Vec::new();
+        for value in &opts.values {
+            words.extend(parse_register(value, opts.format, opts.word_swap)?);
+        }
+        let function = match opts.fc.as_deref() {
+            None if words.len() == 1 => 0x06,
+            None | Some("16") => 0x10,
+            Some("6") => 0x06,
+            Some(other) => bail!("写寄存器应使用功能码 6 或 16，而不是 {}", other),
+        };
+        if function == 0x06 && words.len() != 1 {
+            bail!("功能码 6 只能写一个寄存器");
+        }
+        if words.len() > 123 {
+            bail!("一次最多写 123 个寄存器");
+        }
+        (function, WriteData::Registers(words))
+    };
+
+    let mut pdu = vec![function];
+    pdu.extend_from_slice(&opts.addr.to_be_bytes());
+    let count = match &data {
+        WriteData::Coils(bits) if function == 0x05 => {
+            pdu.extend_from_slice(if bits[0] { &[0xFF, 0x00] } else { &[0x00, 0x00] });
+            1
+        }
+        WriteData::Registers(words) if function == 0x06 => {
+            pdu.extend_from_slice(&words[0].to_be_bytes());
+            1
+        }
+        WriteData::Coils(bits) => {
+            let mut packed = vec![0u8; bits.len().div_ceil(8)];
+            for (i, _) in bits.iter().enumerate().filter(|(_, &bit)| bit) {
+                packed[i / 8] |= 1 << (i % 8);
+            }
+            pdu.extend_from_slice(&(bits.len() as u16).to_be_bytes());
+            pdu.push(packed.len() as u8);
+            pdu.extend_from_slice(&packed);
+            bits.len()
+        }
+        WriteData::Registers(words) => {
+            pdu.extend_from_slice(&(words.len() as u16).to_be_bytes());
+            pdu.push(words.len() as u8 * 2);
+            words.iter().for_each(|w| pdu.extend_from_slice(&w.to_be_bytes()));
+            words.len()
+        }
+    };
+
+    let response = transact(port, rs485, &opts.master, &pdu)?;
+    // 05/06 原样返回请求，15/16 返回起始地址和数量
+    if response.get(1..6) != pdu.get(..5) {
+        bail!("响应与请求不符：{}", format_hex(&response));
+    }
+    events::status(&format!("{}，起始地址 {}，已写入 {} 个", describe(&response), opts.addr, count));
+    Ok(())
+}
+
+/// 待写入的数据
+enum WriteData {
+    Coils(Vec<bool>),
+    Registers(Vec<u16>),
+}
+
+/// 解析线圈值（0/1/on/off）
+pub fn parse_coil(s: &str) -> Result<bool> {
+    match s.to_ascii_lowercase().as_str() {
+        "1" | "on" | "true" => Ok(true),
+        "0" | "off" | "false" => Ok(false),
+        _ => bail!("无效的线圈值 '{}'，应为 0/1/on/off", s),
+    }
+}
+
+/// 解析一个寄存器值，float32 占两个寄存器
+pub fn parse_register(s: &str, format: ValueFormat, word_swap: bool) -> Result<Vec<u16>> {
+    if format == ValueFormat::Float32 {
+        let bits = s.parse::<f32>().with_context(|| format!("无效的浮点数 '{}'", s))?.to_bits();
+        let (high, low) = ((bits >> 16) as u16, bits as u16);
+        return Ok(if word_swap { vec![low, high] } else { vec![high, low] });
+    }
+    let value = match s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .with_context(|| format!("无效的寄存器值 '{}'", s))?;
+    if !(-32768..=65535).contains(&value) {
+        bail!("寄存器值 {} 超出 16 位范围", value);
+    }
+    Ok(vec![value as u16])
+}
+
+/// 发送一个请求（PDU 前加从站地址、后加校验值）并等待响应，返回去掉校验值的响应帧；
+/// 超时或校验错误时按 `--retries` 重试，异常响应和重试用尽都返回错误
+fn transact(port: &mut Box<dyn SerialPort>, rs485: Option<Rs485>, master: &MasterArgs, pdu: &[u8]) -> Result<Vec<u8>> {
+    let slave = master.slave;
+    let request = master.mode.encode(&[&[slave], pdu].concat());
+
+    let mut attempt = 0;
+    let response = loop {
+        // 丢弃上一次请求迟到的响应
+        port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+        terminal::transmit(port, rs485, &request)?;
+
+        let deadline = Instant::now() + master.timeout;
+        let received = match master.mode {
+            Mode::Rtu => receive(port, pdu[0], deadline)?,
+            Mode::Ascii => receive_ascii(port, deadline)?,
+        };
+        let error = match received {
+            None => format!("从站 {} 无响应（超时 {:?}）", slave, master.timeout),
+            Some(frame) => {
+                events::emit(Event::Rx(&frame));
+                match decode_response(master.mode, &frame) {
+                    Err(error) => error,
+                    Ok(response) if response[0] != slave => format!("收到从站 {} 的响应，应为从站 {}", response[0], slave),
+                    Ok(response) => break response,
+                }
+            }
+        };
+        if attempt >= master.retries {
+            bail!("{}", error);
+        }
+        attempt += 1;
+        events::status(&format!("{}，第 {} 次重试", error, attempt));
+    };
+
+    if response[1] & 0x7F != pdu[0] {
+        bail!("响应功能码 0x{:02X} 与请求 0x{:02X} 不符", response[1], pdu[0]);
+    }
+    if response[1] & 0x80 != 0 {
+        bail!("{}", describe(&response));
+    }
+    Ok(response)
+}
+
+/// 校验收到的响应帧，返回去掉校验值的内容
+fn decode_response(mode: Mode, frame: &[u8]) -> std::result::Result<Vec<u8>, String> {
+    let mut data = match mode {
+        Mode::Rtu if crc_ok(frame) => frame.to_vec(),
+        Mode::Rtu => return Err(format!("响应 CRC 错误：{}", format_hex(frame))),
+        Mode::Ascii => {
+            let text = frame.strip_prefix(b":").and_then(|f| f.strip_suffix(b"\r\n")).unwrap_or(frame);
+            let data = ascii_decode(text)
+                .ok_or_else(|| format!("响应不是有效的 Modbus ASCII 帧：{}", String::from_utf8_lossy(frame).trim_end()))?;
+            if !lrc_ok(&data) {
+                return Err(format!("响应 LRC 错误：{}", format_hex(&data)));
+            }
+            data
+        }
+    };
+    data.truncate(data.len() - mode.check_size());
+    Ok(data)
+}
+
+/// 接收一个完整的响应帧（含 CRC），超时返回 `None`
+fn receive(port: &mut Box<dyn SerialPort>, function: u8, deadline: Instant) -> Result<Option<Vec<u8>>> {
+    let mut received = Vec::new();
+    let mut buffer = [0u8; MAX_ADU];
+    while Instant::now() < deadline {
+        match port.read(&mut buffer) {
+            Ok(n) => received.extend_from_slice(&buffer[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e).context("读取串口失败"),
+        }
+        if let Some(len) = response_len(&received, function) {
+            if received.len() >= len {
+                received.truncate(len);
+                return Ok(Some(received));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// 接收一个完整的 Modbus ASCII 帧（从 ':' 到 CRLF），超时返回 `None`
+fn receive_ascii(port: &mut Box<dyn SerialPort>, deadline: Instant) -> Result<Option<Vec<u8>>> {
+    let mut received = Vec::new();
+    let mut buffer = [0u8; MAX_ASCII_FRAME];
+    while Instant::now() < deadline {
+        match port.read(&mut buffer) {
+            Ok(n) => received.extend_from_slice(&buffer[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e).context("读取串口失败"),
+        }
+        // 丢弃起始符之前的数据
+        match received.iter().position(|&b| b == b':') {
+            Some(start) => {
+                received.drain(..start);
+            }
+            None => received.clear(),
+        }
+        if let Some(end) = received.windows(2).position(|w| w == b"\r\n") {
+            received.truncate(end + 2);
+            return Ok(Some(received));
+        }
+    }
+    Ok(None)
+}
+
+/// 根据已收到的响应开头推算整帧长度（含 CRC），数据不足时返回 `None`
+fn response_len(data: &[u8], function: u8) -> Option<usize> {
+    match data {
+        [_, code, ..] if code & 0x80 != 0 => Some(5),
+        [_, _, byte_count, ..] if (0x01..=0x04).contains(&function) => Some(5 + *byte_count as usize),
+        [_, _, ..] => Some(8),
+        _ => None,
+    }
+}
@@ -0,0 +1,733 @@
+//! 串口监听：读取数据并按选项格式化输出
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serialport::SerialPort;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::api::Api;
+use crate::checksum::Checksum;
+use crate::events::{self, Event};
+use crate::framing::{Deframer, Frame, FrameSpec};
+use crate::highlight::{self, HighlightRule};
+use crate::logfile::{self, LogFormat, RotatePolicy, Rotation, RxLog};
+use crate::signal::ModemStatus;
+use crate::webhook::{self, WebhookRule, Webhooks};
+use crate::xfer;
+use crate::xmodem::Link;
+use crate::zmodem::{Detector, Request};
+use crate::{format_hex, parse_duration, parse_escapes, parse_size};
+
+/// monitor 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct MonitorArgs {
+    /// 设备断开后自动重连并继续监听
+    #[arg(long)]
+    pub reconnect: bool,
+
+    /// 为收到的数据加时间戳（wall：本地时间，elapsed：自开始起，delta：距上一块）
+    #[arg(long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "wall")]
+    pub timestamp: Option<TimestampFormat>,
+
+    /// 按行组装输出：收到分隔符或空闲超时后才打印一行
+    #[arg(long)]
+    pub lines: bool,
+
+    /// 行分隔符（支持 \r \n \xNN 等转义）
+    #[arg(long, default_value = "\\n", requires = "lines")]
+    pub delimiter: String,
+
+    /// 行模式下数据空闲多久后输出未结束的行
+    #[arg(long, default_value = "500ms", value_parser = parse_duration, requires = "lines")]
+    pub idle_timeout: Duration,
+
+    /// 以 xxd/hexdump -C 格式显示（偏移量 + 每行16字节 + ASCII列）
+    #[arg(long)]
+    pub dump: bool,
+
+    /// 同时显示十六进制和解码后的文本
+    #[arg(long, conflicts_with = "dump")]
+    pub hex_ascii: bool,
+
+    /// 将不可打印字符显示为 <CR>、<LF>、<ESC>、\x07 等形式
+    #[arg(long)]
+    pub show_control: bool,
+
+    /// 高亮规则 "正则=颜色"（可重复，如 --highlight "ERROR=red"）
+    #[arg(long, value_name = "REGEX=COLOR", value_parser = highlight::parse_rule)]
+    pub highlight: Vec<HighlightRule>,
+
+    /// 从文件读取高亮规则（每行一条 "正则=颜色"）
+    #[arg(long, value_name = "PATH")]
+    pub highlight_file: Option<PathBuf>,
+
+    /// 只输出匹配该正则的内容（可重复，满足任一即可；建议配合 --lines 按行过滤）
+    #[arg(long, value_name = "REGEX")]
+    pub grep: Vec<Regex>,
+
+    /// 不输出匹配该正则的内容（可重复）
+    #[arg(long = "grep-v", value_name = "REGEX")]
+    pub grep_v: Vec<Regex>,
+
+    /// 收到的某一行匹配正则时，把该行和时间戳以 JSON POST 到 URL（可重复，如 --webhook "ALARM|FATAL=http://incident.local/hook"）；
+    /// 只支持 http://，https 地址需经本机的反向代理（如 nginx、stunnel）转发
+    #[arg(long, value_name = "REGEX=URL", value_parser = webhook::parse_rule)]
+    pub webhook: Vec<WebhookRule>,
+
+    /// 从文件读取 webhook 规则（每行一条 "正则=URL"）
+    #[arg(long, value_name = "PATH")]
+    pub webhook_file: Option<PathBuf>,
+
+    /// webhook 请求附加的请求头（可重复，如 --webhook-header "Authorization: Bearer xxx"）
+    #[arg(long, value_name = "HEADER", value_parser = webhook::parse_header)]
+    pub webhook_header: Vec<String>,
+
+    /// 同一规则两次触发的最小间隔，期间的匹配只计数，在下一次请求的 suppressed 字段中报告
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub webhook_cooldown: Option<Duration>,
+
+    /// 启动本地 REST 接口，供其他程序发送数据、查询收到的数据、修改串口参数和操作控制线
+    /// （如 --api 127.0.0.1:8081；只写 :8081 时监听所有网卡，GET / 查看可用的接口）
+    #[arg(long, value_name = "ADDR")]
+    pub api: Option<String>,
+
+    /// REST 接口保留的最近收到的数据量
+    #[arg(long, value_name = "BYTES", value_parser = parse_size, default_value = "64K", requires = "api")]
+    pub api_history: u64,
+
+    /// 同时把收到的数据写入日志文件
+    #[arg(long, value_name = "FILE")]
+    pub log: Option<PathBuf>,
+
+    /// 日志格式
+    #[arg(long, value_enum, default_value = "text", requires = "log")]
+    pub log_format: LogFormat,
+
+    /// 追加到已有日志文件（默认清空重写）
+    #[arg(long, requires = "log")]
+    pub log_append: bool,
+
+    /// 日志轮转：size=<大小>（如 size=50M）或 daily
+    #[arg(long, value_name = "POLICY", value_parser = logfile::parse_rotate, requires = "log")]
+    pub log_rotate: Option<RotatePolicy>,
+
+    /// 轮转时保留的归档数量
+    #[arg(long, default_value = "5", value_name = "N", requires = "log_rotate")]
+    pub log_keep: usize,
+
+    /// gzip 压缩轮转后的归档
+    #[arg(long, requires = "log_rotate")]
+    pub log_gzip: bool,
+
+    /// 监听指定时长后退出（如 30s、5m）
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub exit_after: Option<Duration>,
+
+    /// 累计收到指定字节数后退出
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub exit_bytes: Option<u64>,
+
+    /// 收到匹配该正则的数据后退出；因其他条件结束而未匹配时以非零状态退出
+    #[arg(long, value_name = "REGEX")]
+    pub exit_on: Option<Regex>,
+
+    /// 同时监视 CTS/DSR/CD/RI 状态线，电平变化时输出带时间戳的事件
+    #[arg(long)]
+    pub watch_signals: bool,
+
+    /// 检测到对方发起 ZMODEM 发送（执行了 sz）时自动接收，保存到指定目录（默认为当前目录）
+    #[arg(long, value_name = "DIR", num_args = 0..=1, require_equals = true, default_missing_value = ".")]
+    pub zmodem: Option<PathBuf>,
+}
+
+/// 时间戳格式
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum TimestampFormat {
+    /// 本地时间（精确到毫秒）
+    Wall,
+    /// 距监听开始的时间
+    Elapsed,
+    /// 距上一块数据的时间
+    Delta,
+}
+
+/// 按选定格式生成时间戳
+struct Timestamper {
+    format: TimestampFormat,
+    start: Instant,
+    last: Option<Instant>,
+}
+
+impl Timestamper {
+    fn new(format: TimestampFormat) -> Self {
+        Timestamper { format, start: Instant::now(), last: None }
+    }
+
+    fn stamp(&mut self, now: Instant) -> String {
+        let text = match self.format {
+            TimestampFormat::Wall => jiff::Zoned::now().strftime("%H:%M:%S%.3f").to_string(),
+            TimestampFormat::Elapsed => format!("+{:.3}s", (now - self.start).as_secs_f64()),
+            TimestampFormat::Delta => {
+                let since = self.last.map_or(now - self.start, |last| now - last);
+                format!("Δ{:.3}s", since.as_secs_f64())
+            }
+        };
+        self.last = Some(now);
+        text
+    }
+}
+
+/// 数据显示方式
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum View {
+    Text,
+    Hex,
+    Dump,
+    HexAscii,
+}
+
+/// 负责把一段收到的数据格式化并打印
+struct Printer {
+    view: View,
+    show_control: bool,
+    highlights: Vec<HighlightRule>,
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    timestamper: Option<Timestamper>,
+    log: Option<RxLog>,
+    /// 已输出的总字节数（dump 模式的偏移量）
+    offset: u64,
+    /// 按帧显示时已收到的帧数
+    frame_count: Option<u64>,
+}
+
+impl Printer {
+    fn print(&mut self, data: &[u8], at: Instant) -> Result<()> {
+        self.print_annotated(data, None, at)
+    }
+
+    /// 输出一帧，后面标注校验结果（未校验时为解码失败的原因或帧内容说明）
+    fn print_frame(&mut self, frame: &Frame, check: Option<Note>, at: Instant) -> Result<()> {
+        let error = frame.error.as_deref().map(|text| Note { ok: false, text });
+        let info = frame.info.as_deref().map(|text| Note { ok: true, text });
+        self.print_annotated(&frame.data, check.or(error).or(info), at)
+    }
+
+    fn print_annotated(&mut self, data: &[u8], note: Option<Note>, at: Instant) -> Result<()> {
+        // 日志记录全部数据，不受过滤影响
+        if let Some(log) = self.log.as_mut() {
+            log.write_record(data)?;
+        }
+
+        // 将字节转为字符串（宽松UTF-8处理）
+        let output = match self.view {
+            View::Text if self.show_control => visualize_control(data),
+            View::Text => String::from_utf8_lossy(data).into_owned(),
+            View::Hex => format_hex(data),
+            View::Dump => format_dump(data, self.offset),
+            View::HexAscii => {
+                let text = if self.show_control { visualize_control(data) } else { printable_text(data) };
+                format!("{}  |  {}", format_hex(data), text)
+            }
+        };
+        self.offset += data.len() as u64;
+        let frame = self.frame_count.as_mut().map(|n| {
+            *n += 1;
+            *n
+        });
+
+        // 按显示内容过滤
+        if !self.include.is_empty() && !self.include.iter().any(|re| re.is_match(&output)) {
+            return Ok(());
+        }
+        if self.exclude.iter().any(|re| re.is_match(&output)) {
+            return Ok(());
+        }
+        if events::jsonl() {
+            events::emit(Event::Rx(data));
+            if let Some(note) = note.filter(|n| !n.ok) {
+                events::emit(Event::Error(note.text));
+            }
+            return Ok(());
+        }
+        let mut output = highlight::apply(&self.highlights, &output);
+        if let Some(note) = note {
+            let color = if note.ok { 32 } else { 31 };
+            output = format!("{}  \x1b[{}m[{}]\x1b[0m", output, color, note.text);
+        }
+
+        let mut label = Vec::new();
+        if let Some(ts) = self.timestamper.as_mut() {
+            label.push(format!("[{}]", ts.stamp(at)));
+        }
+        if let Some(n) = frame {
+            label.push(format!("#{}", n));
+        }
+        let label = label.join(" ");
+
+        // anstream 会在不支持颜色的终端或重定向时自动去掉颜色
+        if label.is_empty() {
+            anstream::println!("{}", output); // 实时输出
+        } else if self.view == View::Dump {
+            // 多行转储时时间戳和帧序号单独占一行，保持列对齐
+            anstream::println!("{}\n{}", label, output);
+        } else {
+            anstream::println!("{} {}", label, output);
+        }
+        Ok(())
+    }
+}
+
+/// 附在一帧后面的标注
+#[derive(Clone, Copy)]
+struct Note<'a> {
+    ok: bool,
+    text: &'a str,
+}
+
+/// 帧校验和检查及错误计数
+struct FrameCheck {
+    checksum: Checksum,
+    frames: u64,
+    bad: u64,
+}
+
+impl FrameCheck {
+    /// 校验一帧，返回显示用的标注
+    fn check(&mut self, frame: &Frame) -> (bool, String) {
+        self.frames += 1;
+        let result = match &frame.error {
+            Some(error) => Err(error.clone()),
+            None => self.checksum.verify(&frame.data),
+        };
+        match result {
+            Ok(()) => (true, "OK".to_string()),
+            Err(e) => {
+                self.bad += 1;
+                (false, format!("BAD {}，累计错误 {}/{}", e, self.bad, self.frames))
+            }
+        }
+    }
+}
+
+/// 输出一帧，启用 --checksum 时先校验
+fn print_checked(printer: &mut Printer, check: Option<&mut FrameCheck>, frame: &Frame, at: Instant) -> Result<()> {
+    let check = check.map(|check| check.check(frame));
+    let note = check.as_ref().map(|(ok, text)| Note { ok: *ok, text });
+    printer.print_frame(frame, note, at)
+}
+
+/// 行组装：缓存数据直到遇到分隔符或空闲超时
+struct LineAssembler {
+    delimiter: Vec<u8>,
+    idle_timeout: Duration,
+    pending: Vec<u8>,
+    /// 当前行第一个字节到达的时间
+    started: Option<Instant>,
+    last_rx: Instant,
+}
+
+impl LineAssembler {
+    fn new(delimiter: Vec<u8>, idle_timeout: Duration) -> Self {
+        LineAssembler { delimiter, idle_timeout, pending: Vec::new(), started: None, last_rx: Instant::now() }
+    }
+
+    /// 追加数据，输出所有已完整的行（不含分隔符）
+    fn push(&mut self, data: &[u8], now: Instant, printer: &mut Printer) -> Result<()> {
+        self.last_rx = now;
+        self.started.get_or_insert(now);
+        self.pending.extend_from_slice(data);
+
+        while let Some(pos) = find_subslice(&self.pending, &self.delimiter) {
+            let mut line: Vec<u8> = self.pending.drain(..pos + self.delimiter.len()).collect();
+            line.truncate(pos);
+            // 以 \n 分行时顺便去掉 Windows 风格的 \r
+            if self.delimiter == b"\n" && line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            printer.print(&line, self.started.unwrap_or(now))?;
+            self.started = if self.pending.is_empty() { None } else { Some(now) };
+        }
+        Ok(())
+    }
+
+    /// 空闲超时后输出未结束的行
+    fn poll_idle(&mut self, now: Instant, printer: &mut Printer) -> Result<()> {
+        if now - self.last_rx >= self.idle_timeout {
+            self.flush(now, printer)?;
+        }
+        Ok(())
+    }
+
+    /// 立即输出未结束的行
+    fn flush(&mut self, now: Instant, printer: &mut Printer) -> Result<()> {
+        if !self.pending.is_empty() {
+            printer.print(&self.pending, self.started.unwrap_or(now))?;
+            self.pending.clear();
+            self.started = None;
+        }
+        Ok(())
+    }
+}
+
+/// 匹配 --exit-on 时最多保留的最近接收文本（字节），使跨多次读取的内容也能匹配
+const EXIT_WINDOW: usize = 4096;
+
+/// 监听的退出条件
+struct ExitConditions {
+    deadline: Option<Instant>,
+    byte_limit: Option<u64>,
+    pattern: Option<Regex>,
+    received: u64,
+    /// 最近收到的文本
+    window: String,
+    /// 是否已匹配到 --exit-on
+    seen: bool,
+}
+
+impl ExitConditions {
+    fn new(opts: &MonitorArgs) -> Self {
+        ExitConditions {
+            deadline: opts.exit_after.map(|d| Instant::now() + d),
+            byte_limit: opts.exit_bytes,
+            pattern: opts.exit_on.clone(),
+            received: 0,
+            window: String::new(),
+            seen: false,
+        }
+    }
+
+    /// 记录收到的数据，返回是否应当退出
+    fn on_data(&mut self, data: &[u8]) -> bool {
+        self.received += data.len() as u64;
+        if let Some(pattern) = &self.pattern {
+            self.window.push_str(&String::from_utf8_lossy(data));
+            if pattern.is_match(&self.window) {
+                self.seen = true;
+                return true;
+            }
+            if self.window.len() > EXIT_WINDOW {
+                let mut cut = self.window.len() - EXIT_WINDOW;
+                while !self.window.is_char_boundary(cut) {
+                    cut += 1;
+                }
+                self.window.drain(..cut);
+            }
+        }
+        self.byte_limit.is_some_and(|limit| self.received >= limit) || self.expired()
+    }
+
+    /// 按 --exit-bytes 截断本次读取的长度，使输出恰好为指定字节数
+    fn clamp(&self, n: usize) -> usize {
+        match self.byte_limit {
+            Some(limit) => n.min((limit - self.received) as usize),
+            None => n,
+        }
+    }
+
+    fn expired(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// xxd 风格的多行转储（如 "00000000  41 42 ...  |AB|"）
+fn format_dump(data: &[u8], offset: u64) -> String {
+    let mut out = String::new();
+    for (i, row) in data.chunks(16).enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        out.push_str(&format!("{:08x}  ", offset + (i * 16) as u64));
+        for col in 0..16 {
+            match row.get(col) {
+                Some(b) => out.push_str(&format!("{:02x} ", b)),
+                None => out.push_str("   "),
+            }
+            if col == 7 {
+                out.push(' ');
+            }
+        }
+        out.push_str(" |");
+        out.extend(row.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }));
+        out.push('|');
+    }
+    out
+}
+
+/// 宽松UTF-8解码，并把控制字符替换为 '.'，保证输出不换行
+fn printable_text(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .chars()
+        .map(|c| if c.is_control() { '.' } else { c })
+        .collect()
+}
+
+/// 宽松UTF-8解码，并把控制字符显示为可见的标记（如 "\r\n" -> "<CR><LF>"）
+fn visualize_control(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len());
+    for c in String::from_utf8_lossy(data).chars() {
+        match c {
+            '\r' => out.push_str("<CR>"),
+            '\n' => out.push_str("<LF>"),
+            '\t' => out.push_str("<TAB>"),
+            '\x1b' => out.push_str("<ESC>"),
+            '\0' => out.push_str("<NUL>"),
+            c if c.is_ascii_control() => out.push_str(&format!("\\x{:02X}", c as u32)),
+            c if c.is_control() => out.push_str(&format!("<U+{:04X}>", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// 状态线的最短轮询间隔
+const SIGNAL_POLL: Duration = Duration::from_millis(10);
+
+/// 监视输入状态线的电平变化
+struct SignalWatch {
+    /// 上次读取的状态，首次读取前为 `None`
+    last: Option<ModemStatus>,
+    last_poll: Instant,
+}
+
+impl SignalWatch {
+    fn poll(&mut self, port: &mut Box<dyn SerialPort>) {
+        if self.last.is_some() && self.last_poll.elapsed() < SIGNAL_POLL {
+            return;
+        }
+        self.last_poll = Instant::now();
+        let now = ModemStatus::read(port);
+        let stamp = jiff::Zoned::now().strftime("%Y-%m-%d %H:%M:%S%.3f");
+        let level = |l: bool| if l { 1 } else { 0 };
+
+        let Some(last) = self.last.replace(now) else {
+            let initial: Vec<String> = now
+                .lines()
+                .iter()
+                .filter_map(|&(name, l)| l.map(|l| format!("{}={}", name, level(l))))
+                .collect();
+            if initial.is_empty() {
+                events::status("[该串口不支持读取状态线]");
+            } else {
+                events::status(&format!("[{}] 状态线初始状态 {}", stamp, initial.join(" ")));
+            }
+            return;
+        };
+        for ((name, old), (_, new)) in last.lines().into_iter().zip(now.lines()) {
+            let (Some(old), Some(new)) = (old, new) else { continue };
+            if old == new {
+                continue;
+            }
+            if events::jsonl() {
+                events::emit(Event::Signal { line: name, level: new });
+            } else {
+                println!("[{}] {} {} -> {}", stamp, name, level(old), level(new));
+            }
+        }
+    }
+}
+
+/// 一次监听会话；重连后继续使用同一会话，保持偏移量、时间戳和日志文件
+pub struct Monitor {
+    buffer: Vec<u8>,
+    printer: Printer,
+    lines: Option<LineAssembler>,
+    frames: Option<Deframer>,
+    check: Option<FrameCheck>,
+    exit: ExitConditions,
+    signals: Option<SignalWatch>,
+    webhooks: Option<Webhooks>,
+    api: Option<Api>,
+    /// 自动接收 ZMODEM 文件的目录和起始帧头检测
+    zmodem: Option<(PathBuf, Detector)>,
+}
+
+impl Monitor {
+    pub fn new(
+        opts: &MonitorArgs,
+        port_name: &str,
+        hex_mode: bool,
+        frame: Option<&FrameSpec>,
+        checksum: Option<&Checksum>,
+        rx_buffer: usize,
+    ) -> Result<Self> {
+        if opts.lines && frame.is_some() {
+            anyhow::bail!("--lines 不能与 --frame 同时使用");
+        }
+        if checksum.is_some() && frame.is_none() {
+            anyhow::bail!("监听时 --checksum 需要配合 --frame 使用，以确定每帧的范围");
+        }
+        let view = if opts.dump {
+            View::Dump
+        } else if opts.hex_ascii {
+            View::HexAscii
+        } else if hex_mode || frame.is_some_and(FrameSpec::is_binary) {
+            View::Hex
+        } else {
+            View::Text
+        };
+        let mut highlights = opts.highlight.clone();
+        if let Some(path) = &opts.highlight_file {
+            highlights.extend(highlight::load_rules(path)?);
+        }
+        let log = match &opts.log {
+            Some(path) => {
+                let rotation = opts.log_rotate.map(|policy| Rotation {
+                    policy,
+                    keep: opts.log_keep,
+                    gzip: opts.log_gzip,
+                });
+                Some(RxLog::open(path, opts.log_format, opts.log_append, rotation)?)
+            }
+            None => None,
+        };
+        let printer = Printer {
+            view,
+            show_control: opts.show_control,
+            highlights,
+            include: opts.grep.clone(),
+            exclude: opts.grep_v.clone(),
+            timestamper: opts.timestamp.map(Timestamper::new),
+            log,
+            offset: 0,
+            frame_count: frame.map(|_| 0),
+        };
+        let lines = if opts.lines {
+            let delimiter = parse_escapes(&opts.delimiter).context("行分隔符解析失败")?;
+            if delimiter.is_empty() {
+                anyhow::bail!("行分隔符不能为空");
+            }
+            Some(LineAssembler::new(delimiter, opts.idle_timeout))
+        } else {
+            None
+        };
+
+        let mut webhook_rules = opts.webhook.clone();
+        if let Some(path) = &opts.webhook_file {
+            webhook_rules.extend(webhook::load_rules(path)?);
+        }
+        let webhooks = match webhook_rules.is_empty() {
+            true => None,
+            false => Some(Webhooks::new(webhook_rules, opts.webhook_header.clone(), opts.webhook_cooldown, port_name)),
+        };
+
+        let api = opts.api.as_deref().map(|listen| Api::start(listen, opts.api_history as usize)).transpose()?;
+
+        let signals = opts.watch_signals.then(|| SignalWatch { last: None, last_poll: Instant::now() });
+        if let Some(dir) = opts.zmodem.as_deref().filter(|dir| !dir.is_dir()) {
+            anyhow::bail!("--zmodem 的保存目录 {} 不存在", dir.display());
+        }
+        Ok(Monitor {
+            buffer: vec![0u8; rx_buffer],
+            printer,
+            lines,
+            frames: frame.cloned().map(Deframer::new),
+            check: checksum.map(|&checksum| FrameCheck { checksum, frames: 0, bad: 0 }),
+            exit: ExitConditions::new(opts),
+            signals,
+            webhooks,
+            api,
+            zmodem: opts.zmodem.clone().map(|dir| (dir, Detector::default())),
+        })
+    }
+
+    /// 持续监听串口数据，直到满足退出条件（返回 `Ok`）或读取出错
+    pub fn run(&mut self, port: &mut Box<dyn SerialPort>) -> Result<()> {
+        if let (Some(deframer), Ok(baud)) = (self.frames.as_mut(), port.baud_rate()) {
+            deframer.set_baud(baud);
+        }
+        if let Some(api) = &self.api {
+            api.attach(port.as_ref());
+        }
+        while !self.exit.expired() {
+            if let Some(signals) = self.signals.as_mut() {
+                signals.poll(port);
+            }
+            match port.read(&mut self.buffer) {
+                Ok(0) => continue,
+                Ok(n) => {
+                    let now = Instant::now();
+                    let data = &self.buffer[..self.exit.clamp(n)];
+                    if let Some(log) = self.printer.log.as_mut() {
+                        log.write_raw(data)?;
+                    }
+                    if let Some(webhooks) = self.webhooks.as_mut() {
+                        webhooks.push(data, now);
+                    }
+                    if let Some(api) = &self.api {
+                        api.record(data);
+                    }
+                    if let Some(deframer) = self.frames.as_mut() {
+                        for frame in deframer.push(data, now) {
+                            print_checked(&mut self.printer, self.check.as_mut(), &frame, now)?;
+                        }
+                    } else if let Some(assembler) = self.lines.as_mut() {
+                        assembler.push(data, now, &mut self.printer)?;
+                    } else {
+                        self.printer.print(data, now)?;
+                    }
+                    let request = self.zmodem.as_mut().and_then(|(_, detector)| detector.push(data));
+                    if self.exit.on_data(data) {
+                        break;
+                    }
+                    match (request, &self.zmodem) {
+                        (Some(Request::Receive), Some((dir, _))) => {
+                            events::status(&format!("[检测到 ZMODEM 发送（对方执行了 sz），开始接收到 {}]", dir.display()));
+                            match xfer::receive_zmodem(&mut Link::new(port, None), dir, Duration::from_secs(10), false) {
+                                Ok(()) => events::status("[ZMODEM 传输结束，继续监听]"),
+                                Err(e) => events::status(&format!("[ZMODEM 接收失败：{:#}，继续监听]", e)),
+                            }
+                        }
+                        (Some(Request::Send), _) => {
+                            events::status("[对方在等待 ZMODEM 上传（执行了 rz），请用 xfer --proto zmodem send <文件> 发送]");
+                        }
+                        _ => {}
+                    }
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
+                    if let Some(webhooks) = self.webhooks.as_mut() {
+                        webhooks.poll_idle(Instant::now());
+                    }
+                    if let Some(assembler) = self.lines.as_mut() {
+                        assembler.poll_idle(Instant::now(), &mut self.printer)?;
+                    }
+                    if let Some(deframer) = self.frames.as_mut() {
+                        let now = Instant::now();
+                        for frame in deframer.poll_idle(now) {
+                            print_checked(&mut self.printer, self.check.as_mut(), &frame, now)?;
+                        }
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if let Some(assembler) = self.lines.as_mut() {
+            assembler.flush(Instant::now(), &mut self.printer)?;
+        }
+        if let Some(check) = &self.check {
+            events::status(&format!("[共 {} 帧，校验错误 {} 帧]", check.frames, check.bad));
+        }
+        if let Some(deframer) = self.frames.as_mut() {
+            let pending = deframer.take_pending();
+            if !pending.is_empty() {
+                events::status(&format!("[监听结束，{} 字节未组成完整的帧: {}]", pending.len(), format_hex(&pending)));
+            }
+        }
+        Ok(())
+    }
+
+    /// 监听结束后检查 --exit-on：未匹配到时返回错误，使进程以非零状态退出
+    pub fn finish(&self) -> Result<()> {
+        match &self.exit.pattern {
+            Some(pattern) if !self.exit.seen => anyhow::bail!("监听结束，未收到匹配 '{}' 的数据", pattern),
+            _ => Ok(()),
+        }
+    }
+}
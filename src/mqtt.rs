@@ -0,0 +1,415 @@
+//! MQTT 3.1.1 客户端：连接、发布、订阅和心跳报文的编解码，供网络桥接把串口接入 MQTT 服务器
+//!
+//! 只支持明文 TCP 连接和用户名/密码认证，不支持 TLS（mqtts://）和客户端证书。
+
+use anyhow::{bail, Context, Result};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+/// 报文类型（固定报头的高 4 位）
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+/// 等待 CONNACK、SUBACK 的时长
+const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+/// 单个报文的最大长度，超过时断开
+const MAX_PACKET: usize = 1024 * 1024;
+
+/// 服务器地址：mqtt://[用户名:密码@]主机[:端口]，也可以只写 主机[:端口]（不支持 mqtts://）
+#[derive(Clone, Debug)]
+pub struct Broker {
+    /// 主机:端口
+    pub address: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// 解析服务器地址，端口默认为 1883
+pub fn parse_broker(s: &str) -> std::result::Result<Broker, String> {
+    let rest = match s.split_once("://") {
+        Some(("mqtt" | "tcp", rest)) => rest,
+        Some(("mqtts" | "ssl" | "tls", _)) => {
+            return Err("此版本不支持 TLS（mqtts://），可以用 stunnel 等在本机建立 TLS 隧道后连接 mqtt://127.0.0.1".to_string())
+        }
+        Some((scheme, _)) => return Err(format!("不支持的地址类型 '{}://'，应为 mqtt://", scheme)),
+        None => s,
+    };
+    let rest = rest.trim_end_matches('/');
+    let (credentials, host) = match rest.rsplit_once('@') {
+        Some((credentials, host)) => (Some(credentials), host),
+        None => (None, rest),
+    };
+    let (username, password) = match credentials.map(|credentials| credentials.split_once(':')) {
+        Some(Some((username, password))) => (Some(username.to_string()), Some(password.to_string())),
+        Some(None) => (credentials.map(str::to_string), None),
+        None => (None, None),
+    };
+    if host.is_empty() {
+        return Err(format!("地址 '{}' 中没有主机名", s));
+    }
+    // 没有端口时补上默认端口（IPv6 地址写成 [::1] 或 [::1]:1883）
+    let has_port = match host.rsplit_once(']') {
+        Some((_, after)) => after.starts_with(':'),
+        None => host.contains(':'),
+    };
+    let address = if has_port { host.to_string() } else { format!("{}:1883", host) };
+    Ok(Broker { address, username, password })
+}
+
+/// 主题是否匹配订阅的主题过滤器（+ 匹配一级，# 匹配其余所有级）
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut levels = topic.split('/');
+    for part in filter.split('/') {
+        match (part, levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (part, Some(level)) if part == level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+/// 连接参数
+pub struct Connect<'a> {
+    pub client_id: &'a str,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+    pub keep_alive: Duration,
+}
+
+/// 服务器发来的报文
+pub enum Packet {
+    ConnAck { code: u8 },
+    Publish { payload: Vec<u8>, id: Option<u16> },
+    PubAck,
+    SubAck { id: u16, codes: Vec<u8> },
+    PingResp,
+    /// 客户端用不到的其他报文
+    Other,
+}
+
+fn packet(kind: u8, flags: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![kind << 4 | flags];
+    // 剩余长度：每字节 7 位，最高位表示后面还有
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        match len {
+            0 => {
+                out.push(byte);
+                break;
+            }
+            _ => out.push(byte | 0x80),
+        }
+    }
+    out.extend_from_slice(body);
+    out
+}
+
+fn string(out: &mut Vec<u8>, value: &[u8]) {
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value);
+}
+
+pub fn connect(options: &Connect) -> Vec<u8> {
+    let mut body = Vec::new();
+    string(&mut body, b"MQTT");
+    body.push(4);
+    // 清除会话；有用户名、密码时置对应标志位
+    let mut flags = 0x02;
+    if options.username.is_some() {
+        flags |= 0x80;
+    }
+    if options.password.is_some() {
+        flags |= 0x40;
+    }
+    body.push(flags);
+    body.extend_from_slice(&(options.keep_alive.as_secs().min(u16::MAX as u64) as u16).to_be_bytes());
+    string(&mut body, options.client_id.as_bytes());
+    if let Some(username) = options.username {
+        string(&mut body, username.as_bytes());
+    }
+    if let Some(password) = options.password {
+        string(&mut body, password.as_bytes());
+    }
+    packet(CONNECT, 0, &body)
+}
+
+/// 发布报文，QoS 1 时需要报文标识符
+pub fn publish(topic: &str, payload: &[u8], id: Option<u16>, retain: bool) -> Vec<u8> {
+    let mut body = Vec::with_capacity(topic.len() + payload.len() + 4);
+    string(&mut body, topic.as_bytes());
+    if let Some(id) = id {
+        body.extend_from_slice(&id.to_be_bytes());
+    }
+    body.extend_from_slice(payload);
+    let qos = if id.is_some() { 1 } else { 0 };
+    packet(PUBLISH, qos << 1 | retain as u8, &body)
+}
+
+pub fn subscribe(id: u16, topic: &str, qos: u8) -> Vec<u8> {
+    let mut body = id.to_be_bytes().to_vec();
+    string(&mut body, topic.as_bytes());
+    body.push(qos);
+    packet(SUBSCRIBE, 0x02, &body)
+}
+
+pub fn puback(id: u16) -> Vec<u8> {
+    packet(PUBACK, 0, &id.to_be_bytes())
+}
+
+pub fn pingreq() -> Vec<u8> {
+    packet(PINGREQ, 0, &[])
+}
+
+pub fn disconnect() -> Vec<u8> {
+    packet(DISCONNECT, 0, &[])
+}
+
+/// CONNACK 返回码的含义
+fn refusal(code: u8) -> String {
+    match code {
+        1 => "服务器不支持 MQTT 3.1.1".to_string(),
+        2 => "客户端标识符被拒绝（可用 --client-id 换一个）".to_string(),
+        3 => "服务暂不可用".to_string(),
+        4 => "用户名或密码错误".to_string(),
+        5 => "没有权限".to_string(),
+        code => format!("返回码 {}", code),
+    }
+}
+
+/// 从字节流中解出完整的报文
+#[derive(Default)]
+pub struct Decoder {
+    pending: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder::default()
+    }
+
+    pub fn decode(&mut self, data: &[u8]) -> Result<Vec<Packet>> {
+        self.pending.extend_from_slice(data);
+        let mut packets = Vec::new();
+        loop {
+            // 剩余长度最多 4 字节
+            let mut len = 0usize;
+            let mut at = 1;
+            let complete = loop {
+                let Some(&byte) = self.pending.get(at) else {
+                    break false;
+                };
+                len |= ((byte & 0x7F) as usize) << (7 * (at - 1));
+                at += 1;
+                if byte & 0x80 == 0 {
+                    break true;
+                }
+                if at > 4 {
+                    bail!("报文长度字段无效");
+                }
+            };
+            if len > MAX_PACKET {
+                bail!("报文超过 {} 字节", MAX_PACKET);
+            }
+            if !complete || self.pending.len() < at + len {
+                return Ok(packets);
+            }
+            let header = self.pending[0];
+            let body: Vec<u8> = self.pending.drain(..at + len).skip(at).collect();
+            packets.push(parse(header, &body).context("报文格式有误")?);
+        }
+    }
+}
+
+fn parse(header: u8, body: &[u8]) -> Option<Packet> {
+    let u16_at = |at: usize| Some(u16::from_be_bytes([*body.get(at)?, *body.get(at + 1)?]));
+    Some(match header >> 4 {
+        CONNACK => Packet::ConnAck { code: *body.get(1)? },
+        PUBLISH => {
+            let qos = header >> 1 & 0x03;
+            let len = u16_at(0)? as usize;
+            let (id, start) = match qos {
+                0 => (None, 2 + len),
+                _ => (Some(u16_at(2 + len)?), 4 + len),
+            };
+            Packet::Publish { payload: body.get(start..)?.to_vec(), id }
+        }
+        PUBACK => Packet::PubAck,
+        SUBACK => Packet::SubAck { id: u16_at(0)?, codes: body.get(2..)?.to_vec() },
+        PINGRESP => Packet::PingResp,
+        _ => Packet::Other,
+    })
+}
+
+/// 连接失败的原因
+pub enum Refused {
+    /// 服务器拒绝了连接参数（用户名密码错误等），重连也不会成功
+    Fatal(anyhow::Error),
+    /// 网络错误或服务器暂不可用
+    Retry(anyhow::Error),
+}
+
+/// 在已建立的 TCP 连接上发送 CONNECT，订阅 `subscribe`（主题和 QoS），等待服务器确认；
+/// 确认前收到的其他报文随后交给调用者
+pub fn handshake(
+    stream: &mut TcpStream,
+    decoder: &mut Decoder,
+    options: &Connect,
+    subscribe_to: Option<(&str, u8)>,
+) -> std::result::Result<Vec<Packet>, Refused> {
+    let retry = |e: io::Error| Refused::Retry(anyhow::Error::from(e).context("与服务器通信失败"));
+    stream.write_all(&connect(options)).map_err(retry)?;
+    let mut connected = false;
+    if let Some((topic, qos)) = subscribe_to {
+        stream.write_all(&subscribe(1, topic, qos)).map_err(retry)?;
+    }
+    let mut subscribed = subscribe_to.is_none();
+    let mut early = Vec::new();
+    let deadline = Instant::now() + ACK_TIMEOUT;
+    let mut buffer = [0u8; 4096];
+    while !connected || !subscribed {
+        if Instant::now() >= deadline {
+            return Err(Refused::Retry(anyhow::anyhow!("等待服务器确认超时")));
+        }
+        let n = match stream.read(&mut buffer) {
+            Ok(0) => return Err(Refused::Retry(anyhow::anyhow!("服务器关闭了连接"))),
+            Ok(n) => n,
+            Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(retry(e)),
+        };
+        for packet in decoder.decode(&buffer[..n]).map_err(Refused::Retry)? {
+            match packet {
+                Packet::ConnAck { code: 0 } => connected = true,
+                Packet::ConnAck { code: 3 } => return Err(Refused::Retry(anyhow::anyhow!("服务器拒绝连接：{}", refusal(3)))),
+                Packet::ConnAck { code } => return Err(Refused::Fatal(anyhow::anyhow!("服务器拒绝连接：{}", refusal(code)))),
+                Packet::SubAck { id: 1, codes } => {
+                    if codes.contains(&0x80) {
+                        let topic = subscribe_to.map(|(topic, _)| topic).unwrap_or_default();
+                        return Err(Refused::Fatal(anyhow::anyhow!("服务器拒绝订阅 {}（主题无效或没有权限）", topic)));
+                    }
+                    subscribed = true;
+                }
+                packet => early.push(packet),
+            }
+        }
+    }
+    Ok(early)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brokers() {
+        // 密码中可以有 @，以最后一个 @ 分隔
+        let broker = parse_broker("mqtt://user:p@ss@broker.local").unwrap();
+        assert_eq!((broker.address.as_str(), broker.username.as_deref(), broker.password.as_deref()), ("broker.local:1883", Some("user"), Some("p@ss")));
+        let broker = parse_broker("tcp://u@[::1]:1884/").unwrap();
+        assert_eq!((broker.address.as_str(), broker.username.as_deref(), broker.password), ("[::1]:1884", Some("u"), None));
+        assert_eq!(parse_broker("[::1]").unwrap().address, "[::1]:1883");
+        assert_eq!(parse_broker("10.0.0.2:1883").unwrap().address, "10.0.0.2:1883");
+        for s in ["mqtts://broker:8883", "ws://broker", "mqtt://user@"] {
+            assert!(parse_broker(s).is_err(), "{} 应解析失败", s);
+        }
+    }
+
+    #[test]
+    fn topic_filters() {
+        assert!(topic_matches("a/+/c", "a/b/c"));
+        assert!(topic_matches("a/#", "a/b/c"));
+        assert!(topic_matches("a/#", "a"));
+        assert!(topic_matches("#", "a/b"));
+        assert!(!topic_matches("a/+", "a/b/c"));
+        assert!(!topic_matches("a/b/c", "a/b"));
+        assert!(!topic_matches("a/b", "a/B"));
+    }
+
+    #[test]
+    fn encode_packets() {
+        let options = Connect { client_id: "c", username: None, password: None, keep_alive: Duration::from_secs(60) };
+        assert_eq!(connect(&options), b"\x10\x0D\x00\x04MQTT\x04\x02\x00\x3C\x00\x01c");
+        // 有用户名和密码时置 0x80、0x40 标志，依次跟在客户端标识符之后
+        let options = Connect { client_id: "c", username: Some("u"), password: Some("p"), keep_alive: Duration::ZERO };
+        assert_eq!(connect(&options), b"\x10\x13\x00\x04MQTT\x04\xC2\x00\x00\x00\x01c\x00\x01u\x00\x01p");
+
+        assert_eq!(publish("a/b", b"hi", None, false), b"\x30\x07\x00\x03a/bhi");
+        assert_eq!(publish("a/b", b"hi", Some(10), true), b"\x33\x09\x00\x03a/b\x00\x0Ahi");
+        assert_eq!(subscribe(1, "cmd/#", 1), b"\x82\x0A\x00\x01\x00\x05cmd/#\x01");
+        assert_eq!(puback(10), [0x40, 0x02, 0x00, 0x0A]);
+        assert_eq!(pingreq(), [0xC0, 0x00]);
+        assert_eq!(disconnect(), [0xE0, 0x00]);
+    }
+
+    #[test]
+    fn remaining_length() {
+        // 每字节 7 位，低位在前
+        for (len, encoded) in [
+            (0, &[0x00][..]),
+            (127, &[0x7F]),
+            (128, &[0x80, 0x01]),
+            (16383, &[0xFF, 0x7F]),
+            (16384, &[0x80, 0x80, 0x01]),
+            (2097151, &[0xFF, 0xFF, 0x7F]),
+            (2097152, &[0x80, 0x80, 0x80, 0x01]),
+        ] {
+            let packet = packet(PUBLISH, 0, &vec![0; len]);
+            assert_eq!(&packet[1..1 + encoded.len()], encoded, "长度 {}", len);
+            assert_eq!(packet.len(), 1 + encoded.len() + len);
+        }
+
+        // 服务器发来的 PUBLISH：主题 "t" 占 3 字节，其余为内容；剩余长度字段本身也可以分两次到达
+        for len in [127, 128, 16383, 16384] {
+            let packet = packet(PUBLISH, 0, &[&[0x00, 0x01, b't'][..], &vec![0x55; len - 3]].concat());
+            let mut decoder = Decoder::new();
+            assert!(decoder.decode(&packet[..2]).unwrap().is_empty());
+            let packets = decoder.decode(&packet[2..]).unwrap();
+            assert!(matches!(&packets[..], [Packet::Publish { payload, id: None }] if payload.len() == len - 3), "长度 {}", len);
+        }
+
+        // 超过 4 字节的长度字段和超过上限的报文都报错
+        assert!(Decoder::new().decode(&[0x30, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]).is_err());
+        assert!(Decoder::new().decode(&[0x30, 0x80, 0x80, 0x80, 0x01]).is_err());
+    }
+
+    #[test]
+    fn decode_packets() {
+        let mut decoder = Decoder::new();
+        let data = [
+            &[0x20, 0x02, 0x00, 0x00][..],
+            &[0x90, 0x03, 0x00, 0x01, 0x01],
+            b"\x32\x08\x00\x03cmd\x00\x07A",
+            &[0x40, 0x02, 0x00, 0x02],
+            &[0xD0, 0x00],
+            &[0x20, 0x02, 0x00, 0x04],
+        ]
+        .concat();
+        let (first, rest) = data.split_at(7);
+        let mut packets = decoder.decode(first).unwrap();
+        packets.extend(decoder.decode(rest).unwrap());
+        assert!(matches!(
+            &packets[..],
+            [
+                Packet::ConnAck { code: 0 },
+                Packet::SubAck { id: 1, codes },
+                Packet::Publish { payload, id: Some(7) },
+                Packet::PubAck,
+                Packet::PingResp,
+                Packet::ConnAck { code: 4 },
+            ] if codes == &[0x01] && payload == b"A"
+        ));
+        // 内容不完整的报文报错
+        assert!(Decoder::new().decode(&[0x20, 0x01, 0x00]).is_err());
+    }
+}
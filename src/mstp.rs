@@ -0,0 +1,178 @@
+//! BACnet MS/TP（ASHRAE 135 第 9 章）被动解码：帧类型、源/目的地址、帧头 CRC 和数据 CRC，
+//! 数据帧再简要标注 NPDU/APDU 类型
+//!
+//! 帧结构：`55 FF 类型 目的 源 长度(2, 高字节在前) 帧头CRC [数据 数据CRC(2, 低字节在前)]`
+
+use crate::checksum::crc16_x25;
+
+/// 前导码
+pub const PREAMBLE: [u8; 2] = [0x55, 0xFF];
+/// 前导码到帧头 CRC 的长度
+pub const HEADER_LEN: usize = 8;
+/// 数据最大长度（扩展帧）
+pub const MAX_DATA: usize = 1497;
+
+/// 帧头 CRC（多项式 x^8 + x^7 + 1），返回 CRC 寄存器的值
+fn header_crc(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0xFF, |crc, &byte| {
+        let mut value = (crc ^ byte) as u16;
+        value ^= (value << 1) ^ (value << 2) ^ (value << 3) ^ (value << 4) ^ (value << 5) ^ (value << 6) ^ (value << 7);
+        ((value & 0xFE) ^ ((value >> 8) & 1)) as u8
+    })
+}
+
+/// 帧头（含前导码的前 8 字节）CRC 是否正确：连同 CRC 字节一起计算的余数为 0x55
+pub fn header_ok(header: &[u8]) -> bool {
+    header.len() >= HEADER_LEN && header_crc(&header[2..HEADER_LEN]) == 0x55
+}
+
+/// 帧头中的数据长度
+pub fn data_len(header: &[u8]) -> usize {
+    u16::from_be_bytes([header[5], header[6]]) as usize
+}
+
+/// 整帧长度（有数据时另有 2 字节数据 CRC）
+pub fn frame_len(header: &[u8]) -> usize {
+    match data_len(header) {
+        0 => HEADER_LEN,
+        len => HEADER_LEN + len + 2,
+    }
+}
+
+fn is_extended(frame_type: u8) -> bool {
+    matches!(frame_type, 32..=34)
+}
+
+/// 校验数据 CRC；扩展帧的数据为 COBS 编码并自带 CRC-32K，这里不校验
+pub fn check(frame: &[u8]) -> std::result::Result<(), String> {
+    let len = data_len(frame);
+    if len == 0 || is_extended(frame[2]) {
+        return Ok(());
+    }
+    let (data, crc) = frame[HEADER_LEN..].split_at(len);
+    let expected = crc16_x25(data).to_le_bytes();
+    match crc == expected {
+        true => Ok(()),
+        false => Err(format!("数据 CRC 错误：收到 {:02X} {:02X}，应为 {:02X} {:02X}", crc[0], crc[1], expected[0], expected[1])),
+    }
+}
+
+fn frame_type_name(frame_type: u8) -> &'static str {
+    match frame_type {
+        0 => "令牌",
+        1 => "轮询主站",
+        2 => "轮询主站应答",
+        3 => "测试请求",
+        4 => "测试应答",
+        5 => "BACnet 数据（需应答）",
+        6 => "BACnet 数据（无需应答）",
+        7 => "应答推迟",
+        32 => "BACnet 扩展数据（需应答）",
+        33 => "BACnet 扩展数据（无需应答）",
+        34 => "IPv6 over MS/TP",
+        128..=255 => "厂商专有帧",
+        _ => "未知帧类型",
+    }
+}
+
+fn address(station: u8) -> String {
+    match station {
+        255 => "广播".to_string(),
+        station => station.to_string(),
+    }
+}
+
+/// 帧内容说明，如 "令牌 3 → 5" 或 "BACnet 数据（无需应答） 12 → 广播，24 字节，Who-Is"
+pub fn describe(frame: &[u8]) -> String {
+    let frame_type = frame[2];
+    let mut text = format!("{} {} → {}", frame_type_name(frame_type), address(frame[4]), address(frame[3]));
+    let len = data_len(frame);
+    if len > 0 {
+        text.push_str(&format!("，{} 字节", len));
+    }
+    match frame_type {
+        5 | 6 => {
+            if let Some(npdu) = describe_npdu(&frame[HEADER_LEN..HEADER_LEN + len]) {
+                text.push('，');
+                text.push_str(&npdu);
+            }
+        }
+        32..=34 => text.push_str("，COBS 编码（未校验数据）"),
+        _ => {}
+    }
+    text
+}
+
+/// 简要说明 NPDU：网络层报文类型或 APDU 类型和服务
+fn describe_npdu(npdu: &[u8]) -> Option<String> {
+    let [0x01, control, rest @ ..] = npdu else {
+        return None;
+    };
+    let mut pos = 0;
+    // 目的网络号、地址和跳数，源网络号和地址
+    if control & 0x20 != 0 {
+        pos += 3 + *rest.get(2)? as usize;
+    }
+    if control & 0x08 != 0 {
+        pos += 3 + *rest.get(pos + 2)? as usize;
+    }
+    if control & 0x20 != 0 {
+        pos += 1;
+    }
+    let rest = rest.get(pos..)?;
+    if control & 0x80 != 0 {
+        return Some(format!("网络层报文 0x{:02X}", rest.first()?));
+    }
+    let apdu = rest.first()?;
+    let text = match apdu >> 4 {
+        0 => format!("确认请求 {}", confirmed_service(*rest.get(if apdu & 0x08 != 0 { 5 } else { 3 })?)),
+        1 => format!("非确认请求 {}", unconfirmed_service(*rest.get(1)?)),
+        2 => format!("简单确认 {}", confirmed_service(*rest.get(2)?)),
+        3 => format!("复杂确认 {}", confirmed_service(*rest.get(if apdu & 0x08 != 0 { 4 } else { 2 })?)),
+        4 => "分段确认".to_string(),
+        5 => format!("错误 {}", confirmed_service(*rest.get(2)?)),
+        6 => "拒绝".to_string(),
+        7 => "中止".to_string(),
+        _ => format!("未知 APDU 类型 0x{:02X}", apdu),
+    };
+    Some(text)
+}
+
+fn confirmed_service(code: u8) -> String {
+    let name = match code {
+        0 => "AcknowledgeAlarm",
+        1 => "ConfirmedCOVNotification",
+        5 => "SubscribeCOV",
+        6 => "AtomicReadFile",
+        7 => "AtomicWriteFile",
+        8 => "AddListElement",
+        10 => "CreateObject",
+        11 => "DeleteObject",
+        12 => "ReadProperty",
+        14 => "ReadPropertyMultiple",
+        15 => "WriteProperty",
+        16 => "WritePropertyMultiple",
+        17 => "DeviceCommunicationControl",
+        20 => "ReinitializeDevice",
+        26 => "ReadRange",
+        _ => return format!("服务 {}", code),
+    };
+    name.to_string()
+}
+
+fn unconfirmed_service(code: u8) -> String {
+    let name = match code {
+        0 => "I-Am",
+        1 => "I-Have",
+        2 => "UnconfirmedCOVNotification",
+        3 => "UnconfirmedEventNotification",
+        4 => "UnconfirmedPrivateTransfer",
+        5 => "UnconfirmedTextMessage",
+        6 => "TimeSynchronization",
+        7 => "Who-Has",
+        8 => "Who-Is",
+        9 => "UTCTimeSynchronization",
+        _ => return format!("服务 {}", code),
+    };
+    name.to_string()
+}
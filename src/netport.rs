@@ -0,0 +1,503 @@
+//! 网络串口：`rfc2217://主机:端口` 经 RFC 2217 使用远程串口服务器（可以远程设置波特率、校验位和控制线），
+//! `tcp://主机:端口` 直接以 TCP 收发原始字节（串口参数由服务端决定，如 ser2net 的 raw 模式）。
+//!
+//! 实现了 `SerialPort`，所有子命令都可以像本地串口一样使用网络串口。
+//! 复制的句柄共用同一个连接：一个线程读取时，其他线程设置参数所等待的应答由读取的线程代为处理。
+
+use anyhow::{bail, Context, Result};
+use serialport::{ClearBuffer, DataBits, ErrorKind, FlowControl, Parity, SerialPort, StopBits};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::lock;
+use crate::rfc2217::{self, Item, Telnet};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// 等待服务端应答串口设置的时长
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+/// 顺便处理已到达数据（输入线变化通知、应答）时的读取等待
+const PEEK: Duration = Duration::from_millis(1);
+
+/// 是否为网络串口地址
+pub fn is_url(spec: &str) -> bool {
+    let spec = spec.to_ascii_lowercase();
+    spec.starts_with("rfc2217://") || spec.starts_with("tcp://")
+}
+
+/// 打开时的串口参数
+pub struct Settings {
+    pub baud: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    pub timeout: Duration,
+}
+
+/// 连接网络串口；RFC 2217 时按 `settings` 设置远程串口并等待服务端确认
+pub fn open(url: &str, settings: &Settings) -> Result<Box<dyn SerialPort>> {
+    let (scheme, address) = url.split_once("://").unwrap_or(("", url));
+    let rfc2217 = scheme.eq_ignore_ascii_case("rfc2217");
+    let address = address.trim_end_matches('/');
+    let mut last = None;
+    let mut stream = None;
+    for addr in address.to_socket_addrs().with_context(|| format!("无法解析地址 {}", address))? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(connected) => {
+                stream = Some(connected);
+                break;
+            }
+            Err(e) => last = Some(e),
+        }
+    }
+    let stream = match (stream, last) {
+        (Some(stream), _) => stream,
+        (None, Some(e)) => return Err(e).with_context(|| format!("连接 {} 失败", address)),
+        (None, None) => bail!("地址 {} 没有解析出任何 IP", address),
+    };
+    stream.set_nodelay(true).context("设置网络连接失败")?;
+
+    let shared = Arc::new(Shared {
+        url: url.to_string(),
+        rfc2217,
+        writer: Mutex::new(stream.try_clone().context("无法复制网络连接句柄")?),
+        reader: Mutex::new(Reader { stream, telnet: Telnet::new(), pending: VecDeque::new() }),
+        state: Mutex::new(State {
+            baud: settings.baud,
+            data_bits: settings.data_bits,
+            parity: settings.parity,
+            stop_bits: settings.stop_bits,
+            flow_control: settings.flow_control,
+            modem: 0,
+            replies: [0; 13],
+        }),
+        changed: Condvar::new(),
+    });
+    let mut port = NetPort { shared, timeout: settings.timeout };
+    if rfc2217 {
+        let greeting = lock(&port.shared.reader).telnet.greeting();
+        port.shared.write_raw(&greeting)?;
+        let configure = |port: &mut NetPort| -> serialport::Result<()> {
+            port.set_baud_rate(settings.baud)?;
+            port.set_data_bits(settings.data_bits)?;
+            port.set_parity(settings.parity)?;
+            port.set_stop_bits(settings.stop_bits)?;
+            port.set_flow_control(settings.flow_control)
+        };
+        configure(&mut port).map_err(|e| anyhow::anyhow!("{}，服务端可能不支持 RFC 2217（可改用 tcp://）", e.description))?;
+    }
+    Ok(Box::new(port))
+}
+
+struct Reader {
+    stream: TcpStream,
+    telnet: Telnet,
+    /// 已收到还没被读走的数据
+    pending: VecDeque<u8>,
+}
+
+/// 远程串口的参数（以服务端的应答为准）和输入线状态
+struct State {
+    baud: u32,
+    data_bits: DataBits,
+    parity: Parity,
+    stop_bits: StopBits,
+    flow_control: FlowControl,
+    /// 最近一次 NOTIFY-MODEMSTATE 的值
+    modem: u8,
+    /// 各命令收到应答的次数，用于等待某条命令的应答
+    replies: [u32; 13],
+}
+
+struct Shared {
+    url: String,
+    rfc2217: bool,
+    writer: Mutex<TcpStream>,
+    reader: Mutex<Reader>,
+    state: Mutex<State>,
+    /// 收到应答时通知等待的线程
+    changed: Condvar,
+}
+
+impl Shared {
+    fn write_raw(&self, bytes: &[u8]) -> io::Result<()> {
+        lock(&self.writer).write_all(bytes)
+    }
+
+    /// 读取一次连接（最多等待 `timeout`），数据放入 `pending`，同时处理 Telnet 协商和服务端的应答
+    fn fill(&self, reader: &mut Reader, timeout: Duration) -> io::Result<()> {
+        let mut buffer = [0u8; 4096];
+        reader.stream.set_read_timeout(Some(timeout.max(PEEK)))?;
+        let n = match reader.stream.read(&mut buffer) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "服务端关闭了连接")),
+            Ok(n) => n,
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if !self.rfc2217 {
+            reader.pending.extend(&buffer[..n]);
+            return Ok(());
+        }
+        let mut reply = Vec::new();
+        for item in reader.telnet.decode(&buffer[..n], &mut reply) {
+            match item {
+                Item::Data(data) => reader.pending.extend(data),
+                Item::Command(command, payload) => self.accept(command, &payload),
+            }
+        }
+        if !reply.is_empty() {
+            self.write_raw(&reply)?;
+        }
+        Ok(())
+    }
+
+    /// 记录服务端的应答（命令号加 100）
+    fn accept(&self, command: u8, payload: &[u8]) {
+        let Some(command) = command.checked_sub(rfc2217::SERVER) else {
+            return;
+        };
+        let mut state = lock(&self.state);
+        let value = payload.first().copied().unwrap_or(0);
+        match command {
+            rfc2217::SET_BAUDRATE => {
+                if let [a, b, c, d, ..] = payload {
+                    state.baud = u32::from_be_bytes([*a, *b, *c, *d]);
+                }
+            }
+            rfc2217::SET_DATASIZE => state.data_bits = DataBits::try_from(value).unwrap_or(state.data_bits),
+            rfc2217::SET_PARITY => {
+                state.parity = match value {
+                    1 => Parity::None,
+                    2 => Parity::Odd,
+                    3 => Parity::Even,
+                    _ => state.parity,
+                }
+            }
+            rfc2217::SET_STOPSIZE => state.stop_bits = StopBits::try_from(value).unwrap_or(state.stop_bits),
+            rfc2217::SET_CONTROL => {
+                state.flow_control = match value {
+                    rfc2217::FLOW_NONE => FlowControl::None,
+                    rfc2217::FLOW_XONXOFF => FlowControl::Software,
+                    rfc2217::FLOW_HARDWARE => FlowControl::Hardware,
+                    _ => state.flow_control,
+                }
+            }
+            rfc2217::NOTIFY_MODEMSTATE => state.modem = value,
+            _ => {}
+        }
+        if let Some(count) = state.replies.get_mut(command as usize) {
+            *count += 1;
+        }
+        self.changed.notify_all();
+    }
+
+    /// 发送一条 COM-PORT-OPTION 命令并等待服务端应答
+    fn command(&self, command: u8, payload: &[u8]) -> serialport::Result<()> {
+        if !self.rfc2217 {
+            return Err(serialport::Error::new(ErrorKind::InvalidInput, "tcp:// 连接不能设置串口参数和控制线，请使用 rfc2217://"));
+        }
+        let before = lock(&self.state).replies[command as usize];
+        self.write_raw(&rfc2217::subnegotiation(command, payload))?;
+        let deadline = Instant::now() + REPLY_TIMEOUT;
+        loop {
+            if lock(&self.state).replies[command as usize] != before {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(serialport::Error::new(ErrorKind::Unknown, format!("服务端没有应答串口控制命令 {}", command)));
+            }
+            // 没有其他线程在读取时自己读取应答，否则等读取的线程处理
+            match self.reader.try_lock() {
+                Ok(mut reader) => self.fill(&mut reader, Duration::from_millis(50))?,
+                Err(_) => {
+                    let state = lock(&self.state);
+                    let _ = self.changed.wait_timeout(state, Duration::from_millis(50));
+                }
+            }
+        }
+    }
+
+    /// 没有其他线程在读取时，处理已经到达的数据
+    fn peek(&self) -> io::Result<()> {
+        match self.reader.try_lock() {
+            Ok(mut reader) => self.fill(&mut reader, PEEK),
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn modem_line(&self, bit: u8) -> serialport::Result<bool> {
+        if !self.rfc2217 {
+            return Err(serialport::Error::new(ErrorKind::InvalidInput, "tcp:// 连接不能读取输入状态线，请使用 rfc2217://"));
+        }
+        self.peek()?;
+        Ok(lock(&self.state).modem & bit != 0)
+    }
+}
+
+/// 网络串口的句柄
+pub struct NetPort {
+    shared: Arc<Shared>,
+    timeout: Duration,
+}
+
+impl Read for NetPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut reader = lock(&self.shared.reader);
+        let deadline = Instant::now() + self.timeout;
+        while reader.pending.is_empty() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "读取超时"));
+            }
+            self.shared.fill(&mut reader, remaining)?;
+        }
+        let n = buf.len().min(reader.pending.len());
+        for (slot, byte) in buf.iter_mut().zip(reader.pending.drain(..n)) {
+            *slot = byte;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for NetPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.shared.rfc2217 {
+            true => self.shared.write_raw(&rfc2217::escape(buf))?,
+            false => self.shared.write_raw(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        lock(&self.shared.writer).flush()
+    }
+}
+
+impl SerialPort for NetPort {
+    fn name(&self) -> Option<String> {
+        Some(self.shared.url.clone())
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(lock(&self.shared.state).baud)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(lock(&self.shared.state).data_bits)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(lock(&self.shared.state).flow_control)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(lock(&self.shared.state).parity)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(lock(&self.shared.state).stop_bits)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.shared.command(rfc2217::SET_BAUDRATE, &baud_rate.to_be_bytes())
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+        self.shared.command(rfc2217::SET_DATASIZE, &[u8::from(data_bits)])
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+        let value = match flow_control {
+            FlowControl::None => rfc2217::FLOW_NONE,
+            FlowControl::Software => rfc2217::FLOW_XONXOFF,
+            FlowControl::Hardware => rfc2217::FLOW_HARDWARE,
+        };
+        self.shared.command(rfc2217::SET_CONTROL, &[value])
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+        let value = match parity {
+            Parity::None => 1,
+            Parity::Odd => 2,
+            Parity::Even => 3,
+        };
+        self.shared.command(rfc2217::SET_PARITY, &[value])
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+        self.shared.command(rfc2217::SET_STOPSIZE, &[u8::from(stop_bits)])
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> serialport::Result<()> {
+        self.shared.command(rfc2217::SET_CONTROL, &[if level { rfc2217::RTS_ON } else { rfc2217::RTS_OFF }])
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> serialport::Result<()> {
+        self.shared.command(rfc2217::SET_CONTROL, &[if level { rfc2217::DTR_ON } else { rfc2217::DTR_OFF }])
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        self.shared.modem_line(0x10)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        self.shared.modem_line(0x20)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        self.shared.modem_line(0x40)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        self.shared.modem_line(0x80)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        self.shared.peek()?;
+        Ok(lock(&self.shared.reader).pending.len() as u32)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        if matches!(buffer_to_clear, ClearBuffer::Input | ClearBuffer::All) {
+            self.shared.peek()?;
+            lock(&self.shared.reader).pending.clear();
+        }
+        // 远程串口服务器上的缓冲区也一并清空（不等待应答）
+        if self.shared.rfc2217 {
+            let value = match buffer_to_clear {
+                ClearBuffer::Input => 1,
+                ClearBuffer::Output => 2,
+                ClearBuffer::All => 3,
+            };
+            self.shared.write_raw(&rfc2217::subnegotiation(rfc2217::PURGE_DATA, &[value]))?;
+        }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(Box::new(NetPort { shared: self.shared.clone(), timeout: self.timeout }))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        self.shared.command(rfc2217::SET_CONTROL, &[rfc2217::BREAK_ON])
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        self.shared.command(rfc2217::SET_CONTROL, &[rfc2217::BREAK_OFF])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rfc2217::PortControl;
+    use crate::xmodem::tests::pipe;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn settings() -> Settings {
+        Settings {
+            baud: 9600,
+            data_bits: DataBits::Eight,
+            parity: Parity::Even,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    /// 读取 `len` 个字节
+    fn read_len(port: &mut dyn Read, len: usize) -> Vec<u8> {
+        let mut data = vec![0; len];
+        port.read_exact(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn rfc2217_client() {
+        // 与 bridge tcp-server --rfc2217 相同的服务端，串口为内存中的测试串口（波特率总为 115200）
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("rfc2217://{}", listener.local_addr().unwrap());
+        let (mut serial, mut device) = pipe();
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut telnet = Telnet::new();
+                let mut control = PortControl::new();
+                stream.write_all(&telnet.greeting()).unwrap();
+                let mut buffer = [0; 1024];
+                loop {
+                    let n = match stream.read(&mut buffer) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => n,
+                    };
+                    let mut reply = Vec::new();
+                    for item in telnet.decode(&buffer[..n], &mut reply) {
+                        match item {
+                            Item::Data(data) => serial.write_all(&data).unwrap(),
+                            Item::Command(command, payload) => reply.extend(control.apply(&mut serial, command, &payload).0),
+                        }
+                    }
+                    // 设置校验位后发送数据（0xFF 转义后在 IAC IAC 之间分两次发送）和输入线状态通知
+                    if reply.ends_with(&rfc2217::subnegotiation(rfc2217::SET_PARITY + rfc2217::SERVER, &[1])) {
+                        reply.extend([0x01, rfc2217::IAC]);
+                        stream.write_all(&reply).unwrap();
+                        thread::sleep(Duration::from_millis(50));
+                        reply = vec![rfc2217::IAC, 0x02];
+                        reply.extend(control.apply(&mut serial, rfc2217::NOTIFY_MODEMSTATE, &[]).0);
+                    }
+                    stream.write_all(&reply).unwrap();
+                }
+            });
+
+            // 打开时按参数设置远程串口，以服务端应答的实际值为准
+            let mut port = open(&url, &settings()).unwrap();
+            assert_eq!((port.baud_rate().unwrap(), port.parity().unwrap()), (115200, Parity::None));
+            assert_eq!(read_len(&mut port, 3), [0x01, 0xFF, 0x02]);
+            let deadline = Instant::now() + Duration::from_secs(1);
+            while !port.read_carrier_detect().unwrap() {
+                assert!(Instant::now() < deadline, "没有收到 NOTIFY-MODEMSTATE");
+            }
+            assert!(port.read_clear_to_send().unwrap() && !port.read_ring_indicator().unwrap());
+            // 写入的 0xFF 经 IAC IAC 转义后原样到达串口
+            port.write_all(b"\xffAT\xff").unwrap();
+            assert_eq!(read_len(&mut device, 4), b"\xffAT\xff");
+            port.write_data_terminal_ready(false).unwrap();
+        });
+    }
+
+    #[test]
+    fn tcp_raw() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("tcp://{}", listener.local_addr().unwrap());
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let data = read_len(&mut stream, 3);
+                stream.write_all(&data).unwrap();
+            });
+            // 原始 TCP 不转义 0xFF，也不能设置串口参数
+            let mut port = open(&url, &settings()).unwrap();
+            port.write_all(b"\xff\xfd\x00").unwrap();
+            assert_eq!(read_len(&mut port, 3), b"\xff\xfd\x00");
+            assert_eq!(port.set_baud_rate(115200).unwrap_err().kind(), ErrorKind::InvalidInput);
+        });
+        assert!(is_url("RFC2217://host:2217") && is_url("tcp://1.2.3.4:4001") && !is_url("COM3"));
+    }
+}
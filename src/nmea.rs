@@ -0,0 +1,180 @@
+//! NMEA 0183 语句解码：校验 `*HH` 校验和（'$' 与 '*' 之间所有字符的异或），
+//! 解析 GGA、RMC、GSV、VTG 等常用语句的字段，如 `$GPGGA,092750.000,5321.6802,N,00630.3372,W,1,8,1.03,61.7,M,55.2,M,,*76`
+
+/// 一行语句的上限（标准规定最长 82 字符，部分接收机的私有语句更长）
+pub const MAX_LINE: usize = 256;
+
+/// 是否为语句起始符（'!' 用于 AIS 等封装语句）
+pub fn is_start(byte: u8) -> bool {
+    byte == b'$' || byte == b'!'
+}
+
+/// 校验和；没有 `*HH` 时不校验
+pub fn check(line: &str) -> std::result::Result<(), String> {
+    let Some((body, checksum)) = line[1..].rsplit_once('*') else {
+        return Ok(());
+    };
+    let expected = body.bytes().fold(0u8, |acc, b| acc ^ b);
+    match u8::from_str_radix(checksum.trim(), 16) {
+        Ok(received) if received == expected => Ok(()),
+        Ok(received) => Err(format!("校验和错误：收到 {:02X}，应为 {:02X}", received, expected)),
+        Err(_) => Err(format!("无效的校验和 '{}'", checksum)),
+    }
+}
+
+/// 拆分语句：去掉起始符和校验和，返回地址（如 GPGGA）和各字段
+pub fn split(line: &str) -> (&str, Vec<&str>) {
+    let body = line[1..].rsplit_once('*').map_or(&line[1..], |(body, _)| body);
+    let mut fields: Vec<&str> = body.split(',').collect();
+    let address = fields.remove(0);
+    (address, fields)
+}
+
+/// 语句内容说明，如 "GPS GGA 09:27:50 53.361337°N 6.505620°W GPS 定位 卫星 8 HDOP 1.03 海拔 61.7 m"；
+/// 不认识的语句只标注来源
+pub fn describe(line: &str) -> String {
+    let (address, fields) = split(line);
+    if address.starts_with('P') {
+        return format!("私有语句 {}", address);
+    }
+    let (talker, kind) = address.split_at(address.len().min(2));
+    let source = talker_name(talker);
+    let field = |i: usize| fields.get(i).copied().unwrap_or("");
+    let detail = match kind {
+        "GGA" => format!(
+            "{} {} {} 卫星 {} HDOP {} 海拔 {} m",
+            time(field(0)).unwrap_or_else(|| "--:--:--".to_string()),
+            position(field(1), field(2), field(3), field(4)),
+            gga_quality(field(5)),
+            or_dash(field(6)),
+            or_dash(field(7)),
+            or_dash(field(8))
+        ),
+        "RMC" => format!(
+            "{} {} {} {} 速度 {} 航向 {}{}",
+            date(field(8)).unwrap_or_else(|| "----------".to_string()),
+            time(field(0)).unwrap_or_else(|| "--:--:--".to_string()),
+            if field(1) == "A" { "有效" } else { "无效" },
+            position(field(2), field(3), field(4), field(5)),
+            speed(field(6)),
+            degrees(field(7)),
+            mode(field(11))
+        ),
+        "GSV" => {
+            let satellites: Vec<String> = fields
+                .get(3..)
+                .unwrap_or_default()
+                // 每颗卫星 4 个字段，NMEA 4.10 起末尾多一个信号 ID
+                .chunks_exact(4)
+                .filter(|sat| !sat[0].is_empty())
+                .map(|sat| format!("{}(仰角 {} 方位 {} 信噪比 {})", sat[0], or_dash(sat[1]), or_dash(sat[2]), or_dash(sat[3])))
+                .collect();
+            format!("第 {}/{} 条，可见卫星 {}：{}", field(1), field(0), or_dash(field(2)), satellites.join(" "))
+        }
+        "VTG" => format!("航向 {}（磁 {}） 速度 {}{}", degrees(field(0)), degrees(field(2)), speed(field(4)), mode(field(8))),
+        _ => String::new(),
+    };
+    match detail.is_empty() {
+        true => format!("{} {}", source, kind),
+        false => format!("{} {} {}", source, kind, detail),
+    }
+}
+
+fn talker_name(talker: &str) -> &str {
+    match talker {
+        "GP" => "GPS",
+        "GL" => "GLONASS",
+        "GA" => "Galileo",
+        "GB" | "BD" => "北斗",
+        "GQ" => "QZSS",
+        "GI" => "NavIC",
+        "GN" => "多系统",
+        _ => talker,
+    }
+}
+
+fn or_dash(value: &str) -> &str {
+    if value.is_empty() {
+        "-"
+    } else {
+        value
+    }
+}
+
+/// hhmmss.ss 转为 hh:mm:ss.ss
+pub fn time(value: &str) -> Option<String> {
+    let hms = value.get(..6).filter(|hms| hms.bytes().all(|b| b.is_ascii_digit()))?;
+    Some(format!("{}:{}:{}{}", &hms[..2], &hms[2..4], &hms[4..], &value[6..]))
+}
+
+/// ddmmyy 转为 20yy-mm-dd
+pub fn date(value: &str) -> Option<String> {
+    let valid = value.len() == 6 && value.bytes().all(|b| b.is_ascii_digit());
+    valid.then(|| format!("20{}-{}-{}", &value[4..], &value[2..4], &value[..2]))
+}
+
+/// 纬度 ddmm.mmmm（N/S）或经度 dddmm.mmmm（E/W）转为十进制度，南纬和西经为负数
+pub fn coordinate(value: &str, hemisphere: &str) -> Option<f64> {
+    let (digits, sign) = match hemisphere {
+        "N" => (2, 1.0),
+        "S" => (2, -1.0),
+        "E" => (3, 1.0),
+        "W" => (3, -1.0),
+        _ => return None,
+    };
+    let degrees: f64 = value.get(..digits)?.parse().ok()?;
+    let minutes: f64 = value.get(digits..)?.parse().ok()?;
+    Some(sign * (degrees + minutes / 60.0))
+}
+
+fn position(lat: &str, ns: &str, lon: &str, ew: &str) -> String {
+    match (coordinate(lat, ns), coordinate(lon, ew)) {
+        (Some(lat), Some(lon)) => format!("{:.6}°{} {:.6}°{}", lat.abs(), ns, lon.abs(), ew),
+        _ => "无位置".to_string(),
+    }
+}
+
+/// 节转为 km/h
+fn speed(knots: &str) -> String {
+    match knots.parse::<f64>() {
+        Ok(knots) => format!("{:.1} km/h", knots * 1.852),
+        Err(_) => "-".to_string(),
+    }
+}
+
+fn degrees(value: &str) -> String {
+    match value.is_empty() {
+        true => "-".to_string(),
+        false => format!("{}°", value),
+    }
+}
+
+pub fn gga_quality(quality: &str) -> &'static str {
+    match quality {
+        "0" => "未定位",
+        "1" => "GPS 定位",
+        "2" => "DGPS",
+        "3" => "PPS",
+        "4" => "RTK 固定解",
+        "5" => "RTK 浮点解",
+        "6" => "推算",
+        "7" => "手动输入",
+        "8" => "模拟",
+        _ => "定位状态未知",
+    }
+}
+
+/// RMC/VTG 的模式指示（NMEA 2.3 起）
+fn mode(mode: &str) -> &'static str {
+    match mode {
+        "A" => " 自主定位",
+        "D" => " 差分",
+        "E" => " 推算",
+        "F" => " RTK 浮点解",
+        "R" => " RTK 固定解",
+        "M" => " 手动输入",
+        "S" => " 模拟",
+        "N" => " 无效",
+        _ => "",
+    }
+}
@@ -0,0 +1,254 @@
+//! ELM327 OBD-II 适配器：自动完成初始化（ATZ、ATE0、协议选择），查询 PID 并解码常用发动机数据，
+//! 读取故障码
+//!
+//! ELM327 的每条命令以 CR 结束，应答以 `>` 提示符结束；关闭回显和表头后，
+//! 应答为十六进制字节，如 `010C` 的应答 `41 0C 1A F8`（发动机转速 1726 rpm）。
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::events::{self, Event};
+use crate::format_hex;
+use crate::parse_duration;
+use crate::terminal;
+
+/// obd 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct ObdArgs {
+    #[command(subcommand)]
+    pub command: ObdCommand,
+
+    /// OBD 协议：0 自动，1 J1850 PWM，2 J1850 VPW，3 ISO 9141-2，4 KWP2000 5 波特率初始化，
+    /// 5 KWP2000 快速初始化，6～9 ISO 15765-4 CAN（11/29 位，500k/250k），A J1939
+    #[arg(long, default_value = "0", global = true,
+          value_parser = ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "A", "B", "C"])]
+    pub protocol: String,
+
+    /// 等待每条命令应答（> 提示符）的超时时间；首次查询时自动搜索协议可能需要数秒
+    #[arg(long, default_value = "10s", value_parser = parse_duration, global = true)]
+    pub timeout: Duration,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ObdCommand {
+    /// 查询 PID（服务号 + PID，十六进制），如 010C 转速、010D 车速、0105 冷却液温度、0100 支持的 PID，可一次查询多个
+    Read {
+        #[arg(required = true, value_parser = parse_request)]
+        pids: Vec<Vec<u8>>,
+    },
+    /// 读取已存储的故障码（服务 03）
+    Dtc,
+}
+
+/// 解析十六进制请求，如 010C
+fn parse_request(s: &str) -> std::result::Result<Vec<u8>, String> {
+    match hex_bytes(s) {
+        Some(bytes) if (1..=7).contains(&bytes.len()) => Ok(bytes),
+        _ => Err(format!("无效的 OBD 请求 '{}'，应为服务号加 PID 的十六进制，如 010C", s)),
+    }
+}
+
+/// 解析以空格分隔或连写的十六进制字节，含其他字符时返回 `None`
+fn hex_bytes(text: &str) -> Option<Vec<u8>> {
+    let hex = text.replace(' ', "");
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len() / 2).map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()).collect()
+}
+
+/// 执行 obd 子命令：初始化适配器后查询 PID 或读取故障码
+pub fn run_obd(port: &mut Box<dyn SerialPort>, opts: &ObdArgs) -> Result<()> {
+    port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+    let mut elm = Elm327 { timeout: opts.timeout };
+    let version = elm.command(port, "ATZ")?;
+    events::status(version.iter().rev().find(|line| line.contains("ELM")).map_or("适配器已复位", |s| s.as_str()));
+    for setup in ["ATE0", "ATL0", "ATS1", "ATH0"] {
+        elm.command(port, setup)?;
+    }
+    elm.command(port, &format!("ATSP{}", opts.protocol))?;
+
+    match &opts.command {
+        ObdCommand::Read { pids } => {
+            for request in pids {
+                let responses = elm.query(port, request)?;
+                for response in responses {
+                    events::status(&describe(request, &response));
+                }
+            }
+        }
+        ObdCommand::Dtc => {
+            let responses = elm.query(port, &[0x03])?;
+            let codes: Vec<String> = responses.iter().flat_map(|r| decode_dtcs(r)).collect();
+            match codes.is_empty() {
+                true => events::status("没有已存储的故障码"),
+                false => events::status(&format!("{} 个故障码：{}", codes.len(), codes.join(" "))),
+            }
+        }
+    }
+    if let Some(protocol) = elm.command(port, "ATDP")?.first() {
+        events::status(&format!("协议：{}", protocol));
+    }
+    Ok(())
+}
+
+struct Elm327 {
+    timeout: Duration,
+}
+
+impl Elm327 {
+    /// 发送一条命令，返回 > 提示符之前的各行应答（去掉回显和空行）
+    fn command(&mut self, port: &mut Box<dyn SerialPort>, command: &str) -> Result<Vec<String>> {
+        terminal::transmit(port, None, format!("{}\r", command).as_bytes())?;
+        let deadline = Instant::now() + self.timeout;
+        let mut received = Vec::new();
+        let mut buffer = [0u8; 256];
+        while !received.contains(&b'>') {
+            if Instant::now() >= deadline {
+                bail!("命令 {} 在 {:?} 内无应答，请确认是 ELM327 适配器且串口波特率正确（常见 38400 或 9600）", command, self.timeout);
+            }
+            match port.read(&mut buffer) {
+                Ok(n) => {
+                    events::emit(Event::Rx(&buffer[..n]));
+                    received.extend_from_slice(&buffer[..n]);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e).context("读取串口失败"),
+            }
+        }
+        let text = String::from_utf8_lossy(&received[..received.iter().position(|&b| b == b'>').unwrap_or(0)]).to_string();
+        let lines: Vec<String> = text
+            .split(['\r', '\n'])
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty() && line != command && line != "OK")
+            .collect();
+        if lines.iter().any(|line| line == "?") {
+            bail!("适配器不支持命令 {}", command);
+        }
+        Ok(lines)
+    }
+
+    /// 发送 OBD 请求，返回每个 ECU 的应答（字节）
+    fn query(&mut self, port: &mut Box<dyn SerialPort>, request: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let command: String = request.iter().map(|b| format!("{:02X}", b)).collect();
+        let lines = self.command(port, &command)?;
+        let mut responses = Vec::new();
+        for line in &lines {
+            if line.starts_with("SEARCHING") || (line.starts_with("BUS INIT") && !line.contains("ERROR")) {
+                continue;
+            }
+            if let Some(hint) = error_hint(line) {
+                bail!("{} 失败：{}（{}）", command, line, hint);
+            }
+            match hex_bytes(line) {
+                Some(bytes) if !bytes.is_empty() => responses.push(bytes),
+                _ => events::status(&format!("适配器输出：{}", line)),
+            }
+        }
+        // 否定应答：7F 服务号 原因
+        if let Some(negative) = responses.iter().find(|r| r.first() == Some(&0x7F)) {
+            bail!("{} 被 ECU 拒绝，否定应答码 0x{:02X}", command, negative.get(2).copied().unwrap_or(0));
+        }
+        if responses.is_empty() {
+            bail!("{} 没有收到 ECU 的应答", command);
+        }
+        Ok(responses)
+    }
+}
+
+/// ELM327 错误信息和说明
+fn error_hint(line: &str) -> Option<&'static str> {
+    let hint = match line {
+        "NO DATA" => "ECU 不支持该 PID 或未应答",
+        "UNABLE TO CONNECT" => "未能与车辆建立连接，请确认点火开关已打开",
+        "CAN ERROR" => "CAN 总线错误，请检查协议和接线",
+        "BUS BUSY" => "总线忙",
+        "BUS ERROR" | "FB ERROR" => "总线电平异常，请检查接线",
+        "DATA ERROR" | "<DATA ERROR" => "应答数据校验错误",
+        "BUFFER FULL" => "适配器缓冲区满",
+        "STOPPED" => "命令被中断",
+        _ if line.starts_with("BUS INIT") => "总线初始化失败，请确认点火开关已打开",
+        _ if line.starts_with("LV RESET") => "电源电压过低，适配器已复位",
+        _ => return None,
+    };
+    Some(hint)
+}
+
+/// 解码一个 PID 应答，如 "010C 发动机转速：1726 rpm"
+fn describe(request: &[u8], response: &[u8]) -> String {
+    let command: String = request.iter().map(|b| format!("{:02X}", b)).collect();
+    let raw = format_hex(response);
+    // 服务 01 的应答：41 PID 数据
+    let [0x41, pid, data @ ..] = response else {
+        return format!("{} 应答：{}", command, raw);
+    };
+    if request.first() != Some(&0x01) {
+        return format!("{} 应答：{}", command, raw);
+    }
+    match decode_pid(*pid, data) {
+        Some((name, value)) => format!("{} {}：{}", command, name, value),
+        None => format!("{} PID {:02X}：{}", command, pid, format_hex(data)),
+    }
+}
+
+/// 解码服务 01 的常用 PID，返回 (名称, 数值)
+fn decode_pid(pid: u8, data: &[u8]) -> Option<(&'static str, String)> {
+    let a = *data.first()? as f64;
+    let ab = || data.get(1).map(|&b| a * 256.0 + b as f64);
+    let value = match pid {
+        0x00 | 0x20 | 0x40 | 0x60 | 0x80 | 0xA0 | 0xC0 => {
+            let bits = u32::from_be_bytes(data.get(..4)?.try_into().ok()?);
+            let supported: Vec<String> =
+                (0..32).filter(|i| bits & (0x8000_0000 >> i) != 0).map(|i| format!("{:02X}", pid as u32 + i + 1)).collect();
+            return Some(("支持的 PID", supported.join(" ")));
+        }
+        0x01 => {
+            let mil = if a as u8 & 0x80 != 0 { "亮" } else { "灭" };
+            return Some(("故障灯状态", format!("故障灯{}，{} 个故障码", mil, a as u8 & 0x7F)));
+        }
+        0x04 => ("发动机负荷", format!("{:.1} %", a * 100.0 / 255.0)),
+        0x05 => ("冷却液温度", format!("{} °C", a - 40.0)),
+        0x06 => ("短期燃油修正（第 1 列）", format!("{:.1} %", (a - 128.0) * 100.0 / 128.0)),
+        0x07 => ("长期燃油修正（第 1 列）", format!("{:.1} %", (a - 128.0) * 100.0 / 128.0)),
+        0x08 => ("短期燃油修正（第 2 列）", format!("{:.1} %", (a - 128.0) * 100.0 / 128.0)),
+        0x09 => ("长期燃油修正（第 2 列）", format!("{:.1} %", (a - 128.0) * 100.0 / 128.0)),
+        0x0A => ("燃油压力", format!("{} kPa", a * 3.0)),
+        0x0B => ("进气歧管绝对压力", format!("{} kPa", a)),
+        0x0C => ("发动机转速", format!("{} rpm", ab()? / 4.0)),
+        0x0D => ("车速", format!("{} km/h", a)),
+        0x0E => ("点火提前角", format!("{} °", a / 2.0 - 64.0)),
+        0x0F => ("进气温度", format!("{} °C", a - 40.0)),
+        0x10 => ("空气流量", format!("{:.2} g/s", ab()? / 100.0)),
+        0x11 => ("节气门位置", format!("{:.1} %", a * 100.0 / 255.0)),
+        0x1F => ("发动机运行时间", format!("{} s", ab()?)),
+        0x21 => ("故障灯亮起后行驶距离", format!("{} km", ab()?)),
+        0x2F => ("燃油液位", format!("{:.1} %", a * 100.0 / 255.0)),
+        0x31 => ("清除故障码后行驶距离", format!("{} km", ab()?)),
+        0x33 => ("大气压力", format!("{} kPa", a)),
+        0x42 => ("控制模块电压", format!("{:.3} V", ab()? / 1000.0)),
+        0x46 => ("环境温度", format!("{} °C", a - 40.0)),
+        0x5C => ("机油温度", format!("{} °C", a - 40.0)),
+        0x5E => ("燃油消耗率", format!("{:.2} L/h", ab()? / 20.0)),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// 解码服务 03 的应答（43 [数量] 故障码...），每个故障码 2 字节，0000 为填充
+fn decode_dtcs(response: &[u8]) -> Vec<String> {
+    let Some((&0x43, rest)) = response.split_first() else {
+        return Vec::new();
+    };
+    // CAN 协议的应答在故障码前有一个数量字节，其余协议每行固定 3 个故障码
+    let codes = if !rest.len().is_multiple_of(2) { &rest[1..] } else { rest };
+    codes
+        .chunks_exact(2)
+        .filter(|code| code != &[0, 0])
+        .map(|code| {
+            let system = ['P', 'C', 'B', 'U'][(code[0] >> 6) as usize];
+            format!("{}{}{:X}{:02X}", system, (code[0] >> 4) & 0x03, code[0] & 0x0F, code[1])
+        })
+        .collect()
+}
@@ -0,0 +1,116 @@
+//! 往返延迟测量：向回显设备发送带序号和时间戳的探测包，等待原样返回，
+//! 统计最小/平均/最大延迟和百分位数（用于评估 USB 转换器延迟计时器、无线串口链路等）
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::events;
+use crate::parse_duration;
+use crate::signal::Rs485;
+use crate::terminal;
+
+/// ping 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct PingArgs {
+    /// 发送次数
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    pub count: u32,
+
+    /// 两次发送的间隔
+    #[arg(long, default_value = "1s", value_parser = parse_duration)]
+    pub interval: Duration,
+
+    /// 等待回显的超时时间
+    #[arg(long, default_value = "1s", value_parser = parse_duration)]
+    pub timeout: Duration,
+
+    /// 探测包长度（字节，含换行符；不足部分用填充字符补齐）
+    #[arg(long, default_value_t = 32, value_parser = clap::value_parser!(u16).range(24..=4096))]
+    pub size: u16,
+}
+
+/// 生成探测包：`PING <序号> <发送时刻微秒数>`，补齐到指定长度，以换行结尾
+fn probe(seq: u32, micros: u128, size: usize) -> Vec<u8> {
+    let mut packet = format!("PING {} {} ", seq, micros).into_bytes();
+    packet.resize(size - 1, b'.');
+    packet.push(b'\n');
+    packet
+}
+
+/// 执行 ping 测试，全部超时时返回错误
+pub fn run_ping(port: &mut Box<dyn SerialPort>, opts: &PingArgs, rs485: Option<Rs485>) -> Result<()> {
+    let start = Instant::now();
+    let mut rtts = Vec::new();
+    for seq in 1..=opts.count {
+        if seq > 1 {
+            thread::sleep(opts.interval);
+        }
+        // 丢弃之前超时的探测包迟到的回显
+        port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+
+        let packet = probe(seq, start.elapsed().as_micros(), opts.size as usize);
+        let sent_at = Instant::now();
+        terminal::transmit(port, rs485, &packet)?;
+        if wait_echo(port, &packet, sent_at + opts.timeout)? {
+            let rtt = sent_at.elapsed();
+            events::status(&format!("回显 {} 字节：seq={} 时间={:.2}ms", packet.len(), seq, millis(rtt)));
+            rtts.push(rtt);
+        } else {
+            events::status(&format!("请求超时：seq={}", seq));
+        }
+    }
+
+    let lost = opts.count as usize - rtts.len();
+    events::status(&format!(
+        "已发送 {}，已收到 {}，丢失 {:.0}%",
+        opts.count,
+        rtts.len(),
+        lost as f64 * 100.0 / opts.count as f64
+    ));
+    if rtts.is_empty() {
+        bail!("未收到任何回显，请确认设备会原样返回收到的数据");
+    }
+
+    rtts.sort();
+    let avg = rtts.iter().sum::<Duration>() / rtts.len() as u32;
+    events::status(&format!(
+        "往返延迟 最小 {:.2}ms / 平均 {:.2}ms / 最大 {:.2}ms / P50 {:.2}ms / P90 {:.2}ms / P99 {:.2}ms",
+        millis(rtts[0]),
+        millis(avg),
+        millis(rtts[rtts.len() - 1]),
+        millis(percentile(&rtts, 50)),
+        millis(percentile(&rtts, 90)),
+        millis(percentile(&rtts, 99)),
+    ));
+    Ok(())
+}
+
+/// 读取串口直到收到完整的探测包回显，超时返回 `false`
+fn wait_echo(port: &mut Box<dyn SerialPort>, packet: &[u8], deadline: Instant) -> Result<bool> {
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 512];
+    while Instant::now() < deadline {
+        match port.read(&mut buffer) {
+            Ok(n) => received.extend_from_slice(&buffer[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e).context("读取串口失败"),
+        }
+        if received.windows(packet.len()).any(|w| w == packet) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// 最近秩法计算百分位数（`sorted` 已升序排列且非空）
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    let rank = (sorted.len() * p).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
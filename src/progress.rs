@@ -0,0 +1,112 @@
+//! 传输进度：文件传输、原始收发和固件烧录共用的进度条（已传输字节数、速率和剩余时间）
+//!
+//! 终端上每 100ms 刷新一行进度条；JSON 模式下每秒输出一个 progress 事件；
+//! 输出重定向到文件时不显示。续传时速率和剩余时间只按本次传输的部分计算。
+
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+use crate::events::{self, Event};
+
+/// 一次传输的进度
+pub struct Progress {
+    total: Option<usize>,
+    /// 续传时已有的字节数，不计入速率
+    offset: usize,
+    start: Instant,
+    last_draw: Option<Instant>,
+    visible: bool,
+}
+
+impl Progress {
+    pub fn new(total: Option<usize>) -> Self {
+        Progress::resumed(total, 0)
+    }
+
+    /// 从 `offset` 处续传的进度，`total` 为包括已有部分在内的总长度
+    pub fn resumed(total: Option<usize>, offset: usize) -> Self {
+        Progress { total, offset, start: Instant::now(), last_draw: None, visible: events::jsonl() || io::stdout().is_terminal() }
+    }
+
+    /// 更新进度，`done` 为包括续传前已有部分在内的当前位置
+    pub fn update(&mut self, done: usize) {
+        let finished = self.total == Some(done);
+        let interval = Duration::from_millis(if events::jsonl() { 1000 } else { 100 });
+        if !self.visible || (!finished && self.last_draw.is_some_and(|at| at.elapsed() < interval)) {
+            return;
+        }
+        self.last_draw = Some(Instant::now());
+        let rate = self.rate(done.saturating_sub(self.offset)) as usize;
+        if events::jsonl() {
+            events::emit(Event::Progress { done: done as u64, total: self.total.map(|total| total as u64), rate: rate as u64 });
+            return;
+        }
+        const WIDTH: usize = 30;
+        let speed = match rate {
+            0 => String::new(),
+            rate => format!("{}/秒", size(rate)),
+        };
+        let line = match self.total {
+            Some(total) => {
+                let ratio = if total == 0 { 1.0 } else { (done as f64 / total as f64).min(1.0) };
+                let filled = (ratio * WIDTH as f64) as usize;
+                let eta = match rate {
+                    0 => String::new(),
+                    rate => format!("  剩余 {}", clock(total.saturating_sub(done) as f64 / rate as f64)),
+                };
+                format!(
+                    "[{}{}] {:>3.0}%  {} / {}  {}{}",
+                    "#".repeat(filled),
+                    "-".repeat(WIDTH - filled),
+                    ratio * 100.0,
+                    size(done),
+                    size(total),
+                    speed,
+                    eta
+                )
+            }
+            None => format!("已传输 {}  {}", size(done), speed),
+        };
+        let mut out = io::stdout();
+        let _ = write!(out, "\r\x1b[K{}", line);
+        let _ = out.flush();
+    }
+
+    pub fn finish(&self) {
+        if self.visible && !events::jsonl() && self.last_draw.is_some() {
+            println!();
+        }
+    }
+
+    /// 每秒字节数，刚开始时为 0
+    fn rate(&self, len: usize) -> f64 {
+        let seconds = self.start.elapsed().as_secs_f64();
+        match seconds > 0.0 {
+            true => len as f64 / seconds,
+            false => 0.0,
+        }
+    }
+
+    /// 结束时的统计，`len` 为本次传输的字节数
+    pub fn summary(&self, len: usize) -> String {
+        let rate = self.rate(len);
+        format!("{} 字节，用时 {:.1} 秒（{}/秒）", len, self.start.elapsed().as_secs_f64(), size(rate as usize))
+    }
+}
+
+fn size(bytes: usize) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..1048576 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1048576.0),
+    }
+}
+
+/// 剩余时间：不到一小时为 分:秒，否则为 时:分:秒
+fn clock(seconds: f64) -> String {
+    let seconds = seconds.ceil() as u64;
+    match seconds {
+        0..3600 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        _ => format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60),
+    }
+}
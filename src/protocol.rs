@@ -0,0 +1,236 @@
+//! 用户自定义的二进制协议描述（TOML）：按帧头同步、确定帧长、校验并逐字段解码显示
+//!
+//! 描述文件示例（用 `--frame format:sensor.toml` 加载）：
+//!
+//! ```toml
+//! header = "AA 55"                   # 帧头（十六进制），用于同步
+//! length = "offset=2,size=1,extra=3" # 可选，长度字段（同 len: 帧格式）；省略时帧长为各字段长度之和
+//! checksum = "sum8"                  # 可选，位于帧尾的校验和（同 --checksum）
+//! endian = "big"                     # 多字节字段的默认字节序
+//!
+//! [field.len]
+//! type = "u8"
+//!
+//! [field.temperature]
+//! type = "i16"
+//! scale = 0.1
+//! unit = "°C"
+//!
+//! [field.status]
+//! type = "u8"
+//! hex = true
+//! ```
+//!
+//! 字段按文件中的顺序紧接在帧头之后；类型为 u8/i8/u16/i16/u32/i32/f32、
+//! bytes 或 str（需 size，最后一个字段可省略 size 表示直到校验和之前的全部数据）。
+
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+
+use crate::checksum::{self, Checksum};
+use crate::config::{Config, Table};
+use crate::framing::LengthField;
+use crate::{format_hex, parse_hex};
+
+/// 字段类型
+#[derive(Debug, Clone, Copy)]
+enum FieldType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    /// 原始字节（十六进制显示），`None` 表示直到校验和之前的剩余数据
+    Bytes(Option<usize>),
+    /// 文本
+    Str(Option<usize>),
+}
+
+impl FieldType {
+    fn size(self) -> Option<usize> {
+        match self {
+            FieldType::U8 | FieldType::I8 => Some(1),
+            FieldType::U16 | FieldType::I16 => Some(2),
+            FieldType::U32 | FieldType::I32 | FieldType::F32 => Some(4),
+            FieldType::Bytes(size) | FieldType::Str(size) => size,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    name: String,
+    kind: FieldType,
+    big_endian: bool,
+    /// 数值乘以该系数后显示
+    scale: Option<f64>,
+    unit: String,
+    /// 整数以十六进制显示
+    hex: bool,
+}
+
+impl Field {
+    fn parse(name: &str, table: &Table, big_endian: bool) -> Result<Self> {
+        let size = table.int("size")?.map(|n| usize::try_from(n).context("size 不能为负数")).transpose()?;
+        let kind = match table.str("type")?.with_context(|| format!("字段 {} 缺少 type", name))? {
+            "u8" => FieldType::U8,
+            "i8" => FieldType::I8,
+            "u16" => FieldType::U16,
+            "i16" => FieldType::I16,
+            "u32" => FieldType::U32,
+            "i32" => FieldType::I32,
+            "f32" => FieldType::F32,
+            "bytes" => FieldType::Bytes(size),
+            "str" => FieldType::Str(size),
+            other => bail!("字段 {} 的类型 '{}' 无效，应为 u8/i8/u16/i16/u32/i32/f32/bytes/str", name, other),
+        };
+        let big_endian = match table.str("endian")? {
+            None => big_endian,
+            Some(endian) => parse_endian(endian)?,
+        };
+        Ok(Field {
+            name: name.to_string(),
+            kind,
+            big_endian,
+            scale: table.float("scale")?,
+            unit: table.str("unit")?.unwrap_or_default().to_string(),
+            hex: table.bool("hex")?.unwrap_or(false),
+        })
+    }
+
+    /// 解码字段值用于显示
+    fn format(&self, bytes: &[u8]) -> String {
+        let mut ordered = bytes.to_vec();
+        if !self.big_endian {
+            ordered.reverse();
+        }
+        let unsigned = ordered.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+        let value = match self.kind {
+            FieldType::Bytes(_) => return format_hex(bytes),
+            FieldType::Str(_) => return format!("\"{}\"", String::from_utf8_lossy(bytes).trim_end_matches('\0')),
+            FieldType::F32 => {
+                let value = f32::from_bits(unsigned as u32) as f64 * self.scale.unwrap_or(1.0);
+                return format!("{}{}", value, self.unit);
+            }
+            _ if self.hex => return format!("0x{:0width$X}", unsigned, width = bytes.len() * 2),
+            FieldType::I8 => unsigned as u8 as i8 as f64,
+            FieldType::I16 => unsigned as u16 as i16 as f64,
+            FieldType::I32 => unsigned as u32 as i32 as f64,
+            _ => unsigned as f64,
+        };
+        match self.scale {
+            // 按系数的小数位数显示，避免 0.1 * 235 显示为 23.500000000000004
+            Some(scale) => {
+                let decimals = (-scale.abs().log10().floor()).max(0.0) as usize;
+                format!("{:.*}{}", decimals, value * scale, self.unit)
+            }
+            None => format!("{}{}", value, self.unit),
+        }
+    }
+}
+
+fn parse_endian(s: &str) -> Result<bool> {
+    match s {
+        "big" | "be" => Ok(true),
+        "little" | "le" => Ok(false),
+        _ => bail!("无效的字节序 '{}'，应为 big 或 little", s),
+    }
+}
+
+/// 一个协议描述
+#[derive(Debug, Clone)]
+pub struct Protocol {
+    header: Vec<u8>,
+    length: Option<LengthField>,
+    checksum: Option<Checksum>,
+    fields: Vec<Field>,
+}
+
+impl Protocol {
+    /// 读取协议描述文件
+    pub fn load(path: &Path) -> Result<Self> {
+        let config = Config::load(Some(path))?;
+        let context = || format!("协议描述 {} 有误", path.display());
+        let top = config.table("").context("缺少顶层设置").with_context(context)?;
+
+        let header = parse_hex(top.str("header")?.unwrap_or_default()).with_context(context)?;
+        let length = top
+            .str("length")?
+            .map(LengthField::parse)
+            .transpose()
+            .map_err(anyhow::Error::msg)
+            .with_context(context)?;
+        let checksum = top
+            .str("checksum")?
+            .map(checksum::parse_checksum)
+            .transpose()
+            .map_err(anyhow::Error::msg)
+            .with_context(context)?;
+        let big_endian = parse_endian(top.str("endian")?.unwrap_or("big")).with_context(context)?;
+        let fields = config
+            .subtables("field")
+            .map(|(name, table)| Field::parse(name, table, big_endian))
+            .collect::<Result<Vec<_>>>()
+            .with_context(context)?;
+
+        if fields.is_empty() {
+            bail!("{}：至少需要一个 [field.<名称>]", context());
+        }
+        if let Some(field) = fields.iter().rev().skip(1).find(|f| f.kind.size().is_none()) {
+            bail!("{}：只有最后一个字段可以省略 size（字段 {}）", context(), field.name);
+        }
+        let protocol = Protocol { header, length, checksum, fields };
+        if protocol.length.is_none() && protocol.fixed_len().is_none() {
+            bail!("{}：最后一个字段不定长时需要指定 length", context());
+        }
+        Ok(protocol)
+    }
+
+    fn checksum_size(&self) -> usize {
+        self.checksum.map_or(0, |c| c.algorithm.size())
+    }
+
+    /// 各字段都定长时的帧长
+    fn fixed_len(&self) -> Option<usize> {
+        let fields: Option<usize> = self.fields.iter().map(|f| f.kind.size()).sum();
+        Some(self.header.len() + fields? + self.checksum_size())
+    }
+
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    /// 根据已缓存的数据（以帧头开始）计算帧长，数据不足时返回 `None`
+    pub fn frame_len(&self, data: &[u8]) -> Option<i64> {
+        match &self.length {
+            Some(field) => field.frame_len(data),
+            None => self.fixed_len().map(|len| len as i64),
+        }
+    }
+
+    /// 校验并解码一帧，返回各字段的显示文本
+    pub fn decode(&self, frame: &[u8]) -> std::result::Result<String, String> {
+        if let Some(checksum) = &self.checksum {
+            checksum.verify(frame)?;
+        }
+        let end = frame.len().checked_sub(self.checksum_size()).ok_or("帧长度不足")?;
+        let mut body = frame.get(self.header.len()..end).ok_or("帧长度不足")?;
+
+        let mut values = Vec::new();
+        for field in &self.fields {
+            let size = field.kind.size().unwrap_or(body.len());
+            if body.len() < size {
+                return Err(format!("帧长度不足，缺少字段 {}", field.name));
+            }
+            let (bytes, rest) = body.split_at(size);
+            values.push(format!("{}={}", field.name, field.format(bytes)));
+            body = rest;
+        }
+        if !body.is_empty() {
+            values.push(format!("（另有 {} 字节未定义）", body.len()));
+        }
+        Ok(values.join(" "))
+    }
+}
@@ -0,0 +1,152 @@
+//! 原样保存收到的数据：不做任何解码和换行处理，适合通过控制台导出 flash 等二进制内容
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::events::{self, Event};
+use crate::progress::Progress;
+use crate::{format_hex, parse_duration, parse_hex, parse_size};
+
+/// receive 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct ReceiveArgs {
+    /// 保存收到数据的文件
+    #[arg(long, value_name = "PATH")]
+    pub file: PathBuf,
+
+    /// 停止条件，可重复（满足任一即停止；不指定时一直接收直到按 Ctrl+C）：
+    /// idle:2s（收到数据后空闲该时长）、bytes:N（收满 N 字节，可用 K/M 后缀）、pattern:<hex>（收到该字节序列，包含在文件中）
+    #[arg(long, value_name = "COND", value_parser = parse_until)]
+    pub until: Vec<Until>,
+
+    /// 追加到已有的文件（默认覆盖）
+    #[arg(long)]
+    pub append: bool,
+
+    /// 续传：保留已有的文件内容并追加，bytes:N 按包括已有部分在内的总长度计算
+    /// （需要让设备从已有长度处开始发送）
+    #[arg(long, conflicts_with = "append")]
+    pub resume: bool,
+}
+
+/// 停止条件
+#[derive(Clone, Debug)]
+pub enum Until {
+    Idle(Duration),
+    Bytes(u64),
+    Pattern(Vec<u8>),
+}
+
+fn parse_until(s: &str) -> std::result::Result<Until, String> {
+    let (kind, value) = s.split_once(':').ok_or_else(|| format!("无效的停止条件 '{}'，应为 idle:2s、bytes:N 或 pattern:<hex>", s))?;
+    match kind {
+        "idle" => parse_duration(value).map(Until::Idle),
+        "bytes" => match parse_size(value) {
+            Ok(count) if count > 0 => Ok(Until::Bytes(count)),
+            _ => Err(format!("无效的字节数 '{}'", value)),
+        },
+        "pattern" => match parse_hex(value) {
+            Ok(pattern) if !pattern.is_empty() => Ok(Until::Pattern(pattern)),
+            _ => Err(format!("无效的字节序列 '{}'，应为十六进制（如 0D0A3E）", value)),
+        },
+        _ => Err(format!("未知的停止条件 '{}'，可用 idle、bytes、pattern", kind)),
+    }
+}
+
+/// 执行 receive 子命令
+pub fn run_receive(port: &mut Box<dyn SerialPort>, opts: &ReceiveArgs) -> Result<()> {
+    let mut file = open(opts)?;
+    let mut idle: Option<Duration> = None;
+    let mut limit: Option<u64> = None;
+    let mut patterns: Vec<&[u8]> = Vec::new();
+    for until in &opts.until {
+        match until {
+            Until::Idle(duration) => idle = Some(idle.map_or(*duration, |idle| idle.min(*duration))),
+            Until::Bytes(count) => limit = Some(limit.map_or(*count, |limit| limit.min(*count))),
+            Until::Pattern(pattern) => patterns.push(pattern),
+        }
+    }
+    // 跨两次读取的匹配需要保留上次末尾的数据
+    let keep = patterns.iter().map(|pattern| pattern.len()).max().unwrap_or(1) - 1;
+
+    // 续传时已有的长度
+    let existing = match opts.resume {
+        true => file.metadata().with_context(|| format!("读取 {} 失败", opts.file.display()))?.len(),
+        false => 0,
+    };
+    if limit.is_some_and(|limit| existing >= limit) {
+        bail!("{} 已有 {} 字节，已经收满", opts.file.display(), existing);
+    }
+
+    match existing {
+        0 => events::status(&format!("接收数据保存到 {}...", opts.file.display())),
+        _ => events::status(&format!("接收数据保存到 {}，从已有的 {} 字节处续传...", opts.file.display(), existing)),
+    }
+    let mut progress = Progress::resumed(limit.map(|limit| limit as usize), existing as usize);
+    let mut buffer = [0u8; 4096];
+    let mut tail: Vec<u8> = Vec::new();
+    let mut total = existing;
+    let mut last_data: Option<Instant> = None;
+    let reason = loop {
+        if let (Some(idle), Some(at)) = (idle, last_data) {
+            if at.elapsed() >= idle {
+                break format!("空闲 {:?}", idle);
+            }
+        }
+        let n = match port.read(&mut buffer) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e).context("读取串口失败"),
+        };
+        last_data = Some(Instant::now());
+        let mut chunk = &buffer[..n];
+        let mut stop = None;
+        // 匹配到的字节序列之后的数据不保存
+        let mut window = tail.clone();
+        window.extend_from_slice(chunk);
+        let matched = patterns
+            .iter()
+            .filter_map(|pattern| window.windows(pattern.len()).position(|w| w == *pattern).map(|at| (at + pattern.len(), *pattern)))
+            .min_by_key(|(end, _)| *end);
+        if let Some((end, pattern)) = matched {
+            chunk = &chunk[..end.saturating_sub(tail.len())];
+            stop = Some(format!("收到 {}", format_hex(pattern)));
+        }
+        if let Some(limit) = limit {
+            if total + chunk.len() as u64 >= limit {
+                chunk = &chunk[..(limit - total) as usize];
+                stop.get_or_insert_with(|| format!("已收满 {} 字节", limit));
+            }
+        }
+        events::emit(Event::Rx(chunk));
+        file.write_all(chunk).with_context(|| format!("写入 {} 失败", opts.file.display()))?;
+        total += chunk.len() as u64;
+        progress.update(total as usize);
+        if let Some(reason) = stop {
+            break reason;
+        }
+        window.drain(..window.len().saturating_sub(keep));
+        tail = window;
+    };
+    progress.finish();
+    file.flush().with_context(|| format!("写入 {} 失败", opts.file.display()))?;
+    events::status(&format!("接收结束（{}）：{}，{}", reason, opts.file.display(), progress.summary((total - existing) as usize)));
+    Ok(())
+}
+
+fn open(opts: &ReceiveArgs) -> Result<File> {
+    if opts.file.is_dir() {
+        bail!("{} 是目录，请指定保存的文件", opts.file.display());
+    }
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(opts.append || opts.resume)
+        .truncate(!opts.append && !opts.resume)
+        .open(&opts.file)
+        .with_context(|| format!("创建文件 {} 失败", opts.file.display()))
+}
@@ -0,0 +1,178 @@
+//! 操作前复位设备：按内置或配置文件中定义的 DTR/RTS 序列复位，并可等待设备启动信息
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use serialport::SerialPort;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::events;
+use crate::parse_duration;
+
+/// 内置的复位方式
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum AutoReset {
+    /// Arduino 自动复位：同时拉低 DTR/RTS 产生复位脉冲（经板上电容接到 RESET，部分板子使用 RTS）
+    Arduino,
+}
+
+impl AutoReset {
+    /// 对应的内置序列名
+    pub fn name(self) -> &'static str {
+        match self {
+            AutoReset::Arduino => "arduino",
+        }
+    }
+}
+
+/// 复位序列中的一步
+#[derive(Debug, Clone, Copy)]
+pub enum ResetStep {
+    Dtr(bool),
+    Rts(bool),
+    Delay(Duration),
+}
+
+/// 一个复位序列，以及复位后等待设备就绪的方式
+#[derive(Debug)]
+pub struct ResetSequence {
+    steps: Vec<ResetStep>,
+    wait: Option<Duration>,
+    banner: Option<Regex>,
+}
+
+/// 复位后默认的等待时间
+const DEFAULT_WAIT: Duration = Duration::from_secs(2);
+
+/// 查找复位序列：优先使用配置文件中的 `[reset.<名称>]`，其次是内置序列
+///
+/// 配置示例：
+///
+/// ```toml
+/// [reset.myboard]
+/// steps = ["dtr=0", "rts=1", "delay=100ms", "dtr=1", "rts=0", "delay=50ms", "dtr=0"]
+/// wait = "500ms"                  # 可选，复位后的等待时间
+/// banner = "waiting for download" # 可选，等待匹配的启动信息
+/// ```
+pub fn lookup(name: &str, config: &Config) -> Result<ResetSequence> {
+    if let Some(table) = config.table(&format!("reset.{}", name)) {
+        let context = || format!("配置文件 {} 中的复位序列 {} 有误", config.path.display(), name);
+        let steps = table
+            .str_array("steps")?
+            .with_context(|| format!("[reset.{}] 缺少 steps", name))
+            .with_context(context)?
+            .into_iter()
+            .map(parse_step)
+            .collect::<Result<Vec<_>>>()
+            .with_context(context)?;
+        let wait = table
+            .str("wait")?
+            .map(parse_duration)
+            .transpose()
+            .map_err(anyhow::Error::msg)
+            .with_context(context)?;
+        let banner = table.str("banner")?.map(Regex::new).transpose().with_context(context)?;
+        return Ok(ResetSequence { steps, wait, banner });
+    }
+
+    use ResetStep::*;
+    let ms = Duration::from_millis;
+    let steps = match name {
+        "arduino" => vec![Dtr(false), Rts(false), Delay(ms(100)), Dtr(true), Rts(true)],
+        // 经典 ESP32/ESP8266 自动下载电路（DTR 控制 IO0，RTS 控制 EN）：进入下载模式
+        "esp32" => vec![Dtr(false), Rts(true), Delay(ms(100)), Dtr(true), Rts(false), Delay(ms(50)), Dtr(false)],
+        // 同一电路下普通复位，运行用户程序
+        "esp32-run" => vec![Dtr(false), Rts(true), Delay(ms(100)), Rts(false)],
+        // STM32：DTR 接 BOOT0（DTR 无效时为高电平）、RTS 接 NRST（RTS 有效时复位），进入系统 bootloader
+        "stm32" => vec![Dtr(false), Rts(true), Delay(ms(100)), Rts(false), Delay(ms(50))],
+        // 同一接法下拉低 BOOT0 复位，运行用户程序
+        "stm32-run" => vec![Dtr(true), Rts(true), Delay(ms(100)), Rts(false)],
+        _ => {
+            let custom: Vec<&str> = config.subtables("reset").map(|(name, _)| name).collect();
+            bail!(
+                "未知的复位序列 '{}'，内置序列：arduino、esp32、esp32-run、stm32、stm32-run{}",
+                name,
+                if custom.is_empty() { String::new() } else { format!("；配置文件中：{}", custom.join("、")) }
+            );
+        }
+    };
+    // bootloader 复位后立即可用，不需要等待程序启动
+    let wait = name.starts_with("stm32").then(|| ms(100));
+    Ok(ResetSequence { steps, wait, banner: None })
+}
+
+/// 解析一步操作：`dtr=0`/`dtr=1`（也可用 on/off）、`rts=...` 或 `delay=100ms`
+fn parse_step(s: &str) -> Result<ResetStep> {
+    let (name, value) = s.split_once('=').with_context(|| format!("无效的步骤 '{}'，应为 dtr=1、rts=0 或 delay=100ms", s))?;
+    let level = || match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "on" | "high" | "true" => Ok(true),
+        "0" | "off" | "low" | "false" => Ok(false),
+        _ => bail!("无效的电平 '{}'，应为 0 或 1", value),
+    };
+    match name.trim().to_ascii_lowercase().as_str() {
+        "dtr" => level().map(ResetStep::Dtr),
+        "rts" => level().map(ResetStep::Rts),
+        "delay" => parse_duration(value).map(ResetStep::Delay).map_err(anyhow::Error::msg),
+        _ => bail!("无效的步骤 '{}'，应为 dtr、rts 或 delay", s),
+    }
+}
+
+/// 执行复位：依次应用序列中的各步，之后等待设备就绪
+///
+/// 有启动信息正则时读取串口直到数据匹配（最长等待 `wait`，超时报错），
+/// 否则固定等待 `wait`（如 Arduino bootloader 结束、程序开始运行所需的时间）。
+/// 命令行指定的 `wait`/`banner` 优先于序列自带的设置。
+pub fn reset(
+    port: &mut Box<dyn SerialPort>,
+    sequence: &ResetSequence,
+    wait: Option<Duration>,
+    banner: Option<&Regex>,
+) -> Result<()> {
+    run_steps(port, &sequence.steps)?;
+    // 丢弃复位前残留的数据，避免误匹配
+    port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+    events::status("已复位设备");
+
+    let wait = wait.or(sequence.wait).unwrap_or(DEFAULT_WAIT);
+    match banner.or(sequence.banner.as_ref()) {
+        Some(pattern) => wait_banner(port, pattern, wait),
+        None => {
+            thread::sleep(wait);
+            Ok(())
+        }
+    }
+}
+
+fn run_steps(port: &mut Box<dyn SerialPort>, steps: &[ResetStep]) -> Result<()> {
+    for step in steps {
+        match *step {
+            ResetStep::Dtr(level) => port.write_data_terminal_ready(level).context("设置 DTR 失败")?,
+            ResetStep::Rts(level) => port.write_request_to_send(level).context("设置 RTS 失败")?,
+            ResetStep::Delay(delay) => thread::sleep(delay),
+        }
+    }
+    Ok(())
+}
+
+/// 读取串口直到收到匹配的启动信息
+fn wait_banner(port: &mut Box<dyn SerialPort>, pattern: &Regex, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 256];
+
+    while Instant::now() < deadline {
+        match port.read(&mut buffer) {
+            Ok(n) => received.extend_from_slice(&buffer[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e).context("等待启动信息时读取失败"),
+        }
+        if pattern.is_match(&String::from_utf8_lossy(&received)) {
+            log::debug!("收到启动信息: {}", String::from_utf8_lossy(&received));
+            events::status("设备已就绪");
+            return Ok(());
+        }
+    }
+    anyhow::bail!("复位后 {:?} 内未收到匹配 '{}' 的启动信息", timeout, pattern)
+}
@@ -0,0 +1,154 @@
+//! 端口解析：把命令行中的端口描述转换为实际端口名
+
+use anyhow::{bail, Context, Result};
+use serialport::{SerialPortInfo, SerialPortType};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// 等待端口出现时的轮询间隔
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// 解析端口描述
+///
+/// 支持的写法：
+/// - `COM3` / `/dev/ttyUSB0`：直接使用端口名
+/// - `usb:0403:6001`：按USB VID:PID匹配（十六进制）
+/// - `serial:A50285BI`：按USB序列号匹配
+/// - `auto`：仅有一个可用端口时自动选用
+/// - `USB-SERIAL CH340`：按设备管理器中的友好名称匹配
+/// - `rfc2217://主机:端口`、`tcp://主机:端口`：网络串口，原样使用
+///
+/// Windows 下 `com10`、`\\.\COM10` 等写法会统一成 `COM10`，
+/// 由 serialport 在打开时加上 `\\.\` 设备路径前缀，因此 COM10 以上的端口也能正常打开。
+pub fn resolve_port(spec: &str) -> Result<String> {
+    let ports = serialport::available_ports().context("枚举串口失败")?;
+
+    match find_port(spec, &ports)? {
+        Some(name) => Ok(name),
+        None if spec.eq_ignore_ascii_case("auto") => bail!("没有可用的串口"),
+        None => bail!("没有与 {} 匹配的端口！可用端口：{:?}", spec, port_names(&ports)),
+    }
+}
+
+/// 轮询直到端口出现；`timeout` 为 `None` 时一直等待
+pub fn wait_for_port(spec: &str, timeout: Option<Duration>) -> Result<String> {
+    let start = Instant::now();
+    let mut announced = false;
+
+    loop {
+        let ports = serialport::available_ports().context("枚举串口失败")?;
+        if let Some(name) = find_port(spec, &ports)? {
+            return Ok(name);
+        }
+
+        if !announced {
+            crate::events::status(&format!("等待端口 {} 出现...", spec));
+            announced = true;
+        }
+        if timeout.is_some_and(|t| start.elapsed() >= t) {
+            bail!("等待端口 {} 超时！可用端口：{:?}", spec, port_names(&ports));
+        }
+        thread::sleep(WAIT_POLL_INTERVAL);
+    }
+}
+
+/// 在端口列表中查找；`Ok(None)` 表示暂时没有匹配（可重试）
+fn find_port(spec: &str, ports: &[SerialPortInfo]) -> Result<Option<String>> {
+    // 网络串口不在本机的端口列表中，连接时再检查
+    if crate::netport::is_url(spec) {
+        return Ok(Some(spec.to_string()));
+    }
+    if spec.eq_ignore_ascii_case("auto") {
+        return match ports {
+            [one] => Ok(Some(one.port_name.clone())),
+            [] => Ok(None),
+            _ => bail!("存在多个可用端口，无法自动选择：{:?}", port_names(ports)),
+        };
+    }
+
+    if let Some(ids) = spec.strip_prefix("usb:") {
+        let (vid, pid) = parse_vid_pid(ids)?;
+        let matched: Vec<&SerialPortInfo> = ports
+            .iter()
+            .filter(|p| matches!(&p.port_type, SerialPortType::UsbPort(u) if u.vid == vid && u.pid == pid))
+            .collect();
+        return pick_one(spec, matched);
+    }
+
+    if let Some(serial) = spec.strip_prefix("serial:") {
+        let matched: Vec<&SerialPortInfo> = ports
+            .iter()
+            .filter(|p| {
+                matches!(&p.port_type, SerialPortType::UsbPort(u)
+                    if u.serial_number.as_deref().is_some_and(|s| s.eq_ignore_ascii_case(serial)))
+            })
+            .collect();
+        return pick_one(spec, matched);
+    }
+
+    let name = normalize_port_name(spec);
+    if let Some(p) = ports.iter().find(|p| p.port_name == name) {
+        return Ok(Some(p.port_name.clone()));
+    }
+
+    // 端口名匹配不上时按友好名称查找
+    let wanted = spec.strip_prefix("name:").unwrap_or(spec).to_lowercase();
+    let matched: Vec<&SerialPortInfo> = ports
+        .iter()
+        .filter(|p| friendly_name(p).is_some_and(|n| n.to_lowercase().contains(&wanted)))
+        .collect();
+    pick_one(spec, matched)
+}
+
+/// 规范化 Windows 端口名：去掉 `\\.\` 前缀并统一为大写
+fn normalize_port_name(spec: &str) -> String {
+    if !cfg!(windows) {
+        return spec.to_string();
+    }
+    let name = spec.strip_prefix(r"\\.\").unwrap_or(spec);
+    let is_com = name.get(..3).is_some_and(|p| p.eq_ignore_ascii_case("com"))
+        && name.len() > 3
+        && name[3..].bytes().all(|b| b.is_ascii_digit());
+    if is_com {
+        name.to_ascii_uppercase()
+    } else {
+        name.to_string()
+    }
+}
+
+/// 设备友好名称（去掉末尾的 "(COMx)"）
+fn friendly_name(port: &SerialPortInfo) -> Option<&str> {
+    let SerialPortType::UsbPort(usb) = &port.port_type else {
+        return None;
+    };
+    let product = usb.product.as_deref()?;
+    let suffix = format!("({})", port.port_name);
+    Some(product.strip_suffix(&suffix).unwrap_or(product).trim_end())
+}
+
+/// 解析 "0403:6001" 形式的 VID:PID
+fn parse_vid_pid(ids: &str) -> Result<(u16, u16)> {
+    let (vid, pid) = ids
+        .split_once(':')
+        .with_context(|| format!("无效的VID:PID格式: '{}'", ids))?;
+    let vid = u16::from_str_radix(vid, 16).with_context(|| format!("无效的VID: '{}'", vid))?;
+    let pid = u16::from_str_radix(pid, 16).with_context(|| format!("无效的PID: '{}'", pid))?;
+    Ok((vid, pid))
+}
+
+/// 从匹配结果中取唯一端口
+fn pick_one(spec: &str, matched: Vec<&SerialPortInfo>) -> Result<Option<String>> {
+    match matched.as_slice() {
+        [one] => Ok(Some(one.port_name.clone())),
+        [] => Ok(None),
+        many => bail!(
+            "有多个端口与 {} 匹配：{:?}，请改用 serial:<序列号> 指定",
+            spec,
+            many.iter().map(|p| p.port_name.as_str()).collect::<Vec<_>>()
+        ),
+    }
+}
+
+fn port_names(ports: &[SerialPortInfo]) -> Vec<&str> {
+    ports.iter().map(|p| p.port_name.as_str()).collect()
+}
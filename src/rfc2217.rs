@@ -0,0 +1,503 @@
+//! RFC 2217（Telnet COM-Port-Control）：经 Telnet 连接远程设置串口的波特率、数据位、校验位、
+//! 停止位、流控和 DTR/RTS/break，并上报 CTS/DSR/DCD/RI 的变化
+//!
+//! Telnet 数据中的 0xFF 写作 `IAC IAC`；选项协商为 `IAC WILL/WONT/DO/DONT 选项`；
+//! 串口控制命令为 `IAC SB 44 命令 参数 IAC SE`，服务端应答的命令号为客户端命令号加 100，
+//! 参数为设置后实际生效的值（参数为 0 表示只查询）。与 pyserial 的 rfc2217:// 兼容。
+
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+
+use crate::signal::ModemStatus;
+
+pub const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+// Telnet 选项
+pub const BINARY: u8 = 0;
+pub const SGA: u8 = 3;
+pub const COM_PORT_OPTION: u8 = 44;
+
+// 客户端命令（服务端应答为命令号加 SERVER）
+pub const SIGNATURE: u8 = 0;
+pub const SET_BAUDRATE: u8 = 1;
+pub const SET_DATASIZE: u8 = 2;
+pub const SET_PARITY: u8 = 3;
+pub const SET_STOPSIZE: u8 = 4;
+pub const SET_CONTROL: u8 = 5;
+pub const NOTIFY_LINESTATE: u8 = 6;
+pub const NOTIFY_MODEMSTATE: u8 = 7;
+pub const FLOWCONTROL_SUSPEND: u8 = 8;
+pub const FLOWCONTROL_RESUME: u8 = 9;
+pub const SET_LINESTATE_MASK: u8 = 10;
+pub const SET_MODEMSTATE_MASK: u8 = 11;
+pub const PURGE_DATA: u8 = 12;
+pub const SERVER: u8 = 100;
+
+// SET-CONTROL 的取值
+const FLOW_QUERY: u8 = 0;
+pub const FLOW_NONE: u8 = 1;
+pub const FLOW_XONXOFF: u8 = 2;
+pub const FLOW_HARDWARE: u8 = 3;
+const BREAK_QUERY: u8 = 4;
+pub const BREAK_ON: u8 = 5;
+pub const BREAK_OFF: u8 = 6;
+const DTR_QUERY: u8 = 7;
+pub const DTR_ON: u8 = 8;
+pub const DTR_OFF: u8 = 9;
+const RTS_QUERY: u8 = 10;
+pub const RTS_ON: u8 = 11;
+pub const RTS_OFF: u8 = 12;
+
+/// 解码后的 Telnet 内容
+pub enum Item {
+    /// 串口数据
+    Data(Vec<u8>),
+    /// COM-PORT-OPTION 命令和参数
+    Command(u8, Vec<u8>),
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Data,
+    Iac,
+    /// 收到 IAC 和协商命令，等待选项
+    Negotiate(u8),
+    Sub,
+    SubIac,
+}
+
+/// Telnet 解码和选项协商（只接受二进制传输、抑制继续和 COM-PORT-OPTION 三个选项）
+pub struct Telnet {
+    state: State,
+    sub: Vec<u8>,
+    /// 我方已启用或已请求启用的选项（发过 WILL）
+    local: Vec<u8>,
+    /// 已要求对方启用的选项（发过 DO）
+    remote: Vec<u8>,
+}
+
+impl Telnet {
+    pub fn new() -> Self {
+        Telnet { state: State::Data, sub: Vec::new(), local: Vec::new(), remote: Vec::new() }
+    }
+
+    fn supported(option: u8) -> bool {
+        matches!(option, BINARY | SGA | COM_PORT_OPTION)
+    }
+
+    /// 主动请求启用选项：双方都以二进制方式传输并启用 COM-PORT-OPTION
+    pub fn greeting(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for option in [BINARY, SGA, COM_PORT_OPTION] {
+            self.local.push(option);
+            out.extend([IAC, WILL, option]);
+        }
+        for option in [BINARY, SGA] {
+            self.remote.push(option);
+            out.extend([IAC, DO, option]);
+        }
+        out
+    }
+
+    /// 按 RFC 1143 的原则应答对方的协商：状态不变时不应答，避免来回协商
+    fn negotiate(&mut self, command: u8, option: u8, reply: &mut Vec<u8>) {
+        match command {
+            DO if !Self::supported(option) => reply.extend([IAC, WONT, option]),
+            DO if !self.local.contains(&option) => {
+                self.local.push(option);
+                reply.extend([IAC, WILL, option]);
+            }
+            WILL if !Self::supported(option) => reply.extend([IAC, DONT, option]),
+            WILL if !self.remote.contains(&option) => {
+                self.remote.push(option);
+                reply.extend([IAC, DO, option]);
+            }
+            DONT if self.local.contains(&option) => {
+                self.local.retain(|&o| o != option);
+                reply.extend([IAC, WONT, option]);
+            }
+            WONT if self.remote.contains(&option) => {
+                self.remote.retain(|&o| o != option);
+                reply.extend([IAC, DONT, option]);
+            }
+            _ => {}
+        }
+    }
+
+    /// 解码收到的字节（可以在任意位置截断，状态保留到下一次），协商的应答追加到 `reply`
+    pub fn decode(&mut self, data: &[u8], reply: &mut Vec<u8>) -> Vec<Item> {
+        let mut items = Vec::new();
+        let mut plain = Vec::new();
+        for &byte in data {
+            self.state = match (self.state, byte) {
+                (State::Data, IAC) => State::Iac,
+                (State::Data, _) => {
+                    plain.push(byte);
+                    State::Data
+                }
+                (State::Iac, IAC) => {
+                    plain.push(IAC);
+                    State::Data
+                }
+                (State::Iac, WILL | WONT | DO | DONT) => State::Negotiate(byte),
+                (State::Iac, SB) => {
+                    self.sub.clear();
+                    State::Sub
+                }
+                // NOP、AYT 等其他命令忽略
+                (State::Iac, _) => State::Data,
+                (State::Negotiate(command), _) => {
+                    self.negotiate(command, byte, reply);
+                    State::Data
+                }
+                (State::Sub, IAC) => State::SubIac,
+                (State::Sub, _) => {
+                    self.sub.push(byte);
+                    State::Sub
+                }
+                (State::SubIac, IAC) => {
+                    self.sub.push(IAC);
+                    State::Sub
+                }
+                (State::SubIac, SE) => {
+                    if let [COM_PORT_OPTION, command, payload @ ..] = self.sub.as_slice() {
+                        if !plain.is_empty() {
+                            items.push(Item::Data(std::mem::take(&mut plain)));
+                        }
+                        items.push(Item::Command(*command, payload.to_vec()));
+                    }
+                    State::Data
+                }
+                // 不完整的子协商，丢弃
+                (State::SubIac, _) => State::Data,
+            };
+        }
+        if !plain.is_empty() {
+            items.push(Item::Data(plain));
+        }
+        items
+    }
+}
+
+/// 发送前把数据中的 0xFF 转义为 IAC IAC
+pub fn escape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 8);
+    for &byte in data {
+        if byte == IAC {
+            out.push(IAC);
+        }
+        out.push(byte);
+    }
+    out
+}
+
+/// 编码一条 COM-PORT-OPTION 命令
+pub fn subnegotiation(command: u8, payload: &[u8]) -> Vec<u8> {
+    let mut out = vec![IAC, SB, COM_PORT_OPTION, command];
+    out.extend(escape(payload));
+    out.extend([IAC, SE]);
+    out
+}
+
+/// 服务端：按客户端的命令设置本地串口，并在输入状态线变化时通知客户端
+pub struct PortControl {
+    /// DTR/RTS 只能写不能读，记录最近设置的电平（打开串口后默认有效）
+    dtr: bool,
+    rts: bool,
+    brk: bool,
+    linestate_mask: u8,
+    /// 默认上报所有输入线的变化
+    modemstate_mask: u8,
+    last_modem: Option<u8>,
+    /// 客户端要求暂停发送串口数据（FLOWCONTROL-SUSPEND）
+    pub suspended: bool,
+}
+
+impl PortControl {
+    pub fn new() -> Self {
+        PortControl { dtr: true, rts: true, brk: false, linestate_mask: 0, modemstate_mask: 0xFF, last_modem: None, suspended: false }
+    }
+
+    /// 执行一条客户端命令，返回要发给客户端的应答和（设置了串口时）给用户看的说明
+    pub fn apply(&mut self, port: &mut Box<dyn SerialPort>, command: u8, payload: &[u8]) -> (Vec<u8>, Option<String>) {
+        let value = payload.first().copied().unwrap_or(0);
+        let mut note = None;
+        let reply: Vec<u8> = match command {
+            SIGNATURE => match payload.is_empty() {
+                true => concat!("serial-tool ", env!("CARGO_PKG_VERSION")).as_bytes().to_vec(),
+                // 客户端告知它的名称，不需要应答
+                false => {
+                    note = Some(format!("客户端软件：{}", String::from_utf8_lossy(payload)));
+                    return (Vec::new(), note);
+                }
+            },
+            SET_BAUDRATE => {
+                let baud = match payload {
+                    [a, b, c, d, ..] => u32::from_be_bytes([*a, *b, *c, *d]),
+                    _ => 0,
+                };
+                let result = match baud {
+                    0 => None,
+                    _ => Some(port.set_baud_rate(baud).map_err(|e| e.to_string())),
+                };
+                let actual = port.baud_rate().unwrap_or(0);
+                if let Some(result) = result {
+                    note = Some(outcome("波特率", baud, actual, result));
+                }
+                actual.to_be_bytes().to_vec()
+            }
+            SET_DATASIZE => {
+                let result = match value {
+                    0 => None,
+                    _ => Some(match DataBits::try_from(value) {
+                        Ok(bits) => port.set_data_bits(bits).map_err(|e| e.to_string()),
+                        Err(_) => Err("不支持".to_string()),
+                    }),
+                };
+                let actual = port.data_bits().map_or(0, u8::from);
+                if let Some(result) = result {
+                    note = Some(outcome("数据位", value, actual, result));
+                }
+                vec![actual]
+            }
+            SET_PARITY => {
+                // MARK/SPACE 校验不支持
+                let result = match value {
+                    0 => None,
+                    1 => Some(port.set_parity(Parity::None).map_err(|e| e.to_string())),
+                    2 => Some(port.set_parity(Parity::Odd).map_err(|e| e.to_string())),
+                    3 => Some(port.set_parity(Parity::Even).map_err(|e| e.to_string())),
+                    _ => Some(Err("不支持".to_string())),
+                };
+                let actual = match port.parity() {
+                    Ok(Parity::None) => 1,
+                    Ok(Parity::Odd) => 2,
+                    Ok(Parity::Even) => 3,
+                    Err(_) => 0,
+                };
+                let name = |value: u8| ["?", "NONE", "ODD", "EVEN", "MARK", "SPACE"].get(value as usize).copied().unwrap_or("?");
+                if let Some(result) = result {
+                    note = Some(outcome("校验位", name(value), name(actual), result));
+                }
+                vec![actual]
+            }
+            SET_STOPSIZE => {
+                // 取值 3 为 1.5 位停止位，不支持
+                let result = match value {
+                    0 => None,
+                    _ => Some(match StopBits::try_from(value) {
+                        Ok(bits) => port.set_stop_bits(bits).map_err(|e| e.to_string()),
+                        Err(_) => Err("不支持".to_string()),
+                    }),
+                };
+                let actual = port.stop_bits().map_or(0, u8::from);
+                if let Some(result) = result {
+                    note = Some(outcome("停止位", value, actual, result));
+                }
+                vec![actual]
+            }
+            SET_CONTROL => vec![self.control(port, value, &mut note)],
+            // 不上报线路错误，线路状态总为 0
+            NOTIFY_LINESTATE => vec![0],
+            NOTIFY_MODEMSTATE => {
+                self.last_modem = None;
+                return (self.poll_modem(port).unwrap_or_default(), None);
+            }
+            FLOWCONTROL_SUSPEND | FLOWCONTROL_RESUME => {
+                self.suspended = command == FLOWCONTROL_SUSPEND;
+                Vec::new()
+            }
+            SET_LINESTATE_MASK => {
+                self.linestate_mask = value;
+                vec![self.linestate_mask]
+            }
+            SET_MODEMSTATE_MASK => {
+                self.modemstate_mask = value;
+                vec![self.modemstate_mask]
+            }
+            PURGE_DATA => {
+                let buffer = match value {
+                    1 => Some(ClearBuffer::Input),
+                    2 => Some(ClearBuffer::Output),
+                    3 => Some(ClearBuffer::All),
+                    _ => None,
+                };
+                if let Some(buffer) = buffer {
+                    let _ = port.clear(buffer);
+                }
+                vec![value]
+            }
+            _ => return (Vec::new(), Some(format!("忽略未知的串口控制命令 {}", command))),
+        };
+        (subnegotiation(command + SERVER, &reply), note)
+    }
+
+    /// SET-CONTROL：流控、break、DTR 和 RTS，返回设置后（或查询到）的取值
+    fn control(&mut self, port: &mut Box<dyn SerialPort>, value: u8, note: &mut Option<String>) -> u8 {
+        let mut set = |what: &str, result: serialport::Result<()>| {
+            *note = Some(match result {
+                Ok(()) => what.to_string(),
+                Err(e) => format!("无法{}: {}", what, e),
+            })
+        };
+        match value {
+            FLOW_NONE => set("关闭流控", port.set_flow_control(FlowControl::None)),
+            FLOW_XONXOFF => set("流控设为 XON/XOFF", port.set_flow_control(FlowControl::Software)),
+            FLOW_HARDWARE => set("流控设为 RTS/CTS", port.set_flow_control(FlowControl::Hardware)),
+            BREAK_ON | BREAK_OFF => {
+                self.brk = value == BREAK_ON;
+                set(if self.brk { "开始 break" } else { "结束 break" }, if self.brk { port.set_break() } else { port.clear_break() })
+            }
+            DTR_ON | DTR_OFF => {
+                self.dtr = value == DTR_ON;
+                set(if self.dtr { "置位 DTR" } else { "复位 DTR" }, port.write_data_terminal_ready(self.dtr))
+            }
+            RTS_ON | RTS_OFF => {
+                self.rts = value == RTS_ON;
+                set(if self.rts { "置位 RTS" } else { "复位 RTS" }, port.write_request_to_send(self.rts))
+            }
+            _ => {}
+        }
+        match value {
+            FLOW_QUERY..=FLOW_HARDWARE => match port.flow_control() {
+                Ok(FlowControl::Software) => FLOW_XONXOFF,
+                Ok(FlowControl::Hardware) => FLOW_HARDWARE,
+                _ => FLOW_NONE,
+            },
+            BREAK_QUERY..=BREAK_OFF => if self.brk { BREAK_ON } else { BREAK_OFF },
+            DTR_QUERY..=DTR_OFF => if self.dtr { DTR_ON } else { DTR_OFF },
+            RTS_QUERY..=RTS_OFF => if self.rts { RTS_ON } else { RTS_OFF },
+            // 入站流控等不支持的项原样应答
+            _ => value,
+        }
+    }
+
+    /// 读取输入状态线，与上次不同且在上报掩码内时返回 NOTIFY-MODEMSTATE 通知
+    pub fn poll_modem(&mut self, port: &mut Box<dyn SerialPort>) -> Option<Vec<u8>> {
+        let status = ModemStatus::read(port);
+        let mut state = 0u8;
+        for (level, bit) in [(status.cts, 0x10), (status.dsr, 0x20), (status.ri, 0x40), (status.cd, 0x80)] {
+            if level == Some(true) {
+                state |= bit;
+            }
+        }
+        let first = self.last_modem.is_none();
+        let last = self.last_modem.replace(state).unwrap_or(state);
+        // 低 4 位为变化标志：CTS、DSR 变化，RI 结束，DCD 变化
+        let changed = state ^ last;
+        let mut delta = 0u8;
+        for (mask, bit) in [(0x10, 0x01), (0x20, 0x02), (0x80, 0x08)] {
+            if changed & mask != 0 {
+                delta |= bit;
+            }
+        }
+        if last & 0x40 != 0 && state & 0x40 == 0 {
+            delta |= 0x04;
+        }
+        let notify = state | delta;
+        match first || (delta != 0 && notify & self.modemstate_mask != 0) {
+            true => Some(subnegotiation(NOTIFY_MODEMSTATE + SERVER, &[notify & self.modemstate_mask])),
+            false => None,
+        }
+    }
+}
+
+/// 设置串口参数的结果说明：驱动可能接受设置但实际没有生效，以读回的值为准
+fn outcome<T: PartialEq + std::fmt::Display>(what: &str, requested: T, actual: T, result: Result<(), String>) -> String {
+    match result {
+        Err(e) => format!("无法把{}设为 {}: {}", what, requested, e),
+        Ok(()) if requested == actual => format!("{}设为 {}", what, requested),
+        Ok(()) => format!("{}设为 {} 没有生效，当前为 {}", what, requested, actual),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xmodem::tests::pipe;
+
+    /// 命令为 (Some(命令), 参数)，串口数据为 (None, 数据)
+    type Items = Vec<(Option<u8>, Vec<u8>)>;
+
+    /// 解码依次收到的各段数据，相邻的串口数据合并，返回内容和协商的应答
+    fn decode_chunks(chunks: &[&[u8]]) -> (Items, Vec<u8>) {
+        let mut telnet = Telnet::new();
+        let mut reply = Vec::new();
+        let mut items = Items::new();
+        for chunk in chunks {
+            for item in telnet.decode(chunk, &mut reply) {
+                match (item, items.last_mut()) {
+                    (Item::Data(data), Some((None, last))) => last.extend(data),
+                    (Item::Data(data), _) => items.push((None, data)),
+                    (Item::Command(command, payload), _) => items.push((Some(command), payload)),
+                }
+            }
+        }
+        (items, reply)
+    }
+
+    #[test]
+    fn telnet_decoding() {
+        // 数据中的 IAC IAC、DO BINARY、SET-BAUDRATE 115200、不支持的 WILL 99、NOP、
+        // 其他选项的子协商、参数中转义的 0xFF
+        let stream = b"ab\xff\xffc\xff\xfd\x00\xff\xfa\x2c\x01\x00\x01\xc2\x00\xff\xf0\xff\xfb\x63\xff\xf1\
+            \xff\xfa\x18\x01\xff\xf0\xff\xfa\x2c\x05\xff\xff\xff\xf0d\xff\xff";
+        let expected = vec![
+            (None, b"ab\xffc".to_vec()),
+            (Some(SET_BAUDRATE), vec![0x00, 0x01, 0xC2, 0x00]),
+            (Some(SET_CONTROL), vec![0xFF]),
+            (None, b"d\xff".to_vec()),
+        ];
+        let reply = [IAC, WILL, BINARY, IAC, DONT, 0x63];
+        assert_eq!(decode_chunks(&[stream]), (expected.clone(), reply.to_vec()));
+        // 在任意位置截断（包括 IAC 与后续字节之间、SB 与 SE 之间）结果相同
+        for split in 1..stream.len() {
+            assert_eq!(decode_chunks(&[&stream[..split], &stream[split..]]), (expected.clone(), reply.to_vec()), "在 {} 处截断", split);
+        }
+        let bytes: Vec<&[u8]> = stream.chunks(1).collect();
+        assert_eq!(decode_chunks(&bytes), (expected, reply.to_vec()));
+
+        // 已启用的选项不再应答；缺少 SE 的子协商被丢弃
+        let mut telnet = Telnet::new();
+        let mut reply = telnet.greeting();
+        assert_eq!(reply, [IAC, WILL, BINARY, IAC, WILL, SGA, IAC, WILL, COM_PORT_OPTION, IAC, DO, BINARY, IAC, DO, SGA]);
+        reply.clear();
+        telnet.decode(&[IAC, DO, COM_PORT_OPTION, IAC, WILL, SGA, IAC, DONT, SGA], &mut reply);
+        assert_eq!(reply, [IAC, WONT, SGA]);
+        assert!(telnet.decode(b"\xff\xfa\x2c\x01\x00\xffx", &mut reply).is_empty());
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(escape(b"a\xffb\xff\xff"), b"a\xff\xffb\xff\xff\xff\xff");
+        // SET-BAUDRATE 为 4 字节大端序，参数中的 0xFF 同样转义
+        assert_eq!(subnegotiation(SET_BAUDRATE, &115200u32.to_be_bytes()), [IAC, SB, 44, 1, 0x00, 0x01, 0xC2, 0x00, IAC, SE]);
+        assert_eq!(subnegotiation(SET_BAUDRATE, &255u32.to_be_bytes()), [IAC, SB, 44, 1, 0, 0, 0, IAC, IAC, IAC, SE]);
+        assert_eq!(subnegotiation(SET_CONTROL + SERVER, &[DTR_ON]), [IAC, SB, 44, 105, 8, IAC, SE]);
+    }
+
+    #[test]
+    fn port_control() {
+        let (mut port, _peer) = pipe();
+        let mut control = PortControl::new();
+        // 应答实际生效的值：测试串口的波特率总为 115200
+        let (reply, note) = control.apply(&mut port, SET_BAUDRATE, &9600u32.to_be_bytes());
+        assert_eq!(reply, subnegotiation(SET_BAUDRATE + SERVER, &115200u32.to_be_bytes()));
+        assert_eq!(note.as_deref(), Some("波特率设为 9600 没有生效，当前为 115200"));
+        // 参数为 0 只查询，不提示
+        assert_eq!(control.apply(&mut port, SET_BAUDRATE, &[0; 4]), (subnegotiation(101, &115200u32.to_be_bytes()), None));
+        assert_eq!(control.apply(&mut port, SET_PARITY, &[3]).0, subnegotiation(103, &[1]));
+        assert_eq!(control.apply(&mut port, SET_CONTROL, &[DTR_OFF]).0, subnegotiation(105, &[DTR_OFF]));
+        assert_eq!(control.apply(&mut port, SET_CONTROL, &[DTR_QUERY]).0, subnegotiation(105, &[DTR_OFF]));
+        assert_eq!(control.apply(&mut port, FLOWCONTROL_SUSPEND, &[]), (subnegotiation(108, &[]), None));
+        assert!(control.suspended);
+        // CTS、DSR、DCD 有效：首次查询总是通知，之后没有变化时不通知
+        assert_eq!(control.apply(&mut port, NOTIFY_MODEMSTATE, &[]).0, subnegotiation(107, &[0xB0]));
+        assert_eq!(control.poll_modem(&mut port), None);
+    }
+}
@@ -0,0 +1,155 @@
+//! 控制线操作：设置、清除或脉冲 DTR/RTS（复位开发板、进入 bootloader、给传感器供电等），
+//! RS-485 收发方向控制，以及读取 CTS/DSR/CD/RI 输入状态线
+
+use anyhow::{Context, Result};
+use serialport::SerialPort;
+use std::thread;
+use std::time::Duration;
+
+use crate::events;
+use crate::parse_duration;
+
+/// signal 子命令参数
+#[derive(clap::Args, Debug)]
+#[command(group(clap::ArgGroup::new("line").required(true).multiple(true).args(["dtr", "rts"])))]
+pub struct SignalArgs {
+    /// DTR 操作：on、off 或 pulse:<时长>（置位保持指定时长后复位）
+    #[arg(long, value_name = "ACTION", value_parser = parse_action)]
+    pub dtr: Option<LineAction>,
+
+    /// RTS 操作：on、off 或 pulse:<时长>
+    #[arg(long, value_name = "ACTION", value_parser = parse_action)]
+    pub rts: Option<LineAction>,
+
+    /// 设置完成后保持串口打开的时长（部分系统关闭串口时会复位控制线）
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub hold: Option<Duration>,
+}
+
+/// 对一条控制线的操作
+#[derive(Debug, Clone, Copy)]
+pub enum LineAction {
+    On,
+    Off,
+    /// 置位，保持指定时长后复位
+    Pulse(Duration),
+}
+
+pub fn parse_action(s: &str) -> std::result::Result<LineAction, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "on" | "1" | "high" => Ok(LineAction::On),
+        "off" | "0" | "low" => Ok(LineAction::Off),
+        other => match other.strip_prefix("pulse:") {
+            Some(duration) => parse_duration(duration).map(LineAction::Pulse),
+            None => Err(format!("无效的控制线操作 '{}'，应为 on、off 或 pulse:<时长>", s)),
+        },
+    }
+}
+
+/// 控制线
+#[derive(Debug, Clone, Copy)]
+enum Line {
+    Dtr,
+    Rts,
+}
+
+impl Line {
+    fn name(self) -> &'static str {
+        match self {
+            Line::Dtr => "DTR",
+            Line::Rts => "RTS",
+        }
+    }
+
+    fn set(self, port: &mut Box<dyn SerialPort>, level: bool) -> Result<()> {
+        match self {
+            Line::Dtr => port.write_data_terminal_ready(level),
+            Line::Rts => port.write_request_to_send(level),
+        }
+        .with_context(|| format!("设置 {} 失败", self.name()))
+    }
+}
+
+/// 执行 signal 子命令：先同时设置各控制线，脉冲按时长从短到长依次复位
+pub fn run_signal(port: &mut Box<dyn SerialPort>, opts: &SignalArgs) -> Result<()> {
+    let mut pulses = Vec::new();
+    for (line, action) in [(Line::Dtr, opts.dtr), (Line::Rts, opts.rts)] {
+        let Some(action) = action else { continue };
+        let level = !matches!(action, LineAction::Off);
+        line.set(port, level)?;
+        events::status(&format!("{} 已{}", line.name(), if level { "置位" } else { "复位" }));
+        if let LineAction::Pulse(duration) = action {
+            pulses.push((duration, line));
+        }
+    }
+
+    pulses.sort_by_key(|&(duration, _)| duration);
+    let mut elapsed = Duration::ZERO;
+    for (duration, line) in pulses {
+        thread::sleep(duration - elapsed);
+        elapsed = duration;
+        line.set(port, false)?;
+        events::status(&format!("{} 已复位（脉冲 {:?}）", line.name(), duration));
+    }
+
+    if let Some(hold) = opts.hold {
+        thread::sleep(hold);
+    }
+    Ok(())
+}
+
+/// 输入状态线（CTS/DSR/CD/RI）的电平，读取失败（如虚拟串口不支持）时为 `None`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ModemStatus {
+    pub cts: Option<bool>,
+    pub dsr: Option<bool>,
+    pub cd: Option<bool>,
+    pub ri: Option<bool>,
+}
+
+impl ModemStatus {
+    pub fn read(port: &mut Box<dyn SerialPort>) -> Self {
+        ModemStatus {
+            cts: port.read_clear_to_send().ok(),
+            dsr: port.read_data_set_ready().ok(),
+            cd: port.read_carrier_detect().ok(),
+            ri: port.read_ring_indicator().ok(),
+        }
+    }
+
+    /// 按名称列出各条线的电平
+    pub fn lines(&self) -> [(&'static str, Option<bool>); 4] {
+        [("CTS", self.cts), ("DSR", self.dsr), ("DCD", self.cd), ("RI", self.ri)]
+    }
+}
+
+/// RS-485 收发方向控制（用于没有自动换向的转换器）：发送前置位 RTS，数据发完后复位
+#[derive(Debug, Clone, Copy)]
+pub struct Rs485 {
+    /// 置位 RTS 后到开始发送的等待时间
+    pub pre_delay: Duration,
+    /// 数据发完后到复位 RTS 的等待时间
+    pub post_delay: Duration,
+}
+
+impl Rs485 {
+    /// 切换到接收方向（复位 RTS）
+    pub fn receive(&self, port: &mut Box<dyn SerialPort>) -> Result<()> {
+        port.write_request_to_send(false).context("设置 RTS 失败")
+    }
+
+    /// 切换到发送方向后执行 `write`，等数据完全发出后切回接收方向
+    pub fn transmit(
+        &self,
+        port: &mut Box<dyn SerialPort>,
+        write: impl FnOnce(&mut Box<dyn SerialPort>) -> Result<()>,
+    ) -> Result<()> {
+        port.write_request_to_send(true).context("设置 RTS 失败")?;
+        thread::sleep(self.pre_delay);
+        let result = write(port).and_then(|()| port.flush().context("刷新缓冲区失败"));
+        thread::sleep(self.post_delay);
+        // 写入失败时也要释放总线
+        self.receive(port)?;
+        result
+    }
+}
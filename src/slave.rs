@@ -0,0 +1,364 @@
+//! Modbus 从站模拟（RTU 或 ASCII）：按映射文件（TOML）提供线圈和寄存器，记录每个请求和响应，
+//! 运行中可从标准输入用 set/get 命令修改或查看数据
+//!
+//! 映射文件示例：
+//!
+//! ```toml
+//! slave = 1          # 从站地址
+//!
+//! [holding]          # 保持寄存器：地址 = 值，数组表示从该地址起的连续值
+//! 0 = 100
+//! 10 = [1, 2, 0xFFFF]
+//!
+//! [coils]            # 线圈（true/false 或 0/1）
+//! 0 = true
+//! ```
+//!
+//! 另有 `[discrete]`（离散输入）和 `[input]`（输入寄存器）；未列出的地址返回非法数据地址异常。
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+use crate::config::{Config, Value};
+use crate::events::{self, Event};
+use crate::format_hex;
+use crate::framing::{Deframer, Frame, FrameSpec};
+use crate::modbus::{self, Mode, ValueFormat};
+use crate::signal::Rs485;
+use crate::terminal;
+
+/// modbus slave 参数
+#[derive(clap::Args, Debug)]
+pub struct SlaveArgs {
+    /// 从站地址（默认取映射文件中的 slave，都未指定时为 1）
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=247))]
+    pub slave: Option<u8>,
+
+    /// 线圈和寄存器映射文件（TOML）；不指定时全部地址都可读写，初值为 0
+    #[arg(long, value_name = "PATH")]
+    pub map: Option<PathBuf>,
+
+    /// 传输模式
+    #[arg(long, value_enum, default_value_t = Mode::Rtu)]
+    pub mode: Mode,
+}
+
+/// 数据区
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Area {
+    Coils,
+    Discrete,
+    Holding,
+    Input,
+}
+
+impl Area {
+    const ALL: [Area; 4] = [Area::Coils, Area::Discrete, Area::Holding, Area::Input];
+
+    /// 映射文件中的表名，也用于 set/get 命令
+    fn name(self) -> &'static str {
+        match self {
+            Area::Coils => "coils",
+            Area::Discrete => "discrete",
+            Area::Holding => "holding",
+            Area::Input => "input",
+        }
+    }
+
+    fn is_bit(self) -> bool {
+        matches!(self, Area::Coils | Area::Discrete)
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        Area::ALL
+            .into_iter()
+            .find(|a| a.name() == s)
+            .with_context(|| format!("未知的数据区 '{}'，应为 coils/discrete/holding/input", s))
+    }
+}
+
+/// 从站的线圈和寄存器
+struct DataMap {
+    slave: Option<u8>,
+    areas: [BTreeMap<u16, u16>; 4],
+    /// 未指定映射文件时所有地址都存在，初值为 0
+    open: bool,
+}
+
+impl DataMap {
+    fn open() -> Self {
+        DataMap { slave: None, areas: Default::default(), open: true }
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let config = Config::load(Some(path))?;
+        let context = || format!("映射文件 {} 有误", path.display());
+        let mut map = DataMap { slave: None, areas: Default::default(), open: false };
+
+        if let Some(top) = config.table("") {
+            map.slave = match top.int("slave").with_context(context)? {
+                Some(n @ 1..=247) => Some(n as u8),
+                Some(n) => bail!("{}：从站地址 {} 应在 1～247 之间", context(), n),
+                None => None,
+            };
+        }
+        for area in Area::ALL {
+            let Some(table) = config.table(area.name()) else { continue };
+            for (key, value) in table.entries() {
+                let start: u16 = key.parse().with_context(|| format!("{}：[{}] 中的地址 '{}' 无效", context(), area.name(), key))?;
+                let values = match value {
+                    Value::Array(items) => items.iter().collect(),
+                    value => vec![value],
+                };
+                for (i, value) in values.into_iter().enumerate() {
+                    let addr = u16::try_from(start as usize + i).with_context(|| format!("{}：[{}] {} 起的数组超出地址范围", context(), area.name(), key))?;
+                    let value = map_value(area, value).with_context(|| format!("{}：[{}] 地址 {}", context(), area.name(), addr))?;
+                    map.areas[area as usize].insert(addr, value);
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    fn get(&self, area: Area, addr: u16) -> Option<u16> {
+        match self.areas[area as usize].get(&addr) {
+            Some(&value) => Some(value),
+            None => self.open.then_some(0),
+        }
+    }
+
+    /// 读取连续的一段，有地址不存在时返回 `None`
+    fn range(&self, area: Area, start: u16, count: u16) -> Option<Vec<u16>> {
+        let end = start.checked_add(count.checked_sub(1)?)?;
+        (start..=end).map(|addr| self.get(area, addr)).collect()
+    }
+
+    /// 写入连续的一段；有地址不存在时不写入任何值并返回 `false`
+    fn set_range(&mut self, area: Area, start: u16, values: &[u16]) -> bool {
+        if self.range(area, start, values.len() as u16).is_none() {
+            return false;
+        }
+        for (addr, &value) in (start..).zip(values) {
+            self.areas[area as usize].insert(addr, value);
+        }
+        true
+    }
+
+    /// 处理请求 PDU，返回响应 PDU 或异常码
+    fn handle(&mut self, pdu: &[u8]) -> std::result::Result<Vec<u8>, u8> {
+        const ILLEGAL_FUNCTION: u8 = 0x01;
+        const ILLEGAL_ADDRESS: u8 = 0x02;
+        const ILLEGAL_VALUE: u8 = 0x03;
+
+        let (function, addr, count) = match pdu {
+            [function, a, b, c, d, ..] => (*function, u16::from_be_bytes([*a, *b]), u16::from_be_bytes([*c, *d])),
+            [0x01..=0x06 | 0x0F | 0x10, ..] => return Err(ILLEGAL_VALUE),
+            _ => return Err(ILLEGAL_FUNCTION),
+        };
+        match function {
+            0x01..=0x04 => {
+                let area = [Area::Coils, Area::Discrete, Area::Holding, Area::Input][function as usize - 1];
+                let limit = if area.is_bit() { 2000 } else { 125 };
+                if count == 0 || count > limit {
+                    return Err(ILLEGAL_VALUE);
+                }
+                let values = self.range(area, addr, count).ok_or(ILLEGAL_ADDRESS)?;
+                let data = match area.is_bit() {
+                    true => pack_bits(&values),
+                    false => values.iter().flat_map(|v| v.to_be_bytes()).collect(),
+                };
+                let mut response = vec![function, data.len() as u8];
+                response.extend_from_slice(&data);
+                Ok(response)
+            }
+            0x05 => {
+                let value = match count {
+                    0xFF00 => 1,
+                    0x0000 => 0,
+                    _ => return Err(ILLEGAL_VALUE),
+                };
+                self.set_range(Area::Coils, addr, &[value]).then(|| pdu[..5].to_vec()).ok_or(ILLEGAL_ADDRESS)
+            }
+            0x06 => self.set_range(Area::Holding, addr, &[count]).then(|| pdu[..5].to_vec()).ok_or(ILLEGAL_ADDRESS),
+            0x0F | 0x10 => {
+                let (area, limit, size) = match function {
+                    0x0F => (Area::Coils, 1968, (count as usize).div_ceil(8)),
+                    _ => (Area::Holding, 123, count as usize * 2),
+                };
+                let data = match pdu.get(5..) {
+                    Some([byte_count, data @ ..]) if *byte_count as usize == size && data.len() == size => data,
+                    _ => return Err(ILLEGAL_VALUE),
+                };
+                if count == 0 || count > limit {
+                    return Err(ILLEGAL_VALUE);
+                }
+                let values: Vec<u16> = match area {
+                    Area::Coils => (0..count as usize).map(|i| (data[i / 8] >> (i % 8) & 1) as u16).collect(),
+                    _ => data.chunks(2).map(|w| u16::from_be_bytes([w[0], w[1]])).collect(),
+                };
+                self.set_range(area, addr, &values).then(|| pdu[..5].to_vec()).ok_or(ILLEGAL_ADDRESS)
+            }
+            _ => Err(ILLEGAL_FUNCTION),
+        }
+    }
+
+    /// 执行标准输入中的一条命令，返回要显示的结果
+    fn command(&mut self, line: &str) -> Result<String> {
+        const USAGE: &str = "用法：set <coils|discrete|holding|input> <地址> <值...> 或 get <数据区> <地址> [数量]";
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["set", area, addr, values @ ..] if !values.is_empty() => {
+                let area = Area::parse(area)?;
+                let addr = parse_addr(addr)?;
+                let values = values
+                    .iter()
+                    .map(|v| match area.is_bit() {
+                        true => modbus::parse_coil(v).map(u16::from),
+                        false => modbus::parse_register(v, ValueFormat::Uint16, false).map(|words| words[0]),
+                    })
+                    .collect::<Result<Vec<u16>>>()?;
+                if values.len() > u16::MAX as usize || !self.set_range(area, addr, &values) {
+                    bail!("{} 地址 {} 起的 {} 个值超出映射范围", area.name(), addr, values.len());
+                }
+                Ok(format!("已设置 {} 地址 {} 起的 {} 个值", area.name(), addr, values.len()))
+            }
+            ["get", area, addr, rest @ ..] if rest.len() <= 1 => {
+                let area = Area::parse(area)?;
+                let addr = parse_addr(addr)?;
+                let count = match rest.first() {
+                    Some(count) => count.parse().ok().filter(|&n| n > 0).with_context(|| format!("无效的数量 '{}'", count))?,
+                    None => 1,
+                };
+                let values = self
+                    .range(area, addr, count)
+                    .with_context(|| format!("{} 地址 {} 起的 {} 个值超出映射范围", area.name(), addr, count))?;
+                let values: Vec<String> = values.iter().map(u16::to_string).collect();
+                Ok(format!("{} {}: {}", area.name(), addr, values.join(" ")))
+            }
+            _ => bail!(USAGE),
+        }
+    }
+}
+
+/// 映射文件中的值转为数据区中的值
+fn map_value(area: Area, value: &Value) -> Result<u16> {
+    match (value, area.is_bit()) {
+        (Value::Bool(b), true) => Ok(*b as u16),
+        (Value::Int(n @ (0 | 1)), true) => Ok(*n as u16),
+        (Value::Int(n @ -32768..=65535), false) => Ok(*n as u16),
+        (_, true) => bail!("线圈和离散输入的值应为 true/false 或 0/1"),
+        (_, false) => bail!("寄存器的值应为 -32768～65535 的整数"),
+    }
+}
+
+fn parse_addr(s: &str) -> Result<u16> {
+    s.parse().with_context(|| format!("无效的地址 '{}'", s))
+}
+
+/// 按 Modbus 的顺序把位打包成字节（第一个值在第一个字节的最低位）
+fn pack_bits(bits: &[u16]) -> Vec<u8> {
+    let mut packed = vec![0u8; bits.len().div_ceil(8)];
+    for (i, _) in bits.iter().enumerate().filter(|(_, &bit)| bit != 0) {
+        packed[i / 8] |= 1 << (i % 8);
+    }
+    packed
+}
+
+/// 后台逐行读取标准输入中的命令
+fn spawn_commands() -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// 运行从站模拟，直到出错或被中断
+pub fn run_slave(port: &mut Box<dyn SerialPort>, opts: &SlaveArgs, rs485: Option<Rs485>) -> Result<()> {
+    let mut map = match &opts.map {
+        Some(path) => DataMap::load(path)?,
+        None => DataMap::open(),
+    };
+    let slave = opts.slave.or(map.slave).unwrap_or(1);
+    let mut deframer = Deframer::new(match opts.mode {
+        Mode::Rtu => FrameSpec::ModbusRtu { gap: None },
+        Mode::Ascii => FrameSpec::ModbusAscii,
+    });
+    if let Ok(baud) = port.baud_rate() {
+        deframer.set_baud(baud);
+    }
+    let commands = spawn_commands();
+    events::status(&format!("Modbus 从站 {} 已启动（按 Ctrl+C 退出），可输入 set/get 命令修改或查看数据", slave));
+
+    let mut buffer = [0u8; modbus::MAX_ADU];
+    loop {
+        while let Ok(line) = commands.try_recv() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match map.command(&line) {
+                Ok(message) => events::status(&message),
+                Err(e) => events::status(&format!("{:#}", e)),
+            }
+        }
+
+        let frames = match port.read(&mut buffer) {
+            Ok(n) => deframer.push(&buffer[..n], Instant::now()),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => deframer.poll_idle(Instant::now()),
+            Err(e) => return Err(e).context("读取串口失败"),
+        };
+        for frame in frames {
+            serve(port, rs485, opts.mode, slave, &mut map, &frame)?;
+        }
+    }
+}
+
+/// 响应一个请求帧
+fn serve(
+    port: &mut Box<dyn SerialPort>,
+    rs485: Option<Rs485>,
+    mode: Mode,
+    slave: u8,
+    map: &mut DataMap,
+    frame: &Frame,
+) -> Result<()> {
+    let request = &frame.data;
+    events::emit(Event::Rx(request));
+    if let Some(error) = &frame.error {
+        events::status(&format!("请求 {} [{}]，已忽略", format_hex(request), error));
+        return Ok(());
+    }
+    events::status(&format!("请求 {} [{}]", format_hex(request), modbus::describe(request)));
+    let address = request[0];
+    if address != slave && address != 0 {
+        events::status("  不是本站地址，不响应");
+        return Ok(());
+    }
+
+    let pdu = &request[1..request.len() - mode.check_size()];
+    let mut response = vec![slave];
+    match map.handle(pdu) {
+        Ok(data) => response.extend_from_slice(&data),
+        Err(code) => response.extend_from_slice(&[pdu[0] | 0x80, code]),
+    }
+    // 广播请求只执行，不响应
+    if address == 0 {
+        events::status("  广播请求，不响应");
+        return Ok(());
+    }
+    terminal::transmit(port, rs485, &mode.encode(&response))?;
+    let check = mode.check(&response);
+    events::status(&format!("响应 {} [{}]", format_hex(&[&response[..], &check].concat()), modbus::describe(&response)));
+    Ok(())
+}
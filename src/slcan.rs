@@ -0,0 +1,309 @@
+//! SLCAN（Lawicel 协议）USB-CAN 适配器：设置波特率、打开通道，发送 CAN 帧并解码显示收到的帧
+//!
+//! 帧的写法与 can-utils 的 cansend 相同：`123#DEADBEEF`（标准帧）、`12345678#00`（8 位 ID 为扩展帧）、
+//! `123#R` 或 `123#R4`（远程帧，可带 DLC）。运行中可从标准输入逐行输入要发送的帧。
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::events::{self, Event};
+use crate::parse_duration;
+use crate::terminal;
+
+/// slcan 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct SlcanArgs {
+    /// CAN 波特率：10k、20k、50k、100k、125k、250k、500k、800k 或 1m
+    #[arg(long, default_value = "500k", value_parser = parse_bitrate)]
+    pub bitrate: u8,
+
+    /// 只听模式打开通道（不发送应答位，不影响总线），此时不能发送帧
+    #[arg(long, conflicts_with = "send")]
+    pub listen_only: bool,
+
+    /// 只显示匹配的帧：十六进制 ID[/掩码]（如 123、700/780），可重复，满足任一条即显示
+    #[arg(long, value_name = "ID[/MASK]", value_parser = parse_filter)]
+    pub filter: Vec<Filter>,
+
+    /// 打开通道后发送的帧（如 123#DEADBEEF），可重复
+    #[arg(long, value_name = "FRAME", value_parser = parse_frame)]
+    pub send: Vec<CanFrame>,
+
+    /// 运行指定时长后关闭通道退出（如 30s、5m）
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub exit_after: Option<Duration>,
+}
+
+/// 波特率对应的 Sn 命令参数
+const BITRATES: [&str; 9] = ["10k", "20k", "50k", "100k", "125k", "250k", "500k", "800k", "1m"];
+
+fn parse_bitrate(s: &str) -> std::result::Result<u8, String> {
+    let lower = s.to_ascii_lowercase();
+    let lower = lower.strip_suffix("bps").unwrap_or(&lower);
+    let name = match lower {
+        "1000k" | "1000000" => "1m",
+        other => other,
+    };
+    match BITRATES.iter().position(|&b| b == name || b.replace('k', "000") == name) {
+        Some(code) => Ok(code as u8),
+        None => Err(format!("不支持的 CAN 波特率 '{}'，应为 {}", s, BITRATES.join("、"))),
+    }
+}
+
+/// ID 过滤条件
+#[derive(Debug, Clone, Copy)]
+pub struct Filter {
+    id: u32,
+    mask: u32,
+}
+
+impl Filter {
+    fn matches(&self, id: u32) -> bool {
+        id & self.mask == self.id & self.mask
+    }
+}
+
+fn parse_filter(s: &str) -> std::result::Result<Filter, String> {
+    let (id, mask) = match s.split_once('/') {
+        Some((id, mask)) => (id, Some(mask)),
+        None => (s, None),
+    };
+    let parse = |text: &str| {
+        let hex = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+        u32::from_str_radix(hex, 16).ok().filter(|&v| v <= 0x1FFF_FFFF).ok_or_else(|| format!("无效的 CAN ID '{}'", text))
+    };
+    Ok(Filter { id: parse(id)?, mask: mask.map(parse).transpose()?.unwrap_or(0x1FFF_FFFF) })
+}
+
+/// 一个 CAN 帧
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanFrame {
+    id: u32,
+    extended: bool,
+    remote: bool,
+    dlc: u8,
+    data: Vec<u8>,
+}
+
+impl CanFrame {
+    /// 编码为 SLCAN 发送命令（t/T/r/R）
+    fn encode(&self) -> String {
+        let kind = match (self.extended, self.remote) {
+            (false, false) => 't',
+            (true, false) => 'T',
+            (false, true) => 'r',
+            (true, true) => 'R',
+        };
+        let id = match self.extended {
+            true => format!("{:08X}", self.id),
+            false => format!("{:03X}", self.id),
+        };
+        let data: String = self.data.iter().map(|b| format!("{:02X}", b)).collect();
+        format!("{}{}{}{}\r", kind, id, self.dlc, data)
+    }
+
+    /// 解码适配器收到的帧（t/T/r/R 开头的一行，末尾可能有 4 位时间戳）
+    fn decode(line: &str) -> Option<(CanFrame, Option<u16>)> {
+        let (extended, remote) = match line.chars().next()? {
+            't' => (false, false),
+            'T' => (true, false),
+            'r' => (false, true),
+            'R' => (true, true),
+            _ => return None,
+        };
+        let id_len = if extended { 8 } else { 3 };
+        let id = u32::from_str_radix(line.get(1..1 + id_len)?, 16).ok()?;
+        let dlc = line.get(1 + id_len..2 + id_len)?.parse::<u8>().ok().filter(|&d| d <= 8)?;
+        let mut rest = &line[2 + id_len..];
+        let mut data = Vec::new();
+        if !remote {
+            data = (0..dlc as usize)
+                .map(|i| rest.get(i * 2..i * 2 + 2).and_then(|h| u8::from_str_radix(h, 16).ok()))
+                .collect::<Option<_>>()?;
+            rest = &rest[dlc as usize * 2..];
+        }
+        let timestamp = match rest.len() {
+            0 => None,
+            4 => Some(u16::from_str_radix(rest, 16).ok()?),
+            _ => return None,
+        };
+        Some((CanFrame { id, extended, remote, dlc, data }, timestamp))
+    }
+}
+
+impl std::fmt::Display for CanFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.extended {
+            true => write!(f, "{:08X}", self.id)?,
+            false => write!(f, "     {:03X}", self.id)?,
+        }
+        write!(f, "  [{}]", self.dlc)?;
+        if self.remote {
+            return write!(f, "  远程帧");
+        }
+        for byte in &self.data {
+            write!(f, " {:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// 解析 cansend 格式的帧：`ID#数据` 或 `ID#R[DLC]`，数据可用 '.' 分隔
+pub fn parse_frame(s: &str) -> std::result::Result<CanFrame, String> {
+    let (id, data) = s.trim().split_once('#').ok_or_else(|| format!("无效的 CAN 帧 '{}'，应为 ID#数据（如 123#DEADBEEF）", s))?;
+    let extended = id.len() > 3;
+    let Some(id) = u32::from_str_radix(id, 16).ok().filter(|&v| v <= if extended { 0x1FFF_FFFF } else { 0x7FF }) else {
+        return Err(format!("无效的 CAN ID '{}'：标准帧为 3 位十六进制（不超过 7FF），扩展帧为 8 位", id));
+    };
+    if let Some(dlc) = data.strip_prefix(['R', 'r']) {
+        let dlc = match dlc {
+            "" => 0,
+            dlc => dlc.parse::<u8>().ok().filter(|&d| d <= 8).ok_or_else(|| format!("无效的远程帧 DLC '{}'", dlc))?,
+        };
+        return Ok(CanFrame { id, extended, remote: true, dlc, data: Vec::new() });
+    }
+    let hex = data.replace('.', "");
+    if !hex.len().is_multiple_of(2) || hex.len() > 16 {
+        return Err(format!("无效的 CAN 数据 '{}'：应为 0～8 字节的十六进制", data));
+    }
+    let data = (0..hex.len() / 2)
+        .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| format!("无效的 CAN 数据 '{}'：应为 0～8 字节的十六进制", data))?;
+    Ok(CanFrame { id, extended, remote: false, dlc: data.len() as u8, data })
+}
+
+/// 适配器的一条应答
+enum Reply {
+    /// 命令成功（CR，发送成功时部分适配器回 z/Z）
+    Ok,
+    /// 命令失败（BEL）
+    Error,
+    /// 收到的帧或其他内容
+    Line(String),
+}
+
+/// 按 CR/BEL 切分适配器的应答
+struct Replies {
+    pending: Vec<u8>,
+    queue: VecDeque<Reply>,
+}
+
+impl Replies {
+    /// 读取一次串口，把完整的应答放入队列
+    fn fill(&mut self, port: &mut Box<dyn SerialPort>) -> Result<()> {
+        let mut buffer = [0u8; 512];
+        match port.read(&mut buffer) {
+            Ok(n) => {
+                events::emit(Event::Rx(&buffer[..n]));
+                self.pending.extend_from_slice(&buffer[..n]);
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e).context("读取串口失败"),
+        }
+        while let Some(end) = self.pending.iter().position(|&b| b == b'\r' || b == 0x07) {
+            let bell = self.pending[end] == 0x07;
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            let text = String::from_utf8_lossy(&line[..end]).trim_start_matches('\n').to_string();
+            self.queue.push_back(match (bell, text.as_str()) {
+                (true, _) => Reply::Error,
+                (false, "" | "z" | "Z") => Reply::Ok,
+                (false, _) => Reply::Line(text),
+            });
+        }
+        Ok(())
+    }
+
+    /// 读取一次串口，返回目前所有完整的应答
+    fn read(&mut self, port: &mut Box<dyn SerialPort>) -> Result<Vec<Reply>> {
+        self.fill(port)?;
+        Ok(self.queue.drain(..).collect())
+    }
+
+    /// 发送一条命令并等待 CR 或 BEL
+    fn command(&mut self, port: &mut Box<dyn SerialPort>, command: &str) -> Result<()> {
+        terminal::transmit(port, None, format!("{}\r", command).as_bytes())?;
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while Instant::now() < deadline {
+            self.fill(port)?;
+            while let Some(reply) = self.queue.pop_front() {
+                match reply {
+                    Reply::Ok => return Ok(()),
+                    Reply::Error => bail!("适配器拒绝命令 '{}'", command),
+                    Reply::Line(line) => log::debug!("忽略适配器输出：{}", line),
+                }
+            }
+        }
+        bail!("适配器对命令 '{}' 无应答，请确认是 SLCAN 适配器且串口波特率正确", command)
+    }
+}
+
+fn spawn_frames() -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            let Ok(line) = line else { break };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// 执行 slcan 子命令：打开通道后发送帧并显示收到的帧，直到出错、超时或被中断
+pub fn run_slcan(port: &mut Box<dyn SerialPort>, opts: &SlcanArgs) -> Result<()> {
+    port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+    let mut replies = Replies { pending: Vec::new(), queue: VecDeque::new() };
+    // 先关闭可能已打开的通道，CR 之前的残留命令会被适配器丢弃
+    terminal::transmit(port, None, b"\r\r\rC\r")?;
+    thread::sleep(Duration::from_millis(100));
+    port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+    replies.command(port, &format!("S{}", opts.bitrate))?;
+    replies.command(port, if opts.listen_only { "L" } else { "O" })?;
+    events::status(&format!(
+        "CAN 通道已打开（{}{}），按 Ctrl+C 退出{}",
+        BITRATES[opts.bitrate as usize],
+        if opts.listen_only { "，只听模式" } else { "" },
+        if opts.listen_only { "" } else { "，可输入 ID#数据 发送帧" }
+    ));
+
+    let mut queue = opts.send.clone();
+    let input = spawn_frames();
+    let deadline = opts.exit_after.map(|d| Instant::now() + d);
+    while deadline.is_none_or(|d| Instant::now() < d) {
+        while let Ok(line) = input.try_recv() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_frame(&line) {
+                Ok(_) if opts.listen_only => events::status("只听模式下不能发送帧"),
+                Ok(frame) => queue.push(frame),
+                Err(e) => events::status(&e),
+            }
+        }
+        for frame in queue.drain(..) {
+            terminal::transmit(port, None, frame.encode().as_bytes())?;
+            events::status(&format!("发送 {}", frame));
+        }
+
+        for reply in replies.read(port)? {
+            match reply {
+                Reply::Ok => {}
+                Reply::Error => events::status("适配器返回错误（发送失败或缓冲区满）"),
+                Reply::Line(line) => match CanFrame::decode(&line) {
+                    Some((frame, _)) if !opts.filter.is_empty() && !opts.filter.iter().any(|f| f.matches(frame.id)) => {}
+                    Some((frame, Some(timestamp))) => events::status(&format!("接收 {}  ({} ms)", frame, timestamp)),
+                    Some((frame, None)) => events::status(&format!("接收 {}", frame)),
+                    None => events::status(&format!("适配器输出：{}", line)),
+                },
+            }
+        }
+    }
+    replies.command(port, "C")
+}
@@ -0,0 +1,290 @@
+//! 经 AT 命令发送短信：PDU 模式编码（GSM 7 位默认字母表或 UCS-2，中文自动使用 UCS-2），
+//! 超长短信拆分为带 UDH 的级联短信，逐条通过 AT+CMGS 发送并确认
+//!
+//! SMS-SUBMIT PDU：`00`（使用 SIM 卡中的短信中心）`11 00 目的地址 00 DCS AA UDL UD`。
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::at::Modem;
+use crate::events;
+use crate::parse_duration;
+use crate::signal::Rs485;
+
+/// sms 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct SmsArgs {
+    #[command(subcommand)]
+    pub command: SmsCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum SmsCommand {
+    /// 发送一条短信（超长时自动拆分为级联短信）
+    Send {
+        /// 收信号码，国际格式带 +，如 +8613800000000
+        #[arg(long, value_parser = parse_number)]
+        to: String,
+
+        /// 短信内容
+        #[arg(long)]
+        text: String,
+
+        /// 等待每条 AT 命令结果的超时时间（网络较差时发送可能需要十几秒）
+        #[arg(long, default_value = "30s", value_parser = parse_duration)]
+        timeout: Duration,
+    },
+}
+
+fn parse_number(s: &str) -> std::result::Result<String, String> {
+    let digits = s.strip_prefix('+').unwrap_or(s);
+    if digits.is_empty() || digits.len() > 20 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("无效的号码 '{}'，应为数字，国际格式以 + 开头", s));
+    }
+    Ok(s.to_string())
+}
+
+/// 执行 sms 子命令
+pub fn run_sms(port: &mut Box<dyn SerialPort>, opts: &SmsArgs, rs485: Option<Rs485>) -> Result<()> {
+    let SmsCommand::Send { to, text, timeout } = &opts.command;
+    if text.is_empty() {
+        bail!("短信内容为空");
+    }
+    let pdus = encode_submit(to, text, reference());
+    port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+    let mut modem = Modem::new(rs485, *timeout);
+    modem.command(port, "ATE0")?;
+    modem.command(port, "AT+CMGF=0")?;
+    let total = pdus.len();
+    events::status(&format!("发送短信到 {}，共 {} 条（{}）", to, total, if is_gsm7(text) { "GSM 7 位" } else { "UCS-2" }));
+    for (i, pdu) in pdus.iter().enumerate() {
+        // AT+CMGS 的长度不含短信中心地址（首字节 00）
+        let command = format!("AT+CMGS={}", pdu.len() - 1);
+        let hex: String = pdu.iter().map(|b| format!("{:02X}", b)).collect();
+        modem.prompt(port, &command)?;
+        let response = modem.send_data(port, &command, hex.as_bytes())?;
+        let mr = response.iter().find_map(|line| line.strip_prefix("+CMGS:")).map(str::trim);
+        events::status(&format!("第 {}/{} 条已发送，消息参考号 {}", i + 1, total, mr.unwrap_or("未知")));
+    }
+    Ok(())
+}
+
+/// 级联短信的参考号，同一条长短信的各部分相同
+fn reference() -> u8 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos() as u8)
+}
+
+/// GSM 7 位默认字母表，下标为编码值（0x1B 为扩展表转义，不会匹配）
+const GSM7: &str = "@£$¥èéùìòÇ\nØø\rÅåΔ_ΦΓΛΩΠΨΣΘΞ\u{1B}ÆæßÉ !\"#¤%&'()*+,-./0123456789:;<=>?\
+                    ¡ABCDEFGHIJKLMNOPQRSTUVWXYZÄÖÑÜ§¿abcdefghijklmnopqrstuvwxyzäöñüà";
+
+/// 扩展表字符（以 0x1B 转义，占 2 个 septet）
+fn gsm7_extension(c: char) -> Option<u8> {
+    let code = match c {
+        '\x0C' => 0x0A,
+        '^' => 0x14,
+        '{' => 0x28,
+        '}' => 0x29,
+        '\\' => 0x2F,
+        '[' => 0x3C,
+        '~' => 0x3D,
+        ']' => 0x3E,
+        '|' => 0x40,
+        '€' => 0x65,
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// 一个字符的 GSM 7 位编码（扩展字符为两个 septet）
+fn gsm7_septets(c: char) -> Option<Vec<u8>> {
+    if c == '\u{1B}' {
+        return None;
+    }
+    match GSM7.chars().position(|g| g == c) {
+        Some(code) => Some(vec![code as u8]),
+        None => gsm7_extension(c).map(|code| vec![0x1B, code]),
+    }
+}
+
+fn is_gsm7(text: &str) -> bool {
+    text.chars().all(|c| gsm7_septets(c).is_some())
+}
+
+/// 把 septet 按位打包，前面留出 `fill` 个填充位（跟在 UDH 后面时对齐到 septet 边界）
+fn pack_septets(septets: &[u8], fill: u32) -> Vec<u8> {
+    let mut packed = Vec::new();
+    let (mut acc, mut bits) = (0u32, fill);
+    for &septet in septets {
+        acc |= (septet as u32) << bits;
+        bits += 7;
+        while bits >= 8 {
+            packed.push(acc as u8);
+            acc >>= 8;
+            bits -= 8;
+        }
+    }
+    if bits > 0 {
+        packed.push(acc as u8);
+    }
+    packed
+}
+
+/// 号码编码为目的地址：位数、号码类型（91 国际 / 81 未知），BCD 半字节交换，奇数位补 F
+fn encode_address(number: &str) -> Vec<u8> {
+    let (kind, digits) = match number.strip_prefix('+') {
+        Some(digits) => (0x91, digits),
+        None => (0x81, number),
+    };
+    let mut address = vec![digits.len() as u8, kind];
+    for pair in digits.as_bytes().chunks(2) {
+        let low = pair[0] - b'0';
+        let high = pair.get(1).map_or(0x0F, |b| b - b'0');
+        address.push(high << 4 | low);
+    }
+    address
+}
+
+/// 编码 SMS-SUBMIT PDU，超长时拆分为级联短信（UDH 为 8 位参考号的 IEI 00）
+fn encode_submit(to: &str, text: &str, reference: u8) -> Vec<Vec<u8>> {
+    // 各部分的用户数据（GSM 7 位为未打包的 septet），单条短信不带 UDH
+    let gsm7 = is_gsm7(text);
+    let chunks: Vec<Vec<u8>> = match gsm7 {
+        true => {
+            let septets: Vec<Vec<u8>> = text.chars().filter_map(gsm7_septets).collect();
+            let count: usize = septets.iter().map(Vec::len).sum();
+            split(&septets, if count <= 160 { 160 } else { 153 })
+        }
+        false => {
+            let units: Vec<Vec<u8>> = text
+                .chars()
+                .map(|c| c.encode_utf16(&mut [0; 2]).iter().flat_map(|u| u.to_be_bytes()).collect())
+                .collect();
+            let count: usize = units.iter().map(|u| u.len() / 2).sum();
+            split(&units, if count <= 70 { 140 } else { 134 })
+        }
+    };
+
+    let total = chunks.len();
+    let mut pdus = Vec::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let header = match total {
+            1 => Vec::new(),
+            _ => vec![0x05, 0x00, 0x03, reference, total as u8, i as u8 + 1],
+        };
+        let (udl, data) = match gsm7 {
+            true => {
+                // UDH 之后补齐到 septet 边界
+                let header_bits = header.len() as u32 * 8;
+                let fill = (7 - header_bits % 7) % 7;
+                let udl = (header_bits + fill) / 7 + chunk.len() as u32;
+                (udl as u8, [&header[..], &pack_septets(chunk, fill)].concat())
+            }
+            false => ((header.len() + chunk.len()) as u8, [&header[..], chunk].concat()),
+        };
+        let first = if header.is_empty() { 0x11 } else { 0x51 };
+        let mut pdu = vec![0x00, first, 0x00];
+        pdu.extend_from_slice(&encode_address(to));
+        pdu.extend_from_slice(&[0x00, if gsm7 { 0x00 } else { 0x08 }, 0xAA, udl]);
+        pdu.extend_from_slice(&data);
+        pdus.push(pdu);
+    }
+    pdus
+}
+
+/// 按上限拆分，不拆开同一个字符的编码（扩展字符或 UTF-16 代理对）
+fn split(units: &[Vec<u8>], limit: usize) -> Vec<Vec<u8>> {
+    let mut chunks = vec![Vec::new()];
+    for unit in units {
+        if chunks.last().is_some_and(|chunk: &Vec<u8>| chunk.len() + unit.len() > limit) {
+            chunks.push(Vec::new());
+        }
+        if let Some(chunk) = chunks.last_mut() {
+            chunk.extend_from_slice(unit);
+        }
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(data: &[u8]) -> String {
+        data.iter().map(|b| format!("{:02X}", b)).collect()
+    }
+
+    #[test]
+    fn gsm7_alphabet() {
+        assert_eq!(GSM7.chars().count(), 128);
+        for (c, code) in [('@', 0x00), ('$', 0x02), ('è', 0x04), ('\n', 0x0A), ('A', 0x41), ('a', 0x61), ('à', 0x7F)] {
+            assert_eq!(gsm7_septets(c), Some(vec![code]), "{:?}", c);
+        }
+        assert_eq!(gsm7_septets('€'), Some(vec![0x1B, 0x65]));
+        assert_eq!(gsm7_septets('\u{1B}'), None);
+        assert!(is_gsm7("Hello {world} 100€"));
+        assert!(!is_gsm7("你好"));
+    }
+
+    #[test]
+    fn septet_packing() {
+        let septets: Vec<u8> = "hellohello".bytes().collect();
+        assert_eq!(hex(&pack_septets(&septets, 0)), "E8329BFD4697D9EC37");
+        // 8 个 septet 正好 7 字节
+        assert_eq!(pack_septets(&[0x7F; 8], 0), [0xFF; 7]);
+        // 跟在 6 字节 UDH 后面时留 1 个填充位
+        assert_eq!(hex(&pack_septets(&[0x61], 1)), "C2");
+    }
+
+    #[test]
+    fn address() {
+        assert_eq!(hex(&encode_address("+46708251358")), "0B916407281553F8");
+        assert_eq!(hex(&encode_address("10086")), "05810180F6");
+    }
+
+    #[test]
+    fn single_pdus() {
+        assert_eq!(
+            encode_submit("+46708251358", "hellohello", 0).iter().map(|p| hex(p)).collect::<Vec<_>>(),
+            ["0011000B916407281553F80000AA0AE8329BFD4697D9EC37"]
+        );
+        assert_eq!(
+            encode_submit("10086", "你好", 0).iter().map(|p| hex(p)).collect::<Vec<_>>(),
+            ["00110005810180F60008AA044F60597D"]
+        );
+        assert_eq!(encode_submit("10086", &"a".repeat(160), 0).len(), 1);
+    }
+
+    #[test]
+    fn concatenated_gsm7() {
+        let pdus = encode_submit("10086", &"a".repeat(161), 0x42);
+        assert_eq!(pdus.len(), 2);
+        // 头部 00 51 00 + 地址（5 字节）+ PID DCS VP，之后是 UDL 和 UDH
+        let header = 3 + 5 + 3;
+        for (i, (pdu, septets)) in pdus.iter().zip([153, 8]).enumerate() {
+            assert_eq!(pdu[1], 0x51);
+            assert_eq!(pdu[header] as usize, 7 + septets);
+            assert_eq!(pdu[header + 1..header + 7], [0x05, 0x00, 0x03, 0x42, 0x02, i as u8 + 1]);
+            assert_eq!(pdu.len() - header - 1, 6 + (1 + septets * 7).div_ceil(8));
+        }
+        assert!(pdus[0].len() - header - 1 <= 140);
+    }
+
+    #[test]
+    fn split_keeps_characters_whole() {
+        // 扩展字符的两个 septet 不拆到两条里
+        let text = format!("{}€{}", "a".repeat(152), "b".repeat(10));
+        let pdus = encode_submit("10086", &text, 0);
+        assert_eq!(pdus.len(), 2);
+        assert_eq!(pdus[0][11], 7 + 152);
+
+        // UTF-16 代理对也不拆开
+        let text = format!("{}😀{}", "中".repeat(66), "中".repeat(3));
+        let pdus = encode_submit("10086", &text, 0);
+        assert_eq!(pdus.len(), 2);
+        assert_eq!(pdus[0][11], 6 + 132);
+        assert_eq!(pdus[1][11], 6 + 4 + 6);
+        assert_eq!(pdus[1][18..22], [0xD8, 0x3D, 0xDE, 0x00]);
+    }
+}
@@ -0,0 +1,150 @@
+//! STM32 系统存储器 bootloader 的 USART 协议（AN3155）
+//!
+//! 发送 0x7F 让 bootloader 自动识别波特率，之后每条命令为 `命令 命令反码`，bootloader 回复
+//! ACK（0x79）或 NACK（0x1F）；地址为 4 字节大端加异或校验，数据块最长 256 字节。
+//! 串口格式固定为 8 位数据、偶校验、1 位停止位。
+
+use anyhow::{bail, Result};
+use std::time::Duration;
+
+use crate::xmodem::Link;
+
+pub const ACK: u8 = 0x79;
+pub const NACK: u8 = 0x1F;
+/// 自动波特率同步字节
+const SYNC: u8 = 0x7F;
+
+const GET: u8 = 0x00;
+const GET_ID: u8 = 0x02;
+const READ: u8 = 0x11;
+const GO: u8 = 0x21;
+const WRITE: u8 = 0x31;
+const ERASE: u8 = 0x43;
+const EXTENDED_ERASE: u8 = 0x44;
+
+/// 读写命令一次最多传送的字节数
+pub const BLOCK: usize = 256;
+/// 一般命令等待 ACK 的时长
+const TIMEOUT: Duration = Duration::from_secs(1);
+/// 全片擦除的最长时长（大容量芯片需要数十秒）
+const ERASE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 已同步的 bootloader
+pub struct Bootloader<'l, 'p> {
+    link: &'l mut Link<'p>,
+    /// bootloader 版本（如 0x31 即 3.1）
+    pub version: u8,
+    /// bootloader 支持的命令
+    commands: Vec<u8>,
+}
+
+impl<'l, 'p> Bootloader<'l, 'p> {
+    /// 发送 0x7F 同步，再用 Get 命令读取版本和支持的命令
+    pub fn connect(link: &'l mut Link<'p>) -> Result<Self> {
+        let mut synced = false;
+        for _ in 0..5 {
+            link.send(&[SYNC])?;
+            // 已经同步过的 bootloader 把 0x7F 当作无效命令，回复 NACK
+            if matches!(link.read_byte(Duration::from_millis(500))?, Some(ACK | NACK)) {
+                synced = true;
+                break;
+            }
+        }
+        if !synced {
+            bail!("bootloader 没有响应 0x7F，请确认 BOOT0 为高电平时复位了芯片（可用 --reset-seq stm32），且波特率不超过 115200");
+        }
+        let mut bootloader = Bootloader { link, version: 0, commands: Vec::new() };
+        bootloader.command(GET)?;
+        let len = bootloader.read(1)?[0] as usize;
+        let info = bootloader.read(len + 1)?;
+        bootloader.wait_ack(TIMEOUT)?;
+        bootloader.version = info[0];
+        bootloader.commands = info[1..].to_vec();
+        Ok(bootloader)
+    }
+
+    fn read(&mut self, len: usize) -> Result<Vec<u8>> {
+        match self.link.read_exact(len, TIMEOUT)? {
+            Some(data) => Ok(data),
+            None => bail!("bootloader 应答不完整"),
+        }
+    }
+
+    fn wait_ack(&mut self, timeout: Duration) -> Result<()> {
+        match self.link.read_byte(timeout)? {
+            Some(ACK) => Ok(()),
+            Some(NACK) => bail!("bootloader 回复 NACK"),
+            Some(byte) => bail!("bootloader 回复了意外的字节 {:02X}", byte),
+            None => bail!("等待 bootloader 应答超时"),
+        }
+    }
+
+    fn command(&mut self, command: u8) -> Result<()> {
+        self.link.send(&[command, !command])?;
+        match self.wait_ack(TIMEOUT) {
+            Err(_) if matches!(command, READ | WRITE | ERASE | EXTENDED_ERASE) => {
+                bail!("命令 {:02X} 被拒绝，芯片可能开启了读保护或写保护", command)
+            }
+            result => result,
+        }
+    }
+
+    /// 发送地址：4 字节大端加异或校验
+    fn address(&mut self, address: u32) -> Result<()> {
+        let bytes = address.to_be_bytes();
+        self.link.send(&[bytes[0], bytes[1], bytes[2], bytes[3], xor(&bytes)])?;
+        self.wait_ack(TIMEOUT).map_err(|e| e.context(format!("地址 0x{:08X} 无效", address)))
+    }
+
+    /// 产品 ID（如 0x0410 为 STM32F10x 中容量）
+    pub fn chip_id(&mut self) -> Result<u16> {
+        self.command(GET_ID)?;
+        let len = self.read(1)?[0] as usize;
+        let id = self.read(len + 1)?;
+        self.wait_ack(TIMEOUT)?;
+        Ok(id.iter().fold(0u16, |id, &b| id << 8 | b as u16))
+    }
+
+    /// 全片擦除：支持扩展擦除（0x44）的 bootloader 用 0xFFFF，否则用擦除命令（0x43）的 0xFF
+    pub fn mass_erase(&mut self) -> Result<()> {
+        if self.commands.contains(&EXTENDED_ERASE) {
+            self.command(EXTENDED_ERASE)?;
+            self.link.send(&[0xFF, 0xFF, 0x00])?;
+        } else {
+            self.command(ERASE)?;
+            self.link.send(&[0xFF, 0x00])?;
+        }
+        self.wait_ack(ERASE_TIMEOUT).map_err(|e| e.context("全片擦除失败"))
+    }
+
+    /// 写入一块（最长 256 字节，长度须为 4 的倍数）
+    pub fn write(&mut self, address: u32, data: &[u8]) -> Result<()> {
+        self.command(WRITE)?;
+        self.address(address)?;
+        let mut packet = vec![(data.len() - 1) as u8];
+        packet.extend_from_slice(data);
+        packet.push(xor(&packet));
+        self.link.send(&packet)?;
+        self.wait_ack(TIMEOUT).map_err(|e| e.context(format!("写入 0x{:08X} 失败", address)))
+    }
+
+    /// 读出一块（最长 256 字节）
+    pub fn read_memory(&mut self, address: u32, len: usize) -> Result<Vec<u8>> {
+        self.command(READ)?;
+        self.address(address)?;
+        let count = (len - 1) as u8;
+        self.link.send(&[count, !count])?;
+        self.wait_ack(TIMEOUT)?;
+        self.read(len)
+    }
+
+    /// 从指定地址运行（地址处为向量表：栈顶和复位向量）
+    pub fn go(&mut self, address: u32) -> Result<()> {
+        self.command(GO)?;
+        self.address(address)
+    }
+}
+
+fn xor(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |x, &b| x ^ b)
+}
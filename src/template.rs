@@ -0,0 +1,89 @@
+//! 发送内容中的模板变量展开
+
+use anyhow::{bail, Context, Result};
+use std::cell::Cell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 展开消息中的模板变量
+///
+/// 支持的占位符：
+/// - `{seq}`：当前发送序号（从 1 开始）
+/// - `{timestamp}` / `{timestamp:ms}`：Unix 时间戳（秒/毫秒）
+/// - `{rand:N}`：N 位随机十六进制数字
+/// - `{env:NAME}`：环境变量 NAME 的值
+/// - `{{` / `}}`：字面量花括号
+pub fn expand(template: &str, seq: u64) -> Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(pos) = rest.find(['{', '}']) {
+        out.push_str(&rest[..pos]);
+        let tail = &rest[pos..];
+
+        if let Some(after) = tail.strip_prefix("{{") {
+            out.push('{');
+            rest = after;
+            continue;
+        }
+        if let Some(after) = tail.strip_prefix("}}") {
+            out.push('}');
+            rest = after;
+            continue;
+        }
+        if tail.starts_with('}') {
+            bail!("模板中有多余的 '}}'");
+        }
+
+        let end = tail.find('}').context("模板占位符缺少 '}'")?;
+        out.push_str(&render(&tail[1..end], seq)?);
+        rest = &tail[end + 1..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// 计算单个占位符的值
+fn render(name: &str, seq: u64) -> Result<String> {
+    let (key, arg) = match name.split_once(':') {
+        Some((k, a)) => (k, Some(a)),
+        None => (name, None),
+    };
+
+    match (key, arg) {
+        ("seq", None) => Ok(seq.to_string()),
+        ("timestamp", None) => Ok(unix_now().as_secs().to_string()),
+        ("timestamp", Some("ms")) => Ok(unix_now().as_millis().to_string()),
+        ("rand", Some(n)) => {
+            let n: usize = n.parse().with_context(|| format!("无效的随机位数: '{}'", n))?;
+            Ok((0..n).map(|_| format!("{:X}", next_random() & 0xF)).collect())
+        }
+        ("env", Some(var)) => {
+            std::env::var(var).with_context(|| format!("环境变量 {} 未设置", var))
+        }
+        _ => bail!("未知的模板占位符: '{{{}}}'", name),
+    }
+}
+
+fn unix_now() -> std::time::Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+thread_local! {
+    static RNG_STATE: Cell<u64> = const { Cell::new(0) };
+}
+
+/// 简单的 xorshift 伪随机数（用于生成测试数据，不适合加密用途）
+fn next_random() -> u64 {
+    RNG_STATE.with(|state| {
+        let mut x = state.get();
+        if x == 0 {
+            x = (unix_now().as_nanos() as u64) ^ ((std::process::id() as u64) << 32) | 1;
+        }
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        x
+    })
+}
@@ -0,0 +1,413 @@
+//! 交互式终端：键盘输入发送到串口，同时实时显示收到的数据
+
+use anyhow::{Context, Result};
+use serialport::SerialPort;
+use std::io::{self, BufRead, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::console::{Key, KeyReader, RawMode};
+use crate::editor::LineEditor;
+use crate::events::{self, Event};
+use crate::logfile::{LogFormat, RxLog};
+use crate::macros::Macros;
+use crate::menu::{Menu, Outcome, Runtime};
+use crate::signal::Rs485;
+use crate::tui;
+use crate::{echo_tx, format_hex, lock, parse_hex, LineEnding};
+
+/// 接收数据未换行部分最多记录的字符数，超过后不再在其后重绘输入行
+const RX_TAIL_LIMIT: usize = 256;
+
+/// terminal 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct TerminalArgs {
+    /// 回车时追加发送的换行符
+    #[arg(long, value_enum, default_value = "cr")]
+    pub line_ending: LineEnding,
+
+    /// 本地回显发送的内容（以青色显示，与接收数据区分）
+    #[arg(long)]
+    pub local_echo: bool,
+
+    /// 以字符模式启动：每个按键立即发送（运行中可用 Ctrl+T 切换）
+    #[arg(long)]
+    pub char_mode: bool,
+
+    /// 宏按键文件：每行一条 "F1 = 内容"，按对应功能键一键发送
+    #[arg(long, value_name = "PATH")]
+    pub macros: Option<PathBuf>,
+
+    /// 使用全屏界面：数据区可滚动，底部显示状态栏（端口参数、收发字节数、信号线）和输入框
+    #[arg(long)]
+    pub tui: bool,
+
+    /// 透传模式：收到的数据（含 ANSI 转义序列）原样输出到终端，按键立即发送，
+    /// 用于 U-Boot 菜单、Zephyr shell 等全屏界面
+    #[arg(long, conflicts_with = "tui")]
+    pub passthrough: bool,
+
+    /// 同时把收到的原始数据写入文件
+    #[arg(long, value_name = "FILE")]
+    pub log: Option<PathBuf>,
+
+    /// 追加到已有日志文件（默认清空重写）
+    #[arg(long, requires = "log")]
+    pub log_append: bool,
+}
+
+/// 屏幕输出：协调接收数据与正在编辑的输入行，互不覆盖
+#[derive(Default)]
+struct Screen {
+    /// 最近一次接收数据中尚未换行的部分（如设备提示符 "login: "）
+    rx_tail: String,
+    /// 正在编辑的输入行
+    input: String,
+    /// 光标距输入行末尾的列数
+    cursor_back: usize,
+}
+
+impl Screen {
+    /// 显示收到的数据，之后重绘输入行
+    fn show_rx(&mut self, text: &str) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        if !self.input.is_empty() {
+            // 擦掉当前行（提示符 + 输入），重新输出提示符后接新数据
+            write!(out, "\r\x1b[K{}", self.rx_tail)?;
+        }
+        out.write_all(text.as_bytes())?;
+
+        let joined = format!("{}{}", self.rx_tail, text);
+        let tail = joined.rsplit(['\n', '\r']).next().unwrap_or("");
+        self.rx_tail = if tail.chars().count() > RX_TAIL_LIMIT { String::new() } else { tail.to_string() };
+        if !self.input.is_empty() && self.rx_tail.is_empty() && !text.ends_with(['\n', '\r']) {
+            // 提示符过长无法重绘时，把输入行放到下一行
+            out.write_all(b"\r\n")?;
+        }
+        self.draw_input(&mut out)?;
+        out.flush()
+    }
+
+    /// 本地回显已发送的一行：接在设备提示符后以青色显示并换行，之后重绘输入行
+    fn show_tx(&mut self, text: &str) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        write!(out, "\r\x1b[K{}\x1b[36m{}\x1b[0m\r\n", self.rx_tail, text)?;
+        self.rx_tail.clear();
+        self.draw_input(&mut out)?;
+        out.flush()
+    }
+
+    /// 在单独一行显示提示信息，之后重绘提示符和输入行
+    fn show_status(&mut self, msg: &str) -> io::Result<()> {
+        if events::jsonl() {
+            events::status(msg);
+            return Ok(());
+        }
+        let mut out = io::stdout().lock();
+        write!(out, "\r\x1b[K{}\r\n{}", msg, self.rx_tail)?;
+        self.draw_input(&mut out)?;
+        out.flush()
+    }
+
+    /// 更新输入行内容并重绘
+    fn set_input(&mut self, input: String, cursor_back: usize) -> io::Result<()> {
+        self.input = input;
+        self.cursor_back = cursor_back;
+        let mut out = io::stdout().lock();
+        write!(out, "\r\x1b[K{}", self.rx_tail)?;
+        self.draw_input(&mut out)?;
+        out.flush()
+    }
+
+    fn draw_input(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(self.input.as_bytes())?;
+        if self.cursor_back > 0 {
+            write!(out, "\x1b[{}D", self.cursor_back)?;
+        }
+        Ok(())
+    }
+}
+
+/// 运行交互式终端
+///
+/// 标准输入是终端时进入原始模式，Ctrl+] 退出，Ctrl+T 在两种输入方式间切换：
+/// 行模式支持行编辑、历史记录（↑↓）和 Ctrl+R 搜索，回车发送整行；
+/// 字符模式下每个按键立即原样发送（用于 bootloader 菜单、远程 vi 等）。
+/// 两种模式下按下绑定了宏的功能键都会立即发送对应内容，Ctrl+A 打开设置菜单。
+/// `--passthrough` 时收到的数据原样输出，设备的全屏界面可正常显示。
+/// 标准输入不是终端（如管道输入）时逐行读取，直到输入结束。
+pub fn run_terminal(
+    port: &mut Box<dyn SerialPort>,
+    opts: &TerminalArgs,
+    hex_mode: bool,
+    rs485: Option<Rs485>,
+    rx_buffer: usize,
+) -> Result<()> {
+    let macros = match &opts.macros {
+        Some(path) => Macros::load(path)?,
+        None => Macros::default(),
+    };
+    let log = match &opts.log {
+        Some(path) => Some(RxLog::open(path, LogFormat::Raw, opts.log_append, None)?),
+        None => None,
+    };
+    if opts.tui {
+        return tui::run(port, opts, &macros, log, Runtime::new(hex_mode, opts.line_ending, rs485), rx_buffer);
+    }
+    let reader = port.try_clone().context("无法复制串口句柄")?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let screen = Arc::new(Mutex::new(Screen::default()));
+    let mut runtime = Runtime::new(hex_mode, opts.line_ending, rs485);
+
+    match RawMode::enable() {
+        Ok(raw) => {
+            events::status(
+                "已进入交互模式（回车发送，↑↓ 历史，Ctrl+R 搜索，Ctrl+T 切换字符/行模式，Ctrl+A 设置菜单，Ctrl+] 退出）",
+            );
+            let rx = spawn_receiver(reader, log, opts.passthrough, runtime.hex.clone(), rx_buffer, stop.clone(), screen.clone());
+            let result = edit_loop(port, opts, &macros, &mut runtime, &screen, &stop);
+            if opts.passthrough {
+                // 恢复设备界面可能改动的颜色和光标显示
+                print!("\x1b[0m\x1b[?25h");
+            }
+            drop(raw);
+            stop.store(true, Ordering::Relaxed);
+            println!();
+            join(rx).and(result)
+        }
+        Err(e) => {
+            log::debug!("无法进入原始模式，改为逐行读取: {:#}", e);
+            events::status("已进入交互模式（输入内容回车发送，Ctrl+D/Ctrl+Z 退出）");
+            let rx = spawn_receiver(reader, log, opts.passthrough, runtime.hex.clone(), rx_buffer, stop.clone(), screen.clone());
+            let result = line_loop(port, opts, &runtime, &stop);
+            stop.store(true, Ordering::Relaxed);
+            join(rx).and(result)
+        }
+    }
+}
+
+fn join(rx: thread::JoinHandle<Result<()>>) -> Result<()> {
+    rx.join().map_err(|_| anyhow::anyhow!("接收线程异常退出"))?
+}
+
+/// 原始模式下的按键循环
+fn edit_loop(
+    port: &mut Box<dyn SerialPort>,
+    opts: &TerminalArgs,
+    macros: &Macros,
+    runtime: &mut Runtime,
+    screen: &Mutex<Screen>,
+    stop: &AtomicBool,
+) -> Result<()> {
+    let mut keys = KeyReader::default();
+    let mut editor = LineEditor::default();
+    let mut menu = Menu::default();
+    let mut char_mode = opts.char_mode || opts.passthrough;
+    if char_mode {
+        lock(screen).show_status("[字符模式：按键立即发送]")?;
+    }
+
+    loop {
+        let key = keys.next_key()?;
+        if stop.load(Ordering::Relaxed) {
+            return Ok(()); // 接收线程已因串口错误退出
+        }
+        let Some(key) = key else { return Ok(()) };
+        let key = match menu.handle(key, port, runtime)? {
+            Outcome::Pass(key) => key,
+            Outcome::Prompt(text, back) => {
+                lock(screen).set_input(text, back)?;
+                continue;
+            }
+            Outcome::Done(message) => {
+                let mut screen = lock(screen);
+                let (text, back) = if char_mode { (String::new(), 0) } else { editor.render() };
+                screen.set_input(text, back)?;
+                if let Some(message) = message {
+                    screen.show_status(&message)?;
+                }
+                continue;
+            }
+        };
+        let hex_mode = runtime.hex();
+
+        if let Some(m) = match key {
+            Key::F(n) => macros.get(n),
+            _ => None,
+        } {
+            transmit(port, runtime.rs485, &m.payload)?;
+            if opts.local_echo && !events::jsonl() {
+                let echo = if hex_mode { format_hex(&m.payload) } else { m.label.clone() };
+                lock(screen).show_tx(&echo)?;
+            }
+            continue;
+        }
+        let key = match key {
+            Key::CtrlBracket => return Ok(()),
+            Key::Ctrl('t') => {
+                char_mode = !char_mode;
+                let mut screen = lock(screen);
+                if char_mode {
+                    // 未发送的输入暂时隐藏，切回行模式后恢复
+                    screen.set_input(String::new(), 0)?;
+                    screen.show_status("[字符模式：按键立即发送]")?;
+                } else {
+                    screen.show_status("[行模式：回车发送]")?;
+                    let (text, back) = editor.render();
+                    screen.set_input(text, back)?;
+                }
+                continue;
+            }
+            key if char_mode => {
+                send_key(port, key, opts.local_echo, runtime, screen)?;
+                continue;
+            }
+            Key::Ctrl('d') if editor.render().0.is_empty() => return Ok(()),
+            key => key,
+        };
+
+        let submitted = editor.handle(key);
+        let (text, back) = editor.render();
+        lock(screen).set_input(text, back)?;
+
+        if let Some(line) = submitted {
+            match encode_line(line.clone(), runtime.line_ending, hex_mode) {
+                Ok(bytes) => {
+                    transmit(port, runtime.rs485, &bytes)?;
+                    if opts.local_echo && !events::jsonl() {
+                        let echo = if hex_mode { format_hex(&bytes) } else { line };
+                        lock(screen).show_tx(&echo)?;
+                    }
+                }
+                Err(e) => lock(screen).show_status(&format!("{:#}", e))?,
+            }
+        }
+    }
+}
+
+/// 字符模式下按键对应的发送内容；回车按 --line-ending 发送（none 时发送 CR）
+pub fn key_bytes(key: Key, line_ending: LineEnding) -> Vec<u8> {
+    match key {
+        Key::Enter if !matches!(line_ending, LineEnding::None) => line_ending.as_bytes().to_vec(),
+        key => key.to_bytes(),
+    }
+}
+
+/// 字符模式下发送单个按键
+fn send_key(
+    port: &mut Box<dyn SerialPort>,
+    key: Key,
+    local_echo: bool,
+    runtime: &Runtime,
+    screen: &Mutex<Screen>,
+) -> Result<()> {
+    let bytes = key_bytes(key, runtime.line_ending);
+    transmit(port, runtime.rs485, &bytes)?;
+
+    if local_echo && !events::jsonl() {
+        let echo = match key {
+            _ if runtime.hex() => format!("{} ", format_hex(&bytes)),
+            Key::Char(c) => c.to_string(),
+            Key::Enter => "\r\n".to_string(),
+            _ => return Ok(()), // 控制键不回显
+        };
+        lock(screen).show_rx(&format!("\x1b[36m{}\x1b[0m", echo))?;
+    }
+    Ok(())
+}
+
+/// 非终端输入时逐行读取
+fn line_loop(port: &mut Box<dyn SerialPort>, opts: &TerminalArgs, runtime: &Runtime, stop: &AtomicBool) -> Result<()> {
+    let hex_mode = runtime.hex();
+    for line in io::stdin().lock().lines() {
+        let line = line.context("读取键盘输入失败")?;
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        match encode_line(line, opts.line_ending, hex_mode) {
+            Ok(bytes) => {
+                transmit(port, runtime.rs485, &bytes)?;
+                if opts.local_echo {
+                    echo_tx(&bytes, hex_mode);
+                }
+            }
+            Err(e) => events::status(&format!("{:#}", e)),
+        }
+    }
+    Ok(())
+}
+
+/// 把输入的一行转为待发送字节
+pub fn encode_line(line: String, line_ending: LineEnding, hex_mode: bool) -> Result<Vec<u8>> {
+    if hex_mode {
+        parse_hex(&line).context("十六进制解析失败")
+    } else {
+        let mut bytes = line.into_bytes();
+        bytes.extend_from_slice(line_ending.as_bytes());
+        Ok(bytes)
+    }
+}
+
+pub fn transmit(port: &mut Box<dyn SerialPort>, rs485: Option<Rs485>, bytes: &[u8]) -> Result<()> {
+    let write = |port: &mut Box<dyn SerialPort>| {
+        port.write_all(bytes).context("写入串口失败")?;
+        port.flush().context("刷新缓冲区失败")
+    };
+    match rs485 {
+        Some(rs485) => rs485.transmit(port, write)?,
+        None => write(port)?,
+    }
+    events::emit(Event::Tx(bytes));
+    Ok(())
+}
+
+/// 后台线程：持续读取串口并显示
+fn spawn_receiver(
+    mut port: Box<dyn SerialPort>,
+    mut log: Option<RxLog>,
+    passthrough: bool,
+    hex: Arc<AtomicBool>,
+    rx_buffer: usize,
+    stop: Arc<AtomicBool>,
+    screen: Arc<Mutex<Screen>>,
+) -> thread::JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        let mut buffer = vec![0u8; rx_buffer];
+        while !stop.load(Ordering::Relaxed) {
+            let n = match port.read(&mut buffer) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => {
+                    // 主线程阻塞在键盘输入上，提示用户按键后退出
+                    stop.store(true, Ordering::Relaxed);
+                    events::status(&format!("\n读取串口失败: {}，按任意键退出", e));
+                    return Err(e).context("读取串口失败");
+                }
+            };
+
+            let data = &buffer[..n];
+            if let Some(log) = log.as_mut() {
+                log.write_raw(data)?;
+            }
+            if events::jsonl() {
+                events::emit(Event::Rx(data));
+                continue;
+            }
+            if passthrough {
+                let mut out = io::stdout().lock();
+                out.write_all(data)?;
+                out.flush()?;
+                continue;
+            }
+            let text = if hex.load(Ordering::Relaxed) {
+                format!("{} ", format_hex(data))
+            } else {
+                String::from_utf8_lossy(data).into_owned()
+            };
+            lock(&screen).show_rx(&text)?;
+        }
+        Ok(())
+    })
+}
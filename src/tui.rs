@@ -0,0 +1,469 @@
+//! 全屏终端界面：上方滚动显示收发数据，下方为状态栏和输入框
+
+use anyhow::{Context, Result};
+use serialport::SerialPort;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::console::{self, Key, KeyReader, RawMode};
+use crate::editor::{char_width, LineEditor};
+use crate::events;
+use crate::{format_hex, lock};
+use crate::logfile::RxLog;
+use crate::macros::Macros;
+use crate::menu::{Menu, Outcome, Runtime};
+use crate::signal::ModemStatus;
+use crate::terminal::{encode_line, key_bytes, transmit, TerminalArgs};
+
+/// 数据区最多保留的行数
+const MAX_LINES: usize = 10_000;
+
+/// 单行超过该字符数时自动换行存储，避免无换行的数据流形成超长行
+const MAX_LINE_CHARS: usize = 4096;
+
+/// 输入信号线的刷新间隔
+const SIGNAL_POLL: Duration = Duration::from_millis(500);
+
+/// 数据区的一行
+struct PaneLine {
+    text: String,
+    /// 本地回显的发送内容（青色显示）
+    tx: bool,
+}
+
+/// 界面状态，接收线程和按键线程共享
+struct Tui {
+    /// 端口与参数，如 "COM3 115200 8N1"
+    settings: String,
+    lines: VecDeque<PaneLine>,
+    /// 最后一行接收数据是否还在继续（尚未收到换行）
+    rx_open: bool,
+    /// 向上滚动的行数（按显示行计），0 表示跟随最新数据
+    scroll: usize,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    signals: ModemStatus,
+    /// DTR/RTS 输出线状态
+    dtr: bool,
+    rts: bool,
+    char_mode: bool,
+    input: String,
+    /// 光标距输入行末尾的列数
+    cursor_back: usize,
+    /// 状态栏右侧的临时提示（如串口错误）
+    notice: Option<String>,
+    size: (u16, u16),
+}
+
+impl Tui {
+    fn new(settings: String, runtime: &Runtime, char_mode: bool) -> Self {
+        Tui {
+            settings,
+            lines: VecDeque::new(),
+            rx_open: false,
+            scroll: 0,
+            rx_bytes: 0,
+            tx_bytes: 0,
+            signals: ModemStatus::default(),
+            dtr: runtime.dtr,
+            rts: runtime.rts,
+            char_mode,
+            input: String::new(),
+            cursor_back: 0,
+            notice: None,
+            size: console::size().unwrap_or((80, 24)),
+        }
+    }
+
+    /// 追加收到的数据：文本按换行分行（去掉回车和其他控制字符），十六进制每次读取一行
+    fn push_rx(&mut self, data: &[u8], hex_mode: bool) {
+        self.rx_bytes += data.len() as u64;
+        if hex_mode {
+            self.push_line(format_hex(data), false);
+            return;
+        }
+
+        for (i, part) in String::from_utf8_lossy(data).split('\n').enumerate() {
+            if i > 0 {
+                self.rx_open = false;
+            }
+            let text: String = part
+                .chars()
+                .map(|c| if c == '\t' { ' ' } else { c })
+                .filter(|c| !c.is_control())
+                .collect();
+            match self.lines.back_mut() {
+                Some(last) if self.rx_open && last.text.chars().count() < MAX_LINE_CHARS => last.text.push_str(&text),
+                // 换行后即使暂无新内容也先占一行，后续数据接在其后
+                _ if text.is_empty() && i == 0 => continue,
+                _ => self.push_line(text, false),
+            }
+            self.rx_open = true;
+        }
+    }
+
+    fn push_tx(&mut self, text: String) {
+        if self.rx_open && self.lines.back().is_some_and(|last| last.text.is_empty()) {
+            self.lines.pop_back(); // 回显替换换行后留下的空行
+        }
+        self.push_line(text, true);
+        self.rx_open = false;
+    }
+
+    fn push_line(&mut self, text: String, tx: bool) {
+        self.lines.push_back(PaneLine { text, tx });
+        if self.lines.len() > MAX_LINES {
+            self.lines.pop_front();
+        }
+        if self.scroll > 0 {
+            // 查看历史时保持画面不动
+            self.scroll += 1;
+        }
+    }
+
+    fn pane_rows(&self) -> usize {
+        (self.size.1 as usize).saturating_sub(2).max(1)
+    }
+
+    fn scroll_by(&mut self, rows: isize) {
+        self.scroll = self.scroll.saturating_add_signed(rows);
+    }
+
+    /// 重绘整个界面
+    fn draw(&mut self) -> io::Result<()> {
+        self.size = console::size().unwrap_or(self.size);
+        let cols = (self.size.0 as usize).max(10);
+        let pane = self.pane_rows();
+
+        // 从最新一行向上取出足够的显示行（长行按宽度折行）
+        let mut rows: Vec<(&str, bool)> = Vec::new();
+        for line in self.lines.iter().rev() {
+            let mut wrapped = wrap(&line.text, cols);
+            wrapped.reverse();
+            rows.extend(wrapped.into_iter().map(|r| (r, line.tx)));
+            if rows.len() >= pane + self.scroll {
+                break;
+            }
+        }
+        let max_scroll = rows.len().saturating_sub(pane);
+        self.scroll = self.scroll.min(max_scroll);
+        let visible: Vec<_> = rows.iter().skip(self.scroll).take(pane).rev().collect();
+
+        let mut out = io::stdout().lock();
+        out.write_all(b"\x1b[?25l")?;
+        for i in 0..pane {
+            write!(out, "\x1b[{};1H", i + 1)?;
+            // 数据不足一屏时靠下对齐，与普通终端的滚动效果一致
+            if let Some(&&(text, tx)) = (i + visible.len()).checked_sub(pane).and_then(|j| visible.get(j)) {
+                if tx {
+                    write!(out, "\x1b[36m{}\x1b[0m", text)?;
+                } else {
+                    out.write_all(text.as_bytes())?;
+                }
+            }
+            out.write_all(b"\x1b[K")?;
+        }
+
+        write!(out, "\x1b[{};1H\x1b[7m{}\x1b[0m", pane + 1, fit(&self.status_line(), cols))?;
+
+        // 输入行过长时只显示光标附近的末尾部分
+        let prompt = if self.char_mode { "[字符模式] " } else { "> " };
+        let width: usize = self.input.chars().map(char_width).sum();
+        let room = cols.saturating_sub(prompt.chars().map(char_width).sum::<usize>() + 1);
+        let mut shown = self.input.as_str();
+        let mut shown_width = width;
+        while shown_width > room {
+            let c = shown.chars().next().unwrap_or(' ');
+            shown_width -= char_width(c);
+            shown = &shown[c.len_utf8()..];
+        }
+        write!(out, "\x1b[{};1H\x1b[K{}{}", pane + 2, prompt, shown)?;
+        if self.cursor_back > 0 {
+            write!(out, "\x1b[{}D", self.cursor_back.min(shown_width))?;
+        }
+        out.write_all(b"\x1b[?25h")?;
+        out.flush()
+    }
+
+    fn status_line(&self) -> String {
+        let mark = |s: Option<bool>| match s {
+            Some(true) => "●",
+            Some(false) => "○",
+            None => "-",
+        };
+        let mut status = format!(
+            " {} | RX {} B  TX {} B | DTR{} RTS{}",
+            self.settings,
+            self.rx_bytes,
+            self.tx_bytes,
+            mark(Some(self.dtr)),
+            mark(Some(self.rts)),
+        );
+        for (name, level) in self.signals.lines() {
+            status.push_str(&format!(" {}{}", name, mark(level)));
+        }
+        if self.scroll > 0 {
+            status.push_str(&format!(" | 已上翻 {} 行", self.scroll));
+        }
+        match &self.notice {
+            Some(notice) => status.push_str(&format!(" | {}", notice)),
+            None => status.push_str(" | PgUp/PgDn 滚动  Ctrl+T 字符/行模式  Ctrl+A 设置  Ctrl+] 退出"),
+        }
+        status
+    }
+}
+
+/// 按显示宽度折行
+fn wrap(text: &str, cols: usize) -> Vec<&str> {
+    let mut rows = Vec::new();
+    let (mut start, mut width) = (0, 0);
+    for (i, c) in text.char_indices() {
+        let w = char_width(c);
+        if width + w > cols {
+            rows.push(&text[start..i]);
+            start = i;
+            width = 0;
+        }
+        width += w;
+    }
+    rows.push(&text[start..]);
+    rows
+}
+
+/// 截断或补齐到指定显示宽度
+fn fit(text: &str, cols: usize) -> String {
+    let mut out = String::new();
+    let mut width = 0;
+    for c in text.chars() {
+        let w = char_width(c);
+        if width + w > cols {
+            break;
+        }
+        out.push(c);
+        width += w;
+    }
+    out.extend(std::iter::repeat_n(' ', cols - width));
+    out
+}
+
+/// 切换到备用屏幕，离开作用域时恢复原屏幕内容
+struct AltScreen;
+
+impl AltScreen {
+    fn enter() -> io::Result<Self> {
+        let mut out = io::stdout();
+        out.write_all(b"\x1b[?1049h\x1b[2J")?;
+        out.flush()?;
+        Ok(AltScreen)
+    }
+}
+
+impl Drop for AltScreen {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        let _ = out.write_all(b"\x1b[?25h\x1b[?1049l");
+        let _ = out.flush();
+    }
+}
+
+/// 端口与参数描述，如 "COM3 115200 8N1"
+fn describe(port: &dyn SerialPort) -> String {
+    let parity = match port.parity() {
+        Ok(serialport::Parity::Odd) => 'O',
+        Ok(serialport::Parity::Even) => 'E',
+        _ => 'N',
+    };
+    format!(
+        "{} {} {}{}{}",
+        port.name().unwrap_or_default(),
+        port.baud_rate().unwrap_or(0),
+        port.data_bits().map(u8::from).unwrap_or(8),
+        parity,
+        port.stop_bits().map(u8::from).unwrap_or(1),
+    )
+}
+
+/// 运行全屏界面
+pub fn run(
+    port: &mut Box<dyn SerialPort>,
+    opts: &TerminalArgs,
+    macros: &Macros,
+    log: Option<RxLog>,
+    mut runtime: Runtime,
+    rx_buffer: usize,
+) -> Result<()> {
+    if events::jsonl() {
+        anyhow::bail!("TUI 界面不支持 --output jsonl");
+    }
+    let raw = RawMode::enable().context("TUI 界面需要在终端中运行")?;
+    let screen = AltScreen::enter().context("无法切换到全屏界面")?;
+
+    let reader = port.try_clone().context("无法复制串口句柄")?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let tui = Arc::new(Mutex::new(Tui::new(describe(port.as_ref()), &runtime, opts.char_mode)));
+    lock(&tui).draw()?;
+
+    let rx = spawn_receiver(reader, log, runtime.hex.clone(), rx_buffer, stop.clone(), tui.clone());
+    let result = key_loop(port, opts, macros, &mut runtime, &tui, &stop);
+    stop.store(true, Ordering::Relaxed);
+    let rx_result = rx.join().map_err(|_| anyhow::anyhow!("接收线程异常退出"))?;
+    drop(screen);
+    drop(raw);
+    rx_result.and(result)
+}
+
+fn key_loop(
+    port: &mut Box<dyn SerialPort>,
+    opts: &TerminalArgs,
+    macros: &Macros,
+    runtime: &mut Runtime,
+    tui: &Mutex<Tui>,
+    stop: &AtomicBool,
+) -> Result<()> {
+    let mut keys = KeyReader::default();
+    let mut editor = LineEditor::default();
+    let mut menu = Menu::default();
+
+    loop {
+        let key = keys.next_key()?;
+        if stop.load(Ordering::Relaxed) {
+            return Ok(()); // 接收线程已因串口错误退出
+        }
+        let Some(key) = key else { return Ok(()) };
+        lock(tui).notice = None;
+
+        let key = match menu.handle(key, port, runtime)? {
+            Outcome::Pass(key) => key,
+            Outcome::Prompt(text, back) => {
+                let mut tui = lock(tui);
+                (tui.input, tui.cursor_back) = (text, back);
+                tui.draw()?;
+                continue;
+            }
+            Outcome::Done(message) => {
+                let mut tui = lock(tui);
+                tui.notice = message;
+                tui.settings = describe(port.as_ref());
+                (tui.dtr, tui.rts) = (runtime.dtr, runtime.rts);
+                (tui.input, tui.cursor_back) = if tui.char_mode { (String::new(), 0) } else { editor.render() };
+                tui.draw()?;
+                continue;
+            }
+        };
+        let hex_mode = runtime.hex();
+
+        let bound = match key {
+            Key::F(n) => macros.get(n),
+            _ => None,
+        };
+        let mut sent = None;
+        match (key, bound) {
+            (Key::CtrlBracket, _) => return Ok(()),
+            (Key::Ctrl('t'), _) => {
+                let mut tui = lock(tui);
+                tui.char_mode = !tui.char_mode;
+            }
+            (Key::PageUp, _) => {
+                let mut tui = lock(tui);
+                let rows = tui.pane_rows() as isize;
+                tui.scroll_by(rows - 1);
+            }
+            (Key::PageDown, _) => {
+                let mut tui = lock(tui);
+                let rows = tui.pane_rows() as isize;
+                tui.scroll_by(1 - rows);
+            }
+            (_, Some(m)) => {
+                transmit(port, runtime.rs485, &m.payload)?;
+                let echo = if hex_mode { format_hex(&m.payload) } else { m.label.clone() };
+                sent = Some((m.payload.len(), echo));
+            }
+            (key, None) if lock(tui).char_mode => {
+                let bytes = key_bytes(key, runtime.line_ending);
+                transmit(port, runtime.rs485, &bytes)?;
+                lock(tui).tx_bytes += bytes.len() as u64;
+            }
+            (Key::Ctrl('d'), None) if editor.render().0.is_empty() => return Ok(()),
+            (key, None) => {
+                if let Some(line) = editor.handle(key) {
+                    match encode_line(line.clone(), runtime.line_ending, hex_mode) {
+                        Ok(bytes) => {
+                            transmit(port, runtime.rs485, &bytes)?;
+                            let echo = if hex_mode { format_hex(&bytes) } else { line };
+                            sent = Some((bytes.len(), echo));
+                        }
+                        Err(e) => lock(tui).notice = Some(format!("{:#}", e)),
+                    }
+                }
+            }
+        }
+
+        let mut tui = lock(tui);
+        if let Some((len, echo)) = sent {
+            tui.tx_bytes += len as u64;
+            if opts.local_echo {
+                tui.push_tx(echo);
+            }
+            tui.scroll = 0;
+        }
+        (tui.input, tui.cursor_back) = if tui.char_mode { (String::new(), 0) } else { editor.render() };
+        tui.draw()?;
+    }
+}
+
+/// 后台线程：持续读取串口并刷新界面，同时定期读取输入信号线
+fn spawn_receiver(
+    mut port: Box<dyn SerialPort>,
+    mut log: Option<RxLog>,
+    hex: Arc<AtomicBool>,
+    rx_buffer: usize,
+    stop: Arc<AtomicBool>,
+    tui: Arc<Mutex<Tui>>,
+) -> thread::JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        let mut buffer = vec![0u8; rx_buffer];
+        let mut last_poll = Instant::now() - SIGNAL_POLL;
+        while !stop.load(Ordering::Relaxed) {
+            let n = match port.read(&mut buffer) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => 0,
+                Err(e) => {
+                    // 主线程阻塞在键盘输入上，提示用户按键后退出
+                    stop.store(true, Ordering::Relaxed);
+                    let mut tui = lock(&tui);
+                    tui.notice = Some(format!("读取串口失败: {}，按任意键退出", e));
+                    tui.draw()?;
+                    return Err(e).context("读取串口失败");
+                }
+            };
+
+            let signals = if last_poll.elapsed() >= SIGNAL_POLL {
+                last_poll = Instant::now();
+                Some(ModemStatus::read(&mut port))
+            } else {
+                None
+            };
+
+            let mut tui = lock(&tui);
+            let resized = console::size().is_some_and(|size| size != tui.size);
+            let changed = signals.is_some_and(|s| s != tui.signals);
+            if let Some(signals) = signals {
+                tui.signals = signals;
+            }
+            if n > 0 {
+                if let Some(log) = log.as_mut() {
+                    log.write_raw(&buffer[..n])?;
+                }
+                tui.push_rx(&buffer[..n], hex.load(Ordering::Relaxed));
+            }
+            if n > 0 || resized || changed {
+                tui.draw()?;
+            }
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,470 @@
+//! u-blox UBX 协议：帧编解码、常用消息的字段解析，以及构造 CFG-RATE、CFG-PRT、CFG-MSG 等配置消息
+//! 并等待 ACK 的 ubx 子命令
+//!
+//! 帧结构：`B5 62 类别 ID 长度(2, 低字节在前) 载荷 CK_A CK_B`，
+//! 校验和为类别到载荷末尾的 8 位 Fletcher 校验（CK_A += 字节，CK_B += CK_A）。
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::events::{self, Event};
+use crate::format_hex;
+use crate::framing::{Deframer, FrameSpec};
+use crate::{parse_duration, parse_hex};
+use crate::signal::Rs485;
+use crate::terminal;
+
+/// ubx 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct UbxArgs {
+    #[command(subcommand)]
+    pub command: UbxCommand,
+
+    /// 等待 ACK 或查询应答的超时时间
+    #[arg(long, default_value = "1s", value_parser = parse_duration, global = true)]
+    pub timeout: Duration,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum UbxCommand {
+    /// 构造带正确校验和的消息并发送，配置消息（CFG）等待 ACK-ACK/ACK-NAK
+    #[command(subcommand)]
+    Send(UbxMessage),
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum UbxMessage {
+    /// CFG-RATE：设置导航解算周期，如 `ubx send rate --period 200ms` 为 5 Hz
+    Rate {
+        /// 测量周期（1ms~65s）
+        #[arg(long, value_parser = parse_duration)]
+        period: Duration,
+
+        /// 每几次测量输出一次导航解
+        #[arg(long, default_value_t = 1)]
+        nav_rate: u16,
+
+        /// 时间基准
+        #[arg(long, value_enum, default_value = "gps")]
+        time_ref: TimeRef,
+    },
+    /// CFG-PRT：设置串口的波特率和输入/输出协议，如 `ubx send prt --baud 115200 --out ubx`
+    Prt {
+        /// 接收机的端口号：1 为 UART1，2 为 UART2
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=2))]
+        port_id: u8,
+
+        /// 波特率
+        #[arg(long)]
+        baud: u32,
+
+        /// 输入协议，逗号分隔：ubx、nmea、rtcm3
+        #[arg(long, value_name = "PROTOCOLS", default_value = "ubx,nmea,rtcm3", value_parser = parse_protocols)]
+        r#in: u16,
+
+        /// 输出协议，逗号分隔：ubx、nmea、rtcm3
+        #[arg(long, value_name = "PROTOCOLS", default_value = "ubx,nmea", value_parser = parse_protocols)]
+        out: u16,
+    },
+    /// CFG-MSG：设置当前端口输出某条消息的速率（每几个导航解输出一次，0 为关闭），如 `ubx send msg NAV-PVT`
+    Msg {
+        /// 消息名（如 NAV-PVT、GGA 之类的 NMEA 语句写作 NMEA-GGA）或 类别:ID（如 0x01:0x07）
+        #[arg(value_parser = parse_message)]
+        message: (u8, u8),
+
+        /// 输出速率
+        #[arg(long, default_value_t = 1)]
+        rate: u8,
+    },
+    /// 任意消息：载荷为十六进制，不填为查询（等待同类别和 ID 的应答），如 `ubx send raw MON-VER`
+    Raw {
+        /// 消息名或 类别:ID
+        #[arg(value_parser = parse_message)]
+        message: (u8, u8),
+
+        /// 载荷（十六进制）
+        payload: Option<String>,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum TimeRef {
+    Utc,
+    Gps,
+    Glonass,
+    Beidou,
+    Galileo,
+}
+
+/// 协议掩码位
+const PROTOCOLS: [(&str, u16); 3] = [("ubx", 0x01), ("nmea", 0x02), ("rtcm3", 0x20)];
+
+fn parse_protocols(s: &str) -> std::result::Result<u16, String> {
+    s.split(',').try_fold(0, |mask, name| match PROTOCOLS.iter().find(|(p, _)| p.eq_ignore_ascii_case(name.trim())) {
+        Some((_, bit)) => Ok(mask | bit),
+        None => Err(format!("未知的协议 '{}'，应为 ubx、nmea 或 rtcm3", name)),
+    })
+}
+
+fn protocol_names(mask: u16) -> String {
+    let names: Vec<&str> = PROTOCOLS.iter().filter(|(_, bit)| mask & bit != 0).map(|(name, _)| *name).collect();
+    match names.is_empty() {
+        true => "无".to_string(),
+        false => names.join("+").to_uppercase(),
+    }
+}
+
+/// 常用消息的类别、ID 和名称
+const MESSAGES: &[(u8, u8, &str)] = &[
+    (0x01, 0x01, "NAV-POSECEF"),
+    (0x01, 0x02, "NAV-POSLLH"),
+    (0x01, 0x03, "NAV-STATUS"),
+    (0x01, 0x04, "NAV-DOP"),
+    (0x01, 0x07, "NAV-PVT"),
+    (0x01, 0x12, "NAV-VELNED"),
+    (0x01, 0x20, "NAV-TIMEGPS"),
+    (0x01, 0x21, "NAV-TIMEUTC"),
+    (0x01, 0x35, "NAV-SAT"),
+    (0x02, 0x13, "RXM-SFRBX"),
+    (0x02, 0x15, "RXM-RAWX"),
+    (0x04, 0x00, "INF-ERROR"),
+    (0x04, 0x01, "INF-WARNING"),
+    (0x04, 0x02, "INF-NOTICE"),
+    (0x04, 0x03, "INF-TEST"),
+    (0x04, 0x04, "INF-DEBUG"),
+    (0x05, 0x00, "ACK-NAK"),
+    (0x05, 0x01, "ACK-ACK"),
+    (0x06, 0x00, "CFG-PRT"),
+    (0x06, 0x01, "CFG-MSG"),
+    (0x06, 0x04, "CFG-RST"),
+    (0x06, 0x08, "CFG-RATE"),
+    (0x06, 0x09, "CFG-CFG"),
+    (0x06, 0x24, "CFG-NAV5"),
+    (0x06, 0x3E, "CFG-GNSS"),
+    (0x06, 0x8A, "CFG-VALSET"),
+    (0x06, 0x8B, "CFG-VALGET"),
+    (0x0A, 0x04, "MON-VER"),
+    (0x0A, 0x09, "MON-HW"),
+    (0x0D, 0x01, "TIM-TP"),
+    (0xF0, 0x00, "NMEA-GGA"),
+    (0xF0, 0x01, "NMEA-GLL"),
+    (0xF0, 0x02, "NMEA-GSA"),
+    (0xF0, 0x03, "NMEA-GSV"),
+    (0xF0, 0x04, "NMEA-RMC"),
+    (0xF0, 0x05, "NMEA-VTG"),
+    (0xF0, 0x08, "NMEA-ZDA"),
+];
+
+fn parse_message(s: &str) -> std::result::Result<(u8, u8), String> {
+    if let Some(&(class, id, _)) = MESSAGES.iter().find(|(_, _, name)| name.eq_ignore_ascii_case(s)) {
+        return Ok((class, id));
+    }
+    let byte = |v: &str| {
+        let v = v.trim();
+        let digits = v.strip_prefix("0x").or_else(|| v.strip_prefix("0X")).unwrap_or(v);
+        u8::from_str_radix(digits, 16).ok()
+    };
+    match s.split_once(':').map(|(class, id)| (byte(class), byte(id))) {
+        Some((Some(class), Some(id))) => Ok((class, id)),
+        _ => Err(format!("未知的消息 '{}'，应为消息名（如 NAV-PVT）或 类别:ID（如 0x01:0x07）", s)),
+    }
+}
+
+/// 消息名，不认识的显示为 类别:ID
+pub fn message_name(class: u8, id: u8) -> String {
+    match MESSAGES.iter().find(|(c, i, _)| *c == class && *i == id) {
+        Some((_, _, name)) => name.to_string(),
+        None => format!("{:02X}:{:02X}", class, id),
+    }
+}
+
+/// 同步字符
+pub const SYNC: [u8; 2] = [0xB5, 0x62];
+/// 同步字符、类别、ID 和长度
+pub const HEADER_LEN: usize = 6;
+/// 载荷长度上限（超出时视为误同步）
+pub const MAX_PAYLOAD: usize = 8192;
+
+/// 帧头中的载荷长度
+pub fn payload_len(frame: &[u8]) -> usize {
+    u16::from_le_bytes([frame[4], frame[5]]) as usize
+}
+
+fn checksum(data: &[u8]) -> [u8; 2] {
+    let (mut a, mut b) = (0u8, 0u8);
+    for &byte in data {
+        a = a.wrapping_add(byte);
+        b = b.wrapping_add(a);
+    }
+    [a, b]
+}
+
+/// 编码完整的 UBX 帧
+pub fn encode(class: u8, id: u8, payload: &[u8]) -> Vec<u8> {
+    let body = [&[class, id][..], &(payload.len() as u16).to_le_bytes(), payload].concat();
+    [&SYNC[..], &body, &checksum(&body)].concat()
+}
+
+/// 校验整帧的校验和
+pub fn check(frame: &[u8]) -> std::result::Result<(), String> {
+    let end = frame.len() - 2;
+    let expected = checksum(&frame[2..end]);
+    match frame[end..] == expected {
+        true => Ok(()),
+        false => Err(format!(
+            "校验和错误：收到 {}，应为 {}",
+            format_hex(&frame[end..]),
+            format_hex(&expected)
+        )),
+    }
+}
+
+/// 帧内容说明，如 "NAV-PVT 2024-05-01 08:30:00 3D 定位 卫星 12 纬度 31.2304000 经度 121.4737000 ..."
+pub fn describe(frame: &[u8]) -> String {
+    let (class, id) = (frame[2], frame[3]);
+    let payload = &frame[HEADER_LEN..frame.len() - 2];
+    let name = message_name(class, id);
+    match fields(class, id, payload) {
+        Some(fields) => format!("{} {}", name, fields),
+        None if payload.is_empty() => format!("{}（查询）", name),
+        None => format!("{} 载荷 {} 字节", name, payload.len()),
+    }
+}
+
+fn u16_at(p: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([p[offset], p[offset + 1]])
+}
+
+fn u32_at(p: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([p[offset], p[offset + 1], p[offset + 2], p[offset + 3]])
+}
+
+fn i32_at(p: &[u8], offset: usize) -> i32 {
+    u32_at(p, offset) as i32
+}
+
+/// 定长字符串字段（以 0 结尾）
+fn text_at(p: &[u8], range: std::ops::Range<usize>) -> String {
+    let bytes: Vec<u8> = p[range].iter().copied().take_while(|&b| b != 0).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// 常用消息的主要字段，载荷长度不符时返回 `None`
+fn fields(class: u8, id: u8, p: &[u8]) -> Option<String> {
+    let text = match (class, id, p.len()) {
+        (0x01, 0x07, 92) => {
+            let pvt = nav_pvt(p)?;
+            format!(
+                "{} {} {} 卫星 {} 纬度 {:.7} 经度 {:.7} 海拔 {:.2} m 水平精度 {:.2} m 速度 {:.1} km/h PDOP {:.2}",
+                pvt.date,
+                pvt.time,
+                pvt.fix,
+                pvt.satellites,
+                pvt.lat,
+                pvt.lon,
+                pvt.altitude,
+                pvt.accuracy,
+                pvt.speed,
+                pvt.pdop
+            )
+        }
+        (0x01, 0x04, 18) => format!(
+            "GDOP {:.2} PDOP {:.2} HDOP {:.2} VDOP {:.2}",
+            u16_at(p, 4) as f64 / 100.0,
+            u16_at(p, 6) as f64 / 100.0,
+            u16_at(p, 12) as f64 / 100.0,
+            u16_at(p, 10) as f64 / 100.0
+        ),
+        (0x01, 0x02, 28) => format!(
+            "纬度 {:.7} 经度 {:.7} 海拔 {:.2} m 水平精度 {:.2} m",
+            i32_at(p, 8) as f64 / 1e7,
+            i32_at(p, 4) as f64 / 1e7,
+            i32_at(p, 16) as f64 / 1000.0,
+            u32_at(p, 20) as f64 / 1000.0
+        ),
+        (0x01, 0x03, 16) => fix_type(p[4], p[5] & 0x01 != 0),
+        (0x04, _, _) => text_at(p, 0..p.len()),
+        (0x05, _, 2) => format!("{} {}", if id == 0x01 { "确认" } else { "拒绝" }, message_name(p[0], p[1])),
+        (0x06, 0x00, 20) => format!(
+            "端口 {} 波特率 {} 输入 {} 输出 {}",
+            p[0],
+            u32_at(p, 8),
+            protocol_names(u16_at(p, 12)),
+            protocol_names(u16_at(p, 14))
+        ),
+        (0x06, 0x01, 3) => format!("{} 速率 {}", message_name(p[0], p[1]), p[2]),
+        (0x06, 0x08, 6) => format!(
+            "周期 {} ms 导航速率 {} 时间基准 {}",
+            u16_at(p, 0),
+            u16_at(p, 2),
+            ["UTC", "GPS", "GLONASS", "北斗", "Galileo"].get(u16_at(p, 4) as usize).unwrap_or(&"未知")
+        ),
+        (0x0A, 0x04, n) if n >= 40 => {
+            let extensions: Vec<String> = p[40..].chunks_exact(30).map(|ext| text_at(ext, 0..30)).collect();
+            format!("软件 {} 硬件 {} {}", text_at(p, 0..30), text_at(p, 30..40), extensions.join(" "))
+        }
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// NAV-PVT 中的导航解
+pub struct NavPvt {
+    /// UTC 日期 YYYY-MM-DD 和时间 hh:mm:ss
+    pub date: String,
+    pub time: String,
+    pub fix: String,
+    /// 定位有效（2D/3D 且 gnssFixOK）
+    pub valid: bool,
+    pub satellites: u8,
+    pub lat: f64,
+    pub lon: f64,
+    /// 海拔（平均海平面以上，m）
+    pub altitude: f64,
+    /// 水平精度估计（m）
+    pub accuracy: f64,
+    /// 地速（km/h）和运动方向（°）
+    pub speed: f64,
+    pub heading: f64,
+    pub pdop: f64,
+}
+
+/// 解析 NAV-PVT 载荷
+pub fn nav_pvt(p: &[u8]) -> Option<NavPvt> {
+    if p.len() < 92 {
+        return None;
+    }
+    let ok = p[21] & 0x01 != 0;
+    Some(NavPvt {
+        date: format!("{:04}-{:02}-{:02}", u16_at(p, 4), p[6], p[7]),
+        time: format!("{:02}:{:02}:{:02}", p[8], p[9], p[10]),
+        fix: fix_type(p[20], ok),
+        valid: ok && matches!(p[20], 2..=4),
+        satellites: p[23],
+        lat: i32_at(p, 28) as f64 / 1e7,
+        lon: i32_at(p, 24) as f64 / 1e7,
+        altitude: i32_at(p, 36) as f64 / 1000.0,
+        accuracy: u32_at(p, 40) as f64 / 1000.0,
+        speed: i32_at(p, 60) as f64 * 3.6 / 1000.0,
+        heading: i32_at(p, 64) as f64 / 1e5,
+        pdop: u16_at(p, 76) as f64 / 100.0,
+    })
+}
+
+/// NAV-DOP 载荷中的 HDOP
+pub fn nav_dop_hdop(p: &[u8]) -> Option<f64> {
+    (p.len() >= 18).then(|| u16_at(p, 12) as f64 / 100.0)
+}
+
+fn fix_type(fix: u8, ok: bool) -> String {
+    let name = match fix {
+        0 => "未定位",
+        1 => "航位推算",
+        2 => "2D 定位",
+        3 => "3D 定位",
+        4 => "GNSS + 航位推算",
+        5 => "仅授时",
+        _ => return format!("定位类型 {}", fix),
+    };
+    match ok || fix == 0 {
+        true => name.to_string(),
+        false => format!("{}（无效）", name),
+    }
+}
+
+impl UbxMessage {
+    /// 消息的类别、ID 和载荷
+    fn build(&self) -> Result<(u8, u8, Vec<u8>)> {
+        let message = match self {
+            UbxMessage::Rate { period, nav_rate, time_ref } => {
+                let ms = period.as_millis();
+                if !(1..=u16::MAX as u128).contains(&ms) {
+                    bail!("测量周期 {:?} 超出范围（1ms~65s）", period);
+                }
+                let payload = [(ms as u16).to_le_bytes(), nav_rate.to_le_bytes(), (*time_ref as u16).to_le_bytes()].concat();
+                (0x06, 0x08, payload)
+            }
+            UbxMessage::Prt { port_id, baud, r#in, out } => {
+                // mode 0x08D0 为 8 位数据、无校验、1 位停止位
+                let payload = [
+                    &[*port_id, 0x00][..],
+                    &0u16.to_le_bytes(),
+                    &0x08D0u32.to_le_bytes(),
+                    &baud.to_le_bytes(),
+                    &r#in.to_le_bytes(),
+                    &out.to_le_bytes(),
+                    &[0; 4],
+                ]
+                .concat();
+                (0x06, 0x00, payload)
+            }
+            UbxMessage::Msg { message: (class, id), rate } => (0x06, 0x01, vec![*class, *id, *rate]),
+            UbxMessage::Raw { message: (class, id), payload } => {
+                let payload = match payload {
+                    Some(hex) => parse_hex(hex).with_context(|| format!("载荷 '{}' 不是十六进制", hex))?,
+                    None => Vec::new(),
+                };
+                (*class, *id, payload)
+            }
+        };
+        Ok(message)
+    }
+}
+
+/// 执行 ubx 子命令：发送消息，配置消息等待 ACK，查询等待应答
+pub fn run_ubx(port: &mut Box<dyn SerialPort>, opts: &UbxArgs, rs485: Option<Rs485>) -> Result<()> {
+    let UbxCommand::Send(message) = &opts.command;
+    let (class, id, payload) = message.build()?;
+    let frame = encode(class, id, &payload);
+    let name = message_name(class, id);
+    port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+    events::status(&format!("发送 {}：{}", describe(&frame), format_hex(&frame)));
+    terminal::transmit(port, rs485, &frame)?;
+
+    let mut need_ack = class == 0x06;
+    let mut need_reply = payload.is_empty();
+    if !need_ack && !need_reply {
+        return Ok(());
+    }
+    let mut deframer = Deframer::new(FrameSpec::Ubx);
+    let deadline = Instant::now() + opts.timeout;
+    let mut buffer = [0u8; 1024];
+    while Instant::now() < deadline {
+        let n = match port.read(&mut buffer) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e).context("读取串口失败"),
+        };
+        for received in deframer.push(&buffer[..n], Instant::now()) {
+            events::emit(Event::Rx(&received.data));
+            // 混在其中的 NMEA 语句和其他消息不显示
+            if received.error.is_some() || !received.data.starts_with(&SYNC) {
+                continue;
+            }
+            let data = &received.data;
+            match (data[2], data[3], &data[HEADER_LEN..data.len() - 2]) {
+                (0x05, ack, [c, i]) if need_ack && (*c, *i) == (class, id) => {
+                    if ack == 0x00 {
+                        bail!("接收机拒绝了 {}（ACK-NAK），请检查参数", name);
+                    }
+                    events::status(&format!("收到 {}", describe(data)));
+                    need_ack = false;
+                }
+                (c, i, _) if need_reply && (c, i) == (class, id) => {
+                    events::status(&format!("收到 {}", describe(data)));
+                    need_reply = false;
+                }
+                _ => {}
+            }
+            if !need_ack && !need_reply {
+                return Ok(());
+            }
+        }
+    }
+    match message {
+        UbxMessage::Prt { .. } => bail!("{:?} 内没有收到 {} 的 ACK（接收机切换波特率后应答可能丢失，请用新的波特率重新连接确认）", opts.timeout, name),
+        _ => bail!("{:?} 内没有收到 {} 的应答，请确认接收机已启用 UBX 协议输出且串口波特率正确", opts.timeout, name),
+    }
+}
@@ -0,0 +1,284 @@
+//! 按正则规则触发 webhook：收到的某一行匹配时，把该行和时间戳以 JSON POST 到指定的 URL
+//!
+//! 只支持 http://，不支持 https://；要发往 https 地址时，用本机的反向代理（如 nginx、stunnel）转发。
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::events;
+
+/// 连接和收发的超时
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// 没有换行的数据空闲多久后也当作一行检查
+const IDLE: Duration = Duration::from_millis(500);
+/// 一行的最大长度，超过时截断检查
+const MAX_LINE: usize = 4096;
+/// 等待发送的请求数上限，发得太慢时丢弃新的请求
+const QUEUE: usize = 100;
+
+/// 一条规则：匹配的行发往指定的 URL
+#[derive(Debug, Clone)]
+pub struct WebhookRule {
+    pattern: Regex,
+    url: HttpUrl,
+}
+
+/// http:// 地址（不支持 https://）
+#[derive(Debug, Clone)]
+struct HttpUrl {
+    text: String,
+    /// 主机[:端口]，用于 Host 头
+    host: String,
+    /// 连接的地址（主机:端口）
+    address: String,
+    /// 路径和查询参数
+    path: String,
+}
+
+fn parse_url(s: &str) -> std::result::Result<HttpUrl, String> {
+    let rest = match s.split_once("://") {
+        Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => rest,
+        Some((scheme, _)) if scheme.eq_ignore_ascii_case("https") => {
+            return Err("此版本不支持 https://，可以用本机的反向代理（如 nginx、stunnel）转发到 https 地址".to_string())
+        }
+        _ => return Err(format!("无效的 URL '{}'，应为 http://主机[:端口]/路径", s)),
+    };
+    let (host, path) = match rest.find(['/', '?']) {
+        Some(at) if rest[at..].starts_with('?') => (&rest[..at], format!("/{}", &rest[at..])),
+        Some(at) => (&rest[..at], rest[at..].to_string()),
+        None => (rest, "/".to_string()),
+    };
+    if host.is_empty() {
+        return Err(format!("URL '{}' 中没有主机名", s));
+    }
+    let has_port = match host.rsplit_once(']') {
+        Some((_, after)) => after.starts_with(':'),
+        None => host.contains(':'),
+    };
+    let address = if has_port { host.to_string() } else { format!("{}:80", host) };
+    Ok(HttpUrl { text: s.to_string(), host: host.to_string(), address, path })
+}
+
+/// 解析 "PATTERN=URL" 形式的规则（以第一个后面跟着 http:// 的 '=' 分隔，正则和 URL 中都可包含 '='）；
+/// 也按 https:// 分隔，以便提示不支持 https 而不是把整条规则当作正则
+pub fn parse_rule(s: &str) -> std::result::Result<WebhookRule, String> {
+    let at = ["=http://", "=https://"]
+        .iter()
+        .filter_map(|separator| s.to_ascii_lowercase().find(separator))
+        .min()
+        .ok_or_else(|| format!("无效的 webhook 规则 '{}'，应为 正则=http://主机/路径", s))?;
+    let (pattern, url) = (&s[..at], &s[at + 1..]);
+    let pattern = Regex::new(pattern).map_err(|e| format!("无效的正则 '{}': {}", pattern, e))?;
+    Ok(WebhookRule { pattern, url: parse_url(url)? })
+}
+
+/// 从规则文件读取：每行一条 "PATTERN=URL"，忽略空行和 # 注释
+pub fn load_rules(path: &Path) -> Result<Vec<WebhookRule>> {
+    let content = fs::read_to_string(path).with_context(|| format!("读取 webhook 规则文件 {} 失败", path.display()))?;
+
+    let mut rules = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_rule(line) {
+            Ok(rule) => rules.push(rule),
+            Err(e) => bail!("{} 第 {} 行：{}", path.display(), i + 1, e),
+        }
+    }
+    Ok(rules)
+}
+
+/// 解析 "Name: value" 形式的请求头
+pub fn parse_header(s: &str) -> std::result::Result<String, String> {
+    match s.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() && !s.contains(['\r', '\n']) => Ok(format!("{}: {}", name.trim(), value.trim())),
+        _ => Err(format!("无效的请求头 '{}'，应为 名称: 值（如 \"Authorization: Bearer xxx\"）", s)),
+    }
+}
+
+/// 待发送的请求：第几条规则触发的和请求内容
+struct Request {
+    rule: usize,
+    body: String,
+}
+
+/// 按规则检查收到的每一行，匹配时交给后台线程 POST，不阻塞监听
+pub struct Webhooks {
+    rules: Vec<WebhookRule>,
+    port: String,
+    /// 同一规则两次触发的最小间隔，期间的匹配只计数
+    cooldown: Option<Duration>,
+    /// 每条规则上次触发的时间和之后被略过的次数
+    fired: Vec<(Option<Instant>, u64)>,
+    line: Vec<u8>,
+    last_rx: Option<Instant>,
+    queue: Option<SyncSender<Request>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Webhooks {
+    pub fn new(rules: Vec<WebhookRule>, headers: Vec<String>, cooldown: Option<Duration>, port: &str) -> Self {
+        let (queue, requests) = mpsc::sync_channel::<Request>(QUEUE);
+        let urls: Vec<HttpUrl> = rules.iter().map(|rule| rule.url.clone()).collect();
+        let worker = thread::spawn(move || {
+            for request in requests {
+                let url = &urls[request.rule];
+                match post(url, &headers, &request.body) {
+                    Ok(200..300) => {}
+                    Ok(status) => events::status(&format!("[webhook {} 返回 HTTP {}]", url.text, status)),
+                    Err(e) => events::status(&format!("[webhook 发往 {} 失败: {:#}]", url.text, e)),
+                }
+            }
+        });
+        Webhooks {
+            fired: vec![(None, 0); rules.len()],
+            rules,
+            port: port.to_string(),
+            cooldown,
+            line: Vec::new(),
+            last_rx: None,
+            queue: Some(queue),
+            worker: Some(worker),
+        }
+    }
+
+    /// 追加收到的数据，检查其中所有完整的行
+    pub fn push(&mut self, data: &[u8], now: Instant) {
+        self.last_rx = Some(now);
+        for &byte in data {
+            match byte {
+                b'\n' => self.check_line(now),
+                _ => {
+                    self.line.push(byte);
+                    if self.line.len() >= MAX_LINE {
+                        self.check_line(now);
+                    }
+                }
+            }
+        }
+    }
+
+    /// 空闲超时后检查没有换行的剩余数据（如提示符）
+    pub fn poll_idle(&mut self, now: Instant) {
+        if self.last_rx.is_some_and(|last| now - last >= IDLE) && !self.line.is_empty() {
+            self.check_line(now);
+        }
+    }
+
+    fn check_line(&mut self, now: Instant) {
+        let raw = std::mem::take(&mut self.line);
+        let text = String::from_utf8_lossy(&raw);
+        let text = text.trim_end_matches('\r');
+        if text.is_empty() {
+            return;
+        }
+        for (index, (rule, (last, suppressed))) in self.rules.iter().zip(self.fired.iter_mut()).enumerate() {
+            if !rule.pattern.is_match(text) {
+                continue;
+            }
+            if let (Some(cooldown), Some(at)) = (self.cooldown, *last) {
+                if now - at < cooldown {
+                    *suppressed += 1;
+                    continue;
+                }
+            }
+            let body = format!(
+                "{{\"ts\":\"{}\",\"port\":{},\"pattern\":{},\"line\":{},\"suppressed\":{}}}",
+                jiff::Zoned::now().strftime("%Y-%m-%dT%H:%M:%S%.3f%:z"),
+                events::quote(&self.port),
+                events::quote(rule.pattern.as_str()),
+                events::quote(text),
+                suppressed
+            );
+            *last = Some(now);
+            *suppressed = 0;
+            let request = Request { rule: index, body };
+            if let Some(Err(TrySendError::Full(_))) = self.queue.as_ref().map(|queue| queue.try_send(request)) {
+                events::status(&format!("[webhook 发送太慢，丢弃发往 {} 的一次触发]", rule.url.text));
+            }
+        }
+    }
+}
+
+impl Drop for Webhooks {
+    /// 等待已触发的请求发完再退出
+    fn drop(&mut self) {
+        self.queue = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// 发送一个 POST 请求，返回 HTTP 状态码
+fn post(url: &HttpUrl, headers: &[String], body: &str) -> Result<u16> {
+    let addr = url
+        .address
+        .to_socket_addrs()
+        .with_context(|| format!("无法解析地址 {}", url.address))?
+        .next()
+        .with_context(|| format!("地址 {} 没有解析出任何 IP", url.address))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT).with_context(|| format!("连接 {} 失败", url.address))?;
+    stream.set_read_timeout(Some(TIMEOUT)).context("设置网络连接超时失败")?;
+    stream.set_write_timeout(Some(TIMEOUT)).context("设置网络连接超时失败")?;
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: serial-tool\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        url.path,
+        url.host,
+        body.len()
+    );
+    for header in headers {
+        request.push_str(header);
+        request.push_str("\r\n");
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+    stream.write_all(request.as_bytes()).context("发送请求失败")?;
+
+    // 只需要状态行
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 256];
+    while !response.windows(2).any(|w| w == b"\r\n") {
+        match stream.read(&mut buffer).context("读取应答失败")? {
+            0 => break,
+            n => response.extend_from_slice(&buffer[..n]),
+        }
+    }
+    let text = String::from_utf8_lossy(&response);
+    let status = text.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1).and_then(|code| code.parse().ok()) {
+        Some(code) if status.starts_with("HTTP/") => Ok(code),
+        _ => bail!("无效的应答 '{}'", status),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules() {
+        let rule = parse_rule("ALARM|x=1=http://incident.local:8080/hook?a=b").unwrap();
+        assert_eq!(rule.pattern.as_str(), "ALARM|x=1");
+        assert_eq!((rule.url.host.as_str(), rule.url.address.as_str(), rule.url.path.as_str()), ("incident.local:8080", "incident.local:8080", "/hook?a=b"));
+        let rule = parse_rule("FATAL=HTTP://[::1]?x").unwrap();
+        assert_eq!((rule.url.address.as_str(), rule.url.path.as_str()), ("[::1]:80", "/?x"));
+
+        assert!(parse_rule("ALARM=https://incident.example.com/hook").unwrap_err().contains("不支持 https"));
+        for rule in ["ALARM", "ALARM=ftp://x", "(=http://x", "ALARM=http://"] {
+            assert!(parse_rule(rule).is_err(), "{} 应解析失败", rule);
+        }
+        assert_eq!(parse_header(" X-Token :  abc ").unwrap(), "X-Token: abc");
+        assert!(parse_header("X-Token").is_err());
+        assert!(parse_header("X: a\r\nY: b").is_err());
+    }
+}
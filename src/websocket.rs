@@ -0,0 +1,379 @@
+//! WebSocket（RFC 6455）服务端：HTTP 升级握手和消息帧的编解码，供网络桥接让浏览器直接访问串口
+
+use anyhow::{bail, Context, Result};
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+/// 帧类型
+pub const CONTINUATION: u8 = 0x0;
+pub const TEXT: u8 = 0x1;
+pub const BINARY: u8 = 0x2;
+pub const CLOSE: u8 = 0x8;
+pub const PING: u8 = 0x9;
+pub const PONG: u8 = 0xA;
+
+/// 握手时计算 Sec-WebSocket-Accept 用的固定 GUID
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// 握手请求的最大长度
+const MAX_REQUEST: usize = 16 * 1024;
+/// 单条消息的最大长度，超过时断开
+const MAX_MESSAGE: usize = 1024 * 1024;
+/// 等待握手请求的时长
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 收到的一条完整消息
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// 读取客户端的 HTTP 升级请求并应答；不是 WebSocket 请求时回复 426 并报错
+/// （客户端在收到应答前不会发送消息，所以不会多读到请求之后的数据）
+pub fn handshake<S: Read + Write>(stream: &mut S) -> Result<()> {
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    let end = loop {
+        if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if request.len() > MAX_REQUEST {
+            bail!("握手请求过长");
+        }
+        if Instant::now() >= deadline {
+            bail!("等待握手请求超时");
+        }
+        match stream.read(&mut buffer) {
+            Ok(0) => bail!("对方在握手前关闭了连接"),
+            Ok(n) => request.extend_from_slice(&buffer[..n]),
+            Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e).context("读取握手请求失败"),
+        }
+    };
+    let text = String::from_utf8_lossy(&request[..end]);
+    let header = |name: &str| {
+        text.lines()
+            .skip(1)
+            .filter_map(|line| line.split_once(':'))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim().to_string())
+    };
+    let upgrade = header("Upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let key = header("Sec-WebSocket-Key");
+    let (true, Some(key)) = (upgrade, key) else {
+        let body = "这里是串口的 WebSocket 桥接，请用 WebSocket 客户端（如浏览器的 new WebSocket(\"ws://...\")）连接\n";
+        let response = format!(
+            "HTTP/1.1 426 Upgrade Required\r\nSec-WebSocket-Version: 13\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let _ = stream.write_all(response.as_bytes());
+        bail!("不是 WebSocket 请求（{}）", text.lines().next().unwrap_or_default());
+    };
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(&key)
+    );
+    stream.write_all(response.as_bytes()).context("发送握手应答失败")
+}
+
+/// 按 Sec-WebSocket-Key 计算 Sec-WebSocket-Accept
+fn accept_key(key: &str) -> String {
+    base64_encode(&sha1(format!("{}{}", key, GUID).as_bytes()))
+}
+
+/// 编码服务端发出的一帧（服务端发出的帧不加掩码）
+pub fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 10);
+    out.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => out.push(len as u8),
+        len @ 126..=0xFFFF => {
+            out.push(126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            out.push(127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(payload);
+    out
+}
+
+/// 从字节流中解出消息：拼接分片，应答 ping，收到 close 时回应并标记关闭
+#[derive(Default)]
+pub struct Decoder {
+    pending: Vec<u8>,
+    /// 正在接收的分片消息：类型和已收到的内容
+    fragments: Option<(u8, Vec<u8>)>,
+    /// 对方已发起关闭
+    pub closed: bool,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder::default()
+    }
+
+    /// 解出 `data` 中所有完整的消息，需要发回的帧（pong、close）追加到 `reply`
+    pub fn decode(&mut self, data: &[u8], reply: &mut Vec<u8>) -> Result<Vec<Message>> {
+        self.pending.extend_from_slice(data);
+        let mut messages = Vec::new();
+        while !self.closed {
+            let Some((fin, opcode, payload)) = self.next_frame()? else {
+                break;
+            };
+            match opcode {
+                PING => reply.extend(frame(PONG, &payload)),
+                PONG => {}
+                CLOSE => {
+                    // 回应时带回对方的状态码
+                    reply.extend(frame(CLOSE, payload.get(..2).unwrap_or_default()));
+                    self.closed = true;
+                }
+                TEXT | BINARY | CONTINUATION => {
+                    let (kind, mut content) = match (opcode, self.fragments.take()) {
+                        (CONTINUATION, Some(fragments)) => fragments,
+                        (CONTINUATION, None) => bail!("收到没有开头的分片"),
+                        (_, Some(_)) => bail!("上一条分片消息还没有结束"),
+                        (kind, None) => (kind, Vec::new()),
+                    };
+                    content.extend_from_slice(&payload);
+                    if content.len() > MAX_MESSAGE {
+                        bail!("消息超过 {} 字节", MAX_MESSAGE);
+                    }
+                    match (fin, kind) {
+                        (false, _) => self.fragments = Some((kind, content)),
+                        (true, TEXT) => messages.push(Message::Text(String::from_utf8_lossy(&content).into_owned())),
+                        (true, _) => messages.push(Message::Binary(content)),
+                    }
+                }
+                _ => bail!("未知的帧类型 0x{:X}", opcode),
+            }
+        }
+        Ok(messages)
+    }
+
+    /// 取出缓存中的一个完整帧：是否为最后一片、类型和去掉掩码后的内容
+    fn next_frame(&mut self) -> Result<Option<(bool, u8, Vec<u8>)>> {
+        let data = &self.pending;
+        if data.len() < 2 {
+            return Ok(None);
+        }
+        let fin = data[0] & 0x80 != 0;
+        let opcode = data[0] & 0x0F;
+        let masked = data[1] & 0x80 != 0;
+        let (len, mut at) = match data[1] & 0x7F {
+            126 if data.len() >= 4 => (u16::from_be_bytes([data[2], data[3]]) as u64, 4),
+            127 if data.len() >= 10 => (u64::from_be_bytes(data[2..10].try_into().unwrap()), 10),
+            126 | 127 => return Ok(None),
+            len => (len as u64, 2),
+        };
+        if len > MAX_MESSAGE as u64 {
+            bail!("消息超过 {} 字节", MAX_MESSAGE);
+        }
+        let mask = match masked {
+            true if data.len() >= at + 4 => {
+                at += 4;
+                Some([data[at - 4], data[at - 3], data[at - 2], data[at - 1]])
+            }
+            true => return Ok(None),
+            false => None,
+        };
+        let end = at + len as usize;
+        if data.len() < end {
+            return Ok(None);
+        }
+        let mut payload = data[at..end].to_vec();
+        if let Some(mask) = mask {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+        self.pending.drain(..end);
+        Ok(Some((fin, opcode, payload)))
+    }
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes(block[i * 4..i * 4 + 4].try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..20 => ((b & c) | (!b & d), 0x5A827999),
+                20..40 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..60 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, add) in h.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+    let mut out = [0u8; 20];
+    for (chunk, value) in out.chunks_mut(4).zip(h) {
+        chunk.copy_from_slice(&value.to_be_bytes());
+    }
+    out
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let value = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(ALPHABET[(value >> (18 - i * 6) & 0x3F) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(data: &[u8]) -> String {
+        data.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// 依次解码各段数据，返回解出的消息（文本消息加上 "text:" 前缀）和需要发回的帧
+    fn decode(chunks: &[&[u8]]) -> (Vec<String>, Vec<u8>) {
+        let mut decoder = Decoder::new();
+        let mut reply = Vec::new();
+        let mut messages = Vec::new();
+        for chunk in chunks {
+            for message in decoder.decode(chunk, &mut reply).unwrap() {
+                messages.push(match message {
+                    Message::Text(text) => format!("text:{}", text),
+                    Message::Binary(data) => hex(&data),
+                });
+            }
+        }
+        (messages, reply)
+    }
+
+    #[test]
+    fn digests() {
+        // FIPS 180-2 附录 A 的示例和空消息
+        assert_eq!(hex(&sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex(&sha1(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")), "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+        // RFC 4648 第 10 节
+        let encoded: Vec<String> = ["", "f", "fo", "foo", "foob", "fooba", "foobar"].iter().map(|s| base64_encode(s.as_bytes())).collect();
+        assert_eq!(encoded, ["", "Zg==", "Zm8=", "Zm9v", "Zm9vYg==", "Zm9vYmE=", "Zm9vYmFy"]);
+        // RFC 6455 第 1.3 节
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    /// 读取预先给定的请求、记录写出的应答
+    struct Mock {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Mock {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Mock {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn upgrade() {
+        let request = "GET /chat HTTP/1.1\r\nHost: server.example.com\r\nupgrade: WebSocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+        let mut stream = Mock { input: io::Cursor::new(request.as_bytes().to_vec()), output: Vec::new() };
+        handshake(&mut stream).unwrap();
+        let response = String::from_utf8(stream.output).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 "));
+        assert!(response.contains("\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        // 普通的 HTTP 请求回复 426
+        let mut stream = Mock { input: io::Cursor::new(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n".to_vec()), output: Vec::new() };
+        assert!(handshake(&mut stream).is_err());
+        assert!(stream.output.starts_with(b"HTTP/1.1 426 "));
+    }
+
+    #[test]
+    fn frames() {
+        // RFC 6455 第 5.7 节的示例
+        assert_eq!(frame(TEXT, b"Hello"), [0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+        assert_eq!(frame(BINARY, &[0; 256])[..4], [0x82, 0x7E, 0x01, 0x00]);
+        assert_eq!(frame(BINARY, &[0; 65536])[..10], [0x82, 0x7F, 0, 0, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(frame(BINARY, &[0; 125])[..2], [0x82, 0x7D]);
+        assert_eq!(frame(BINARY, &[0; 0xFFFF])[..4], [0x82, 0x7E, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn decode_frames() {
+        // 带掩码的 "Hello"，逐字节到达
+        let masked = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let chunks: Vec<&[u8]> = masked.chunks(1).collect();
+        assert_eq!(decode(&chunks), (vec!["text:Hello".to_string()], Vec::new()));
+
+        // 分片的 "Hel" + "lo"，中间插入的 ping 立即应答
+        let (messages, reply) = decode(&[&[0x01, 0x03, 0x48, 0x65, 0x6c], &[0x89, 0x02, 0x68, 0x69], &[0x80, 0x02, 0x6c, 0x6f]]);
+        assert_eq!(messages, ["text:Hello"]);
+        assert_eq!(reply, [0x8A, 0x02, 0x68, 0x69]);
+
+        // 16 位和 64 位扩展长度，长度字段本身也可以分两次到达
+        let mut long = vec![0x82, 0xFE, 0x01, 0x00, 1, 2, 3, 4];
+        long.extend((0..256).map(|i| (i as u8) ^ [1, 2, 3, 4][i % 4]));
+        let (messages, _) = decode(&[&long[..3], &long[3..]]);
+        assert_eq!(messages, [hex(&(0..=255).collect::<Vec<u8>>())]);
+        let mut long = vec![0x82, 0x7F, 0, 0, 0, 0, 0, 1, 0, 0];
+        long.extend(std::iter::repeat_n(0xAB, 65536));
+        let (messages, _) = decode(&[&long[..9], &long[9..]]);
+        assert_eq!(messages, [hex(&[0xAB; 65536])]);
+    }
+
+    #[test]
+    fn close_and_errors() {
+        // 带掩码的 close（状态码 1000），回应时带回状态码，之后的数据不再解码
+        let mut decoder = Decoder::new();
+        let mut reply = Vec::new();
+        let close = [0x88, 0x82, 1, 2, 3, 4, 0x03 ^ 1, 0xE8 ^ 2];
+        let messages = decoder.decode(&[&close[..], &[0x81, 0x01, 0x41]].concat(), &mut reply).unwrap();
+        assert!(messages.is_empty() && decoder.closed);
+        assert_eq!(reply, [0x88, 0x02, 0x03, 0xE8]);
+
+        let error = |data: &[u8]| Decoder::new().decode(data, &mut Vec::new()).is_err();
+        assert!(error(&[0x80, 0x00]), "没有开头的分片");
+        assert!(error(&[0x01, 0x00, 0x81, 0x00]), "分片消息未结束时开始新消息");
+        assert!(error(&[0x83, 0x00]), "未知的帧类型");
+        assert!(error(&[0x82, 0x7F, 0, 0, 0, 0, 0, 0x10, 0, 1]), "超过最大长度");
+    }
+}
@@ -0,0 +1,391 @@
+//! XBee API 帧：编解码（0x7E 起始符、2 字节长度、帧类型、校验和，API 2 模式的转义），
+//! 常用帧类型的字段解析，以及发送本地/远程 AT 命令和数据包的 xbee 子命令
+//!
+//! 帧结构：`7E 长度(2, 高字节在前) 帧数据 校验和`，校验和 = 0xFF - 帧数据字节和；
+//! API 2 模式下起始符之后的 7E、7D、11、13 转义为 `7D (字节 ^ 0x20)`。
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::events::{self, Event};
+use crate::format_hex;
+use crate::framing::{Deframer, FrameSpec};
+use crate::{parse_duration, parse_hex};
+use crate::signal::Rs485;
+use crate::terminal;
+
+/// xbee 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct XbeeArgs {
+    #[command(subcommand)]
+    pub command: XbeeCommand,
+
+    /// 模块工作在 API 2 模式（AP=2，带转义）
+    #[arg(long, global = true)]
+    pub escaped: bool,
+
+    /// 等待应答帧的超时时间
+    #[arg(long, default_value = "2s", value_parser = parse_duration, global = true)]
+    pub timeout: Duration,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum XbeeCommand {
+    /// 本地 AT 命令（帧类型 0x08），如 `xbee at NI`、`xbee at ID 3332`
+    At(AtCommand),
+    /// 远程 AT 命令（帧类型 0x17），如 `xbee remote-at --dest 0013A20040A1B2C3 D0 04`
+    RemoteAt {
+        /// 目标的 64 位地址（16 位十六进制）
+        #[arg(long, value_parser = parse_address64)]
+        dest: u64,
+
+        /// 远程设置后立即生效（否则需另发 AC 命令）
+        #[arg(long)]
+        apply: bool,
+
+        #[command(flatten)]
+        command: AtCommand,
+    },
+    /// 发送数据包（帧类型 0x10）并等待发送状态
+    Tx {
+        /// 目标的 64 位地址，默认为协调器；000000000000FFFF 为广播
+        #[arg(long, default_value = "0000000000000000", value_parser = parse_address64)]
+        dest: u64,
+
+        /// 要发送的数据（文本，配合 --hex 为十六进制）
+        data: String,
+
+        /// 数据为十六进制
+        #[arg(long)]
+        hex: bool,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+pub struct AtCommand {
+    /// 两个字母的 AT 命令，如 NI、ID、D0、WR
+    #[arg(value_parser = parse_at_name)]
+    pub name: String,
+
+    /// 参数：数值以十六进制书写（如 3332），配合 --text 为文本（如节点名）；不填为查询
+    pub param: Option<String>,
+
+    /// 参数为文本
+    #[arg(long)]
+    pub text: bool,
+}
+
+impl AtCommand {
+    fn bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = self.name.as_bytes().to_vec();
+        match &self.param {
+            Some(param) if self.text => bytes.extend_from_slice(param.as_bytes()),
+            Some(param) => {
+                // 数值参数可以是奇数位十六进制，按高位补 0 处理
+                let hex = if !param.len().is_multiple_of(2) { format!("0{}", param) } else { param.clone() };
+                bytes.extend(parse_hex(&hex).with_context(|| format!("AT 参数 '{}' 不是十六进制，文本参数请加 --text", param))?);
+            }
+            None => {}
+        }
+        Ok(bytes)
+    }
+}
+
+fn parse_at_name(s: &str) -> std::result::Result<String, String> {
+    match s.len() == 2 && s.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        true => Ok(s.to_ascii_uppercase()),
+        false => Err(format!("无效的 AT 命令 '{}'，应为两个字符，如 NI", s)),
+    }
+}
+
+fn parse_address64(s: &str) -> std::result::Result<u64, String> {
+    match s.len() == 16 {
+        true => u64::from_str_radix(s, 16).map_err(|_| format!("无效的 64 位地址 '{}'", s)),
+        false => Err(format!("无效的 64 位地址 '{}'，应为 16 位十六进制", s)),
+    }
+}
+
+/// 起始符
+pub const START: u8 = 0x7E;
+const ESCAPE: u8 = 0x7D;
+/// 帧数据最大长度
+const MAX_DATA: usize = 1024;
+
+fn needs_escape(byte: u8) -> bool {
+    matches!(byte, 0x7E | 0x7D | 0x11 | 0x13)
+}
+
+/// 按帧数据（帧类型及之后的字段）编码完整的 API 帧
+pub fn encode(data: &[u8], escaped: bool) -> Vec<u8> {
+    let checksum = 0xFF - data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    let body = [&(data.len() as u16).to_be_bytes()[..], data, &[checksum]].concat();
+    let mut frame = vec![START];
+    for byte in body {
+        if escaped && needs_escape(byte) {
+            frame.extend_from_slice(&[ESCAPE, byte ^ 0x20]);
+        } else {
+            frame.push(byte);
+        }
+    }
+    frame
+}
+
+/// 从 7E 开头的数据中取一帧的结果
+pub enum Take {
+    /// 数据还不完整
+    Incomplete,
+    /// 消耗的字节数和去掉转义后的整帧（可能因遇到下一个起始符而被截断，需再经 `check` 校验）
+    Frame(usize, Vec<u8>),
+    /// 长度无效，丢弃起始符重新对齐
+    Invalid,
+}
+
+/// 从 7E 开头的数据中取出一帧并去掉转义
+pub fn take_frame(pending: &[u8], escaped: bool) -> Take {
+    let mut frame = vec![START];
+    let mut i = 1;
+    while i < pending.len() {
+        let mut byte = pending[i];
+        // API 2 模式下数据中不会出现未转义的起始符，出现说明上一帧不完整
+        if escaped && byte == START {
+            return Take::Frame(i, frame);
+        }
+        if escaped && byte == ESCAPE {
+            let Some(&next) = pending.get(i + 1) else { return Take::Incomplete };
+            byte = next ^ 0x20;
+            i += 1;
+        }
+        frame.push(byte);
+        i += 1;
+        if frame.len() >= 3 {
+            let len = u16::from_be_bytes([frame[1], frame[2]]) as usize;
+            if len == 0 || len > MAX_DATA {
+                return Take::Invalid;
+            }
+            if frame.len() == len + 4 {
+                return Take::Frame(i, frame);
+            }
+        }
+    }
+    Take::Incomplete
+}
+
+/// 校验去掉转义后的整帧的长度和校验和
+pub fn check(frame: &[u8]) -> std::result::Result<(), String> {
+    if frame.len() < 5 {
+        return Err("XBee 帧不完整".to_string());
+    }
+    let len = u16::from_be_bytes([frame[1], frame[2]]) as usize;
+    if frame.len() != len + 4 {
+        return Err(format!("XBee 帧不完整：长度字段为 {}，实际 {} 字节", len, frame.len().saturating_sub(4)));
+    }
+    let sum = frame[3..].iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    match sum == 0xFF {
+        true => Ok(()),
+        false => Err(format!("XBee 校验和错误：应为 {:02X}", 0xFF - (sum.wrapping_sub(frame[frame.len() - 1])))),
+    }
+}
+
+/// 整帧的帧数据部分（帧类型到校验和之前）
+pub fn frame_data(frame: &[u8]) -> &[u8] {
+    &frame[3..frame.len() - 1]
+}
+
+fn frame_type_name(frame_type: u8) -> &'static str {
+    match frame_type {
+        0x00 => "64 位地址发送请求",
+        0x01 => "16 位地址发送请求",
+        0x08 => "AT 命令",
+        0x09 => "AT 命令（排队）",
+        0x10 => "发送请求",
+        0x11 => "显式寻址发送请求",
+        0x17 => "远程 AT 命令",
+        0x80 => "64 位地址接收",
+        0x81 => "16 位地址接收",
+        0x88 => "AT 命令应答",
+        0x89 => "发送状态（旧格式）",
+        0x8A => "模块状态",
+        0x8B => "发送状态",
+        0x90 => "接收数据包",
+        0x91 => "显式寻址接收",
+        0x92 => "IO 采样",
+        0x95 => "节点标识",
+        0x97 => "远程 AT 命令应答",
+        _ => "未知帧类型",
+    }
+}
+
+fn at_status(status: u8) -> String {
+    match status {
+        0 => "成功".to_string(),
+        1 => "错误".to_string(),
+        2 => "无效命令".to_string(),
+        3 => "无效参数".to_string(),
+        4 => "远程发送失败".to_string(),
+        status => format!("状态 0x{:02X}", status),
+    }
+}
+
+fn delivery_status(status: u8) -> String {
+    match status {
+        0x00 => "成功".to_string(),
+        0x01 => "MAC 层无确认".to_string(),
+        0x02 => "CCA 失败".to_string(),
+        0x15 => "目的端点无效".to_string(),
+        0x21 => "网络层无确认".to_string(),
+        0x22 => "未加入网络".to_string(),
+        0x23 => "自寻址".to_string(),
+        0x24 => "找不到地址".to_string(),
+        0x25 => "找不到路由".to_string(),
+        0x74 => "数据过长".to_string(),
+        status => format!("状态 0x{:02X}", status),
+    }
+}
+
+fn modem_status(status: u8) -> &'static str {
+    match status {
+        0x00 => "硬件复位",
+        0x01 => "看门狗复位",
+        0x02 => "已加入网络",
+        0x03 => "已退出网络",
+        0x06 => "协调器已启动",
+        0x07 => "网络安全密钥已更新",
+        0x0D => "电压超出范围",
+        0x11 => "配置在加入网络时已更改",
+        _ => "其他状态",
+    }
+}
+
+fn address64(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// AT 命令的值：可打印文本同时显示文本
+fn at_value(value: &[u8]) -> String {
+    if value.is_empty() {
+        return String::new();
+    }
+    match value.iter().all(|b| (0x20..0x7F).contains(b)) {
+        true => format!("，值 {}（\"{}\"）", format_hex(value), String::from_utf8_lossy(value)),
+        false => format!("，值 {}", format_hex(value)),
+    }
+}
+
+fn payload(data: &[u8]) -> String {
+    match data.iter().all(|b| (0x20..0x7F).contains(b) || matches!(b, b'\r' | b'\n')) && !data.is_empty() {
+        true => format!("，数据 \"{}\"", String::from_utf8_lossy(data).escape_debug()),
+        false => format!("，数据 {}", format_hex(data)),
+    }
+}
+
+/// 帧数据的说明，如 "AT 命令应答 #1 NI 成功，值 ..."；字段不足时只给出帧类型
+pub fn describe(data: &[u8]) -> String {
+    let frame_type = data[0];
+    let name = frame_type_name(frame_type);
+    let fields = &data[1..];
+    let detail = match (frame_type, fields) {
+        (0x08 | 0x09, [id, a, b, param @ ..]) => {
+            Some(format!(" #{} {}{}{}", id, *a as char, *b as char, at_value(param)))
+        }
+        (0x10, [id, rest @ ..]) if rest.len() >= 12 => Some(format!(" #{} → {}{}", id, address64(&rest[..8]), payload(&rest[12..]))),
+        (0x17, [id, rest @ ..]) if rest.len() >= 13 => Some(format!(
+            " #{} → {} {}{}{}",
+            id,
+            address64(&rest[..8]),
+            rest[11] as char,
+            rest[12] as char,
+            at_value(&rest[13..])
+        )),
+        (0x88, [id, a, b, status, value @ ..]) => {
+            Some(format!(" #{} {}{} {}{}", id, *a as char, *b as char, at_status(*status), at_value(value)))
+        }
+        (0x8A, [status, ..]) => Some(format!(" {}（0x{:02X}）", modem_status(*status), status)),
+        (0x8B, [id, _, _, retries, delivery, ..]) => {
+            Some(format!(" #{} {}，重试 {} 次", id, delivery_status(*delivery), retries))
+        }
+        (0x90, rest) if rest.len() >= 11 => Some(format!(" ← {}{}", address64(&rest[..8]), payload(&rest[11..]))),
+        (0x91, rest) if rest.len() >= 17 => Some(format!(
+            " ← {} 端点 {:02X}→{:02X} 簇 {:04X} 规范 {:04X}{}",
+            address64(&rest[..8]),
+            rest[10],
+            rest[11],
+            u16::from_be_bytes([rest[12], rest[13]]),
+            u16::from_be_bytes([rest[14], rest[15]]),
+            payload(&rest[17..])
+        )),
+        (0x97, [id, rest @ ..]) if rest.len() >= 13 => Some(format!(
+            " #{} ← {} {}{} {}{}",
+            id,
+            address64(&rest[..8]),
+            rest[10] as char,
+            rest[11] as char,
+            at_status(rest[12]),
+            at_value(&rest[13..])
+        )),
+        _ => None,
+    };
+    format!("{}{}", name, detail.unwrap_or_default())
+}
+
+/// 执行 xbee 子命令：发送请求帧并等待对应帧 ID 的应答
+pub fn run_xbee(port: &mut Box<dyn SerialPort>, opts: &XbeeArgs, rs485: Option<Rs485>) -> Result<()> {
+    const FRAME_ID: u8 = 1;
+    let (request, response_type) = match &opts.command {
+        XbeeCommand::At(command) => ([&[0x08, FRAME_ID][..], &command.bytes()?].concat(), 0x88),
+        XbeeCommand::RemoteAt { dest, apply, command } => {
+            let options = if *apply { 0x02 } else { 0x00 };
+            let header = [&[0x17, FRAME_ID][..], &dest.to_be_bytes(), &[0xFF, 0xFE, options]].concat();
+            ([header, command.bytes()?].concat(), 0x97)
+        }
+        XbeeCommand::Tx { dest, data, hex } => {
+            let data = match hex {
+                true => parse_hex(data)?,
+                false => data.as_bytes().to_vec(),
+            };
+            let header = [&[0x10, FRAME_ID][..], &dest.to_be_bytes(), &[0xFF, 0xFE, 0x00, 0x00]].concat();
+            ([header, data].concat(), 0x8B)
+        }
+    };
+
+    port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+    events::status(&format!("发送 {}", describe(&request)));
+    terminal::transmit(port, rs485, &encode(&request, opts.escaped))?;
+
+    let mut deframer = Deframer::new(FrameSpec::Xbee { escaped: opts.escaped });
+    let deadline = Instant::now() + opts.timeout;
+    let mut buffer = [0u8; 256];
+    while Instant::now() < deadline {
+        let n = match port.read(&mut buffer) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e).context("读取串口失败"),
+        };
+        for frame in deframer.push(&buffer[..n], Instant::now()) {
+            events::emit(Event::Rx(&frame.data));
+            if let Some(error) = &frame.error {
+                events::status(&format!("收到无效的帧 {}：{}", format_hex(&frame.data), error));
+                continue;
+            }
+            let data = frame_data(&frame.data);
+            let description = describe(data);
+            if data[0] != response_type || data.get(1) != Some(&FRAME_ID) {
+                events::status(&format!("收到 {}", description));
+                continue;
+            }
+            // AT 应答的状态字节和发送状态的投递状态
+            let status = match response_type {
+                0x88 => data.get(4),
+                0x97 => data.get(14),
+                _ => data.get(5),
+            };
+            if status != Some(&0) {
+                bail!("{}", description);
+            }
+            events::status(&format!("收到 {}", description));
+            return Ok(());
+        }
+    }
+    bail!("{:?} 内没有收到应答，请确认模块工作在 API 模式（AP=1，带转义时 AP=2 并加 --escaped）且串口波特率正确", opts.timeout)
+}
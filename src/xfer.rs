@@ -0,0 +1,418 @@
+//! 文件传输：通过 XMODEM/YMODEM/ZMODEM/Kermit 向 bootloader、路由器等设备发送文件，或接收设备发来的文件
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::events;
+use crate::kermit::Kermit;
+use crate::parse_duration;
+use crate::progress::Progress;
+use crate::signal::Rs485;
+use crate::xmodem::{self, Block, Link, BLOCK_TIMEOUT, SUB};
+use crate::ymodem;
+use crate::zmodem::Zmodem;
+
+/// xfer 子命令参数
+#[derive(clap::Args, Debug)]
+pub struct XferArgs {
+    #[command(subcommand)]
+    pub command: XferCommand,
+
+    /// 传输协议
+    #[arg(long, value_enum, default_value = "xmodem", global = true)]
+    pub proto: Proto,
+
+    /// 等待对方开始传输的时长
+    #[arg(long, default_value = "60s", value_name = "DURATION", value_parser = parse_duration, global = true)]
+    pub start_timeout: Duration,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum XferCommand {
+    /// 发送文件（等待接收方发出 'C' 或 NAK 后开始）；YMODEM、ZMODEM 和 Kermit 可以一次发送多个文件
+    Send {
+        /// 要发送的文件
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// 使用 1K 数据块（XMODEM-1K，需要接收方使用 CRC 校验；YMODEM 总是使用 1K 块）
+        #[arg(long = "1k")]
+        one_k: bool,
+
+        /// 请求接收方续传（ZMODEM：接收方已有部分文件时从其长度处继续发送）
+        #[arg(long)]
+        resume: bool,
+    },
+    /// 接收文件并保存
+    Receive {
+        /// 保存的文件（XMODEM），或保存收到的各文件的目录（YMODEM、ZMODEM、Kermit，默认为当前目录）
+        path: Option<PathBuf>,
+
+        /// 使用累加和校验（只支持原始 XMODEM 的发送方；默认请求 CRC 校验，没有回应时自动改用累加和）
+        #[arg(long)]
+        no_crc: bool,
+
+        /// 去掉文件末尾的 0x1A 填充（XMODEM 不传送文件长度，最后一块总是填满；YMODEM 按文件头中的长度截断）
+        #[arg(long)]
+        trim: bool,
+
+        /// 续传已存在的同名文件（ZMODEM：从已有的长度处继续接收；发送方也可以请求续传）
+        #[arg(long)]
+        resume: bool,
+    },
+}
+
+/// 传输协议
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Proto {
+    Xmodem,
+    /// 批量传输，带文件名和长度
+    Ymodem,
+    /// 流式批量传输，出错时从出错位置重发，支持断点续传
+    Zmodem,
+    /// 停等式批量传输，只用可打印字符，适合老式仪器和网络设备的 ROM 监控程序
+    Kermit,
+}
+
+/// 执行 xfer 子命令
+pub fn run_xfer(port: &mut Box<dyn SerialPort>, opts: &XferArgs, rs485: Option<Rs485>) -> Result<()> {
+    port.clear(serialport::ClearBuffer::Input).context("清空接收缓冲区失败")?;
+    let mut link = Link::new(port, rs485);
+    match (&opts.command, opts.proto) {
+        (XferCommand::Send { files, one_k, .. }, Proto::Xmodem) => {
+            let [file] = files.as_slice() else {
+                bail!("XMODEM 一次只能发送一个文件，发送多个文件请使用 --proto ymodem");
+            };
+            let data = fs::read(file).with_context(|| format!("读取文件 {} 失败", file.display()))?;
+            events::status(&format!("等待接收方开始接收 {}（{} 字节）...", file.display(), data.len()));
+            let crc = xmodem::wait_start(&mut link, opts.start_timeout)?;
+            // 累加和模式的接收方（原始 XMODEM）不支持 1K 块
+            let one_k = *one_k && crc;
+            let mut progress = Progress::new(Some(data.len()));
+            xmodem::send_data(&mut link, &data, 1, one_k, crc, &mut |done| progress.update(done))?;
+            xmodem::send_eot(&mut link)?;
+            progress.finish();
+            events::status(&format!("发送完成：{}，{}", file.display(), progress.summary(data.len())));
+        }
+        (XferCommand::Send { files, .. }, Proto::Ymodem) => send_ymodem(&mut link, files, opts.start_timeout)?,
+        (XferCommand::Send { files, resume, .. }, Proto::Zmodem) => send_zmodem(&mut link, files, opts.start_timeout, *resume)?,
+        (XferCommand::Send { files, .. }, Proto::Kermit) => send_kermit(&mut link, files, opts.start_timeout)?,
+        (XferCommand::Receive { path, no_crc, trim, .. }, Proto::Xmodem) => {
+            let Some(file) = path else {
+                bail!("XMODEM 不传送文件名，请指定保存的文件");
+            };
+            events::status("等待发送方开始发送...");
+            let (first, crc) = xmodem::start_receive(&mut link, !no_crc, true, opts.start_timeout)?;
+            let mut progress = Progress::new(None);
+            let mut data = xmodem::receive_data(&mut link, first, 1, crc, &mut |done| progress.update(done))?;
+            progress.finish();
+            if *trim {
+                let len = data.iter().rposition(|&b| b != SUB).map_or(0, |pos| pos + 1);
+                data.truncate(len);
+            }
+            fs::write(file, &data).with_context(|| format!("写入文件 {} 失败", file.display()))?;
+            events::status(&format!("接收完成：{}，{}", file.display(), progress.summary(data.len())));
+        }
+        (XferCommand::Receive { path, .. }, Proto::Ymodem) => receive_ymodem(&mut link, path.as_deref().unwrap_or(Path::new(".")), opts.start_timeout)?,
+        (XferCommand::Receive { path, resume, .. }, Proto::Zmodem) => {
+            let dir = path.as_deref().unwrap_or(Path::new("."));
+            if !dir.is_dir() {
+                bail!("{} 不是目录，ZMODEM 按发送方的文件名保存，请指定保存的目录", dir.display());
+            }
+            events::status("等待发送方开始发送...");
+            receive_zmodem(&mut link, dir, opts.start_timeout, *resume)?
+        }
+        (XferCommand::Receive { path, .. }, Proto::Kermit) => receive_kermit(&mut link, path.as_deref().unwrap_or(Path::new(".")), opts.start_timeout)?,
+    }
+    Ok(())
+}
+
+/// YMODEM 发送：每个文件先发文件头（块 0），接收方再次发出 'C' 后发送数据，最后以空文件头结束
+fn send_ymodem(link: &mut Link, files: &[PathBuf], start_timeout: Duration) -> Result<()> {
+    let mut wait = start_timeout;
+    for file in files {
+        let data = fs::read(file).with_context(|| format!("读取文件 {} 失败", file.display()))?;
+        let name = file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let mtime = fs::metadata(file).and_then(|meta| meta.modified()).ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok());
+        let header = ymodem::header(&name, data.len() as u64, mtime.map_or(0, |time| time.as_secs()));
+
+        events::status(&format!("等待接收方开始接收 {}（{} 字节）...", name, data.len()));
+        if !xmodem::wait_start(link, wait)? {
+            bail!("接收方请求累加和校验（NAK），对方可能只支持 XMODEM，请使用 --proto xmodem");
+        }
+        xmodem::send_block(link, &xmodem::encode_block(0, &header, header.len(), true))?;
+        xmodem::wait_start(link, BLOCK_TIMEOUT)?;
+        let mut progress = Progress::new(Some(data.len()));
+        xmodem::send_data(link, &data, 1, true, true, &mut |done| progress.update(done))?;
+        xmodem::send_eot(link)?;
+        progress.finish();
+        events::status(&format!("发送完成：{}，{}", name, progress.summary(data.len())));
+        wait = BLOCK_TIMEOUT;
+    }
+    xmodem::wait_start(link, BLOCK_TIMEOUT)?;
+    xmodem::send_block(link, &xmodem::encode_block(0, &ymodem::end_of_batch(), 128, true))?;
+    if files.len() > 1 {
+        events::status(&format!("批量发送完成：共 {} 个文件", files.len()));
+    }
+    Ok(())
+}
+
+/// YMODEM 接收：按文件头中的文件名保存到目录，直到收到空文件头
+fn receive_ymodem(link: &mut Link, dir: &Path, start_timeout: Duration) -> Result<()> {
+    if !dir.is_dir() {
+        bail!("{} 不是目录，YMODEM 按发送方的文件名保存，请指定保存的目录", dir.display());
+    }
+    events::status("等待发送方开始发送...");
+    let mut count = 0;
+    let mut wait = start_timeout;
+    while let Some(header) = receive_header(link, wait)? {
+        wait = BLOCK_TIMEOUT;
+        let Some(file) = local_path(dir, &header.name) else {
+            link.cancel()?;
+            bail!("无效的文件名 '{}'，已取消传输", header.name);
+        };
+        events::status(&format!("接收 {}（{}）...", file.display(), header.size.map_or("长度未知".to_string(), |size| format!("{} 字节", size))));
+
+        let (first, _) = xmodem::start_receive(link, true, false, BLOCK_TIMEOUT)?;
+        // 最后一块的填充不计入进度
+        let total = header.size.map(|size| size as usize);
+        let mut progress = Progress::new(total);
+        let mut data = xmodem::receive_data(link, first, 1, true, &mut |done| progress.update(total.map_or(done, |total| done.min(total))))?;
+        progress.finish();
+        if let Some(size) = header.size {
+            data.truncate(size as usize);
+        }
+        fs::write(&file, &data).with_context(|| format!("写入文件 {} 失败", file.display()))?;
+        events::status(&format!("接收完成：{}，{}", file.display(), progress.summary(data.len())));
+        count += 1;
+    }
+    events::status(&format!("批量接收完成：共 {} 个文件", count));
+    Ok(())
+}
+
+/// 发送方提供的文件名在保存目录中的路径；只取文件名部分，不允许写到目录之外
+fn local_path(dir: &Path, name: &str) -> Option<PathBuf> {
+    Path::new(&name.replace('\\', "/")).file_name().map(|name| dir.join(name))
+}
+
+/// ZMODEM 发送：对方是 shell 时会自动启动 rz
+fn send_zmodem(link: &mut Link, files: &[PathBuf], start_timeout: Duration, resume: bool) -> Result<()> {
+    let mut zmodem = Zmodem::new(link);
+    events::status("等待接收方开始 ZMODEM 接收...");
+    zmodem.start_send(start_timeout)?;
+    for file in files {
+        let data = fs::read(file).with_context(|| format!("读取文件 {} 失败", file.display()))?;
+        let name = file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let mtime = fs::metadata(file).and_then(|meta| meta.modified()).ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok());
+        events::status(&format!("发送 {}（{} 字节）...", name, data.len()));
+        let Some(start) = zmodem.offer(&name, data.len(), mtime.map_or(0, |time| time.as_secs()), resume)? else {
+            events::status(&format!("接收方跳过了 {}", name));
+            continue;
+        };
+        if start > 0 {
+            events::status(&format!("接收方已有 {} 字节，从该处续传", start));
+        }
+        let mut progress = Progress::resumed(Some(data.len()), start as usize);
+        zmodem.send_file(&data, start, &mut |done| progress.update(done))?;
+        progress.finish();
+        events::status(&format!("发送完成：{}，{}", name, progress.summary(data.len().saturating_sub(start as usize))));
+    }
+    zmodem.finish_send()?;
+    if files.len() > 1 {
+        events::status(&format!("批量发送完成：共 {} 个文件", files.len()));
+    }
+    Ok(())
+}
+
+/// ZMODEM 接收：按发送方的文件名保存到目录，`resume` 时续传已存在的同名文件。
+/// 监听时检测到对方执行 sz 也调用这里
+pub fn receive_zmodem(link: &mut Link, dir: &Path, start_timeout: Duration, resume: bool) -> Result<()> {
+    let mut zmodem = Zmodem::new(link);
+    let mut count = 0;
+    let mut wait = start_timeout;
+    while let Some(offer) = zmodem.next_file(wait)? {
+        wait = BLOCK_TIMEOUT;
+        let Some(file) = local_path(dir, &offer.name) else {
+            events::status(&format!("跳过无效的文件名 '{}'", offer.name));
+            zmodem.skip()?;
+            continue;
+        };
+        // 已有的部分不超过发送方的文件长度时才续传，否则重新接收
+        let existing = fs::metadata(&file).map(|meta| meta.len()).ok();
+        let offset = match existing {
+            Some(len) if (resume || offer.resume) && offer.size.is_none_or(|size| len <= size) => len,
+            _ => 0,
+        };
+        let mut out = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(offset > 0)
+            .truncate(offset == 0)
+            .open(&file)
+            .with_context(|| format!("创建文件 {} 失败", file.display()))?;
+        let size = offer.size.map_or("长度未知".to_string(), |size| format!("{} 字节", size));
+        match offset {
+            0 => events::status(&format!("接收 {}（{}）...", file.display(), size)),
+            _ => events::status(&format!("接收 {}（{}），从 {} 字节处续传...", file.display(), size, offset)),
+        }
+
+        let mut progress = Progress::resumed(offer.size.map(|size| size as usize), offset as usize);
+        let end = zmodem.receive_file(offset, &mut out, &mut |done| progress.update(done))?;
+        progress.finish();
+        events::status(&format!("接收完成：{}，{}", file.display(), progress.summary((end - offset) as usize)));
+        count += 1;
+    }
+    events::status(&format!("接收结束：共 {} 个文件", count));
+    Ok(())
+}
+
+/// Kermit 发送：先用 S 包协商参数，再逐个发送文件，最后发送 B 包结束
+fn send_kermit(link: &mut Link, files: &[PathBuf], start_timeout: Duration) -> Result<()> {
+    let mut kermit = Kermit::new(link);
+    events::status("等待接收方开始 Kermit 接收...");
+    kermit.start_send(start_timeout)?;
+    for file in files {
+        let data = fs::read(file).with_context(|| format!("读取文件 {} 失败", file.display()))?;
+        let name = file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        events::status(&format!("发送 {}（{} 字节）...", name, data.len()));
+        let mut progress = Progress::new(Some(data.len()));
+        kermit.send_file(&name, &data, &mut |done| progress.update(done))?;
+        progress.finish();
+        events::status(&format!("发送完成：{}，{}", name, progress.summary(data.len())));
+    }
+    kermit.finish_send()?;
+    if files.len() > 1 {
+        events::status(&format!("批量发送完成：共 {} 个文件", files.len()));
+    }
+    Ok(())
+}
+
+/// Kermit 接收：按发送方的文件名保存到目录，直到收到 B 包
+fn receive_kermit(link: &mut Link, dir: &Path, start_timeout: Duration) -> Result<()> {
+    if !dir.is_dir() {
+        bail!("{} 不是目录，Kermit 按发送方的文件名保存，请指定保存的目录", dir.display());
+    }
+    events::status("等待发送方开始发送...");
+    let mut kermit = Kermit::new(link);
+    let mut count = 0;
+    while let Some(name) = kermit.next_file(start_timeout)? {
+        let Some(file) = local_path(dir, &name) else {
+            bail!("无效的文件名 '{}'", name);
+        };
+        let mut out = fs::File::create(&file).with_context(|| format!("创建文件 {} 失败", file.display()))?;
+        events::status(&format!("接收 {}...", file.display()));
+        let mut progress = Progress::new(None);
+        let len = kermit.receive_file(&mut out, &mut |done| progress.update(done))?;
+        progress.finish();
+        events::status(&format!("接收完成：{}，{}", file.display(), progress.summary(len as usize)));
+        count += 1;
+    }
+    events::status(&format!("批量接收完成：共 {} 个文件", count));
+    Ok(())
+}
+
+/// 请求并接收文件头（块 0），整批结束时返回 None
+fn receive_header(link: &mut Link, wait: Duration) -> Result<Option<ymodem::Header>> {
+    for _ in 0..xmodem::RETRIES {
+        match xmodem::start_receive(link, true, false, wait)?.0 {
+            Block::Data { number: 0, data } => {
+                link.send(&[xmodem::ACK])?;
+                return Ok(ymodem::parse_header(&data));
+            }
+            Block::Data { number, .. } => {
+                link.cancel()?;
+                bail!("应为文件头（块 0），收到块 {}，已取消传输", number);
+            }
+            Block::Cancelled => bail!("发送方取消了传输"),
+            // 上一个文件的 EOT 重发，或块有误：再次发送 'C' 请求
+            Block::Eot => link.send(&[xmodem::ACK])?,
+            Block::Timeout | Block::Bad(_) => {}
+        }
+    }
+    link.cancel()?;
+    bail!("连续 {} 次没有收到有效的文件头，已取消传输", xmodem::RETRIES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xmodem::tests::{pipe, sample};
+    use std::thread;
+
+    type Sender = fn(&mut Link, &[PathBuf], Duration) -> Result<()>;
+    type Receiver = fn(&mut Link, &Path, Duration) -> Result<()>;
+
+    /// 在两个线程中批量发送两个文件（其中一个为空），检查接收目录中的文件内容
+    fn batch(name: &str, send: Sender, receive: Receiver) {
+        let root = std::env::temp_dir().join(format!("serial-tool-{}-{}", name, std::process::id()));
+        let (src, dst) = (root.join("src"), root.join("dst"));
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&dst).unwrap();
+        let files = [("data.bin", sample(5000)), ("empty.txt", Vec::new())];
+        let paths: Vec<PathBuf> = files.iter().map(|(name, data)| {
+            let path = src.join(name);
+            fs::write(&path, data).unwrap();
+            path
+        }).collect();
+
+        let (mut a, mut b) = pipe();
+        thread::scope(|scope| {
+            let sender = scope.spawn(|| send(&mut Link::new(&mut a, None), &paths, Duration::from_secs(5)));
+            receive(&mut Link::new(&mut b, None), &dst, Duration::from_secs(5)).unwrap();
+            sender.join().unwrap().unwrap();
+        });
+        for (name, data) in &files {
+            assert_eq!(&fs::read(dst.join(name)).unwrap(), data, "{}", name);
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn ymodem_batch() {
+        // 按文件头中的长度去掉最后一块的填充
+        batch("ymodem", send_ymodem, receive_ymodem);
+    }
+
+    #[test]
+    fn zmodem_batch() {
+        batch("zmodem", |link, files, wait| send_zmodem(link, files, wait, false), |link, dir, wait| receive_zmodem(link, dir, wait, false));
+    }
+
+    #[test]
+    fn zmodem_resume() {
+        // 接收方已有 3000 字节：用 ZRPOS 告知长度，发送方从该处继续，已有的部分不会被覆盖
+        let root = std::env::temp_dir().join(format!("serial-tool-resume-{}", std::process::id()));
+        fs::create_dir_all(root.join("dst")).unwrap();
+        let data = sample(10000);
+        let file = root.join("fw.bin");
+        fs::write(&file, &data).unwrap();
+        fs::write(root.join("dst/fw.bin"), [0; 3000]).unwrap();
+
+        let (mut a, mut b) = pipe();
+        thread::scope(|scope| {
+            let sender = scope.spawn(|| send_zmodem(&mut Link::new(&mut a, None), std::slice::from_ref(&file), Duration::from_secs(5), false));
+            receive_zmodem(&mut Link::new(&mut b, None), &root.join("dst"), Duration::from_secs(5), true).unwrap();
+            sender.join().unwrap().unwrap();
+        });
+        let received = fs::read(root.join("dst/fw.bin")).unwrap();
+        assert_eq!((received.len(), &received[..3000], &received[3000..]), (data.len(), &[0; 3000][..], &data[3000..]));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn kermit_batch() {
+        batch("kermit", send_kermit, receive_kermit);
+    }
+
+    #[test]
+    fn local_paths() {
+        let dir = Path::new("out");
+        assert_eq!(local_path(dir, "a.bin"), Some(dir.join("a.bin")));
+        assert_eq!(local_path(dir, "../../etc/passwd"), Some(dir.join("passwd")));
+        assert_eq!(local_path(dir, "C:\\fw\\image.hex"), Some(dir.join("image.hex")));
+        assert_eq!(local_path(dir, ".."), None);
+    }
+}
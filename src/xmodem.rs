@@ -0,0 +1,522 @@
+//! XMODEM 协议：128 字节块（SOH）和 1K 块（STX，XMODEM-1K），CRC-16 或累加和校验
+//!
+//! 接收方先发送 'C'（请求 CRC 校验）或 NAK（累加和校验），发送方逐块发送
+//! `SOH/STX 块号 块号反码 数据 校验`，收到 ACK 后发下一块，收到 NAK 时重发，
+//! 全部发完后发送 EOT。任一方连续发送两个 CAN 即取消传输。
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::checksum::crc16_xmodem;
+use crate::events::{self, Event};
+use crate::signal::Rs485;
+use crate::terminal;
+
+pub const SOH: u8 = 0x01;
+pub const STX: u8 = 0x02;
+pub const EOT: u8 = 0x04;
+pub const ACK: u8 = 0x06;
+pub const NAK: u8 = 0x15;
+pub const CAN: u8 = 0x18;
+/// 接收方请求 CRC 校验
+pub const CRC_MODE: u8 = b'C';
+/// 最后一块不足时的填充字节
+pub const SUB: u8 = 0x1A;
+
+/// 每块（及 EOT）的最多发送或请求重发次数
+pub const RETRIES: usize = 10;
+/// 等待对方确认或下一块的时长
+pub const BLOCK_TIMEOUT: Duration = Duration::from_secs(10);
+/// 接收方发送 'C' 或 NAK 的间隔
+const START_INTERVAL: Duration = Duration::from_secs(3);
+/// 发送几次 'C' 没有回应后改用累加和校验
+const CRC_ATTEMPTS: usize = 4;
+
+/// 传输用的串口连接
+pub struct Link<'a> {
+    port: &'a mut Box<dyn SerialPort>,
+    rs485: Option<Rs485>,
+}
+
+impl<'a> Link<'a> {
+    pub fn new(port: &'a mut Box<dyn SerialPort>, rs485: Option<Rs485>) -> Self {
+        Link { port, rs485 }
+    }
+
+    pub fn send(&mut self, bytes: &[u8]) -> Result<()> {
+        terminal::transmit(self.port, self.rs485, bytes)
+    }
+
+    /// 读取一个字节，超时返回 None
+    pub fn read_byte(&mut self, timeout: Duration) -> Result<Option<u8>> {
+        Ok(self.read_exact(1, timeout)?.map(|bytes| bytes[0]))
+    }
+
+    /// 读取指定长度的数据，超时返回 None
+    pub fn read_exact(&mut self, len: usize, timeout: Duration) -> Result<Option<Vec<u8>>> {
+        let deadline = Instant::now() + timeout;
+        let mut data = vec![0u8; len];
+        let mut filled = 0;
+        while filled < len {
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            match self.port.read(&mut data[filled..]) {
+                Ok(n) => {
+                    events::emit(Event::Rx(&data[filled..filled + n]));
+                    filled += n;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e).context("读取串口失败"),
+            }
+        }
+        Ok(Some(data))
+    }
+
+    /// 是否有已收到、还未读取的数据
+    pub fn pending(&mut self) -> Result<bool> {
+        Ok(self.port.bytes_to_read().context("读取接收缓冲区状态失败")? > 0)
+    }
+
+    /// 丢弃对方还在发送的数据（出错的块剩余的部分），直到线路空闲
+    pub fn purge(&mut self) -> Result<()> {
+        while self.read_exact(1, Duration::from_millis(200))?.is_some() {}
+        Ok(())
+    }
+
+    /// 通知对方取消传输
+    pub fn cancel(&mut self) -> Result<()> {
+        self.send(&[CAN; 3])
+    }
+
+    /// 刚收到一个 CAN 后，再收到一个即为取消
+    fn cancelled(&mut self) -> Result<bool> {
+        Ok(self.read_byte(Duration::from_secs(1))? == Some(CAN))
+    }
+}
+
+/// 组装一个数据块，不足一块时用 SUB 填充
+pub fn encode_block(number: u8, data: &[u8], size: usize, crc: bool) -> Vec<u8> {
+    let mut block = vec![if size == 1024 { STX } else { SOH }, number, !number];
+    let start = block.len();
+    block.extend_from_slice(data);
+    block.resize(start + size, SUB);
+    match crc {
+        true => block.extend(crc16_xmodem(&block[start..]).to_be_bytes()),
+        false => block.push(block[start..].iter().fold(0u8, |sum, &b| sum.wrapping_add(b))),
+    }
+    block
+}
+
+/// 发送方等待接收方开始：收到 'C' 时使用 CRC 校验，收到 NAK 时使用累加和校验
+pub fn wait_start(link: &mut Link, wait: Duration) -> Result<bool> {
+    let deadline = Instant::now() + wait;
+    while Instant::now() < deadline {
+        match link.read_byte(Duration::from_millis(100))? {
+            Some(CRC_MODE) => return Ok(true),
+            Some(NAK) => return Ok(false),
+            Some(CAN) if link.cancelled()? => bail!("接收方取消了传输"),
+            _ => {}
+        }
+    }
+    bail!("{:?} 内接收方没有开始接收（没有收到 'C' 或 NAK），请先在设备上启动接收", wait)
+}
+
+/// 逐块发送数据，块号从 `first` 开始，每块确认后以已发送的字节数回调
+pub fn send_data(link: &mut Link, data: &[u8], first: u8, one_k: bool, crc: bool, progress: &mut dyn FnMut(usize)) -> Result<()> {
+    let mut number = first;
+    let mut offset = 0;
+    while offset < data.len() {
+        // 剩余不超过 128 字节时改用小块，减少填充
+        let size = if one_k && data.len() - offset > 128 { 1024 } else { 128 };
+        let end = (offset + size).min(data.len());
+        send_block(link, &encode_block(number, &data[offset..end], size, crc))?;
+        offset = end;
+        number = number.wrapping_add(1);
+        progress(offset);
+    }
+    Ok(())
+}
+
+/// 发送一块并等待确认，NAK 或超时时重发
+pub fn send_block(link: &mut Link, block: &[u8]) -> Result<()> {
+    for _ in 0..RETRIES {
+        link.send(block)?;
+        let deadline = Instant::now() + BLOCK_TIMEOUT;
+        while Instant::now() < deadline {
+            match link.read_byte(Duration::from_millis(100))? {
+                Some(ACK) => return Ok(()),
+                Some(NAK) => break,
+                Some(CAN) if link.cancelled()? => bail!("接收方取消了传输"),
+                // 接收方开始时多发的 'C' 等
+                _ => {}
+            }
+        }
+    }
+    link.cancel()?;
+    bail!("块 {} 发送 {} 次仍未被确认，已取消传输", block[1], RETRIES)
+}
+
+/// 发送 EOT 结束传输（部分接收方对第一个 EOT 回复 NAK）
+pub fn send_eot(link: &mut Link) -> Result<()> {
+    for _ in 0..RETRIES {
+        link.send(&[EOT])?;
+        match link.read_byte(BLOCK_TIMEOUT)? {
+            Some(ACK) => return Ok(()),
+            Some(CAN) if link.cancelled()? => bail!("接收方取消了传输"),
+            _ => {}
+        }
+    }
+    bail!("发送 EOT {} 次仍未被确认", RETRIES)
+}
+
+/// 收到的一个块
+pub enum Block {
+    Data { number: u8, data: Vec<u8> },
+    Eot,
+    Cancelled,
+    Timeout,
+    /// 块有误，说明原因
+    Bad(String),
+}
+
+/// 接收一个块，`crc` 为当前使用的校验方式
+pub fn receive_block(link: &mut Link, crc: bool, timeout: Duration) -> Result<Block> {
+    let size = match link.read_byte(timeout)? {
+        None => return Ok(Block::Timeout),
+        Some(SOH) => 128,
+        Some(STX) => 1024,
+        Some(EOT) => return Ok(Block::Eot),
+        Some(CAN) if link.cancelled()? => return Ok(Block::Cancelled),
+        Some(byte) => return Ok(Block::Bad(format!("意外的字节 {:02X}", byte))),
+    };
+    let Some(rest) = link.read_exact(2 + size + if crc { 2 } else { 1 }, Duration::from_secs(1))? else {
+        return Ok(Block::Bad("块不完整".to_string()));
+    };
+    let number = rest[0];
+    if rest[1] != !number {
+        return Ok(Block::Bad(format!("块号 {:02X} 与反码 {:02X} 不符", number, rest[1])));
+    }
+    let data = &rest[2..2 + size];
+    let check = &rest[2 + size..];
+    let valid = match crc {
+        true => check == crc16_xmodem(data).to_be_bytes(),
+        false => check[0] == data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)),
+    };
+    if !valid {
+        return Ok(Block::Bad(format!("块 {} {}错误", number, if crc { "CRC " } else { "校验和" })));
+    }
+    Ok(Block::Data { number, data: data.to_vec() })
+}
+
+/// 接收方开始传输：每隔几秒发送 'C'（或 NAK），返回收到的第一个块和最终使用的校验方式；
+/// `fallback` 时几次 'C' 没有回应即改用累加和校验，兼容只支持原始 XMODEM 的发送方
+pub fn start_receive(link: &mut Link, crc: bool, fallback: bool, wait: Duration) -> Result<(Block, bool)> {
+    let deadline = Instant::now() + wait;
+    let mut crc = crc;
+    let mut attempts = 0;
+    while Instant::now() < deadline {
+        link.send(&[if crc { CRC_MODE } else { NAK }])?;
+        attempts += 1;
+        match receive_block(link, crc, START_INTERVAL.min(deadline.saturating_duration_since(Instant::now())))? {
+            Block::Timeout => {
+                if crc && fallback && attempts >= CRC_ATTEMPTS {
+                    crc = false;
+                }
+            }
+            block => return Ok((block, crc)),
+        }
+    }
+    bail!("{:?} 内发送方没有开始发送，请先在设备上启动发送", wait)
+}
+
+/// 接收数据块直到 EOT，`first` 为已收到的第一个块，块号从 `number` 开始；每块以已接收的字节数回调
+pub fn receive_data(link: &mut Link, first: Block, number: u8, crc: bool, progress: &mut dyn FnMut(usize)) -> Result<Vec<u8>> {
+    let mut received = Vec::new();
+    let mut expected = number;
+    let mut errors = 0;
+    let mut block = first;
+    loop {
+        match block {
+            Block::Data { number, data } if number == expected => {
+                received.extend_from_slice(&data);
+                expected = expected.wrapping_add(1);
+                errors = 0;
+                link.send(&[ACK])?;
+                progress(received.len());
+            }
+            // 上一块的 ACK 没有送达，发送方重发
+            Block::Data { number, .. } if number == expected.wrapping_sub(1) => link.send(&[ACK])?,
+            Block::Data { number, .. } => {
+                link.cancel()?;
+                bail!("块号错误：收到 {}，应为 {}，已取消传输", number, expected);
+            }
+            Block::Eot => {
+                link.send(&[ACK])?;
+                return Ok(received);
+            }
+            Block::Cancelled => bail!("发送方取消了传输"),
+            Block::Timeout | Block::Bad(_) => {
+                errors += 1;
+                if errors >= RETRIES {
+                    link.cancel()?;
+                    let reason = match block {
+                        Block::Bad(reason) => reason,
+                        _ => "等待超时".to_string(),
+                    };
+                    bail!("连续 {} 次接收失败（{}），已取消传输", RETRIES, reason);
+                }
+                link.purge()?;
+                link.send(&[NAK])?;
+            }
+        }
+        block = receive_block(link, crc, BLOCK_TIMEOUT)?;
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use serialport::{ClearBuffer, DataBits, FlowControl, Parity, StopBits};
+    use std::collections::VecDeque;
+    use std::io::{Read, Write};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+
+    use crate::lock;
+
+    /// 一个方向上的数据
+    #[derive(Default)]
+    struct Channel {
+        data: Mutex<VecDeque<u8>>,
+        ready: Condvar,
+    }
+
+    /// 内存中的串口：写入的数据从另一端读出
+    struct PipePort {
+        rx: Arc<Channel>,
+        tx: Arc<Channel>,
+        timeout: Duration,
+    }
+
+    /// 一对相连的串口，供传输协议的测试在两个线程中分别作为发送方和接收方
+    pub fn pipe() -> (Box<dyn SerialPort>, Box<dyn SerialPort>) {
+        let (a, b) = (Arc::new(Channel::default()), Arc::new(Channel::default()));
+        let timeout = Duration::from_millis(10);
+        (Box::new(PipePort { rx: a.clone(), tx: b.clone(), timeout }), Box::new(PipePort { rx: b, tx: a, timeout }))
+    }
+
+    impl Read for PipePort {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut data = lock(&self.rx.data);
+            if data.is_empty() {
+                data = self.rx.ready.wait_timeout(data, self.timeout).unwrap_or_else(|e| e.into_inner()).0;
+            }
+            if data.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "读取超时"));
+            }
+            let n = buf.len().min(data.len());
+            for (slot, byte) in buf.iter_mut().zip(data.drain(..n)) {
+                *slot = byte;
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for PipePort {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            lock(&self.tx.data).extend(buf);
+            self.tx.ready.notify_all();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SerialPort for PipePort {
+        fn name(&self) -> Option<String> {
+            Some("pipe".to_string())
+        }
+        fn baud_rate(&self) -> serialport::Result<u32> {
+            Ok(115200)
+        }
+        fn data_bits(&self) -> serialport::Result<DataBits> {
+            Ok(DataBits::Eight)
+        }
+        fn flow_control(&self) -> serialport::Result<FlowControl> {
+            Ok(FlowControl::None)
+        }
+        fn parity(&self) -> serialport::Result<Parity> {
+            Ok(Parity::None)
+        }
+        fn stop_bits(&self) -> serialport::Result<StopBits> {
+            Ok(StopBits::One)
+        }
+        fn timeout(&self) -> Duration {
+            self.timeout
+        }
+        fn set_baud_rate(&mut self, _: u32) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn set_data_bits(&mut self, _: DataBits) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn set_flow_control(&mut self, _: FlowControl) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn set_parity(&mut self, _: Parity) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn set_stop_bits(&mut self, _: StopBits) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+            self.timeout = timeout;
+            Ok(())
+        }
+        fn write_request_to_send(&mut self, _: bool) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn write_data_terminal_ready(&mut self, _: bool) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+            Ok(true)
+        }
+        fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+            Ok(true)
+        }
+        fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+            Ok(false)
+        }
+        fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+            Ok(true)
+        }
+        fn bytes_to_read(&self) -> serialport::Result<u32> {
+            Ok(lock(&self.rx.data).len() as u32)
+        }
+        fn bytes_to_write(&self) -> serialport::Result<u32> {
+            Ok(0)
+        }
+        fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+            if matches!(buffer_to_clear, ClearBuffer::Input | ClearBuffer::All) {
+                lock(&self.rx.data).clear();
+            }
+            Ok(())
+        }
+        fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+            Ok(Box::new(PipePort { rx: self.rx.clone(), tx: self.tx.clone(), timeout: self.timeout }))
+        }
+        fn set_break(&self) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn clear_break(&self) -> serialport::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// 测试数据：每个字节值都出现，长度不是块长度的整数倍
+    pub fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+    }
+
+    #[test]
+    fn block_layout() {
+        // 128 字节块：SOH、块号、反码、SUB 填充的数据，累加和或 CRC-16/XMODEM（大端）
+        let block = encode_block(1, b"Hello", 128, false);
+        assert_eq!((block.len(), &block[..8], block[131]), (132, &[SOH, 0x01, 0xFE, b'H', b'e', b'l', b'l', b'o'][..], 0x72));
+        assert!(block[8..131].iter().all(|&b| b == SUB));
+        let block = encode_block(1, b"Hello", 128, true);
+        assert_eq!((block.len(), &block[131..]), (133, &[0x14, 0x43][..]));
+        // 1K 块以 STX 开头，块号回绕
+        let data: Vec<u8> = (0..1024).map(|i| i as u8).collect();
+        let block = encode_block(0xFF, &data, 1024, true);
+        assert_eq!((block.len(), &block[..3], &block[1027..]), (1029, &[STX, 0xFF, 0x00][..], &[0xC2, 0xE0][..]));
+    }
+
+    #[test]
+    fn receive_blocks() {
+        let (mut a, mut b) = pipe();
+        let mut sender = Link::new(&mut a, None);
+        let mut receiver = Link::new(&mut b, None);
+        let mut corrupt = encode_block(3, b"abc", 128, true);
+        corrupt[10] ^= 1;
+        let mut wrong_complement = encode_block(4, b"abc", 128, true);
+        wrong_complement[2] = 0;
+        for block in [encode_block(2, b"abc", 128, true), corrupt, wrong_complement, encode_block(5, &[1; 1024], 1024, false), vec![EOT]] {
+            sender.send(&block).unwrap();
+        }
+        let summary: Vec<String> = [true, true, true, false, true]
+            .into_iter()
+            .map(|crc| match receive_block(&mut receiver, crc, Duration::from_secs(1)).unwrap() {
+                Block::Data { number, data } => format!("{}:{}", number, data.len()),
+                Block::Bad(_) => "bad".to_string(),
+                Block::Eot => "eot".to_string(),
+                Block::Cancelled => "can".to_string(),
+                Block::Timeout => "timeout".to_string(),
+            })
+            .collect();
+        assert_eq!(summary, ["2:128", "bad", "bad", "5:1024", "eot"]);
+        assert!(matches!(receive_block(&mut receiver, true, Duration::from_millis(50)).unwrap(), Block::Timeout));
+        sender.send(&[CAN, CAN]).unwrap();
+        assert!(matches!(receive_block(&mut receiver, true, Duration::from_secs(1)).unwrap(), Block::Cancelled));
+    }
+
+    /// 在两个线程中按指定的方式发送和接收，返回收到的数据
+    fn transfer(data: &[u8], one_k: bool, crc: bool) -> Vec<u8> {
+        let (mut a, mut b) = pipe();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut link = Link::new(&mut a, None);
+                let crc = wait_start(&mut link, Duration::from_secs(5)).unwrap();
+                send_data(&mut link, data, 1, one_k, crc, &mut |_| {}).unwrap();
+                send_eot(&mut link).unwrap();
+            });
+            let mut link = Link::new(&mut b, None);
+            let (first, crc_used) = start_receive(&mut link, crc, false, Duration::from_secs(5)).unwrap();
+            assert_eq!(crc_used, crc);
+            receive_data(&mut link, first, 1, crc, &mut |_| {}).unwrap()
+        })
+    }
+
+    #[test]
+    fn round_trip() {
+        // 最后一块不足时用 SUB 填满；1K 模式剩余不超过 128 字节时改用小块
+        let data = sample(300 * 256 + 5);
+        for (one_k, crc) in [(false, false), (false, true), (true, true)] {
+            let received = transfer(&data, one_k, crc);
+            assert_eq!(&received[..data.len()], data, "1K: {}，CRC: {}", one_k, crc);
+            assert!(received[data.len()..].iter().all(|&b| b == SUB));
+            assert_eq!(received.len(), data.len().next_multiple_of(128));
+        }
+        assert!(transfer(&[], true, true).is_empty());
+    }
+
+    #[test]
+    fn retransmit_bad_block() {
+        // 接收方对校验错误的块回复 NAK，发送方重发后继续
+        let good = encode_block(1, b"data", 128, true);
+        let mut bad = good.clone();
+        bad[5] ^= 0xFF;
+        let (mut a, mut b) = pipe();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut link = Link::new(&mut a, None);
+                assert!(wait_start(&mut link, Duration::from_secs(5)).unwrap());
+                link.send(&bad).unwrap();
+                assert_eq!(link.read_byte(Duration::from_secs(5)).unwrap(), Some(NAK));
+                send_block(&mut link, &good).unwrap();
+                send_eot(&mut link).unwrap();
+            });
+            let mut link = Link::new(&mut b, None);
+            let (first, _) = start_receive(&mut link, true, false, Duration::from_secs(5)).unwrap();
+            assert!(matches!(first, Block::Bad(_)));
+            let received = receive_data(&mut link, first, 1, true, &mut |_| {}).unwrap();
+            assert_eq!(&received[..4], b"data");
+        });
+    }
+}
@@ -0,0 +1,67 @@
+//! YMODEM 批量传输：在 XMODEM-1K（CRC 校验）的基础上，每个文件前先发送块 0 作为文件头，
+//! 内容为 `文件名\0长度 修改时间(八进制)`，后面用 0 填充；文件名为空的块 0 表示整批结束。
+//! 接收方确认块 0 后再发送 'C' 请求文件数据，与 lrzsz（sb/rb）和 SecureCRT 的实现一致。
+
+/// 文件头
+pub struct Header {
+    pub name: String,
+    /// 文件长度，用于去掉最后一块的填充（部分发送方不提供）
+    pub size: Option<u64>,
+}
+
+/// 组装块 0 的内容，用 0 填满 128 字节（文件名较长时为 1K）
+pub fn header(name: &str, size: u64, mtime: u64) -> Vec<u8> {
+    let mut data = format!("{}\0{} {:o}\0", name, size, mtime).into_bytes();
+    data.resize(if data.len() > 128 { 1024 } else { 128 }, 0);
+    data
+}
+
+/// 结束整批传输的块 0（全为 0）
+pub fn end_of_batch() -> Vec<u8> {
+    vec![0; 128]
+}
+
+/// 解析块 0，文件名为空时（整批结束）返回 None
+pub fn parse_header(data: &[u8]) -> Option<Header> {
+    let mut fields = data.split(|&b| b == 0);
+    let name = String::from_utf8_lossy(fields.next().unwrap_or_default()).into_owned();
+    if name.is_empty() {
+        return None;
+    }
+    let size = fields
+        .next()
+        .and_then(|info| String::from_utf8_lossy(info).split_whitespace().next().and_then(|size| size.parse().ok()));
+    Some(Header { name, size })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xmodem::encode_block;
+
+    #[test]
+    fn block_zero() {
+        // 与 lrzsz 的 sb 相同：文件名、长度、八进制修改时间，0 填充到 128 字节，块号 0，CRC 校验
+        let data = header("foo.bin", 1234, 1600000000);
+        assert_eq!((data.len(), &data[..25]), (128, &b"foo.bin\x001234 13727410000\x00"[..]));
+        assert!(data[25..].iter().all(|&b| b == 0));
+        let block = encode_block(0, &data, data.len(), true);
+        assert_eq!((&block[..3], block.len()), (&[0x01, 0x00, 0xFF][..], 133));
+        // 全 0 的结束块 CRC 为 0
+        assert_eq!(&encode_block(0, &end_of_batch(), 128, true)[131..], [0, 0]);
+        // 文件名较长时使用 1K 块
+        assert_eq!(header(&"a".repeat(125), 1, 0).len(), 1024);
+    }
+
+    #[test]
+    fn parse_headers() {
+        let parsed = parse_header(&header("foo.bin", 1234, 1600000000)).unwrap();
+        assert_eq!((parsed.name.as_str(), parsed.size), ("foo.bin", Some(1234)));
+        // 只有文件名、长度后没有修改时间，或长度不是数字
+        let parsed = parse_header(b"a.txt\x0042\x00\x00").unwrap();
+        assert_eq!((parsed.name.as_str(), parsed.size), ("a.txt", Some(42)));
+        assert_eq!(parse_header(b"a.txt\x00\x00").unwrap().size, None);
+        assert_eq!(parse_header(b"a.txt\x00x1 0\x00").unwrap().size, None);
+        assert!(parse_header(&end_of_batch()).is_none());
+    }
+}
@@ -0,0 +1,753 @@
+//! ZMODEM 协议：流式发送（数据子包不等待确认，接收方出错时用 ZRPOS 要求从指定位置重发）、
+//! CRC-16/CRC-32 校验，以及断点续传（接收方用 ZRPOS 告知已有的长度，发送方从该位置继续）
+//!
+//! 帧头有两种：`** ZDLE B` 加十六进制的帧类型、4 字节参数和 CRC-16（十六进制帧头），
+//! 或 `* ZDLE A`（CRC-16）/`* ZDLE C`（CRC-32）加转义的二进制内容。文件信息和文件数据以转义的
+//! 数据子包发送，子包以 ZDLE 加结束类型（是否继续、是否需要 ZACK）和 CRC 结尾。
+//! 与 lrzsz（sz/rz）、Tera Term、SecureCRT 的实现兼容。
+
+use anyhow::{bail, Context, Result};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::checksum::crc16_xmodem;
+use crate::gzip::crc32;
+use crate::xmodem::Link;
+
+const ZPAD: u8 = b'*';
+/// 转义字符（与 CAN 相同，连续 5 个即为取消传输）
+const ZDLE: u8 = 0x18;
+const ZBIN: u8 = b'A';
+const ZHEX: u8 = b'B';
+const ZBIN32: u8 = b'C';
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+// 帧类型
+const ZRQINIT: u8 = 0;
+const ZRINIT: u8 = 1;
+const ZSINIT: u8 = 2;
+const ZACK: u8 = 3;
+const ZFILE: u8 = 4;
+const ZSKIP: u8 = 5;
+const ZNAK: u8 = 6;
+const ZABORT: u8 = 7;
+const ZFIN: u8 = 8;
+const ZRPOS: u8 = 9;
+const ZDATA: u8 = 10;
+const ZEOF: u8 = 11;
+const ZFERR: u8 = 12;
+
+// 数据子包的结束类型
+/// 帧结束，不需要确认
+const ZCRCE: u8 = b'h';
+/// 帧继续，不需要确认（流式发送）
+const ZCRCG: u8 = b'i';
+/// 帧继续，需要 ZACK
+const ZCRCQ: u8 = b'j';
+/// 帧结束，需要 ZACK
+const ZCRCW: u8 = b'k';
+const ZRUB0: u8 = b'l';
+const ZRUB1: u8 = b'm';
+
+// ZRINIT 的能力标志（ZF0）
+const CANFDX: u8 = 0x01;
+const CANOVIO: u8 = 0x02;
+const CANFC32: u8 = 0x20;
+const ESCCTL: u8 = 0x40;
+// ZFILE 的转换选项（ZF0）
+const ZCBIN: u8 = 1;
+const ZCRESUM: u8 = 3;
+
+/// 对方执行 sz 时发出的 ZRQINIT 帧头，用于监听时自动开始接收
+const ZRQINIT_HEADER: &[u8] = b"**\x18B00";
+/// 对方执行 rz 时发出的 ZRINIT 帧头
+const ZRINIT_HEADER: &[u8] = b"**\x18B01";
+
+/// 每个数据子包的长度（lrzsz 按线路速度使用 1K~8K）
+const SUBPACKET: usize = 1024;
+/// 接收时允许的最大子包长度
+const MAX_SUBPACKET: usize = 8192;
+const RETRIES: usize = 10;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 帧头：帧类型和 4 字节参数（位置为小端序，标志 ZF0 为最后一个字节）
+#[derive(Clone, Copy)]
+struct Header {
+    kind: u8,
+    args: [u8; 4],
+}
+
+impl Header {
+    fn position(kind: u8, position: u64) -> Self {
+        Header { kind, args: (position as u32).to_le_bytes() }
+    }
+
+    fn flags(kind: u8, zf0: u8) -> Self {
+        Header { kind, args: [0, 0, 0, zf0] }
+    }
+
+    fn pos(&self) -> u64 {
+        u32::from_le_bytes(self.args) as u64
+    }
+
+    fn zf0(&self) -> u8 {
+        self.args[3]
+    }
+}
+
+/// 转义后读到的内容
+enum Escaped {
+    Byte(u8),
+    /// 数据子包结束
+    End(u8),
+    Invalid,
+}
+
+/// 发送方提供的文件信息
+pub struct Offer {
+    pub name: String,
+    pub size: Option<u64>,
+    /// 发送方请求续传
+    pub resume: bool,
+}
+
+/// 监听时检测到的 ZMODEM 传输请求
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    /// 对方执行了 sz，要发送文件
+    Receive,
+    /// 对方执行了 rz，在等待上传
+    Send,
+}
+
+/// 在收到的数据中查找 sz/rz 发出的起始帧头（可能跨两次读取）
+#[derive(Default)]
+pub struct Detector {
+    tail: Vec<u8>,
+}
+
+impl Detector {
+    pub fn push(&mut self, data: &[u8]) -> Option<Request> {
+        self.tail.extend_from_slice(data);
+        let found = if contains(&self.tail, ZRQINIT_HEADER) {
+            Some(Request::Receive)
+        } else if contains(&self.tail, ZRINIT_HEADER) {
+            Some(Request::Send)
+        } else {
+            None
+        };
+        let keep = ZRQINIT_HEADER.len() - 1;
+        match found {
+            Some(_) => self.tail.clear(),
+            None if self.tail.len() > keep => {
+                self.tail.drain(..self.tail.len() - keep);
+            }
+            None => {}
+        }
+        found
+    }
+}
+
+fn contains(data: &[u8], pattern: &[u8]) -> bool {
+    data.windows(pattern.len()).any(|w| w == pattern)
+}
+
+/// 一次 ZMODEM 会话
+pub struct Zmodem<'l, 'p> {
+    link: &'l mut Link<'p>,
+    /// 发送二进制帧头和数据时使用 CRC-32（接收方支持时）
+    crc32: bool,
+    /// 最近收到的二进制帧头使用 CRC-32，其后的数据子包也是
+    rx_crc32: bool,
+    /// 接收方要求转义所有控制字符
+    escape_all: bool,
+    /// 接收方的缓冲区大小，0 表示可以连续流式发送
+    window: usize,
+}
+
+impl<'l, 'p> Zmodem<'l, 'p> {
+    pub fn new(link: &'l mut Link<'p>) -> Self {
+        Zmodem { link, crc32: false, rx_crc32: false, escape_all: false, window: 0 }
+    }
+
+    // ---- 发送 ----
+
+    fn escape(&self, data: &[u8], out: &mut Vec<u8>) {
+        let mut last = 0u8;
+        for &byte in data {
+            let escape = match byte {
+                ZDLE | 0x10 | XON | XOFF | 0x90 | 0x91 | 0x93 => true,
+                // Telenet 的 "@CR" 转义序列
+                0x0D | 0x8D => self.escape_all || last & 0x7F == b'@',
+                _ => self.escape_all && byte & 0x60 == 0,
+            };
+            match escape {
+                true => out.extend([ZDLE, byte ^ 0x40]),
+                false => out.push(byte),
+            }
+            last = byte;
+        }
+    }
+
+    fn send_hex_header(&mut self, header: Header) -> Result<()> {
+        let mut bytes = vec![header.kind];
+        bytes.extend(header.args);
+        let crc = crc16_xmodem(&bytes);
+        bytes.extend(crc.to_be_bytes());
+        let mut frame = vec![ZPAD, ZPAD, ZDLE, ZHEX];
+        for byte in bytes {
+            frame.extend(format!("{:02x}", byte).bytes());
+        }
+        frame.extend(b"\r\x8a");
+        if header.kind != ZFIN && header.kind != ZACK {
+            frame.push(XON);
+        }
+        self.link.send(&frame)
+    }
+
+    fn send_binary_header(&mut self, header: Header) -> Result<()> {
+        let mut bytes = vec![header.kind];
+        bytes.extend(header.args);
+        let mut frame = vec![ZPAD, ZDLE, if self.crc32 { ZBIN32 } else { ZBIN }];
+        match self.crc32 {
+            true => bytes.extend(crc32(&bytes).to_le_bytes()),
+            false => bytes.extend(crc16_xmodem(&bytes).to_be_bytes()),
+        }
+        self.escape(&bytes, &mut frame);
+        self.link.send(&frame)
+    }
+
+    fn send_subpacket(&mut self, data: &[u8], end: u8) -> Result<()> {
+        let mut frame = Vec::with_capacity(data.len() * 2 + 12);
+        self.escape(data, &mut frame);
+        frame.extend([ZDLE, end]);
+        let mut checked = data.to_vec();
+        checked.push(end);
+        let crc = match self.crc32 {
+            true => crc32(&checked).to_le_bytes().to_vec(),
+            false => crc16_xmodem(&checked).to_be_bytes().to_vec(),
+        };
+        self.escape(&crc, &mut frame);
+        if end == ZCRCW {
+            frame.push(XON);
+        }
+        self.link.send(&frame)
+    }
+
+    /// 发送方开始：发送 "rz\r"（对方是 shell 时启动 rz）和 ZRQINIT，等待接收方的 ZRINIT
+    pub fn start_send(&mut self, wait: Duration) -> Result<()> {
+        self.link.send(b"rz\r")?;
+        let deadline = Instant::now() + wait;
+        while Instant::now() < deadline {
+            self.send_hex_header(Header::flags(ZRQINIT, 0))?;
+            let timeout = Duration::from_secs(5).min(deadline.saturating_duration_since(Instant::now()));
+            if let Some(header) = self.read_header(timeout)? {
+                match header.kind {
+                    ZRINIT => {
+                        self.accept_rinit(header);
+                        return Ok(());
+                    }
+                    ZABORT | ZFERR => bail!("接收方中止了传输"),
+                    _ => {}
+                }
+            }
+        }
+        bail!("{:?} 内接收方没有响应（没有收到 ZRINIT），请先在设备上启动 rz 或 ZMODEM 接收", wait)
+    }
+
+    fn accept_rinit(&mut self, header: Header) {
+        self.crc32 = header.zf0() & CANFC32 != 0;
+        self.escape_all = header.zf0() & ESCCTL != 0;
+        self.window = u16::from_le_bytes([header.args[0], header.args[1]]) as usize;
+    }
+
+    /// 从接收方要求的位置 `start`（见 [`Zmodem::offer`]）开始发送文件数据，每发出一个子包以当前位置回调
+    pub fn send_file(&mut self, data: &[u8], start: u64, progress: &mut dyn FnMut(usize)) -> Result<()> {
+        let mut position = Some(start);
+        let mut retries = 0;
+        'data: while let Some(from) = position.take() {
+            let mut offset = (from as usize).min(data.len());
+            self.send_binary_header(Header::position(ZDATA, offset as u64))?;
+            let mut unacked = 0;
+            loop {
+                let end = (offset + SUBPACKET).min(data.len());
+                let last = end == data.len();
+                // 接收方缓冲区有限时，填满缓冲区前要等待确认
+                let wait_ack = !last && self.window > 0 && unacked + (end - offset) >= self.window;
+                let kind = match (last, wait_ack) {
+                    (true, _) => ZCRCE,
+                    (false, true) => ZCRCW,
+                    (false, false) => ZCRCG,
+                };
+                self.send_subpacket(&data[offset..end], kind)?;
+                unacked += end - offset;
+                offset = end;
+                progress(offset);
+                if kind == ZCRCE {
+                    break;
+                }
+                let reply = match kind {
+                    ZCRCW => self.read_header(TIMEOUT)?,
+                    // 流式发送时只检查接收方是否发来了 ZRPOS
+                    _ if self.link.pending()? => self.read_header(Duration::from_millis(100))?,
+                    _ => None,
+                };
+                match reply {
+                    Some(header) if header.kind == ZRPOS => {
+                        position = Some(header.pos());
+                        retries += 1;
+                        if retries > RETRIES {
+                            bail!("接收方连续 {} 次要求重发，线路质量太差", RETRIES);
+                        }
+                        continue 'data;
+                    }
+                    Some(header) if matches!(header.kind, ZABORT | ZFERR | ZSKIP) => bail!("接收方中止了传输"),
+                    None if kind == ZCRCW => bail!("{:?} 内接收方没有确认数据", TIMEOUT),
+                    _ => {}
+                }
+                // 收到确认后用新的 ZDATA 帧继续
+                if kind == ZCRCW {
+                    position = Some(offset as u64);
+                    continue 'data;
+                }
+            }
+            // 文件结束：接收方回复 ZRINIT 表示已完整收到，回复 ZRPOS 表示要从某处重发
+            for _ in 0..RETRIES {
+                self.send_binary_header(Header::position(ZEOF, data.len() as u64))?;
+                loop {
+                    match self.read_header(TIMEOUT)? {
+                        Some(header) if header.kind == ZRINIT => return Ok(()),
+                        Some(header) if header.kind == ZRPOS => {
+                            position = Some(header.pos());
+                            continue 'data;
+                        }
+                        Some(header) if header.kind == ZSKIP => return Ok(()),
+                        Some(header) if matches!(header.kind, ZABORT | ZFERR) => bail!("接收方中止了传输"),
+                        Some(_) => {}
+                        None => break,
+                    }
+                }
+            }
+            bail!("发送 ZEOF {} 次仍未被确认", RETRIES);
+        }
+        Ok(())
+    }
+
+    /// 发送 ZFILE 和文件信息，返回接收方要求的开始位置（续传时大于 0），接收方跳过该文件时返回 None
+    pub fn offer(&mut self, name: &str, len: usize, mtime: u64, resume: bool) -> Result<Option<u64>> {
+        let info = format!("{}\0{} {:o}\0", name, len, mtime).into_bytes();
+        for _ in 0..RETRIES {
+            self.send_binary_header(Header::flags(ZFILE, if resume { ZCRESUM } else { ZCBIN }))?;
+            self.send_subpacket(&info, ZCRCW)?;
+            loop {
+                match self.read_header(TIMEOUT)? {
+                    Some(header) if header.kind == ZRPOS => return Ok(Some(header.pos())),
+                    Some(header) if header.kind == ZSKIP => return Ok(None),
+                    Some(header) if matches!(header.kind, ZABORT | ZFERR) => bail!("接收方中止了传输"),
+                    // 接收方没有收到 ZFILE（仍在发送 ZRINIT）或文件信息有误：重发
+                    Some(header) if matches!(header.kind, ZRINIT | ZNAK) => break,
+                    Some(_) => {}
+                    None => break,
+                }
+            }
+        }
+        bail!("发送文件信息 {} 次仍未被接收", RETRIES)
+    }
+
+    /// 结束会话：ZFIN 得到回应后发送 "OO"
+    pub fn finish_send(&mut self) -> Result<()> {
+        for _ in 0..RETRIES {
+            self.send_hex_header(Header::flags(ZFIN, 0))?;
+            match self.read_header(TIMEOUT)? {
+                Some(header) if header.kind == ZFIN => return self.link.send(b"OO"),
+                Some(_) | None => {}
+            }
+        }
+        bail!("发送 ZFIN {} 次仍未被确认", RETRIES)
+    }
+
+    // ---- 接收 ----
+
+    /// 等待发送方的下一个文件：发送 ZRINIT，收到 ZFILE 时返回文件信息，收到 ZFIN 时结束会话并返回 None
+    pub fn next_file(&mut self, wait: Duration) -> Result<Option<Offer>> {
+        let deadline = Instant::now() + wait;
+        while Instant::now() < deadline {
+            self.send_hex_header(Header { kind: ZRINIT, args: [0, 0, 0, CANFDX | CANOVIO | CANFC32] })?;
+            let timeout = Duration::from_secs(5).min(deadline.saturating_duration_since(Instant::now()));
+            let Some(header) = self.read_header(timeout)? else {
+                continue;
+            };
+            match header.kind {
+                ZSINIT => {
+                    // 发送方要求转义控制字符；附带的 Attn 字符串不使用
+                    self.escape_all = header.zf0() & ESCCTL != 0;
+                    if self.read_subpacket(TIMEOUT)?.is_some() {
+                        self.send_hex_header(Header::position(ZACK, 1))?;
+                    }
+                }
+                ZFILE => {
+                    let Some((info, _)) = self.read_subpacket(TIMEOUT)? else {
+                        self.send_hex_header(Header::flags(ZNAK, 0))?;
+                        continue;
+                    };
+                    let mut fields = info.split(|&b| b == 0);
+                    let name = String::from_utf8_lossy(fields.next().unwrap_or_default()).into_owned();
+                    let size = fields
+                        .next()
+                        .and_then(|rest| String::from_utf8_lossy(rest).split_whitespace().next().and_then(|size| size.parse().ok()));
+                    return Ok(Some(Offer { name, size, resume: header.zf0() == ZCRESUM }));
+                }
+                ZFIN => {
+                    self.send_hex_header(Header::flags(ZFIN, 0))?;
+                    // 发送方最后的 "OO"
+                    self.link.read_exact(2, Duration::from_secs(1))?;
+                    return Ok(None);
+                }
+                ZABORT | ZFERR => bail!("发送方中止了传输"),
+                _ => {}
+            }
+        }
+        bail!("{:?} 内发送方没有开始发送，请先在设备上启动 sz 或 ZMODEM 发送", wait)
+    }
+
+    /// 跳过刚才提供的文件
+    pub fn skip(&mut self) -> Result<()> {
+        self.send_hex_header(Header::flags(ZSKIP, 0))
+    }
+
+    /// 从 `offset` 开始接收文件数据并写入 `out`，每收到一个子包以当前位置回调，返回文件的最终长度
+    pub fn receive_file(&mut self, offset: u64, out: &mut dyn Write, progress: &mut dyn FnMut(usize)) -> Result<u64> {
+        let mut position = offset;
+        let mut errors = 0;
+        self.send_hex_header(Header::position(ZRPOS, position))?;
+        loop {
+            if errors > RETRIES {
+                self.link.cancel()?;
+                bail!("连续 {} 次接收失败，已取消传输", RETRIES);
+            }
+            let Some(header) = self.read_header(TIMEOUT)? else {
+                errors += 1;
+                self.send_hex_header(Header::position(ZRPOS, position))?;
+                continue;
+            };
+            match header.kind {
+                ZDATA if header.pos() == position => loop {
+                    let Some((data, end)) = self.read_subpacket(TIMEOUT)? else {
+                        // 数据有误：要求从出错的位置重发，之后的数据都丢弃，直到新的 ZDATA
+                        errors += 1;
+                        self.send_hex_header(Header::position(ZRPOS, position))?;
+                        break;
+                    };
+                    out.write_all(&data).context("写入文件失败")?;
+                    position += data.len() as u64;
+                    errors = 0;
+                    progress(position as usize);
+                    match end {
+                        ZCRCW => {
+                            self.send_hex_header(Header::position(ZACK, position))?;
+                            break;
+                        }
+                        ZCRCQ => self.send_hex_header(Header::position(ZACK, position))?,
+                        ZCRCG => {}
+                        _ => break,
+                    }
+                },
+                // 位置不符（之前的 ZRPOS 还没生效）
+                ZDATA => {
+                    errors += 1;
+                    self.send_hex_header(Header::position(ZRPOS, position))?;
+                }
+                ZEOF if header.pos() == position => return Ok(position),
+                // 发送方没有收到 ZRPOS，仍在重发文件信息
+                ZFILE => {
+                    self.read_subpacket(TIMEOUT)?;
+                    self.send_hex_header(Header::position(ZRPOS, position))?;
+                }
+                ZABORT | ZFERR | ZFIN => bail!("发送方中止了传输"),
+                _ => {}
+            }
+        }
+    }
+
+    // ---- 读取 ----
+
+    /// 读取一个字节，跳过线路上的流控字符
+    fn read_raw(&mut self, timeout: Duration) -> Result<Option<u8>> {
+        loop {
+            match self.link.read_byte(timeout)? {
+                Some(XON | XOFF | 0x91 | 0x93) => continue,
+                other => return Ok(other),
+            }
+        }
+    }
+
+    fn read_escaped(&mut self, timeout: Duration) -> Result<Option<Escaped>> {
+        let Some(byte) = self.read_raw(timeout)? else {
+            return Ok(None);
+        };
+        if byte != ZDLE {
+            return Ok(Some(Escaped::Byte(byte)));
+        }
+        let mut cancels = 1;
+        loop {
+            let Some(next) = self.read_raw(timeout)? else {
+                return Ok(None);
+            };
+            return Ok(Some(match next {
+                ZDLE => {
+                    cancels += 1;
+                    if cancels >= 5 {
+                        bail!("对方取消了传输");
+                    }
+                    continue;
+                }
+                ZCRCE | ZCRCG | ZCRCQ | ZCRCW => Escaped::End(next),
+                ZRUB0 => Escaped::Byte(0x7F),
+                ZRUB1 => Escaped::Byte(0xFF),
+                _ if next & 0x60 == 0x40 => Escaped::Byte(next ^ 0x40),
+                _ => Escaped::Invalid,
+            }));
+        }
+    }
+
+    /// 查找并读取下一个帧头，跳过其间的其他数据和校验错误的帧头；超时返回 None
+    fn read_header(&mut self, timeout: Duration) -> Result<Option<Header>> {
+        let deadline = Instant::now() + timeout;
+        let mut cancels = 0;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            match self.read_raw(remaining)? {
+                None => return Ok(None),
+                Some(ZPAD) => cancels = 0,
+                Some(ZDLE) => {
+                    cancels += 1;
+                    if cancels >= 5 {
+                        bail!("对方取消了传输");
+                    }
+                    continue;
+                }
+                Some(_) => {
+                    cancels = 0;
+                    continue;
+                }
+            }
+            let mut byte = self.read_raw(Duration::from_secs(1))?;
+            while byte == Some(ZPAD) {
+                byte = self.read_raw(Duration::from_secs(1))?;
+            }
+            if byte != Some(ZDLE) {
+                continue;
+            }
+            let header = match self.read_raw(Duration::from_secs(1))? {
+                Some(ZHEX) => self.read_hex_header()?,
+                Some(ZBIN) => self.read_binary_header(false)?,
+                Some(ZBIN32) => self.read_binary_header(true)?,
+                _ => None,
+            };
+            if let Some(header) = header {
+                return Ok(Some(header));
+            }
+        }
+    }
+
+    fn read_hex_header(&mut self) -> Result<Option<Header>> {
+        let Some(hex) = self.link.read_exact(14, Duration::from_secs(1))? else {
+            return Ok(None);
+        };
+        let digits: Option<Vec<u8>> = hex
+            .chunks(2)
+            .map(|pair| std::str::from_utf8(pair).ok().and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+            .collect();
+        let Some(bytes) = digits else {
+            return Ok(None);
+        };
+        // 帧头后的 CR LF
+        if let Some(0x0D | 0x8D) = self.link.read_byte(Duration::from_millis(100))? {
+            self.link.read_byte(Duration::from_millis(100))?;
+        }
+        if crc16_xmodem(&bytes[..5]).to_be_bytes() != bytes[5..] {
+            return Ok(None);
+        }
+        Ok(Some(Header { kind: bytes[0], args: [bytes[1], bytes[2], bytes[3], bytes[4]] }))
+    }
+
+    fn read_binary_header(&mut self, long_crc: bool) -> Result<Option<Header>> {
+        let len = if long_crc { 9 } else { 7 };
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            match self.read_escaped(Duration::from_secs(1))? {
+                Some(Escaped::Byte(byte)) => bytes.push(byte),
+                _ => return Ok(None),
+            }
+        }
+        let valid = match long_crc {
+            true => crc32(&bytes[..5]).to_le_bytes() == bytes[5..],
+            false => crc16_xmodem(&bytes[..5]).to_be_bytes() == bytes[5..],
+        };
+        self.rx_crc32 = long_crc;
+        Ok(valid.then(|| Header { kind: bytes[0], args: [bytes[1], bytes[2], bytes[3], bytes[4]] }))
+    }
+
+    /// 读取一个数据子包，返回数据和结束类型；超时或校验错误时返回 None
+    fn read_subpacket(&mut self, timeout: Duration) -> Result<Option<(Vec<u8>, u8)>> {
+        let mut data = Vec::new();
+        loop {
+            match self.read_escaped(timeout)? {
+                Some(Escaped::Byte(byte)) if data.len() < MAX_SUBPACKET => data.push(byte),
+                Some(Escaped::End(end)) => {
+                    let mut crc = Vec::new();
+                    while crc.len() < if self.rx_crc32 { 4 } else { 2 } {
+                        match self.read_escaped(Duration::from_secs(1))? {
+                            Some(Escaped::Byte(byte)) => crc.push(byte),
+                            _ => return Ok(None),
+                        }
+                    }
+                    let mut checked = data.clone();
+                    checked.push(end);
+                    let valid = match self.rx_crc32 {
+                        true => crc == crc32(&checked).to_le_bytes(),
+                        false => crc == crc16_xmodem(&checked).to_be_bytes(),
+                    };
+                    return Ok(valid.then_some((data, end)));
+                }
+                _ => return Ok(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xmodem::tests::pipe;
+    use serialport::SerialPort;
+
+    /// 读出线路另一端收到的全部数据
+    fn output(port: &mut Box<dyn SerialPort>) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut buf = [0; 4096];
+        while let Ok(n) = port.read(&mut buf) {
+            out.extend_from_slice(&buf[..n]);
+        }
+        out
+    }
+
+    #[test]
+    fn hex_headers() {
+        let (mut a, mut b) = pipe();
+        let mut link = Link::new(&mut a, None);
+        let mut zmodem = Zmodem::new(&mut link);
+        // 与 lrzsz 的 rz/sz 发出的相同：小写十六进制、CR 加 0x8A、XON（ZFIN 和 ZACK 后没有 XON）
+        zmodem.send_hex_header(Header::flags(ZRINIT, CANFDX | CANOVIO | CANFC32)).unwrap();
+        assert_eq!(output(&mut b), b"**\x18B0100000023be50\r\x8a\x11");
+        zmodem.send_hex_header(Header::flags(ZRQINIT, 0)).unwrap();
+        assert_eq!(output(&mut b), b"**\x18B00000000000000\r\x8a\x11");
+        zmodem.send_hex_header(Header::flags(ZFIN, 0)).unwrap();
+        assert_eq!(output(&mut b), b"**\x18B0800000000022d\r\x8a");
+
+        // 跳过帧头前的其他数据和 CRC 错误的帧头
+        b.write_all(b"rz waiting\r\n**\x18B0100000023be51\r\x8a\x11**\x18B0100000023be50\r\x8a\x11").unwrap();
+        let header = zmodem.read_header(Duration::from_secs(1)).unwrap().unwrap();
+        assert_eq!((header.kind, header.zf0()), (ZRINIT, 0x23));
+        assert!(zmodem.read_header(Duration::from_millis(50)).unwrap().is_none());
+        b.write_all(&[ZDLE; 5]).unwrap();
+        assert!(zmodem.read_header(Duration::from_secs(1)).is_err(), "连续的 ZDLE 表示取消");
+    }
+
+    #[test]
+    fn binary_headers() {
+        let (mut a, mut b) = pipe();
+        let (mut c, mut d) = pipe();
+        let mut link = Link::new(&mut a, None);
+        let mut sender = Zmodem::new(&mut link);
+        let mut peer = Link::new(&mut c, None);
+        let mut receiver = Zmodem::new(&mut peer);
+        // 位置以小端序放在参数中，0x10 和 0x90 需要转义；CRC-16 为大端序，CRC-32 为小端序
+        for (crc32, expected) in [
+            (false, &b"*\x18A\x0a\x00\x18\x50\x00\x00\x05\xcd"[..]),
+            (true, &b"*\x18C\x0a\x00\x18\x50\x00\x00\xcc\x4c\xb4\x18\xd0"[..]),
+        ] {
+            sender.crc32 = crc32;
+            sender.send_binary_header(Header::position(ZDATA, 0x1000)).unwrap();
+            let frame = output(&mut b);
+            assert_eq!(frame, expected);
+            d.write_all(&frame).unwrap();
+            let header = receiver.read_header(Duration::from_secs(1)).unwrap().unwrap();
+            assert_eq!((header.kind, header.pos(), receiver.rx_crc32), (ZDATA, 0x1000, crc32));
+            // 改动一位后校验失败，帧头被丢弃
+            let mut corrupt = frame.clone();
+            corrupt[4] ^= 1;
+            d.write_all(&corrupt).unwrap();
+            assert!(receiver.read_header(Duration::from_millis(200)).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn escaping() {
+        let (mut a, mut b) = pipe();
+        let mut link = Link::new(&mut a, None);
+        let mut zmodem = Zmodem::new(&mut link);
+        let data = [ZDLE, 0x10, XON, XOFF, 0x90, 0x91, 0x93, b'A', b'@', 0x0D, 0x01, 0x7F, 0xFF];
+        let mut out = Vec::new();
+        zmodem.escape(&data, &mut out);
+        assert_eq!(out, b"\x18\x58\x18\x50\x18\x51\x18\x53\x18\xd0\x18\xd1\x18\xd3A@\x18\x4d\x01\x7f\xff");
+        zmodem.escape_all = true;
+        out.clear();
+        zmodem.escape(&[0x01, 0x0D, 0x81, b' '], &mut out);
+        assert_eq!(out, b"\x18\x41\x18\x4d\x18\xc1 ");
+
+        // 所有字节值转义后都能还原；线路上的 XON/XOFF 被忽略
+        let all: Vec<u8> = (0..=255).collect();
+        out.clear();
+        zmodem.escape(&all, &mut out);
+        assert!(!out.iter().any(|&b| matches!(b, XON | XOFF | 0x91 | 0x93)));
+        out.extend([XON, ZDLE, ZRUB0, ZDLE, ZRUB1, ZDLE, ZCRCE, ZDLE, b'z']);
+        b.write_all(&out).unwrap();
+        let mut bytes = Vec::new();
+        while let Some(Escaped::Byte(byte)) = zmodem.read_escaped(Duration::from_secs(1)).unwrap() {
+            bytes.push(byte);
+        }
+        assert_eq!(&bytes[..256], all);
+        assert_eq!(&bytes[256..], [0x7F, 0xFF], "ZRUB0/ZRUB1 表示 0x7F 和 0xFF，随后是子包结束");
+        assert!(matches!(zmodem.read_escaped(Duration::from_secs(1)).unwrap(), Some(Escaped::Invalid)));
+        assert!(zmodem.read_escaped(Duration::from_millis(50)).unwrap().is_none());
+    }
+
+    #[test]
+    fn subpackets() {
+        let (mut a, mut b) = pipe();
+        let mut link = Link::new(&mut a, None);
+        let mut zmodem = Zmodem::new(&mut link);
+        // CRC 覆盖数据和结束类型；ZCRCW 后发送 XON
+        zmodem.send_subpacket(b"abc", ZCRCW).unwrap();
+        let crc16 = output(&mut b);
+        assert_eq!(crc16, b"abc\x18k\x59\xd9\x11");
+        zmodem.crc32 = true;
+        zmodem.send_subpacket(b"abc", ZCRCE).unwrap();
+        let crc32 = output(&mut b);
+        assert_eq!(crc32, b"abc\x18h\x3a\x81\x34\xe4");
+
+        for (frame, long_crc, end) in [(crc16, false, ZCRCW), (crc32, true, ZCRCE)] {
+            zmodem.rx_crc32 = long_crc;
+            b.write_all(&frame).unwrap();
+            assert_eq!(zmodem.read_subpacket(Duration::from_secs(1)).unwrap(), Some((b"abc".to_vec(), end)));
+            let mut corrupt = frame;
+            corrupt[1] = b'B';
+            b.write_all(&corrupt).unwrap();
+            assert_eq!(zmodem.read_subpacket(Duration::from_secs(1)).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn detect_requests() {
+        let mut detector = Detector::default();
+        assert_eq!(detector.push(b"$ sz file.bin\r\nrz\r**\x18B0"), None);
+        assert_eq!(detector.push(b"0000000000000\r\x8a\x11"), Some(Request::Receive));
+        assert_eq!(detector.push(b"**\x18B0100000023be50\r\x8a\x11"), Some(Request::Send));
+        assert_eq!(detector.push(b"**\x18A"), None);
+    }
+}